@@ -0,0 +1,52 @@
+//! 同协议转发替换模型名：完整解析为 `Value` 与原始请求体上替换的对比
+//!
+//! 运行：cargo bench --bench model_field
+
+use axongate_engine::protocol::model_field::replace_model;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde_json::{json, Value};
+
+// 带 base64 图片的多模态对话请求，`image_bytes` 为图片数据的长度
+fn multimodal_request(image_bytes: usize) -> Vec<u8> {
+    let image = "A".repeat(image_bytes);
+    serde_json::to_vec(&json!({
+        "model": "gpt-4o",
+        "stream": true,
+        "max_tokens": 1024,
+        "messages": [
+            {"role": "system", "content": "You are a helpful assistant."},
+            {"role": "user", "content": [
+                {"type": "text", "text": "What is in this image?"},
+                {"type": "image_url", "image_url": {"url": format!("data:image/png;base64,{}", image)}}
+            ]}
+        ]
+    }))
+    .unwrap()
+}
+
+// 改动前的做法：解析为 Value、替换字段后重新序列化
+fn replace_with_value(body: &[u8], model: &str) -> Vec<u8> {
+    let mut json: Value = serde_json::from_slice(body).unwrap();
+    if let Value::Object(ref mut obj) = json {
+        obj.insert("model".to_string(), Value::String(model.to_string()));
+    }
+    serde_json::to_vec(&json).unwrap()
+}
+
+fn bench_replace_model(c: &mut Criterion) {
+    let mut group = c.benchmark_group("replace_model");
+    for size in [1024, 64 * 1024, 1024 * 1024] {
+        let body = multimodal_request(size);
+        group.throughput(Throughput::Bytes(body.len() as u64));
+        group.bench_with_input(BenchmarkId::new("value", size), &body, |b, body| {
+            b.iter(|| replace_with_value(black_box(body), "gpt-4o-2024-08-06"))
+        });
+        group.bench_with_input(BenchmarkId::new("splice", size), &body, |b, body| {
+            b.iter(|| replace_model(black_box(body), "gpt-4o-2024-08-06").unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_replace_model);
+criterion_main!(benches);
@@ -0,0 +1,485 @@
+use crate::error::Result;
+use crate::ledger::Ledger;
+use crate::models::{RouteConfig, UsageEvent};
+use crate::proxy::ProxyForwarder;
+use crate::telemetry::TelemetryModule;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use futures::{Stream, StreamExt, TryStreamExt};
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+/// 批处理结果在上游的保留时长（Anthropic 为创建后29天），超过后登记信息不再有用
+const BATCH_RETENTION: Duration = Duration::from_secs(29 * 24 * 3600);
+
+/// 登记数超过该数量时清理过期条目
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// 登记信息在账本数据库中的对象类型
+const OBJECT_KIND: &str = "batch";
+
+/// 网关自行查询上游时使用的 Anthropic API 版本
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// 批处理所在的上游及归属
+#[derive(Serialize, Deserialize)]
+pub struct BatchRoute {
+    /// 创建批处理的用户令牌
+    pub user_token: String,
+    pub tenant_id: Option<String>,
+    /// 创建批处理时的客户端IP和 JWT 声明，结果用量随之上报
+    pub client_ip: Option<String>,
+    pub claims: Option<HashMap<String, Value>>,
+    /// 客户端请求的模型名
+    pub model: String,
+    /// 创建批处理时使用的路由，后续请求都发往该上游和供应商令牌
+    pub route: RouteConfig,
+    created_at: DateTime<Utc>,
+    // 结果用量是否已完整上报
+    usage_reported: AtomicBool,
+}
+
+impl BatchRoute {
+    /// 结果用量是否已完整上报
+    pub fn usage_reported(&self) -> bool {
+        self.usage_reported.load(Ordering::Relaxed)
+    }
+
+    fn expired(&self) -> bool {
+        (Utc::now() - self.created_at)
+            .to_std()
+            .is_ok_and(|age| age >= BATCH_RETENTION)
+    }
+
+    /// 结果中单个成功请求的用量事件，请求ID为 `批处理ID:custom_id`
+    pub fn usage_event(&self, batch_id: &str, usage: BatchResultUsage) -> UsageEvent {
+        UsageEvent {
+            request_id: format!("{}:{}", batch_id, usage.custom_id),
+            token: self.user_token.clone(),
+            model: self.model.clone(),
+            api: self.route.api_endpoint.clone(),
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            model_id: self.route.model_id.clone(),
+            provider_id: self.route.provider_id.clone(),
+            provider_token_id: self.route.provider_token_id.clone(),
+            canary: self.route.canary.as_ref().map(|c| c.tag.clone()),
+            client_ip: self.client_ip.clone(),
+            claims: self.claims.clone(),
+            tenant_id: self.tenant_id.clone(),
+            batch_id: Some(batch_id.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+/// 新建批处理的归属信息
+pub struct BatchOwner {
+    pub user_token: String,
+    pub tenant_id: Option<String>,
+    pub client_ip: Option<String>,
+    pub claims: Option<HashMap<String, Value>>,
+}
+
+/// 已创建批处理的登记表
+///
+/// 批处理的查询、取消和结果下载必须发往创建它的上游和供应商令牌，登记表按批处理ID记录该路由。
+/// 配置了本地账本时登记信息同时写入账本数据库，网关重启后由 [`BatchRegistry::restore`] 加载；
+/// 未配置时只保存在进程内，重启后此前创建的批处理无法再通过网关访问。
+#[derive(Default)]
+pub struct BatchRegistry {
+    batches: DashMap<String, Arc<BatchRoute>>,
+    ledger: Option<Arc<Ledger>>,
+}
+
+impl BatchRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 登记信息写入账本数据库
+    pub fn with_ledger(mut self, ledger: Option<Arc<Ledger>>) -> Self {
+        self.ledger = ledger;
+        self
+    }
+
+    /// 从账本数据库加载仍在保留期内的批处理，返回加载数量
+    pub async fn restore(&self) -> Result<usize> {
+        let Some(ledger) = &self.ledger else {
+            return Ok(0);
+        };
+        let mut restored = 0;
+        for object in ledger.load_objects(OBJECT_KIND, BATCH_RETENTION).await? {
+            match serde_json::from_str::<BatchRoute>(&object.payload) {
+                Ok(batch) => {
+                    self.batches.insert(object.id, Arc::new(batch));
+                    restored += 1;
+                }
+                Err(e) => warn!("Skipping unreadable batch record {}: {}", object.id, e),
+            }
+        }
+        Ok(restored)
+    }
+
+    /// 登记新创建的批处理
+    pub async fn register(
+        &self,
+        batch_id: String,
+        owner: BatchOwner,
+        model: String,
+        route: RouteConfig,
+    ) {
+        if self.batches.len() > PRUNE_THRESHOLD {
+            self.batches.retain(|_, batch| !batch.expired());
+        }
+
+        let batch = Arc::new(BatchRoute {
+            user_token: owner.user_token,
+            tenant_id: owner.tenant_id,
+            client_ip: owner.client_ip,
+            claims: owner.claims,
+            model,
+            route,
+            created_at: Utc::now(),
+            usage_reported: AtomicBool::new(false),
+        });
+        self.persist(&batch_id, &batch).await;
+        self.batches.insert(batch_id, batch);
+    }
+
+    /// 查找用户令牌名下的批处理，不存在或不属于该令牌时返回 None
+    pub fn get(&self, batch_id: &str, user_token: &str) -> Option<Arc<BatchRoute>> {
+        self.batches
+            .get(batch_id)
+            .filter(|batch| batch.user_token == user_token)
+            .map(|batch| batch.clone())
+    }
+
+    /// 批处理已在上游删除
+    pub async fn remove(&self, batch_id: &str) {
+        self.batches.remove(batch_id);
+        if let Some(ledger) = &self.ledger {
+            if let Err(e) = ledger.delete_object(OBJECT_KIND, batch_id).await {
+                error!("Failed to delete batch record {}: {}", batch_id, e);
+            }
+        }
+    }
+
+    /// 结果用量尚未上报的批处理
+    fn unreported(&self) -> Vec<(String, Arc<BatchRoute>)> {
+        self.batches
+            .iter()
+            .filter(|batch| !batch.usage_reported() && !batch.expired())
+            .map(|batch| (batch.key().clone(), batch.value().clone()))
+            .collect()
+    }
+
+    async fn mark_usage_reported(&self, batch_id: &str, batch: &BatchRoute) {
+        batch.usage_reported.store(true, Ordering::Relaxed);
+        self.persist(batch_id, batch).await;
+    }
+
+    // 写入失败只记录日志：批处理已在上游创建，进程内的登记仍然可用
+    async fn persist(&self, batch_id: &str, batch: &BatchRoute) {
+        let Some(ledger) = &self.ledger else {
+            return;
+        };
+        let result = match serde_json::to_string(batch) {
+            Ok(payload) => {
+                ledger
+                    .save_object(OBJECT_KIND, batch_id, &payload, batch.created_at)
+                    .await
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            error!("Failed to persist batch {}: {}", batch_id, e);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.batches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+}
+
+/// 批处理结果中单个成功请求的用量
+#[derive(Debug, Clone)]
+pub struct BatchResultUsage {
+    pub custom_id: String,
+    pub input_tokens: i32,
+    pub output_tokens: i32,
+}
+
+/// 解析结果文件（JSONL）中的一行，只有成功的请求带有用量
+pub fn parse_result_line(line: &[u8]) -> Option<BatchResultUsage> {
+    let v: Value = serde_json::from_slice(line.trim_ascii()).ok()?;
+    let result = v.get("result")?;
+    if result.get("type")?.as_str()? != "succeeded" {
+        return None;
+    }
+
+    let usage = result.pointer("/message/usage")?;
+    Some(BatchResultUsage {
+        custom_id: v.get("custom_id")?.as_str()?.to_string(),
+        input_tokens: usage.get("input_tokens")?.as_i64()? as i32,
+        output_tokens: usage.get("output_tokens")?.as_i64()? as i32,
+    })
+}
+
+/// 包装结果下载流，原样输出的同时逐行解析成功请求的用量上报遥测
+///
+/// 结果完整下载后标记该批处理已上报，之后的重复下载不再解析；
+/// 下载中断时不标记，重新下载会再次上报，由请求ID去重。
+pub fn ingest_results<S>(
+    stream: S,
+    registry: Arc<BatchRegistry>,
+    telemetry: Arc<TelemetryModule>,
+    batch_id: String,
+    batch: Arc<BatchRoute>,
+) -> impl Stream<Item = Result<Bytes>> + Send
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        let mut pending = BytesMut::new();
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+
+            pending.extend_from_slice(&chunk);
+            while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+                let line = pending.split_to(end + 1);
+                if let Some(usage) = parse_result_line(&line) {
+                    telemetry.report_usage(batch.usage_event(&batch_id, usage));
+                }
+            }
+            yield Ok(chunk);
+        }
+
+        // 最后一行可能没有换行符
+        if let Some(usage) = parse_result_line(&pending) {
+            telemetry.report_usage(batch.usage_event(&batch_id, usage));
+        }
+        registry.mark_usage_reported(&batch_id, &batch).await;
+    }
+}
+
+/// 批处理已结束时下载结果并上报用量，返回是否已上报
+///
+/// 客户端可能从不下载结果，网关在后台轮询和删除批处理前自行下载，保证结果用量都被计费。
+pub async fn collect_usage(
+    registry: &Arc<BatchRegistry>,
+    proxy: &ProxyForwarder,
+    telemetry: &Arc<TelemetryModule>,
+    batch_id: &str,
+    batch: &Arc<BatchRoute>,
+) -> Result<bool> {
+    if batch.usage_reported() {
+        return Ok(true);
+    }
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "anthropic-version",
+        HeaderValue::from_static(ANTHROPIC_VERSION),
+    );
+    let path = format!("/v1/messages/batches/{}", batch_id);
+    let upstream = proxy
+        .forward_batch(&batch.route, reqwest::Method::GET, &path, None, &headers)
+        .await?;
+    let body: Vec<u8> = upstream
+        .body
+        .try_fold(Vec::new(), |mut body, chunk| async move {
+            body.extend_from_slice(&chunk);
+            Ok(body)
+        })
+        .await?;
+    let status = serde_json::from_slice::<Value>(&body)?;
+    if status["processing_status"] != "ended" {
+        return Ok(false);
+    }
+
+    let upstream = proxy
+        .forward_batch(
+            &batch.route,
+            reqwest::Method::GET,
+            &format!("{}/results", path),
+            None,
+            &headers,
+        )
+        .await?;
+    let results = ingest_results(
+        upstream.body,
+        registry.clone(),
+        telemetry.clone(),
+        batch_id.to_string(),
+        batch.clone(),
+    );
+    futures::pin_mut!(results);
+    while let Some(chunk) = results.next().await {
+        chunk?;
+    }
+    Ok(batch.usage_reported())
+}
+
+/// 启动批处理结果轮询任务：定期查询用量尚未上报的批处理，结束后下载结果并上报用量
+///
+/// 上游以4xx拒绝查询（批处理已删除或供应商令牌失效）时不再轮询该批处理。
+pub fn spawn_usage_poller(
+    registry: Arc<BatchRegistry>,
+    proxy: Arc<ProxyForwarder>,
+    telemetry: Arc<TelemetryModule>,
+    interval: Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+
+            for (batch_id, batch) in registry.unreported() {
+                match collect_usage(&registry, &proxy, &telemetry, &batch_id, &batch).await {
+                    Ok(true) => info!("Reported result usage for batch {}", batch_id),
+                    Ok(false) => debug!("Batch {} still processing", batch_id),
+                    Err(e) if proxy.is_client_error(&e) => {
+                        warn!(
+                            "Batch {} is no longer accessible, stop polling: {}",
+                            batch_id, e
+                        );
+                        registry.remove(&batch_id).await;
+                    }
+                    Err(e) => warn!("Failed to poll batch {}: {}", batch_id, e),
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LedgerConfig;
+    use serde_json::json;
+
+    fn route() -> RouteConfig {
+        serde_json::from_value(json!({
+            "token": "sk-ant-test",
+            "model": "claude",
+            "api": "https://api.anthropic.com",
+            "protocol": "anthropic",
+            "model_id": "m1",
+            "provider_id": "p1",
+            "provider_token_id": "pt1",
+        }))
+        .unwrap()
+    }
+
+    fn owner(user_token: &str) -> BatchOwner {
+        BatchOwner {
+            user_token: user_token.to_string(),
+            tenant_id: Some("t1".to_string()),
+            client_ip: Some("10.0.0.1".to_string()),
+            claims: None,
+        }
+    }
+
+    async fn ledger() -> Arc<Ledger> {
+        let config: LedgerConfig =
+            serde_json::from_value(json!({"url": "sqlite::memory:", "max_connections": 1}))
+                .unwrap();
+        Arc::new(Ledger::connect(&config).await.unwrap())
+    }
+
+    #[test]
+    fn parses_usage_of_succeeded_results_only() {
+        let line = br#"{"custom_id":"a","result":{"type":"succeeded","message":{"usage":{"input_tokens":10,"output_tokens":5}}}}"#;
+        let usage = parse_result_line(line).unwrap();
+        assert_eq!(usage.custom_id, "a");
+        assert_eq!((usage.input_tokens, usage.output_tokens), (10, 5));
+
+        let errored = br#"{"custom_id":"b","result":{"type":"errored","error":{}}}"#;
+        assert!(parse_result_line(errored).is_none());
+        assert!(parse_result_line(b"").is_none());
+    }
+
+    #[tokio::test]
+    async fn registry_is_restored_from_ledger() {
+        let ledger = ledger().await;
+        let registry = Arc::new(BatchRegistry::new().with_ledger(Some(ledger.clone())));
+        registry
+            .register(
+                "batch_1".to_string(),
+                owner("user-a"),
+                "claude".to_string(),
+                route(),
+            )
+            .await;
+        registry
+            .register(
+                "batch_2".to_string(),
+                owner("user-a"),
+                "claude".to_string(),
+                route(),
+            )
+            .await;
+        let batch = registry.get("batch_1", "user-a").unwrap();
+        registry.mark_usage_reported("batch_1", &batch).await;
+        registry.remove("batch_2").await;
+
+        let restored = BatchRegistry::new().with_ledger(Some(ledger));
+        assert_eq!(restored.restore().await.unwrap(), 1);
+        let batch = restored.get("batch_1", "user-a").unwrap();
+        assert!(batch.usage_reported());
+        assert_eq!(batch.route.token, "sk-ant-test");
+        assert_eq!(batch.client_ip.as_deref(), Some("10.0.0.1"));
+        assert!(restored.get("batch_1", "user-b").is_none());
+        assert!(restored.get("batch_2", "user-a").is_none());
+    }
+
+    #[tokio::test]
+    async fn usage_event_carries_batch_owner() {
+        let registry = BatchRegistry::new();
+        registry
+            .register(
+                "batch_1".to_string(),
+                owner("user-a"),
+                "claude".to_string(),
+                route(),
+            )
+            .await;
+        let batch = registry.get("batch_1", "user-a").unwrap();
+        let event = batch.usage_event(
+            "batch_1",
+            BatchResultUsage {
+                custom_id: "req-1".to_string(),
+                input_tokens: 10,
+                output_tokens: 5,
+            },
+        );
+        assert_eq!(event.request_id, "batch_1:req-1");
+        assert_eq!(event.token, "user-a");
+        assert_eq!(event.tenant_id.as_deref(), Some("t1"));
+        assert_eq!(event.provider_token_id, "pt1");
+        assert_eq!(event.batch_id.as_deref(), Some("batch_1"));
+        assert_eq!(registry.unreported().len(), 1);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::config::BusinessApiAuthConfig;
+use crate::secrets::token_eq;
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
+use reqwest::RequestBuilder;
+use sha2::Sha256;
+use std::sync::Arc;
+
+/// 租户ID header
+pub const TENANT_HEADER: &str = "x-gateway-tenant";
+/// 签名时间戳 header（Unix秒）
+pub const TIMESTAMP_HEADER: &str = "x-gateway-timestamp";
+/// HMAC签名 header
+pub const SIGNATURE_HEADER: &str = "x-gateway-signature";
+/// 签名随机数 header（开启 `hmac_nonce` 时）
+pub const NONCE_HEADER: &str = "x-gateway-nonce";
+
+/// 允许的时间戳偏差（重放窗口），校验业务API回调时使用，控制面校验网关请求时建议使用相同窗口
+pub const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// 业务API请求认证
+///
+/// 为网关发往业务API的请求（路由解析、默认模型、遥测上报）附加认证信息，
+/// 让控制面可以确认请求来自受信任的网关实例：
+/// - `Authorization: Bearer <token>`
+/// - `x-gateway-tenant: <tenant_id>`
+/// - `x-gateway-timestamp` + `x-gateway-signature`：
+///   hex(HMAC-SHA256(secret, "{timestamp}.{body}"))
+/// - 开启 `hmac_nonce` 时另附 `x-gateway-nonce`，签名内容为 "{timestamp}.{nonce}.{body}"
+///
+/// 控制面校验建议：用常量时间比较签名；拒绝时间戳与本地时间相差超过 `MAX_CLOCK_SKEW_SECS` 的请求；
+/// 开启 `hmac_nonce` 时记录窗口内见过的随机数并拒绝重复，窗口外的请求已被时间戳拒绝，记录可随窗口过期。
+#[derive(Clone, Default)]
+pub struct BusinessApiAuth {
+    config: BusinessApiAuthConfig,
+    // 回调请求中见过的随机数 -> 时间戳，用于拒绝重放
+    seen_nonces: Arc<DashMap<String, i64>>,
+}
+
+impl BusinessApiAuth {
+    pub fn new(config: BusinessApiAuthConfig) -> Self {
+        Self {
+            config,
+            seen_nonces: Arc::default(),
+        }
+    }
+
+    /// 为请求附加认证 header 并设置请求体
+    ///
+    /// 签名覆盖完整请求体，因此请求体必须通过此方法设置，不能再调用 `.json()`。
+    pub fn apply(&self, builder: RequestBuilder, body: Vec<u8>) -> RequestBuilder {
+        let mut builder = builder.header("content-type", "application/json");
+
+        if let Some(token) = self.bearer_token() {
+            builder = builder.bearer_auth(token);
+        }
+
+        if let Some(tenant) = self.config.tenant_id.as_deref().filter(|t| !t.is_empty()) {
+            builder = builder.header(TENANT_HEADER, tenant);
+        }
+
+        if let Some(secret) = self.hmac_secret() {
+            let timestamp = chrono::Utc::now().timestamp().to_string();
+            let nonce = self
+                .config
+                .hmac_nonce
+                .then(|| uuid::Uuid::new_v4().simple().to_string());
+            if let Some(nonce) = &nonce {
+                builder = builder.header(NONCE_HEADER, nonce);
+            }
+            builder = builder.header(TIMESTAMP_HEADER, &timestamp).header(
+                SIGNATURE_HEADER,
+                sign(secret, &timestamp, nonce.as_deref(), &body),
+            );
+        }
+
+        builder.body(body)
+    }
+
+    /// 是否配置了可用于校验业务API回调的凭据（Bearer令牌或HMAC密钥）
+    pub fn can_verify(&self) -> bool {
+        self.bearer_token().is_some() || self.hmac_secret().is_some()
+    }
+
+    /// 校验业务API发往网关的回调请求（如缓存失效通知）
+    ///
+    /// 与 `apply` 对称：配置了Bearer令牌时要求 `Authorization` 匹配，配置了HMAC密钥时要求签名有效
+    /// 且时间戳与本地时间相差不超过5分钟；两者都配置时都要满足。开启 `hmac_nonce` 时还要求随机数
+    /// 在窗口内未出现过。`header` 按名称返回请求头的值。
+    pub fn verify<'a>(&self, header: impl Fn(&str) -> Option<&'a str>, body: &[u8]) -> bool {
+        if !self.can_verify() {
+            return false;
+        }
+
+        if let Some(token) = self.bearer_token() {
+            let provided = header("authorization").and_then(|v| v.strip_prefix("Bearer "));
+            if !provided.is_some_and(|provided| token_eq(provided, token)) {
+                return false;
+            }
+        }
+
+        if let Some(secret) = self.hmac_secret() {
+            let (Some(timestamp), Some(signature)) =
+                (header(TIMESTAMP_HEADER), header(SIGNATURE_HEADER))
+            else {
+                return false;
+            };
+            let nonce = header(NONCE_HEADER);
+            if self.config.hmac_nonce && nonce.is_none() {
+                return false;
+            }
+            let now = chrono::Utc::now().timestamp();
+            let Some(ts) = timestamp
+                .parse::<i64>()
+                .ok()
+                .filter(|ts| (now - ts).abs() <= MAX_CLOCK_SKEW_SECS)
+            else {
+                return false;
+            };
+            let Ok(signature) = hex::decode(signature) else {
+                return false;
+            };
+            let nonce = nonce.filter(|_| self.config.hmac_nonce);
+            if mac(secret, timestamp, nonce, body)
+                .verify_slice(&signature)
+                .is_err()
+            {
+                return false;
+            }
+            if let Some(nonce) = nonce {
+                if !self.remember_nonce(nonce, ts, now) {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    // 记录随机数，窗口内已出现过时返回 false；顺带清理窗口外的记录
+    fn remember_nonce(&self, nonce: &str, timestamp: i64, now: i64) -> bool {
+        self.seen_nonces
+            .retain(|_, ts| (now - *ts).abs() <= MAX_CLOCK_SKEW_SECS);
+        match self.seen_nonces.entry(nonce.to_string()) {
+            dashmap::mapref::entry::Entry::Occupied(_) => false,
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                entry.insert(timestamp);
+                true
+            }
+        }
+    }
+
+    fn bearer_token(&self) -> Option<&str> {
+        self.config
+            .bearer_token
+            .as_deref()
+            .filter(|t| !t.is_empty())
+    }
+
+    fn hmac_secret(&self) -> Option<&str> {
+        self.config.hmac_secret.as_deref().filter(|s| !s.is_empty())
+    }
+}
+
+/// 计算签名：hex(HMAC-SHA256(secret, 签名内容))
+fn sign(secret: &str, timestamp: &str, nonce: Option<&str>, body: &[u8]) -> String {
+    hex::encode(mac(secret, timestamp, nonce, body).finalize().into_bytes())
+}
+
+// 签名内容："{timestamp}.{body}"，带随机数时为 "{timestamp}.{nonce}.{body}"
+fn mac(secret: &str, timestamp: &str, nonce: Option<&str>, body: &[u8]) -> Hmac<Sha256> {
+    // HMAC 接受任意长度的密钥，new_from_slice 不会失败
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    if let Some(nonce) = nonce {
+        mac.update(nonce.as_bytes());
+        mac.update(b".");
+    }
+    mac.update(body);
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn auth(bearer_token: Option<&str>, hmac_secret: Option<&str>) -> BusinessApiAuth {
+        BusinessApiAuth::new(BusinessApiAuthConfig {
+            bearer_token: bearer_token.map(str::to_string),
+            hmac_secret: hmac_secret.map(str::to_string),
+            ..Default::default()
+        })
+    }
+
+    fn verify(auth: &BusinessApiAuth, headers: &[(&str, String)], body: &[u8]) -> bool {
+        let headers: HashMap<&str, &str> = headers.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        auth.verify(|name| headers.get(name).copied(), body)
+    }
+
+    #[test]
+    fn verifies_bearer_token() {
+        let auth = auth(Some("callback-token"), None);
+        let header = |token: &str| [("authorization", format!("Bearer {}", token))];
+
+        assert!(verify(&auth, &header("callback-token"), b"{}"));
+        assert!(!verify(&auth, &header("callback-tokem"), b"{}"));
+        assert!(!verify(&auth, &header("callback"), b"{}"));
+        assert!(!verify(&auth, &[], b"{}"));
+    }
+
+    #[test]
+    fn verifies_hmac_signature() {
+        let auth = auth(None, Some("secret"));
+        let timestamp = chrono::Utc::now().timestamp().to_string();
+        let signed = |body: &[u8]| {
+            [
+                (TIMESTAMP_HEADER, timestamp.clone()),
+                (SIGNATURE_HEADER, sign("secret", &timestamp, None, body)),
+            ]
+        };
+
+        assert!(verify(&auth, &signed(b"{}"), b"{}"));
+        assert!(!verify(&auth, &signed(b"{}"), b"{\"x\":1}"));
+    }
+
+    #[test]
+    fn cannot_verify_without_credentials() {
+        assert!(!verify(&auth(None, None), &[], b"{}"));
+    }
+}
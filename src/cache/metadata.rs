@@ -0,0 +1,128 @@
+use crate::error::Result;
+use axum::body::Bytes;
+use dashmap::DashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+// 单个缓存条目：响应体及回源时间，未回源或回源失败时为 None
+type Slot = Arc<Mutex<Option<(Bytes, Instant)>>>;
+
+/// 上游元数据缓存
+///
+/// 缓存模型列表等与请求内容无关的上游响应，按供应商令牌和接口路径（含查询参数）区分，
+/// 使用独立于路由缓存的TTL。条目缺失或过期时只有一个请求回源，并发请求等待其结果，
+/// 避免 IDE 批量刷新模型列表时逐个打到上游。回源失败不缓存。
+pub struct MetadataCache {
+    ttl: Duration,
+    // (provider_token_id, path) -> 缓存条目；键数量受供应商令牌数限制
+    entries: DashMap<(String, String), Slot>,
+}
+
+impl MetadataCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: DashMap::new(),
+        }
+    }
+
+    /// TTL 为 0 时不缓存，每次都回源
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    /// 返回缓存的响应体，缺失或过期时调用 `fetch` 回源
+    pub async fn get_or_fetch<F, Fut>(
+        &self,
+        provider_token_id: &str,
+        path: &str,
+        fetch: F,
+    ) -> Result<Bytes>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Bytes>>,
+    {
+        if !self.is_enabled() {
+            return fetch().await;
+        }
+
+        let slot = self
+            .entries
+            .entry((provider_token_id.to_string(), path.to_string()))
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().await;
+        if let Some((body, fetched_at)) = slot.as_ref() {
+            if fetched_at.elapsed() < self.ttl {
+                metrics::increment_counter!("gateway_metadata_cache_hits_total");
+                return Ok(body.clone());
+            }
+        }
+
+        metrics::increment_counter!("gateway_metadata_cache_misses_total");
+        let body = fetch().await?;
+        *slot = Some((body.clone(), Instant::now()));
+        Ok(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn fetch(cache: &MetadataCache, calls: &AtomicUsize, provider: &str) -> Result<Bytes> {
+        cache
+            .get_or_fetch(provider, "/v1/models", || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Ok(Bytes::from_static(b"{\"data\":[]}"))
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn caches_per_provider_until_expired() {
+        let cache = MetadataCache::new(Duration::from_millis(50));
+        let calls = AtomicUsize::new(0);
+
+        fetch(&cache, &calls, "p1").await.unwrap();
+        fetch(&cache, &calls, "p1").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        fetch(&cache, &calls, "p2").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        fetch(&cache, &calls, "p1").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_fetch_once() {
+        let cache = MetadataCache::new(Duration::from_secs(60));
+        let calls = AtomicUsize::new(0);
+
+        let results = futures::future::join_all((0..8).map(|_| fetch(&cache, &calls, "p1"))).await;
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn failures_are_not_cached() {
+        let cache = MetadataCache::new(Duration::from_secs(60));
+        let calls = AtomicUsize::new(0);
+
+        let failed = cache
+            .get_or_fetch("p1", "/v1/models", || async {
+                Err(Error::Proxy("Upstream returned 503".to_string()))
+            })
+            .await;
+        assert!(failed.is_err());
+
+        fetch(&cache, &calls, "p1").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,7 +1,25 @@
+pub mod metadata;
+
 use crate::models::RouteConfig;
+use crate::secrets::{mask_token, TokenCipher};
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// 缓存键：租户 + 用户token + 模型名
+///
+/// 未识别租户的请求 `tenant` 为 None，与各租户的条目互不冲突
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    tenant: Option<String>,
+    token: String,
+    model: String,
+}
 
 /// 缓存条目结构
 ///
@@ -12,10 +30,6 @@ struct CacheEntry {
     /// 包含多个供应商的API端点，支持故障转移
     configs: Vec<RouteConfig>,
 
-    /// 创建时间点
-    /// 用于计算硬过期时间
-    created_at: Instant,
-
     /// 软过期时间点（滑动TTL）
     /// 每次命中时会刷新，但不超过硬过期时间
     expires_at: Instant,
@@ -23,6 +37,75 @@ struct CacheEntry {
     /// 硬过期时间点（最大生存时间）
     /// 无论访问频率如何，到达此时间后强制失效
     hard_expires_at: Instant,
+
+    /// 该条目的滑动TTL（业务API指定TTL时与其一致）
+    ttl: Duration,
+
+    /// 路由的来源信息
+    provenance: RouteProvenance,
+
+    /// 写入序号，用于识别租户索引中已被覆盖的记录
+    seq: u64,
+}
+
+/// 租户条目索引
+///
+/// 按写入顺序记录租户的缓存键，配额淘汰时从队首取最早写入的条目，不必扫描整个缓存
+#[derive(Default)]
+struct TenantIndex {
+    /// (写入序号, 缓存键)，可能含已删除或已被覆盖的失效记录，淘汰和压缩时跳过
+    order: VecDeque<(u64, CacheKey)>,
+    /// 该租户当前的条目数
+    live: usize,
+}
+
+/// 缓存路由的来源信息，用于排查缓存中的路由为何仍指向已失效的供应商
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteProvenance {
+    /// 业务API解析出该路由的时间
+    pub resolved_at: DateTime<Utc>,
+    /// 业务API解析耗时（毫秒，含重试）
+    pub business_api_latency_ms: u64,
+    /// 业务API返回的路由列表版本
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl RouteProvenance {
+    /// 距解析时间的秒数
+    pub fn age_secs(&self) -> u64 {
+        (Utc::now() - self.resolved_at).num_seconds().max(0) as u64
+    }
+}
+
+/// 缓存检查条件
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CacheQuery {
+    /// 用户令牌（完整值）
+    pub token: Option<String>,
+    pub model: Option<String>,
+    /// 最多返回条数，默认100，最大1000
+    pub limit: Option<usize>,
+}
+
+const DEFAULT_QUERY_LIMIT: usize = 100;
+const MAX_QUERY_LIMIT: usize = 1000;
+
+/// 缓存条目概要，令牌已脱敏
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheEntryInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub token: String,
+    pub model: String,
+    /// 路由依次使用的供应商令牌ID
+    pub provider_token_ids: Vec<String>,
+    #[serde(flatten)]
+    pub provenance: RouteProvenance,
+    pub age_secs: u64,
+    /// 距过期的秒数，已过期（仅作为业务API不可用时的降级保留）时为0
+    pub expires_in_secs: u64,
+    pub expired: bool,
 }
 
 /// 路由缓存管理器
@@ -35,9 +118,9 @@ struct CacheEntry {
 /// - 硬过期：最大生存时间到达后强制失效
 #[derive(Clone)]
 pub struct Cache {
-    /// Key格式: "user_token:model_name"
+    /// Key: (租户, 用户token, 模型名)
     /// Value: 缓存条目(路由配置列表+过期时间)
-    storage: Arc<DashMap<String, CacheEntry>>,
+    storage: Arc<DashMap<CacheKey, CacheEntry>>,
 
     /// 缓存生存时间(TTL) - 滑动过期
     /// 每次命中时会刷新软过期时间
@@ -46,6 +129,21 @@ pub struct Cache {
     /// 缓存最大生存时间 - 硬过期
     /// 无论访问频率，到达此时间后强制失效
     max_lifetime: Duration,
+
+    /// 单个租户最多占用的条目数（None 表示不限）
+    tenant_quota: Option<usize>,
+
+    /// 各租户的条目索引，仅在配置租户配额时维护
+    tenant_index: Arc<DashMap<String, TenantIndex>>,
+
+    /// 下一个写入序号
+    next_seq: Arc<AtomicU64>,
+
+    /// 供应商令牌加密器，配置后缓存中只保存令牌密文
+    cipher: Option<Arc<TokenCipher>>,
+
+    /// 过期条目的保留时长，期间可通过 `get_stale` 读取（业务API不可用时使用）
+    stale_window: Duration,
 }
 
 impl Cache {
@@ -62,20 +160,48 @@ impl Cache {
             storage: Arc::new(DashMap::new()),
             ttl,
             max_lifetime,
+            tenant_quota: None,
+            tenant_index: Arc::new(DashMap::new()),
+            next_seq: Arc::new(AtomicU64::new(0)),
+            cipher: None,
+            stale_window: Duration::ZERO,
         }
     }
 
-    /// 生成缓存键
+    /// 过期条目在 `window` 内继续保留，供业务API不可用时通过 `get_stale` 使用
+    pub fn with_stale_window(mut self, window: Duration) -> Self {
+        self.stale_window = window;
+        self
+    }
+
+    /// 加密缓存中的供应商令牌，读取时解密
+    pub fn with_token_cipher(mut self, cipher: Option<Arc<TokenCipher>>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// 限制单个租户最多占用的条目数
     ///
-    /// 将用户token和模型名组合成唯一的缓存键
-    /// 格式: "token:model"
-    fn make_key(token: &str, model: &str) -> String {
-        format!("{}:{}", token, model)
+    /// 租户条目数达到上限时，写入新条目会淘汰该租户自己最早写入的条目，
+    /// 不会影响其他租户
+    pub fn with_tenant_quota(mut self, quota: Option<usize>) -> Self {
+        self.tenant_quota = quota;
+        self
+    }
+
+    /// 生成缓存键
+    fn make_key(tenant: Option<&str>, token: &str, model: &str) -> CacheKey {
+        CacheKey {
+            tenant: tenant.map(str::to_string),
+            token: token.to_string(),
+            model: model.to_string(),
+        }
     }
 
     /// 获取缓存的路由配置
     ///
     /// # 参数
+    /// * `tenant` - 租户ID（未识别租户时为 None）
     /// * `token` - 用户认证token
     /// * `model` - AI模型名称
     ///
@@ -84,72 +210,194 @@ impl Cache {
     /// * `None` - 缓存未命中或已过期
     ///
     /// # 行为
-    /// - 检查硬过期和软过期，任一过期则删除条目
+    /// - 检查硬过期和软过期，任一过期则按未命中处理，超出过期保留时长的条目被删除
     /// - 如果未过期，自动刷新软过期时间（滑动续期）
     /// - 返回的是配置列表的克隆，避免并发修改问题
-    pub async fn get(&self, token: &str, model: &str) -> Option<Vec<RouteConfig>> {
-        let key = Self::make_key(token, model);
+    pub async fn get(
+        &self,
+        tenant: Option<&str>,
+        token: &str,
+        model: &str,
+    ) -> Option<Vec<RouteConfig>> {
+        let key = Self::make_key(tenant, token, model);
         let now = Instant::now();
+        let mut expired = false;
         let mut need_remove = false;
 
         // 第一阶段：检查过期（只读锁）
         if let Some(entry) = self.storage.get(&key) {
             // 硬过期检查：到达最大生存时间
-            if now >= entry.hard_expires_at {
-                need_remove = true;
-            }
             // 软过期检查：到达滑动TTL过期时间
-            else if now >= entry.expires_at {
-                need_remove = true;
+            if now >= entry.hard_expires_at || now >= entry.expires_at {
+                expired = true;
+                need_remove = now >= self.stale_deadline(&entry);
             }
         }
 
-        // 第二阶段：删除过期条目
+        // 第二阶段：删除超出保留时长的过期条目
         if need_remove {
-            self.storage.remove(&key);
+            self.remove_key(&key);
+        }
+        if expired {
             return None;
         }
 
         // 第三阶段：刷新软过期时间并返回（写锁）
         if let Some(mut entry) = self.storage.get_mut(&key) {
             // 滑动续期：刷新软过期时间，但不超过硬过期时间
-            let new_expires_at = (now + self.ttl).min(entry.hard_expires_at);
+            let new_expires_at = (now + entry.ttl).min(entry.hard_expires_at);
             entry.expires_at = new_expires_at;
 
             let configs = entry.configs.clone();
             // 显式释放写锁
             drop(entry);
 
-            return Some(configs);
+            // 令牌无法解密（如主密钥轮换）时丢弃条目，按未命中处理
+            return match self.open_tokens(configs) {
+                Some(configs) => Some(configs),
+                None => {
+                    self.remove_key(&key);
+                    None
+                }
+            };
         }
 
         None
     }
 
+    /// 获取已过期但仍在保留时长内的路由配置（也返回未过期的条目），不续期
+    ///
+    /// 仅用于业务API不可用时的降级
+    pub fn get_stale(
+        &self,
+        tenant: Option<&str>,
+        token: &str,
+        model: &str,
+    ) -> Option<Vec<RouteConfig>> {
+        let key = Self::make_key(tenant, token, model);
+        let configs = {
+            let entry = self.storage.get(&key)?;
+            if Instant::now() >= self.stale_deadline(&entry) {
+                return None;
+            }
+            entry.configs.clone()
+        };
+        self.open_tokens(configs)
+    }
+
+    /// 路由的来源信息（含已过期但仍保留的条目），不续期
+    pub fn provenance(
+        &self,
+        tenant: Option<&str>,
+        token: &str,
+        model: &str,
+    ) -> Option<RouteProvenance> {
+        let key = Self::make_key(tenant, token, model);
+        self.storage.get(&key).map(|entry| entry.provenance.clone())
+    }
+
+    /// 按条件列出缓存条目，按解析时间从新到旧排列
+    pub fn inspect(&self, query: &CacheQuery) -> Vec<CacheEntryInfo> {
+        let now = Instant::now();
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_QUERY_LIMIT)
+            .min(MAX_QUERY_LIMIT);
+
+        let mut entries: Vec<CacheEntryInfo> = self
+            .storage
+            .iter()
+            .filter(|entry| {
+                query
+                    .token
+                    .as_deref()
+                    .is_none_or(|t| entry.key().token == t)
+                    && query
+                        .model
+                        .as_deref()
+                        .is_none_or(|m| entry.key().model == m)
+            })
+            .map(|entry| {
+                let (key, value) = entry.pair();
+                let expires_at = value.expires_at.min(value.hard_expires_at);
+                CacheEntryInfo {
+                    tenant: key.tenant.clone(),
+                    token: mask_token(&key.token),
+                    model: key.model.clone(),
+                    provider_token_ids: value
+                        .configs
+                        .iter()
+                        .map(|c| c.provider_token_id.clone())
+                        .collect(),
+                    provenance: value.provenance.clone(),
+                    age_secs: value.provenance.age_secs(),
+                    expires_in_secs: expires_at.saturating_duration_since(now).as_secs(),
+                    expired: now >= expires_at,
+                }
+            })
+            .collect();
+
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.provenance.resolved_at));
+        entries.truncate(limit);
+        entries
+    }
+
+    // 条目过期后可继续保留到的时间点
+    fn stale_deadline(&self, entry: &CacheEntry) -> Instant {
+        entry.expires_at.min(entry.hard_expires_at) + self.stale_window
+    }
+
     /// 设置缓存的路由配置
     ///
     /// # 参数
+    /// * `tenant` - 租户ID（未识别租户时为 None）
     /// * `token` - 用户认证token
     /// * `model` - AI模型名称
     /// * `configs` - 要缓存的路由配置列表
+    /// * `ttl` - 业务API为本次解析结果指定的缓存时长（可选）
+    /// * `provenance` - 路由的来源信息
     ///
     /// # 行为
     /// - 如果键已存在，会覆盖原有值
+    /// - 租户条目数超过上限时，淘汰该租户最早写入的条目
     /// - 自动设置创建时间、软过期时间和硬过期时间
     /// - 软过期时间 = min(now + ttl, now + max_lifetime)
     /// - 硬过期时间 = now + max_lifetime
-    pub async fn set(&self, token: &str, model: &str, configs: Vec<RouteConfig>) {
-        let key = Self::make_key(token, model);
+    /// - 指定 `ttl` 时条目在该时长后失效，不滑动续期（仍不超过 max_lifetime）
+    pub async fn set(
+        &self,
+        tenant: Option<&str>,
+        token: &str,
+        model: &str,
+        configs: Vec<RouteConfig>,
+        ttl: Option<Duration>,
+        provenance: RouteProvenance,
+    ) {
+        let key = Self::make_key(tenant, token, model);
         let now = Instant::now();
 
+        let Some(configs) = self.seal_tokens(configs) else {
+            return;
+        };
+
+        let (ttl, lifetime) = match ttl {
+            Some(ttl) => (ttl, ttl.min(self.max_lifetime)),
+            None => (self.ttl, self.max_lifetime),
+        };
         let entry = CacheEntry {
             configs,
-            created_at: now,
-            hard_expires_at: now + self.max_lifetime,
-            expires_at: (now + self.ttl).min(now + self.max_lifetime),
+            hard_expires_at: now + lifetime,
+            expires_at: (now + ttl).min(now + lifetime),
+            ttl,
+            provenance,
+            seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
         };
 
-        self.storage.insert(key, entry);
+        let seq = entry.seq;
+        let replaced = self.storage.insert(key.clone(), entry).is_some();
+        if let (Some(tenant), Some(quota)) = (tenant, self.tenant_quota) {
+            self.index_tenant_entry(tenant, key, seq, replaced, quota);
+        }
     }
 
     /// 从缓存中移除失败的路由配置
@@ -158,6 +406,7 @@ impl Cache {
     /// 避免后续请求继续使用失败的端点
     ///
     /// # 参数
+    /// * `tenant` - 租户ID（未识别租户时为 None）
     /// * `token` - 用户认证token
     /// * `model` - AI模型名称
     /// * `failed_config` - 需要移除的失败配置
@@ -165,15 +414,22 @@ impl Cache {
     /// # 行为
     /// - 只移除匹配的特定配置(token和api_endpoint都相同)
     /// - 如果移除后配置列表为空，则删除整个缓存条目
-    pub async fn remove_config(&self, token: &str, model: &str, failed_config: &RouteConfig) {
-        let key = Self::make_key(token, model);
+    pub async fn remove_config(
+        &self,
+        tenant: Option<&str>,
+        token: &str,
+        model: &str,
+        failed_config: &RouteConfig,
+    ) {
+        let key = Self::make_key(tenant, token, model);
 
         let mut should_remove_entry = false;
 
         if let Some(mut entry) = self.storage.get_mut(&key) {
             // 保留不匹配失败配置的其他配置
             entry.configs.retain(|c| {
-                c.token != failed_config.token || c.api_endpoint != failed_config.api_endpoint
+                c.api_endpoint != failed_config.api_endpoint
+                    || !self.token_matches(&c.token, &failed_config.token)
             });
 
             // DashMap 的 RefMut 在作用域结束前会持有写锁。
@@ -184,14 +440,264 @@ impl Cache {
         }
 
         if should_remove_entry {
-            self.storage.remove(&key);
+            self.remove_key(&key);
+        }
+    }
+
+    // 加密令牌，失败时返回 None（不缓存该条目）
+    fn seal_tokens(&self, mut configs: Vec<RouteConfig>) -> Option<Vec<RouteConfig>> {
+        let Some(cipher) = &self.cipher else {
+            return Some(configs);
+        };
+        for config in &mut configs {
+            match cipher.seal(&config.token) {
+                Ok(sealed) => config.token = sealed,
+                Err(e) => {
+                    warn!("Not caching routes: {}", e);
+                    return None;
+                }
+            }
+        }
+        Some(configs)
+    }
+
+    // 解密令牌，失败时返回 None
+    fn open_tokens(&self, mut configs: Vec<RouteConfig>) -> Option<Vec<RouteConfig>> {
+        let Some(cipher) = &self.cipher else {
+            return Some(configs);
+        };
+        for config in &mut configs {
+            match cipher.open(&config.token) {
+                Ok(token) => config.token = token,
+                Err(e) => {
+                    warn!("Dropping cached routes: {}", e);
+                    return None;
+                }
+            }
+        }
+        Some(configs)
+    }
+
+    // 比较缓存中的令牌（可能为密文）与明文令牌
+    fn token_matches(&self, cached: &str, token: &str) -> bool {
+        match &self.cipher {
+            Some(cipher) => cipher.open(cached).is_ok_and(|cached| cached == token),
+            None => cached == token,
+        }
+    }
+
+    /// 记录租户新写入的条目，条目数超过配额时从最早写入的条目开始淘汰
+    ///
+    /// 每条记录只入队、出队各一次，失效记录超过配额时压缩队列，淘汰开销按写入均摊为 O(1)
+    fn index_tenant_entry(
+        &self,
+        tenant: &str,
+        key: CacheKey,
+        seq: u64,
+        replaced: bool,
+        quota: usize,
+    ) {
+        // 锁顺序固定为先租户索引后缓存条目，其他路径不会在持有条目锁时访问租户索引
+        let mut index = self.tenant_index.entry(tenant.to_string()).or_default();
+        if !replaced {
+            index.live += 1;
+        }
+        index.order.push_back((seq, key));
+
+        while index.live > quota {
+            let Some((seq, key)) = index.order.pop_front() else {
+                break;
+            };
+            if self
+                .storage
+                .remove_if(&key, |_, entry| entry.seq == seq)
+                .is_some()
+            {
+                index.live -= 1;
+            }
+        }
+
+        if index.order.len() > quota * 2 {
+            index
+                .order
+                .retain(|(seq, key)| self.storage.get(key).is_some_and(|e| e.seq == *seq));
+        }
+    }
+
+    // 删除条目并更新租户条目数
+    fn remove_key(&self, key: &CacheKey) {
+        if self.storage.remove(key).is_some() {
+            self.forget_tenant_entries([key]);
+        }
+    }
+
+    // 条目被删除后减少所属租户的条目数（索引中的记录在淘汰或压缩时跳过）
+    fn forget_tenant_entries<'a>(&self, keys: impl IntoIterator<Item = &'a CacheKey>) {
+        if self.tenant_quota.is_none() {
+            return;
+        }
+        for key in keys {
+            let Some(tenant) = key.tenant.as_deref() else {
+                continue;
+            };
+            if let Some(mut index) = self.tenant_index.get_mut(tenant) {
+                index.live = index.live.saturating_sub(1);
+            }
         }
     }
 
+    // 删除不满足 `keep` 的条目，返回删除的条目数
+    fn retain_entries(&self, mut keep: impl FnMut(&CacheKey, &CacheEntry) -> bool) -> usize {
+        let mut removed = Vec::new();
+        self.storage.retain(|key, entry| {
+            let kept = keep(key, entry);
+            if !kept {
+                removed.push(key.clone());
+            }
+            kept
+        });
+        self.forget_tenant_entries(&removed);
+        removed.len()
+    }
+
+    /// 失效用户令牌在所有租户下的条目，指定 `model` 时只失效该模型，返回删除的条目数
+    pub fn invalidate_token(&self, token: &str, model: Option<&str>) -> usize {
+        self.retain_entries(|key, _| {
+            key.token != token || model.is_some_and(|model| key.model != model)
+        })
+    }
+
+    /// 失效包含指定供应商令牌的条目，返回删除的条目数
+    pub fn invalidate_provider(&self, provider_token_id: &str) -> usize {
+        self.retain_entries(|_, entry| {
+            !entry
+                .configs
+                .iter()
+                .any(|c| c.provider_token_id == provider_token_id)
+        })
+    }
+
+    /// 当前条目数（含尚未清理的过期条目）
+    pub fn len(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+
     /// 清空所有缓存
     ///
     /// 用于强制刷新缓存或系统重置
     pub async fn clear(&self) {
         self.storage.clear();
+        self.tenant_index.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn routes(provider_token_id: &str) -> Vec<RouteConfig> {
+        vec![serde_json::from_value(json!({
+            "token": "sk-test",
+            "model": "gpt-4o",
+            "api": "https://api.openai.com",
+            "protocol": "openai",
+            "model_id": "m1",
+            "provider_id": "p1",
+            "provider_token_id": provider_token_id,
+        }))
+        .unwrap()]
+    }
+
+    fn provenance() -> RouteProvenance {
+        RouteProvenance {
+            resolved_at: Utc::now(),
+            business_api_latency_ms: 0,
+            version: None,
+        }
+    }
+
+    fn cache(quota: usize) -> Cache {
+        Cache::new(Duration::from_secs(60), Duration::from_secs(600)).with_tenant_quota(Some(quota))
+    }
+
+    async fn set(cache: &Cache, tenant: Option<&str>, token: &str, provider: &str) {
+        cache
+            .set(
+                tenant,
+                token,
+                "gpt-4o",
+                routes(provider),
+                None,
+                provenance(),
+            )
+            .await;
+    }
+
+    async fn cached(cache: &Cache, tenant: Option<&str>, token: &str) -> bool {
+        cache.get(tenant, token, "gpt-4o").await.is_some()
+    }
+
+    #[tokio::test]
+    async fn evicts_oldest_entries_of_the_same_tenant() {
+        let cache = cache(2);
+        set(&cache, Some("a"), "u1", "pt1").await;
+        set(&cache, Some("b"), "u1", "pt1").await;
+        set(&cache, Some("a"), "u2", "pt1").await;
+        set(&cache, None, "u1", "pt1").await;
+        set(&cache, Some("a"), "u3", "pt1").await;
+
+        assert!(!cached(&cache, Some("a"), "u1").await);
+        assert!(cached(&cache, Some("a"), "u2").await);
+        assert!(cached(&cache, Some("a"), "u3").await);
+        assert!(cached(&cache, Some("b"), "u1").await);
+        assert!(cached(&cache, None, "u1").await);
+        assert_eq!(cache.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn overwriting_an_entry_does_not_evict() {
+        let cache = cache(2);
+        set(&cache, Some("a"), "u1", "pt1").await;
+        set(&cache, Some("a"), "u2", "pt1").await;
+        for _ in 0..10 {
+            set(&cache, Some("a"), "u2", "pt2").await;
+        }
+
+        assert!(cached(&cache, Some("a"), "u1").await);
+        assert!(cached(&cache, Some("a"), "u2").await);
+        // 覆盖留下的失效记录会被压缩，队列长度不随写入次数增长
+        assert!(cache.tenant_index.get("a").unwrap().order.len() <= 4);
+
+        // 覆盖后 u2 的写入时间最新，再写入新条目时淘汰 u1
+        set(&cache, Some("a"), "u3", "pt1").await;
+        assert!(!cached(&cache, Some("a"), "u1").await);
+        assert!(cached(&cache, Some("a"), "u2").await);
+    }
+
+    #[tokio::test]
+    async fn removed_entries_free_tenant_quota() {
+        let cache = cache(2);
+        set(&cache, Some("a"), "u1", "pt1").await;
+        set(&cache, Some("a"), "u2", "pt2").await;
+
+        assert_eq!(cache.invalidate_provider("pt2"), 1);
+        set(&cache, Some("a"), "u3", "pt1").await;
+        assert!(cached(&cache, Some("a"), "u1").await);
+        assert!(cached(&cache, Some("a"), "u3").await);
+
+        assert_eq!(cache.invalidate_token("u1", None), 1);
+        cache
+            .remove_config(Some("a"), "u3", "gpt-4o", &routes("pt1")[0])
+            .await;
+        set(&cache, Some("a"), "u4", "pt1").await;
+        set(&cache, Some("a"), "u5", "pt1").await;
+        assert!(cached(&cache, Some("a"), "u4").await);
+        assert!(cached(&cache, Some("a"), "u5").await);
+        assert_eq!(cache.tenant_index.get("a").unwrap().live, 2);
     }
 }
@@ -0,0 +1,102 @@
+use crate::protocol::anthropic::{AnthropicRequest, Message, MessageContent, Metadata};
+use serde_json::{Map, Value};
+
+impl AnthropicRequest {
+    /// 不含消息的 Messages 请求
+    pub fn new(model: impl Into<String>, max_tokens: i32) -> Self {
+        Self {
+            model: model.into(),
+            messages: Vec::new(),
+            max_tokens,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stream: None,
+            system: None,
+            stop_sequences: None,
+            metadata: None,
+            service_tier: None,
+            extra: Value::Object(Map::new()),
+        }
+    }
+
+    pub fn with_message(mut self, message: Message) -> Self {
+        self.messages.push(message);
+        self
+    }
+
+    pub fn with_user_message(self, text: impl Into<String>) -> Self {
+        self.with_message(Message::user(text))
+    }
+
+    pub fn with_assistant_message(self, text: impl Into<String>) -> Self {
+        self.with_message(Message::assistant(text))
+    }
+
+    pub fn with_system(mut self, system: impl Into<String>) -> Self {
+        self.system = Some(system.into());
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_top_p(mut self, top_p: f32) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    pub fn with_top_k(mut self, top_k: i32) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+
+    pub fn with_stop_sequences(mut self, stop_sequences: Vec<String>) -> Self {
+        self.stop_sequences = Some(stop_sequences);
+        self
+    }
+
+    pub fn with_stream(mut self) -> Self {
+        self.stream = Some(true);
+        self
+    }
+
+    /// 终端用户标识（`metadata.user_id`）
+    pub fn with_user_id(mut self, user_id: impl Into<String>) -> Self {
+        self.metadata = Some(Metadata {
+            user_id: Some(user_id.into()),
+        });
+        self
+    }
+
+    /// 结构中没有的请求参数（如 `tools`、`thinking`）
+    pub fn with_extra(mut self, key: impl Into<String>, value: Value) -> Self {
+        if !self.extra.is_object() {
+            self.extra = Value::Object(Map::new());
+        }
+        if let Value::Object(extra) = &mut self.extra {
+            extra.insert(key.into(), value);
+        }
+        self
+    }
+}
+
+impl Message {
+    /// 指定角色的文本消息
+    pub fn text(role: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            content: MessageContent::Text(text.into()),
+        }
+    }
+
+    pub fn user(text: impl Into<String>) -> Self {
+        Self::text("user", text)
+    }
+
+    pub fn assistant(text: impl Into<String>) -> Self {
+        Self::text("assistant", text)
+    }
+}
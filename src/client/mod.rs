@@ -0,0 +1,17 @@
+//! 类型化的客户端请求结构（`client` feature）
+//!
+//! 供部署在网关前面的服务构造请求和解析响应，直接复用网关的协议类型而不必重复定义：
+//! 请求结构提供 `new` 和 `with_*` 构建方法，响应和流式事件类型可直接反序列化。
+//! 流式响应中每个 SSE `data:` 的 JSON 对应一个 [`OpenAIStreamChunk`] 或 [`AnthropicStreamEvent`]。
+
+mod anthropic;
+mod openai;
+
+pub use crate::protocol::anthropic::{
+    AnthropicRequest, AnthropicResponse, AnthropicStreamEvent, ContentBlock, ContentDelta,
+    Message as AnthropicMessage, MessageContent as AnthropicMessageContent,
+};
+pub use crate::protocol::openai::{
+    ContentPart, Message as OpenAIMessage, MessageContent as OpenAIMessageContent, OpenAIRequest,
+    OpenAIResponse, OpenAIStreamChunk, Stop, ToolCall,
+};
@@ -0,0 +1,118 @@
+use crate::protocol::openai::{Message, MessageContent, OpenAIRequest, Stop};
+use serde_json::{json, Map, Value};
+
+impl OpenAIRequest {
+    /// 不含消息的 Chat Completions 请求
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+            messages: Vec::new(),
+            temperature: None,
+            max_tokens: None,
+            stream: None,
+            top_p: None,
+            frequency_penalty: None,
+            presence_penalty: None,
+            stop: None,
+            user: None,
+            service_tier: None,
+            extra: Value::Object(Map::new()),
+        }
+    }
+
+    pub fn with_message(mut self, message: Message) -> Self {
+        self.messages.push(message);
+        self
+    }
+
+    pub fn with_system_message(self, text: impl Into<String>) -> Self {
+        self.with_message(Message::system(text))
+    }
+
+    pub fn with_user_message(self, text: impl Into<String>) -> Self {
+        self.with_message(Message::user(text))
+    }
+
+    pub fn with_assistant_message(self, text: impl Into<String>) -> Self {
+        self.with_message(Message::assistant(text))
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: i32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_top_p(mut self, top_p: f32) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    pub fn with_stop(mut self, stop: Stop) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// 终端用户标识
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// 流式请求，`include_usage` 为 true 时流末尾返回用量 chunk
+    pub fn with_stream(mut self, include_usage: bool) -> Self {
+        self.stream = Some(true);
+        if include_usage {
+            self = self.with_extra("stream_options", json!({"include_usage": true}));
+        }
+        self
+    }
+
+    /// 结构中没有的请求参数（如 `tools`、`response_format`）
+    pub fn with_extra(mut self, key: impl Into<String>, value: Value) -> Self {
+        if !self.extra.is_object() {
+            self.extra = Value::Object(Map::new());
+        }
+        if let Value::Object(extra) = &mut self.extra {
+            extra.insert(key.into(), value);
+        }
+        self
+    }
+}
+
+impl Message {
+    /// 指定角色的文本消息
+    pub fn text(role: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            content: Some(MessageContent::Text(text.into())),
+            tool_calls: None,
+            tool_call_id: None,
+            extra: Map::new(),
+        }
+    }
+
+    pub fn system(text: impl Into<String>) -> Self {
+        Self::text("system", text)
+    }
+
+    pub fn user(text: impl Into<String>) -> Self {
+        Self::text("user", text)
+    }
+
+    pub fn assistant(text: impl Into<String>) -> Self {
+        Self::text("assistant", text)
+    }
+
+    /// 工具调用结果
+    pub fn tool(tool_call_id: impl Into<String>, text: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id.into()),
+            ..Self::text("tool", text)
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use crate::error::{Error, Result};
+use axum::http::HeaderMap;
+use dashmap::DashMap;
+use ipnet::IpNet;
+use std::hash::Hash;
+use std::net::IpAddr;
+
+/// 客户端IP解析器
+///
+/// 只有当直连对端位于受信任代理列表中时，才会采信 `Forwarded` /
+/// `X-Forwarded-For` 头；否则直接使用TCP对端地址，防止客户端伪造IP。
+pub struct ClientIpResolver {
+    trusted_proxies: Vec<IpNet>,
+}
+
+impl ClientIpResolver {
+    /// 根据受信任代理列表创建解析器
+    ///
+    /// # 参数
+    /// * `trusted_proxies` - CIDR（如 "10.0.0.0/8"）或单个IP地址列表
+    pub fn new(trusted_proxies: &[String]) -> Result<Self> {
+        let trusted_proxies = trusted_proxies
+            .iter()
+            .map(|s| {
+                s.parse::<IpNet>()
+                    .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| Error::Config(format!("Invalid trusted proxy: {}", s)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { trusted_proxies })
+    }
+
+    /// 地址是否属于受信任代理，只有受信任代理转发的请求才采信其附加的请求头
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(ip))
+    }
+
+    /// 解析真实客户端IP
+    ///
+    /// 从转发链右侧（最靠近网关的一跳）向左遍历，跳过受信任代理，
+    /// 返回第一个不受信任的地址；整条链都受信任时返回最左侧地址。
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(&peer) {
+            return peer;
+        }
+
+        let chain = forwarded_chain(headers);
+        for ip in chain.iter().rev() {
+            if !self.is_trusted(ip) {
+                return *ip;
+            }
+        }
+
+        chain.first().copied().unwrap_or(peer)
+    }
+}
+
+/// 提取转发链，优先使用标准的 `Forwarded` 头，其次 `X-Forwarded-For`
+fn forwarded_chain(headers: &HeaderMap) -> Vec<IpAddr> {
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                if key.eq_ignore_ascii_case("for") {
+                    parse_forwarded_node(value)
+                } else {
+                    None
+                }
+            })
+        })
+        .collect();
+
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+
+    headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|s| s.trim().parse().ok())
+        .collect()
+}
+
+/// 解析 `Forwarded` 中的节点，支持 `192.0.2.1`、`"192.0.2.1:8080"`、`"[2001:db8::1]:443"`
+fn parse_forwarded_node(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+
+    if let Some(rest) = value.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+
+    value
+        .parse()
+        .ok()
+        .or_else(|| value.rsplit_once(':')?.0.parse().ok())
+}
+
+/// 固定窗口限流器（每分钟请求数）
+pub struct RateLimiter<K> {
+    requests_per_minute: u32,
+    // Key: 限流对象，Value: (窗口起始分钟, 窗口内请求数)
+    windows: DashMap<K, (i64, u32)>,
+}
+
+/// 按客户端IP的限流器，启用多租户时按 (租户, IP) 分别计数，
+/// 不同租户共用出口IP时互不挤占限额
+pub type IpRateLimiter = RateLimiter<(Option<String>, IpAddr)>;
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(requests_per_minute: u32) -> Self {
+        Self {
+            requests_per_minute,
+            windows: DashMap::new(),
+        }
+    }
+
+    /// 记录一次请求，超过限额时返回 false
+    pub fn check(&self, key: K) -> bool {
+        let minute = chrono::Utc::now().timestamp() / 60;
+
+        // 条目过多时清理已过期窗口，避免内存无限增长
+        if self.windows.len() > 10_000 {
+            self.windows.retain(|_, (window, _)| *window == minute);
+        }
+
+        let mut entry = self.windows.entry(key).or_insert((minute, 0));
+        if entry.0 != minute {
+            *entry = (minute, 0);
+        }
+
+        if entry.1 >= self.requests_per_minute {
+            return false;
+        }
+
+        entry.1 += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver() -> ClientIpResolver {
+        ClientIpResolver::new(&["10.0.0.0/8".to_string(), "192.168.1.1".to_string()]).unwrap()
+    }
+
+    #[test]
+    fn trusts_only_configured_proxies() {
+        let resolver = resolver();
+        assert!(resolver.is_trusted(&"10.1.2.3".parse().unwrap()));
+        assert!(resolver.is_trusted(&"192.168.1.1".parse().unwrap()));
+        assert!(!resolver.is_trusted(&"203.0.113.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn ignores_forwarded_headers_from_untrusted_peers() {
+        let resolver = resolver();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "198.51.100.9, 10.0.0.2".parse().unwrap());
+
+        let client = resolver.resolve("10.0.0.1".parse().unwrap(), &headers);
+        assert_eq!(client, "198.51.100.9".parse::<IpAddr>().unwrap());
+        let peer = "203.0.113.7".parse().unwrap();
+        assert_eq!(resolver.resolve(peer, &headers), peer);
+    }
+}
@@ -4,33 +4,48 @@ use thiserror::Error;
 pub enum Error {
     #[error("Configuration error: {0}")]
     Config(String),
-    
+
     #[error("Protocol error: {0}")]
     Protocol(String),
-    
+
     #[error("Routing error: {0}")]
     Routing(String),
-    
+
+    #[error("Service unavailable: {0}")]
+    Unavailable(String),
+
     #[error("Proxy error: {0}")]
     Proxy(String),
-    
+
     #[error("Cache error: {0}")]
     Cache(String),
-    
+
+    #[error("Crypto error: {0}")]
+    Crypto(String),
+
     #[error("Telemetry error: {0}")]
     Telemetry(String),
-    
+
+    #[error("Authentication error: {0}")]
+    Auth(String),
+
     #[error("HTTP error: {0}")]
     Http(#[from] reqwest::Error),
-    
+
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    #[error("Redis error: {0}")]
+    Redis(#[from] redis::RedisError),
+
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
-    
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
-    
+
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
 
-pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
+pub type Result<T> = std::result::Result<T, Error>;
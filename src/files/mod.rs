@@ -0,0 +1,282 @@
+use crate::error::{Error, Result};
+use crate::ledger::Ledger;
+use crate::models::{ClientProtocol, RouteConfig};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, warn};
+
+/// 登记信息的保留时长，上游文件不会自动过期，超过该时长的登记在清理时丢弃
+const FILE_RETENTION: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// 登记数超过该数量时清理过期条目
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// 登记信息在账本数据库中的对象类型
+const OBJECT_KIND: &str = "file";
+
+/// 文件所在的上游及归属
+#[derive(Serialize, Deserialize)]
+pub struct FileRoute {
+    /// 上传文件的用户令牌
+    pub user_token: String,
+    pub tenant_id: Option<String>,
+    /// 上传文件时使用的路由，后续请求都发往该上游和供应商令牌
+    pub route: RouteConfig,
+    created_at: DateTime<Utc>,
+}
+
+impl FileRoute {
+    fn expired(&self) -> bool {
+        (Utc::now() - self.created_at)
+            .to_std()
+            .is_ok_and(|age| age >= FILE_RETENTION)
+    }
+}
+
+/// 已上传文件的登记表
+///
+/// 文件只存在于上传它的上游和供应商令牌下，登记表按文件ID记录该路由，并限制只有上传者可以访问。
+/// 配置了本地账本时登记信息同时写入账本数据库，网关重启后由 [`FileRegistry::restore`] 加载；
+/// 未配置时只保存在进程内，重启后此前上传的文件无法再通过网关访问。
+#[derive(Default)]
+pub struct FileRegistry {
+    files: DashMap<String, Arc<FileRoute>>,
+    ledger: Option<Arc<Ledger>>,
+}
+
+impl FileRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 登记信息写入账本数据库
+    pub fn with_ledger(mut self, ledger: Option<Arc<Ledger>>) -> Self {
+        self.ledger = ledger;
+        self
+    }
+
+    /// 从账本数据库加载仍在保留期内的文件，返回加载数量
+    pub async fn restore(&self) -> Result<usize> {
+        let Some(ledger) = &self.ledger else {
+            return Ok(0);
+        };
+        let mut restored = 0;
+        for object in ledger.load_objects(OBJECT_KIND, FILE_RETENTION).await? {
+            match serde_json::from_str::<FileRoute>(&object.payload) {
+                Ok(file) => {
+                    self.files.insert(object.id, Arc::new(file));
+                    restored += 1;
+                }
+                Err(e) => warn!("Skipping unreadable file record {}: {}", object.id, e),
+            }
+        }
+        Ok(restored)
+    }
+
+    /// 登记新上传的文件
+    pub async fn register(
+        &self,
+        file_id: String,
+        user_token: String,
+        tenant_id: Option<String>,
+        route: RouteConfig,
+    ) {
+        if self.files.len() > PRUNE_THRESHOLD {
+            self.files.retain(|_, file| !file.expired());
+        }
+
+        let file = Arc::new(FileRoute {
+            user_token,
+            tenant_id,
+            route,
+            created_at: Utc::now(),
+        });
+        self.persist(&file_id, &file).await;
+        self.files.insert(file_id, file);
+    }
+
+    // 写入失败只记录日志：文件已上传到上游，进程内的登记仍然可用
+    async fn persist(&self, file_id: &str, file: &FileRoute) {
+        let Some(ledger) = &self.ledger else {
+            return;
+        };
+        let result = match serde_json::to_string(file) {
+            Ok(payload) => {
+                ledger
+                    .save_object(OBJECT_KIND, file_id, &payload, file.created_at)
+                    .await
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            error!("Failed to persist file {}: {}", file_id, e);
+        }
+    }
+
+    /// 查找用户令牌名下的文件，不存在或不属于该令牌时返回 None
+    pub fn get(&self, file_id: &str, user_token: &str) -> Option<Arc<FileRoute>> {
+        self.files
+            .get(file_id)
+            .filter(|file| file.user_token == user_token)
+            .map(|file| file.clone())
+    }
+
+    /// 文件已在上游删除
+    pub async fn remove(&self, file_id: &str) {
+        self.files.remove(file_id);
+        if let Some(ledger) = &self.ledger {
+            if let Err(e) = ledger.delete_object(OBJECT_KIND, file_id).await {
+                error!("Failed to delete file record {}: {}", file_id, e);
+            }
+        }
+    }
+
+    /// 用户令牌名下的文件ID
+    pub fn owned_by(&self, user_token: &str) -> HashSet<String> {
+        self.files
+            .iter()
+            .filter(|file| file.user_token == user_token)
+            .map(|file| file.key().clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+/// 文件请求的客户端协议：带有 `anthropic-version` 或 `anthropic-beta` 请求头时为 Anthropic Files，
+/// 否则为 OpenAI Files
+pub fn client_protocol(headers: &axum::http::HeaderMap) -> ClientProtocol {
+    if headers.contains_key("anthropic-version") || headers.contains_key("anthropic-beta") {
+        ClientProtocol::Anthropic
+    } else {
+        ClientProtocol::OpenAI
+    }
+}
+
+/// 文件对象的ID
+pub fn file_id(body: &[u8]) -> Option<String> {
+    let v: Value = serde_json::from_slice(body).ok()?;
+    v.get("id")?.as_str().map(str::to_string)
+}
+
+/// 文件列表只保留 `owned` 中的文件，避免共用供应商令牌的其他用户的文件泄露给调用方
+pub fn filter_list(body: Bytes, owned: &HashSet<String>) -> Bytes {
+    let Ok(mut v) = serde_json::from_slice::<Value>(&body) else {
+        return body;
+    };
+    let Some(files) = v.get_mut("data").and_then(Value::as_array_mut) else {
+        return body;
+    };
+    files.retain(|file| {
+        file.get("id")
+            .and_then(Value::as_str)
+            .is_some_and(|id| owned.contains(id))
+    });
+    serde_json::to_vec(&v).map(Bytes::from).unwrap_or(body)
+}
+
+/// 限制上传请求体的字节数，超过 `max_bytes` 时以错误结束并设置 `exceeded`
+pub fn limit_upload<S, E>(
+    stream: S,
+    max_bytes: u64,
+    exceeded: Arc<AtomicBool>,
+) -> impl Stream<Item = Result<Bytes>> + Send
+where
+    S: Stream<Item = std::result::Result<Bytes, E>> + Send + 'static,
+    E: std::fmt::Display,
+{
+    let mut total = 0u64;
+    stream.map(move |chunk| {
+        let chunk = chunk.map_err(|e| Error::Proxy(format!("Failed to read upload: {}", e)))?;
+        total += chunk.len() as u64;
+        if total > max_bytes {
+            exceeded.store(true, Ordering::Relaxed);
+            return Err(Error::Proxy(format!("Upload exceeds {} bytes", max_bytes)));
+        }
+        Ok(chunk)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LedgerConfig;
+    use serde_json::json;
+
+    fn route() -> RouteConfig {
+        serde_json::from_value(json!({
+            "token": "sk-test",
+            "model": "gpt-4o",
+            "api": "https://api.openai.com",
+            "protocol": "openai",
+            "model_id": "m1",
+            "provider_id": "p1",
+            "provider_token_id": "pt1",
+        }))
+        .unwrap()
+    }
+
+    async fn ledger() -> Arc<Ledger> {
+        let config: LedgerConfig =
+            serde_json::from_value(json!({"url": "sqlite::memory:", "max_connections": 1}))
+                .unwrap();
+        Arc::new(Ledger::connect(&config).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn only_owner_can_access_file() {
+        let registry = FileRegistry::new();
+        registry
+            .register("file-1".into(), "alice".into(), None, route())
+            .await;
+
+        assert!(registry.get("file-1", "alice").is_some());
+        assert!(registry.get("file-1", "bob").is_none());
+        assert_eq!(registry.owned_by("alice").len(), 1);
+        assert!(registry.owned_by("bob").is_empty());
+    }
+
+    #[tokio::test]
+    async fn restores_files_from_ledger() {
+        let ledger = ledger().await;
+        let registry = FileRegistry::new().with_ledger(Some(ledger.clone()));
+        registry
+            .register("file-1".into(), "alice".into(), Some("t1".into()), route())
+            .await;
+        registry
+            .register("file-2".into(), "alice".into(), None, route())
+            .await;
+        registry.remove("file-2").await;
+
+        let restored = FileRegistry::new().with_ledger(Some(ledger));
+        assert_eq!(restored.restore().await.unwrap(), 1);
+        let file = restored.get("file-1", "alice").unwrap();
+        assert_eq!(file.tenant_id.as_deref(), Some("t1"));
+        assert_eq!(file.route.provider_token_id, "pt1");
+        assert!(restored.get("file-2", "alice").is_none());
+    }
+
+    #[test]
+    fn filters_list_to_owned_files() {
+        let body = Bytes::from(
+            json!({"object": "list", "data": [{"id": "file-1"}, {"id": "file-2"}]}).to_string(),
+        );
+        let owned = HashSet::from(["file-2".to_string()]);
+        let filtered: Value = serde_json::from_slice(&filter_list(body, &owned)).unwrap();
+        assert_eq!(filtered["data"], json!([{"id": "file-2"}]));
+    }
+}
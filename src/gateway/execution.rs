@@ -0,0 +1,454 @@
+//! 对话请求的路由尝试
+//!
+//! 流式和非流式请求共用一个请求上下文：生成请求ID，持有调试采样和流式字节记录，
+//! 按路由准备上游请求（包括非流式请求的路由竞速），统一处理失败尝试的错误上报、
+//! 客户端错误返回和故障转移，以及返回给客户端的响应头。
+//! 流式和非流式处理只负责各自的响应转换，新增的按尝试逻辑在这里实现一次即可。
+
+use crate::{
+    client_error_response, create_error_response, with_upstream_headers, AppState,
+    REQUEST_ID_HEADER,
+};
+use axongate_engine::{
+    error::Error,
+    logging::{fanout::StreamMeta, transcript::TranscriptRecorder, CaptureStage, RequestCapture},
+    models::{ClientProtocol, ErrorEvent, RouteConfig, UsageEvent},
+    protocol::{
+        agent_clients::AgentClient,
+        responses::{self, Bridge},
+        ProtocolAdapter,
+    },
+    proxy::{
+        compression::CompressionStats, generation_defaults, model_rewrite, output_cap, size_limit,
+        upstream_request_id_of, FailureClass, UpstreamResponse,
+    },
+    router::failover::FailoverQueue,
+    secrets::mask_token,
+    usage_collector::StreamUsageCollector,
+    Result,
+};
+use axum::{
+    body::{Body, Bytes},
+    http::{response::Builder, Response, StatusCode},
+};
+use chrono::Utc;
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+use tracing::{error, info};
+use uuid::Uuid;
+
+/// 单个对话请求在各路由尝试间共享的上下文
+pub struct RequestContext {
+    pub state: AppState,
+    pub client_protocol: ClientProtocol,
+    pub body_bytes: Bytes,
+    pub user_token: String,
+    pub requested_model: String,
+    pub request_path: String,
+    pub client_headers: reqwest::header::HeaderMap,
+    pub client_ip: String,
+    pub claims: Option<HashMap<String, serde_json::Value>>,
+    pub tenant_id: Option<String>,
+    /// 是否为流式请求
+    pub stream: bool,
+    /// 客户端是否使用旧版 functions/function_call 字段
+    pub legacy_functions: bool,
+    /// 客户端是否调用 Responses API
+    pub responses_client: bool,
+    /// 识别出的 Claude Code / Codex CLI 客户端
+    pub agent_client: Option<AgentClient>,
+    /// 网关请求ID，用于上报去重和查询流式字节记录
+    pub request_id: String,
+    pub capture: Option<RequestCapture>,
+    pub transcript: Option<TranscriptRecorder>,
+}
+
+// 路由竞速中完成的一次尝试
+pub struct RacedAttempt {
+    pub attempt: u32,
+    pub config: RouteConfig,
+    pub compression: Option<CompressionStats>,
+    pub forwarded: Result<UpstreamResponse<Bytes>>,
+    pub finished_at: Instant,
+}
+
+impl RequestContext {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        state: AppState,
+        client_protocol: ClientProtocol,
+        body_bytes: Bytes,
+        user_token: String,
+        requested_model: String,
+        request_path: String,
+        client_headers: reqwest::header::HeaderMap,
+        client_ip: String,
+        claims: Option<HashMap<String, serde_json::Value>>,
+        tenant_id: Option<String>,
+        stream: bool,
+        legacy_functions: bool,
+    ) -> Self {
+        Self {
+            state,
+            client_protocol,
+            body_bytes,
+            user_token,
+            requested_model,
+            client_ip,
+            claims,
+            tenant_id,
+            stream,
+            legacy_functions,
+            responses_client: request_path == responses::RESPONSES_PATH,
+            agent_client: AgentClient::detect(&client_headers),
+            request_path,
+            client_headers,
+            // 生成请求ID用于去重
+            request_id: Uuid::new_v4().to_string(),
+            capture: None,
+            transcript: None,
+        }
+    }
+
+    /// 令牌开启调试采样时记录本次请求的完整内容
+    pub fn with_capture(mut self, sampled: bool) -> Self {
+        self.capture = sampled.then(|| {
+            let capture = self
+                .state
+                .logging
+                .capture(&self.request_id, &self.user_token);
+            capture.record(CaptureStage::ClientRequest, None, &self.body_bytes);
+            capture
+        });
+        self
+    }
+
+    /// 客户端请求时记录流式响应的原始字节
+    pub fn with_transcript(mut self, transcript: bool) -> Self {
+        self.transcript = transcript
+            .then(|| {
+                self.state
+                    .transcripts
+                    .start(&self.request_id, &self.user_token)
+            })
+            .flatten();
+        self
+    }
+
+    /// 转发上游的客户端请求头，路由启用 Claude Code / Codex CLI 兼容处理时按目标协议调整
+    pub fn upstream_headers(&self, config: &RouteConfig) -> Cow<'_, reqwest::header::HeaderMap> {
+        let agents = self.state.compat.agents();
+        match self.agent_client.filter(|_| agents.enabled_for(config)) {
+            Some(client) => {
+                let mut headers = self.client_headers.clone();
+                agents.apply_headers(client, &config.protocol, &mut headers);
+                Cow::Owned(headers)
+            }
+            None => Cow::Borrowed(&self.client_headers),
+        }
+    }
+
+    /// 客户端接口与上游接口不同时在 Responses API 和 Chat Completions 之间转换
+    pub fn bridge(&self, config: &RouteConfig) -> Option<Bridge> {
+        responses::bridge(self.responses_client, config)
+    }
+
+    /// 上游接口路径，None 表示使用路由的默认路径
+    pub fn upstream_path(&self, bridge: Option<Bridge>) -> Option<&'static str> {
+        responses::upstream_path(self.responses_client, bridge)
+    }
+
+    /// 按路由准备上游请求：限制最大输出Token数、填入默认生成参数、转换为目标协议格式并压缩提示词，
+    /// 需要时在 Responses API 和 Chat Completions 之间转换，转换失败时返回 None
+    pub async fn prepare(
+        &self,
+        config: &RouteConfig,
+        bridge: Option<Bridge>,
+    ) -> Option<(Bytes, Option<CompressionStats>)> {
+        let state = &self.state;
+        // 按路由的输出上限限制请求的最大输出Token数
+        let request_body = match state.proxy.output_cap(config) {
+            Some(cap) => output_cap::clamp_max_tokens(self.body_bytes.clone(), cap),
+            None => self.body_bytes.clone(),
+        };
+        // 填入路由的默认生成参数
+        let request_body = match &config.generation_defaults {
+            Some(defaults) => generation_defaults::apply(request_body, defaults),
+            None => request_body,
+        };
+        // Claude Code / Codex CLI 请求按目标路由调整
+        let agents = state.compat.agents();
+        let request_body = match self.agent_client.filter(|_| agents.enabled_for(config)) {
+            Some(client) => agents.apply_request(
+                client,
+                &self.client_protocol,
+                &config.protocol,
+                bridge,
+                request_body,
+            ),
+            None => request_body,
+        };
+
+        // Responses API 请求先转换为 Chat Completions，再由协议适配器转换为目标协议格式
+        let request_body = match bridge {
+            Some(Bridge::ToChat) => responses::request_to_chat(&request_body),
+            _ => Ok(request_body),
+        };
+        // 按路由的改写规则使用上游要求的模型名
+        let model = model_rewrite::upstream_model(config);
+        let transformed_request = match request_body {
+            Ok(body) => {
+                state
+                    .adapter
+                    .transform_request(&self.client_protocol, &config.protocol, &model, body)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        let transformed_request = match transformed_request {
+            Ok(body) => body,
+            Err(e) => {
+                self.record_error(config, &format!("Failed to transform request: {}", e));
+                return None;
+            }
+        };
+        let (transformed_request, compression) = state
+            .proxy
+            .compress_prompt(config, transformed_request)
+            .await;
+        // 只支持 Responses API 的上游最后转换为 Responses API 请求
+        let transformed_request = match bridge {
+            Some(Bridge::ToResponses) => match responses::request_from_chat(&transformed_request) {
+                Ok(body) => body,
+                Err(e) => {
+                    self.record_error(
+                        config,
+                        &format!("Failed to convert request to Responses API: {}", e),
+                    );
+                    return None;
+                }
+            },
+            _ => transformed_request,
+        };
+        if let Some(capture) = &self.capture {
+            capture.record(
+                CaptureStage::UpstreamRequest,
+                Some(&config.api_endpoint),
+                &transformed_request,
+            );
+        }
+        Some((transformed_request, compression))
+    }
+
+    /// 路由竞速：同时向前 `width` 个路由转发，按完成顺序返回各尝试直到首个成功为止；
+    /// 其余仍在进行的请求随之取消（不上报用量），在路由追踪中记为取消
+    pub async fn race(&self, failover: &mut FailoverQueue, width: usize) -> VecDeque<RacedAttempt> {
+        let mut racers = FuturesUnordered::new();
+        for (attempt, config) in failover.next_race(width) {
+            let bridge = self.bridge(&config);
+            let custom_path = self.upstream_path(bridge);
+            let Some((request, compression)) = self.prepare(&config, bridge).await else {
+                // 切换为当前尝试，转向下一路由时记为跳过
+                failover.resume_race(attempt, Instant::now());
+                continue;
+            };
+            racers.push(async move {
+                let forwarded = self
+                    .state
+                    .proxy
+                    .forward_request(
+                        &config,
+                        request,
+                        custom_path,
+                        &self.upstream_headers(&config),
+                    )
+                    .await;
+                RacedAttempt {
+                    attempt,
+                    config,
+                    compression,
+                    forwarded,
+                    finished_at: Instant::now(),
+                }
+            });
+        }
+        if racers.is_empty() {
+            return VecDeque::new();
+        }
+        info!("Racing {} routes for non-stream request", racers.len());
+
+        let mut completed = VecDeque::new();
+        while let Some(raced) = racers.next().await {
+            let won = raced.forwarded.is_ok();
+            completed.push_back(raced);
+            if won {
+                break;
+            }
+        }
+        // 丢弃未完成的请求即取消
+        drop(racers);
+
+        let finished: Vec<u32> = completed.iter().map(|raced| raced.attempt).collect();
+        let cancelled = failover.settle_race(&finished);
+        if cancelled > 0 {
+            info!(
+                "Route race settled, cancelled {} slower request(s)",
+                cancelled
+            );
+            metrics::counter!("gateway_race_cancelled_total", cancelled as u64);
+        }
+        completed
+    }
+
+    /// 上游已返回响应：记录尝试成功和上游请求ID
+    pub fn upstream_responded(
+        &self,
+        failover: &mut FailoverQueue,
+        config: &RouteConfig,
+        upstream_request_id: Option<String>,
+    ) {
+        self.state.stats.record_attempt(config, true);
+        failover.record_upstream_request_id(upstream_request_id);
+    }
+
+    /// 记录本次尝试在网关内的处理失败（请求或响应转换失败），调用方随后转向下一路由
+    pub fn record_error(&self, config: &RouteConfig, message: &str) {
+        error!("{}", message);
+        if let Some(capture) = &self.capture {
+            capture.record_error(&config.api_endpoint, &message);
+        }
+    }
+
+    /// 上游请求失败：上报错误；客户端错误（4xx）和响应体超限直接返回给客户端的响应，
+    /// 否则从缓存中移除该路由并记录故障，返回 None 由调用方转向下一路由
+    pub async fn fail_attempt(
+        &self,
+        failover: &mut FailoverQueue,
+        attempt: u32,
+        config: &RouteConfig,
+        e: &Error,
+    ) -> Option<Response<Body>> {
+        let state = &self.state;
+        state.stats.record_attempt(config, false);
+        if let Some(capture) = &self.capture {
+            capture.record_error(&config.api_endpoint, e);
+        }
+        if self.stream {
+            error!("Stream request failed for {}: {}", config.api_endpoint, e);
+        } else {
+            error!("Request failed for {}: {}", config.api_endpoint, e);
+        }
+
+        // 上报错误
+        state.telemetry.report_error(ErrorEvent {
+            request_id: self.request_id.clone(),
+            attempt,
+            token: config.token.clone(),
+            model: config.model.clone(),
+            api: config.api_endpoint.clone(),
+            msg: e.to_string(),
+            provider_token_id: Some(config.provider_token_id.clone()),
+            client_ip: Some(self.client_ip.clone()),
+            claims: self.claims.clone(),
+            tenant_id: self.tenant_id.clone(),
+            upstream_request_id: upstream_request_id_of(e),
+        });
+        state
+            .telemetry
+            .usage_stats()
+            .record_error(&self.user_token, &config.provider_id);
+        failover.record_upstream_request_id(upstream_request_id_of(e));
+
+        // 检查是否为客户端错误（4xx），如果是则直接返回
+        if state.proxy.is_client_error(e) {
+            failover.record_client_error();
+            return Some(create_error_response(&self.client_protocol, e));
+        }
+        // 响应体超过网关上限：换路由通常得到同样大的响应，直接结束请求，不从缓存中移除路由
+        if size_limit::is_too_large(e) {
+            failover.record_failure(config, FailureClass::Deterministic);
+            return Some(create_error_response(&self.client_protocol, e));
+        }
+
+        // 从缓存中移除失败的配置
+        state
+            .router
+            .remove_failed_route(
+                &self.user_token,
+                self.tenant_id.as_deref(),
+                &self.requested_model,
+                config,
+            )
+            .await;
+        failover.record_failure(config, state.proxy.classify_failure(e));
+        None
+    }
+
+    /// 所有路由都失败时返回给客户端的响应
+    pub fn all_routes_failed(&self) -> Response<Body> {
+        let message = if self.stream {
+            "All stream routes failed"
+        } else {
+            "All routes failed"
+        };
+        client_error_response(
+            &self.client_protocol,
+            StatusCode::SERVICE_UNAVAILABLE,
+            message,
+        )
+    }
+
+    /// 成功响应的响应头：白名单内的上游响应头，开启字节记录时附加网关请求ID
+    pub fn response_builder(&self, upstream_headers: &reqwest::header::HeaderMap) -> Builder {
+        let builder = with_upstream_headers(Response::builder(), upstream_headers);
+        match &self.transcript {
+            Some(_) => builder.header(REQUEST_ID_HEADER, self.request_id.as_str()),
+            None => builder,
+        }
+    }
+
+    /// 按路由填好请求和身份字段的用量事件，Token数等由调用方补充
+    pub fn usage_event(&self, config: &RouteConfig) -> UsageEvent {
+        UsageEvent {
+            request_id: self.request_id.clone(),
+            token: self.user_token.clone(),
+            model: self.requested_model.clone(),
+            api: config.api_endpoint.clone(),
+            model_id: config.model_id.clone(),
+            provider_id: config.provider_id.clone(),
+            provider_token_id: config.provider_token_id.clone(),
+            canary: config.canary.as_ref().map(|c| c.tag.clone()),
+            client_ip: Some(self.client_ip.clone()),
+            claims: self.claims.clone(),
+            tenant_id: self.tenant_id.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// 流式响应分发给旁路消费者时附带的请求信息
+    pub fn stream_meta(&self) -> StreamMeta {
+        StreamMeta {
+            request_id: self.request_id.clone(),
+            token: mask_token(&self.user_token),
+            tenant_id: self.tenant_id.clone(),
+            model: self.requested_model.clone(),
+            path: self.request_path.clone(),
+            started_at: Utc::now(),
+        }
+    }
+
+    /// 按路由创建流式用量收集器
+    pub fn usage_collector(&self, config: &RouteConfig) -> StreamUsageCollector {
+        StreamUsageCollector::new(
+            self.request_id.clone(),
+            self.user_token.clone(),
+            Some(self.client_ip.clone()),
+            self.claims.clone(),
+            self.tenant_id.clone(),
+            config.clone(),
+            self.state.telemetry.clone(),
+        )
+    }
+}
@@ -0,0 +1,509 @@
+// `tonic::Status` 是 gRPC 接口约定的错误类型
+#![allow(clippy::result_large_err)]
+
+use super::Dispatch;
+use axongate_engine::protocol::framing::ClientStreamFormat;
+use axongate_engine::protocol::openai::{
+    self, ChunkUsage, FunctionCall, MessageContent, OpenAIRequest, OpenAIResponse,
+    OpenAIStreamChunk, Stop,
+};
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderValue, Request, Response, StatusCode};
+use bytes::BytesMut;
+use futures::{Stream, StreamExt};
+use serde_json::{json, Map, Value};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use tonic::Status;
+use tracing::Instrument;
+
+/// 由 `proto/gateway.proto` 生成的类型
+pub mod pb {
+    tonic::include_proto!("axongate.v1");
+}
+
+use pb::gateway_server::{Gateway, GatewayServer};
+use pb::{ChatChunk, ChatRequest, ChatResponse};
+
+/// 数据面 gRPC 服务
+///
+/// 类型化的请求映射为等价的 Chat Completions HTTP 请求交给 `dispatch` 处理，与 HTTP 接口共用认证、
+/// 路由、协议转换和遥测流程；响应和流式增量再映射回 protobuf 消息，内部服务无需处理 JSON 或 SSE。
+/// 流式请求以 NDJSON 格式取回增量，每行直接解析为一条 chunk。
+pub struct GatewayService {
+    dispatch: Dispatch,
+}
+
+impl GatewayService {
+    pub fn new(dispatch: Dispatch) -> Self {
+        Self { dispatch }
+    }
+
+    pub fn into_server(self) -> GatewayServer<Self> {
+        GatewayServer::new(self)
+    }
+
+    async fn call(
+        &self,
+        request: tonic::Request<ChatRequest>,
+        stream: bool,
+    ) -> Result<Response<Body>, Status> {
+        let peer = request
+            .remote_addr()
+            .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)));
+        let (metadata, _, message) = request.into_parts();
+
+        let body = serde_json::to_vec(&chat_request(message, stream)?)
+            .map_err(|e| Status::internal(format!("Failed to encode request body: {}", e)))?;
+        let mut http_request = Request::post("/v1/chat/completions")
+            .body(Body::from(body))
+            .map_err(|e| Status::internal(e.to_string()))?;
+        *http_request.headers_mut() = metadata.into_headers();
+        let headers = http_request.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        if stream {
+            headers.insert(
+                header::ACCEPT,
+                HeaderValue::from_static(ClientStreamFormat::Ndjson.content_type()),
+            );
+        }
+
+        let span = tracing::info_span!(
+            "grpc",
+            stream,
+            client_ip = tracing::field::Empty,
+            routing_trace = tracing::field::Empty
+        );
+        let response = (self.dispatch)(peer, http_request).instrument(span).await;
+
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap_or_default();
+        Err(Status::new(grpc_code(status), error_message(&body)))
+    }
+}
+
+#[tonic::async_trait]
+impl Gateway for GatewayService {
+    async fn complete(
+        &self,
+        request: tonic::Request<ChatRequest>,
+    ) -> Result<tonic::Response<ChatResponse>, Status> {
+        let response = self.call(request, false).await?;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .map_err(|e| Status::unavailable(format!("Failed to read response: {}", e)))?;
+        let response: OpenAIResponse = serde_json::from_slice(&body)
+            .map_err(|e| Status::internal(format!("Unexpected response body: {}", e)))?;
+
+        Ok(tonic::Response::new(chat_response(response)))
+    }
+
+    type StreamCompleteStream = Pin<Box<dyn Stream<Item = Result<ChatChunk, Status>> + Send>>;
+
+    async fn stream_complete(
+        &self,
+        request: tonic::Request<ChatRequest>,
+    ) -> Result<tonic::Response<Self::StreamCompleteStream>, Status> {
+        let response = self.call(request, true).await?;
+        let lines = ndjson_lines(response.into_body().into_data_stream());
+
+        let chunks = lines.map(|line| {
+            let line =
+                line.map_err(|e| Status::unavailable(format!("Stream interrupted: {}", e)))?;
+            let chunk: OpenAIStreamChunk = serde_json::from_slice(&line)
+                .map_err(|e| Status::internal(format!("Unexpected stream chunk: {}", e)))?;
+            chat_chunk(chunk)
+        });
+
+        Ok(tonic::Response::new(Box::pin(chunks)))
+    }
+}
+
+/// 类型化请求对应的 Chat Completions 请求；流式请求要求上游在最后返回用量
+fn chat_request(request: ChatRequest, stream: bool) -> Result<OpenAIRequest, Status> {
+    let mut extra = Map::new();
+    if !request.tools.is_empty() {
+        let tools = request
+            .tools
+            .into_iter()
+            .map(|tool| {
+                let parameters: Value = if tool.parameters.is_empty() {
+                    json!({"type": "object", "properties": {}})
+                } else {
+                    serde_json::from_str(&tool.parameters).map_err(|e| {
+                        Status::invalid_argument(format!(
+                            "Invalid parameters for tool {}: {}",
+                            tool.name, e
+                        ))
+                    })?
+                };
+                Ok(json!({
+                    "type": "function",
+                    "function": {
+                        "name": tool.name,
+                        "description": tool.description,
+                        "parameters": parameters,
+                    }
+                }))
+            })
+            .collect::<Result<Vec<_>, Status>>()?;
+        extra.insert("tools".to_string(), Value::Array(tools));
+    }
+    if stream {
+        extra.insert("stream_options".to_string(), json!({"include_usage": true}));
+    }
+
+    Ok(OpenAIRequest {
+        model: request.model,
+        messages: request.messages.into_iter().map(chat_message).collect(),
+        temperature: request.temperature,
+        max_tokens: request.max_tokens,
+        stream: Some(stream),
+        top_p: request.top_p,
+        frequency_penalty: None,
+        presence_penalty: None,
+        stop: (!request.stop.is_empty()).then_some(Stop::Many(request.stop)),
+        user: non_empty(request.user),
+        service_tier: None,
+        extra: Value::Object(extra),
+    })
+}
+
+fn chat_message(message: pb::ChatMessage) -> openai::Message {
+    // 只有工具调用的 assistant 消息省略 content
+    let content = (!message.content.is_empty() || message.tool_calls.is_empty())
+        .then_some(MessageContent::Text(message.content));
+    let tool_calls = message
+        .tool_calls
+        .into_iter()
+        .map(|call| openai::ToolCall {
+            id: call.id,
+            call_type: "function".to_string(),
+            function: FunctionCall {
+                name: call.name,
+                arguments: call.arguments,
+            },
+        })
+        .collect::<Vec<_>>();
+
+    openai::Message {
+        role: message.role,
+        content,
+        tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        tool_call_id: non_empty(message.tool_call_id),
+        extra: Map::new(),
+    }
+}
+
+fn chat_response(response: OpenAIResponse) -> ChatResponse {
+    let usage = &response.usage;
+    ChatResponse {
+        id: response.id,
+        model: response.model,
+        choices: response
+            .choices
+            .into_iter()
+            .map(|choice| pb::Choice {
+                index: choice.index.max(0) as u32,
+                message: Some(response_message(choice.message)),
+                finish_reason: choice.finish_reason.unwrap_or_default(),
+            })
+            .collect(),
+        usage: Some(pb::Usage {
+            prompt_tokens: usage.prompt_tokens.max(0) as u64,
+            completion_tokens: usage.completion_tokens.max(0) as u64,
+            total_tokens: usage.total_tokens.max(0) as u64,
+            cached_tokens: usage
+                .prompt_tokens_details
+                .as_ref()
+                .and_then(|details| details.cached_tokens)
+                .map_or(0, |tokens| tokens.max(0) as u64),
+            reasoning_tokens: usage
+                .completion_tokens_details
+                .as_ref()
+                .and_then(|details| details.reasoning_tokens)
+                .map_or(0, |tokens| tokens.max(0) as u64),
+        }),
+    }
+}
+
+fn response_message(message: openai::Message) -> pb::ChatMessage {
+    let content = match message.content {
+        Some(MessageContent::Text(text)) => text,
+        Some(MessageContent::Array(parts)) => parts
+            .into_iter()
+            .filter_map(|part| match part {
+                openai::ContentPart::Text { text } => Some(text),
+                _ => None,
+            })
+            .collect(),
+        None => String::new(),
+    };
+
+    pb::ChatMessage {
+        role: message.role,
+        content,
+        tool_calls: message
+            .tool_calls
+            .unwrap_or_default()
+            .into_iter()
+            .map(|call| pb::ToolCall {
+                id: call.id,
+                name: call.function.name,
+                arguments: call.function.arguments,
+            })
+            .collect(),
+        tool_call_id: message.tool_call_id.unwrap_or_default(),
+    }
+}
+
+/// 流式增量对应的消息，流中途的错误结束 gRPC 流
+fn chat_chunk(chunk: OpenAIStreamChunk) -> Result<ChatChunk, Status> {
+    if let Some(error) = chunk.error {
+        return Err(Status::unavailable(
+            error
+                .message
+                .unwrap_or_else(|| "Upstream stream failed".to_string()),
+        ));
+    }
+
+    Ok(ChatChunk {
+        id: chunk.id.unwrap_or_default(),
+        model: chunk.model.unwrap_or_default(),
+        choices: chunk
+            .choices
+            .into_iter()
+            .map(|choice| pb::ChunkChoice {
+                index: choice.index,
+                delta: Some(pb::Delta {
+                    role: choice.delta.role.unwrap_or_default(),
+                    content: choice.delta.content.unwrap_or_default(),
+                    reasoning_content: choice.delta.reasoning_content.unwrap_or_default(),
+                    tool_calls: choice
+                        .delta
+                        .tool_calls
+                        .into_iter()
+                        .map(|call| {
+                            let function = call.function.unwrap_or_default();
+                            pb::ToolCallDelta {
+                                index: call.index as u32,
+                                id: call.id.unwrap_or_default(),
+                                name: function.name.unwrap_or_default(),
+                                arguments: function.arguments.unwrap_or_default(),
+                            }
+                        })
+                        .collect(),
+                }),
+                finish_reason: choice.finish_reason.unwrap_or_default(),
+            })
+            .collect(),
+        usage: chunk.usage.map(chunk_usage),
+    })
+}
+
+// Responses API 风格的 input/output 字段作为 prompt/completion 的回退
+fn chunk_usage(usage: ChunkUsage) -> pb::Usage {
+    let tokens = |value: Option<i64>| value.map_or(0, |tokens| tokens.max(0) as u64);
+    let prompt_details = usage.prompt_tokens_details.or(usage.input_tokens_details);
+    let completion_details = usage
+        .completion_tokens_details
+        .or(usage.output_tokens_details);
+    pb::Usage {
+        prompt_tokens: tokens(usage.prompt_tokens.or(usage.input_tokens)),
+        completion_tokens: tokens(usage.completion_tokens.or(usage.output_tokens)),
+        total_tokens: tokens(usage.total_tokens),
+        cached_tokens: tokens(prompt_details.and_then(|details| details.cached_tokens)),
+        reasoning_tokens: tokens(completion_details.and_then(|details| details.reasoning_tokens)),
+    }
+}
+
+/// 按行拆分 NDJSON 响应体，跳过空行
+fn ndjson_lines<S, E>(body: S) -> impl Stream<Item = Result<Bytes, E>> + Send
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Send + 'static,
+{
+    async_stream::stream! {
+        let mut body = Box::pin(body);
+        let mut buffer = BytesMut::new();
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(chunk) => buffer.extend_from_slice(&chunk),
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+            while let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+                let line = buffer.split_to(end + 1).freeze().slice(..end);
+                if !line.trim_ascii().is_empty() {
+                    yield Ok(line);
+                }
+            }
+        }
+        if !buffer.trim_ascii().is_empty() {
+            yield Ok(buffer.freeze());
+        }
+    }
+}
+
+// 错误响应体中的错误信息（OpenAI 格式 `error.message`），无法解析时使用原文
+fn error_message(body: &[u8]) -> String {
+    serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|v| v["error"]["message"].as_str().map(str::to_string))
+        .unwrap_or_else(|| String::from_utf8_lossy(body).into_owned())
+}
+
+fn non_empty(value: String) -> Option<String> {
+    (!value.is_empty()).then_some(value)
+}
+
+/// HTTP 状态码对应的 gRPC 状态码
+fn grpc_code(status: StatusCode) -> tonic::Code {
+    match status {
+        StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => tonic::Code::InvalidArgument,
+        StatusCode::UNAUTHORIZED => tonic::Code::Unauthenticated,
+        StatusCode::FORBIDDEN => tonic::Code::PermissionDenied,
+        StatusCode::NOT_FOUND => tonic::Code::NotFound,
+        StatusCode::TOO_MANY_REQUESTS => tonic::Code::ResourceExhausted,
+        StatusCode::GATEWAY_TIMEOUT | StatusCode::REQUEST_TIMEOUT => tonic::Code::DeadlineExceeded,
+        StatusCode::SERVICE_UNAVAILABLE | StatusCode::BAD_GATEWAY => tonic::Code::Unavailable,
+        _ => tonic::Code::Internal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_request_to_chat_completions() {
+        let request = ChatRequest {
+            model: "gpt-4o".to_string(),
+            messages: vec![
+                pb::ChatMessage {
+                    role: "user".to_string(),
+                    content: "weather?".to_string(),
+                    ..Default::default()
+                },
+                pb::ChatMessage {
+                    role: "assistant".to_string(),
+                    tool_calls: vec![pb::ToolCall {
+                        id: "call_1".to_string(),
+                        name: "get_weather".to_string(),
+                        arguments: r#"{"city":"Paris"}"#.to_string(),
+                    }],
+                    ..Default::default()
+                },
+            ],
+            max_tokens: Some(64),
+            tools: vec![pb::Tool {
+                name: "get_weather".to_string(),
+                parameters: r#"{"type":"object"}"#.to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let body = serde_json::to_value(chat_request(request, true).unwrap()).unwrap();
+        assert_eq!(body["model"], "gpt-4o");
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["max_tokens"], 64);
+        assert_eq!(body["stream_options"]["include_usage"], true);
+        assert_eq!(body["messages"][0]["content"], "weather?");
+        assert!(body["messages"][1]["content"].is_null());
+        assert_eq!(
+            body["messages"][1]["tool_calls"][0]["function"]["name"],
+            "get_weather"
+        );
+        assert_eq!(
+            body["tools"][0]["function"]["parameters"],
+            json!({"type": "object"})
+        );
+        assert!(body.get("temperature").is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_tool_parameters() {
+        let request = ChatRequest {
+            tools: vec![pb::Tool {
+                name: "broken".to_string(),
+                parameters: "{".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let status = chat_request(request, false).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn maps_response() {
+        let response: OpenAIResponse = serde_json::from_value(json!({
+            "id": "c1", "object": "chat.completion", "created": 1, "model": "m",
+            "choices": [{"index": 0, "finish_reason": "stop",
+                "message": {"role": "assistant", "content": "hi"}}],
+            "usage": {"prompt_tokens": 3, "completion_tokens": 2, "total_tokens": 5,
+                "prompt_tokens_details": {"cached_tokens": 1}}
+        }))
+        .unwrap();
+
+        let response = chat_response(response);
+        let message = response.choices[0].message.as_ref().unwrap();
+        assert_eq!(
+            (message.role.as_str(), message.content.as_str()),
+            ("assistant", "hi")
+        );
+        assert_eq!(response.choices[0].finish_reason, "stop");
+        let usage = response.usage.unwrap();
+        assert_eq!((usage.prompt_tokens, usage.cached_tokens), (3, 1));
+    }
+
+    #[test]
+    fn maps_stream_chunks_and_errors() {
+        let chunk: OpenAIStreamChunk = serde_json::from_value(json!({
+            "id": "c1", "model": "m",
+            "choices": [{"index": 0, "delta": {"content": "he", "tool_calls": [
+                {"index": 0, "id": "call_1", "function": {"name": "f", "arguments": "{"}}
+            ]}}],
+            "usage": {"prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4}
+        }))
+        .unwrap();
+        let chunk = chat_chunk(chunk).unwrap();
+        let delta = chunk.choices[0].delta.as_ref().unwrap();
+        assert_eq!(delta.content, "he");
+        assert_eq!(delta.tool_calls[0].arguments, "{");
+        assert_eq!(chunk.usage.unwrap().total_tokens, 4);
+
+        let error: OpenAIStreamChunk =
+            serde_json::from_value(json!({"error": {"message": "too large"}})).unwrap();
+        let status = chat_chunk(error).unwrap_err();
+        assert_eq!(status.message(), "too large");
+    }
+
+    #[tokio::test]
+    async fn splits_ndjson_lines() {
+        let body = futures::stream::iter(
+            ["{\"a\":1}\n{\"b\"", ":2}\n\n", "{\"c\":3}"].map(|c| Ok::<_, ()>(Bytes::from(c))),
+        );
+        let lines: Vec<_> = ndjson_lines(body).map(Result::unwrap).collect().await;
+        assert_eq!(lines, ["{\"a\":1}", "{\"b\":2}", "{\"c\":3}"]);
+    }
+
+    #[test]
+    fn extracts_error_message() {
+        let body = br#"{"error":{"message":"Invalid authorization","type":"gateway_error"}}"#;
+        assert_eq!(error_message(body), "Invalid authorization");
+        assert_eq!(error_message(b"plain"), "plain");
+    }
+}
@@ -0,0 +1,24 @@
+//! 网关入口的请求处理（仅服务端二进制使用，依赖入口中的 `AppState`）
+
+pub mod execution;
+pub mod grpc;
+pub mod ws;
+
+use crate::{handle_request, AppState};
+use axum::body::Body;
+use axum::extract::{ConnectInfo, State};
+use axum::http::{Request, Response};
+use futures::future::BoxFuture;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// 请求分发函数：接收客户端地址和等价的 HTTP 请求，返回 HTTP 响应
+pub type Dispatch =
+    Arc<dyn Fn(SocketAddr, Request<Body>) -> BoxFuture<'static, Response<Body>> + Send + Sync>;
+
+/// 将请求交给 `handle_request` 处理的分发函数，供 gRPC、WebSocket 等传输复用同一流程
+pub fn dispatcher(state: AppState) -> Dispatch {
+    Arc::new(move |peer, req| {
+        Box::pin(handle_request(State(state.clone()), ConnectInfo(peer), req))
+    })
+}
@@ -0,0 +1,119 @@
+use super::Dispatch;
+use axongate_engine::protocol::framing;
+use axum::body::Body;
+use axum::extract::ws::{Message, WebSocket};
+use axum::http::{header, HeaderMap, HeaderValue, Method, Request, Uri};
+use futures::StreamExt;
+use std::net::SocketAddr;
+use tracing::{debug, Instrument};
+
+/// 握手请求中不转发给处理流程的请求头
+const HANDSHAKE_HEADERS: [&str; 6] = [
+    "connection",
+    "upgrade",
+    "sec-websocket-key",
+    "sec-websocket-version",
+    "sec-websocket-extensions",
+    "sec-websocket-protocol",
+];
+
+/// WebSocket 流式传输，供 SSE 会被企业代理缓冲或中断的客户端使用
+///
+/// 客户端每发送一条消息（JSON 请求体）即发起一次流式请求，网关将转换后的流式事件
+/// 逐条以文本消息返回，内容为 SSE 事件的 `data`（OpenAI 协议以 `[DONE]` 结束）。
+/// 请求失败时返回一条错误 JSON 消息。一个请求完成后可在同一连接上发送下一个请求。
+///
+/// 认证等请求头取自握手请求，按 `path` 对应的客户端协议处理。
+pub async fn serve(
+    mut socket: WebSocket,
+    dispatch: Dispatch,
+    peer: SocketAddr,
+    path: &'static str,
+    mut headers: HeaderMap,
+) {
+    for name in HANDSHAKE_HEADERS {
+        headers.remove(name);
+    }
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+
+    while let Some(message) = socket.recv().await {
+        let body = match message {
+            Ok(Message::Text(text)) => text.into_bytes(),
+            Ok(Message::Binary(data)) => data,
+            Ok(Message::Close(_)) | Err(_) => break,
+            Ok(_) => continue,
+        };
+
+        let span = tracing::info_span!(
+            "websocket",
+            path,
+            client_ip = tracing::field::Empty,
+            routing_trace = tracing::field::Empty
+        );
+        if !forward(&mut socket, &dispatch, peer, path, &headers, body)
+            .instrument(span)
+            .await
+        {
+            break;
+        }
+    }
+
+    debug!("WebSocket connection from {} closed", peer);
+}
+
+/// 处理一个请求并把响应写回客户端，客户端断开时返回 false
+async fn forward(
+    socket: &mut WebSocket,
+    dispatch: &Dispatch,
+    peer: SocketAddr,
+    path: &'static str,
+    headers: &HeaderMap,
+    body: Vec<u8>,
+) -> bool {
+    // 始终以流式请求处理
+    let body = match serde_json::from_slice::<serde_json::Value>(&body) {
+        Ok(serde_json::Value::Object(mut fields)) => {
+            fields.insert("stream".to_string(), serde_json::Value::Bool(true));
+            serde_json::to_vec(&fields).unwrap_or(body)
+        }
+        _ => body,
+    };
+
+    let mut request = Request::new(Body::from(body));
+    *request.method_mut() = Method::POST;
+    *request.uri_mut() = Uri::from_static(path);
+    *request.headers_mut() = headers.clone();
+
+    let response = dispatch(peer, request).await;
+    if !response.status().is_success() {
+        // 错误响应体是客户端协议格式的 JSON
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap_or_default();
+        return socket
+            .send(Message::Text(String::from_utf8_lossy(&body).into_owned()))
+            .await
+            .is_ok();
+    }
+
+    let mut events = framing::sse_events(response.into_body().into_data_stream());
+    while let Some(event) = events.next().await {
+        let message = match event {
+            Ok(event) => event.data,
+            Err(e) => {
+                debug!("WebSocket stream interrupted: {}", e);
+                let _ = socket.send(Message::Close(None)).await;
+                return false;
+            }
+        };
+        // 客户端断开时丢弃剩余的流，上游请求随之取消
+        if socket.send(Message::Text(message)).await.is_err() {
+            return false;
+        }
+    }
+
+    true
+}
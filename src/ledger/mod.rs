@@ -0,0 +1,425 @@
+use crate::config::LedgerConfig;
+use crate::error::{Error, Result};
+use crate::secrets::{mask_token, TokenCipher};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::any::{AnyPoolOptions, AnyRow};
+use sqlx::{AnyPool, Row};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+
+/// 账本中的事件类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Usage,
+    Error,
+    Alert,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Usage => "usage",
+            Self::Error => "error",
+            Self::Alert => "alert",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "usage" => Some(Self::Usage),
+            "error" => Some(Self::Error),
+            "alert" => Some(Self::Alert),
+            _ => None,
+        }
+    }
+
+    /// 对应的业务API默认上报路径
+    pub fn telemetry_path(&self) -> &'static str {
+        match self {
+            Self::Usage => "/v1/telemetry/usage",
+            Self::Error => "/v1/telemetry/errors",
+            Self::Alert => "/v1/telemetry/alerts",
+        }
+    }
+}
+
+/// 账本记录
+#[derive(Debug, Clone, Serialize)]
+pub struct LedgerEntry {
+    pub id: String,
+    pub kind: EventKind,
+    pub request_id: String,
+    pub model: String,
+    /// 事件原文（用户令牌已脱敏）
+    pub payload: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+    /// 业务API确认时间，未确认时为 None
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    /// 对账任务重新上报的次数
+    pub attempts: i64,
+}
+
+/// 待重新上报的事件
+#[derive(Debug, Clone)]
+pub struct PendingEvent {
+    pub id: String,
+    pub kind: EventKind,
+    pub payload: Vec<u8>,
+}
+
+/// 账本查询条件
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LedgerQuery {
+    pub kind: Option<EventKind>,
+    pub request_id: Option<String>,
+    /// 只返回未被业务API确认的事件
+    #[serde(default)]
+    pub unacknowledged: bool,
+    /// 只返回该时间之后写入的事件（RFC3339）
+    pub since: Option<DateTime<Utc>>,
+    /// 最多返回条数，默认100，最大1000
+    pub limit: Option<u32>,
+}
+
+const DEFAULT_QUERY_LIMIT: u32 = 100;
+const MAX_QUERY_LIMIT: u32 = 1000;
+
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS ledger_events (
+        id TEXT PRIMARY KEY,
+        kind TEXT NOT NULL,
+        request_id TEXT NOT NULL,
+        model TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        created_at BIGINT NOT NULL,
+        acknowledged_at BIGINT,
+        attempts BIGINT NOT NULL DEFAULT 0
+    )",
+    "CREATE INDEX IF NOT EXISTS ledger_events_pending ON ledger_events (acknowledged_at, created_at)",
+    "CREATE INDEX IF NOT EXISTS ledger_events_request_id ON ledger_events (request_id)",
+    "CREATE TABLE IF NOT EXISTS gateway_objects (
+        kind TEXT NOT NULL,
+        id TEXT NOT NULL,
+        payload TEXT NOT NULL,
+        created_at BIGINT NOT NULL,
+        PRIMARY KEY (kind, id)
+    )",
+];
+
+/// 网关登记的上游对象（批处理、文件）
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub id: String,
+    pub payload: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 本地使用量账本
+///
+/// 遥测事件在上报业务API前写入本地数据库（SQLite 或 Postgres），
+/// 业务API确认后标记为已确认；未确认的事件由对账任务重新上报。
+/// 时间统一以Unix毫秒存储，保证两种数据库的SQL一致。
+/// 配置了令牌加密时，事件原文（含供应商令牌）加密后存储。
+pub struct Ledger {
+    pool: AnyPool,
+    config: LedgerConfig,
+    cipher: Option<Arc<TokenCipher>>,
+}
+
+impl Ledger {
+    /// 连接数据库并创建表结构
+    pub async fn connect(config: &LedgerConfig) -> Result<Self> {
+        sqlx::any::install_default_drivers();
+
+        let pool = AnyPoolOptions::new()
+            .max_connections(config.max_connections)
+            .connect(&config.url)
+            .await?;
+
+        for statement in SCHEMA {
+            sqlx::query(statement).execute(&pool).await?;
+        }
+
+        // 连接地址可能包含密码，只记录数据库类型
+        info!(
+            "Usage ledger enabled ({})",
+            config.url.split(':').next().unwrap_or_default()
+        );
+
+        Ok(Self {
+            pool,
+            config: config.clone(),
+            cipher: None,
+        })
+    }
+
+    /// 加密存储事件原文
+    pub fn with_token_cipher(mut self, cipher: Option<Arc<TokenCipher>>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    fn seal_payload(&self, payload: &str) -> Result<String> {
+        match &self.cipher {
+            Some(cipher) => cipher.seal(payload),
+            None => Ok(payload.to_string()),
+        }
+    }
+
+    // 未加密的历史记录原样返回
+    fn open_payload(&self, payload: &str) -> Result<String> {
+        match &self.cipher {
+            Some(cipher) => cipher.open(payload),
+            None => Ok(payload.to_string()),
+        }
+    }
+
+    pub fn config(&self) -> &LedgerConfig {
+        &self.config
+    }
+
+    /// 记录一个事件，返回记录ID
+    pub async fn record(
+        &self,
+        kind: EventKind,
+        request_id: &str,
+        model: &str,
+        payload: &[u8],
+    ) -> Result<String> {
+        let id = uuid::Uuid::now_v7().to_string();
+        let payload = std::str::from_utf8(payload)
+            .map_err(|e| Error::Telemetry(format!("Event payload is not UTF-8: {}", e)))?;
+        let payload = self.seal_payload(payload)?;
+
+        sqlx::query(
+            "INSERT INTO ledger_events (id, kind, request_id, model, payload, created_at, attempts)
+             VALUES ($1, $2, $3, $4, $5, $6, 0)",
+        )
+        .bind(&id)
+        .bind(kind.as_str())
+        .bind(request_id)
+        .bind(model)
+        .bind(&payload)
+        .bind(Utc::now().timestamp_millis())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(id)
+    }
+
+    /// 标记事件已被业务API确认
+    pub async fn acknowledge(&self, id: &str) -> Result<()> {
+        sqlx::query("UPDATE ledger_events SET acknowledged_at = $1 WHERE id = $2")
+            .bind(Utc::now().timestamp_millis())
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// 记录一次重新上报尝试
+    pub async fn record_attempt(&self, id: &str) -> Result<()> {
+        sqlx::query("UPDATE ledger_events SET attempts = attempts + 1 WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// 取出需要重新上报的事件：未确认、写入时间早于 `reconcile_after`、重试次数未超限
+    pub async fn pending(&self) -> Result<Vec<PendingEvent>> {
+        let cutoff = millis_before(self.config.reconcile_after);
+
+        let rows = sqlx::query(
+            "SELECT id, kind, payload FROM ledger_events
+             WHERE acknowledged_at IS NULL AND created_at <= $1 AND attempts < $2
+             ORDER BY created_at
+             LIMIT $3",
+        )
+        .bind(cutoff)
+        .bind(self.config.max_attempts as i64)
+        .bind(self.config.reconcile_batch as i64)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                let kind: String = row.try_get("kind")?;
+                let payload: String = row.try_get("payload")?;
+                Ok(PendingEvent {
+                    id: row.try_get("id")?,
+                    kind: EventKind::parse(&kind).ok_or_else(|| {
+                        Error::Telemetry(format!("Unknown event kind {:?}", kind))
+                    })?,
+                    payload: self.open_payload(&payload)?.into_bytes(),
+                })
+            })
+            .collect()
+    }
+
+    /// 删除超过保留时长的已确认事件，返回删除条数
+    pub async fn prune(&self) -> Result<u64> {
+        let result = sqlx::query(
+            "DELETE FROM ledger_events WHERE acknowledged_at IS NOT NULL AND created_at < $1",
+        )
+        .bind(millis_before(self.config.retention))
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// 保存网关登记的上游对象，同类型同ID的对象已存在时覆盖其内容
+    ///
+    /// 批处理、文件只存在于创建它的上游和供应商令牌下，登记信息与账本共用数据库，
+    /// 网关重启后仍可找到对象所在的路由。对象内容含令牌，配置了令牌加密时加密后存储。
+    pub async fn save_object(
+        &self,
+        kind: &str,
+        id: &str,
+        payload: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let payload = self.seal_payload(payload)?;
+
+        sqlx::query(
+            "INSERT INTO gateway_objects (kind, id, payload, created_at) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (kind, id) DO UPDATE SET payload = excluded.payload",
+        )
+        .bind(kind)
+        .bind(id)
+        .bind(&payload)
+        .bind(created_at.timestamp_millis())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// 删除网关登记的上游对象
+    pub async fn delete_object(&self, kind: &str, id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM gateway_objects WHERE kind = $1 AND id = $2")
+            .bind(kind)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// 取出某类型在 `retention` 内登记的全部对象，并删除更早的对象
+    pub async fn load_objects(&self, kind: &str, retention: Duration) -> Result<Vec<StoredObject>> {
+        let cutoff = millis_before(retention);
+        sqlx::query("DELETE FROM gateway_objects WHERE kind = $1 AND created_at < $2")
+            .bind(kind)
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?;
+
+        let rows = sqlx::query(
+            "SELECT id, payload, created_at FROM gateway_objects WHERE kind = $1 ORDER BY created_at",
+        )
+        .bind(kind)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.iter()
+            .map(|row| {
+                let payload: String = row.try_get("payload")?;
+                Ok(StoredObject {
+                    id: row.try_get("id")?,
+                    payload: self.open_payload(&payload)?,
+                    created_at: from_millis(row.try_get("created_at")?),
+                })
+            })
+            .collect()
+    }
+
+    /// 按条件查询事件，按写入时间倒序，返回的事件中用户令牌已脱敏
+    pub async fn query(&self, query: &LedgerQuery) -> Result<Vec<LedgerEntry>> {
+        let mut conditions = Vec::new();
+        let mut next_param = 1;
+        let mut param = || {
+            let placeholder = format!("${}", next_param);
+            next_param += 1;
+            placeholder
+        };
+
+        if query.kind.is_some() {
+            conditions.push(format!("kind = {}", param()));
+        }
+        if query.request_id.is_some() {
+            conditions.push(format!("request_id = {}", param()));
+        }
+        if query.since.is_some() {
+            conditions.push(format!("created_at >= {}", param()));
+        }
+        if query.unacknowledged {
+            conditions.push("acknowledged_at IS NULL".to_string());
+        }
+
+        let mut sql =
+            "SELECT id, kind, request_id, model, payload, created_at, acknowledged_at, attempts
+                       FROM ledger_events"
+                .to_string();
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        sql.push_str(&format!(" ORDER BY created_at DESC LIMIT {}", param()));
+
+        let mut statement = sqlx::query(&sql);
+        if let Some(kind) = query.kind {
+            statement = statement.bind(kind.as_str());
+        }
+        if let Some(request_id) = &query.request_id {
+            statement = statement.bind(request_id);
+        }
+        if let Some(since) = query.since {
+            statement = statement.bind(since.timestamp_millis());
+        }
+        let limit = query
+            .limit
+            .unwrap_or(DEFAULT_QUERY_LIMIT)
+            .clamp(1, MAX_QUERY_LIMIT);
+        statement = statement.bind(limit as i64);
+
+        let rows = statement.fetch_all(&self.pool).await?;
+        rows.iter().map(|row| self.entry_from_row(row)).collect()
+    }
+
+    fn entry_from_row(&self, row: &AnyRow) -> Result<LedgerEntry> {
+        let kind: String = row.try_get("kind")?;
+        let payload: String = row.try_get("payload")?;
+        let acknowledged_at: Option<i64> = row.try_get("acknowledged_at")?;
+
+        let mut payload: serde_json::Value = serde_json::from_str(&self.open_payload(&payload)?)?;
+        if let Some(token) = payload.get_mut("token") {
+            if let Some(masked) = token.as_str().map(mask_token) {
+                *token = serde_json::Value::String(masked);
+            }
+        }
+
+        Ok(LedgerEntry {
+            id: row.try_get("id")?,
+            kind: EventKind::parse(&kind)
+                .ok_or_else(|| Error::Telemetry(format!("Unknown event kind {:?}", kind)))?,
+            request_id: row.try_get("request_id")?,
+            model: row.try_get("model")?,
+            payload,
+            created_at: from_millis(row.try_get("created_at")?),
+            acknowledged_at: acknowledged_at.map(from_millis),
+            attempts: row.try_get("attempts")?,
+        })
+    }
+}
+
+fn millis_before(duration: Duration) -> i64 {
+    Utc::now().timestamp_millis() - duration.as_millis().min(i64::MAX as u128) as i64
+}
+
+fn from_millis(millis: i64) -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .unwrap_or_default()
+}
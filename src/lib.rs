@@ -1,11 +1,23 @@
+pub mod auth;
+pub mod batches;
+pub mod business_auth;
 pub mod cache;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod client_ip;
 pub mod config;
 pub mod error;
+pub mod files;
+pub mod ledger;
+pub mod logging;
 pub mod models;
 pub mod protocol;
 pub mod proxy;
 pub mod router;
+pub mod secrets;
+pub mod stats;
 pub mod telemetry;
+pub mod usage;
 pub mod usage_collector;
 
 pub use error::{Error, Result};
\ No newline at end of file
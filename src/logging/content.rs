@@ -0,0 +1,274 @@
+//! 对话内容记录
+//!
+//! 面向开通质量评审的客户：业务API在路由解析响应中为用户令牌下发 `content_logging` 策略后，
+//! 该令牌的客户端请求和返回客户端的响应内容被记录下来。与调试采样和审计元数据分开，
+//! 记录经有界队列交给后台任务写入配置的存储（本地 JSONL 文件或 HTTP 地址），
+//! 写入跟不上时丢弃新记录，不影响请求处理。每条记录附带保留标签，由存储侧按标签清理。
+
+use crate::config::{ContentLoggingConfig, ContentSinkConfig};
+use crate::error::{Error, Result};
+use crate::models::ContentLoggingPolicy;
+use crate::secrets::mask_token;
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{mpsc, Mutex};
+use tracing::{error, info};
+
+/// 一条对话内容记录
+#[derive(Debug, Clone, Serialize)]
+pub struct ContentRecord {
+    pub request_id: String,
+    /// 已脱敏的用户令牌
+    pub token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    pub model: String,
+    pub path: String,
+    /// 保留标签，存储侧据此清理
+    pub retention: String,
+    /// 返回客户端的状态码
+    pub status: u16,
+    pub logged_at: DateTime<Utc>,
+    /// 客户端请求体
+    pub request: String,
+    pub request_truncated: bool,
+    /// 返回客户端的响应体（流式响应为完整的事件流，包括客户端中途断开前已发出的部分）
+    pub response: String,
+    pub response_truncated: bool,
+}
+
+/// 对话内容记录的存储
+#[async_trait]
+pub trait ContentSink: Send + Sync {
+    async fn write(&self, record: &ContentRecord) -> Result<()>;
+}
+
+/// 追加写入本地文件，每行一条 JSON 记录
+pub struct FileSink {
+    file: Mutex<tokio::fs::File>,
+}
+
+impl FileSink {
+    pub async fn open(path: &str) -> Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|e| Error::Config(format!("Failed to open content log {:?}: {}", path, e)))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+#[async_trait]
+impl ContentSink for FileSink {
+    async fn write(&self, record: &ContentRecord) -> Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().await;
+        file.write_all(&line)
+            .await
+            .map_err(|e| Error::Unknown(format!("Failed to write content log: {}", e)))?;
+        file.flush()
+            .await
+            .map_err(|e| Error::Unknown(format!("Failed to write content log: {}", e)))
+    }
+}
+
+/// 逐条 POST 到指定地址
+pub struct HttpSink {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl HttpSink {
+    pub fn new(url: String, timeout: std::time::Duration) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(Error::Http)?;
+        Ok(Self { client, url })
+    }
+}
+
+#[async_trait]
+impl ContentSink for HttpSink {
+    async fn write(&self, record: &ContentRecord) -> Result<()> {
+        let resp = self
+            .client
+            .post(&self.url)
+            .json(record)
+            .send()
+            .await
+            .map_err(Error::Http)?;
+        if !resp.status().is_success() {
+            return Err(Error::Unknown(format!(
+                "Content log sink returned status: {}",
+                resp.status()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// 对话内容记录管道
+pub struct ContentLogger {
+    sender: mpsc::Sender<ContentRecord>,
+    max_body_bytes: usize,
+    default_retention: String,
+}
+
+impl ContentLogger {
+    /// 按配置打开存储并启动后台写入任务
+    pub async fn from_config(config: &ContentLoggingConfig) -> Result<Self> {
+        let sink: Arc<dyn ContentSink> = match &config.sink {
+            ContentSinkConfig::File { path } => Arc::new(FileSink::open(path).await?),
+            ContentSinkConfig::Http { url, timeout } => {
+                Arc::new(HttpSink::new(url.clone(), *timeout)?)
+            }
+        };
+        info!("Content logging enabled ({:?})", config.sink);
+        Ok(Self::with_sink(config, sink))
+    }
+
+    /// 使用指定的存储启动后台写入任务
+    pub fn with_sink(config: &ContentLoggingConfig, sink: Arc<dyn ContentSink>) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<ContentRecord>(config.queue_size);
+        tokio::spawn(async move {
+            while let Some(record) = receiver.recv().await {
+                match sink.write(&record).await {
+                    Ok(()) => metrics::increment_counter!(
+                        "gateway_content_log_records_total",
+                        "outcome" => "written"
+                    ),
+                    Err(e) => {
+                        error!(
+                            "Failed to write content log for request {}: {}",
+                            record.request_id, e
+                        );
+                        metrics::increment_counter!(
+                            "gateway_content_log_records_total",
+                            "outcome" => "failed"
+                        );
+                    }
+                }
+            }
+        });
+
+        Self {
+            sender,
+            max_body_bytes: config.max_body_bytes,
+            default_retention: config.default_retention.clone(),
+        }
+    }
+
+    /// 为开通内容记录的请求创建记录器，请求体按策略和配置中较小的上限截断
+    pub fn start(
+        &self,
+        policy: &ContentLoggingPolicy,
+        user_token: &str,
+        tenant_id: Option<&str>,
+        model: &str,
+        path: &str,
+        request: &[u8],
+    ) -> ContentSession {
+        let limit = policy
+            .max_body_bytes
+            .map_or(self.max_body_bytes, |max| max.min(self.max_body_bytes));
+        ContentSession {
+            sender: self.sender.clone(),
+            limit,
+            record: ContentRecord {
+                request_id: String::new(),
+                token: mask_token(user_token),
+                tenant_id: tenant_id.map(str::to_string),
+                model: model.to_string(),
+                path: path.to_string(),
+                retention: policy
+                    .retention
+                    .clone()
+                    .filter(|r| !r.is_empty())
+                    .unwrap_or_else(|| self.default_retention.clone()),
+                status: 0,
+                logged_at: Utc::now(),
+                request: String::from_utf8_lossy(&request[..request.len().min(limit)]).into_owned(),
+                request_truncated: request.len() > limit,
+                response: String::new(),
+                response_truncated: false,
+            },
+        }
+    }
+}
+
+/// 单个请求的内容记录器，响应体结束（包括客户端中途断开）时提交记录
+pub struct ContentSession {
+    sender: mpsc::Sender<ContentRecord>,
+    limit: usize,
+    record: ContentRecord,
+}
+
+impl ContentSession {
+    /// 记录经过的响应体
+    pub fn tap_response<S, E>(
+        mut self,
+        request_id: String,
+        status: u16,
+        stream: S,
+    ) -> impl Stream<Item = std::result::Result<Bytes, E>> + Send
+    where
+        S: Stream<Item = std::result::Result<Bytes, E>> + Send + 'static,
+        E: Send + 'static,
+    {
+        self.record.request_id = request_id;
+        self.record.status = status;
+        let mut tap = ResponseTap {
+            session: self,
+            buffer: Vec::new(),
+        };
+        async_stream::stream! {
+            let mut stream = Box::pin(stream);
+            while let Some(chunk) = stream.next().await {
+                if let Ok(bytes) = &chunk {
+                    tap.extend(bytes);
+                }
+                yield chunk;
+            }
+        }
+    }
+}
+
+// 累积响应体，释放时提交记录
+struct ResponseTap {
+    session: ContentSession,
+    buffer: Vec<u8>,
+}
+
+impl ResponseTap {
+    fn extend(&mut self, bytes: &[u8]) {
+        let room = self.session.limit.saturating_sub(self.buffer.len());
+        self.session.record.response_truncated |= bytes.len() > room;
+        self.buffer
+            .extend_from_slice(&bytes[..bytes.len().min(room)]);
+    }
+}
+
+impl Drop for ResponseTap {
+    fn drop(&mut self) {
+        let mut record = self.session.record.clone();
+        record.response = String::from_utf8_lossy(&self.buffer).into_owned();
+        record.logged_at = Utc::now();
+        if self.session.sender.try_send(record).is_err() {
+            metrics::increment_counter!(
+                "gateway_content_log_records_total",
+                "outcome" => "dropped"
+            );
+        }
+    }
+}
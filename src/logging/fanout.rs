@@ -0,0 +1,326 @@
+//! 流式响应分发
+//!
+//! 返回客户端的流式响应经广播通道复制给旁路消费者（审计记录、内容安全扫描、在线评估抽样等）。
+//! 发送不等待消费者，客户端延迟不受影响；每个消费者在独立任务中接收，落后超过通道容量时
+//! 跳过最早的事件并得到通知，受影响的流在记录中标记为不完整。
+
+use crate::config::{StreamConsumerConfig, StreamFanoutConfig};
+use crate::error::{Error, Result};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use rand::Rng;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, info, warn};
+
+/// 消费者同时跟踪的流数量上限，超出时提交空闲过久的流（结束事件可能因落后丢失）
+const MAX_PENDING_STREAMS: usize = 10_000;
+/// 流空闲超过该时长视为结束事件已丢失
+const STALE_AFTER: Duration = Duration::from_secs(300);
+
+/// 被分发的流的请求信息
+#[derive(Debug, Clone)]
+pub struct StreamMeta {
+    pub request_id: String,
+    /// 已脱敏的用户令牌
+    pub token: String,
+    pub tenant_id: Option<String>,
+    pub model: String,
+    pub path: String,
+    pub started_at: DateTime<Utc>,
+}
+
+/// 广播给消费者的事件
+#[derive(Debug, Clone)]
+pub enum FanoutEvent {
+    /// 流开始
+    Start(Arc<StreamMeta>),
+    /// 返回客户端的一段字节
+    Chunk { request_id: Arc<str>, bytes: Bytes },
+    /// 流结束（包括客户端中途断开）
+    End { request_id: Arc<str> },
+}
+
+/// 流式响应的旁路消费者
+///
+/// 在独立任务中按顺序收到事件，处理应尽快返回，耗时的工作（如网络请求）另起任务执行。
+pub trait StreamConsumer: Send {
+    fn name(&self) -> &str;
+
+    fn event(&mut self, event: FanoutEvent);
+
+    /// 消费者落后，跳过了 `skipped` 个最早的事件
+    fn lagged(&mut self, skipped: u64);
+}
+
+/// 流式响应分发器
+pub struct StreamFanout {
+    sender: broadcast::Sender<FanoutEvent>,
+}
+
+impl StreamFanout {
+    /// 按配置创建消费者并启动接收任务
+    pub fn from_config(config: &StreamFanoutConfig) -> Result<Self> {
+        let consumers = config
+            .consumers
+            .iter()
+            .map(|consumer| {
+                HttpStreamConsumer::new(consumer)
+                    .map(|consumer| Box::new(consumer) as Box<dyn StreamConsumer>)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        info!(
+            "Stream fanout enabled ({} consumer(s), capacity {})",
+            consumers.len(),
+            config.capacity
+        );
+        Ok(Self::with_consumers(config.capacity, consumers))
+    }
+
+    /// 使用指定的消费者启动接收任务，`capacity` 为广播通道容量（事件数）
+    pub fn with_consumers(capacity: usize, consumers: Vec<Box<dyn StreamConsumer>>) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        for mut consumer in consumers {
+            let mut receiver = sender.subscribe();
+            tokio::spawn(async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(event) => consumer.event(event),
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!(
+                                "Stream fanout consumer {} lagged, skipped {} event(s)",
+                                consumer.name(),
+                                skipped
+                            );
+                            metrics::counter!(
+                                "gateway_stream_fanout_lagged_events_total",
+                                skipped,
+                                "consumer" => consumer.name().to_string()
+                            );
+                            consumer.lagged(skipped);
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+        }
+        Self { sender }
+    }
+
+    /// 将经过的字节流复制给各消费者，流本身原样返回
+    pub fn tap<S>(
+        &self,
+        meta: StreamMeta,
+        stream: S,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+    where
+        S: Stream<Item = Result<Bytes>> + Send + 'static,
+    {
+        let guard = EndGuard {
+            sender: self.sender.clone(),
+            request_id: Arc::from(meta.request_id.as_str()),
+        };
+        // 没有消费者时发送失败，忽略即可
+        let _ = self.sender.send(FanoutEvent::Start(Arc::new(meta)));
+        Box::pin(async_stream::stream! {
+            let guard = guard;
+            let mut stream = Box::pin(stream);
+            while let Some(chunk) = stream.next().await {
+                if let Ok(bytes) = &chunk {
+                    let _ = guard.sender.send(FanoutEvent::Chunk {
+                        request_id: guard.request_id.clone(),
+                        bytes: bytes.clone(),
+                    });
+                }
+                yield chunk;
+            }
+        })
+    }
+}
+
+// 流被释放时（正常结束或客户端断开）发送结束事件
+struct EndGuard {
+    sender: broadcast::Sender<FanoutEvent>,
+    request_id: Arc<str>,
+}
+
+impl Drop for EndGuard {
+    fn drop(&mut self) {
+        let _ = self.sender.send(FanoutEvent::End {
+            request_id: self.request_id.clone(),
+        });
+    }
+}
+
+/// 一条完整的流记录
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamRecord {
+    pub consumer: String,
+    pub request_id: String,
+    /// 已脱敏的用户令牌
+    pub token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    pub model: String,
+    pub path: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// 返回客户端的事件流
+    pub stream: String,
+    pub truncated: bool,
+    /// 消费者落后时可能丢失了部分事件，此时为 false
+    pub complete: bool,
+}
+
+/// 按请求抽样，流结束时将记录 POST 到指定地址
+pub struct HttpStreamConsumer {
+    name: String,
+    client: reqwest::Client,
+    url: String,
+    sample_rate: f64,
+    max_bytes: usize,
+    pending: HashMap<Arc<str>, PendingStream>,
+}
+
+struct PendingStream {
+    meta: Arc<StreamMeta>,
+    buffer: Vec<u8>,
+    truncated: bool,
+    complete: bool,
+    updated_at: Instant,
+}
+
+impl HttpStreamConsumer {
+    pub fn new(config: &StreamConsumerConfig) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .map_err(Error::Http)?;
+        Ok(Self {
+            name: config.name.clone(),
+            client,
+            url: config.url.clone(),
+            sample_rate: config.sample_rate,
+            max_bytes: config.max_bytes,
+            pending: HashMap::new(),
+        })
+    }
+
+    // 提交空闲过久的流
+    fn evict_stale(&mut self) {
+        let stale: Vec<Arc<str>> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.updated_at.elapsed() >= STALE_AFTER)
+            .map(|(request_id, _)| request_id.clone())
+            .collect();
+        for request_id in stale {
+            if let Some(mut pending) = self.pending.remove(&request_id) {
+                pending.complete = false;
+                self.submit(pending);
+            }
+        }
+    }
+
+    fn submit(&self, pending: PendingStream) {
+        let meta = pending.meta;
+        let record = StreamRecord {
+            consumer: self.name.clone(),
+            request_id: meta.request_id.clone(),
+            token: meta.token.clone(),
+            tenant_id: meta.tenant_id.clone(),
+            model: meta.model.clone(),
+            path: meta.path.clone(),
+            started_at: meta.started_at,
+            finished_at: Utc::now(),
+            stream: String::from_utf8_lossy(&pending.buffer).into_owned(),
+            truncated: pending.truncated,
+            complete: pending.complete,
+        };
+        let client = self.client.clone();
+        let url = self.url.clone();
+        tokio::spawn(async move {
+            let result = match client.post(&url).json(&record).send().await {
+                Ok(resp) if resp.status().is_success() => Ok(()),
+                Ok(resp) => Err(Error::Unknown(format!(
+                    "Stream consumer returned status: {}",
+                    resp.status()
+                ))),
+                Err(e) => Err(Error::Http(e)),
+            };
+            let outcome = match result {
+                Ok(()) => "sent",
+                Err(e) => {
+                    error!(
+                        "Failed to send stream record for request {} to consumer {}: {}",
+                        record.request_id, record.consumer, e
+                    );
+                    "failed"
+                }
+            };
+            metrics::increment_counter!(
+                "gateway_stream_fanout_records_total",
+                "consumer" => record.consumer,
+                "outcome" => outcome
+            );
+        });
+    }
+}
+
+impl StreamConsumer for HttpStreamConsumer {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn event(&mut self, event: FanoutEvent) {
+        match event {
+            FanoutEvent::Start(meta) => {
+                if self.sample_rate < 1.0 && !rand::thread_rng().gen_bool(self.sample_rate) {
+                    return;
+                }
+                if self.pending.len() >= MAX_PENDING_STREAMS {
+                    self.evict_stale();
+                }
+                self.pending.insert(
+                    Arc::from(meta.request_id.as_str()),
+                    PendingStream {
+                        meta,
+                        buffer: Vec::new(),
+                        truncated: false,
+                        complete: true,
+                        updated_at: Instant::now(),
+                    },
+                );
+            }
+            FanoutEvent::Chunk { request_id, bytes } => {
+                let Some(pending) = self.pending.get_mut(&request_id) else {
+                    return;
+                };
+                let room = self.max_bytes.saturating_sub(pending.buffer.len());
+                pending.truncated |= bytes.len() > room;
+                pending
+                    .buffer
+                    .extend_from_slice(&bytes[..bytes.len().min(room)]);
+                pending.updated_at = Instant::now();
+            }
+            FanoutEvent::End { request_id } => {
+                if let Some(pending) = self.pending.remove(&request_id) {
+                    self.submit(pending);
+                }
+            }
+        }
+    }
+
+    fn lagged(&mut self, _skipped: u64) {
+        // 无法得知丢失的事件属于哪些流，进行中的流都标记为不完整
+        for pending in self.pending.values_mut() {
+            pending.complete = false;
+        }
+        self.evict_stale();
+    }
+}
@@ -0,0 +1,336 @@
+//! 运行时日志控制
+//!
+//! 通过管理接口在运行时替换日志过滤规则（EnvFilter 语法），无需重启；
+//! 并可对指定令牌开启调试采样：该令牌接下来 N 个请求的客户端请求、上游请求、
+//! 上游响应和返回客户端的响应被完整记录，保存在内存中供 `/admin/logging/captures` 查询，
+//! 同时以 `debug_capture` 为 target 输出日志。
+
+pub mod content;
+pub mod fanout;
+pub mod transcript;
+
+use crate::config::DebugCaptureConfig;
+use crate::error::{Error, Result};
+use crate::secrets::mask_token;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+/// 请求处理中被记录的阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureStage {
+    /// 客户端请求体
+    ClientRequest,
+    /// 协议转换后发往上游的请求体
+    UpstreamRequest,
+    /// 上游响应（流式响应为完整的事件流）
+    UpstreamResponse,
+    /// 协议转换后返回客户端的响应
+    ClientResponse,
+    /// 转发或转换失败的错误信息
+    Error,
+}
+
+/// 一条调试采样记录
+#[derive(Debug, Clone, Serialize)]
+pub struct DebugCapture {
+    pub request_id: String,
+    /// 已脱敏的用户令牌
+    pub token: String,
+    #[serde(skip)]
+    routing_token: String,
+    pub stage: CaptureStage,
+    /// 上游地址（上游阶段和错误）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api: Option<String>,
+    pub captured_at: DateTime<Utc>,
+    pub body: String,
+    /// 内容超过上限被截断
+    pub truncated: bool,
+}
+
+/// 调试采样记录查询条件
+#[derive(Debug, Default, Deserialize)]
+pub struct CaptureQuery {
+    /// 用户令牌
+    pub token: Option<String>,
+    pub request_id: Option<String>,
+    /// 返回最近的条数
+    pub limit: Option<usize>,
+}
+
+/// 令牌的采样状态
+#[derive(Debug, Clone, Serialize)]
+pub struct SamplingStatus {
+    /// 已脱敏的用户令牌
+    pub token: String,
+    /// 剩余采样请求数
+    pub remaining: u32,
+}
+
+/// 日志过滤规则和调试采样的运行时控制
+pub struct LogControl {
+    filter: reload::Handle<EnvFilter, Registry>,
+    directives: Mutex<String>,
+    // 用户令牌 -> 剩余采样请求数
+    sampling: DashMap<String, u32>,
+    captures: Mutex<VecDeque<DebugCapture>>,
+    max_captures: usize,
+    max_body_bytes: usize,
+}
+
+impl LogControl {
+    /// 初始化全局日志，过滤规则取自 `RUST_LOG`，默认 info 级别
+    pub fn init() -> Self {
+        let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+        let directives = filter.to_string();
+        let (filter, handle) = reload::Layer::new(filter);
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(fmt::layer())
+            .init();
+
+        let config = DebugCaptureConfig::default();
+        Self {
+            filter: handle,
+            directives: Mutex::new(directives),
+            sampling: DashMap::new(),
+            captures: Mutex::new(VecDeque::new()),
+            max_captures: config.max_captures,
+            max_body_bytes: config.max_body_bytes,
+        }
+    }
+
+    pub fn with_capture_config(mut self, config: &DebugCaptureConfig) -> Self {
+        self.max_captures = config.max_captures;
+        self.max_body_bytes = config.max_body_bytes;
+        self
+    }
+
+    /// 当前日志过滤规则
+    pub fn filter(&self) -> String {
+        self.directives.lock().unwrap().clone()
+    }
+
+    /// 替换日志过滤规则，如 `info,axongate_engine::proxy=debug`
+    pub fn set_filter(&self, directives: &str) -> Result<()> {
+        let filter = EnvFilter::try_new(directives)
+            .map_err(|e| Error::Config(format!("Invalid log filter {:?}: {}", directives, e)))?;
+        self.filter
+            .reload(filter)
+            .map_err(|e| Error::Unknown(format!("Failed to reload log filter: {}", e)))?;
+        *self.directives.lock().unwrap() = directives.to_string();
+        info!("Log filter changed to {}", directives);
+        Ok(())
+    }
+
+    /// 对令牌接下来的 `requests` 个请求开启调试采样，为0时取消
+    pub fn sample(&self, token: &str, requests: u32) {
+        if requests == 0 {
+            self.sampling.remove(token);
+            info!("Debug sampling disabled for token {}", mask_token(token));
+        } else {
+            self.sampling.insert(token.to_string(), requests);
+            info!(
+                "Debug sampling enabled for next {} requests of token {}",
+                requests,
+                mask_token(token)
+            );
+        }
+    }
+
+    /// 令牌是否正在采样，不消耗采样次数
+    pub fn is_sampling(&self, token: &str) -> bool {
+        self.sampling.contains_key(token)
+    }
+
+    /// 请求是否需要采样，需要时消耗一次采样次数
+    pub fn take_sample(&self, token: &str) -> bool {
+        if self.sampling.is_empty() {
+            return false;
+        }
+        match self.sampling.get_mut(token) {
+            Some(mut remaining) => *remaining = remaining.saturating_sub(1),
+            None => return false,
+        }
+        self.sampling
+            .remove_if(token, |_, remaining| *remaining == 0);
+        true
+    }
+
+    /// 正在采样的令牌
+    pub fn sampling(&self) -> Vec<SamplingStatus> {
+        self.sampling
+            .iter()
+            .map(|entry| SamplingStatus {
+                token: mask_token(entry.key()),
+                remaining: *entry.value(),
+            })
+            .collect()
+    }
+
+    /// 为一个采样请求创建记录器
+    pub fn capture(self: &Arc<Self>, request_id: &str, token: &str) -> RequestCapture {
+        RequestCapture {
+            control: self.clone(),
+            request_id: request_id.to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    /// 按条件查询采样记录，按记录时间排序
+    pub fn captures(&self, query: &CaptureQuery) -> Vec<DebugCapture> {
+        let captures = self.captures.lock().unwrap();
+        let matched: Vec<&DebugCapture> = captures
+            .iter()
+            .filter(|c| query.token.as_ref().is_none_or(|t| *t == c.routing_token))
+            .filter(|c| {
+                query
+                    .request_id
+                    .as_ref()
+                    .is_none_or(|id| *id == c.request_id)
+            })
+            .collect();
+        let skip = query
+            .limit
+            .map_or(0, |limit| matched.len().saturating_sub(limit));
+        matched.into_iter().skip(skip).cloned().collect()
+    }
+
+    /// 已保存的采样记录数
+    pub fn capture_count(&self) -> usize {
+        self.captures.lock().unwrap().len()
+    }
+
+    fn push(&self, capture: DebugCapture) {
+        info!(
+            target: "debug_capture",
+            request_id = %capture.request_id,
+            token = %capture.token,
+            stage = ?capture.stage,
+            api = capture.api.as_deref().unwrap_or_default(),
+            truncated = capture.truncated,
+            "{}",
+            capture.body
+        );
+        if self.max_captures == 0 {
+            return;
+        }
+        let mut captures = self.captures.lock().unwrap();
+        while captures.len() >= self.max_captures {
+            captures.pop_front();
+        }
+        captures.push_back(capture);
+    }
+}
+
+/// 单个采样请求的记录器
+#[derive(Clone)]
+pub struct RequestCapture {
+    control: Arc<LogControl>,
+    request_id: String,
+    token: String,
+}
+
+impl RequestCapture {
+    /// 记录一个阶段的完整内容，超出上限的部分截断
+    pub fn record(&self, stage: CaptureStage, api: Option<&str>, body: &[u8]) {
+        let limit = self.control.max_body_bytes;
+        self.push(
+            stage,
+            api,
+            &body[..body.len().min(limit)],
+            body.len() > limit,
+        );
+    }
+
+    /// 记录某个上游的转发或转换错误
+    pub fn record_error(&self, api: &str, error: &dyn std::fmt::Display) {
+        self.record(CaptureStage::Error, Some(api), error.to_string().as_bytes());
+    }
+
+    /// 记录流式内容：流结束（包括客户端中途断开）时记录已经过的全部内容
+    pub fn tap_stream<S>(
+        &self,
+        stage: CaptureStage,
+        api: Option<&str>,
+        stream: S,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+    where
+        S: Stream<Item = Result<Bytes>> + Send + 'static,
+    {
+        let mut tap = StreamTap {
+            capture: self.clone(),
+            stage,
+            api: api.map(str::to_string),
+            buffer: Vec::new(),
+            truncated: false,
+        };
+        Box::pin(async_stream::stream! {
+            let mut stream = Box::pin(stream);
+            while let Some(chunk) = stream.next().await {
+                match &chunk {
+                    Ok(bytes) => tap.extend(bytes),
+                    Err(e) => tap.extend(format!("\n[stream error: {}]", e).as_bytes()),
+                }
+                yield chunk;
+            }
+        })
+    }
+
+    fn push(&self, stage: CaptureStage, api: Option<&str>, body: &[u8], truncated: bool) {
+        self.control.push(DebugCapture {
+            request_id: self.request_id.clone(),
+            token: mask_token(&self.token),
+            routing_token: self.token.clone(),
+            stage,
+            api: api.map(str::to_string),
+            captured_at: Utc::now(),
+            body: String::from_utf8_lossy(body).into_owned(),
+            truncated,
+        });
+    }
+}
+
+// 累积流式内容，释放时写入记录
+struct StreamTap {
+    capture: RequestCapture,
+    stage: CaptureStage,
+    api: Option<String>,
+    buffer: Vec<u8>,
+    truncated: bool,
+}
+
+impl StreamTap {
+    fn extend(&mut self, bytes: &[u8]) {
+        let room = self
+            .capture
+            .control
+            .max_body_bytes
+            .saturating_sub(self.buffer.len());
+        self.truncated |= bytes.len() > room;
+        self.buffer
+            .extend_from_slice(&bytes[..bytes.len().min(room)]);
+    }
+}
+
+impl Drop for StreamTap {
+    fn drop(&mut self) {
+        self.capture.push(
+            self.stage,
+            self.api.as_deref(),
+            &self.buffer,
+            self.truncated,
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! 流式响应字节记录
+//!
+//! 按请求开启，分别保留上游原始字节流和转换后返回客户端的字节流的最后 `max_bytes` 字节，
+//! 用于排查协议转换问题。记录保存在内存中，超过保留时长或数量上限后丢弃。
+
+use crate::config::StreamTranscriptConfig;
+use crate::error::Result;
+use crate::secrets::mask_token;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// 记录的字节流方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptSide {
+    /// 上游返回的原始字节流
+    Upstream,
+    /// 转换后返回客户端的字节流
+    Downstream,
+}
+
+/// 一个请求的流式字节记录
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamTranscript {
+    pub request_id: String,
+    /// 已脱敏的用户令牌
+    pub token: String,
+    /// 上游地址
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api: Option<String>,
+    pub started_at: DateTime<Utc>,
+    /// 两个方向的流都已结束（包括客户端中途断开）
+    pub completed: bool,
+    pub upstream: TranscriptTail,
+    pub downstream: TranscriptTail,
+}
+
+/// 一个方向保留的字节
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TranscriptTail {
+    /// 流经的总字节数
+    pub total_bytes: u64,
+    /// 超出上限被丢弃的开头字节数
+    pub dropped_bytes: u64,
+    /// 保留的最后一段内容
+    pub body: String,
+}
+
+// 只保留最后 capacity 字节的环形缓冲
+#[derive(Default)]
+struct Ring {
+    bytes: VecDeque<u8>,
+    total: u64,
+    open: bool,
+}
+
+impl Ring {
+    fn push(&mut self, chunk: &[u8], capacity: usize) {
+        self.total += chunk.len() as u64;
+        let chunk = &chunk[chunk.len().saturating_sub(capacity)..];
+        let overflow = (self.bytes.len() + chunk.len()).saturating_sub(capacity);
+        self.bytes.drain(..overflow);
+        self.bytes.extend(chunk);
+    }
+
+    fn tail(&self) -> TranscriptTail {
+        let (front, back) = self.bytes.as_slices();
+        TranscriptTail {
+            total_bytes: self.total,
+            dropped_bytes: self.total - self.bytes.len() as u64,
+            body: String::from_utf8_lossy(&[front, back].concat()).into_owned(),
+        }
+    }
+}
+
+struct Entry {
+    token: String,
+    api: Option<String>,
+    started_at: DateTime<Utc>,
+    created: Instant,
+    upstream: Ring,
+    downstream: Ring,
+}
+
+impl Entry {
+    fn ring(&mut self, side: TranscriptSide) -> &mut Ring {
+        match side {
+            TranscriptSide::Upstream => &mut self.upstream,
+            TranscriptSide::Downstream => &mut self.downstream,
+        }
+    }
+}
+
+/// 流式字节记录的内存存储
+pub struct TranscriptStore {
+    enabled: bool,
+    max_bytes: usize,
+    retention: Duration,
+    max_transcripts: usize,
+    transcripts: DashMap<String, Arc<Mutex<Entry>>>,
+}
+
+impl TranscriptStore {
+    pub fn from_config(config: &StreamTranscriptConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            max_bytes: config.max_bytes,
+            retention: config.retention,
+            max_transcripts: config.max_transcripts,
+            transcripts: DashMap::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 为一个请求开始记录，未开启时返回 None
+    pub fn start(&self, request_id: &str, token: &str) -> Option<TranscriptRecorder> {
+        if !self.enabled || self.max_transcripts == 0 {
+            return None;
+        }
+        self.evict();
+        while self.transcripts.len() >= self.max_transcripts {
+            let oldest = self
+                .transcripts
+                .iter()
+                .min_by_key(|entry| entry.value().lock().unwrap().created)
+                .map(|entry| entry.key().clone());
+            match oldest {
+                Some(key) => self.transcripts.remove(&key),
+                None => break,
+            };
+        }
+
+        let entry = Arc::new(Mutex::new(Entry {
+            token: token.to_string(),
+            api: None,
+            started_at: Utc::now(),
+            created: Instant::now(),
+            upstream: Ring::default(),
+            downstream: Ring::default(),
+        }));
+        self.transcripts
+            .insert(request_id.to_string(), entry.clone());
+        Some(TranscriptRecorder {
+            entry,
+            max_bytes: self.max_bytes,
+        })
+    }
+
+    /// 查询请求的记录，已过期时返回 None
+    pub fn get(&self, request_id: &str) -> Option<StreamTranscript> {
+        self.evict();
+        let entry = self.transcripts.get(request_id)?;
+        let entry = entry.value().lock().unwrap();
+        Some(StreamTranscript {
+            request_id: request_id.to_string(),
+            token: mask_token(&entry.token),
+            api: entry.api.clone(),
+            started_at: entry.started_at,
+            completed: !entry.upstream.open && !entry.downstream.open,
+            upstream: entry.upstream.tail(),
+            downstream: entry.downstream.tail(),
+        })
+    }
+
+    fn evict(&self) {
+        let retention = self.retention;
+        self.transcripts
+            .retain(|_, entry| entry.lock().unwrap().created.elapsed() < retention);
+    }
+}
+
+/// 单个请求的字节记录器
+#[derive(Clone)]
+pub struct TranscriptRecorder {
+    entry: Arc<Mutex<Entry>>,
+    max_bytes: usize,
+}
+
+impl TranscriptRecorder {
+    /// 记录一个方向的字节流，`api` 为该流对应的上游地址
+    pub fn tap_stream<S>(
+        &self,
+        side: TranscriptSide,
+        api: Option<&str>,
+        stream: S,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+    where
+        S: Stream<Item = Result<Bytes>> + Send + 'static,
+    {
+        {
+            let mut entry = self.entry.lock().unwrap();
+            if let Some(api) = api {
+                entry.api = Some(api.to_string());
+            }
+            entry.ring(side).open = true;
+        }
+        let tap = SideTap {
+            recorder: self.clone(),
+            side,
+        };
+        Box::pin(async_stream::stream! {
+            let mut stream = Box::pin(stream);
+            while let Some(chunk) = stream.next().await {
+                match &chunk {
+                    Ok(bytes) => tap.push(bytes),
+                    Err(e) => tap.push(format!("\n[stream error: {}]", e).as_bytes()),
+                }
+                yield chunk;
+            }
+        })
+    }
+}
+
+// 向记录中追加一个方向的字节，释放时标记该方向结束
+struct SideTap {
+    recorder: TranscriptRecorder,
+    side: TranscriptSide,
+}
+
+impl SideTap {
+    fn push(&self, bytes: &[u8]) {
+        let mut entry = self.recorder.entry.lock().unwrap();
+        entry.ring(self.side).push(bytes, self.recorder.max_bytes);
+    }
+}
+
+impl Drop for SideTap {
+    fn drop(&mut self) {
+        self.recorder.entry.lock().unwrap().ring(self.side).open = false;
+    }
+}
@@ -1,26 +1,76 @@
+mod gateway;
+
 use axongate_engine::{
-    cache::Cache,
-    config::Config,
+    auth::{AuthIdentity, Authenticator},
+    batches::{self, BatchOwner, BatchRegistry, BatchRoute},
+    cache::{metadata::MetadataCache, Cache, CacheQuery},
+    client_ip::{ClientIpResolver, IpRateLimiter, RateLimiter},
+    config::{AdminConfig, Config},
     error::Error,
-    models::{ClientProtocol, ErrorEvent, RouteConfig, UsageEvent, TargetProtocol},
-    protocol::{adapter::UniversalAdapter, detector::ProtocolDetector, ProtocolAdapter},
-    proxy::ProxyForwarder,
-    router::Router,
-    telemetry::TelemetryModule,
+    files::{self, FileRegistry, FileRoute},
+    ledger::{Ledger, LedgerQuery},
+    logging::{
+        content::{ContentLogger, ContentSession},
+        fanout::StreamFanout,
+        transcript::{TranscriptSide, TranscriptStore},
+        CaptureQuery, CaptureStage, LogControl,
+    },
+    models::{
+        ClientProtocol, ErrorEvent, InvalidationRequest, LatencyClass, RerankProvider, RouteConfig,
+        RouteHints, RouteTableResponse, TargetProtocol, UsageEvent,
+    },
+    protocol::{
+        adapter::UniversalAdapter,
+        agent_clients::AgentClient,
+        compat::{self, ClientCompat},
+        detector::ProtocolDetector,
+        framing::{self, ClientStreamFormat},
+        legacy_functions, multipart,
+        rerank::{self, RerankRequest},
+        responses::{self, Bridge},
+        stream_usage, ProtocolAdapter,
+    },
+    proxy::{
+        model_rewrite, output_cap, size_limit, smoothing::smooth_stream, upstream_request_id_of,
+        upstream_status, warmup, FailureClass, FileUpload, ProxyForwarder, UpstreamResponse,
+    },
+    router::{
+        failover::{AttemptOutcome, FailoverQueue, RoutingTrace},
+        latency::LatencySlo,
+        route_table,
+        shared::{self, SharedProviderState},
+        Router,
+    },
+    secrets::{mask_token, token_eq, TokenCipher},
+    stats::{RuntimeStats, StreamLimiter, StreamSlot},
+    telemetry::{
+        prometheus, spawn_ledger_reconciliation, spawn_route_health_reports,
+        usage_stats::CostEstimate, TelemetryModule,
+    },
+    usage,
     usage_collector::StreamUsageCollector,
     Result,
 };
 use axum::{
     body::{Body, Bytes},
-    extract::State,
-    http::{Request, Response, StatusCode},
+    extract::{ws::WebSocketUpgrade, ConnectInfo, Path, Query, State},
+    http::{HeaderMap, HeaderValue, Request, Response, StatusCode},
     routing::{get, post},
     Router as AxumRouter,
 };
+use futures::{Stream, TryStreamExt};
+use gateway::execution::RequestContext;
+use gateway::{dispatcher, grpc::GatewayService, ws};
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tracing_subscriber::EnvFilter;
+use std::time::{Duration, Instant};
 use tower_http::trace::TraceLayer;
-use tracing::{error, info};
+use tracing::{debug, error, info, warn};
 use uuid::Uuid;
 
 #[derive(Clone)]
@@ -29,49 +79,430 @@ struct AppState {
     proxy: Arc<ProxyForwarder>,
     adapter: Arc<UniversalAdapter>,
     telemetry: Arc<TelemetryModule>,
+    admin: AdminConfig,
+    client_ip: Arc<ClientIpResolver>,
+    ip_rate_limiter: Option<Arc<IpRateLimiter>>,
+    tenant_rate_limiter: Option<Arc<RateLimiter<String>>>,
+    stream_limiter: Option<Arc<StreamLimiter>>,
+    tenant_header: Option<String>,
+    authenticator: Arc<Authenticator>,
+    request_timeout: Option<Duration>,
+    streaming_body_threshold: Option<u64>,
+    expose_routing_trace: bool,
+    expose_cost_estimate: bool,
+    stats: Arc<RuntimeStats>,
+    metrics: PrometheusHandle,
+    batches: Arc<BatchRegistry>,
+    files: Arc<FileRegistry>,
+    max_file_bytes: u64,
+    metadata: Arc<MetadataCache>,
+    // 网关对外地址，用于改写批处理对象中的 results_url
+    public_base_url: Option<String>,
+    logging: Arc<LogControl>,
+    transcripts: Arc<TranscriptStore>,
+    content_log: Option<Arc<ContentLogger>>,
+    stream_fanout: Option<Arc<StreamFanout>>,
+    compat: Arc<ClientCompat>,
+    latency_slo: Arc<LatencySlo>,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // 初始化日志，支持通过环境变量配置，默认info级别
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
-        )
-        .init();
+/// 请求路由追踪的调试开关请求头
+const ROUTING_DEBUG_HEADER: &str = "x-gateway-debug";
+
+/// 返回路由追踪的响应头
+const ROUTING_TRACE_HEADER: &str = "x-gateway-routing-trace";
+
+/// 开启流式字节记录的请求头
+const TRANSCRIPT_HEADER: &str = "x-gateway-transcript";
+
+/// 返回网关请求ID的响应头，用于查询流式字节记录
+const REQUEST_ID_HEADER: &str = "x-gateway-request-id";
+
+/// 返回缓存路由距解析时间秒数的响应头
+const ROUTE_AGE_HEADER: &str = "x-gateway-route-age";
+
+/// 返回执行前预估费用的响应头
+const ESTIMATED_COST_HEADER: &str = "x-gateway-estimated-cost";
+
+fn main() -> Result<()> {
+    // 初始化日志，支持通过环境变量配置，默认info级别；运行时可通过 /admin/logging 调整
+    let logging = LogControl::init();
 
     info!("Starting AI Gateway Engine...");
 
-    // 加载配置
-    let config = Config::from_file("config.yaml").unwrap_or_else(|_| {
-        info!("Failed to load config.yaml, using default config");
+    // 加载配置：配置文件不存在时使用默认配置，存在但解析失败则拒绝启动
+    let config = if std::path::Path::new("config.yaml").exists() {
+        Config::from_file("config.yaml").inspect_err(|e| {
+            error!("Failed to load config.yaml: {}", e);
+        })?
+    } else {
+        info!("config.yaml not found, using default config");
         Config::default()
-    });
+    };
+
+    // 启动前校验配置，一次性输出全部问题
+    config.validate().inspect_err(|e| {
+        error!("Invalid configuration, refusing to start. {}", e);
+    })?;
+
+    // 按配置构建运行时，线程数等参数在运行时启动前确定
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime
+        .enable_all()
+        .worker_threads(config.server.workers)
+        .thread_name(config.server.thread_name.clone());
+    if let Some(max_blocking_threads) = config.server.max_blocking_threads {
+        runtime.max_blocking_threads(max_blocking_threads);
+    }
+    let runtime = runtime.build()?;
+    info!(
+        "Runtime started with {} worker threads",
+        config.server.workers
+    );
+
+    runtime.block_on(run(config, logging))
+}
+
+async fn run(config: Config, logging: LogControl) -> Result<()> {
+    let token_cipher = match &config.token_encryption {
+        Some(encryption) => Some(Arc::new(TokenCipher::from_config(encryption).inspect_err(
+            |e| {
+                error!("Failed to load token encryption key: {}", e);
+            },
+        )?)),
+        None => None,
+    };
+
+    // 子命令 encrypt-token：从标准输入读取令牌，输出可写入配置文件的密文
+    if std::env::args().nth(1).as_deref() == Some("encrypt-token") {
+        return encrypt_token_command(token_cipher.as_deref());
+    }
+
+    let logging = Arc::new(logging.with_capture_config(&config.admin.debug_capture));
+    let content_log = match &config.content_logging {
+        Some(content_config) => Some(Arc::new(
+            ContentLogger::from_config(content_config)
+                .await
+                .inspect_err(|e| {
+                    error!("Failed to open content log: {}", e);
+                })?,
+        )),
+        None => None,
+    };
+    let stream_fanout = match &config.stream_fanout {
+        Some(fanout_config) => Some(Arc::new(
+            StreamFanout::from_config(fanout_config).inspect_err(|e| {
+                error!("Failed to start stream fanout: {}", e);
+            })?,
+        )),
+        None => None,
+    };
 
     // 初始化各模块
-    let cache = Arc::new(Cache::new(config.cache.ttl, config.cache.max_lifetime));
-    let router = Arc::new(Router::new(cache.clone(), config.business_api.clone())?);
-    let proxy = Arc::new(ProxyForwarder::new(config.proxy.clone())?);
-    let adapter = Arc::new(UniversalAdapter::new());
-    let telemetry = Arc::new(TelemetryModule::new(config.business_api.base_url.clone())?);
+    let state = build_state(
+        &config,
+        token_cipher.clone(),
+        logging.clone(),
+        content_log.clone(),
+        stream_fanout.clone(),
+    )
+    .await?;
+
+    // 启动数据面 gRPC 服务（可选），与HTTP接口共用同一处理流程
+    if let Some(grpc_port) = config.server.grpc_port {
+        let grpc_addr: SocketAddr = format!("{}:{}", config.server.host, grpc_port)
+            .parse()
+            .map_err(|e| Error::Config(format!("Invalid gRPC listen address: {}", e)))?;
+        let service = GatewayService::new(dispatcher(state.clone()));
+
+        info!("gRPC server listening on {}", grpc_addr);
+        tokio::spawn(async move {
+            if let Err(e) = tonic::transport::Server::builder()
+                .add_service(service.into_server())
+                .serve(grpc_addr)
+                .await
+            {
+                error!("gRPC server failed: {}", e);
+            }
+        });
+    }
+
+    let mut app = build_app(state);
 
-    let state = AppState {
+    // 逻辑网关：各自的状态和路由，按独立端口或主端口上的 Host 请求头选择
+    let mut host_apps = HashMap::new();
+    for profile in &config.profiles {
+        let profile_config = config.for_profile(profile);
+        let state = build_state(
+            &profile_config,
+            token_cipher.clone(),
+            logging.clone(),
+            content_log.clone(),
+            stream_fanout.clone(),
+        )
+        .await
+        .inspect_err(|e| {
+            error!("Failed to initialize profile {}: {}", profile.name, e);
+        })?;
+        let profile_app = build_app(state);
+        for host in &profile.hosts {
+            host_apps.insert(host.to_ascii_lowercase(), profile_app.clone());
+        }
+        if let Some(port) = profile.port {
+            let addr = format!("{}:{}", config.server.host, port);
+            let listener = tokio::net::TcpListener::bind(&addr).await.map_err(|e| {
+                Error::Config(format!(
+                    "Failed to bind profile {} on {}: {}",
+                    profile.name, addr, e
+                ))
+            })?;
+            info!("Profile {} listening on {}", profile.name, addr);
+            let name = profile.name.clone();
+            tokio::spawn(async move {
+                if let Err(e) = axum::serve(
+                    listener,
+                    profile_app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .await
+                {
+                    error!("Profile {} server failed: {}", name, e);
+                }
+            });
+        }
+        if !profile.hosts.is_empty() {
+            info!("Profile {} serving hosts {:?}", profile.name, profile.hosts);
+        }
+    }
+    if !host_apps.is_empty() {
+        app = dispatch_by_host(Arc::new(host_apps), app);
+    }
+
+    // 启动服务器
+    let addr = format!("{}:{}", config.server.host, config.server.port);
+    info!("Server listening on {}", addr);
+
+    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
+
+    Ok(())
+}
+
+// 按配置初始化一个网关（主配置或逻辑网关）的处理状态，日志控制、对话内容记录和流式响应分发由各网关共享
+async fn build_state(
+    config: &Config,
+    token_cipher: Option<Arc<TokenCipher>>,
+    logging: Arc<LogControl>,
+    content_log: Option<Arc<ContentLogger>>,
+    stream_fanout: Option<Arc<StreamFanout>>,
+) -> Result<AppState> {
+    let cache = Arc::new(
+        Cache::new(config.cache.ttl, config.cache.max_lifetime)
+            .with_stale_window(config.cache.stale_if_error)
+            .with_tenant_quota(config.tenancy.max_cache_entries)
+            .with_token_cipher(token_cipher.clone()),
+    );
+    let shared_state = match &config.shared_state {
+        Some(shared_config) => Some(Arc::new(
+            SharedProviderState::connect(shared_config)
+                .await
+                .inspect_err(|e| {
+                    error!("Failed to connect shared state: {}", e);
+                })?,
+        )),
+        None => None,
+    };
+    let router = Arc::new(
+        Router::new(
+            cache.clone(),
+            config.business_api.clone(),
+            config.canary.clone(),
+            token_cipher.clone(),
+        )?
+        .with_breaker(&config.breaker)
+        .with_error_budget(&config.error_budget)
+        .with_default_model(&config.default_model)
+        .with_shared_state(shared_state.clone()),
+    );
+    if let Some(shared_state) = shared_state {
+        shared::spawn_subscriber(shared_state, router.clone());
+    }
+    route_table::spawn_sync(router.clone());
+    let proxy = Arc::new(ProxyForwarder::new(config.proxy.clone())?);
+    if let Some(warmup) = &config.proxy.warmup {
+        // 固定预热目标：配置的上游和灰度规则中的上游
+        let endpoints = warmup
+            .endpoints
+            .iter()
+            .cloned()
+            .chain(
+                config
+                    .canary
+                    .iter()
+                    .map(|rule| rule.route.api_endpoint.clone()),
+            )
+            .collect();
+        warmup::spawn(proxy.clone(), warmup.clone(), endpoints);
+    }
+    let adapter = Arc::new(UniversalAdapter::from_config(&config.adapter));
+    let ledger = match &config.ledger {
+        Some(ledger_config) => Some(Arc::new(
+            Ledger::connect(ledger_config)
+                .await
+                .inspect_err(|e| {
+                    error!("Failed to open usage ledger: {}", e);
+                })?
+                .with_token_cipher(token_cipher.clone()),
+        )),
+        None => None,
+    };
+    let telemetry = Arc::new(TelemetryModule::new(
+        config.business_api.base_url.clone(),
+        &config.business_api.auth,
+        &config.telemetry,
+        &config.usage_stats,
+        &config.alerts,
+        ledger,
+    )?);
+    spawn_ledger_reconciliation(telemetry.clone());
+    spawn_route_health_reports(telemetry.clone());
+    let client_ip = Arc::new(ClientIpResolver::new(&config.server.trusted_proxies)?);
+    let ip_rate_limiter = config
+        .server
+        .per_ip_rate_limit
+        .map(|limit| Arc::new(IpRateLimiter::new(limit)));
+    let tenant_rate_limiter = config
+        .tenancy
+        .rate_limit
+        .map(|limit| Arc::new(RateLimiter::new(limit)));
+    let stream_limiter = config
+        .server
+        .max_streams_per_token
+        .map(|limit| Arc::new(StreamLimiter::new(limit as usize)));
+    let authenticator = Arc::new(Authenticator::new(&config.auth)?);
+    let batches = Arc::new(BatchRegistry::new().with_ledger(telemetry.ledger().cloned()));
+    match batches.restore().await {
+        Ok(0) => {}
+        Ok(count) => info!("Restored {} message batch(es) from ledger", count),
+        Err(e) => error!("Failed to restore message batches: {}", e),
+    }
+    let files = Arc::new(FileRegistry::new().with_ledger(telemetry.ledger().cloned()));
+    match files.restore().await {
+        Ok(0) => {}
+        Ok(count) => info!("Restored {} uploaded file(s) from ledger", count),
+        Err(e) => error!("Failed to restore uploaded files: {}", e),
+    }
+    batches::spawn_usage_poller(
+        batches.clone(),
+        proxy.clone(),
+        telemetry.clone(),
+        config.batches.poll_interval,
+    );
+    Ok(AppState {
         router,
         proxy,
         adapter,
         telemetry,
-    };
+        admin: config.admin.clone(),
+        client_ip,
+        ip_rate_limiter,
+        tenant_rate_limiter,
+        stream_limiter,
+        tenant_header: config.tenancy.header.clone(),
+        authenticator,
+        request_timeout: config.server.request_timeout,
+        streaming_body_threshold: config.proxy.streaming_body_threshold,
+        expose_routing_trace: config.server.expose_routing_trace,
+        expose_cost_estimate: config.server.expose_cost_estimate,
+        stats: Arc::new(RuntimeStats::new()),
+        metrics: prometheus::install()?,
+        batches,
+        files,
+        max_file_bytes: config.files.max_file_bytes,
+        metadata: Arc::new(MetadataCache::new(config.cache.metadata_ttl)),
+        public_base_url: config.server.public_base_url.clone(),
+        logging,
+        transcripts: Arc::new(TranscriptStore::from_config(
+            &config.admin.stream_transcript,
+        )),
+        content_log,
+        stream_fanout,
+        compat: Arc::new(ClientCompat::from_config(&config.compat)),
+        latency_slo: Arc::new(LatencySlo::from_config(&config.latency_routing)),
+    })
+}
 
-    // 创建路由
-    let app = AxumRouter::new()
+// 网关的HTTP路由
+fn build_app(state: AppState) -> AxumRouter {
+    AxumRouter::new()
         .route("/health", get(health))
         .route("/v1/chat/completions", post(handle_request))
         .route("/v1/messages", post(handle_request))
         .route("/v1/responses", post(handle_request))
+        .route("/v1/ws", get(handle_websocket))
+        .route(
+            "/openai/deployments/:deployment/chat/completions",
+            post(handle_request),
+        )
+        .route("/v1/audio/transcriptions", post(handle_passthrough))
+        .route("/v1/audio/speech", post(handle_passthrough))
+        .route("/v1/images/generations", post(handle_passthrough))
+        .route("/v1/embeddings", post(handle_passthrough))
+        .route("/v1/rerank", post(handle_passthrough))
+        .route("/v1/estimate", post(handle_estimate))
+        .route("/v1/messages/batches", post(handle_batch_create))
+        .route(
+            "/v1/messages/batches/:batch_id",
+            get(handle_batch_retrieve).delete(handle_batch_delete),
+        )
+        .route(
+            "/v1/messages/batches/:batch_id/cancel",
+            post(handle_batch_cancel),
+        )
+        .route(
+            "/v1/messages/batches/:batch_id/results",
+            get(handle_batch_results),
+        )
+        .route("/v1/models", get(handle_model_list))
+        .route("/v1/files", post(handle_file_upload).get(handle_file_list))
+        .route(
+            "/v1/files/:file_id",
+            get(handle_file_retrieve).delete(handle_file_delete),
+        )
+        .route("/v1/files/:file_id/content", get(handle_file_content))
+        .route("/internal/invalidate", post(handle_invalidate))
+        .route("/internal/route_table", post(handle_route_table_push))
+        .route("/admin/usage/summary", get(admin_usage_summary))
+        .route("/admin/ledger", get(admin_ledger_events))
+        .route("/admin/stats", get(admin_stats))
+        .route("/metrics", get(admin_metrics))
+        .route("/admin/providers/drained", get(admin_drained_providers))
+        .route("/admin/cache", get(admin_cache_entries))
+        .route(
+            "/admin/logging",
+            get(admin_logging).put(admin_update_logging),
+        )
+        .route("/admin/logging/captures", get(admin_logging_captures))
+        .route(
+            "/admin/requests/:request_id/transcript",
+            get(admin_request_transcript),
+        )
+        .route(
+            "/admin/providers/:provider_token_id/drain",
+            post(admin_drain_provider),
+        )
+        .route(
+            "/admin/providers/:provider_token_id/undrain",
+            post(admin_undrain_provider),
+        )
         .layer(
             TraceLayer::new_for_http().make_span_with(|request: &Request<Body>| {
-                // 过滤掉健康检查的日志
-                if request.uri().path() == "/health" {
+                // 过滤掉健康检查和指标抓取的日志
+                if matches!(request.uri().path(), "/health" | "/metrics") {
                     tracing::trace_span!("health_check")
                 } else {
                     tracing::info_span!(
@@ -79,19 +510,60 @@ async fn main() -> Result<()> {
                         method = %request.method(),
                         uri = %request.uri(),
                         version = ?request.version(),
+                        client_ip = tracing::field::Empty,
+                        routing_trace = tracing::field::Empty,
                     )
                 }
             }),
         )
-        .with_state(state);
+        .with_state(state)
+}
 
-    // 启动服务器
-    let addr = format!("{}:{}", config.server.host, config.server.port);
-    info!("Server listening on {}", addr);
+// 按 Host 请求头（HTTP/2 为 :authority）把请求交给对应逻辑网关，未匹配的交给主配置
+fn dispatch_by_host(
+    host_apps: Arc<HashMap<String, AxumRouter>>,
+    default: AxumRouter,
+) -> AxumRouter {
+    AxumRouter::new().fallback(move |req: Request<Body>| {
+        let host = req
+            .headers()
+            .get(axum::http::header::HOST)
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| req.uri().host())
+            .map(|host| {
+                // 去掉端口，保留 IPv6 地址的方括号
+                let host = match host.rfind(':') {
+                    Some(i) if !host[i..].contains(']') => &host[..i],
+                    _ => host,
+                };
+                host.to_ascii_lowercase()
+            });
+        let app = host
+            .and_then(|host| host_apps.get(&host))
+            .unwrap_or(&default)
+            .clone();
+        async move {
+            match tower::ServiceExt::oneshot(app, req).await {
+                Ok(response) => response,
+                Err(infallible) => match infallible {},
+            }
+        }
+    })
+}
 
-    let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+fn encrypt_token_command(cipher: Option<&TokenCipher>) -> Result<()> {
+    let cipher = cipher.ok_or_else(|| {
+        Error::Config("token_encryption must be configured to encrypt tokens".to_string())
+    })?;
+
+    let mut token = String::new();
+    std::io::stdin().read_line(&mut token)?;
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(Error::Config("No token provided on stdin".to_string()));
+    }
 
+    println!("{}", cipher.seal(token)?);
     Ok(())
 }
 
@@ -107,11 +579,451 @@ async fn health() -> Response<Body> {
         .unwrap()
 }
 
-async fn handle_request(State(state): State<AppState>, req: Request<Body>) -> Response<Body> {
+#[derive(Debug, Deserialize)]
+struct UsageSummaryQuery {
+    /// 汇总窗口，humantime格式（如 "15m", "1h"），默认1小时
+    window: Option<String>,
+}
+
+// 管理接口：查询本地滚动使用量汇总
+async fn admin_usage_summary(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<UsageSummaryQuery>,
+) -> Response<Body> {
+    if let Some(resp) = authorize_admin(&state.admin, &headers) {
+        return resp;
+    }
+
+    let window = match query.window.as_deref() {
+        Some(w) => match humantime::parse_duration(w) {
+            Ok(d) => d,
+            Err(_) => return error_response(StatusCode::BAD_REQUEST, "Invalid window"),
+        },
+        None => Duration::from_secs(3600),
+    };
+
+    let summary = state.telemetry.usage_stats().summary(window);
+    json_response(&summary)
+}
+
+#[derive(Debug, Deserialize)]
+struct DrainQuery {
+    /// 摘除时长，humantime格式（如 "30m", "2h"），不传则需手动恢复
+    duration: Option<String>,
+}
+
+// 管理接口：摘除供应商令牌，计划内维护期间不再参与路由
+async fn admin_drain_provider(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(provider_token_id): Path<String>,
+    Query(query): Query<DrainQuery>,
+) -> Response<Body> {
+    if let Some(resp) = authorize_admin(&state.admin, &headers) {
+        return resp;
+    }
+
+    let duration = match query.duration.as_deref() {
+        Some(d) => match humantime::parse_duration(d) {
+            Ok(d) => Some(d),
+            Err(_) => return error_response(StatusCode::BAD_REQUEST, "Invalid duration"),
+        },
+        None => None,
+    };
+
+    state.router.drain(&provider_token_id, duration);
+    json_response(&serde_json::json!({
+        "provider_token_id": provider_token_id,
+        "drained": true,
+    }))
+}
+
+// 管理接口：恢复被摘除的供应商令牌
+async fn admin_undrain_provider(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(provider_token_id): Path<String>,
+) -> Response<Body> {
+    if let Some(resp) = authorize_admin(&state.admin, &headers) {
+        return resp;
+    }
+
+    if !state.router.undrain(&provider_token_id) {
+        return error_response(StatusCode::NOT_FOUND, "Provider is not drained");
+    }
+    json_response(&serde_json::json!({
+        "provider_token_id": provider_token_id,
+        "drained": false,
+    }))
+}
+
+// 管理接口：查询当前被摘除的供应商令牌
+async fn admin_drained_providers(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Some(resp) = authorize_admin(&state.admin, &headers) {
+        return resp;
+    }
+
+    json_response(&serde_json::json!({
+        "providers": state.router.drained_providers(),
+        "cooling_down": state.router.cooling_down_providers(),
+    }))
+}
+
+// 管理接口：查看路由缓存条目及其来源信息
+async fn admin_cache_entries(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<CacheQuery>,
+) -> Response<Body> {
+    if let Some(resp) = authorize_admin(&state.admin, &headers) {
+        return resp;
+    }
+
+    json_response(&serde_json::json!({ "entries": state.router.inspect_cache(&query) }))
+}
+
+// 管理接口：查看当前日志过滤规则和调试采样状态
+async fn admin_logging(State(state): State<AppState>, headers: HeaderMap) -> Response<Body> {
+    if let Some(resp) = authorize_admin(&state.admin, &headers) {
+        return resp;
+    }
+
+    logging_status(&state.logging)
+}
+
+#[derive(Debug, Deserialize)]
+struct LoggingUpdate {
+    /// 新的日志过滤规则（EnvFilter 语法），如 "info,axongate_engine::proxy=debug"
+    filter: Option<String>,
+    /// 对指定令牌开启调试采样
+    sample: Option<SampleRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SampleRequest {
+    /// 用户令牌（路由令牌）
+    token: String,
+    /// 采样接下来的请求数，0 表示取消
+    requests: u32,
+}
+
+// 管理接口：运行时调整日志过滤规则，或对指定令牌开启调试采样
+async fn admin_update_logging(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    if let Some(resp) = authorize_admin(&state.admin, &headers) {
+        return resp;
+    }
+
+    let update: LoggingUpdate = match serde_json::from_slice(&body) {
+        Ok(update) => update,
+        Err(e) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid logging update: {}", e),
+            )
+        }
+    };
+    if update.filter.is_none() && update.sample.is_none() {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "One of filter or sample is required",
+        );
+    }
+
+    if let Some(filter) = &update.filter {
+        if let Err(e) = state.logging.set_filter(filter) {
+            return error_response(StatusCode::BAD_REQUEST, &e.to_string());
+        }
+    }
+    if let Some(sample) = &update.sample {
+        state.logging.sample(&sample.token, sample.requests);
+    }
+    logging_status(&state.logging)
+}
+
+// 管理接口：查询调试采样记录（包含完整的请求和响应内容）
+async fn admin_logging_captures(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<CaptureQuery>,
+) -> Response<Body> {
+    if let Some(resp) = authorize_admin(&state.admin, &headers) {
+        return resp;
+    }
+
+    json_response(&serde_json::json!({ "captures": state.logging.captures(&query) }))
+}
+
+// 管理接口：查询流式请求的字节记录
+async fn admin_request_transcript(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(request_id): Path<String>,
+) -> Response<Body> {
+    if let Some(resp) = authorize_admin(&state.admin, &headers) {
+        return resp;
+    }
+
+    match state.transcripts.get(&request_id) {
+        Some(transcript) => json_response(&transcript),
+        None => error_response(StatusCode::NOT_FOUND, "Transcript not found or expired"),
+    }
+}
+
+fn logging_status(logging: &LogControl) -> Response<Body> {
+    json_response(&serde_json::json!({
+        "filter": logging.filter(),
+        "sampling": logging.sampling(),
+        "captures": logging.capture_count(),
+    }))
+}
+
+// 管理接口：查询本地账本中的遥测事件
+async fn admin_ledger_events(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<LedgerQuery>,
+) -> Response<Body> {
+    if let Some(resp) = authorize_admin(&state.admin, &headers) {
+        return resp;
+    }
+
+    let Some(ledger) = state.telemetry.ledger() else {
+        return error_response(StatusCode::NOT_FOUND, "Usage ledger disabled");
+    };
+
+    match ledger.query(&query).await {
+        Ok(events) => json_response(&serde_json::json!({ "events": events })),
+        Err(e) => {
+            error!("Failed to query usage ledger: {}", e);
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to query ledger")
+        }
+    }
+}
+
+// 管理接口：运行时状态概览
+async fn admin_stats(State(state): State<AppState>, headers: HeaderMap) -> Response<Body> {
+    if let Some(resp) = authorize_admin(&state.admin, &headers) {
+        return resp;
+    }
+
+    json_response(&serde_json::json!({
+        "uptime_secs": state.stats.uptime_secs(),
+        "in_flight_requests": state.stats.in_flight(),
+        "active_streams": state.stats.active_streams(),
+        "streaming_tokens": state.stream_limiter.as_ref().map(|l| l.streaming_tokens()),
+        "route_attempts": state.stats.route_attempts(),
+        "drained_providers": state.router.drained_providers(),
+        "cooling_down_providers": state.router.cooling_down_providers(),
+        "downweighted_providers": state.router.downweighted_providers(),
+        "cache": state.router.cache_sizes(),
+        "route_table": state.router.route_table_status(),
+        "latency_slo": state.latency_slo.status(),
+        "batches": state.batches.len(),
+        "files": state.files.len(),
+    }))
+}
+
+// 管理接口：Prometheus 格式的网关指标，抓取时需携带管理令牌
+async fn admin_metrics(State(state): State<AppState>, headers: HeaderMap) -> Response<Body> {
+    if let Some(resp) = authorize_admin(&state.admin, &headers) {
+        return resp;
+    }
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", prometheus::CONTENT_TYPE)
+        .body(Body::from(state.metrics.render()))
+        .unwrap()
+}
+
+fn json_response<T: serde::Serialize>(value: &T) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_string(value).unwrap_or_default()))
+        .unwrap()
+}
+
+// 校验管理令牌，未通过时返回错误响应
+fn authorize_admin(admin: &AdminConfig, headers: &HeaderMap) -> Option<Response<Body>> {
+    let expected = match admin.token.as_deref() {
+        Some(token) if !token.is_empty() => token,
+        _ => return Some(error_response(StatusCode::NOT_FOUND, "Admin API disabled")),
+    };
+
+    let provided = headers
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.strip_prefix("Bearer "));
+
+    if provided.is_some_and(|provided| token_eq(provided, expected)) {
+        None
+    } else {
+        Some(error_response(
+            StatusCode::UNAUTHORIZED,
+            "Invalid admin token",
+        ))
+    }
+}
+
+/// 缓存失效通知的请求体上限
+const INVALIDATE_BODY_LIMIT: usize = 64 * 1024;
+
+// 业务API推送缓存失效通知，使用业务API认证的凭据校验（Bearer令牌和/或HMAC签名）
+// 多副本部署时业务API需通知每个副本
+async fn handle_invalidate(State(state): State<AppState>, req: Request<Body>) -> Response<Body> {
+    let auth = state.router.business_auth();
+    if !auth.can_verify() {
+        return error_response(StatusCode::NOT_FOUND, "Invalidation endpoint disabled");
+    }
+
+    let (parts, body) = req.into_parts();
+    let body = match axum::body::to_bytes(body, INVALIDATE_BODY_LIMIT).await {
+        Ok(body) => body,
+        Err(_) => return error_response(StatusCode::PAYLOAD_TOO_LARGE, "Request body too large"),
+    };
+
+    let header = |name: &str| parts.headers.get(name).and_then(|v| v.to_str().ok());
+    if !auth.verify(header, &body) {
+        warn!("Rejected cache invalidation with invalid credentials");
+        return error_response(StatusCode::UNAUTHORIZED, "Invalid credentials");
+    }
+
+    let request: InvalidationRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid invalidation request: {}", e),
+            )
+        }
+    };
+    if !request.all && request.token.is_none() && request.provider_token_id.is_none() {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "One of token, provider_token_id or all is required",
+        );
+    }
+    if request.model.is_some() && request.token.is_none() {
+        return error_response(StatusCode::BAD_REQUEST, "model requires token");
+    }
+
+    let removed = state.router.invalidate(&request).await;
+    json_response(&serde_json::json!({ "invalidated": removed }))
+}
+
+/// 路由表推送的请求体上限
+const ROUTE_TABLE_BODY_LIMIT: usize = 64 * 1024 * 1024;
+
+// 业务API推送完整路由表（路由表模式），请求体与拉取 /v1/route/table 的响应相同，
+// 使用业务API认证的凭据校验；多副本部署时业务API需推送到每个副本
+async fn handle_route_table_push(
+    State(state): State<AppState>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let auth = state.router.business_auth();
+    if !auth.can_verify() || state.router.route_table_status().is_none() {
+        return error_response(StatusCode::NOT_FOUND, "Route table endpoint disabled");
+    }
+
+    let (parts, body) = req.into_parts();
+    let body = match axum::body::to_bytes(body, ROUTE_TABLE_BODY_LIMIT).await {
+        Ok(body) => body,
+        Err(_) => return error_response(StatusCode::PAYLOAD_TOO_LARGE, "Request body too large"),
+    };
+
+    let header = |name: &str| parts.headers.get(name).and_then(|v| v.to_str().ok());
+    if !auth.verify(header, &body) {
+        warn!("Rejected route table push with invalid credentials");
+        return error_response(StatusCode::UNAUTHORIZED, "Invalid credentials");
+    }
+
+    let table: RouteTableResponse = match serde_json::from_slice(&body) {
+        Ok(table) => table,
+        Err(e) => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                &format!("Invalid route table: {}", e),
+            )
+        }
+    };
+
+    match state.router.apply_route_table(table) {
+        Ok(routes) => json_response(&serde_json::json!({ "routes": routes })),
+        Err(e) => {
+            error!("Failed to apply pushed route table: {}", e);
+            error_response(StatusCode::BAD_REQUEST, &e.to_string())
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct WebSocketQuery {
+    /// 请求体使用的客户端协议：openai（默认）或 anthropic
+    protocol: Option<String>,
+}
+
+// WebSocket 流式传输：每条消息为一个请求，流式事件逐条以消息返回
+async fn handle_websocket(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Query(query): Query<WebSocketQuery>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response<Body> {
+    let path = match query.protocol.as_deref() {
+        None | Some("openai") => "/v1/chat/completions",
+        Some("anthropic") => "/v1/messages",
+        Some(_) => return error_response(StatusCode::BAD_REQUEST, "Unsupported protocol"),
+    };
+
+    let dispatch = dispatcher(state);
+    upgrade.on_upgrade(move |socket| ws::serve(socket, dispatch, peer, path, headers))
+}
+
+async fn handle_request(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let _in_flight = state.stats.request_started();
+
+    let Some(limit) = state.request_timeout else {
+        return process_request(state, peer, req).await;
+    };
+
+    // 整体截止时间：路由解析重试和所有故障转移尝试共享同一个时限
+    let client_protocol =
+        ProtocolDetector::detect_from_request(&req).unwrap_or(ClientProtocol::OpenAI);
+    match tokio::time::timeout(limit, process_request(state, peer, req)).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!("Request exceeded overall deadline of {:?}", limit);
+            metrics::increment_counter!("gateway_request_deadline_exceeded_total");
+            client_error_response(
+                &client_protocol,
+                StatusCode::GATEWAY_TIMEOUT,
+                "Request timed out",
+            )
+        }
+    }
+}
+
+async fn process_request(state: AppState, peer: SocketAddr, req: Request<Body>) -> Response<Body> {
+    let started = Instant::now();
     // 提取请求路径
     let request_path = req.uri().path().to_string();
 
-    // 检测客户端协议
+    // 检测客户端协议（决定错误响应的格式）
+    let protocol_hint = ProtocolDetector::detect_hint(&req);
     let client_protocol = match ProtocolDetector::detect_from_request(&req) {
         Ok(p) => p,
         Err(e) => {
@@ -120,100 +1032,2071 @@ async fn handle_request(State(state): State<AppState>, req: Request<Body>) -> Re
         }
     };
 
-    // 提取认证信息
-    let user_token = match extract_token(&req) {
+    // 解析真实客户端IP
+    let client_ip = resolve_client_ip(&state, peer, req.headers());
+
+    // 提取并校验认证信息，得到路由令牌
+    let bearer = match extract_token(req.headers()) {
         Some(token) => token,
         None => {
-            return error_response(StatusCode::UNAUTHORIZED, "Missing authorization");
+            return client_error_response(
+                &client_protocol,
+                StatusCode::UNAUTHORIZED,
+                "Missing authorization",
+            );
         }
     };
-
-    // 提取客户端headers（排除拦截列表）
-    let client_headers = filter_client_headers(&req);
-
-    // 读取请求体
-    let body_bytes = match axum::body::to_bytes(req.into_body(), usize::MAX).await {
-        Ok(bytes) => bytes,
+    let AuthIdentity {
+        routing_token: user_token,
+        claims,
+        tenant_id,
+    } = match state.authenticator.authenticate(&bearer).await {
+        Ok(identity) => identity,
         Err(e) => {
-            error!("Failed to read request body: {}", e);
-            return error_response(StatusCode::BAD_REQUEST, "Invalid request body");
+            warn!("Authentication failed: {}", e);
+            return client_error_response(
+                &client_protocol,
+                StatusCode::UNAUTHORIZED,
+                "Invalid authorization",
+            );
         }
     };
 
-    // 打印客户端请求日志
-    let token_display = if user_token.len() > 8 {
-        format!(
-            "{}...{}",
-            &user_token[..4],
-            &user_token[user_token.len() - 4..]
-        )
+    // 识别租户并按客户端IP、租户限流
+    let tenant_id = resolve_tenant(&state, tenant_id, peer, req.headers(), &user_token);
+    if !admit_client(&state, client_ip, tenant_id.as_deref()) {
+        return client_error_response(
+            &client_protocol,
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too Many Requests",
+        );
+    }
+    let client_ip = client_ip.to_string();
+
+    // 客户端声明的 API 版本对应的兼容处理
+    let compat = match state.compat.select(req.headers()) {
+        Ok(profile) => profile.cloned(),
+        Err(version) => {
+            return client_error_response(
+                &client_protocol,
+                StatusCode::BAD_REQUEST,
+                &format!("Unsupported API version: {}", version),
+            );
+        }
+    };
+
+    // 提取客户端headers（排除拦截列表）
+    let mut client_headers = filter_client_headers(req.headers());
+    if let Some(header) = state.compat.header() {
+        client_headers.remove(header);
+    }
+    if let Some(profile) = &compat {
+        compat::apply_headers(profile, &mut client_headers);
+    }
+    let client_app = extract_client_app(&state, req.headers());
+    let priority = state.latency_slo.priority(req.headers());
+    let expose_trace =
+        state.expose_routing_trace && req.headers().contains_key(ROUTING_DEBUG_HEADER);
+    let transcript = state.transcripts.enabled() && req.headers().contains_key(TRANSCRIPT_HEADER);
+
+    // 请求体之外的模型名来源（header / Azure 部署路径）
+    let model_hint = extract_model_hint(&req);
+
+    // 大请求体在协议和模型无需读取请求体即可确定时，尝试不经缓冲直接转发（兼容处理需改写请求体时除外）
+    let rewrites_body = compat.as_ref().is_some_and(compat::rewrites_body);
+    let req = match (&protocol_hint, &model_hint) {
+        (Some(protocol), Some(model)) if request_path != "/v1/responses" && !rewrites_body => {
+            match try_forward_streaming_body(
+                &state,
+                req,
+                protocol,
+                model,
+                &user_token,
+                tenant_id.as_deref(),
+                &client_headers,
+                &client_ip,
+                &claims,
+                &client_app,
+                priority,
+            )
+            .await
+            {
+                Ok(response) => {
+                    record_latency_slo(&state.latency_slo, priority, started, &response);
+                    return response;
+                }
+                Err(req) => req,
+            }
+        }
+        _ => req,
+    };
+
+    // 读取请求体
+    let body_bytes = match axum::body::to_bytes(req.into_body(), usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Failed to read request body: {}", e);
+            return client_error_response(
+                &client_protocol,
+                StatusCode::BAD_REQUEST,
+                "Invalid request body",
+            );
+        }
+    };
+
+    // 打印客户端请求日志
+    let token_display = mask_token(&user_token);
+
+    // 解析请求获取模型名：请求体 -> header / 路径 -> 业务API配置的默认模型 -> 配置的兜底模型
+    let body_model = extract_model(&body_bytes);
+    let requested_model = match body_model.clone().or(model_hint) {
+        Some(model) => model,
+        None => match state
+            .router
+            .default_model_for(&user_token, &client_protocol)
+            .await
+        {
+            Some(model) => model,
+            None => {
+                return client_error_response(
+                    &client_protocol,
+                    StatusCode::BAD_REQUEST,
+                    "Missing model field",
+                );
+            }
+        },
+    };
+
+    // 请求体中没有模型名时补齐，保证后续协议转换可用
+    let body_bytes = if body_model.is_none() {
+        match inject_model(&body_bytes, &requested_model) {
+            Some(bytes) => bytes,
+            None => {
+                return client_error_response(
+                    &client_protocol,
+                    StatusCode::BAD_REQUEST,
+                    "Invalid request body",
+                )
+            }
+        }
+    } else {
+        body_bytes
+    };
+
+    // 旧版函数调用格式（functions / function_call）规范化为 tools 格式，响应再转换回旧版格式
+    let legacy_functions = matches!(client_protocol, ClientProtocol::OpenAI)
+        && legacy_functions::is_legacy_request(&body_bytes);
+    let body_bytes = if legacy_functions {
+        legacy_functions::normalize_request(body_bytes)
+    } else {
+        body_bytes
+    };
+    let body_bytes = match &compat {
+        Some(profile) => compat::apply_request(profile, &client_protocol, body_bytes),
+        None => body_bytes,
+    };
+
+    info!(
+        "Request received - protocol: {:?}, model: {}, path: {}, client_ip: {}, token: {}",
+        client_protocol, requested_model, request_path, client_ip, token_display
+    );
+
+    // 判断是否是流式请求
+    let is_stream = ProtocolDetector::is_stream_request(&body_bytes);
+
+    // 执行前预估费用，随响应返回
+    let estimated_cost = state
+        .expose_cost_estimate
+        .then(|| estimate_request_cost(&state, &requested_model, &body_bytes).headline())
+        .flatten();
+
+    // 流式请求占用令牌的流式名额，直到流结束才释放
+    let stream_slot = if is_stream {
+        match admit_stream(&state, &client_protocol, &user_token) {
+            Ok(slot) => slot,
+            Err(response) => return response,
+        }
+    } else {
+        None
+    };
+
+    // 获取路由配置
+    let hints = RouteHints {
+        priority,
+        ..collect_route_hints(&state, &body_bytes, &client_protocol, is_stream, client_app)
+    };
+    let route_configs = match state
+        .router
+        .resolve_route(&user_token, tenant_id.as_deref(), &requested_model, &hints)
+        .await
+    {
+        Ok(configs) => configs,
+        Err(e) => {
+            error!("Failed to resolve route: {}", e);
+            return client_error_response(
+                &client_protocol,
+                StatusCode::SERVICE_UNAVAILABLE,
+                "No available routes",
+            );
+        }
+    };
+    // 延续上游会话的 Responses API 请求只能发往原生支持 Responses API 的路由
+    let route_configs = if request_path == responses::RESPONSES_PATH
+        && responses::continues_previous_response(&body_bytes)
+    {
+        let native: Vec<RouteConfig> = route_configs
+            .into_iter()
+            .filter(|config| responses::bridge(true, config).is_none())
+            .collect();
+        if native.is_empty() {
+            return invalid_request_response(
+                responses::PREVIOUS_RESPONSE_UNSUPPORTED,
+                "previous_response_id",
+            );
+        }
+        native
+    } else {
+        route_configs
+    };
+    // 首次请求时租户可能刚由业务API返回
+    let tenant_id = tenant_id.or_else(|| state.router.tenant_of(&user_token));
+    // 调试模式下返回所用路由距业务API解析的秒数
+    let route_age = expose_trace
+        .then(|| {
+            state
+                .router
+                .route_provenance(&user_token, tenant_id.as_deref(), &requested_model)
+        })
+        .flatten()
+        .map(|provenance| provenance.age_secs());
+
+    info!(
+        "Request routing - stream: {}, protocol: {:?}, model: {}, path: {}",
+        is_stream, client_protocol, requested_model, request_path
+    );
+
+    // 令牌开启调试采样时记录本次请求的完整内容
+    let sampled = state.logging.take_sample(&user_token);
+    // 业务API为令牌开通对话内容记录时记录请求和返回客户端的响应
+    let content_session = state.content_log.as_ref().and_then(|content_log| {
+        let policy = state.router.content_logging_of(&user_token)?;
+        Some(content_log.start(
+            &policy,
+            &user_token,
+            tenant_id.as_deref(),
+            &requested_model,
+            &request_path,
+            &body_bytes,
+        ))
+    });
+
+    // 依次尝试各路由，过程记录在路由追踪中
+    let router = state.router.clone();
+    let telemetry = state.telemetry.clone();
+    let latency_slo = state.latency_slo.clone();
+    let mut failover = FailoverQueue::new(route_configs);
+    let ctx = RequestContext::new(
+        state,
+        client_protocol,
+        body_bytes,
+        user_token,
+        requested_model,
+        request_path,
+        client_headers,
+        client_ip,
+        claims,
+        tenant_id,
+        is_stream,
+        legacy_functions,
+    )
+    .with_capture(sampled);
+    let mut response = if is_stream {
+        handle_stream(ctx.with_transcript(transcript), &mut failover, stream_slot).await
+    } else {
+        handle_non_stream(ctx, &mut failover).await
+    };
+
+    let trace = failover.trace();
+    router.observe_trace(&trace);
+    telemetry.observe_trace(&trace);
+    record_routing_trace(&trace, expose_trace, &mut response);
+    if let Some(age) = route_age {
+        response
+            .headers_mut()
+            .insert(ROUTE_AGE_HEADER, HeaderValue::from(age));
+    }
+    if let Some(cost) = estimated_cost {
+        if let Ok(value) = HeaderValue::from_str(&format!("{:.6}", cost)) {
+            response.headers_mut().insert(ESTIMATED_COST_HEADER, value);
+        }
+    }
+    record_latency_slo(&latency_slo, priority, started, &response);
+    match content_session {
+        Some(session) => log_response_content(session, response),
+        None => response,
+    }
+}
+
+// 响应体经过内容记录器，响应结束时提交记录
+fn log_response_content(session: ContentSession, response: Response<Body>) -> Response<Body> {
+    let request_id = response
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map_or_else(|| Uuid::new_v4().to_string(), str::to_string);
+    let status = response.status().as_u16();
+    let (parts, body) = response.into_parts();
+    let body = session.tap_response(request_id, status, body.into_data_stream());
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+// 按请求声明的优先级统计成功响应的延迟是否达到目标（流式响应为开始输出的时间）
+fn record_latency_slo(
+    latency_slo: &LatencySlo,
+    priority: Option<LatencyClass>,
+    started: Instant,
+    response: &Response<Body>,
+) {
+    if let Some(priority) = priority.filter(|_| response.status().is_success()) {
+        latency_slo.record(priority, started.elapsed());
+    }
+}
+
+// 按请求体估算提示词Token数，结合客户端请求的最大输出Token数和模型价格预估费用
+fn estimate_request_cost(state: &AppState, model: &str, body: &Bytes) -> CostEstimate {
+    state.telemetry.usage_stats().estimate(
+        model,
+        ProtocolDetector::estimate_prompt_tokens(body).unwrap_or(0),
+        ProtocolDetector::requested_max_tokens(body),
+    )
+}
+
+// 费用预估：认证后按请求体预估费用，不转发上游
+// 请求体与对话接口相同，未携带模型名时使用业务API配置的默认模型
+async fn handle_estimate(State(state): State<AppState>, req: Request<Body>) -> Response<Body> {
+    if !state.expose_cost_estimate {
+        return error_response(StatusCode::NOT_FOUND, "Not Found");
+    }
+
+    let protocol_hint = ProtocolDetector::detect_hint(&req);
+    let protocol = protocol_hint.clone().unwrap_or(ClientProtocol::OpenAI);
+    let Some(bearer) = extract_token(req.headers()) else {
+        return client_error_response(&protocol, StatusCode::UNAUTHORIZED, "Missing authorization");
+    };
+    let user_token = match state.authenticator.authenticate(&bearer).await {
+        Ok(identity) => identity.routing_token,
+        Err(e) => {
+            warn!("Authentication failed: {}", e);
+            return client_error_response(
+                &protocol,
+                StatusCode::UNAUTHORIZED,
+                "Invalid authorization",
+            );
+        }
+    };
+    let model_hint = extract_model_hint(&req);
+
+    let body_bytes = match axum::body::to_bytes(req.into_body(), usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Failed to read request body: {}", e);
+            return client_error_response(
+                &protocol,
+                StatusCode::BAD_REQUEST,
+                "Invalid request body",
+            );
+        }
+    };
+    if serde_json::from_slice::<serde_json::Value>(&body_bytes).is_err() {
+        return client_error_response(&protocol, StatusCode::BAD_REQUEST, "Invalid request body");
+    }
+    // 估算接口的路径不区分协议，请求头也无法确定时按请求体结构判断，决定使用哪个协议的默认模型
+    let protocol = protocol_hint
+        .or_else(|| ProtocolDetector::detect_from_body(&body_bytes))
+        .unwrap_or(protocol);
+
+    let model = match extract_model(&body_bytes).or(model_hint) {
+        Some(model) => Some(model),
+        None => state.router.default_model_for(&user_token, &protocol).await,
+    };
+    let Some(model) = model else {
+        return client_error_response(&protocol, StatusCode::BAD_REQUEST, "Missing model field");
+    };
+
+    json_response(&estimate_request_cost(&state, &model, &body_bytes))
+}
+
+// 将路由追踪记录到请求 span，按需通过响应头返回给客户端
+fn record_routing_trace(trace: &RoutingTrace, expose: bool, response: &mut Response<Body>) {
+    if trace.attempts.is_empty() {
+        return;
+    }
+    let Ok(json) = serde_json::to_string(trace) else {
+        return;
+    };
+
+    tracing::Span::current().record("routing_trace", json.as_str());
+    if trace.attempts.len() > 1 {
+        info!("Routing trace: {}", json);
+    } else {
+        debug!("Routing trace: {}", json);
+    }
+
+    if expose {
+        if let Ok(value) = HeaderValue::from_str(&json) {
+            response.headers_mut().insert(ROUTING_TRACE_HEADER, value);
+        }
+    }
+}
+
+// 大请求体流式转发：Content-Length 不小于阈值、且首选路由无需协议转换、改写模型名及其他
+// 请求体处理时，不读取请求体，边读边转发给上游，避免大体积多模态请求整体驻留内存。
+// 请求体无法重放，只尝试首选路由；不满足条件时原样交还请求，由常规流程处理。
+#[allow(clippy::too_many_arguments)]
+async fn try_forward_streaming_body(
+    state: &AppState,
+    req: Request<Body>,
+    client_protocol: &ClientProtocol,
+    requested_model: &str,
+    user_token: &str,
+    tenant_id: Option<&str>,
+    client_headers: &reqwest::header::HeaderMap,
+    client_ip: &str,
+    claims: &Option<HashMap<String, serde_json::Value>>,
+    client_app: &Option<String>,
+    priority: Option<LatencyClass>,
+) -> std::result::Result<Response<Body>, Request<Body>> {
+    let Some(threshold) = state.streaming_body_threshold else {
+        return Err(req);
+    };
+    let content_length = req
+        .headers()
+        .get("content-length")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    let Some(content_length) = content_length.filter(|len| *len >= threshold) else {
+        return Err(req);
+    };
+
+    // 请求体未读取，只能提供不依赖请求体的路由提示
+    let enrichment = state.router.route_enrichment();
+    let hints = RouteHints {
+        client_protocol: enrichment.client_protocol.then(|| client_protocol.clone()),
+        client_app: client_app.clone(),
+        priority,
+        ..Default::default()
+    };
+    let Ok(route_configs) = state
+        .router
+        .resolve_route(user_token, tenant_id, requested_model, &hints)
+        .await
+    else {
+        return Err(req);
+    };
+    let Some((attempt, config)) = FailoverQueue::new(route_configs).next_route() else {
+        return Err(req);
+    };
+    if state.proxy.is_mocked(&config)
+        || responses::bridge(false, &config).is_some()
+        || !state.adapter.passes_through(
+            client_protocol,
+            &config.protocol,
+            requested_model,
+            &model_rewrite::upstream_model(&config),
+        )
+        || buffers_body(state, &config, user_token, client_headers)
+    {
+        return Err(req);
+    }
+    // 未读取请求体无法判断是否流式请求，转发前先占用流式名额（非流式响应时随之释放），
+    // 已达上限时交由常规流程按请求体判断是否拒绝，避免上游已开始生成后才拒绝
+    let Ok(stream_slot) = admit_stream(state, client_protocol, user_token) else {
+        return Err(req);
+    };
+    let tenant_id = tenant_id
+        .map(str::to_string)
+        .or_else(|| state.router.tenant_of(user_token));
+
+    info!(
+        "Streaming request body ({} bytes) to {} without buffering",
+        content_length, config.api_endpoint
+    );
+    let request_id = Uuid::new_v4().to_string();
+    let body = reqwest::Body::wrap_stream(req.into_body().into_data_stream());
+
+    let upstream = match state
+        .proxy
+        .forward_streaming_body(&config, body, content_length, client_headers)
+        .await
+    {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            record_upstream_attempt(state, &config, Err(&e));
+            error!(
+                "Streaming body request failed for {}: {}",
+                config.api_endpoint, e
+            );
+
+            state.telemetry.report_error(ErrorEvent {
+                request_id,
+                attempt,
+                token: config.token.clone(),
+                model: config.model.clone(),
+                api: config.api_endpoint.clone(),
+                msg: e.to_string(),
+                provider_token_id: Some(config.provider_token_id.clone()),
+                client_ip: Some(client_ip.to_string()),
+                claims: claims.clone(),
+                tenant_id: tenant_id.clone(),
+                upstream_request_id: upstream_request_id_of(&e),
+            });
+            state
+                .telemetry
+                .usage_stats()
+                .record_error(user_token, &config.provider_id);
+
+            if state.proxy.is_client_error(&e) || size_limit::is_too_large(&e) {
+                return Ok(create_error_response(client_protocol, &e));
+            }
+            state
+                .router
+                .remove_failed_route(user_token, tenant_id.as_deref(), requested_model, &config)
+                .await;
+
+            // 请求体已被读取，无法故障转移到其他路由
+            return Ok(client_error_response(
+                client_protocol,
+                StatusCode::BAD_GATEWAY,
+                "Upstream request failed",
+            ));
+        }
+    };
+    record_upstream_attempt(state, &config, Ok(()));
+
+    let mut upstream_headers = upstream.headers;
+    let is_sse = upstream_headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"));
+
+    if is_sse {
+        upstream_headers.remove(reqwest::header::CONTENT_TYPE);
+
+        let usage_collector = Arc::new(
+            StreamUsageCollector::new(
+                request_id,
+                user_token.to_string(),
+                Some(client_ip.to_string()),
+                claims.clone(),
+                tenant_id,
+                config.clone(),
+                state.telemetry.clone(),
+            )
+            .with_upstream_request_id(upstream.request_id)
+            .with_max_event_bytes(state.proxy.max_sse_event_bytes()),
+        );
+        let byte_stream = framing::normalize_to_sse(&config.protocol, upstream.body);
+        let byte_stream = framing::limit_event_size(
+            &config.protocol,
+            byte_stream,
+            state.proxy.max_sse_event_bytes(),
+        );
+        let byte_stream = match state.proxy.max_stream_bytes() {
+            Some(max) => framing::limit_stream_size(&config.protocol, byte_stream, max),
+            None => byte_stream,
+        };
+        let stream: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>> =
+            Box::pin(usage_collector.wrap_stream(byte_stream).await);
+        let stream = match &config.smooth_streaming {
+            Some(smooth) => Box::pin(smooth_stream(stream, smooth.tokens_per_second)),
+            None => stream,
+        };
+        let format = client_stream_format(client_headers);
+        let stream = format.encode(stream);
+        let stream = match (state.proxy.watermark(&config), format) {
+            (Some(watermark), ClientStreamFormat::Sse) => watermark.mark_sse_stream(stream),
+            _ => stream,
+        };
+        let stream = state.stats.track_stream(stream, stream_slot);
+
+        return Ok(
+            with_upstream_headers(Response::builder(), &upstream_headers)
+                .status(StatusCode::OK)
+                .header("content-type", format.content_type())
+                .header("cache-control", "no-cache")
+                .header("connection", "keep-alive")
+                .header("x-accel-buffering", "no")
+                .body(Body::from_stream(stream))
+                .unwrap(),
+        );
+    }
+
+    // 非流式响应体积小，读取完整响应以提取用量（超过 proxy.max_response_bytes 时中止）
+    let body = size_limit::limit_body(upstream.body, state.proxy.max_response_bytes());
+    let response_body = match read_upstream_body(Box::pin(body)).await {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to read upstream response: {}", e);
+            if size_limit::is_too_large(&e) {
+                return Ok(create_error_response(client_protocol, &e));
+            }
+            return Ok(client_error_response(
+                client_protocol,
+                StatusCode::BAD_GATEWAY,
+                "Upstream request failed",
+            ));
+        }
+    };
+
+    let usage = usage::parse_response(&config.protocol, &response_body).unwrap_or_default();
+    if let Some((input_tokens, output_tokens)) = usage.totals() {
+        state.telemetry.report_usage(usage.annotate(UsageEvent {
+            request_id,
+            token: user_token.to_string(),
+            model: requested_model.to_string(),
+            api: config.api_endpoint.clone(),
+            input_tokens,
+            output_tokens,
+            model_id: config.model_id.clone(),
+            provider_id: config.provider_id.clone(),
+            provider_token_id: config.provider_token_id.clone(),
+            canary: config.canary.as_ref().map(|c| c.tag.clone()),
+            client_ip: Some(client_ip.to_string()),
+            claims: claims.clone(),
+            tenant_id,
+            upstream_request_id: upstream.request_id,
+            ..Default::default()
+        }));
+    }
+
+    // content-type 已包含在上游响应头中
+    upstream_headers
+        .entry(reqwest::header::CONTENT_TYPE)
+        .or_insert(reqwest::header::HeaderValue::from_static(
+            "application/json",
+        ));
+    let response_body = match state.proxy.watermark(&config) {
+        Some(watermark) => watermark.mark_response(response_body),
+        None => response_body,
+    };
+    Ok(
+        with_upstream_headers(Response::builder(), &upstream_headers)
+            .status(StatusCode::OK)
+            .body(Body::from(response_body))
+            .unwrap(),
+    )
+}
+
+// 处理非对话类接口（音频、图像生成）
+// 请求体原样转发，不做协议转换：
+// - 语音转写：multipart/form-data，模型名取自表单 model 字段，按音频时长上报用量
+// - 语音合成：JSON，按输入字符数上报用量
+// - 图像生成：JSON，按图片数量/尺寸/质量上报用量；自定义图像后端可通过
+//   路由配置的 image_generation_path 指定上游路径
+// - 向量嵌入：JSON，按输入Token数上报用量
+// - 重排序：网关统一格式，按路由配置的 rerank_provider 转换为 Cohere/Voyage/Jina
+//   格式，响应规范化后返回，按文档数量、Token数或搜索单元上报用量
+// 只能路由到 OpenAI 兼容或自定义上游，Anthropic 上游会被跳过。
+async fn handle_passthrough(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let _in_flight = state.stats.request_started();
+    let request_path = req.uri().path().to_string();
+
+    let client_ip = resolve_client_ip(&state, peer, req.headers());
+
+    let bearer = match extract_token(req.headers()) {
+        Some(token) => token,
+        None => {
+            return error_response(StatusCode::UNAUTHORIZED, "Missing authorization");
+        }
+    };
+    let AuthIdentity {
+        routing_token: user_token,
+        claims,
+        tenant_id,
+    } = match state.authenticator.authenticate(&bearer).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            warn!("Authentication failed: {}", e);
+            return error_response(StatusCode::UNAUTHORIZED, "Invalid authorization");
+        }
+    };
+
+    let tenant_id = resolve_tenant(&state, tenant_id, peer, req.headers(), &user_token);
+    if !admit_client(&state, client_ip, tenant_id.as_deref()) {
+        return error_response(StatusCode::TOO_MANY_REQUESTS, "Too Many Requests");
+    }
+    let client_ip = client_ip.to_string();
+
+    let mut client_headers = filter_client_headers(req.headers());
+    if let Some(header) = state.compat.header() {
+        client_headers.remove(header);
+    }
+    let client_app = extract_client_app(&state, req.headers());
+    let content_type = req
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/json")
+        .to_string();
+    let boundary = multipart::boundary(&content_type);
+
+    let body_bytes = match axum::body::to_bytes(req.into_body(), usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Failed to read request body: {}", e);
+            return error_response(StatusCode::BAD_REQUEST, "Invalid request body");
+        }
+    };
+
+    let requested_model = match &boundary {
+        Some(boundary) => multipart::extract_field(&body_bytes, boundary, "model"),
+        None => extract_model(&body_bytes),
+    };
+    let requested_model = match requested_model {
+        Some(model) => model,
+        None => return error_response(StatusCode::BAD_REQUEST, "Missing model field"),
+    };
+
+    let rerank = if request_path == "/v1/rerank" {
+        match RerankRequest::parse(&body_bytes) {
+            Ok(request) => Some(request),
+            Err(e) => return error_response(StatusCode::BAD_REQUEST, &e.to_string()),
+        }
+    } else {
+        None
+    };
+
+    // 从请求体中提取计费维度（字符数、图片尺寸等）
+    let request_usage = passthrough_request_usage(&request_path, &body_bytes);
+
+    info!(
+        "Passthrough request received - model: {}, path: {}, client_ip: {}",
+        requested_model, request_path, client_ip
+    );
+
+    let hints = collect_route_hints(
+        &state,
+        &body_bytes,
+        &ClientProtocol::OpenAI,
+        false,
+        client_app,
+    );
+    let route_configs = match state
+        .router
+        .resolve_route(&user_token, tenant_id.as_deref(), &requested_model, &hints)
+        .await
+    {
+        Ok(configs) => configs,
+        Err(e) => {
+            error!("Failed to resolve route: {}", e);
+            return error_response(StatusCode::SERVICE_UNAVAILABLE, "No available routes");
+        }
+    };
+    let tenant_id = tenant_id.or_else(|| state.router.tenant_of(&user_token));
+
+    let request_id = Uuid::new_v4().to_string();
+
+    let mut failover = FailoverQueue::new(route_configs);
+    while let Some((attempt, config)) = failover.next_route() {
+        if matches!(config.protocol, TargetProtocol::Anthropic) {
+            info!(
+                "Skipping route {} for {}: Anthropic upstream does not support this endpoint",
+                config.api_endpoint, request_path
+            );
+            continue;
+        }
+
+        let rerank_provider = config.rerank_provider.unwrap_or(RerankProvider::Cohere);
+
+        // 自定义图像后端、重排序供应商可能使用不同的接口路径
+        let upstream_path = match (&config.image_generation_path, &config.rerank_path) {
+            (Some(path), _) if request_path == "/v1/images/generations" => path.as_str(),
+            (_, Some(path)) if rerank.is_some() => path.as_str(),
+            _ if rerank.is_some() => rerank::default_path(rerank_provider),
+            _ => request_path.as_str(),
+        };
+
+        // 将模型名替换为上游模型，重排序请求转换为供应商格式
+        let upstream_model = model_rewrite::upstream_model(&config);
+        let upstream_body = match (&rerank, &boundary) {
+            (Some(rerank), _) => Some(rerank.to_provider(rerank_provider, &upstream_model)),
+            (None, Some(boundary)) => {
+                multipart::replace_field(&body_bytes, boundary, "model", &upstream_model)
+            }
+            (None, None) => inject_model(&body_bytes, &upstream_model),
+        };
+        let upstream_content_type = match &rerank {
+            Some(_) => "application/json",
+            None => content_type.as_str(),
+        };
+        let upstream_body = match upstream_body {
+            Some(body) => body,
+            None => return error_response(StatusCode::BAD_REQUEST, "Invalid request body"),
+        };
+
+        match state
+            .proxy
+            .forward_raw(
+                &config,
+                upstream_body,
+                upstream_path,
+                upstream_content_type,
+                &client_headers,
+            )
+            .await
+        {
+            Ok(upstream) => {
+                record_upstream_attempt(&state, &config, Ok(()));
+                let mut usage = UsageEvent {
+                    request_id: request_id.clone(),
+                    token: user_token.clone(),
+                    model: requested_model.clone(),
+                    api: config.api_endpoint.clone(),
+                    model_id: config.model_id.clone(),
+                    provider_id: config.provider_id.clone(),
+                    provider_token_id: config.provider_token_id.clone(),
+                    canary: config.canary.as_ref().map(|c| c.tag.clone()),
+                    client_ip: Some(client_ip.clone()),
+                    claims: claims.clone(),
+                    tenant_id: tenant_id.clone(),
+                    upstream_request_id: upstream.request_id.clone(),
+                    ..request_usage.clone()
+                };
+                let body = match &rerank {
+                    Some(rerank) => {
+                        match rerank.normalize_response(rerank_provider, &upstream.body) {
+                            Ok((body, reranked)) => {
+                                usage.input_tokens = reranked.total_tokens.unwrap_or(0) as i32;
+                                usage.search_units = reranked.search_units.map(|u| u as u32);
+                                body
+                            }
+                            Err(e) => {
+                                error!(
+                                    "Invalid rerank response from {}: {}",
+                                    config.api_endpoint, e
+                                );
+                                return error_response(
+                                    StatusCode::BAD_GATEWAY,
+                                    "Invalid rerank response",
+                                );
+                            }
+                        }
+                    }
+                    None => {
+                        extract_passthrough_usage(&upstream.body, &mut usage);
+                        upstream.body
+                    }
+                };
+                state.telemetry.report_usage(usage);
+
+                // content-type 已包含在上游响应头中（音频为二进制）
+                return with_upstream_headers(Response::builder(), &upstream.headers)
+                    .status(StatusCode::OK)
+                    .body(Body::from(body))
+                    .unwrap();
+            }
+            Err(e) => {
+                record_upstream_attempt(&state, &config, Err(&e));
+                error!(
+                    "Passthrough request failed for {}: {}",
+                    config.api_endpoint, e
+                );
+
+                state.telemetry.report_error(ErrorEvent {
+                    request_id: request_id.clone(),
+                    attempt,
+                    token: config.token.clone(),
+                    model: config.model.clone(),
+                    api: config.api_endpoint.clone(),
+                    msg: e.to_string(),
+                    provider_token_id: Some(config.provider_token_id.clone()),
+                    client_ip: Some(client_ip.clone()),
+                    claims: claims.clone(),
+                    tenant_id: tenant_id.clone(),
+                    upstream_request_id: upstream_request_id_of(&e),
+                });
+                state
+                    .telemetry
+                    .usage_stats()
+                    .record_error(&user_token, &config.provider_id);
+
+                if state.proxy.is_client_error(&e) {
+                    return create_error_response(&ClientProtocol::OpenAI, &e);
+                }
+                if size_limit::is_too_large(&e) {
+                    failover.record_failure(&config, FailureClass::Deterministic);
+                    return create_error_response(&ClientProtocol::OpenAI, &e);
+                }
+
+                state
+                    .router
+                    .remove_failed_route(
+                        &user_token,
+                        tenant_id.as_deref(),
+                        &requested_model,
+                        &config,
+                    )
+                    .await;
+                failover.record_failure(&config, state.proxy.classify_failure(&e));
+                continue;
+            }
+        }
+    }
+
+    error_response(StatusCode::SERVICE_UNAVAILABLE, "All routes failed")
+}
+
+// 批处理、文件接口的调用方
+struct BatchClient {
+    user_token: String,
+    tenant_id: Option<String>,
+    claims: Option<HashMap<String, serde_json::Value>>,
+    client_ip: String,
+    client_headers: reqwest::header::HeaderMap,
+}
+
+// 认证批处理、文件接口的调用方并按客户端IP、租户限流，失败时返回客户端协议格式的错误响应
+#[allow(clippy::result_large_err)]
+async fn authenticate_batch_client(
+    state: &AppState,
+    peer: SocketAddr,
+    headers: &HeaderMap,
+    protocol: &ClientProtocol,
+) -> std::result::Result<BatchClient, Response<Body>> {
+    let client_ip = resolve_client_ip(state, peer, headers);
+
+    let Some(bearer) = extract_token(headers) else {
+        return Err(client_error_response(
+            protocol,
+            StatusCode::UNAUTHORIZED,
+            "Missing authorization",
+        ));
+    };
+    let AuthIdentity {
+        routing_token: user_token,
+        claims,
+        tenant_id,
+    } = match state.authenticator.authenticate(&bearer).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            warn!("Authentication failed: {}", e);
+            return Err(client_error_response(
+                protocol,
+                StatusCode::UNAUTHORIZED,
+                "Invalid authorization",
+            ));
+        }
+    };
+
+    let tenant_id = resolve_tenant(state, tenant_id, peer, headers, &user_token);
+    if !admit_client(state, client_ip, tenant_id.as_deref()) {
+        return Err(client_error_response(
+            protocol,
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too Many Requests",
+        ));
+    }
+
+    Ok(BatchClient {
+        user_token,
+        tenant_id,
+        claims,
+        client_ip: client_ip.to_string(),
+        client_headers: filter_client_headers(headers),
+    })
+}
+
+// Anthropic Message Batches：创建批处理
+// 批处理内所有请求需使用同一模型，按该模型解析路由，只能路由到 Anthropic 上游。
+// 创建成功后登记批处理所在路由，之后的查询、取消和结果下载都发往同一上游和供应商令牌。
+async fn handle_batch_create(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let _in_flight = state.stats.request_started();
+    let protocol = ClientProtocol::Anthropic;
+
+    let client = match authenticate_batch_client(&state, peer, req.headers(), &protocol).await {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+    let client_app = extract_client_app(&state, req.headers());
+
+    let body_bytes = match axum::body::to_bytes(req.into_body(), usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Failed to read request body: {}", e);
+            return client_error_response(
+                &protocol,
+                StatusCode::BAD_REQUEST,
+                "Invalid request body",
+            );
+        }
+    };
+    let Ok(body) = serde_json::from_slice::<serde_json::Value>(&body_bytes) else {
+        return client_error_response(&protocol, StatusCode::BAD_REQUEST, "Invalid request body");
+    };
+    let requested_model = match batch_model(&body) {
+        Ok(model) => model,
+        Err(message) => {
+            return client_error_response(&protocol, StatusCode::BAD_REQUEST, message);
+        }
+    };
+
+    info!(
+        "Batch create request received - model: {}, client_ip: {}",
+        requested_model, client.client_ip
+    );
+
+    let hints = collect_route_hints(&state, &Bytes::new(), &protocol, false, client_app);
+    let route_configs = match state
+        .router
+        .resolve_route(
+            &client.user_token,
+            client.tenant_id.as_deref(),
+            &requested_model,
+            &hints,
+        )
+        .await
+    {
+        Ok(configs) => configs,
+        Err(e) => {
+            error!("Failed to resolve route: {}", e);
+            return client_error_response(
+                &protocol,
+                StatusCode::SERVICE_UNAVAILABLE,
+                "No available routes",
+            );
+        }
+    };
+    let tenant_id = client
+        .tenant_id
+        .clone()
+        .or_else(|| state.router.tenant_of(&client.user_token));
+
+    let request_id = Uuid::new_v4().to_string();
+
+    let mut failover = FailoverQueue::new(route_configs);
+    while let Some((attempt, config)) = failover.next_route() {
+        if !matches!(config.protocol, TargetProtocol::Anthropic) {
+            info!(
+                "Skipping route {} for message batches: only Anthropic upstreams are supported",
+                config.api_endpoint
+            );
+            continue;
+        }
+
+        // 将模型名替换为上游模型
+        let mut upstream_body = body.clone();
+        if let Some(requests) = upstream_body
+            .get_mut("requests")
+            .and_then(|r| r.as_array_mut())
+        {
+            for request in requests {
+                request["params"]["model"] =
+                    serde_json::Value::String(model_rewrite::upstream_model(&config).into_owned());
+            }
+        }
+        let upstream_body = match serde_json::to_vec(&upstream_body) {
+            Ok(body) => Bytes::from(body),
+            Err(e) => {
+                error!("Failed to encode batch request: {}", e);
+                return client_error_response(
+                    &protocol,
+                    StatusCode::BAD_REQUEST,
+                    "Invalid request body",
+                );
+            }
+        };
+
+        let result = match state
+            .proxy
+            .forward_batch(
+                &config,
+                reqwest::Method::POST,
+                "/v1/messages/batches",
+                Some(upstream_body),
+                &client.client_headers,
+            )
+            .await
+        {
+            Ok(upstream) => read_upstream_body(upstream.body)
+                .await
+                .map(|body| (upstream.headers, body)),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok((upstream_headers, response_body)) => {
+                record_upstream_attempt(&state, &config, Ok(()));
+                let batch_id = serde_json::from_slice::<serde_json::Value>(&response_body)
+                    .ok()
+                    .and_then(|v| v.get("id")?.as_str().map(str::to_string));
+                match &batch_id {
+                    Some(batch_id) => {
+                        info!("Batch {} created on {}", batch_id, config.api_endpoint);
+                        state
+                            .batches
+                            .register(
+                                batch_id.clone(),
+                                BatchOwner {
+                                    user_token: client.user_token.clone(),
+                                    tenant_id: tenant_id.clone(),
+                                    client_ip: Some(client.client_ip.clone()),
+                                    claims: client.claims.clone(),
+                                },
+                                requested_model.clone(),
+                                config.clone(),
+                            )
+                            .await;
+                    }
+                    None => warn!(
+                        "Batch create response from {} has no id, batch will not be routable",
+                        config.api_endpoint
+                    ),
+                }
+
+                return with_upstream_headers(Response::builder(), &upstream_headers)
+                    .status(StatusCode::OK)
+                    .body(Body::from(rewrite_batch_object(
+                        response_body,
+                        state.public_base_url.as_deref(),
+                    )))
+                    .unwrap();
+            }
+            Err(e) => {
+                record_upstream_attempt(&state, &config, Err(&e));
+                error!("Batch create failed for {}: {}", config.api_endpoint, e);
+
+                state.telemetry.report_error(ErrorEvent {
+                    request_id: request_id.clone(),
+                    attempt,
+                    token: config.token.clone(),
+                    model: config.model.clone(),
+                    api: config.api_endpoint.clone(),
+                    msg: e.to_string(),
+                    provider_token_id: Some(config.provider_token_id.clone()),
+                    client_ip: Some(client.client_ip.clone()),
+                    claims: client.claims.clone(),
+                    tenant_id: tenant_id.clone(),
+                    upstream_request_id: upstream_request_id_of(&e),
+                });
+                state
+                    .telemetry
+                    .usage_stats()
+                    .record_error(&client.user_token, &config.provider_id);
+
+                if state.proxy.is_client_error(&e) {
+                    return create_error_response(&protocol, &e);
+                }
+                if size_limit::is_too_large(&e) {
+                    failover.record_failure(&config, FailureClass::Deterministic);
+                    return create_error_response(&protocol, &e);
+                }
+
+                state
+                    .router
+                    .remove_failed_route(
+                        &client.user_token,
+                        tenant_id.as_deref(),
+                        &requested_model,
+                        &config,
+                    )
+                    .await;
+                failover.record_failure(&config, state.proxy.classify_failure(&e));
+                continue;
+            }
+        }
+    }
+
+    client_error_response(
+        &protocol,
+        StatusCode::SERVICE_UNAVAILABLE,
+        "All routes failed",
+    )
+}
+
+// Anthropic Message Batches：查询批处理状态
+async fn handle_batch_retrieve(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(batch_id): Path<String>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let _in_flight = state.stats.request_started();
+    batch_object_operation(&state, peer, req, &batch_id, reqwest::Method::GET, "").await
+}
+
+// Anthropic Message Batches：取消批处理
+async fn handle_batch_cancel(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(batch_id): Path<String>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let _in_flight = state.stats.request_started();
+    batch_object_operation(
+        &state,
+        peer,
+        req,
+        &batch_id,
+        reqwest::Method::POST,
+        "/cancel",
+    )
+    .await
+}
+
+// Anthropic Message Batches：删除批处理
+async fn handle_batch_delete(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(batch_id): Path<String>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let _in_flight = state.stats.request_started();
+    batch_object_operation(&state, peer, req, &batch_id, reqwest::Method::DELETE, "").await
+}
+
+// Anthropic Message Batches：下载结果（JSONL）
+// 结果边下载边转发给客户端，同时解析每个成功请求的用量上报遥测；
+// 同一批处理的结果完整下载过一次后不再重复上报
+async fn handle_batch_results(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(batch_id): Path<String>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let _in_flight = state.stats.request_started();
+    let (_, batch, upstream) = match forward_batch_operation(
+        &state,
+        peer,
+        req,
+        &batch_id,
+        reqwest::Method::GET,
+        "/results",
+    )
+    .await
+    {
+        Ok(forwarded) => forwarded,
+        Err(response) => return response,
+    };
+
+    let body = if batch.usage_reported() {
+        upstream.body
     } else {
-        "***".to_string()
+        Box::pin(batches::ingest_results(
+            upstream.body,
+            state.batches.clone(),
+            state.telemetry.clone(),
+            batch_id,
+            batch,
+        ))
+    };
+
+    with_upstream_headers(Response::builder(), &upstream.headers)
+        .status(StatusCode::OK)
+        .body(Body::from_stream(body))
+        .unwrap()
+}
+
+// 转发返回批处理对象的操作（查询、取消、删除），改写其中的 results_url
+async fn batch_object_operation(
+    state: &AppState,
+    peer: SocketAddr,
+    req: Request<Body>,
+    batch_id: &str,
+    method: reqwest::Method,
+    suffix: &str,
+) -> Response<Body> {
+    let deleting = method == reqwest::Method::DELETE;
+    let (_, _, upstream) =
+        match forward_batch_operation(state, peer, req, batch_id, method, suffix).await {
+            Ok(forwarded) => forwarded,
+            Err(response) => return response,
+        };
+    if deleting {
+        state.batches.remove(batch_id).await;
+    }
+
+    match read_upstream_body(upstream.body).await {
+        Ok(body) => with_upstream_headers(Response::builder(), &upstream.headers)
+            .status(StatusCode::OK)
+            .body(Body::from(rewrite_batch_object(
+                body,
+                state.public_base_url.as_deref(),
+            )))
+            .unwrap(),
+        Err(e) => {
+            error!("Failed to read upstream batch response: {}", e);
+            client_error_response(
+                &ClientProtocol::Anthropic,
+                StatusCode::BAD_GATEWAY,
+                "Upstream request failed",
+            )
+        }
+    }
+}
+
+// 将批处理操作转发到创建该批处理的上游，批处理不存在或不属于调用方时返回 404
+#[allow(clippy::result_large_err)]
+async fn forward_batch_operation(
+    state: &AppState,
+    peer: SocketAddr,
+    req: Request<Body>,
+    batch_id: &str,
+    method: reqwest::Method,
+    suffix: &str,
+) -> std::result::Result<
+    (
+        BatchClient,
+        Arc<BatchRoute>,
+        UpstreamResponse<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>>,
+    ),
+    Response<Body>,
+> {
+    let protocol = ClientProtocol::Anthropic;
+    let client = authenticate_batch_client(state, peer, req.headers(), &protocol).await?;
+    let Some(batch) = state.batches.get(batch_id, &client.user_token) else {
+        return Err(client_error_response(
+            &protocol,
+            StatusCode::NOT_FOUND,
+            "Batch not found",
+        ));
+    };
+
+    // 删除后结果无法再下载，先上报尚未上报的结果用量
+    if method == reqwest::Method::DELETE && !batch.usage_reported() {
+        if let Err(e) = batches::collect_usage(
+            &state.batches,
+            &state.proxy,
+            &state.telemetry,
+            batch_id,
+            &batch,
+        )
+        .await
+        {
+            warn!(
+                "Failed to collect result usage before deleting batch {}: {}",
+                batch_id, e
+            );
+        }
+    }
+
+    let path = format!("/v1/messages/batches/{}{}", batch_id, suffix);
+    match state
+        .proxy
+        .forward_batch(&batch.route, method, &path, None, &client.client_headers)
+        .await
+    {
+        Ok(upstream) => {
+            record_upstream_attempt(state, &batch.route, Ok(()));
+            Ok((client, batch, upstream))
+        }
+        Err(e) => {
+            record_upstream_attempt(state, &batch.route, Err(&e));
+            error!(
+                "Batch request {} failed for {}: {}",
+                path, batch.route.api_endpoint, e
+            );
+            if state.proxy.is_client_error(&e) || size_limit::is_too_large(&e) {
+                return Err(create_error_response(&protocol, &e));
+            }
+            Err(client_error_response(
+                &protocol,
+                StatusCode::BAD_GATEWAY,
+                "Upstream request failed",
+            ))
+        }
+    }
+}
+
+// 批处理内所有请求使用的模型，缺少模型或模型不一致时返回错误信息
+fn batch_model(body: &serde_json::Value) -> std::result::Result<String, &'static str> {
+    let requests = match body.get("requests").and_then(|r| r.as_array()) {
+        Some(requests) if !requests.is_empty() => requests,
+        _ => return Err("Batch must contain at least one request"),
+    };
+
+    let mut model: Option<&str> = None;
+    for request in requests {
+        let Some(request_model) = request.pointer("/params/model").and_then(|m| m.as_str()) else {
+            return Err("Missing model field");
+        };
+        match model {
+            Some(model) if model != request_model => {
+                return Err("All requests in a batch must use the same model");
+            }
+            _ => model = Some(request_model),
+        }
+    }
+
+    model.map(str::to_string).ok_or("Missing model field")
+}
+
+// 将批处理对象中的 results_url 改写为网关地址，结果下载同样经过网关；
+// 未配置网关对外地址时改写为相对路径，由客户端 SDK 按其 base_url 解析
+fn rewrite_batch_object(body: Bytes, public_base_url: Option<&str>) -> Bytes {
+    let Ok(mut batch) = serde_json::from_slice::<serde_json::Value>(&body) else {
+        return body;
+    };
+    let Some(id) = batch
+        .get("id")
+        .and_then(|id| id.as_str())
+        .map(str::to_string)
+    else {
+        return body;
+    };
+    if !batch.get("results_url").is_some_and(|url| url.is_string()) {
+        return body;
+    }
+
+    batch["results_url"] = serde_json::Value::String(format!(
+        "{}/v1/messages/batches/{}/results",
+        public_base_url.unwrap_or_default().trim_end_matches('/'),
+        id
+    ));
+    serde_json::to_vec(&batch).map(Bytes::from).unwrap_or(body)
+}
+
+// 文件接口：上传文件（OpenAI Files、Anthropic Files beta）
+// 请求体不经缓冲直接转发给首个协议一致的路由，请求体只能读取一次，不做故障转移
+async fn handle_file_upload(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let _in_flight = state.stats.request_started();
+    let protocol = files::client_protocol(req.headers());
+    let client = match authenticate_batch_client(&state, peer, req.headers(), &protocol).await {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+    let (config, tenant_id) =
+        match resolve_client_route(&state, &client, &protocol, req.headers(), "files").await {
+            Ok(resolved) => resolved,
+            Err(response) => return response,
+        };
+
+    let headers = req.headers();
+    let content_type = headers
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let content_length = headers
+        .get("content-length")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    let max_bytes = config.max_file_bytes.unwrap_or(state.max_file_bytes);
+    if content_length.is_some_and(|len| len > max_bytes) {
+        metrics::increment_counter!("gateway_file_upload_rejected_total");
+        return client_error_response(
+            &protocol,
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "File exceeds the size limit",
+        );
+    }
+
+    info!(
+        "File upload received - {} bytes, client_ip: {}",
+        content_length.map_or("unknown".to_string(), |len| len.to_string()),
+        client.client_ip
+    );
+
+    // 分块上传时边转发边计数，超过上限时中断上传
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let upload = FileUpload {
+        body: reqwest::Body::wrap_stream(files::limit_upload(
+            req.into_body().into_data_stream(),
+            max_bytes,
+            exceeded.clone(),
+        )),
+        content_type,
+        content_length,
+    };
+    let result = match state
+        .proxy
+        .forward_file(
+            &config,
+            reqwest::Method::POST,
+            "/v1/files",
+            Some(upload),
+            &client.client_headers,
+        )
+        .await
+    {
+        Ok(upstream) => read_upstream_body(upstream.body)
+            .await
+            .map(|body| (upstream.headers, body)),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok((upstream_headers, response_body)) => {
+            record_upstream_attempt(&state, &config, Ok(()));
+            match files::file_id(&response_body) {
+                Some(file_id) => {
+                    info!("File {} uploaded to {}", file_id, config.api_endpoint);
+                    state
+                        .files
+                        .register(
+                            file_id,
+                            client.user_token.clone(),
+                            tenant_id,
+                            config.clone(),
+                        )
+                        .await;
+                }
+                None => warn!(
+                    "File upload response from {} has no id, file will not be routable",
+                    config.api_endpoint
+                ),
+            }
+            with_upstream_headers(Response::builder(), &upstream_headers)
+                .status(StatusCode::OK)
+                .body(Body::from(response_body))
+                .unwrap()
+        }
+        Err(_) if exceeded.load(Ordering::Relaxed) => {
+            warn!("File upload exceeds {} bytes, aborted", max_bytes);
+            metrics::increment_counter!("gateway_file_upload_rejected_total");
+            client_error_response(
+                &protocol,
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "File exceeds the size limit",
+            )
+        }
+        Err(e) => {
+            record_upstream_attempt(&state, &config, Err(&e));
+            error!("File upload failed for {}: {}", config.api_endpoint, e);
+            state.telemetry.report_error(ErrorEvent {
+                request_id: Uuid::new_v4().to_string(),
+                attempt: 0,
+                token: config.token.clone(),
+                model: config.model.clone(),
+                api: config.api_endpoint.clone(),
+                msg: e.to_string(),
+                provider_token_id: Some(config.provider_token_id.clone()),
+                client_ip: Some(client.client_ip.clone()),
+                claims: client.claims.clone(),
+                tenant_id: tenant_id.clone(),
+                upstream_request_id: upstream_request_id_of(&e),
+            });
+            if state.proxy.is_client_error(&e) || size_limit::is_too_large(&e) {
+                return create_error_response(&protocol, &e);
+            }
+            client_error_response(
+                &protocol,
+                StatusCode::BAD_GATEWAY,
+                "Upstream request failed",
+            )
+        }
+    }
+}
+
+// 文件接口：列出文件，只返回调用方通过网关上传的文件
+async fn handle_file_list(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let _in_flight = state.stats.request_started();
+    let protocol = files::client_protocol(req.headers());
+    let client = match authenticate_batch_client(&state, peer, req.headers(), &protocol).await {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+    let (config, _) =
+        match resolve_client_route(&state, &client, &protocol, req.headers(), "files").await {
+            Ok(resolved) => resolved,
+            Err(response) => return response,
+        };
+
+    let path = match req.uri().query() {
+        Some(query) => format!("/v1/files?{}", query),
+        None => "/v1/files".to_string(),
+    };
+    let result = match state
+        .proxy
+        .forward_file(
+            &config,
+            reqwest::Method::GET,
+            &path,
+            None,
+            &client.client_headers,
+        )
+        .await
+    {
+        Ok(upstream) => read_upstream_body(upstream.body)
+            .await
+            .map(|body| (upstream.headers, body)),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok((upstream_headers, body)) => {
+            record_upstream_attempt(&state, &config, Ok(()));
+            let owned = state.files.owned_by(&client.user_token);
+            with_upstream_headers(Response::builder(), &upstream_headers)
+                .status(StatusCode::OK)
+                .body(Body::from(files::filter_list(body, &owned)))
+                .unwrap()
+        }
+        Err(e) => {
+            record_upstream_attempt(&state, &config, Err(&e));
+            error!("File list failed for {}: {}", config.api_endpoint, e);
+            if state.proxy.is_client_error(&e) || size_limit::is_too_large(&e) {
+                return create_error_response(&protocol, &e);
+            }
+            client_error_response(
+                &protocol,
+                StatusCode::BAD_GATEWAY,
+                "Upstream request failed",
+            )
+        }
+    }
+}
+
+// 文件接口：查询文件信息
+async fn handle_file_retrieve(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let _in_flight = state.stats.request_started();
+    file_object_operation(&state, peer, req, &file_id, reqwest::Method::GET).await
+}
+
+// 文件接口：删除文件，成功后移除登记
+async fn handle_file_delete(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let _in_flight = state.stats.request_started();
+    let response =
+        file_object_operation(&state, peer, req, &file_id, reqwest::Method::DELETE).await;
+    if response.status().is_success() {
+        state.files.remove(&file_id).await;
+    }
+    response
+}
+
+// 文件接口：下载文件内容，边下载边转发给客户端
+async fn handle_file_content(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(file_id): Path<String>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let _in_flight = state.stats.request_started();
+    let path = format!("/v1/files/{}/content", file_id);
+    match forward_file_operation(&state, peer, req, &file_id, reqwest::Method::GET, &path).await {
+        Ok((_, upstream)) => with_upstream_headers(Response::builder(), &upstream.headers)
+            .status(StatusCode::OK)
+            .body(Body::from_stream(upstream.body))
+            .unwrap(),
+        Err(response) => response,
+    }
+}
+
+// 转发返回文件对象的操作（查询、删除）
+async fn file_object_operation(
+    state: &AppState,
+    peer: SocketAddr,
+    req: Request<Body>,
+    file_id: &str,
+    method: reqwest::Method,
+) -> Response<Body> {
+    let protocol = files::client_protocol(req.headers());
+    let path = format!("/v1/files/{}", file_id);
+    let (_, upstream) = match forward_file_operation(state, peer, req, file_id, method, &path).await
+    {
+        Ok(forwarded) => forwarded,
+        Err(response) => return response,
+    };
+
+    match read_upstream_body(upstream.body).await {
+        Ok(body) => with_upstream_headers(Response::builder(), &upstream.headers)
+            .status(StatusCode::OK)
+            .body(Body::from(body))
+            .unwrap(),
+        Err(e) => {
+            error!("Failed to read upstream file response: {}", e);
+            client_error_response(
+                &protocol,
+                StatusCode::BAD_GATEWAY,
+                "Upstream request failed",
+            )
+        }
+    }
+}
+
+// 将文件操作转发到上传该文件的上游，文件不存在或不属于调用方时返回 404
+#[allow(clippy::result_large_err)]
+async fn forward_file_operation(
+    state: &AppState,
+    peer: SocketAddr,
+    req: Request<Body>,
+    file_id: &str,
+    method: reqwest::Method,
+    path: &str,
+) -> std::result::Result<
+    (
+        Arc<FileRoute>,
+        UpstreamResponse<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>>,
+    ),
+    Response<Body>,
+> {
+    let protocol = files::client_protocol(req.headers());
+    let client = authenticate_batch_client(state, peer, req.headers(), &protocol).await?;
+    let Some(file) = state.files.get(file_id, &client.user_token) else {
+        return Err(client_error_response(
+            &protocol,
+            StatusCode::NOT_FOUND,
+            "File not found",
+        ));
+    };
+
+    match state
+        .proxy
+        .forward_file(&file.route, method, path, None, &client.client_headers)
+        .await
+    {
+        Ok(upstream) => {
+            record_upstream_attempt(state, &file.route, Ok(()));
+            Ok((file, upstream))
+        }
+        Err(e) => {
+            record_upstream_attempt(state, &file.route, Err(&e));
+            error!(
+                "File request {} failed for {}: {}",
+                path, file.route.api_endpoint, e
+            );
+            if state.proxy.is_client_error(&e) || size_limit::is_too_large(&e) {
+                return Err(create_error_response(&protocol, &e));
+            }
+            Err(client_error_response(
+                &protocol,
+                StatusCode::BAD_GATEWAY,
+                "Upstream request failed",
+            ))
+        }
+    }
+}
+
+// 模型列表：转发到 x-model 请求头或令牌默认模型所在路由的上游模型列表接口，
+// 响应按供应商令牌缓存（cache.metadata_ttl），客户端频繁刷新模型列表时不逐次回源
+async fn handle_model_list(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let protocol = files::client_protocol(req.headers());
+    let client = match authenticate_batch_client(&state, peer, req.headers(), &protocol).await {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+    let (config, _) =
+        match resolve_client_route(&state, &client, &protocol, req.headers(), "models").await {
+            Ok(resolved) => resolved,
+            Err(response) => return response,
+        };
+
+    let path = match req.uri().query() {
+        Some(query) => format!("/v1/models?{}", query),
+        None => "/v1/models".to_string(),
     };
+    let result = state
+        .metadata
+        .get_or_fetch(&config.provider_token_id, &path, || async {
+            let result = match state
+                .proxy
+                .forward_file(
+                    &config,
+                    reqwest::Method::GET,
+                    &path,
+                    None,
+                    &client.client_headers,
+                )
+                .await
+            {
+                Ok(upstream) => read_upstream_body(upstream.body).await,
+                Err(e) => Err(e),
+            };
+            record_upstream_attempt(&state, &config, result.as_ref().map(|_| ()));
+            result
+        })
+        .await;
+
+    match result {
+        Ok(body) => Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap(),
+        Err(e) => {
+            error!("Model list failed for {}: {}", config.api_endpoint, e);
+            if state.proxy.is_client_error(&e) || size_limit::is_too_large(&e) {
+                return create_error_response(&protocol, &e);
+            }
+            client_error_response(
+                &protocol,
+                StatusCode::BAD_GATEWAY,
+                "Upstream request failed",
+            )
+        }
+    }
+}
 
-    // 解析请求获取模型名
-    let requested_model = match extract_model(&body_bytes) {
-        Some(model) => model,
+// 请求体不含模型的接口（上传和列出文件、模型列表）使用的路由：按 x-model 请求头或令牌的默认模型解析，
+// 取首个协议与客户端一致的路由
+#[allow(clippy::result_large_err)]
+async fn resolve_client_route(
+    state: &AppState,
+    client: &BatchClient,
+    protocol: &ClientProtocol,
+    headers: &HeaderMap,
+    api: &str,
+) -> std::result::Result<(RouteConfig, Option<String>), Response<Body>> {
+    let model_hint = headers
+        .get("x-model")
+        .and_then(|v| v.to_str().ok())
+        .filter(|s| !s.is_empty())
+        .map(str::to_string);
+    let model = match model_hint {
+        Some(model) => Some(model),
         None => {
-            return error_response(StatusCode::BAD_REQUEST, "Missing model field");
+            state
+                .router
+                .default_model_for(&client.user_token, protocol)
+                .await
         }
     };
+    let Some(model) = model else {
+        return Err(client_error_response(
+            protocol,
+            StatusCode::BAD_REQUEST,
+            "Missing model: set the x-model header",
+        ));
+    };
 
-    info!(
-        "Request received - protocol: {:?}, model: {}, path: {}",
-        client_protocol, requested_model, request_path
-    );
-
-    // 获取路由配置
+    let client_app = extract_client_app(state, headers);
+    let hints = collect_route_hints(state, &Bytes::new(), protocol, false, client_app);
     let route_configs = match state
         .router
-        .resolve_route(&user_token, &requested_model)
+        .resolve_route(
+            &client.user_token,
+            client.tenant_id.as_deref(),
+            &model,
+            &hints,
+        )
         .await
     {
         Ok(configs) => configs,
         Err(e) => {
             error!("Failed to resolve route: {}", e);
-            return error_response(StatusCode::SERVICE_UNAVAILABLE, "No available routes");
+            return Err(client_error_response(
+                protocol,
+                StatusCode::SERVICE_UNAVAILABLE,
+                "No available routes",
+            ));
         }
     };
 
-    // 判断是否是流式请求
-    let is_stream = ProtocolDetector::is_stream_request(&body_bytes);
+    let config = route_configs.into_iter().find(|config| {
+        let anthropic = matches!(config.protocol, TargetProtocol::Anthropic);
+        anthropic == matches!(protocol, ClientProtocol::Anthropic) && !state.proxy.is_mocked(config)
+    });
+    let Some(config) = config else {
+        return Err(client_error_response(
+            protocol,
+            StatusCode::SERVICE_UNAVAILABLE,
+            &format!("No route supports the {} API", api),
+        ));
+    };
+    let tenant_id = client
+        .tenant_id
+        .clone()
+        .or_else(|| state.router.tenant_of(&client.user_token));
+    Ok((config, tenant_id))
+}
 
-    info!(
-        "Request routing - stream: {}, protocol: {:?}, model: {}, path: {}",
-        is_stream, client_protocol, requested_model, request_path
-    );
+// 记录不经过路由追踪的上游调用结果（大请求体快速通道、透传、文件和批处理接口），
+// 计入路由成功率统计以及供应商熔断和错误率
+fn record_upstream_attempt(
+    state: &AppState,
+    config: &RouteConfig,
+    result: std::result::Result<(), &Error>,
+) {
+    let outcome = match result {
+        Ok(()) => AttemptOutcome::Success,
+        Err(e) if state.proxy.is_client_error(e) => AttemptOutcome::ClientError,
+        Err(e) if size_limit::is_too_large(e) => AttemptOutcome::Deterministic,
+        Err(e) => state.proxy.classify_failure(e).into(),
+    };
+    state.stats.record_attempt(config, result.is_ok());
+    state
+        .router
+        .observe_attempt(&config.provider_token_id, outcome);
+}
 
-    if is_stream {
-        handle_stream(
-            state,
-            route_configs,
-            client_protocol,
-            body_bytes,
-            user_token,
-            requested_model,
-            request_path,
-            client_headers,
-        )
-        .await
-    } else {
-        handle_non_stream(
-            state,
-            route_configs,
-            client_protocol,
-            body_bytes,
-            user_token,
-            requested_model,
-            request_path,
-            client_headers,
-        )
-        .await
+// 读取完整的上游响应体
+async fn read_upstream_body(
+    body: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>,
+) -> Result<Bytes> {
+    body.try_fold(Vec::new(), |mut body, chunk| async move {
+        body.extend_from_slice(&chunk);
+        Ok(body)
+    })
+    .await
+    .map(Bytes::from)
+}
+
+// 解析真实客户端IP（仅信任配置中的代理转发头）
+fn resolve_client_ip(state: &AppState, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+    let client_ip = state.client_ip.resolve(peer.ip(), headers);
+    tracing::Span::current().record("client_ip", tracing::field::display(client_ip));
+    client_ip
+}
+
+// 按客户端IP和租户限流，超过任一限额时返回 false
+// IP限额按 (租户, IP) 计数，不同租户共用出口IP时互不挤占
+fn admit_client(state: &AppState, client_ip: IpAddr, tenant_id: Option<&str>) -> bool {
+    if let Some(limiter) = &state.ip_rate_limiter {
+        if !limiter.check((tenant_id.map(str::to_string), client_ip)) {
+            return false;
+        }
+    }
+
+    if let (Some(limiter), Some(tenant_id)) = (&state.tenant_rate_limiter, tenant_id) {
+        if !limiter.check(tenant_id.to_string()) {
+            warn!("Tenant {} exceeded its rate limit", tenant_id);
+            return false;
+        }
+    }
+
+    true
+}
+
+// 路由或令牌启用了需要读取请求体的处理时不能直接转发请求体：输出上限、默认生成参数、
+// 命令行客户端兼容、提示词压缩、调试采样、对话内容记录和故障注入
+fn buffers_body(
+    state: &AppState,
+    config: &RouteConfig,
+    user_token: &str,
+    client_headers: &reqwest::header::HeaderMap,
+) -> bool {
+    state.proxy.alters_request(config)
+        || config.generation_defaults.is_some()
+        || AgentClient::detect(client_headers).is_some()
+            && state.compat.agents().enabled_for(config)
+        || state.logging.is_sampling(user_token)
+        || state.content_log.is_some() && state.router.content_logging_of(user_token).is_some()
+}
+
+// 占用令牌的流式名额，已达上限时返回 429 响应；未配置上限时不占用
+#[allow(clippy::result_large_err)]
+fn admit_stream(
+    state: &AppState,
+    client_protocol: &ClientProtocol,
+    user_token: &str,
+) -> std::result::Result<Option<StreamSlot>, Response<Body>> {
+    let Some(limiter) = &state.stream_limiter else {
+        return Ok(None);
+    };
+
+    match limiter.try_acquire(user_token) {
+        Some(slot) => Ok(Some(slot)),
+        None => {
+            warn!(
+                "Token {} exceeded its concurrent stream limit",
+                mask_token(user_token)
+            );
+            metrics::increment_counter!("gateway_stream_limit_rejected_total");
+            Err(client_error_response(
+                client_protocol,
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many concurrent streams",
+            ))
+        }
+    }
+}
+
+// 识别请求所属租户：JWT 租户声明 -> 配置的租户请求头 -> 业务API返回的令牌所属租户
+// 租户请求头由前置代理设置，只采信受信任代理（server.trusted_proxies）直连转发的请求，
+// 否则客户端可以自行声明租户，绕过租户限流并读写其他租户的路由缓存
+fn resolve_tenant(
+    state: &AppState,
+    claim_tenant: Option<String>,
+    peer: SocketAddr,
+    headers: &HeaderMap,
+    user_token: &str,
+) -> Option<String> {
+    claim_tenant
+        .or_else(|| {
+            let header = state.tenant_header.as_deref()?;
+            if !state.client_ip.is_trusted(&peer.ip()) {
+                return None;
+            }
+            headers
+                .get(header)
+                .and_then(|v| v.to_str().ok())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+        })
+        .or_else(|| state.router.tenant_of(user_token))
+}
+
+// 读取配置的客户端应用标识请求头
+fn extract_client_app(state: &AppState, headers: &HeaderMap) -> Option<String> {
+    let header = state
+        .router
+        .route_enrichment()
+        .client_app_header
+        .as_deref()?;
+    headers
+        .get(header)
+        .and_then(|v| v.to_str().ok())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+}
+
+// 按配置开关收集路由提示，未开启的字段不计算也不发送
+fn collect_route_hints(
+    state: &AppState,
+    body: &Bytes,
+    client_protocol: &ClientProtocol,
+    is_stream: bool,
+    client_app: Option<String>,
+) -> RouteHints {
+    let enrichment = state.router.route_enrichment();
+
+    RouteHints {
+        estimated_prompt_tokens: enrichment
+            .estimated_prompt_tokens
+            .then(|| ProtocolDetector::estimate_prompt_tokens(body))
+            .flatten(),
+        stream: enrichment.stream.then_some(is_stream),
+        client_protocol: enrichment.client_protocol.then(|| client_protocol.clone()),
+        max_tokens: enrichment
+            .max_tokens
+            .then(|| ProtocolDetector::requested_max_tokens(body))
+            .flatten(),
+        client_app,
+        priority: None,
     }
 }
 
-fn extract_token(req: &Request<Body>) -> Option<String> {
-    req.headers()
+fn extract_token(headers: &HeaderMap) -> Option<String> {
+    headers
         .get("authorization")
         .and_then(|v| v.to_str().ok())
         .and_then(|s| s.strip_prefix("Bearer "))
@@ -225,19 +3108,61 @@ fn extract_model(body: &[u8]) -> Option<String> {
     v.get("model")?.as_str().map(|s| s.to_string())
 }
 
-fn filter_client_headers(req: &Request<Body>) -> reqwest::header::HeaderMap {
+// 从请求体以外的位置提取模型名
+// 1) x-model header  2) Azure 风格路径 /openai/deployments/{deployment}/...
+fn extract_model_hint(req: &Request<Body>) -> Option<String> {
+    if let Some(model) = req
+        .headers()
+        .get("x-model")
+        .and_then(|v| v.to_str().ok())
+        .filter(|s| !s.is_empty())
+    {
+        return Some(model.to_string());
+    }
+
+    req.uri()
+        .path()
+        .strip_prefix("/openai/deployments/")
+        .and_then(|rest| rest.split('/').next())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+}
+
+// 将模型名写入请求体JSON
+fn inject_model(body: &[u8], model: &str) -> Option<Bytes> {
+    let mut v: serde_json::Value = serde_json::from_slice(body).ok()?;
+    v.as_object_mut()?.insert(
+        "model".to_string(),
+        serde_json::Value::String(model.to_string()),
+    );
+    serde_json::to_vec(&v).ok().map(Bytes::from)
+}
+
+// 客户端期望的流式响应格式
+fn client_stream_format(client_headers: &reqwest::header::HeaderMap) -> ClientStreamFormat {
+    ClientStreamFormat::negotiate(
+        client_headers
+            .get(reqwest::header::ACCEPT)
+            .and_then(|v| v.to_str().ok()),
+    )
+}
+
+fn filter_client_headers(headers: &HeaderMap) -> reqwest::header::HeaderMap {
     let mut filtered = reqwest::header::HeaderMap::new();
 
     // 拦截列表：这些header不应该转发到上游
     let blocked_headers = [
-        "authorization",     // 需要根据protocol重写
-        "host",              // 指向目标endpoint
-        "content-length",    // reqwest自动计算
-        "transfer-encoding", // 避免冲突
-        "connection",        // 避免冲突
+        "authorization",        // 需要根据protocol重写
+        "host",                 // 指向目标endpoint
+        "content-length",       // reqwest自动计算
+        "transfer-encoding",    // 避免冲突
+        "connection",           // 避免冲突
+        "x-model",              // 网关内部使用的模型名
+        "x-gateway-debug",      // 网关调试开关
+        "x-gateway-transcript", // 流式字节记录开关
     ];
 
-    for (name, value) in req.headers().iter() {
+    for (name, value) in headers.iter() {
         let name_str = name.as_str().to_lowercase();
         if !blocked_headers.contains(&name_str.as_str()) {
             // 将axum的HeaderName/HeaderValue转换为reqwest的类型
@@ -256,31 +3181,85 @@ fn filter_client_headers(req: &Request<Body>) -> reqwest::header::HeaderMap {
     filtered
 }
 
-// 从响应中提取usage信息
-fn extract_usage_from_response(
-    protocol: &TargetProtocol,
-    body: &[u8],
-) -> Option<(i32, i32)> {
-    let v: serde_json::Value = serde_json::from_slice(body).ok()?;
-    let usage = v.get("usage")?;
+// 从非对话类接口的请求体中提取计费维度
+// - 语音合成：input 字符数
+// - 图像生成：请求的图片数量 n（默认1）、尺寸 size、质量 quality
+// - 重排序：文档数量
+fn passthrough_request_usage(request_path: &str, body: &[u8]) -> UsageEvent {
+    let mut usage = UsageEvent::default();
+    let v: serde_json::Value = match serde_json::from_slice(body) {
+        Ok(v) => v,
+        Err(_) => return usage,
+    };
+    let text = |key: &str| v.get(key).and_then(|s| s.as_str()).map(|s| s.to_string());
 
-    match protocol {
-        TargetProtocol::OpenAI
-        | TargetProtocol::Custom(_) => {
-            // OpenAI格式: { "prompt_tokens": N, "completion_tokens": M }
-            let input = usage.get("prompt_tokens")?.as_i64()? as i32;
-            let output = usage.get("completion_tokens")?.as_i64()? as i32;
-            Some((input, output))
+    match request_path {
+        "/v1/audio/speech" => {
+            usage.input_characters = text("input").map(|s| s.chars().count() as u64);
         }
-        TargetProtocol::Anthropic => {
-            // Anthropic格式: { "input_tokens": N, "output_tokens": M }
-            let input = usage.get("input_tokens")?.as_i64()? as i32;
-            let output = usage.get("output_tokens")?.as_i64()? as i32;
-            Some((input, output))
+        "/v1/images/generations" => {
+            usage.image_count = Some(v.get("n").and_then(|n| n.as_u64()).unwrap_or(1) as u32);
+            usage.image_size = text("size");
+            usage.image_quality = text("quality");
+        }
+        "/v1/rerank" => {
+            usage.document_count = v
+                .get("documents")
+                .and_then(|d| d.as_array())
+                .map(|d| d.len() as u32);
+        }
+        _ => {}
+    }
+
+    usage
+}
+
+// 从非对话类接口响应中补充用量
+// - 转写接口的 verbose_json 响应带 duration；新版模型在 usage 中返回
+//   {"type":"duration","seconds":N} 或 {"type":"tokens","input_tokens":..,"output_tokens":..}
+// - 图像生成响应以 data 数组长度作为实际生成的图片数量
+// - 嵌入接口在 usage 中返回 prompt_tokens
+fn extract_passthrough_usage(body: &[u8], usage: &mut UsageEvent) {
+    let v: serde_json::Value = match serde_json::from_slice(body) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+
+    let reported = v.get("usage");
+    let token_count = |key: &str| {
+        reported
+            .and_then(|u| u.get(key))
+            .and_then(|t| t.as_i64())
+            .unwrap_or(0) as i32
+    };
+    usage.input_tokens = match token_count("input_tokens") {
+        0 => token_count("prompt_tokens"),
+        tokens => tokens,
+    };
+    usage.output_tokens = token_count("output_tokens");
+    usage.audio_seconds = reported
+        .and_then(|u| u.get("seconds"))
+        .or_else(|| v.get("duration"))
+        .and_then(|d| d.as_f64());
+
+    if usage.image_count.is_some() {
+        if let Some(images) = v.get("data").and_then(|d| d.as_array()) {
+            usage.image_count = Some(images.len() as u32);
         }
     }
 }
 
+// 将白名单内的上游响应头（限流、请求ID等）附加到网关响应
+fn with_upstream_headers(
+    mut builder: axum::http::response::Builder,
+    upstream_headers: &reqwest::header::HeaderMap,
+) -> axum::http::response::Builder {
+    for (name, value) in upstream_headers.iter() {
+        builder = builder.header(name.as_str(), value.as_bytes());
+    }
+    builder
+}
+
 fn error_response(status: StatusCode, message: &str) -> Response<Body> {
     let body = serde_json::json!({
         "error": {
@@ -296,285 +3275,494 @@ fn error_response(status: StatusCode, message: &str) -> Response<Body> {
         .unwrap()
 }
 
-fn create_error_response(error: &Error) -> Response<Body> {
+// 请求参数无效：OpenAI 格式的 invalid_request_error，指明出错的参数
+fn invalid_request_response(message: &str, param: &str) -> Response<Body> {
+    let body = serde_json::json!({
+        "error": {
+            "message": message,
+            "type": "invalid_request_error",
+            "param": param,
+            "code": null,
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+// 按客户端协议构造错误响应
+// - OpenAI：{"error":{"message":...,"type":"gateway_error"}}
+// - Anthropic：{"type":"error","error":{"type":...,"message":...}}
+fn client_error_response(
+    protocol: &ClientProtocol,
+    status: StatusCode,
+    message: &str,
+) -> Response<Body> {
+    match protocol {
+        ClientProtocol::Anthropic => {
+            let body = serde_json::json!({
+                "type": "error",
+                "error": {
+                    "type": anthropic_error_type(status),
+                    "message": message,
+                }
+            });
+
+            Response::builder()
+                .status(status)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        }
+        ClientProtocol::OpenAI | ClientProtocol::Custom(_) => error_response(status, message),
+    }
+}
+
+// HTTP 状态码对应的 Anthropic 错误类型
+fn anthropic_error_type(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        400 | 422 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        413 => "request_too_large",
+        429 => "rate_limit_error",
+        503 | 529 => "overloaded_error",
+        _ => "api_error",
+    }
+}
+
+fn create_error_response(protocol: &ClientProtocol, error: &Error) -> Response<Body> {
     match error {
+        Error::Proxy(msg) if size_limit::is_too_large(error) => {
+            response_too_large_response(protocol, msg)
+        }
         Error::Proxy(msg) => {
-            // 解析上游错误信息
-            if msg.contains("400") {
+            // 解析上游错误信息：优先按上游状态码判断，避免误匹配请求ID和响应体中的数字
+            let status = upstream_status(msg);
+            let is =
+                |code: u16| status.map_or_else(|| msg.contains(&code.to_string()), |s| s == code);
+            if is(400) {
                 // 提取上游的错误响应体
                 if let Some(start) = msg.find(": ") {
-                    let upstream_error = &msg[start + 2..];
-                    return Response::builder()
-                        .status(StatusCode::BAD_REQUEST)
-                        .header("content-type", "application/json")
-                        .body(Body::from(upstream_error.to_string()))
-                        .unwrap();
+                    return upstream_bad_request_response(protocol, &msg[start + 2..]);
                 }
-                error_response(StatusCode::BAD_REQUEST, msg)
-            } else if msg.contains("401") {
-                error_response(StatusCode::UNAUTHORIZED, "Unauthorized")
-            } else if msg.contains("403") {
-                error_response(StatusCode::FORBIDDEN, "Forbidden")
-            } else if msg.contains("404") {
-                error_response(StatusCode::NOT_FOUND, "Not Found")
-            } else if msg.contains("422") {
-                error_response(StatusCode::UNPROCESSABLE_ENTITY, msg)
-            } else if msg.contains("429") {
-                error_response(StatusCode::TOO_MANY_REQUESTS, "Too Many Requests")
+                client_error_response(protocol, StatusCode::BAD_REQUEST, msg)
+            } else if is(401) {
+                client_error_response(protocol, StatusCode::UNAUTHORIZED, "Unauthorized")
+            } else if is(403) {
+                client_error_response(protocol, StatusCode::FORBIDDEN, "Forbidden")
+            } else if is(404) {
+                client_error_response(protocol, StatusCode::NOT_FOUND, "Not Found")
+            } else if is(422) {
+                client_error_response(protocol, StatusCode::UNPROCESSABLE_ENTITY, msg)
+            } else if is(429) {
+                client_error_response(protocol, StatusCode::TOO_MANY_REQUESTS, "Too Many Requests")
             } else {
-                error_response(StatusCode::INTERNAL_SERVER_ERROR, msg)
+                client_error_response(protocol, StatusCode::INTERNAL_SERVER_ERROR, msg)
             }
         }
-        _ => error_response(StatusCode::INTERNAL_SERVER_ERROR, &error.to_string()),
+        _ => client_error_response(
+            protocol,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &error.to_string(),
+        ),
+    }
+}
+
+// 上游响应体超过网关上限：502，附带错误码以便客户端与一般的上游故障区分
+fn response_too_large_response(protocol: &ClientProtocol, message: &str) -> Response<Body> {
+    let body = match protocol {
+        ClientProtocol::Anthropic => serde_json::json!({
+            "type": "error",
+            "error": {
+                "type": "api_error",
+                "message": message,
+                "code": size_limit::RESPONSE_TOO_LARGE_CODE,
+            }
+        }),
+        ClientProtocol::OpenAI | ClientProtocol::Custom(_) => serde_json::json!({
+            "error": {
+                "message": message,
+                "type": "gateway_error",
+                "code": size_limit::RESPONSE_TOO_LARGE_CODE,
+            }
+        }),
+    };
+
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+// 上游 400 错误：格式与客户端协议一致时原样透传（保留 param/code 等细节），
+// 否则提取错误信息后按客户端协议重新包装
+fn upstream_bad_request_response(
+    protocol: &ClientProtocol,
+    upstream_error: &str,
+) -> Response<Body> {
+    let parsed: Option<serde_json::Value> = serde_json::from_str(upstream_error).ok();
+    let is_anthropic_shape = parsed
+        .as_ref()
+        .is_some_and(|v| v.get("type").and_then(|t| t.as_str()) == Some("error"));
+    let is_openai_shape = parsed
+        .as_ref()
+        .is_some_and(|v| v.get("error").is_some_and(|e| e.is_object()) && !is_anthropic_shape);
+
+    let matches_client = match protocol {
+        ClientProtocol::Anthropic => is_anthropic_shape,
+        ClientProtocol::OpenAI | ClientProtocol::Custom(_) => is_openai_shape,
+    };
+
+    if matches_client {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .header("content-type", "application/json")
+            .body(Body::from(upstream_error.to_string()))
+            .unwrap();
     }
+
+    let message = parsed
+        .as_ref()
+        .and_then(|v| v.pointer("/error/message"))
+        .and_then(|m| m.as_str())
+        .unwrap_or(upstream_error);
+    client_error_response(protocol, StatusCode::BAD_REQUEST, message)
 }
 
 // 处理流式请求
 // 架构重构后：Transport 层负责构建 Response，Proxy 层只返回纯粹的字节流
 async fn handle_stream(
-    state: AppState,
-    route_configs: Vec<RouteConfig>,
-    client_protocol: ClientProtocol,
-    body_bytes: Bytes,
-    user_token: String,
-    requested_model: String,
-    request_path: String,
-    client_headers: reqwest::header::HeaderMap,
+    ctx: RequestContext,
+    failover: &mut FailoverQueue,
+    stream_slot: Option<StreamSlot>,
 ) -> Response<Body> {
-    // 生成请求ID用于去重
-    let request_id = Uuid::new_v4().to_string();
-
-    // 判断是否需要自定义路径
-    let custom_path = if request_path == "/v1/responses" {
-        Some("/v1/responses")
-    } else {
-        None
-    };
+    let state = &ctx.state;
+    let client_protocol = &ctx.client_protocol;
+    // Chat Completions 客户端是否需要流末尾的用量 chunk（None 表示不是 Chat Completions 客户端）
+    let include_usage = (matches!(client_protocol, ClientProtocol::OpenAI)
+        && !ctx.responses_client)
+        .then(|| stream_usage::include_usage_requested(&ctx.body_bytes));
 
     // 尝试每个路由配置
-    for (index, config) in route_configs.iter().enumerate() {
+    while let Some((attempt, config)) = failover.next_route() {
         let target_protocol = &config.protocol;
+        let bridge = ctx.bridge(&config);
+        let custom_path = ctx.upstream_path(bridge);
 
-        // 将请求转换为目标协议格式
-        let transformed_request = match state
-            .adapter
-            .transform_request(
-                &client_protocol,
-                target_protocol,
-                &config.model,
-                body_bytes.clone(),
-            )
-            .await
-        {
-            Ok(body) => body,
-            Err(e) => {
-                error!("Failed to transform request: {}", e);
-                continue;
-            }
+        let output_cap = state.proxy.output_cap(&config);
+        let Some((transformed_request, compression)) = ctx.prepare(&config, bridge).await else {
+            continue;
         };
 
+        // 输出上限的结束事件和上游不返回用量时的上报使用估算的输入Token数
+        let prompt_tokens = ProtocolDetector::estimate_prompt_tokens(&transformed_request);
+
         // 使用新的 stream 接口获取纯粹的字节流
-        match state
+        let upstream = match state
             .proxy
-            .stream(&config, transformed_request, custom_path, &client_headers)
+            .stream(
+                &config,
+                transformed_request,
+                custom_path,
+                &ctx.upstream_headers(&config),
+            )
             .await
         {
-            Ok(byte_stream) => {
-
-                // 创建Usage收集器来收集流式响应的token使用情况（在协议转换前）
-                let usage_collector = Arc::new(StreamUsageCollector::new(
-                    request_id.clone(),
-                    user_token.clone(),
-                    config.clone(), // 传递完整的RouteConfig
-                    state.telemetry.clone(),
-                ));
+            Ok(upstream) => upstream,
+            Err(e) => match ctx.fail_attempt(failover, attempt, &config, &e).await {
+                Some(response) => return response,
+                None => continue,
+            },
+        };
+        ctx.upstream_responded(failover, &config, upstream.request_id.clone());
+        let upstream_body = match &ctx.capture {
+            Some(capture) => capture.tap_stream(
+                CaptureStage::UpstreamResponse,
+                Some(&config.api_endpoint),
+                upstream.body,
+            ),
+            None => upstream.body,
+        };
+        let upstream_body = match &ctx.transcript {
+            Some(transcript) => transcript.tap_stream(
+                TranscriptSide::Upstream,
+                Some(&config.api_endpoint),
+                upstream_body,
+            ),
+            None => upstream_body,
+        };
+        // 统一上游分帧格式（NDJSON、CRLF 换行等）为标准 SSE，再做用量收集和协议转换
+        let byte_stream = framing::normalize_to_sse(target_protocol, upstream_body);
+        // 单个事件超过上限时以错误事件终止流，避免后续各环节的缓冲无限增长
+        let byte_stream = framing::limit_event_size(
+            target_protocol,
+            byte_stream,
+            state.proxy.max_sse_event_bytes(),
+        );
+        // 累计字节数超过上限时同样以错误事件终止流，避免异常的长生成持续消耗额度
+        let byte_stream = match state.proxy.max_stream_bytes() {
+            Some(max) => framing::limit_stream_size(target_protocol, byte_stream, max),
+            None => byte_stream,
+        };
+        // Responses API 上游的事件流先转换为 Chat Completions 流，后续各环节按 Chat Completions 处理
+        let byte_stream = match bridge {
+            Some(Bridge::ToResponses) => responses::stream_to_chat(byte_stream),
+            _ => byte_stream,
+        };
 
-                // 包装原始流以收集usage信息
-                let wrapped_stream = usage_collector.wrap_stream(byte_stream).await;
+        // 创建Usage收集器来收集流式响应的token使用情况（在协议转换前）
+        let usage_collector = Arc::new(
+            ctx.usage_collector(&config)
+                .with_compression(compression)
+                .with_upstream_request_id(upstream.request_id)
+                .with_max_event_bytes(state.proxy.max_sse_event_bytes())
+                .with_estimated_input_tokens(prompt_tokens),
+        );
 
-                // 对流进行协议转换
-                match state
-                    .adapter
-                    .transform_stream_chunk(target_protocol, &client_protocol, wrapped_stream)
-                    .await
-                {
-                    Ok(transformed_stream) => {
-                        // 在 Transport 层构建流式响应
-                        // 设置 SSE 必要的响应头
-                        let response = Response::builder()
-                            .status(StatusCode::OK)
-                            .header("content-type", "text/event-stream")
-                            .header("cache-control", "no-cache")
-                            .header("connection", "keep-alive")
-                            .header("x-accel-buffering", "no") // 禁用 nginx 缓冲
-                            .body(Body::from_stream(transformed_stream))
-                            .unwrap();
-
-                        return response;
-                    }
-                    Err(e) => {
-                        error!("Failed to transform stream: {}", e);
-                        continue;
-                    }
-                }
+        // 包装原始流以收集usage信息
+        let wrapped_stream = usage_collector.wrap_stream(byte_stream).await;
+        // 上游未遵守最大输出Token数时在上限处终止流（在用量收集后，补发的结束事件不计入用量）
+        let wrapped_stream = match output_cap {
+            Some(cap) => {
+                output_cap::cap_output_stream(wrapped_stream, target_protocol.clone(), cap)
             }
-            Err(e) => {
-                error!("Stream request failed for {}: {}", config.api_endpoint, e);
-
-                // 上报错误
-                state.telemetry.report_error(ErrorEvent {
-                    token: config.token.clone(),
-                    model: config.model.clone(),
-                    api: config.api_endpoint.clone(),
-                    msg: e.to_string(),
-                    provider_token_id: Some(config.provider_token_id.clone()), // 添加provider_token_id
-                });
-
-                // 检查是否为客户端错误（4xx），如果是则直接返回
-                if state.proxy.is_client_error(&e) {
-                    return create_error_response(&e);
-                }
+            None => Box::pin(wrapped_stream),
+        };
 
-                // 从缓存中移除失败的配置
-                state
-                    .router
-                    .remove_failed_route(&user_token, &requested_model, &config)
-                    .await;
+        // 对流进行协议转换
+        let transformed_stream = match state
+            .adapter
+            .transform_stream_chunk(target_protocol, client_protocol, wrapped_stream)
+            .await
+        {
+            Ok(transformed_stream) => transformed_stream,
+            Err(e) => {
+                ctx.record_error(&config, &format!("Failed to transform stream: {}", e));
                 continue;
             }
-        }
+        };
+        // 按客户端的 stream_options.include_usage 统一用量 chunk
+        let transformed_stream = match include_usage {
+            Some(include_usage) => {
+                stream_usage::conform(transformed_stream, include_usage, prompt_tokens)
+            }
+            None => transformed_stream,
+        };
+        // 按路由配置对输出做平滑节奏处理
+        let transformed_stream = match &config.smooth_streaming {
+            Some(smooth) => Box::pin(smooth_stream(transformed_stream, smooth.tokens_per_second)),
+            None => transformed_stream,
+        };
+        let transformed_stream = if ctx.legacy_functions {
+            legacy_functions::stream_to_legacy(transformed_stream)
+        } else {
+            transformed_stream
+        };
+        let transformed_stream = match bridge {
+            Some(Bridge::ToChat) => responses::stream_from_chat(transformed_stream),
+            _ => transformed_stream,
+        };
+        // 按客户端 Accept 请求头输出 SSE 或 NDJSON
+        let format = client_stream_format(&ctx.client_headers);
+        let transformed_stream = format.encode(transformed_stream);
+        // 按路由配置在 SSE 流末尾附加网关元数据
+        let transformed_stream = match (state.proxy.watermark(&config), format) {
+            (Some(watermark), ClientStreamFormat::Sse) => {
+                watermark.mark_sse_stream(transformed_stream)
+            }
+            _ => transformed_stream,
+        };
+        let transformed_stream = match &ctx.capture {
+            Some(capture) => {
+                capture.tap_stream(CaptureStage::ClientResponse, None, transformed_stream)
+            }
+            None => transformed_stream,
+        };
+        let transformed_stream = match &ctx.transcript {
+            Some(transcript) => {
+                transcript.tap_stream(TranscriptSide::Downstream, None, transformed_stream)
+            }
+            None => transformed_stream,
+        };
+        // 复制给旁路消费者（审计、内容安全、在线评估等），不等待消费者
+        let transformed_stream = match &state.stream_fanout {
+            Some(fanout) => fanout.tap(ctx.stream_meta(), transformed_stream),
+            None => transformed_stream,
+        };
+        let transformed_stream = state.stats.track_stream(transformed_stream, stream_slot);
+
+        // 在 Transport 层构建流式响应
+        // 设置 SSE 必要的响应头
+        let response = ctx
+            .response_builder(&upstream.headers)
+            .status(StatusCode::OK)
+            .header("content-type", format.content_type())
+            .header("cache-control", "no-cache")
+            .header("connection", "keep-alive")
+            .header("x-accel-buffering", "no") // 禁用 nginx 缓冲
+            .body(Body::from_stream(transformed_stream))
+            .unwrap();
+
+        failover.record_success();
+        return response;
     }
 
     // 所有路由都失败
-    error_response(StatusCode::SERVICE_UNAVAILABLE, "All stream routes failed")
+    ctx.all_routes_failed()
 }
 
 // 处理非流式请求
 // 非流式路径会等待上游请求完整完成：
 // 1) 发送请求 -> 2) 读取完整响应体 -> 3) 做协议转换 -> 4) 一次性返回给客户端。
 // 与流式不同，这里不会提前把响应返回给客户端，也没有持续推送的后台任务。
-async fn handle_non_stream(
-    state: AppState,
-    route_configs: Vec<RouteConfig>,
-    client_protocol: ClientProtocol,
-    body_bytes: Bytes,
-    user_token: String,
-    requested_model: String,
-    request_path: String,
-    client_headers: reqwest::header::HeaderMap,
-) -> Response<Body> {
-    // 生成请求ID用于去重
-    let request_id = Uuid::new_v4().to_string();
+async fn handle_non_stream(ctx: RequestContext, failover: &mut FailoverQueue) -> Response<Body> {
+    let state = &ctx.state;
 
-    // 判断是否需要自定义路径
-    let custom_path = if request_path == "/v1/responses" {
-        Some("/v1/responses")
-    } else {
-        None
+    // 路由竞速：首轮同时请求前几个路由，已完成的尝试按完成顺序依次处理
+    let mut raced = match state.proxy.race_width(&ctx.client_headers) {
+        Some(width) => ctx.race(failover, width).await,
+        None => VecDeque::new(),
     };
+    // 竞速中先完成的路由返回客户端错误而其他路由已成功时，优先返回成功的响应，
+    // 成功的响应无法使用时再返回该客户端错误
+    let mut raced_client_error = None;
 
     // 尝试每个路由配置
-    for config in route_configs {
-        let target_protocol = &config.protocol;
+    loop {
+        let (attempt, config, compression, forwarded) = match raced.pop_front() {
+            Some(raced) => {
+                failover.resume_race(raced.attempt, raced.finished_at);
+                (
+                    raced.attempt,
+                    raced.config,
+                    raced.compression,
+                    raced.forwarded,
+                )
+            }
+            None => {
+                if let Some(response) = raced_client_error.take() {
+                    return response;
+                }
+                let Some((attempt, config)) = failover.next_route() else {
+                    break;
+                };
+                let bridge = ctx.bridge(&config);
+                let Some((transformed_request, compression)) = ctx.prepare(&config, bridge).await
+                else {
+                    continue;
+                };
 
-        // 将请求转换为目标协议格式
-        let transformed_request = match state
-            .adapter
-            .transform_request(
-                &client_protocol,
-                target_protocol,
-                &config.model,
-                body_bytes.clone(),
-            )
-            .await
-        {
-            Ok(body) => body,
-            Err(e) => {
-                error!("Failed to transform request: {}", e);
-                continue;
+                // 转发请求
+                let forwarded = state
+                    .proxy
+                    .forward_request(
+                        &config,
+                        transformed_request,
+                        ctx.upstream_path(bridge),
+                        &ctx.upstream_headers(&config),
+                    )
+                    .await;
+                (attempt, config, compression, forwarded)
             }
         };
+        let target_protocol = &config.protocol;
+        let bridge = ctx.bridge(&config);
 
-        // 转发请求
-        match state
-            .proxy
-            .forward_request(&config, transformed_request, custom_path, &client_headers)
-            .await
-        {
-            Ok(response_body) => {
-                // 立即提取并上报usage信息（无论后续转换是否成功）
-                if let Some((input_tokens, output_tokens)) =
-                    extract_usage_from_response(&target_protocol, &response_body)
-                {
-                    state.telemetry.report_usage(UsageEvent {
-                        request_id: request_id.clone(),
-                        token: user_token.clone(),
-                        model: requested_model.clone(),
-                        api: config.api_endpoint.clone(),
-                        input_tokens,
-                        output_tokens,
-                        // 新增的ID字段
-                        model_id: config.model_id.clone(),
-                        provider_id: config.provider_id.clone(),
-                        provider_token_id: config.provider_token_id.clone(),
-                    });
-                }
-
-                // 验证响应体非空
-                if response_body.is_empty() {
-                    error!(
-                        "Empty response body from upstream: endpoint={}, model={}, protocol={:?}",
-                        config.api_endpoint, config.model, target_protocol
-                    );
+        let upstream = match forwarded {
+            Ok(upstream) => upstream,
+            Err(e) => match ctx.fail_attempt(failover, attempt, &config, &e).await {
+                Some(response) if raced.iter().any(|raced| raced.forwarded.is_ok()) => {
+                    raced_client_error = Some(response);
                     continue;
                 }
+                Some(response) => return response,
+                None => continue,
+            },
+        };
+        ctx.upstream_responded(failover, &config, upstream.request_id.clone());
+        let response_body = upstream.body;
+        if let Some(capture) = &ctx.capture {
+            capture.record(
+                CaptureStage::UpstreamResponse,
+                Some(&config.api_endpoint),
+                &response_body,
+            );
+        }
+
+        // 立即提取并上报usage信息（无论后续转换是否成功）
+        let usage = usage::parse_response(target_protocol, &response_body).unwrap_or_default();
+        if let Some((input_tokens, output_tokens)) = usage.totals() {
+            state.telemetry.report_usage(usage.annotate(UsageEvent {
+                input_tokens,
+                output_tokens,
+                original_prompt_tokens: compression.map(|c| c.original_tokens),
+                compressed_prompt_tokens: compression.map(|c| c.compressed_tokens),
+                upstream_request_id: upstream.request_id.clone(),
+                ..ctx.usage_event(&config)
+            }));
+        }
 
-                // 转换响应
-                match state
+        // 验证响应体非空
+        if response_body.is_empty() {
+            error!(
+                "Empty response body from upstream: endpoint={}, model={}, protocol={:?}",
+                config.api_endpoint, config.model, target_protocol
+            );
+            continue;
+        }
+
+        // 转换响应：Responses API 上游的响应先转换为 Chat Completions 格式
+        let converted = match bridge {
+            Some(Bridge::ToResponses) => responses::response_to_chat(&response_body),
+            _ => Ok(response_body),
+        };
+        let transformed = match converted {
+            Ok(body) => {
+                state
                     .adapter
-                    .transform_response(target_protocol, &client_protocol, response_body)
+                    .transform_response(target_protocol, &ctx.client_protocol, body)
                     .await
-                {
-                    Ok(transformed) => {
-                        return Response::builder()
-                            .status(StatusCode::OK)
-                            .header("content-type", "application/json")
-                            .body(Body::from(transformed))
-                            .unwrap();
-                    }
-                    Err(e) => {
-                        error!("Failed to transform response: {}", e);
-                        continue;
-                    }
-                }
             }
+            Err(e) => Err(e),
+        };
+        let transformed = match (transformed, bridge) {
+            (Ok(body), Some(Bridge::ToChat)) => responses::response_from_chat(&body),
+            (transformed, _) => transformed,
+        };
+        let transformed = match transformed {
+            Ok(transformed) => transformed,
             Err(e) => {
-                error!("Request failed for {}: {}", config.api_endpoint, e);
-
-                // 上报错误
-                state.telemetry.report_error(ErrorEvent {
-                    token: config.token.clone(),
-                    model: config.model.clone(),
-                    api: config.api_endpoint.clone(),
-                    msg: e.to_string(),
-                    provider_token_id: Some(config.provider_token_id.clone()), // 添加provider_token_id
-                });
-
-                // 检查是否为客户端错误（4xx），如果是则直接返回
-                if state.proxy.is_client_error(&e) {
-                    return create_error_response(&e);
-                }
-
-                // 从缓存中移除失败的配置
-                state
-                    .router
-                    .remove_failed_route(&user_token, &requested_model, &config)
-                    .await;
+                ctx.record_error(&config, &format!("Failed to transform response: {}", e));
                 continue;
             }
+        };
+        let transformed = if ctx.legacy_functions {
+            legacy_functions::response_to_legacy(transformed)
+        } else {
+            transformed
+        };
+        let transformed = match state.proxy.watermark(&config) {
+            Some(watermark) => watermark.mark_response(transformed),
+            None => transformed,
+        };
+        failover.record_success();
+        if let Some(capture) = &ctx.capture {
+            capture.record(CaptureStage::ClientResponse, None, &transformed);
         }
+        return ctx
+            .response_builder(&upstream.headers)
+            .status(StatusCode::OK)
+            .header("content-type", "application/json")
+            .body(Body::from(transformed))
+            .unwrap();
     }
 
     // 所有路由都失败
-    error_response(StatusCode::SERVICE_UNAVAILABLE, "All routes failed")
+    ctx.all_routes_failed()
 }
@@ -1,3 +1,4 @@
+use crate::secrets::mask_token;
 use serde::{Deserialize, Serialize};
 
 /// 客户端协议类型
@@ -28,7 +29,8 @@ pub enum TargetProtocol {
 
 /// 路由配置信息
 /// 包含将请求路由到目标服务所需的完整配置
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Debug 输出中的令牌已脱敏
+#[derive(Clone, Serialize, Deserialize)]
 pub struct RouteConfig {
     /// 供应商的API令牌/密钥
     pub token: String,
@@ -50,6 +52,236 @@ pub struct RouteConfig {
     /// 供应商Token ID
     #[serde(rename = "provider_token_id")]
     pub provider_token_id: String,
+
+    /// 平滑流式输出配置（可选，未配置时按上游节奏直接透传）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub smooth_streaming: Option<SmoothStreamingConfig>,
+
+    /// 图像生成接口路径（可选，用于自定义图像后端；未配置时使用 /v1/images/generations）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_generation_path: Option<String>,
+
+    /// 上游对话接口路径模板（可选），如 "/api/paas/v4/chat/completions"，
+    /// 支持 `{model}` 占位符；配置后拼接在 api 之后，不再按 /v1 后缀推断路径
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_template: Option<String>,
+
+    /// 金丝雀标记（可选）：带此标记的路由只承接按比例分桶命中的流量
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary: Option<CanarySpec>,
+
+    /// 是否清理客户端请求头（可选），未指定时使用 `proxy.header_hygiene.enabled`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub header_hygiene: Option<bool>,
+
+    /// 是否压缩提示词（可选），未指定时使用 `proxy.prompt_compression.enabled`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_compression: Option<bool>,
+
+    /// 上游认证方式（可选），如 "bearer"、"x-api-key"、"header:api-key"、"query:key"、"none"，
+    /// 多个方式以逗号分隔同时使用；未指定时按协议选择
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_scheme: Option<String>,
+
+    /// 单次请求的最大输出Token数（可选），未指定时使用 `proxy.max_output_tokens`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+
+    /// 文件上传的最大字节数（可选），未指定时使用 `files.max_file_bytes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_bytes: Option<u64>,
+
+    /// 重排序接口的供应商格式（可选），未指定时使用 Cohere 格式
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rerank_provider: Option<RerankProvider>,
+
+    /// 重排序接口路径（可选），未配置时按 rerank_provider 选择默认路径
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rerank_path: Option<String>,
+
+    /// 延迟等级（可选）：interactive 为低延迟供应商，batch 为低价慢速供应商
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_class: Option<LatencyClass>,
+
+    /// 所属路由池（可选），业务API按池返回路由时由网关填写
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool: Option<String>,
+
+    /// 池内权重（可选），同一池内的路由按权重随机排序，未指定时为1，为0时只作为池内兜底
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+
+    /// OpenAI 上游支持的接口（可选）：chat 为只支持 Chat Completions，Responses API 请求转换后发送；
+    /// responses 为只支持 Responses API，Chat Completions 请求转换后发送。未指定时按客户端请求的接口转发
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub openai_api: Option<OpenAIApi>,
+
+    /// 默认生成参数（可选），客户端未指定时填入，force 为 true 时覆盖客户端的值
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation_defaults: Option<GenerationDefaults>,
+
+    /// 上游 mTLS 客户端身份（可选），引用 `proxy.client_identities` 中的名称；
+    /// 未指定时按上游地址匹配身份的 endpoints
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_identity: Option<String>,
+
+    /// 发往上游的模型名改写规则（可选），业务API保存规范的模型ID，由网关按上游要求改写
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_rewrite: Option<ModelRewrite>,
+
+    /// 是否在响应中附加网关元数据（可选），未指定时使用 `proxy.watermark.enabled`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watermark: Option<bool>,
+
+    /// 是否启用 Claude Code / Codex CLI 客户端兼容处理（可选），未指定时使用 `compat.agent_clients.enabled`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent_compat: Option<bool>,
+}
+
+impl std::fmt::Debug for RouteConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RouteConfig")
+            .field("token", &mask_token(&self.token))
+            .field("model", &self.model)
+            .field("api_endpoint", &self.api_endpoint)
+            .field("protocol", &self.protocol)
+            .field("model_id", &self.model_id)
+            .field("provider_id", &self.provider_id)
+            .field("provider_token_id", &self.provider_token_id)
+            .field("smooth_streaming", &self.smooth_streaming)
+            .field("image_generation_path", &self.image_generation_path)
+            .field("path_template", &self.path_template)
+            .field("canary", &self.canary)
+            .field("header_hygiene", &self.header_hygiene)
+            .field("prompt_compression", &self.prompt_compression)
+            .field("auth_scheme", &self.auth_scheme)
+            .field("max_output_tokens", &self.max_output_tokens)
+            .field("max_file_bytes", &self.max_file_bytes)
+            .field("rerank_provider", &self.rerank_provider)
+            .field("rerank_path", &self.rerank_path)
+            .field("latency_class", &self.latency_class)
+            .field("pool", &self.pool)
+            .field("weight", &self.weight)
+            .field("openai_api", &self.openai_api)
+            .field("generation_defaults", &self.generation_defaults)
+            .field("client_identity", &self.client_identity)
+            .field("model_rewrite", &self.model_rewrite)
+            .field("watermark", &self.watermark)
+            .field("agent_compat", &self.agent_compat)
+            .finish()
+    }
+}
+
+/// 重排序接口的供应商格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RerankProvider {
+    /// Cohere `/v2/rerank`
+    Cohere,
+    /// Voyage AI `/v1/rerank`
+    Voyage,
+    /// Jina AI `/v1/rerank`
+    Jina,
+}
+
+/// OpenAI 上游的接口
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpenAIApi {
+    /// Chat Completions（`/v1/chat/completions`）
+    Chat,
+    /// Responses API（`/v1/responses`）
+    Responses,
+}
+
+/// 路由的默认生成参数，由平台统一不同客户端的采样行为
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationDefaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    /// 为 true 时覆盖客户端指定的值，否则只在客户端未指定时填入
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// 上游模型名改写规则：先按 `pattern` 正则替换，再套用 `template`
+///
+/// 例如聚合平台要求供应商前缀时使用 `{"template": "anthropic/{model}"}`，
+/// 上游不接受日期后缀时使用 `{"pattern": "-\\d{8}$", "replacement": ""}`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelRewrite {
+    /// 正则表达式（可选），匹配的部分替换为 `replacement`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// 替换内容，支持 `$1` 等捕获组引用
+    #[serde(default)]
+    pub replacement: String,
+    /// 模板（可选），`{model}` 替换为正则改写后的模型名
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+}
+
+/// 延迟等级，同时用于标注路由和请求声明的优先级
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LatencyClass {
+    /// 交互式，要求低延迟
+    Interactive,
+    /// 批量，可接受较高延迟以换取更低价格
+    Batch,
+}
+
+impl LatencyClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Interactive => "interactive",
+            Self::Batch => "batch",
+        }
+    }
+}
+
+/// 金丝雀发布参数
+/// 按用户令牌确定性分桶，命中比例内的用户优先使用候选路由，其余用户使用稳定路由
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CanarySpec {
+    /// 分流比例（0-100，支持小数，如 0.5 表示千分之五）
+    pub percentage: f64,
+    /// 金丝雀标签，随 UsageEvent 上报用于对比分析
+    pub tag: String,
+    /// 生效开始时间（可选，RFC3339）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// 生效结束时间（可选，RFC3339）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// 平滑流式输出配置
+/// 将上游突发的大块输出重新按固定节奏推送给客户端（打字机效果）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SmoothStreamingConfig {
+    /// 每秒最多下发的token数（文本增量按每4个字符一个token拆分，其他事件各计一个）
+    pub tokens_per_second: u32,
+}
+
+/// 路由缓存失效通知
+/// 业务API在用户套餐或供应商密钥变更时推送给网关（`POST /internal/invalidate`），立即失效相关缓存
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InvalidationRequest {
+    /// 用户令牌：失效该令牌在所有租户下的路由缓存，以及默认模型和所属租户缓存
+    #[serde(default)]
+    pub token: Option<String>,
+    /// 模型名：与 `token` 同时指定时只失效该模型的路由缓存
+    #[serde(default)]
+    pub model: Option<String>,
+    /// 供应商令牌ID：失效包含该供应商令牌的全部路由缓存
+    #[serde(default)]
+    pub provider_token_id: Option<String>,
+    /// 失效全部缓存
+    #[serde(default)]
+    pub all: bool,
 }
 
 /// 路由解析请求
@@ -60,6 +292,36 @@ pub struct RouteRequest {
     pub token: String,
     /// 请求的模型名称
     pub model: String,
+
+    // 可选的路由提示，按配置开关附加，未开启时不发送
+    /// 估算的提示词Token数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estimated_prompt_tokens: Option<u32>,
+    /// 是否为流式请求
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<bool>,
+    /// 客户端协议
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_protocol: Option<ClientProtocol>,
+    /// 客户端请求的最大输出Token数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    /// 客户端应用标识（取自配置的请求头）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_app: Option<String>,
+}
+
+/// 路由提示
+/// 网关按配置开关从客户端请求中收集的信息，随路由解析请求发送给业务API
+#[derive(Debug, Clone, Default)]
+pub struct RouteHints {
+    pub estimated_prompt_tokens: Option<u32>,
+    pub stream: Option<bool>,
+    pub client_protocol: Option<ClientProtocol>,
+    pub max_tokens: Option<u32>,
+    pub client_app: Option<String>,
+    /// 请求声明的优先级，只用于网关本地调整路由顺序，不发送给业务API
+    pub priority: Option<LatencyClass>,
 }
 
 /// 路由解析响应
@@ -74,12 +336,125 @@ pub struct RouteResponse {
     pub message: String,
     /// 路由配置列表（可能包含多个备选路由）
     pub data: Vec<RouteConfig>,
+    /// 按池分组的路由（可选），按列表顺序依次降级（如 primary、overflow、emergency），
+    /// 同时返回 `data` 时 `data` 中的路由排在所有池之后
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pools: Vec<RoutePool>,
+    /// 令牌所属租户（可选），请求未携带租户时以此隔离缓存、限流和遥测
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    /// 本次解析结果的缓存秒数（可选），覆盖全局缓存TTL，到期即失效不滑动续期
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_seconds: Option<u64>,
+    /// 不缓存本次解析结果（如控制面正在调整该令牌的路由）
+    #[serde(default)]
+    pub no_cache: bool,
+    /// 路由列表版本（可选），随缓存条目保存，便于排查缓存中的路由是否为最新
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// 令牌开通的对话内容记录（可选），未返回时不记录该令牌的请求内容
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_logging: Option<ContentLoggingPolicy>,
+}
+
+/// 业务API为用户令牌下发的对话内容记录策略
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContentLoggingPolicy {
+    /// 保留标签（如 "30d"、"90d"），随记录写入，存储侧据此清理；未指定时使用网关配置的默认标签
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<String>,
+    /// 请求和响应各自记录的内容上限（字节），不超过网关配置的上限
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_body_bytes: Option<usize>,
+}
+
+/// 路由池
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutePool {
+    /// 池名称
+    pub name: String,
+    /// 池内路由，各路由可通过 `weight` 指定权重
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+}
+
+/// 路由表拉取请求
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteTableRequest {
+    /// 网关当前持有的路由表版本，业务API可据此返回未变化
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// 业务后端返回（或推送）的完整路由表
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteTableResponse {
+    /// 响应状态码（0表示成功）
+    pub code: i32,
+    /// 请求是否成功
+    pub success: bool,
+    /// 响应消息（错误时包含错误信息）
+    pub message: String,
+    /// 所有活跃令牌的路由
+    #[serde(default)]
+    pub data: Vec<RouteTableEntry>,
+    /// 路由表版本（可选）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// 路由表与请求中的版本相同，未携带数据
+    #[serde(default)]
+    pub not_modified: bool,
+}
+
+/// 路由表中一个用户令牌的路由
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteTableEntry {
+    /// 用户令牌
+    pub token: String,
+    /// 令牌所属租户（可选）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    /// 令牌的默认模型（可选）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_model: Option<String>,
+    /// 令牌开通的对话内容记录（可选）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_logging: Option<ContentLoggingPolicy>,
+    /// 模型名 -> 路由配置列表
+    #[serde(default)]
+    pub routes: std::collections::HashMap<String, Vec<RouteConfig>>,
+}
+
+/// 默认模型查询请求
+/// 客户端未指定模型时，向业务后端查询该令牌的默认模型
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DefaultModelRequest {
+    /// 用户令牌
+    pub token: String,
+}
+
+/// 默认模型查询响应
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DefaultModelResponse {
+    /// 响应状态码（0表示成功）
+    pub code: i32,
+    /// 请求是否成功
+    pub success: bool,
+    /// 响应消息（错误时包含错误信息）
+    pub message: String,
+    /// 默认模型名称（未配置时为空）
+    pub data: Option<String>,
 }
 
 /// 错误事件
 /// 用于记录和上报代理请求的错误信息
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Debug 输出中的令牌已脱敏
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ErrorEvent {
+    /// 请求ID（同一请求的所有故障转移尝试共享）
+    pub request_id: String,
+    /// 尝试序号（从0开始，按实际尝试顺序递增；瞬时故障的路由可能在最后被重试）
+    pub attempt: u32,
     /// 用户令牌
     pub token: String,
     /// 使用的模型名称
@@ -93,11 +468,41 @@ pub struct ErrorEvent {
     /// 供应商Token ID
     #[serde(rename = "provider_token_id", skip_serializing_if = "Option::is_none")]
     pub provider_token_id: Option<String>,
+    /// 客户端真实IP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<String>,
+    /// JWT 认证模式下附加的客户端声明
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claims: Option<std::collections::HashMap<String, serde_json::Value>>,
+    /// 租户ID（启用多租户且识别出租户时）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    /// 上游请求ID（上游返回错误响应且带有请求ID时）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_request_id: Option<String>,
+}
+
+impl std::fmt::Debug for ErrorEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ErrorEvent")
+            .field("request_id", &self.request_id)
+            .field("attempt", &self.attempt)
+            .field("token", &mask_token(&self.token))
+            .field("model", &self.model)
+            .field("api", &self.api)
+            .field("msg", &self.msg)
+            .field("provider_token_id", &self.provider_token_id)
+            .field("client_ip", &self.client_ip)
+            .field("claims", &self.claims)
+            .field("tenant_id", &self.tenant_id)
+            .field("upstream_request_id", &self.upstream_request_id)
+            .finish()
+    }
 }
 
 /// Usage事件
 /// 用于记录和上报Token使用情况
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct UsageEvent {
     /// 请求ID（用于去重）
     pub request_id: String,
@@ -111,6 +516,18 @@ pub struct UsageEvent {
     pub input_tokens: i32,
     /// 输出Token数
     pub output_tokens: i32,
+    /// 上游返回的总Token数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_tokens: Option<i32>,
+    /// 输出中的推理Token数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_tokens: Option<i32>,
+    /// 命中提示词缓存的输入Token数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<i32>,
+    /// 写入提示词缓存的输入Token数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<i32>,
 
     // 新增ID字段 - 精确计费
     /// 模型ID
@@ -122,6 +539,148 @@ pub struct UsageEvent {
     /// 供应商Token ID
     #[serde(rename = "provider_token_id")]
     pub provider_token_id: String,
+    /// 客户端真实IP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_ip: Option<String>,
+    /// JWT 认证模式下附加的客户端声明
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claims: Option<std::collections::HashMap<String, serde_json::Value>>,
+    /// 租户ID（启用多租户且识别出租户时）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    /// 金丝雀标签（请求命中金丝雀路由时）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary: Option<String>,
+    /// 音频时长（秒），语音转写按时长计费
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_seconds: Option<f64>,
+    /// 输入字符数，语音合成按字符计费
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_characters: Option<u64>,
+    /// 生成的图片数量，图像生成按张计费
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_count: Option<u32>,
+    /// 图片尺寸（如 1024x1024）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_size: Option<String>,
+    /// 图片质量（如 standard、hd）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_quality: Option<String>,
+    /// 重排序的文档数量
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document_count: Option<u32>,
+    /// 计费的搜索单元数（Cohere 重排序按搜索单元计费）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search_units: Option<u32>,
+    /// 批处理ID（Message Batches 结果的用量），批处理通常按折扣价计费
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_id: Option<String>,
+    /// 流式响应未完整结束（客户端中途断开或上游中断）时为 false，用量为断开前已观测到的部分
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed: Option<bool>,
+    /// 提示词压缩前的输入Token数（估算，启用提示词压缩时）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_prompt_tokens: Option<u32>,
+    /// 提示词压缩后的输入Token数（估算，启用提示词压缩时）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compressed_prompt_tokens: Option<u32>,
+    /// 上游请求ID（供应商响应头中的 `x-request-id` 等）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_request_id: Option<String>,
+    /// 上游未返回用量、输入或输出Token数为网关按文本估算的值时为 true
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub estimated: Option<bool>,
+}
+
+/// 告警范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertScope {
+    /// 单个用户令牌
+    Token,
+    /// 单个供应商
+    Provider,
+    /// 全局
+    Global,
+}
+
+/// 费用告警事件
+/// 窗口内的估算费用超过配置阈值时上报
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AlertEvent {
+    /// 告警ID
+    pub alert_id: String,
+    /// 告警范围
+    pub scope: AlertScope,
+    /// 用户令牌（scope 为 token 时）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// 供应商ID（scope 为 provider 时）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_id: Option<String>,
+    /// 触发告警的请求所属租户
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    /// 配置的阈值
+    pub threshold: f64,
+    /// 窗口内的估算费用
+    pub spend: f64,
+    /// 统计窗口（秒）
+    pub window_secs: u64,
+    /// 触发时间
+    pub triggered_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl std::fmt::Debug for AlertEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AlertEvent")
+            .field("alert_id", &self.alert_id)
+            .field("scope", &self.scope)
+            .field("token", &self.token.as_deref().map(mask_token))
+            .field("provider_id", &self.provider_id)
+            .field("tenant_id", &self.tenant_id)
+            .field("threshold", &self.threshold)
+            .field("spend", &self.spend)
+            .field("window_secs", &self.window_secs)
+            .field("triggered_at", &self.triggered_at)
+            .finish()
+    }
+}
+
+/// 路由健康报告
+/// 一个上报周期内网关观测到的各供应商令牌的尝试结果，业务API可据此调整路由优先级
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteHealthReport {
+    /// 报告ID
+    pub report_id: String,
+    /// 统计周期开始时间
+    pub period_start: chrono::DateTime<chrono::Utc>,
+    /// 统计周期结束时间
+    pub period_end: chrono::DateTime<chrono::Utc>,
+    /// 周期内有尝试的供应商令牌
+    pub routes: Vec<RouteHealth>,
+}
+
+/// 单个供应商令牌在一个上报周期内的统计
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RouteHealth {
+    pub provider_token_id: String,
+    pub provider_id: String,
+    /// 尝试次数（不含竞速中被取消和未实际转发的尝试）
+    pub attempts: u64,
+    pub successes: u64,
+    /// 上游返回客户端错误（4xx）的次数，与供应商健康状况无关
+    pub client_errors: u64,
+    /// 瞬时故障和确定性故障的次数
+    pub failures: u64,
+    /// 成功尝试的 p95 延迟（毫秒，流式请求按开始输出计算），周期内没有成功尝试时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub p95_latency_ms: Option<u64>,
+    /// 周期内最近一次错误
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 /// 遥测响应
@@ -1,18 +1,99 @@
+use crate::config::AdapterConfig;
 use crate::error::{Error, Result};
 use crate::models::{ClientProtocol, TargetProtocol};
-use crate::protocol::{anthropic, openai, ProtocolAdapter};
+use crate::protocol::anthropic::AnthropicStreamEvent;
+use crate::protocol::anthropic_stream::AnthropicEventWriter;
+use crate::protocol::capabilities::{self, CapabilityTable};
+use crate::protocol::{anthropic, model_field, openai, stop_reason, ProtocolAdapter};
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
 use futures::{Stream, StreamExt};
 use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
 use std::pin::Pin;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
-pub struct UniversalAdapter;
+pub struct UniversalAdapter {
+    // OpenAI 消息角色映射（如 developer -> system）
+    role_aliases: HashMap<String, String>,
+    // 转发到 OpenAI 上游时是否也应用角色映射
+    normalize_openai_roles: bool,
+    // OpenAI 目标模型的参数能力表
+    capabilities: CapabilityTable,
+}
+
+impl Default for UniversalAdapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl UniversalAdapter {
     pub fn new() -> Self {
-        Self
+        Self::from_config(&AdapterConfig::default())
+    }
+
+    pub fn from_config(config: &AdapterConfig) -> Self {
+        Self {
+            role_aliases: config.role_aliases.clone(),
+            normalize_openai_roles: config.normalize_openai_roles,
+            capabilities: CapabilityTable::new(
+                config.reasoning_models.clone(),
+                config.non_reasoning_models.clone(),
+            ),
+        }
+    }
+
+    /// 请求体是否无需任何改写即可转发给目标协议的上游
+    ///
+    /// 同协议且上游模型名与请求一致时，`transform_request` 不会改变请求内容，
+    /// 调用方可以跳过解析直接转发原始请求体。
+    pub fn passes_through(
+        &self,
+        source_protocol: &ClientProtocol,
+        target_protocol: &TargetProtocol,
+        requested_model: &str,
+        target_model: &str,
+    ) -> bool {
+        self.only_replaces_model(source_protocol, target_protocol, target_model)
+            && requested_model == target_model
+    }
+
+    /// `transform_request` 是否只需替换请求体中的模型名（同协议且无需角色映射和参数适配）
+    fn only_replaces_model(
+        &self,
+        source_protocol: &ClientProtocol,
+        target_protocol: &TargetProtocol,
+        target_model: &str,
+    ) -> bool {
+        match (source_protocol, target_protocol) {
+            (ClientProtocol::OpenAI, TargetProtocol::OpenAI) => {
+                !self.normalize_openai_roles && !self.capabilities.needs_adaptation(target_model)
+            }
+            (ClientProtocol::Anthropic, TargetProtocol::Anthropic) => true,
+            _ => false,
+        }
+    }
+
+    /// 按配置映射 OpenAI 消息角色，未配置映射的角色原样返回
+    fn normalize_role<'a>(&'a self, role: &'a str) -> &'a str {
+        self.role_aliases.get(role).map_or(role, String::as_str)
+    }
+
+    /// 映射 OpenAI 请求 JSON 中的消息角色，保留其余字段不变
+    fn normalize_openai_json_roles(&self, json: &mut Value) {
+        let Some(messages) = json.get_mut("messages").and_then(Value::as_array_mut) else {
+            return;
+        };
+        for message in messages {
+            let Some(role) = message.get("role").and_then(Value::as_str) else {
+                continue;
+            };
+            let normalized = self.normalize_role(role);
+            if normalized != role {
+                message["role"] = Value::String(normalized.to_string());
+            }
+        }
     }
 
     // ================== SSE 解析辅助函数 ==================
@@ -45,7 +126,7 @@ impl UniversalAdapter {
     /// 将 OpenAI 流式响应转换为 Anthropic 格式
     /// 
     /// OpenAI 格式示例:
-    /// ```
+    /// ```text
     /// data: {"id":"chatcmpl-123","choices":[{"delta":{"role":"assistant","content":""},"finish_reason":null}]}
     /// data: {"id":"chatcmpl-123","choices":[{"delta":{"content":"Hello"},"finish_reason":null}]}
     /// data: {"id":"chatcmpl-123","choices":[{"delta":{"content":""},"finish_reason":"stop"}]}
@@ -53,16 +134,25 @@ impl UniversalAdapter {
     /// data: [DONE]
     /// ```
     /// 
-    /// Anthropic 格式示例:
-    /// ```
+    /// Anthropic 格式示例（完整事件序列见 [`AnthropicEventWriter`]）:
+    /// ```text
     /// event: message_start
     /// data: {"type":"message_start","message":{...}}
     /// 
+    /// event: ping
+    /// data: {"type":"ping"}
+    ///
+    /// event: content_block_start
+    /// data: {"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}
+    ///
     /// event: content_block_delta
-    /// data: {"type":"content_block_delta","delta":{"text":"Hello"}}
+    /// data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hello"}}
+    ///
+    /// event: content_block_stop
+    /// data: {"type":"content_block_stop","index":0}
     /// 
     /// event: message_delta
-    /// data: {"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":5}}
+    /// data: {"type":"message_delta","delta":{"stop_reason":"end_turn","stop_sequence":null},"usage":{"output_tokens":5}}
     /// 
     /// event: message_stop
     /// data: {"type":"message_stop"}
@@ -72,191 +162,46 @@ impl UniversalAdapter {
         stream: impl Stream<Item = Result<Bytes>> + Send + 'static,
     ) -> impl Stream<Item = Result<Bytes>> + Send + 'static {
         let mut buffer = BytesMut::new();
-        let mut message_started = false;
-        let mut content_block_started = false;
-        let mut message_id = String::new();
-        let mut model = String::new();
-        let mut usage_tokens = None;
-        
-        stream.then(move |chunk_result| {
-            let mut buffer = buffer.clone();
-            let mut message_started = message_started;
-            let mut content_block_started = content_block_started;
-            let mut message_id = message_id.clone();
-            let mut model = model.clone();
-            let mut usage_tokens = usage_tokens.clone();
-            
-            async move {
-                match chunk_result {
-                    Ok(chunk) => {
-                        // 将新数据追加到缓冲区
-                        buffer.extend_from_slice(&chunk);
-                        
-                        let mut output = Vec::new();
-                        
-                        // 按行处理缓冲区
-                        while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
-                            let line = buffer.split_to(pos + 1);
-                            let line_str = String::from_utf8_lossy(&line);
-                            let line_str = line_str.trim();
-                            
-                            if line_str.is_empty() {
-                                continue;
-                            }
-                            
-                            // 解析 OpenAI SSE 格式: "data: ..."
-                            if let Some((field, value)) = Self::parse_sse_line(&line_str) {
-                                if field == "data" {
-                                    // 处理结束标记
-                                    if value == "[DONE]" {
-                                        // 生成 Anthropic 结束事件
-                                        if content_block_started {
-                                            let stop_event = json!({
-                                                "type": "content_block_stop",
-                                                "index": 0
-                                            });
-                                            output.push(Self::format_sse(
-                                                Some("content_block_stop"),
-                                                &stop_event.to_string()
-                                            ));
-                                        }
-                                        
-                                        // 生成 message_delta，包含 usage 信息（如果有）
-                                        let delta_event = if let Some(usage) = usage_tokens {
-                                            json!({
-                                                "type": "message_delta",
-                                                "delta": {"stop_reason": "end_turn"},
-                                                "usage": {"output_tokens": usage}
-                                            })
-                                        } else {
-                                            json!({
-                                                "type": "message_delta",
-                                                "delta": {"stop_reason": "end_turn"}
-                                            })
-                                        };
-                                        output.push(Self::format_sse(
-                                            Some("message_delta"),
-                                            &delta_event.to_string()
-                                        ));
-                                        
-                                        let stop_event = json!({"type": "message_stop"});
-                                        output.push(Self::format_sse(
-                                            Some("message_stop"),
-                                            &stop_event.to_string()
-                                        ));
-                                        
-                                        break;
-                                    }
-                                    
-                                    // 解析 OpenAI JSON 数据
-                                    if let Ok(json_data) = serde_json::from_str::<Value>(value) {
-                                        // 检查是否有 usage 信息（某些实现会单独发送 usage chunk）
-                                        if let Some(usage) = json_data.get("usage") {
-                                            if !usage.is_null() {
-                                                usage_tokens = usage.get("completion_tokens")
-                                                    .and_then(|t| t.as_i64())
-                                                    .map(|t| t as i32);
-                                            }
-                                        }
-                                        
-                                        // 提取元数据
-                                        if !message_started {
-                                            message_id = json_data["id"].as_str().unwrap_or("msg_unknown").to_string();
-                                            model = json_data["model"].as_str().unwrap_or("unknown").to_string();
-                                            
-                                            // 生成 message_start 事件
-                                            let start_event = json!({
-                                                "type": "message_start",
-                                                "message": {
-                                                    "id": message_id,
-                                                    "type": "message",
-                                                    "role": "assistant",
-                                                    "content": [],
-                                                    "model": model,
-                                                    "stop_reason": null,
-                                                    "stop_sequence": null
-                                                }
-                                            });
-                                            output.push(Self::format_sse(
-                                                Some("message_start"),
-                                                &start_event.to_string()
-                                            ));
-                                            message_started = true;
-                                        }
-                                        
-                                        // 处理内容增量
-                                        if let Some(choices) = json_data["choices"].as_array() {
-                                            if let Some(choice) = choices.get(0) {
-                                                if let Some(delta) = choice.get("delta") {
-                                                    // 检查是否有角色信息（第一个 chunk）
-                                                    if delta.get("role").is_some() && !content_block_started {
-                                                        let block_start = json!({
-                                                            "type": "content_block_start",
-                                                            "index": 0,
-                                                            "content_block": {
-                                                                "type": "text",
-                                                                "text": ""
-                                                            }
-                                                        });
-                                                        output.push(Self::format_sse(
-                                                            Some("content_block_start"),
-                                                            &block_start.to_string()
-                                                        ));
-                                                        content_block_started = true;
-                                                    }
-                                                    
-                                                    // 处理内容（跳过空内容）
-                                                    if let Some(content) = delta.get("content").and_then(|c| c.as_str()) {
-                                                        if !content.is_empty() {
-                                                            if !content_block_started {
-                                                                let block_start = json!({
-                                                                    "type": "content_block_start",
-                                                                    "index": 0,
-                                                                    "content_block": {
-                                                                        "type": "text",
-                                                                        "text": ""
-                                                                    }
-                                                                });
-                                                                output.push(Self::format_sse(
-                                                                    Some("content_block_start"),
-                                                                    &block_start.to_string()
-                                                                ));
-                                                                content_block_started = true;
-                                                            }
-                                                            
-                                                            let delta_event = json!({
-                                                                "type": "content_block_delta",
-                                                                "index": 0,
-                                                                "delta": {
-                                                                    "type": "text_delta",
-                                                                    "text": content
-                                                                }
-                                                            });
-                                                            output.push(Self::format_sse(
-                                                                Some("content_block_delta"),
-                                                                &delta_event.to_string()
-                                                            ));
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        
-                        // 返回转换后的数据
-                        if !output.is_empty() {
-                            Ok(Bytes::from(output.join("")))
-                        } else {
-                            Ok(Bytes::new()) // 返回空数据，等待更多输入
-                        }
+        let mut writer = AnthropicEventWriter::new();
+
+        async_stream::stream! {
+            let mut stream = Box::pin(stream);
+            while let Some(chunk_result) = stream.next().await {
+                let chunk = match chunk_result {
+                    Ok(chunk) => chunk,
+                    Err(e) => {
+                        // 流已开始，无法再返回错误状态码，按规范以 error 事件结束
+                        warn!("Upstream stream failed during OpenAI -> Anthropic conversion: {}", e);
+                        yield Ok(Bytes::from(writer.fail("Upstream stream interrupted")));
+                        return;
                     }
-                    Err(e) => Err(e),
+                };
+                buffer.extend_from_slice(&chunk);
+
+                // 按行处理缓冲区，解析 OpenAI SSE 格式: "data: ..."
+                let mut output = String::new();
+                while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+                    let line = buffer.split_to(pos + 1);
+                    let line_str = String::from_utf8_lossy(&line);
+                    if let Some(("data", value)) = Self::parse_sse_line(line_str.trim()) {
+                        output.push_str(&writer.push_data(value));
+                    }
+                }
+
+                if !output.is_empty() {
+                    yield Ok(Bytes::from(output));
+                }
+                if writer.is_finished() {
+                    return;
                 }
             }
-        })
+
+            // 上游未发送 [DONE] 即关闭连接时补齐结束事件
+            let output = writer.finish();
+            if !output.is_empty() {
+                yield Ok(Bytes::from(output));
+            }
+        }
     }
 
     // ================== Anthropic -> OpenAI 流转换 ==================
@@ -264,7 +209,7 @@ impl UniversalAdapter {
     /// 将 Anthropic 流式响应转换为 OpenAI 格式
     /// 
     /// Anthropic 输入格式:
-    /// ```
+    /// ```text
     /// event: message_start
     /// data: {"type":"message_start","message":{...}}
     /// 
@@ -279,11 +224,11 @@ impl UniversalAdapter {
     /// ```
     /// 
     /// OpenAI 输出格式:
-    /// ```
+    /// ```text
     /// data: {"id":"chatcmpl-123","choices":[{"delta":{"role":"assistant","content":""},"finish_reason":null}]}
     /// data: {"id":"chatcmpl-123","choices":[{"delta":{"content":"Hello"},"finish_reason":null}]}
     /// data: {"id":"chatcmpl-123","choices":[{"delta":{"content":""},"finish_reason":"stop"}]}
-    /// data: {"id":"chatcmpl-123","choices":[],"usage":{"prompt_tokens":0,"completion_tokens":5,"total_tokens":5}}
+    /// data: {"id":"chatcmpl-123","choices":[],"usage":{"prompt_tokens":10,"completion_tokens":5,"total_tokens":15}}
     /// data: [DONE]
     /// ```
     fn convert_anthropic_to_openai_stream(
@@ -295,16 +240,15 @@ impl UniversalAdapter {
         let mut message_id = String::from("chatcmpl-unknown");
         let mut model = String::from("unknown");
         let mut usage_info: Option<Value> = None;
-        
-        stream.then(move |chunk_result| {
-            let mut buffer = buffer.clone();
-            let mut current_event = current_event.clone();
-            let mut message_id = message_id.clone();
-            let mut model = model.clone();
-            let mut usage_info = usage_info.clone();
-            
-            async move {
-                match chunk_result {
+        // message_start 中的输入用量（含缓存读写），用于补全 usage 的 prompt_tokens
+        let mut usage = anthropic::Usage::default();
+        // tool_use 内容块的索引 -> OpenAI tool_calls 的索引
+        let mut tool_calls: HashMap<u32, usize> = HashMap::new();
+
+        async_stream::stream! {
+            let mut stream = Box::pin(stream);
+            while let Some(chunk_result) = stream.next().await {
+                yield match chunk_result {
                     Ok(chunk) => {
                         // 将新数据追加到缓冲区
                         buffer.extend_from_slice(&chunk);
@@ -324,115 +268,165 @@ impl UniversalAdapter {
                             }
                             
                             // 解析 Anthropic SSE 格式
-                            if let Some((field, value)) = Self::parse_sse_line(&line_str) {
+                            if let Some((field, value)) = Self::parse_sse_line(line_str) {
                                 match field {
                                     "event" => {
                                         current_event = Some(value.to_string());
                                     }
                                     "data" => {
-                                        if let Ok(json_data) = serde_json::from_str::<Value>(value) {
-                                            match current_event.as_deref() {
-                                                Some("message_start") => {
-                                                    // 提取消息元数据
-                                                    if let Some(message) = json_data.get("message") {
-                                                        message_id = message["id"]
-                                                            .as_str()
-                                                            .unwrap_or("chatcmpl-unknown")
-                                                            .to_string();
-                                                        model = message["model"]
-                                                            .as_str()
-                                                            .unwrap_or("unknown")
-                                                            .to_string();
-                                                    }
-                                                    
-                                                    // 生成第一个 OpenAI chunk（包含角色）
-                                                    let openai_chunk = json!({
-                                                        "id": message_id,
-                                                        "object": "chat.completion.chunk",
-                                                        "created": chrono::Utc::now().timestamp(),
-                                                        "model": model,
-                                                        "choices": [{
-                                                            "index": 0,
-                                                            "delta": {"role": "assistant", "content": ""},
-                                                            "finish_reason": null
-                                                        }],
-                                                        "usage": null
-                                                    });
-                                                    output.push(Self::format_sse(None, &openai_chunk.to_string()));
+                                        let Ok(event) = serde_json::from_str::<AnthropicStreamEvent>(value) else {
+                                            continue;
+                                        };
+                                        match event {
+                                            AnthropicStreamEvent::MessageStart { message } => {
+                                                // 提取消息元数据
+                                                message_id = message.id.unwrap_or_else(|| "chatcmpl-unknown".to_string());
+                                                model = message.model.unwrap_or_else(|| "unknown".to_string());
+                                                if let Some(start_usage) = &message.usage {
+                                                    merge_anthropic_usage(&mut usage, start_usage);
                                                 }
-                                                Some("content_block_delta") => {
-                                                    // 转换内容增量
-                                                    if let Some(delta) = json_data.get("delta") {
-                                                        if let Some(text) = delta.get("text").and_then(|t| t.as_str()) {
-                                                            let openai_chunk = json!({
-                                                                "id": message_id,
-                                                                "object": "chat.completion.chunk",
-                                                                "created": chrono::Utc::now().timestamp(),
-                                                                "model": model,
-                                                                "choices": [{
-                                                                    "index": 0,
-                                                                    "delta": {"content": text},
-                                                                    "finish_reason": null
-                                                                }],
-                                                                "usage": null
-                                                            });
-                                                            output.push(Self::format_sse(None, &openai_chunk.to_string()));
-                                                        }
-                                                    }
+
+                                                // 生成第一个 OpenAI chunk（包含角色）
+                                                let openai_chunk = json!({
+                                                    "id": message_id,
+                                                    "object": "chat.completion.chunk",
+                                                    "created": chrono::Utc::now().timestamp(),
+                                                    "model": model,
+                                                    "choices": [{
+                                                        "index": 0,
+                                                        "delta": {"role": "assistant", "content": ""},
+                                                        "finish_reason": null
+                                                    }],
+                                                    "usage": null
+                                                });
+                                                output.push(Self::format_sse(None, &openai_chunk.to_string()));
+                                            }
+                                            AnthropicStreamEvent::ContentBlockDelta {
+                                                delta: anthropic::ContentDelta::TextDelta { text },
+                                                ..
+                                            } => {
+                                                // 转换内容增量
+                                                let openai_chunk = json!({
+                                                    "id": message_id,
+                                                    "object": "chat.completion.chunk",
+                                                    "created": chrono::Utc::now().timestamp(),
+                                                    "model": model,
+                                                    "choices": [{
+                                                        "index": 0,
+                                                        "delta": {"content": text},
+                                                        "finish_reason": null
+                                                    }],
+                                                    "usage": null
+                                                });
+                                                output.push(Self::format_sse(None, &openai_chunk.to_string()));
+                                            }
+                                            AnthropicStreamEvent::ContentBlockStart { index, content_block }
+                                                if content_block["type"] == "tool_use" =>
+                                            {
+                                                // 工具调用开始：按出现顺序分配 tool_calls 索引，带上 id 和函数名
+                                                let tool_index = tool_calls.len();
+                                                tool_calls.insert(index, tool_index);
+                                                let openai_chunk = json!({
+                                                    "id": message_id,
+                                                    "object": "chat.completion.chunk",
+                                                    "created": chrono::Utc::now().timestamp(),
+                                                    "model": model,
+                                                    "choices": [{
+                                                        "index": 0,
+                                                        "delta": {"tool_calls": [{
+                                                            "index": tool_index,
+                                                            "id": content_block["id"],
+                                                            "type": "function",
+                                                            "function": {"name": content_block["name"], "arguments": ""}
+                                                        }]},
+                                                        "finish_reason": null
+                                                    }],
+                                                    "usage": null
+                                                });
+                                                output.push(Self::format_sse(None, &openai_chunk.to_string()));
+                                            }
+                                            AnthropicStreamEvent::ContentBlockDelta {
+                                                index,
+                                                delta: anthropic::ContentDelta::InputJsonDelta { partial_json },
+                                            } if tool_calls.contains_key(&index) => {
+                                                // 工具调用参数片段
+                                                let openai_chunk = json!({
+                                                    "id": message_id,
+                                                    "object": "chat.completion.chunk",
+                                                    "created": chrono::Utc::now().timestamp(),
+                                                    "model": model,
+                                                    "choices": [{
+                                                        "index": 0,
+                                                        "delta": {"tool_calls": [{
+                                                            "index": tool_calls[&index],
+                                                            "function": {"arguments": partial_json}
+                                                        }]},
+                                                        "finish_reason": null
+                                                    }],
+                                                    "usage": null
+                                                });
+                                                output.push(Self::format_sse(None, &openai_chunk.to_string()));
+                                            }
+                                            AnthropicStreamEvent::MessageDelta { delta, usage: delta_usage } => {
+                                                // 提取 usage 信息和结束原因
+                                                let stop_reason = delta
+                                                    .stop_reason
+                                                    .as_deref()
+                                                    .map(stop_reason::anthropic_to_openai)
+                                                    .unwrap_or("stop");
+
+                                                // 保存 usage 信息
+                                                if let Some(delta_usage) = &delta_usage {
+                                                    // message_delta 若带有 input_tokens 等字段则以其为准
+                                                    merge_anthropic_usage(&mut usage, delta_usage);
+                                                    usage_info = serde_json::to_value(anthropic_usage_to_openai(&usage)).ok();
                                                 }
-                                                Some("message_delta") => {
-                                                    // 提取 usage 信息和结束原因
-                                                    let stop_reason = json_data["delta"]["stop_reason"]
-                                                        .as_str()
-                                                        .unwrap_or("stop");
-                                                    
-                                                    // 保存 usage 信息
-                                                    if let Some(usage) = json_data.get("usage") {
-                                                        let output_tokens = usage["output_tokens"].as_i64().unwrap_or(0);
-                                                        // Anthropic 不提供 prompt_tokens，设为 0
-                                                        usage_info = Some(json!({
-                                                            "prompt_tokens": 0,
-                                                            "completion_tokens": output_tokens,
-                                                            "total_tokens": output_tokens
-                                                        }));
-                                                    }
-                                                    
-                                                    // 生成带 finish_reason 的 chunk
-                                                    let openai_chunk = json!({
+
+                                                // 生成带 finish_reason 的 chunk
+                                                let openai_chunk = json!({
+                                                    "id": message_id,
+                                                    "object": "chat.completion.chunk",
+                                                    "created": chrono::Utc::now().timestamp(),
+                                                    "model": model,
+                                                    "choices": [{
+                                                        "index": 0,
+                                                        "delta": {"content": ""},
+                                                        "finish_reason": stop_reason
+                                                    }],
+                                                    "usage": null
+                                                });
+                                                output.push(Self::format_sse(None, &openai_chunk.to_string()));
+                                            }
+                                            AnthropicStreamEvent::MessageStop => {
+                                                // 如果有 usage 信息，生成单独的 usage chunk（像阿里云的格式）
+                                                if let Some(ref usage) = usage_info {
+                                                    let usage_chunk = json!({
                                                         "id": message_id,
                                                         "object": "chat.completion.chunk",
                                                         "created": chrono::Utc::now().timestamp(),
                                                         "model": model,
-                                                        "choices": [{
-                                                            "index": 0,
-                                                            "delta": {"content": ""},
-                                                            "finish_reason": stop_reason
-                                                        }],
-                                                        "usage": null
+                                                        "choices": [],
+                                                        "usage": usage
                                                     });
-                                                    output.push(Self::format_sse(None, &openai_chunk.to_string()));
+                                                    output.push(Self::format_sse(None, &usage_chunk.to_string()));
                                                 }
-                                                Some("message_stop") => {
-                                                    // 如果有 usage 信息，生成单独的 usage chunk（像阿里云的格式）
-                                                    if let Some(ref usage) = usage_info {
-                                                        let usage_chunk = json!({
-                                                            "id": message_id,
-                                                            "object": "chat.completion.chunk",
-                                                            "created": chrono::Utc::now().timestamp(),
-                                                            "model": model,
-                                                            "choices": [],
-                                                            "usage": usage
-                                                        });
-                                                        output.push(Self::format_sse(None, &usage_chunk.to_string()));
+
+                                                // 生成 [DONE] 标记
+                                                output.push(Self::format_sse(None, "[DONE]"));
+                                            }
+                                            AnthropicStreamEvent::Error { error } => {
+                                                // 上游（或网关）的错误事件按 OpenAI 流式错误格式转发
+                                                let openai_error = json!({
+                                                    "error": {
+                                                        "message": error.message.as_deref().unwrap_or("Upstream error"),
+                                                        "type": error.error_type.as_deref().unwrap_or("api_error"),
                                                     }
-                                                    
-                                                    // 生成 [DONE] 标记
-                                                    output.push(Self::format_sse(None, "[DONE]"));
-                                                }
-                                                _ => {
-                                                    // 忽略其他事件类型（如 content_block_start, content_block_stop）
-                                                    debug!("Ignoring Anthropic event type: {:?}", current_event);
-                                                }
+                                                });
+                                                output.push(Self::format_sse(None, &openai_error.to_string()));
+                                            }
+                                            _ => {
+                                                // 忽略其他事件类型（如文本块的 content_block_start, content_block_stop）
+                                                debug!("Ignoring Anthropic event type: {:?}", current_event);
                                             }
                                         }
                                     }
@@ -449,9 +443,9 @@ impl UniversalAdapter {
                         }
                     }
                     Err(e) => Err(e),
-                }
+                };
             }
-        })
+        }
     }
 
     // ================== 原有的请求/响应转换函数 ==================
@@ -460,69 +454,287 @@ impl UniversalAdapter {
         openai_req: &openai::OpenAIRequest,
         target_model: &str,
     ) -> Result<anthropic::AnthropicRequest> {
-        let mut messages = Vec::new();
-        let mut system_prompt = None;
+        let mut messages: Vec<anthropic::Message> = Vec::new();
+        let mut system_prompts = Vec::new();
+        // 无法转换而被丢弃的字段，转换结束后统一告警
+        let mut dropped = BTreeSet::new();
 
         for msg in &openai_req.messages {
+            dropped.extend(msg.extra.keys().map(|key| format!("messages[].{}", key)));
+
             match msg.role.as_str() {
+                // 多条 system（含映射后的 developer）消息合并，避免丢失
                 "system" => {
-                    if let openai::MessageContent::Text(text) = &msg.content {
-                        system_prompt = Some(text.clone());
+                    let text = Self::openai_content_text(&msg.content, &mut dropped);
+                    if !text.is_empty() {
+                        system_prompts.push(text);
                     }
                 }
                 "user" | "assistant" => {
-                    let content = match &msg.content {
-                        openai::MessageContent::Text(text) => {
-                            anthropic::MessageContent::Text(text.clone())
-                        }
-                        _ => anthropic::MessageContent::Text("".to_string()),
+                    let mut blocks = Self::openai_content_to_blocks(&msg.content, &mut dropped);
+                    blocks.extend(
+                        msg.tool_calls
+                            .iter()
+                            .flatten()
+                            .map(openai_tool_call_to_anthropic),
+                    );
+                    Self::push_anthropic_message(&mut messages, &msg.role, blocks);
+                }
+                // tool 消息转换为 user 消息中的 tool_result 块，连续的工具结果合并到同一条消息
+                "tool" => {
+                    let text = Self::openai_content_text(&msg.content, &mut dropped);
+                    let block = anthropic::ContentBlock::ToolResult {
+                        tool_use_id: msg.tool_call_id.clone().unwrap_or_default(),
+                        content: Some(anthropic::ToolResultContent::Text(text)),
+                        is_error: None,
                     };
+                    Self::push_anthropic_message(&mut messages, "user", vec![block]);
+                }
+                role => {
+                    dropped.insert(format!("messages[].role={}", role));
+                }
+            }
+        }
 
-                    messages.push(anthropic::Message {
-                        role: msg.role.clone(),
-                        content,
-                    });
+        if openai_req.frequency_penalty.is_some() {
+            dropped.insert("frequency_penalty".to_string());
+        }
+        if openai_req.presence_penalty.is_some() {
+            dropped.insert("presence_penalty".to_string());
+        }
+
+        let mut extra = vendor_extensions(&openai_req.extra);
+        let fields = openai_req.extra.as_object();
+        for key in fields.into_iter().flat_map(|fields| fields.keys()) {
+            match key.as_str() {
+                // 已在下方单独处理的字段
+                "tools" | "tool_choice" | "parallel_tool_calls" | "max_completion_tokens" => {}
+                // Anthropic 流式响应总是带有用量，无需转换
+                "stream_options" => {}
+                key if key.starts_with("x-") || key.starts_with("x_") => {}
+                key => {
+                    dropped.insert(key.to_string());
                 }
-                _ => {}
             }
         }
 
+        let tools = openai_req.extra.get("tools");
+        match tools.map(openai_tools_to_anthropic) {
+            Some(Some(tools)) => {
+                extra["tools"] = tools;
+                let choice = openai_req
+                    .extra
+                    .get("tool_choice")
+                    .and_then(openai_tool_choice_to_anthropic);
+                let parallel = openai_req
+                    .extra
+                    .get("parallel_tool_calls")
+                    .and_then(Value::as_bool);
+                match (choice, parallel) {
+                    (Some(mut choice), Some(false)) => {
+                        choice["disable_parallel_tool_use"] = json!(true);
+                        extra["tool_choice"] = choice;
+                    }
+                    (Some(choice), _) => extra["tool_choice"] = choice,
+                    (None, Some(false)) => {
+                        extra["tool_choice"] =
+                            json!({"type": "auto", "disable_parallel_tool_use": true});
+                    }
+                    (None, _) => {}
+                }
+                if openai_req.extra.get("tool_choice").is_some()
+                    && extra.get("tool_choice").is_none()
+                {
+                    dropped.insert("tool_choice".to_string());
+                }
+            }
+            Some(None) => {
+                dropped.insert("tools".to_string());
+            }
+            None => {}
+        }
+
+        if !dropped.is_empty() {
+            warn!(
+                dropped_fields = ?dropped,
+                "OpenAI request fields not supported by Anthropic were dropped"
+            );
+        }
+
+        // 新版客户端使用 max_completion_tokens 代替 max_tokens
+        let max_tokens = openai_req.max_tokens.or_else(|| {
+            openai_req
+                .extra
+                .get("max_completion_tokens")
+                .and_then(Value::as_i64)
+                .map(|n| n as i32)
+        });
+
         Ok(anthropic::AnthropicRequest {
             model: target_model.to_string(),
             messages,
-            max_tokens: openai_req.max_tokens.unwrap_or(1024),
+            max_tokens: max_tokens.unwrap_or(1024),
             temperature: openai_req.temperature,
             top_p: openai_req.top_p,
             top_k: None,
             stream: openai_req.stream,
-            system: system_prompt,
-            extra: Value::Object(Default::default()),
+            system: (!system_prompts.is_empty()).then(|| system_prompts.join("\n\n")),
+            stop_sequences: openai_req.stop.clone().map(openai::Stop::into_vec),
+            metadata: openai_req.user.clone().map(|user| anthropic::Metadata {
+                user_id: Some(user),
+            }),
+            service_tier: openai_req
+                .service_tier
+                .as_deref()
+                .and_then(openai_service_tier_to_anthropic),
+            extra,
         })
     }
 
+    /// 取出 OpenAI 消息内容中的文本，多段内容按换行拼接，非文本段记为丢弃
+    fn openai_content_text(
+        content: &Option<openai::MessageContent>,
+        dropped: &mut BTreeSet<String>,
+    ) -> String {
+        match content {
+            Some(openai::MessageContent::Text(text)) => text.clone(),
+            Some(openai::MessageContent::Array(parts)) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    openai::ContentPart::Text { text } => Some(text.as_str()),
+                    _ => {
+                        dropped.insert("messages[].content[].non_text".to_string());
+                        None
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            None => String::new(),
+        }
+    }
+
+    /// 将 OpenAI 消息内容转换为 Anthropic 内容块
+    ///
+    /// 只有 base64 data URL 形式的图片可以转换，远程图片 URL 和其他内容类型记为丢弃
+    fn openai_content_to_blocks(
+        content: &Option<openai::MessageContent>,
+        dropped: &mut BTreeSet<String>,
+    ) -> Vec<anthropic::ContentBlock> {
+        let parts = match content {
+            Some(openai::MessageContent::Text(text)) => {
+                return vec![anthropic::ContentBlock::Text { text: text.clone() }];
+            }
+            Some(openai::MessageContent::Array(parts)) => parts,
+            None => return Vec::new(),
+        };
+
+        let mut blocks = Vec::new();
+        for part in parts {
+            match part {
+                openai::ContentPart::Text { text } => {
+                    blocks.push(anthropic::ContentBlock::Text { text: text.clone() });
+                }
+                openai::ContentPart::ImageUrl { image_url } => {
+                    match parse_data_url(&image_url.url) {
+                        Some((media_type, data)) => blocks.push(anthropic::ContentBlock::Image {
+                            source: anthropic::ImageSource {
+                                source_type: "base64".to_string(),
+                                media_type: media_type.to_string(),
+                                data: data.to_string(),
+                            },
+                        }),
+                        None => {
+                            dropped.insert("messages[].content[].image_url(remote)".to_string());
+                        }
+                    }
+                }
+                openai::ContentPart::Unsupported => {
+                    dropped.insert("messages[].content[].unsupported".to_string());
+                }
+            }
+        }
+        blocks
+    }
+
+    /// 追加 Anthropic 消息，与上一条同角色时合并内容块（Anthropic 要求角色交替）
+    ///
+    /// 只有一个文本块时使用字符串内容，与原先的转换结果保持一致
+    fn push_anthropic_message(
+        messages: &mut Vec<anthropic::Message>,
+        role: &str,
+        mut blocks: Vec<anthropic::ContentBlock>,
+    ) {
+        if let Some(last) = messages.last_mut().filter(|last| last.role == role) {
+            let mut merged = match std::mem::replace(
+                &mut last.content,
+                anthropic::MessageContent::Array(Vec::new()),
+            ) {
+                anthropic::MessageContent::Text(text) => {
+                    vec![anthropic::ContentBlock::Text { text }]
+                }
+                anthropic::MessageContent::Array(existing) => existing,
+            };
+            merged.append(&mut blocks);
+            last.content = anthropic::MessageContent::Array(merged);
+            return;
+        }
+
+        let content = match blocks.as_slice() {
+            [] => anthropic::MessageContent::Text(String::new()),
+            [anthropic::ContentBlock::Text { text }] => {
+                anthropic::MessageContent::Text(text.clone())
+            }
+            _ => anthropic::MessageContent::Array(blocks),
+        };
+        messages.push(anthropic::Message {
+            role: role.to_string(),
+            content,
+        });
+    }
+
     fn anthropic_to_openai(
         anthropic_req: &anthropic::AnthropicRequest,
         target_model: &str,
     ) -> Result<openai::OpenAIRequest> {
         let mut messages = Vec::new();
 
+        // 扩展思考按预算映射为 reasoning_effort，目标模型不支持时由能力表去掉
+        let mut extra = vendor_extensions(&anthropic_req.extra);
+        let thinking = anthropic_req.extra.get("thinking");
+        if thinking.and_then(|t| t.get("type")).and_then(Value::as_str) == Some("enabled") {
+            let budget = thinking
+                .and_then(|t| t.get("budget_tokens"))
+                .and_then(Value::as_i64)
+                .unwrap_or(0);
+            extra["reasoning_effort"] =
+                Value::String(capabilities::reasoning_effort_for_budget(budget).to_string());
+        }
+
         if let Some(system) = &anthropic_req.system {
             messages.push(openai::Message {
                 role: "system".to_string(),
-                content: openai::MessageContent::Text(system.clone()),
+                content: Some(openai::MessageContent::Text(system.clone())),
+                tool_calls: None,
+                tool_call_id: None,
+                extra: Default::default(),
             });
         }
 
         for msg in &anthropic_req.messages {
-            let content = match &msg.content {
-                anthropic::MessageContent::Text(text) => openai::MessageContent::Text(text.clone()),
-                _ => openai::MessageContent::Text("".to_string()),
-            };
-
-            messages.push(openai::Message {
-                role: msg.role.clone(),
-                content,
-            });
+            match &msg.content {
+                anthropic::MessageContent::Text(text) => {
+                    messages.push(openai::Message {
+                        role: msg.role.clone(),
+                        content: Some(openai::MessageContent::Text(text.clone())),
+                        tool_calls: None,
+                        tool_call_id: None,
+                        extra: Default::default(),
+                    });
+                }
+                anthropic::MessageContent::Array(blocks) => {
+                    messages.extend(Self::anthropic_blocks_to_openai_messages(&msg.role, blocks));
+                }
+            }
         }
 
         Ok(openai::OpenAIRequest {
@@ -534,10 +746,123 @@ impl UniversalAdapter {
             stream: anthropic_req.stream,
             frequency_penalty: None,
             presence_penalty: None,
-            extra: Value::Object(Default::default()),
+            // OpenAI 最多支持4个停止序列
+            stop: anthropic_req
+                .stop_sequences
+                .as_ref()
+                .filter(|stops| !stops.is_empty())
+                .map(|stops| {
+                    openai::Stop::Many(stops.iter().take(OPENAI_MAX_STOP).cloned().collect())
+                }),
+            user: anthropic_req
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.user_id.clone()),
+            service_tier: anthropic_req
+                .service_tier
+                .as_deref()
+                .and_then(anthropic_service_tier_to_openai),
+            extra,
         })
     }
 
+    /// 将 Anthropic 多段内容消息转换为 OpenAI 消息
+    ///
+    /// - `tool_result` 块转换为独立的 `role: "tool"` 消息（放在前面，紧跟上一条 assistant 的 tool_calls）
+    /// - `tool_use` 块转换为 assistant 消息的 `tool_calls`
+    /// - 文本/图片块合并为一条消息：只有文本时拼接为字符串，包含图片时使用多段内容
+    fn anthropic_blocks_to_openai_messages(
+        role: &str,
+        blocks: &[anthropic::ContentBlock],
+    ) -> Vec<openai::Message> {
+        let mut messages = Vec::new();
+        let mut parts = Vec::new();
+        let mut tool_calls = Vec::new();
+
+        for block in blocks {
+            match block {
+                anthropic::ContentBlock::Text { text } => {
+                    parts.push(openai::ContentPart::Text { text: text.clone() });
+                }
+                anthropic::ContentBlock::Image { source } => {
+                    parts.push(openai::ContentPart::ImageUrl {
+                        image_url: openai::ImageUrl {
+                            url: format!("data:{};base64,{}", source.media_type, source.data),
+                        },
+                    });
+                }
+                anthropic::ContentBlock::ToolUse { id, name, input } => {
+                    tool_calls.push(anthropic_tool_use_to_openai(id, name, input));
+                }
+                anthropic::ContentBlock::ToolResult {
+                    tool_use_id,
+                    content,
+                    ..
+                } => {
+                    // OpenAI 的 tool 消息只支持文本，图片等其他块被忽略
+                    let text = match content {
+                        Some(anthropic::ToolResultContent::Text(text)) => text.clone(),
+                        Some(anthropic::ToolResultContent::Array(inner)) => inner
+                            .iter()
+                            .filter_map(|b| match b {
+                                anthropic::ContentBlock::Text { text } => Some(text.as_str()),
+                                _ => None,
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n"),
+                        None => String::new(),
+                    };
+
+                    messages.push(openai::Message {
+                        role: "tool".to_string(),
+                        content: Some(openai::MessageContent::Text(text)),
+                        tool_calls: None,
+                        tool_call_id: Some(tool_use_id.clone()),
+                        extra: Default::default(),
+                    });
+                }
+            }
+        }
+
+        if parts.is_empty() && tool_calls.is_empty() {
+            return messages;
+        }
+
+        let only_text = parts
+            .iter()
+            .all(|p| matches!(p, openai::ContentPart::Text { .. }));
+        let content = if parts.is_empty() {
+            None
+        } else if only_text {
+            let text = parts
+                .iter()
+                .filter_map(|p| match p {
+                    openai::ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            Some(openai::MessageContent::Text(text))
+        } else {
+            Some(openai::MessageContent::Array(parts))
+        };
+
+        messages.push(openai::Message {
+            role: role.to_string(),
+            content,
+            tool_calls: if tool_calls.is_empty() {
+                None
+            } else {
+                Some(tool_calls)
+            },
+            tool_call_id: None,
+            extra: Default::default(),
+        });
+
+        messages
+    }
+
+    /// 文本与工具调用同时存在时都保留：文本块在前，随后是 tool_use 块
     fn openai_response_to_anthropic(
         openai_resp: &openai::OpenAIResponse,
     ) -> Result<anthropic::AnthropicResponse> {
@@ -546,38 +871,56 @@ impl UniversalAdapter {
             .first()
             .ok_or_else(|| Error::Protocol("No choices in OpenAI response".into()))?;
 
-        let text = match &first_choice.message.content {
-            openai::MessageContent::Text(text) => text.clone(),
-            _ => "".to_string(),
-        };
+        let text = Self::openai_content_text(&first_choice.message.content, &mut BTreeSet::new());
+        let tool_calls = first_choice
+            .message
+            .tool_calls
+            .as_deref()
+            .unwrap_or_default();
+
+        let mut content = Vec::new();
+        // 只有工具调用时省略空文本块
+        if !text.is_empty() || tool_calls.is_empty() {
+            content.push(anthropic::ContentBlock::Text { text });
+        }
+        content.extend(tool_calls.iter().map(openai_tool_call_to_anthropic));
 
         Ok(anthropic::AnthropicResponse {
             id: openai_resp.id.clone(),
             response_type: "message".to_string(),
             role: "assistant".to_string(),
-            content: vec![anthropic::ContentBlock::Text { text }],
+            content,
             model: openai_resp.model.clone(),
-            stop_reason: first_choice.finish_reason.clone(),
+            stop_reason: first_choice
+                .finish_reason
+                .as_deref()
+                .map(|reason| stop_reason::openai_to_anthropic(reason).to_string()),
             stop_sequence: None,
-            usage: anthropic::Usage {
-                input_tokens: openai_resp.usage.prompt_tokens,
-                output_tokens: openai_resp.usage.completion_tokens,
-            },
+            usage: openai_usage_to_anthropic(&openai_resp.usage),
         })
     }
 
+    /// 文本块拼接为 `content`，tool_use 块转换为 `tool_calls`；只有工具调用时 `content` 为 null
     fn anthropic_response_to_openai(
         anthropic_resp: &anthropic::AnthropicResponse,
     ) -> Result<openai::OpenAIResponse> {
-        let text = anthropic_resp
-            .content
-            .iter()
-            .filter_map(|block| match block {
-                anthropic::ContentBlock::Text { text } => Some(text.clone()),
-                _ => None,
-            })
-            .collect::<Vec<_>>()
-            .join("");
+        let mut text = String::new();
+        let mut tool_calls = Vec::new();
+        for block in &anthropic_resp.content {
+            match block {
+                anthropic::ContentBlock::Text { text: part } => text.push_str(part),
+                anthropic::ContentBlock::ToolUse { id, name, input } => {
+                    tool_calls.push(anthropic_tool_use_to_openai(id, name, input));
+                }
+                _ => {}
+            }
+        }
+
+        let content = if text.is_empty() && !tool_calls.is_empty() {
+            None
+        } else {
+            Some(openai::MessageContent::Text(text))
+        };
 
         Ok(openai::OpenAIResponse {
             id: anthropic_resp.id.clone(),
@@ -588,20 +931,184 @@ impl UniversalAdapter {
                 index: 0,
                 message: openai::Message {
                     role: "assistant".to_string(),
-                    content: openai::MessageContent::Text(text),
+                    content,
+                    tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+                    tool_call_id: None,
+                    extra: Default::default(),
                 },
-                finish_reason: anthropic_resp.stop_reason.clone(),
+                finish_reason: anthropic_resp
+                    .stop_reason
+                    .as_deref()
+                    .map(|reason| stop_reason::anthropic_to_openai(reason).to_string()),
             }],
-            usage: openai::Usage {
-                prompt_tokens: anthropic_resp.usage.input_tokens,
-                completion_tokens: anthropic_resp.usage.output_tokens,
-                total_tokens: anthropic_resp.usage.input_tokens
-                    + anthropic_resp.usage.output_tokens,
-            },
+            usage: anthropic_usage_to_openai(&anthropic_resp.usage),
         })
     }
 }
 
+/// OpenAI 请求允许的最大停止序列数
+const OPENAI_MAX_STOP: usize = 4;
+
+// OpenAI service_tier -> Anthropic：default 对应 standard_only，flex / priority 等无对应值时丢弃
+fn openai_service_tier_to_anthropic(tier: &str) -> Option<String> {
+    match tier {
+        "auto" => Some("auto".to_string()),
+        "default" => Some("standard_only".to_string()),
+        _ => None,
+    }
+}
+
+// Anthropic service_tier -> OpenAI
+fn anthropic_service_tier_to_openai(tier: &str) -> Option<String> {
+    match tier {
+        "auto" => Some("auto".to_string()),
+        "standard_only" => Some("default".to_string()),
+        _ => None,
+    }
+}
+
+// 跨协议转换时只保留厂商扩展字段（`x-` / `x_` 前缀），其余未知字段属于源协议，丢弃
+// 同协议转发时请求体原样透传，所有字段都会保留
+fn vendor_extensions(extra: &Value) -> Value {
+    let fields = extra
+        .as_object()
+        .map(|fields| {
+            fields
+                .iter()
+                .filter(|(key, _)| key.starts_with("x-") || key.starts_with("x_"))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+    Value::Object(fields)
+}
+
+// Anthropic usage -> OpenAI：Anthropic 的 input_tokens 不含缓存读写，
+// OpenAI 的 prompt_tokens 包含全部输入，其中命中缓存的部分记入 prompt_tokens_details.cached_tokens
+fn anthropic_usage_to_openai(usage: &anthropic::Usage) -> openai::Usage {
+    let cache_read = usage.cache_read_input_tokens.unwrap_or(0);
+    let prompt_tokens =
+        usage.input_tokens + cache_read + usage.cache_creation_input_tokens.unwrap_or(0);
+    openai::Usage {
+        prompt_tokens,
+        completion_tokens: usage.output_tokens,
+        total_tokens: prompt_tokens + usage.output_tokens,
+        prompt_tokens_details: Some(openai::PromptTokensDetails {
+            cached_tokens: Some(cache_read),
+            ..Default::default()
+        }),
+        completion_tokens_details: None,
+    }
+}
+
+// OpenAI usage -> Anthropic：命中缓存的部分从 input_tokens 中拆出，记为 cache_read_input_tokens
+fn openai_usage_to_anthropic(usage: &openai::Usage) -> anthropic::Usage {
+    let cached = usage
+        .prompt_tokens_details
+        .as_ref()
+        .and_then(|details| details.cached_tokens)
+        .unwrap_or(0)
+        .clamp(0, usage.prompt_tokens.max(0));
+    anthropic::Usage {
+        input_tokens: usage.prompt_tokens - cached,
+        output_tokens: usage.completion_tokens,
+        cache_creation_input_tokens: None,
+        cache_read_input_tokens: (cached > 0).then_some(cached),
+    }
+}
+
+// 将 Anthropic 流式事件中的 usage 字段合并到已有用量，事件中没有的字段保持不变
+fn merge_anthropic_usage(usage: &mut anthropic::Usage, update: &anthropic::StreamUsage) {
+    let field = |value: Option<i64>| value.map(|n| n as i32);
+    if let Some(input_tokens) = field(update.input_tokens) {
+        usage.input_tokens = input_tokens;
+    }
+    if let Some(output_tokens) = field(update.output_tokens) {
+        usage.output_tokens = output_tokens;
+    }
+    if let Some(tokens) = field(update.cache_creation_input_tokens) {
+        usage.cache_creation_input_tokens = Some(tokens);
+    }
+    if let Some(tokens) = field(update.cache_read_input_tokens) {
+        usage.cache_read_input_tokens = Some(tokens);
+    }
+}
+
+// OpenAI tool_call -> Anthropic tool_use 块
+fn openai_tool_call_to_anthropic(call: &openai::ToolCall) -> anthropic::ContentBlock {
+    anthropic::ContentBlock::ToolUse {
+        id: call.id.clone(),
+        name: call.function.name.clone(),
+        // 参数不是合法 JSON 时按空对象处理，Anthropic 要求 input 为对象
+        input: serde_json::from_str(&call.function.arguments).unwrap_or_else(|_| json!({})),
+    }
+}
+
+// Anthropic tool_use 块 -> OpenAI tool_call，input 序列化为 JSON 字符串
+fn anthropic_tool_use_to_openai(id: &str, name: &str, input: &Value) -> openai::ToolCall {
+    openai::ToolCall {
+        id: id.to_string(),
+        call_type: "function".to_string(),
+        function: openai::FunctionCall {
+            name: name.to_string(),
+            arguments: input.to_string(),
+        },
+    }
+}
+
+// OpenAI tools -> Anthropic tools，只转换 function 类型；没有可转换的工具时返回 None
+fn openai_tools_to_anthropic(tools: &Value) -> Option<Value> {
+    let tools: Vec<Value> = tools
+        .as_array()?
+        .iter()
+        .filter(|tool| {
+            matches!(
+                tool.get("type").and_then(Value::as_str),
+                None | Some("function")
+            )
+        })
+        .filter_map(|tool| {
+            let function = tool.get("function")?;
+            let mut converted = json!({
+                "name": function.get("name")?.as_str()?,
+                "input_schema": function
+                    .get("parameters")
+                    .cloned()
+                    .unwrap_or_else(|| json!({"type": "object", "properties": {}})),
+            });
+            if let Some(description) = function.get("description").and_then(Value::as_str) {
+                converted["description"] = json!(description);
+            }
+            Some(converted)
+        })
+        .collect();
+    (!tools.is_empty()).then_some(Value::Array(tools))
+}
+
+// OpenAI tool_choice -> Anthropic：required 对应 any，指定函数对应 tool
+fn openai_tool_choice_to_anthropic(choice: &Value) -> Option<Value> {
+    match choice {
+        Value::String(mode) => match mode.as_str() {
+            "auto" => Some(json!({"type": "auto"})),
+            "required" => Some(json!({"type": "any"})),
+            "none" => Some(json!({"type": "none"})),
+            _ => None,
+        },
+        Value::Object(_) => {
+            let name = choice.pointer("/function/name")?.as_str()?;
+            Some(json!({"type": "tool", "name": name}))
+        }
+        _ => None,
+    }
+}
+
+// 解析 `data:<media_type>;base64,<data>` 形式的图片 URL
+fn parse_data_url(url: &str) -> Option<(&str, &str)> {
+    let (meta, data) = url.strip_prefix("data:")?.split_once(',')?;
+    let media_type = meta.strip_suffix(";base64")?;
+    Some((media_type, data))
+}
+
 #[async_trait]
 impl ProtocolAdapter for UniversalAdapter {
     async fn transform_request(
@@ -611,6 +1118,13 @@ impl ProtocolAdapter for UniversalAdapter {
         target_model: &str,
         request_body: Bytes,
     ) -> Result<Bytes> {
+        // 只需替换模型名时直接在原始请求体上替换，避免完整解析大请求体
+        if self.only_replaces_model(source_protocol, target_protocol, target_model) {
+            if let Some(body) = model_field::replace_model(&request_body, target_model) {
+                return Ok(body);
+            }
+        }
+
         let json_value: Value = serde_json::from_slice(&request_body)?;
 
         let transformed = match (source_protocol, target_protocol) {
@@ -618,14 +1132,22 @@ impl ProtocolAdapter for UniversalAdapter {
             (ClientProtocol::OpenAI, TargetProtocol::OpenAI) => {
                 // 对于OpenAI到OpenAI，需要替换模型名
                 let mut json = json_value;
+                if self.normalize_openai_roles {
+                    self.normalize_openai_json_roles(&mut json);
+                }
                 // 这里是map 所以insert是新建或替换 没有重复
                 if let Value::Object(ref mut obj) = json {
                     obj.insert("model".to_string(), Value::String(target_model.to_string()));
                 }
+                self.capabilities
+                    .adapt_openai_request(&mut json, target_model, false);
                 json
             }
             (ClientProtocol::OpenAI, TargetProtocol::Anthropic) => {
-                let openai_req: openai::OpenAIRequest = serde_json::from_value(json_value)?;
+                let mut openai_req: openai::OpenAIRequest = serde_json::from_value(json_value)?;
+                for msg in &mut openai_req.messages {
+                    msg.role = self.normalize_role(&msg.role).to_string();
+                }
                 let anthropic_req = Self::openai_to_anthropic(&openai_req, target_model)?;
                 serde_json::to_value(anthropic_req)?
             }
@@ -633,7 +1155,10 @@ impl ProtocolAdapter for UniversalAdapter {
                 let anthropic_req: anthropic::AnthropicRequest =
                     serde_json::from_value(json_value)?;
                 let openai_req = Self::anthropic_to_openai(&anthropic_req, target_model)?;
-                serde_json::to_value(openai_req)?
+                let mut json = serde_json::to_value(openai_req)?;
+                self.capabilities
+                    .adapt_openai_request(&mut json, target_model, true);
+                json
             }
             // Anthropic 同类型替换 换模型就好
             (ClientProtocol::Anthropic, TargetProtocol::Anthropic) => {
@@ -741,3 +1266,289 @@ impl ProtocolAdapter for UniversalAdapter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn convert_response(
+        source: TargetProtocol,
+        target: ClientProtocol,
+        body: Value,
+    ) -> Value {
+        let converted = UniversalAdapter::new()
+            .transform_response(&source, &target, Bytes::from(body.to_string()))
+            .await
+            .unwrap();
+        serde_json::from_slice(&converted).unwrap()
+    }
+
+    fn openai_response(content: Value, tool_calls: Value, finish_reason: &str) -> Value {
+        json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": content, "tool_calls": tool_calls},
+                "finish_reason": finish_reason,
+            }],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15},
+        })
+    }
+
+    fn anthropic_response(content: Value, stop_reason: &str) -> Value {
+        json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": content,
+            "model": "claude",
+            "stop_reason": stop_reason,
+            "stop_sequence": null,
+            "usage": {"input_tokens": 10, "output_tokens": 5},
+        })
+    }
+
+    #[tokio::test]
+    async fn openai_response_keeps_text_and_tool_calls() {
+        let response = convert_response(
+            TargetProtocol::OpenAI,
+            ClientProtocol::Anthropic,
+            openai_response(
+                json!("Let me check."),
+                json!([{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"},
+                }]),
+                "tool_calls",
+            ),
+        )
+        .await;
+        assert_eq!(
+            response["content"],
+            json!([
+                {"type": "text", "text": "Let me check."},
+                {"type": "tool_use", "id": "call_1", "name": "get_weather", "input": {"city": "Paris"}},
+            ])
+        );
+        assert_eq!(response["stop_reason"], "tool_use");
+    }
+
+    #[tokio::test]
+    async fn openai_tool_only_response_omits_empty_text() {
+        let response = convert_response(
+            TargetProtocol::OpenAI,
+            ClientProtocol::Anthropic,
+            openai_response(
+                Value::Null,
+                json!([{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "get_weather", "arguments": "not json"},
+                }]),
+                "tool_calls",
+            ),
+        )
+        .await;
+        let content = response["content"].as_array().unwrap();
+        assert_eq!(content.len(), 1);
+        assert_eq!(content[0]["type"], "tool_use");
+        // 参数不是合法 JSON 时按空对象处理
+        assert_eq!(content[0]["input"], json!({}));
+    }
+
+    #[tokio::test]
+    async fn openai_text_response_keeps_single_text_block() {
+        let response = convert_response(
+            TargetProtocol::OpenAI,
+            ClientProtocol::Anthropic,
+            openai_response(json!(""), Value::Null, "stop"),
+        )
+        .await;
+        assert_eq!(response["content"], json!([{"type": "text", "text": ""}]));
+        assert_eq!(response["stop_reason"], "end_turn");
+    }
+
+    #[tokio::test]
+    async fn anthropic_response_keeps_text_and_tool_calls() {
+        let response = convert_response(
+            TargetProtocol::Anthropic,
+            ClientProtocol::OpenAI,
+            anthropic_response(
+                json!([
+                    {"type": "text", "text": "Let me "},
+                    {"type": "text", "text": "check."},
+                    {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"city": "Paris"}},
+                ]),
+                "tool_use",
+            ),
+        )
+        .await;
+        let choice = &response["choices"][0];
+        assert_eq!(choice["message"]["content"], "Let me check.");
+        let call = &choice["message"]["tool_calls"][0];
+        assert_eq!(call["id"], "toolu_1");
+        assert_eq!(call["type"], "function");
+        assert_eq!(call["function"]["name"], "get_weather");
+        let arguments: Value =
+            serde_json::from_str(call["function"]["arguments"].as_str().unwrap()).unwrap();
+        assert_eq!(arguments, json!({"city": "Paris"}));
+        assert_eq!(choice["finish_reason"], "tool_calls");
+    }
+
+    #[tokio::test]
+    async fn anthropic_tool_only_response_has_null_content() {
+        let response = convert_response(
+            TargetProtocol::Anthropic,
+            ClientProtocol::OpenAI,
+            anthropic_response(
+                json!([{"type": "tool_use", "id": "toolu_1", "name": "f", "input": {}}]),
+                "tool_use",
+            ),
+        )
+        .await;
+        let message = &response["choices"][0]["message"];
+        assert!(message["content"].is_null());
+        assert_eq!(message["tool_calls"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn anthropic_usage_counts_cache_in_prompt_tokens() {
+        let usage = anthropic::Usage {
+            input_tokens: 10,
+            output_tokens: 5,
+            cache_creation_input_tokens: Some(20),
+            cache_read_input_tokens: Some(30),
+        };
+        let converted = anthropic_usage_to_openai(&usage);
+        assert_eq!(converted.prompt_tokens, 60);
+        assert_eq!(converted.total_tokens, 65);
+        assert_eq!(
+            converted.prompt_tokens_details.unwrap().cached_tokens,
+            Some(30)
+        );
+    }
+
+    #[test]
+    fn openai_cached_tokens_split_out_of_input_tokens() {
+        let usage: openai::Usage = serde_json::from_value(json!({
+            "prompt_tokens": 100,
+            "completion_tokens": 5,
+            "total_tokens": 105,
+            "prompt_tokens_details": {"cached_tokens": 80, "audio_tokens": 0},
+        }))
+        .unwrap();
+        let converted = openai_usage_to_anthropic(&usage);
+        assert_eq!(converted.input_tokens, 20);
+        assert_eq!(converted.cache_read_input_tokens, Some(80));
+        assert_eq!(converted.cache_creation_input_tokens, None);
+
+        // cached_tokens 超过 prompt_tokens 时按 prompt_tokens 截断
+        let usage: openai::Usage = serde_json::from_value(json!({
+            "prompt_tokens": 10,
+            "completion_tokens": 0,
+            "total_tokens": 10,
+            "prompt_tokens_details": {"cached_tokens": 50},
+        }))
+        .unwrap();
+        let converted = openai_usage_to_anthropic(&usage);
+        assert_eq!(converted.input_tokens, 0);
+        assert_eq!(converted.cache_read_input_tokens, Some(10));
+    }
+
+    #[test]
+    fn merge_keeps_fields_missing_from_event() {
+        let stream_usage =
+            |value: Value| -> anthropic::StreamUsage { serde_json::from_value(value).unwrap() };
+        let mut usage = anthropic::Usage::default();
+        merge_anthropic_usage(
+            &mut usage,
+            &stream_usage(json!({"input_tokens": 10, "cache_read_input_tokens": 30})),
+        );
+        merge_anthropic_usage(&mut usage, &stream_usage(json!({"output_tokens": 7})));
+        assert_eq!(usage.input_tokens, 10);
+        assert_eq!(usage.output_tokens, 7);
+        assert_eq!(usage.cache_read_input_tokens, Some(30));
+        assert_eq!(usage.cache_creation_input_tokens, None);
+    }
+
+    #[tokio::test]
+    async fn anthropic_response_carries_cached_tokens() {
+        let mut body = anthropic_response(json!([{"type": "text", "text": "hi"}]), "end_turn");
+        body["usage"] = json!({
+            "input_tokens": 10,
+            "output_tokens": 5,
+            "cache_creation_input_tokens": 20,
+            "cache_read_input_tokens": 30,
+        });
+        let response =
+            convert_response(TargetProtocol::Anthropic, ClientProtocol::OpenAI, body).await;
+        assert_eq!(response["usage"]["prompt_tokens"], 60);
+        assert_eq!(response["usage"]["total_tokens"], 65);
+        assert_eq!(
+            response["usage"]["prompt_tokens_details"]["cached_tokens"],
+            30
+        );
+    }
+
+    #[tokio::test]
+    async fn openai_response_carries_cached_tokens() {
+        let mut body = openai_response(json!("hi"), Value::Null, "stop");
+        body["usage"] = json!({
+            "prompt_tokens": 100,
+            "completion_tokens": 5,
+            "total_tokens": 105,
+            "prompt_tokens_details": {"cached_tokens": 80},
+        });
+        let response =
+            convert_response(TargetProtocol::OpenAI, ClientProtocol::Anthropic, body).await;
+        assert_eq!(response["usage"]["input_tokens"], 20);
+        assert_eq!(response["usage"]["cache_read_input_tokens"], 80);
+        assert!(response["usage"]
+            .get("cache_creation_input_tokens")
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn anthropic_stream_usage_merges_message_start_and_delta() {
+        let events = [
+            json!({
+                "type": "message_start",
+                "message": {
+                    "id": "msg_1", "type": "message", "role": "assistant", "content": [],
+                    "model": "claude", "stop_reason": null, "stop_sequence": null,
+                    "usage": {"input_tokens": 10, "output_tokens": 1, "cache_creation_input_tokens": 20, "cache_read_input_tokens": 30},
+                },
+            }),
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "hi"}}),
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "end_turn", "stop_sequence": null}, "usage": {"output_tokens": 5}}),
+            json!({"type": "message_stop"}),
+        ];
+        let transcript: String = events
+            .iter()
+            .map(|e| format!("event: {}\ndata: {}\n\n", e["type"].as_str().unwrap(), e))
+            .collect();
+        let output = crate::protocol::testkit::transform_chunks(
+            &UniversalAdapter::new(),
+            &TargetProtocol::Anthropic,
+            &ClientProtocol::OpenAI,
+            vec![Bytes::from(transcript)],
+        )
+        .await
+        .unwrap();
+        let usage = crate::protocol::testkit::parse_sse(&output)
+            .iter()
+            .filter_map(|event| event.json())
+            .find_map(|chunk| chunk.get("usage").filter(|u| !u.is_null()).cloned())
+            .unwrap();
+        assert_eq!(usage["prompt_tokens"], 60);
+        assert_eq!(usage["completion_tokens"], 5);
+        assert_eq!(usage["total_tokens"], 65);
+        assert_eq!(usage["prompt_tokens_details"]["cached_tokens"], 30);
+    }
+}
@@ -0,0 +1,336 @@
+//! Claude Code / Codex CLI 客户端兼容处理
+//!
+//! 这两个命令行客户端是网关最常见的真实客户端，带有各自的请求头和请求体约定：
+//! Claude Code 发送 `anthropic-beta`、文本块数组形式的 `system`、带签名的交错思考块和
+//! 包含账号会话标识的 `metadata.user_id`；Codex CLI 发送 ChatGPT 账号和会话请求头，
+//! 并以 `developer` 角色携带指令。按请求头识别客户端后，每次尝试按目标路由调整请求，
+//! 使请求在协议转换和第三方上游中保持可用。
+//!
+//! 响应方向上，OpenAI 上游的推理内容由 [`AnthropicEventWriter`] 合成为带签名的思考块，
+//! 满足 Claude Code 对交错思考事件的要求；这些思考块回传时只能发往非 Anthropic 上游，
+//! 发往 Anthropic 上游前按合成签名去掉。
+//!
+//! [`AnthropicEventWriter`]: crate::protocol::anthropic_stream::AnthropicEventWriter
+
+use crate::config::{AgentClientsConfig, UserIdPolicy};
+use crate::models::{ClientProtocol, RouteConfig, TargetProtocol};
+use crate::protocol::anthropic_stream::SYNTHETIC_SIGNATURE;
+use crate::protocol::responses::Bridge;
+use bytes::Bytes;
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+/// 需要兼容处理的命令行客户端
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgentClient {
+    ClaudeCode,
+    Codex,
+}
+
+impl AgentClient {
+    /// 按请求头识别客户端
+    pub fn detect(headers: &HeaderMap) -> Option<Self> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+        };
+        let user_agent = header("user-agent");
+        if user_agent.starts_with("claude-cli/") || header("x-app") == "cli" {
+            Some(Self::ClaudeCode)
+        } else if header("originator").starts_with("codex") || user_agent.starts_with("codex") {
+            Some(Self::Codex)
+        } else {
+            None
+        }
+    }
+}
+
+/// 转发到非 Anthropic 上游时去掉的 Claude Code 请求头
+const ANTHROPIC_HEADERS: [&str; 2] = ["anthropic-beta", "anthropic-version"];
+
+/// 不与上游兼容的历史消息内容块
+const THINKING_BLOCKS: [&str; 2] = ["thinking", "redacted_thinking"];
+
+/// 按配置对识别出的客户端做兼容处理
+pub struct AgentCompat {
+    config: AgentClientsConfig,
+}
+
+impl AgentCompat {
+    pub fn from_config(config: &AgentClientsConfig) -> Self {
+        let mut config = config.clone();
+        for headers in [
+            &mut config.claude_code.strip_headers,
+            &mut config.codex.strip_headers,
+        ] {
+            headers.iter_mut().for_each(|h| h.make_ascii_lowercase());
+        }
+        Self { config }
+    }
+
+    /// 路由是否启用兼容处理，未在路由上指定时使用全局配置
+    pub fn enabled_for(&self, route_config: &RouteConfig) -> bool {
+        route_config.agent_compat.unwrap_or(self.config.enabled)
+    }
+
+    /// 调整转发上游的客户端请求头
+    pub fn apply_headers(
+        &self,
+        client: AgentClient,
+        target_protocol: &TargetProtocol,
+        headers: &mut HeaderMap,
+    ) {
+        match client {
+            AgentClient::ClaudeCode => {
+                let config = &self.config.claude_code;
+                for header in &config.strip_headers {
+                    headers.remove(header.as_str());
+                }
+                if !matches!(target_protocol, TargetProtocol::Anthropic) {
+                    for header in ANTHROPIC_HEADERS {
+                        headers.remove(header);
+                    }
+                } else if let Some(allowed) = &config.betas {
+                    filter_betas(headers, allowed);
+                }
+            }
+            AgentClient::Codex => {
+                for header in &self.config.codex.strip_headers {
+                    headers.remove(header.as_str());
+                }
+            }
+        }
+    }
+
+    /// 调整客户端请求体，请求体不是 JSON 对象或无需调整时原样返回
+    pub fn apply_request(
+        &self,
+        client: AgentClient,
+        client_protocol: &ClientProtocol,
+        target_protocol: &TargetProtocol,
+        bridge: Option<Bridge>,
+        body: Bytes,
+    ) -> Bytes {
+        let rewrites = match client {
+            AgentClient::ClaudeCode => matches!(client_protocol, ClientProtocol::Anthropic),
+            AgentClient::Codex => bridge == Some(Bridge::ToChat),
+        };
+        if !rewrites {
+            return body;
+        }
+        let Ok(Value::Object(mut obj)) = serde_json::from_slice::<Value>(&body) else {
+            return body;
+        };
+
+        let changed = match client {
+            AgentClient::ClaudeCode => {
+                let mut changed = self.apply_user_id(&mut obj);
+                if matches!(target_protocol, TargetProtocol::Anthropic) {
+                    // 网关合成的签名无法通过 Anthropic 校验
+                    changed |= drop_thinking_blocks(&mut obj, |block| {
+                        block["signature"].as_str() == Some(SYNTHETIC_SIGNATURE)
+                    });
+                } else {
+                    changed |= flatten_system(&mut obj);
+                    changed |= drop_thinking_blocks(&mut obj, |_| true);
+                }
+                changed
+            }
+            AgentClient::Codex => developer_to_system(&mut obj),
+        };
+        if !changed {
+            return body;
+        }
+        serde_json::to_vec(&obj).map(Bytes::from).unwrap_or(body)
+    }
+
+    // 按配置处理 metadata.user_id
+    fn apply_user_id(&self, obj: &mut Map<String, Value>) -> bool {
+        let Some(metadata) = obj.get_mut("metadata").and_then(Value::as_object_mut) else {
+            return false;
+        };
+        match self.config.claude_code.user_id {
+            UserIdPolicy::Keep => false,
+            UserIdPolicy::Drop => metadata.remove("user_id").is_some(),
+            UserIdPolicy::Hash => match metadata.get_mut("user_id") {
+                Some(Value::String(user_id)) => {
+                    *user_id = hex::encode(Sha256::digest(user_id.as_bytes()));
+                    true
+                }
+                _ => false,
+            },
+        }
+    }
+}
+
+// 只保留允许的 beta，全部被去掉时删除请求头
+fn filter_betas(headers: &mut HeaderMap, allowed: &[String]) {
+    let Some(betas) = headers.get("anthropic-beta").and_then(|v| v.to_str().ok()) else {
+        return;
+    };
+    let kept: Vec<&str> = betas
+        .split(',')
+        .map(str::trim)
+        .filter(|beta| allowed.iter().any(|a| a == beta))
+        .collect();
+    match HeaderValue::from_str(&kept.join(",")) {
+        Ok(value) if !kept.is_empty() => {
+            headers.insert("anthropic-beta", value);
+        }
+        _ => {
+            headers.remove("anthropic-beta");
+        }
+    }
+}
+
+// 文本块数组形式的 system 合并为字符串（缓存控制等块属性随之丢弃）
+fn flatten_system(obj: &mut Map<String, Value>) -> bool {
+    let Some(Value::Array(blocks)) = obj.get("system") else {
+        return false;
+    };
+    let text = blocks
+        .iter()
+        .filter_map(|block| block.get("text").and_then(Value::as_str))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    obj.insert("system".to_string(), Value::String(text));
+    true
+}
+
+// 去掉历史消息中符合条件的思考块，只剩思考块的消息一并去掉
+fn drop_thinking_blocks(obj: &mut Map<String, Value>, drop: impl Fn(&Value) -> bool) -> bool {
+    let Some(Value::Array(messages)) = obj.get_mut("messages") else {
+        return false;
+    };
+    let mut changed = false;
+    messages.retain_mut(|message| {
+        let Some(Value::Array(blocks)) = message.get_mut("content") else {
+            return true;
+        };
+        let before = blocks.len();
+        blocks.retain(|block| {
+            !(block["type"]
+                .as_str()
+                .is_some_and(|t| THINKING_BLOCKS.contains(&t))
+                && drop(block))
+        });
+        if blocks.len() == before {
+            return true;
+        }
+        changed = true;
+        !blocks.is_empty()
+    });
+    changed
+}
+
+// Responses API 输入中 developer 角色的消息改为 system
+fn developer_to_system(obj: &mut Map<String, Value>) -> bool {
+    let Some(Value::Array(items)) = obj.get_mut("input") else {
+        return false;
+    };
+    let mut changed = false;
+    for item in items {
+        if item.get("role").and_then(Value::as_str) == Some("developer") {
+            item["role"] = Value::String("system".to_string());
+            changed = true;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn claude_code_request(target: TargetProtocol, body: Value) -> Value {
+        let compat = AgentCompat::from_config(&AgentClientsConfig::default());
+        let body = compat.apply_request(
+            AgentClient::ClaudeCode,
+            &ClientProtocol::Anthropic,
+            &target,
+            None,
+            Bytes::from(body.to_string()),
+        );
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn history(signature: &str) -> Value {
+        json!({
+            "model": "claude",
+            "system": [{"type": "text", "text": "a"}, {"type": "text", "text": "b"}],
+            "messages": [
+                {"role": "user", "content": "hi"},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "hmm", "signature": signature},
+                    {"type": "tool_use", "id": "t1", "name": "f", "input": {}}
+                ]},
+                {"role": "assistant", "content": [
+                    {"type": "thinking", "thinking": "done", "signature": signature}
+                ]}
+            ]
+        })
+    }
+
+    #[test]
+    fn detects_clients_by_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("user-agent", HeaderValue::from_static("claude-cli/1.0.0"));
+        assert_eq!(AgentClient::detect(&headers), Some(AgentClient::ClaudeCode));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("originator", HeaderValue::from_static("codex_cli_rs"));
+        assert_eq!(AgentClient::detect(&headers), Some(AgentClient::Codex));
+        assert_eq!(AgentClient::detect(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn drops_thinking_and_flattens_system_for_openai_targets() {
+        let body = claude_code_request(TargetProtocol::OpenAI, history("sig"));
+        assert_eq!(body["system"], "a\n\nb");
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[1]["content"],
+            json!([{"type": "tool_use", "id": "t1", "name": "f", "input": {}}])
+        );
+    }
+
+    #[test]
+    fn drops_only_synthetic_thinking_for_anthropic_targets() {
+        let body = claude_code_request(TargetProtocol::Anthropic, history(SYNTHETIC_SIGNATURE));
+        assert!(body["system"].is_array());
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1]["content"][0]["type"], "tool_use");
+
+        let body = claude_code_request(TargetProtocol::Anthropic, history("real-signature"));
+        assert_eq!(body["messages"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            body["messages"][1]["content"][0]["signature"],
+            "real-signature"
+        );
+    }
+
+    #[test]
+    fn filters_betas() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "anthropic-beta",
+            HeaderValue::from_static(
+                "interleaved-thinking-2025-05-14, fine-grained-tool-streaming-2025-05-14",
+            ),
+        );
+        filter_betas(
+            &mut headers,
+            &["interleaved-thinking-2025-05-14".to_string()],
+        );
+        assert_eq!(headers["anthropic-beta"], "interleaved-thinking-2025-05-14");
+
+        filter_betas(&mut headers, &[]);
+        assert!(headers.get("anthropic-beta").is_none());
+    }
+}
@@ -16,10 +16,22 @@ pub struct AnthropicRequest {
     pub stream: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub system: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<String>,
     #[serde(flatten)]
     pub extra: Value,
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: String,
@@ -40,6 +52,27 @@ pub enum ContentBlock {
     Text { text: String },
     #[serde(rename = "image")]
     Image { source: ImageSource },
+    #[serde(rename = "tool_use")]
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    #[serde(rename = "tool_result")]
+    ToolResult {
+        tool_use_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        content: Option<ToolResultContent>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        is_error: Option<bool>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ToolResultContent {
+    Text(String),
+    Array(Vec<ContentBlock>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -63,31 +96,222 @@ pub struct AnthropicResponse {
     pub usage: Usage,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// `input_tokens` 不含缓存读写的Token
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Usage {
     pub input_tokens: i32,
     pub output_tokens: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<i32>,
 }
 
+/// Messages 流式事件（SSE `data:` 的 JSON，按 `type` 区分）
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct AnthropicStreamEvent {
-    #[serde(rename = "type")]
-    pub event_type: String,
-    #[serde(flatten)]
-    pub data: Value,
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum AnthropicStreamEvent {
+    MessageStart {
+        #[serde(default)]
+        message: StreamMessage,
+    },
+    ContentBlockStart {
+        #[serde(default)]
+        index: u32,
+        #[serde(default)]
+        content_block: Value,
+    },
+    ContentBlockDelta {
+        #[serde(default)]
+        index: u32,
+        delta: ContentDelta,
+    },
+    ContentBlockStop {
+        #[serde(default)]
+        index: u32,
+    },
+    MessageDelta {
+        #[serde(default)]
+        delta: MessageDelta,
+        /// 累积的输出用量，带有输入用量时以其为准
+        #[serde(default, deserialize_with = "crate::protocol::lenient")]
+        usage: Option<StreamUsage>,
+    },
+    MessageStop,
+    Ping,
+    Error {
+        #[serde(default)]
+        error: StreamError,
+    },
+    /// 尚未支持的事件类型
+    #[serde(other)]
+    Unknown,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ContentBlockDelta {
-    #[serde(rename = "type")]
-    pub delta_type: String,
-    pub index: i32,
-    pub delta: Delta,
+/// `message_start` 中的消息元数据
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct StreamMessage {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// 输入用量（含缓存读写）
+    #[serde(default, deserialize_with = "crate::protocol::lenient")]
+    pub usage: Option<StreamUsage>,
 }
 
+/// 内容块增量
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Delta {
-    #[serde(rename = "type")]
-    pub delta_type: String,
-    pub text: String,
-}
\ No newline at end of file
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ContentDelta {
+    TextDelta {
+        text: String,
+    },
+    InputJsonDelta {
+        partial_json: String,
+    },
+    ThinkingDelta {
+        thinking: String,
+    },
+    SignatureDelta {
+        signature: String,
+    },
+    CitationsDelta {
+        citation: Value,
+    },
+    /// 尚未支持的增量类型
+    #[serde(other)]
+    Unknown,
+}
+
+/// `message_delta` 中的结束原因
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MessageDelta {
+    #[serde(default)]
+    pub stop_reason: Option<String>,
+    #[serde(default)]
+    pub stop_sequence: Option<String>,
+}
+
+/// 流式事件中的用量，事件未携带的项为空
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct StreamUsage {
+    #[serde(default)]
+    pub input_tokens: Option<i64>,
+    #[serde(default)]
+    pub output_tokens: Option<i64>,
+    #[serde(default)]
+    pub cache_creation_input_tokens: Option<i64>,
+    #[serde(default)]
+    pub cache_read_input_tokens: Option<i64>,
+}
+
+/// 流式错误
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StreamError {
+    #[serde(default, rename = "type")]
+    pub error_type: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(value: Value) -> AnthropicStreamEvent {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn message_start_parses_usage_with_cache_tokens() {
+        let event = parse(json!({"type": "message_start", "message": {
+            "id": "msg_1", "model": "claude", "content": [],
+            "usage": {"input_tokens": 10, "output_tokens": 1, "cache_read_input_tokens": 30},
+        }}));
+        let AnthropicStreamEvent::MessageStart { message } = event else {
+            panic!("unexpected event {:?}", event);
+        };
+        assert_eq!(message.id.as_deref(), Some("msg_1"));
+        let usage = message.usage.unwrap();
+        assert_eq!(usage.input_tokens, Some(10));
+        assert_eq!(usage.cache_read_input_tokens, Some(30));
+        assert_eq!(usage.cache_creation_input_tokens, None);
+    }
+
+    #[test]
+    fn content_deltas_parse_by_type() {
+        let delta = |delta: Value| match parse(
+            json!({"type": "content_block_delta", "index": 2, "delta": delta}),
+        ) {
+            AnthropicStreamEvent::ContentBlockDelta { index, delta } => {
+                assert_eq!(index, 2);
+                delta
+            }
+            other => panic!("unexpected event {:?}", other),
+        };
+        assert!(matches!(
+            delta(json!({"type": "text_delta", "text": "Hi"})),
+            ContentDelta::TextDelta { text } if text == "Hi"
+        ));
+        assert!(matches!(
+            delta(json!({"type": "input_json_delta", "partial_json": "{\"a\""})),
+            ContentDelta::InputJsonDelta { partial_json } if partial_json == "{\"a\""
+        ));
+        assert!(matches!(
+            delta(json!({"type": "signature_delta", "signature": "sig"})),
+            ContentDelta::SignatureDelta { .. }
+        ));
+        assert!(matches!(
+            delta(json!({"type": "future_delta", "payload": 1})),
+            ContentDelta::Unknown
+        ));
+    }
+
+    #[test]
+    fn unknown_events_parse_as_unknown() {
+        assert!(matches!(
+            parse(json!({"type": "future_event", "data": {}})),
+            AnthropicStreamEvent::Unknown
+        ));
+        assert!(matches!(
+            parse(json!({"type": "ping"})),
+            AnthropicStreamEvent::Ping
+        ));
+    }
+
+    #[test]
+    fn message_delta_tolerates_malformed_usage() {
+        let event = parse(json!({"type": "message_delta",
+            "delta": {"stop_reason": "max_tokens"},
+            "usage": {"output_tokens": "many"}}));
+        let AnthropicStreamEvent::MessageDelta { delta, usage } = event else {
+            panic!("unexpected event {:?}", event);
+        };
+        assert_eq!(delta.stop_reason.as_deref(), Some("max_tokens"));
+        assert!(usage.is_none());
+
+        let event = parse(json!({"type": "message_delta", "usage": {"output_tokens": 7}}));
+        let AnthropicStreamEvent::MessageDelta { delta, usage } = event else {
+            panic!("unexpected event {:?}", event);
+        };
+        assert_eq!(delta.stop_reason, None);
+        assert_eq!(usage.unwrap().output_tokens, Some(7));
+    }
+
+    #[test]
+    fn error_event_parses_type_and_message() {
+        let event = parse(
+            json!({"type": "error", "error": {"type": "overloaded_error", "message": "Overloaded"}}),
+        );
+        let AnthropicStreamEvent::Error { error } = event else {
+            panic!("unexpected event {:?}", event);
+        };
+        assert_eq!(error.error_type.as_deref(), Some("overloaded_error"));
+        assert_eq!(error.message.as_deref(), Some("Overloaded"));
+    }
+}
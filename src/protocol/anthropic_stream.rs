@@ -0,0 +1,697 @@
+//! 由 OpenAI 流式响应合成 Anthropic 事件流
+//!
+//! 按 Anthropic Messages 流式规范生成完整的事件序列：
+//! `message_start` → `ping` → 依次编号的内容块（`content_block_start` / `_delta` / `_stop`）
+//! → `message_delta` → `message_stop`。推理内容、文本、工具调用各自成块，块序号从0递增；
+//! OpenAI 的 `url_citation` 标注转换为 `citations_delta`；上游报错时输出 `error` 事件并结束。
+//!
+//! 推理内容（`reasoning_content`）转换为思考块，与工具调用交错时各自成块（交错思考）。
+//! Claude Code 等客户端要求思考块带有签名并在后续轮次原样回传，OpenAI 上游没有签名，
+//! 思考块关闭前以 `signature_delta` 补上 [`SYNTHETIC_SIGNATURE`]。
+//!
+//! Anthropic 内容块关闭后不能重新打开，而 OpenAI 的并行工具调用片段可能交错到达：
+//! 一个工具调用的块打开期间，其余工具调用的片段先缓存，流结束时按序号各自输出完整的块。
+
+use crate::protocol::openai::{Annotation, OpenAIStreamChunk, ToolCallDelta};
+use crate::protocol::stop_reason;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use tracing::warn;
+
+/// 合成思考块的签名，回传给 Anthropic 上游前需要去掉带有该签名的思考块
+pub const SYNTHETIC_SIGNATURE: &str = "axongate-synthetic";
+
+/// 当前打开的内容块
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    Thinking,
+    Text,
+    /// OpenAI `tool_calls` 中的序号
+    ToolUse(u64),
+}
+
+/// 缓存的工具调用
+#[derive(Debug)]
+struct HeldCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+/// 将 OpenAI chunk 逐个转换为 Anthropic SSE 事件
+pub struct AnthropicEventWriter {
+    message_started: bool,
+    finished: bool,
+    // 下一个内容块的序号
+    next_index: usize,
+    open_block: Option<(usize, BlockKind)>,
+    // 已打开过内容块的工具调用序号
+    tool_calls: HashSet<u64>,
+    // 其他工具调用的块打开期间到达的工具调用，按序号缓存到流结束
+    held_calls: BTreeMap<u64, HeldCall>,
+    // 已输出的全部文本（按字符），用于截取引用原文
+    text: Vec<char>,
+    stop_reason: &'static str,
+    // OpenAI 的 prompt_tokens，包含命中缓存的部分
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+    cached_tokens: Option<u64>,
+}
+
+impl Default for AnthropicEventWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnthropicEventWriter {
+    pub fn new() -> Self {
+        Self {
+            message_started: false,
+            finished: false,
+            next_index: 0,
+            open_block: None,
+            tool_calls: HashSet::new(),
+            held_calls: BTreeMap::new(),
+            text: Vec::new(),
+            stop_reason: "end_turn",
+            input_tokens: None,
+            output_tokens: None,
+            cached_tokens: None,
+        }
+    }
+
+    /// 事件流是否已结束（已输出 `message_stop` 或 `error`）
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// 处理一个 OpenAI SSE `data:` 值，返回生成的 Anthropic SSE 文本
+    pub fn push_data(&mut self, data: &str) -> String {
+        if self.finished {
+            return String::new();
+        }
+        if data == "[DONE]" {
+            return self.finish();
+        }
+        let Ok(chunk) = serde_json::from_str::<OpenAIStreamChunk>(data) else {
+            return String::new();
+        };
+
+        let mut out = String::new();
+        if let Some(error) = &chunk.error {
+            let message = error.message.as_deref().unwrap_or("Upstream error");
+            let code = error.code.as_ref().and_then(Value::as_str);
+            let kind = error.error_type.as_deref().or(code).unwrap_or_default();
+            out.push_str(&self.error(anthropic_error_type(kind), message));
+            return out;
+        }
+
+        if let Some(usage) = &chunk.usage {
+            let as_u64 = |n: Option<i64>| n.and_then(|n| u64::try_from(n).ok());
+            self.input_tokens = as_u64(usage.prompt_tokens).or(self.input_tokens);
+            self.output_tokens = as_u64(usage.completion_tokens).or(self.output_tokens);
+            self.cached_tokens = as_u64(
+                usage
+                    .prompt_tokens_details
+                    .as_ref()
+                    .and_then(|details| details.cached_tokens),
+            )
+            .or(self.cached_tokens);
+        }
+        if !self.message_started {
+            out.push_str(&self.message_start(&chunk));
+        }
+
+        let Some(choice) = chunk.choices.first() else {
+            return out;
+        };
+        if let Some(reason) = &choice.finish_reason {
+            self.stop_reason = stop_reason::openai_to_anthropic(reason);
+        }
+
+        let delta = &choice.delta;
+        if let Some(reasoning) = delta.reasoning_content.as_deref().filter(|r| !r.is_empty()) {
+            out.push_str(&self.thinking_delta(reasoning));
+        }
+        if let Some(content) = delta.content.as_deref().filter(|c| !c.is_empty()) {
+            out.push_str(&self.text_delta(content));
+        }
+        for annotation in &delta.annotations {
+            out.push_str(&self.citation_delta(annotation));
+        }
+        for call in &delta.tool_calls {
+            out.push_str(&self.tool_call_delta(call));
+        }
+        out
+    }
+
+    /// 上游流结束：补齐未输出的结束事件
+    ///
+    /// 上游未发送任何数据时不输出事件
+    pub fn finish(&mut self) -> String {
+        if self.finished || !self.message_started {
+            self.finished = true;
+            return String::new();
+        }
+        self.finished = true;
+
+        let mut out = self.close_block();
+        out.push_str(&self.flush_held_calls());
+        let mut usage = json!({ "output_tokens": self.output_tokens.unwrap_or(0) });
+        if let Some(input_tokens) = self.input_tokens {
+            usage["input_tokens"] = json!(self.uncached_input_tokens(input_tokens));
+        }
+        if let Some(cached_tokens) = self.cached_tokens.filter(|n| *n > 0) {
+            usage["cache_read_input_tokens"] = json!(cached_tokens);
+        }
+        out.push_str(&sse(
+            "message_delta",
+            &json!({
+                "type": "message_delta",
+                "delta": {"stop_reason": self.stop_reason, "stop_sequence": null},
+                "usage": usage,
+            }),
+        ));
+        out.push_str(&sse("message_stop", &json!({"type": "message_stop"})));
+        out
+    }
+
+    /// 上游流异常中断：输出 `error` 事件并结束
+    pub fn fail(&mut self, message: &str) -> String {
+        if self.finished {
+            return String::new();
+        }
+        self.error("api_error", message)
+    }
+
+    fn error(&mut self, error_type: &str, message: &str) -> String {
+        self.finished = true;
+        sse(
+            "error",
+            &json!({
+                "type": "error",
+                "error": {"type": error_type, "message": message},
+            }),
+        )
+    }
+
+    // Anthropic 的 input_tokens 不含命中缓存的部分
+    fn uncached_input_tokens(&self, prompt_tokens: u64) -> u64 {
+        prompt_tokens.saturating_sub(self.cached_tokens.unwrap_or(0))
+    }
+
+    fn message_start(&mut self, chunk: &OpenAIStreamChunk) -> String {
+        self.message_started = true;
+        let message = json!({
+            "type": "message_start",
+            "message": {
+                "id": chunk.id.as_deref().unwrap_or("msg_unknown"),
+                "type": "message",
+                "role": "assistant",
+                "content": [],
+                "model": chunk.model.as_deref().unwrap_or("unknown"),
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": {
+                    "input_tokens": self.uncached_input_tokens(self.input_tokens.unwrap_or(0)),
+                    "output_tokens": 0,
+                },
+            }
+        });
+        let mut out = sse("message_start", &message);
+        out.push_str(&sse("ping", &json!({"type": "ping"})));
+        out
+    }
+
+    fn thinking_delta(&mut self, reasoning: &str) -> String {
+        let mut out = self.ensure_block(
+            BlockKind::Thinking,
+            || json!({"type": "thinking", "thinking": "", "signature": ""}),
+        );
+        out.push_str(&self.block_delta(json!({"type": "thinking_delta", "thinking": reasoning})));
+        out
+    }
+
+    fn text_delta(&mut self, content: &str) -> String {
+        let mut out = self.ensure_block(BlockKind::Text, || json!({"type": "text", "text": ""}));
+        self.text.extend(content.chars());
+        out.push_str(&self.block_delta(json!({"type": "text_delta", "text": content})));
+        out
+    }
+
+    // OpenAI 标注：{"type":"url_citation","url_citation":{"url","title","start_index","end_index"}}
+    fn citation_delta(&mut self, annotation: &Annotation) -> String {
+        let Annotation::UrlCitation {
+            url_citation: source,
+        } = annotation
+        else {
+            return String::new();
+        };
+        let Some(url) = source.url.as_deref() else {
+            return String::new();
+        };
+        let cited_text: String = self
+            .text
+            .get(source.start_index..source.end_index.min(self.text.len()))
+            .unwrap_or_default()
+            .iter()
+            .collect();
+
+        let mut out = self.ensure_block(BlockKind::Text, || json!({"type": "text", "text": ""}));
+        out.push_str(&self.block_delta(json!({
+            "type": "citations_delta",
+            "citation": {
+                "type": "web_search_result_location",
+                "url": url,
+                "title": source.title,
+                "cited_text": cited_text,
+                "encrypted_index": "",
+            }
+        })));
+        out
+    }
+
+    // OpenAI 工具调用片段：首个片段带 id 和函数名，后续片段只有参数增量
+    fn tool_call_delta(&mut self, call: &ToolCallDelta) -> String {
+        let call_index = call.index;
+        let kind = BlockKind::ToolUse(call_index);
+        let function = call.function.as_ref();
+        let arguments = function
+            .and_then(|function| function.arguments.as_deref())
+            .unwrap_or_default();
+
+        let mut out = String::new();
+        if self.open_block.map(|(_, k)| k) != Some(kind) {
+            let tool_open = matches!(self.open_block, Some((_, BlockKind::ToolUse(_))));
+            if let Some(held) = self.held_calls.get_mut(&call_index) {
+                held.arguments.push_str(arguments);
+                return out;
+            }
+            if self.tool_calls.contains(&call_index) {
+                // 工具调用的块已被文本块关闭，之后的片段无法表示
+                warn!(
+                    "Dropping tool call fragment for closed index {}",
+                    call_index
+                );
+                return out;
+            }
+            let id = call
+                .id
+                .clone()
+                .unwrap_or_else(|| format!("call_{}", call_index));
+            let name = function
+                .and_then(|function| function.name.clone())
+                .unwrap_or_default();
+            if tool_open {
+                // 不关闭正在输出的工具调用，避免其后续片段无处输出
+                self.held_calls.insert(
+                    call_index,
+                    HeldCall {
+                        id,
+                        name,
+                        arguments: arguments.to_string(),
+                    },
+                );
+                return out;
+            }
+            out.push_str(&self.ensure_block(
+                kind,
+                || json!({"type": "tool_use", "id": id, "name": name, "input": {}}),
+            ));
+            self.tool_calls.insert(call_index);
+        }
+
+        if !arguments.is_empty() {
+            out.push_str(&self.block_delta(json!({
+                "type": "input_json_delta",
+                "partial_json": arguments,
+            })));
+        }
+        out
+    }
+
+    // 按序号输出缓存的工具调用，每个调用一个完整的块
+    fn flush_held_calls(&mut self) -> String {
+        let mut out = String::new();
+        for (call_index, call) in std::mem::take(&mut self.held_calls) {
+            let HeldCall {
+                id,
+                name,
+                arguments,
+            } = call;
+            out.push_str(&self.ensure_block(
+                BlockKind::ToolUse(call_index),
+                || json!({"type": "tool_use", "id": id, "name": name, "input": {}}),
+            ));
+            self.tool_calls.insert(call_index);
+            if !arguments.is_empty() {
+                out.push_str(&self.block_delta(json!({
+                    "type": "input_json_delta",
+                    "partial_json": arguments,
+                })));
+            }
+            out.push_str(&self.close_block());
+        }
+        out
+    }
+
+    // 确保当前打开的是指定类型的内容块，否则关闭当前块并打开新块
+    fn ensure_block(&mut self, kind: BlockKind, content_block: impl FnOnce() -> Value) -> String {
+        if self.open_block.map(|(_, k)| k) == Some(kind) {
+            return String::new();
+        }
+        let mut out = self.close_block();
+        let index = self.next_index;
+        self.next_index += 1;
+        self.open_block = Some((index, kind));
+        out.push_str(&sse(
+            "content_block_start",
+            &json!({
+                "type": "content_block_start",
+                "index": index,
+                "content_block": content_block(),
+            }),
+        ));
+        out
+    }
+
+    fn close_block(&mut self) -> String {
+        let mut out = String::new();
+        if let Some((_, BlockKind::Thinking)) = self.open_block {
+            out.push_str(&self.block_delta(json!({
+                "type": "signature_delta",
+                "signature": SYNTHETIC_SIGNATURE,
+            })));
+        }
+        if let Some((index, _)) = self.open_block.take() {
+            out.push_str(&sse(
+                "content_block_stop",
+                &json!({"type": "content_block_stop", "index": index}),
+            ));
+        }
+        out
+    }
+
+    fn block_delta(&self, delta: Value) -> String {
+        let index = self.open_block.map_or(0, |(index, _)| index);
+        sse(
+            "content_block_delta",
+            &json!({"type": "content_block_delta", "index": index, "delta": delta}),
+        )
+    }
+}
+
+// OpenAI 错误类型 / 错误码 -> Anthropic 错误类型
+fn anthropic_error_type(kind: &str) -> &'static str {
+    match kind {
+        "invalid_request_error" => "invalid_request_error",
+        "authentication_error" | "invalid_api_key" => "authentication_error",
+        "permission_error" | "insufficient_quota" => "permission_error",
+        "not_found_error" | "model_not_found" => "not_found_error",
+        "rate_limit_error" | "rate_limit_exceeded" | "tokens" | "requests" => "rate_limit_error",
+        "overloaded_error" | "server_overloaded" => "overloaded_error",
+        _ => "api_error",
+    }
+}
+
+fn sse(event: &str, data: &Value) -> String {
+    format!("event: {}\ndata: {}\n\n", event, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::testkit::{accumulate_anthropic_message, event_types, parse_sse};
+
+    fn chunk(delta: Value) -> String {
+        json!({
+            "id": "chatcmpl-1",
+            "model": "gpt-4o",
+            "choices": [{"index": 0, "delta": delta, "finish_reason": null}],
+        })
+        .to_string()
+    }
+
+    fn finish_chunk(reason: &str) -> String {
+        json!({
+            "id": "chatcmpl-1",
+            "model": "gpt-4o",
+            "choices": [{"index": 0, "delta": {}, "finish_reason": reason}],
+        })
+        .to_string()
+    }
+
+    fn tool_call(index: u64, id: Option<&str>, name: Option<&str>, arguments: &str) -> String {
+        let mut call = json!({"index": index, "function": {"arguments": arguments}});
+        if let Some(id) = id {
+            call["id"] = json!(id);
+            call["type"] = json!("function");
+        }
+        if let Some(name) = name {
+            call["function"]["name"] = json!(name);
+        }
+        chunk(json!({"tool_calls": [call]}))
+    }
+
+    // 依次送入 OpenAI data 值，返回合成的 Anthropic SSE 文本
+    fn synthesize(data: &[String]) -> String {
+        let mut writer = AnthropicEventWriter::new();
+        data.iter().map(|d| writer.push_data(d)).collect()
+    }
+
+    fn accumulate(data: &[String]) -> std::result::Result<Value, String> {
+        accumulate_anthropic_message(&parse_sse(synthesize(data).as_bytes()))
+    }
+
+    #[test]
+    fn starts_with_message_start_and_ping() {
+        let sse = synthesize(&[
+            chunk(json!({"role": "assistant", "content": "Hi"})),
+            finish_chunk("stop"),
+            "[DONE]".to_string(),
+        ]);
+        let types = event_types(&parse_sse(sse.as_bytes()));
+        assert_eq!(
+            types,
+            [
+                "message_start",
+                "ping",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+    }
+
+    #[test]
+    fn text_accumulates_like_the_sdk() {
+        let message = accumulate(&[
+            chunk(json!({"role": "assistant", "content": ""})),
+            chunk(json!({"content": "Hello"})),
+            chunk(json!({"content": " world"})),
+            finish_chunk("stop"),
+            json!({
+                "id": "chatcmpl-1",
+                "choices": [],
+                "usage": {
+                    "prompt_tokens": 10,
+                    "completion_tokens": 2,
+                    "prompt_tokens_details": {"cached_tokens": 4},
+                },
+            })
+            .to_string(),
+            "[DONE]".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(
+            message["content"],
+            json!([{"type": "text", "text": "Hello world"}])
+        );
+        assert_eq!(message["stop_reason"], "end_turn");
+        assert_eq!(message["usage"]["input_tokens"], 6);
+        assert_eq!(message["usage"]["output_tokens"], 2);
+    }
+
+    #[test]
+    fn blocks_after_text_use_increasing_indexes() {
+        let message = accumulate(&[
+            chunk(json!({"content": "Checking."})),
+            tool_call(0, Some("call_a"), Some("get_weather"), ""),
+            tool_call(0, None, None, "{\"city\":"),
+            tool_call(0, None, None, "\"Paris\"}"),
+            finish_chunk("tool_calls"),
+            "[DONE]".to_string(),
+        ])
+        .unwrap();
+        let content = message["content"].as_array().unwrap();
+        assert_eq!(content.len(), 2);
+        assert_eq!(content[1]["type"], "tool_use");
+        assert_eq!(content[1]["id"], "call_a");
+        assert_eq!(content[1]["input"], json!({"city": "Paris"}));
+        assert_eq!(message["stop_reason"], "tool_use");
+    }
+
+    #[test]
+    fn interleaved_tool_calls_keep_all_arguments() {
+        let message = accumulate(&[
+            tool_call(0, Some("call_a"), Some("get_weather"), ""),
+            tool_call(1, Some("call_b"), Some("get_time"), "{\"tz\":"),
+            tool_call(0, None, None, "{\"city\":"),
+            tool_call(1, None, None, "\"UTC\"}"),
+            tool_call(0, None, None, "\"Paris\"}"),
+            finish_chunk("tool_calls"),
+            "[DONE]".to_string(),
+        ])
+        .unwrap();
+        let content = message["content"].as_array().unwrap();
+        assert_eq!(content.len(), 2);
+        assert_eq!(content[0]["name"], "get_weather");
+        assert_eq!(content[0]["input"], json!({"city": "Paris"}));
+        assert_eq!(content[1]["name"], "get_time");
+        assert_eq!(content[1]["input"], json!({"tz": "UTC"}));
+    }
+
+    #[test]
+    fn url_citations_become_citations_deltas() {
+        let message = accumulate(&[
+            chunk(json!({"content": "The sky is blue."})),
+            chunk(json!({"annotations": [{
+                "type": "url_citation",
+                "url_citation": {
+                    "url": "https://example.com/sky",
+                    "title": "Sky",
+                    "start_index": 4,
+                    "end_index": 7,
+                },
+            }]})),
+            finish_chunk("stop"),
+            "[DONE]".to_string(),
+        ])
+        .unwrap();
+        let citations = &message["content"][0]["citations"];
+        assert_eq!(citations[0]["type"], "web_search_result_location");
+        assert_eq!(citations[0]["url"], "https://example.com/sky");
+        assert_eq!(citations[0]["cited_text"], "sky");
+    }
+
+    #[test]
+    fn upstream_error_terminates_stream() {
+        let mut writer = AnthropicEventWriter::new();
+        let mut sse = writer.push_data(&chunk(json!({"content": "partial"})));
+        sse.push_str(&writer.push_data(
+            &json!({"error": {"message": "overloaded", "type": "server_overloaded"}}).to_string(),
+        ));
+        assert!(writer.is_finished());
+        assert!(writer
+            .push_data(&chunk(json!({"content": "more"})))
+            .is_empty());
+        assert!(writer.finish().is_empty());
+
+        let events = parse_sse(sse.as_bytes());
+        let error = events.last().unwrap().json().unwrap();
+        assert_eq!(error["error"]["type"], "overloaded_error");
+        assert_eq!(
+            accumulate_anthropic_message(&events).unwrap_err(),
+            "stream error: overloaded"
+        );
+    }
+
+    #[test]
+    fn interrupted_stream_fails_with_api_error() {
+        let mut writer = AnthropicEventWriter::new();
+        let mut sse = writer.push_data(&chunk(json!({"content": "partial"})));
+        sse.push_str(&writer.fail("connection reset"));
+        let events = parse_sse(sse.as_bytes());
+        assert_eq!(
+            events.last().unwrap().json().unwrap()["error"]["type"],
+            "api_error"
+        );
+        assert!(writer.fail("again").is_empty());
+    }
+
+    #[test]
+    fn empty_upstream_produces_no_events() {
+        assert!(synthesize(&["[DONE]".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn cached_tokens_become_cache_read_input_tokens() {
+        let sse = synthesize(&[
+            json!({
+                "id": "chatcmpl-1",
+                "model": "gpt-4o",
+                "choices": [{"index": 0, "delta": {"content": "Hi"}, "finish_reason": null}],
+                "usage": {
+                    "prompt_tokens": 100,
+                    "completion_tokens": 0,
+                    "prompt_tokens_details": {"cached_tokens": 80},
+                },
+            })
+            .to_string(),
+            finish_chunk("stop"),
+            "[DONE]".to_string(),
+        ]);
+        let events = parse_sse(sse.as_bytes());
+        let start = events[0].json().unwrap();
+        assert_eq!(start["message"]["usage"]["input_tokens"], 20);
+        let delta = events
+            .iter()
+            .filter_map(|event| event.json())
+            .find(|event| event["type"] == "message_delta")
+            .unwrap();
+        assert_eq!(delta["usage"]["input_tokens"], 20);
+        assert_eq!(delta["usage"]["cache_read_input_tokens"], 80);
+    }
+
+    #[test]
+    fn reasoning_becomes_signed_thinking_blocks() {
+        let message = accumulate(&[
+            chunk(json!({"role": "assistant", "reasoning_content": "Need the "})),
+            chunk(json!({"reasoning_content": "weather."})),
+            tool_call(
+                0,
+                Some("call_a"),
+                Some("get_weather"),
+                "{\"city\":\"Paris\"}",
+            ),
+            chunk(json!({"reasoning_content": "Then answer."})),
+            chunk(json!({"content": "Sunny."})),
+            finish_chunk("stop"),
+            "[DONE]".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(
+            message["content"],
+            json!([
+                {"type": "thinking", "thinking": "Need the weather.", "signature": SYNTHETIC_SIGNATURE},
+                {"type": "tool_use", "id": "call_a", "name": "get_weather", "input": {"city": "Paris"}},
+                {"type": "thinking", "thinking": "Then answer.", "signature": SYNTHETIC_SIGNATURE},
+                {"type": "text", "text": "Sunny."},
+            ])
+        );
+    }
+
+    #[test]
+    fn signature_precedes_thinking_block_stop() {
+        let sse = synthesize(&[
+            chunk(json!({"reasoning_content": "Hmm."})),
+            "[DONE]".to_string(),
+        ]);
+        let events = parse_sse(sse.as_bytes());
+        let types = event_types(&events);
+        assert_eq!(
+            types[2..6],
+            [
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+            ]
+        );
+        let signature = events[4].json().unwrap();
+        assert_eq!(signature["delta"]["type"], "signature_delta");
+    }
+}
@@ -0,0 +1,221 @@
+//! OpenAI 目标模型的参数能力表
+//!
+//! o1 / o3 等推理模型不接受 `max_tokens`（需改用 `max_completion_tokens`），也不支持
+//! `temperature`、`top_p` 等采样参数；普通模型则不认识 `reasoning_effort`。
+//! 转发前按目标模型改写请求参数，避免转换后的请求被上游以400拒绝。
+//! 能力表之外的模型（如新发布的推理模型）无从判断，同协议转发时参数原样保留。
+
+use serde_json::Value;
+use tracing::debug;
+
+/// 推理模型不支持的采样参数
+const SAMPLING_PARAMS: &[&str] = &[
+    "temperature",
+    "top_p",
+    "presence_penalty",
+    "frequency_penalty",
+    "logprobs",
+    "top_logprobs",
+    "logit_bias",
+];
+
+/// 目标模型支持的参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelCapabilities {
+    /// 输出长度使用 `max_completion_tokens`（不接受 `max_tokens`），否则使用 `max_tokens`
+    pub max_completion_tokens: bool,
+    /// 支持 `temperature`、`top_p` 等采样参数
+    pub sampling: bool,
+    /// 支持 `reasoning_effort`
+    pub reasoning_effort: bool,
+}
+
+impl ModelCapabilities {
+    const REASONING: Self = Self {
+        max_completion_tokens: true,
+        sampling: false,
+        reasoning_effort: true,
+    };
+
+    const STANDARD: Self = Self {
+        max_completion_tokens: false,
+        sampling: true,
+        reasoning_effort: false,
+    };
+}
+
+/// 模型名分隔符：前缀之后紧跟其一即匹配（如 `o3-mini`、`gpt-5.1`、`o3:latest`）
+const NAME_SEPARATORS: &[char] = &['-', '.', ':'];
+
+/// 模型能力表：按模型名前缀识别推理模型和明确不支持推理的模型
+pub struct CapabilityTable {
+    reasoning_models: Vec<String>,
+    non_reasoning_models: Vec<String>,
+}
+
+impl CapabilityTable {
+    pub fn new(reasoning_models: Vec<String>, non_reasoning_models: Vec<String>) -> Self {
+        Self {
+            reasoning_models,
+            non_reasoning_models,
+        }
+    }
+
+    /// 查询模型能力，未列入能力表的模型返回 `None`
+    ///
+    /// 模型名等于前缀或前缀之后紧跟 `-`、`.`、`:` 即匹配（如 `o3` 匹配 `o3`、`o3-mini`、`o3:latest`），
+    /// 模型名和前缀中的供应商部分（如 `openai/o3`）都按最后一段匹配；同时匹配两个列表时按推理模型处理
+    pub fn lookup(&self, model: &str) -> Option<ModelCapabilities> {
+        let name = bare_name(model);
+        let listed = |prefixes: &[String]| {
+            prefixes.iter().any(|prefix| {
+                let prefix = bare_name(prefix);
+                name.strip_prefix(prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(NAME_SEPARATORS))
+            })
+        };
+        if listed(&self.reasoning_models) {
+            Some(ModelCapabilities::REASONING)
+        } else if listed(&self.non_reasoning_models) {
+            Some(ModelCapabilities::STANDARD)
+        } else {
+            None
+        }
+    }
+
+    /// 是否需要按目标模型改写同协议转发的 OpenAI 请求参数
+    pub fn needs_adaptation(&self, model: &str) -> bool {
+        self.lookup(model).is_some()
+    }
+
+    /// 按目标模型能力改写 OpenAI 请求 JSON
+    ///
+    /// `converted` 为请求是否由其他协议转换而来。未列入能力表的模型只在转换时去掉转换生成的
+    /// `reasoning_effort`（由扩展思考映射而来，普通模型不认识），客户端原样发送的参数不做改动。
+    pub fn adapt_openai_request(&self, json: &mut Value, model: &str, converted: bool) {
+        let capabilities = match self.lookup(model) {
+            Some(capabilities) => capabilities,
+            None if converted => ModelCapabilities::STANDARD,
+            None => return,
+        };
+        let Value::Object(obj) = json else {
+            return;
+        };
+
+        let mut dropped = Vec::new();
+        let (from, to) = if capabilities.max_completion_tokens {
+            ("max_tokens", "max_completion_tokens")
+        } else {
+            ("max_completion_tokens", "max_tokens")
+        };
+        if let Some(max_tokens) = obj.remove(from) {
+            obj.entry(to).or_insert(max_tokens);
+        }
+        if !capabilities.sampling {
+            for param in SAMPLING_PARAMS {
+                if obj.remove(*param).is_some() {
+                    dropped.push(*param);
+                }
+            }
+        }
+        if !capabilities.reasoning_effort && obj.remove("reasoning_effort").is_some() {
+            dropped.push("reasoning_effort");
+        }
+
+        if !dropped.is_empty() {
+            debug!(
+                "Dropped parameters unsupported by model {}: {:?}",
+                model, dropped
+            );
+        }
+    }
+}
+
+// 去掉供应商部分（如 `openai/o3` -> `o3`）
+fn bare_name(model: &str) -> &str {
+    model.rsplit('/').next().unwrap_or(model)
+}
+
+/// 将 Anthropic 扩展思考预算映射为 OpenAI `reasoning_effort`
+pub fn reasoning_effort_for_budget(budget_tokens: i64) -> &'static str {
+    match budget_tokens {
+        ..=4095 => "low",
+        4096..=16383 => "medium",
+        _ => "high",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn table() -> CapabilityTable {
+        CapabilityTable::new(
+            vec!["o3".to_string(), "openai/gpt-5".to_string()],
+            vec!["gpt-4o".to_string()],
+        )
+    }
+
+    #[test]
+    fn prefix_matches_on_separators_and_bare_names() {
+        let table = table();
+        for model in [
+            "o3",
+            "o3-mini",
+            "o3:latest",
+            "o3.1",
+            "azure/o3",
+            "gpt-5",
+            "gpt-5.1",
+        ] {
+            assert_eq!(
+                table.lookup(model),
+                Some(ModelCapabilities::REASONING),
+                "{}",
+                model
+            );
+        }
+        for model in ["o30", "o3mini", "gpt-50"] {
+            assert_eq!(table.lookup(model), None, "{}", model);
+        }
+        assert_eq!(
+            table.lookup("gpt-4o-mini"),
+            Some(ModelCapabilities::STANDARD)
+        );
+    }
+
+    #[test]
+    fn reasoning_model_uses_max_completion_tokens() {
+        let mut body = json!({"max_tokens": 100, "temperature": 0.2, "reasoning_effort": "low"});
+        table().adapt_openai_request(&mut body, "o3-mini", false);
+        assert_eq!(
+            body,
+            json!({"max_completion_tokens": 100, "reasoning_effort": "low"})
+        );
+    }
+
+    #[test]
+    fn listed_standard_model_drops_reasoning_params() {
+        let mut body =
+            json!({"max_completion_tokens": 100, "temperature": 0.2, "reasoning_effort": "low"});
+        table().adapt_openai_request(&mut body, "gpt-4o", false);
+        assert_eq!(body, json!({"max_tokens": 100, "temperature": 0.2}));
+    }
+
+    #[test]
+    fn unlisted_model_keeps_client_params() {
+        let original = json!({"max_completion_tokens": 100, "reasoning_effort": "high"});
+        let mut body = original.clone();
+        table().adapt_openai_request(&mut body, "deepseek-r1", false);
+        assert_eq!(body, original);
+        assert!(!table().needs_adaptation("deepseek-r1"));
+    }
+
+    #[test]
+    fn unlisted_model_drops_converted_reasoning_effort() {
+        let mut body = json!({"max_tokens": 100, "reasoning_effort": "high"});
+        table().adapt_openai_request(&mut body, "deepseek-r1", true);
+        assert_eq!(body, json!({"max_tokens": 100}));
+    }
+}
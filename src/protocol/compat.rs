@@ -0,0 +1,133 @@
+//! 客户端 API 版本兼容处理
+//!
+//! 客户端通过请求头（默认 `x-gateway-api-version`）声明所用的 API 版本，
+//! 网关在协议转换前按该版本配置的兼容处理改写请求体和转发上游的请求头。
+
+use crate::config::{CompatConfig, CompatProfile, MaxTokensField};
+use crate::models::ClientProtocol;
+use crate::protocol::agent_clients::AgentCompat;
+use bytes::Bytes;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// 按客户端声明的 API 版本选择兼容处理
+pub struct ClientCompat {
+    header: Option<HeaderName>,
+    default_version: Option<String>,
+    versions: HashMap<String, CompatProfile>,
+    agents: AgentCompat,
+}
+
+impl ClientCompat {
+    pub fn from_config(config: &CompatConfig) -> Self {
+        Self {
+            header: HeaderName::from_bytes(config.header.as_bytes()).ok(),
+            default_version: config.default_version.clone(),
+            versions: config.versions.clone(),
+            agents: AgentCompat::from_config(&config.agent_clients),
+        }
+    }
+
+    /// 声明 API 版本的请求头，转发上游前需剥离
+    pub fn header(&self) -> Option<&HeaderName> {
+        self.header.as_ref()
+    }
+
+    /// Claude Code / Codex CLI 客户端兼容处理
+    pub fn agents(&self) -> &AgentCompat {
+        &self.agents
+    }
+
+    /// 请求对应的兼容处理
+    ///
+    /// 未携带版本请求头时使用默认版本；声明了未配置的版本时返回 `Err(版本)`
+    pub fn select<'a>(
+        &'a self,
+        headers: &axum::http::HeaderMap,
+    ) -> std::result::Result<Option<&'a CompatProfile>, String> {
+        if self.versions.is_empty() {
+            return Ok(None);
+        }
+        let requested = self
+            .header
+            .as_ref()
+            .and_then(|header| headers.get(header.as_str()))
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty());
+        match requested.or(self.default_version.as_deref()) {
+            Some(version) => self
+                .versions
+                .get(version)
+                .map(Some)
+                .ok_or_else(|| version.to_string()),
+            None => Ok(None),
+        }
+    }
+}
+
+/// 兼容处理是否需要改写请求体
+pub fn rewrites_body(profile: &CompatProfile) -> bool {
+    profile.max_tokens_field.is_some() || profile.stop_as_array || profile.system_as_blocks
+}
+
+/// 按兼容处理改写客户端请求体，请求体不是 JSON 对象或无需改写时原样返回
+pub fn apply_request(profile: &CompatProfile, protocol: &ClientProtocol, body: Bytes) -> Bytes {
+    if !rewrites_body(profile) {
+        return body;
+    }
+    let Ok(Value::Object(mut obj)) = serde_json::from_slice::<Value>(&body) else {
+        return body;
+    };
+
+    let mut changed = false;
+    match protocol {
+        ClientProtocol::OpenAI => {
+            if let Some(field) = profile.max_tokens_field {
+                let (from, to) = match field {
+                    MaxTokensField::MaxTokens => ("max_completion_tokens", "max_tokens"),
+                    MaxTokensField::MaxCompletionTokens => ("max_tokens", "max_completion_tokens"),
+                };
+                if let Some(value) = obj.remove(from) {
+                    // 两个字段同时存在时保留目标字段
+                    obj.entry(to).or_insert(value);
+                    changed = true;
+                }
+            }
+            if profile.stop_as_array {
+                if let Some(Value::String(stop)) = obj.get("stop") {
+                    let stop = json!([stop]);
+                    obj.insert("stop".to_string(), stop);
+                    changed = true;
+                }
+            }
+        }
+        ClientProtocol::Anthropic => {
+            if profile.system_as_blocks {
+                if let Some(Value::String(system)) = obj.get("system") {
+                    let blocks = json!([{"type": "text", "text": system}]);
+                    obj.insert("system".to_string(), blocks);
+                    changed = true;
+                }
+            }
+        }
+        ClientProtocol::Custom(_) => {}
+    }
+
+    if !changed {
+        return body;
+    }
+    serde_json::to_vec(&obj).map(Bytes::from).unwrap_or(body)
+}
+
+/// 按兼容处理改写转发上游的客户端请求头
+pub fn apply_headers(profile: &CompatProfile, headers: &mut HeaderMap) {
+    if let Some(version) = profile
+        .anthropic_version
+        .as_deref()
+        .and_then(|v| HeaderValue::from_str(v).ok())
+    {
+        headers.insert("anthropic-version", version);
+    }
+}
@@ -7,38 +7,115 @@ use serde_json::Value;
 pub struct ProtocolDetector;
 
 impl ProtocolDetector {
-    // 判断客户端请求协议
+    // 判断客户端请求协议，路径和请求头都无法确定时默认为 OpenAI
     pub fn detect_from_request(req: &Request<Body>) -> Result<ClientProtocol> {
-        // 根据路径判断
-        let path = req.uri().path();
+        Ok(Self::detect_hint(req).unwrap_or(ClientProtocol::OpenAI))
+    }
+
+    // 根据路径和请求头判断客户端协议，无法确定时返回 None
+    pub fn detect_hint(req: &Request<Body>) -> Option<ClientProtocol> {
+        Self::detect_from_path(req.uri().path()).or_else(|| Self::detect_from_headers(req))
+    }
 
+    fn detect_from_path(path: &str) -> Option<ClientProtocol> {
         if path.starts_with("/v1/chat/completions") {
-            return Ok(ClientProtocol::OpenAI);
+            return Some(ClientProtocol::OpenAI);
         }
 
         if path.starts_with("/v1/messages") {
-            return Ok(ClientProtocol::Anthropic);
+            return Some(ClientProtocol::Anthropic);
         }
 
         // 支持 /v1/responses 路径，识别为 OpenAI 协议
         if path.starts_with("/v1/responses") {
-            return Ok(ClientProtocol::OpenAI);
+            return Some(ClientProtocol::OpenAI);
         }
 
-        // 根据 Authorization header 判断
-        if let Some(auth) = req.headers().get("authorization") {
-            if let Ok(auth_str) = auth.to_str() {
-                if auth_str.starts_with("Bearer sk-") {
-                    return Ok(ClientProtocol::OpenAI);
-                }
-                if auth_str.starts_with("x-api-key") {
-                    return Ok(ClientProtocol::Anthropic);
+        // Azure 风格部署路径，识别为 OpenAI 协议
+        if path.starts_with("/openai/deployments/") {
+            return Some(ClientProtocol::OpenAI);
+        }
+
+        None
+    }
+
+    // Anthropic 客户端会发送 x-api-key 或 anthropic-version 请求头
+    fn detect_from_headers(req: &Request<Body>) -> Option<ClientProtocol> {
+        let headers = req.headers();
+        if headers.contains_key("x-api-key") || headers.contains_key("anthropic-version") {
+            return Some(ClientProtocol::Anthropic);
+        }
+        None
+    }
+
+    // 根据请求体结构判断客户端协议，作为路径和请求头无法确定时的兜底
+    // 分别统计两种协议的特征字段，特征相当时返回 None
+    pub fn detect_from_body(req: &Bytes) -> Option<ClientProtocol> {
+        let json: Value = serde_json::from_slice(req).ok()?;
+        let mut anthropic = 0;
+        let mut openai = 0;
+
+        // Anthropic：顶层 system、anthropic_version、stop_sequences、top_k
+        for key in ["system", "anthropic_version", "stop_sequences", "top_k"] {
+            if json.get(key).is_some() {
+                anthropic += 1;
+            }
+        }
+        // OpenAI：Responses API 的 input/instructions，Chat 特有的参数
+        for key in [
+            "input",
+            "instructions",
+            "max_completion_tokens",
+            "max_output_tokens",
+            "response_format",
+            "n",
+            "stop",
+        ] {
+            if json.get(key).is_some() {
+                openai += 1;
+            }
+        }
+
+        let messages = json.get("messages").and_then(|m| m.as_array());
+        for message in messages.into_iter().flatten() {
+            // system/developer/tool 角色只存在于 OpenAI 消息中
+            if matches!(
+                message.get("role").and_then(|r| r.as_str()),
+                Some("system" | "developer" | "tool")
+            ) {
+                openai += 1;
+            }
+            if message.get("tool_calls").is_some() {
+                openai += 1;
+            }
+
+            let blocks = message.get("content").and_then(|c| c.as_array());
+            for block in blocks.into_iter().flatten() {
+                match block.get("type").and_then(|t| t.as_str()) {
+                    Some("tool_use" | "tool_result" | "thinking" | "document") => anthropic += 1,
+                    // Anthropic 图片块使用 source，OpenAI 使用 image_url
+                    Some("image") if block.get("source").is_some() => anthropic += 1,
+                    Some("image_url" | "input_audio" | "input_text" | "input_image") => openai += 1,
+                    _ => {}
                 }
             }
         }
 
-        // 默认为 OpenAI
-        Ok(ClientProtocol::OpenAI)
+        // Anthropic 的工具定义使用 input_schema，OpenAI 使用 function
+        let tools = json.get("tools").and_then(|t| t.as_array());
+        for tool in tools.into_iter().flatten() {
+            if tool.get("input_schema").is_some() {
+                anthropic += 1;
+            } else if tool.get("function").is_some() {
+                openai += 1;
+            }
+        }
+
+        match anthropic.cmp(&openai) {
+            std::cmp::Ordering::Greater => Some(ClientProtocol::Anthropic),
+            std::cmp::Ordering::Less => Some(ClientProtocol::OpenAI),
+            std::cmp::Ordering::Equal => None,
+        }
     }
 
     // 判断是否是流式请求
@@ -54,4 +131,88 @@ impl ProtocolDetector {
             .and_then(|v| v.as_bool())
             .unwrap_or(false)
     }
+
+    // 粗略估算提示词Token数（按约4个字符1个Token）
+    // 统计 system / messages / input / prompt 中的文本内容
+    pub fn estimate_prompt_tokens(req: &Bytes) -> Option<u32> {
+        let json: Value = serde_json::from_slice(req).ok()?;
+
+        let chars: usize = ["system", "messages", "input", "prompt"]
+            .iter()
+            .filter_map(|key| json.get(key))
+            .map(count_text_chars)
+            .sum();
+
+        Some(chars.div_ceil(4) as u32)
+    }
+
+    // 客户端请求的最大输出Token数
+    // 兼容 max_tokens（Chat/Anthropic）、max_completion_tokens、max_output_tokens（Responses）
+    pub fn requested_max_tokens(req: &Bytes) -> Option<u32> {
+        let json: Value = serde_json::from_slice(req).ok()?;
+
+        ["max_tokens", "max_completion_tokens", "max_output_tokens"]
+            .iter()
+            .find_map(|key| json.get(key)?.as_u64())
+            .map(|n| n.min(u32::MAX as u64) as u32)
+    }
+}
+
+// 递归统计JSON中文本字段（字符串、content、text）的字符数
+fn count_text_chars(value: &Value) -> usize {
+    match value {
+        Value::String(s) => s.chars().count(),
+        Value::Array(items) => items.iter().map(count_text_chars).sum(),
+        Value::Object(map) => ["content", "text"]
+            .iter()
+            .filter_map(|key| map.get(*key))
+            .map(count_text_chars)
+            .sum(),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(body: serde_json::Value) -> Option<ClientProtocol> {
+        ProtocolDetector::detect_from_body(&Bytes::from(body.to_string()))
+    }
+
+    #[test]
+    fn detects_anthropic_body() {
+        let body = serde_json::json!({
+            "model": "claude",
+            "system": "be brief",
+            "max_tokens": 16,
+            "messages": [{"role": "user", "content": [{"type": "text", "text": "hi"}]}],
+            "tools": [{"name": "f", "input_schema": {"type": "object"}}]
+        });
+        assert!(matches!(detect(body), Some(ClientProtocol::Anthropic)));
+    }
+
+    #[test]
+    fn detects_openai_body() {
+        let body = serde_json::json!({
+            "model": "gpt",
+            "messages": [{"role": "system", "content": "be brief"}, {"role": "user", "content": "hi"}],
+            "response_format": {"type": "json_object"}
+        });
+        assert!(matches!(detect(body), Some(ClientProtocol::OpenAI)));
+
+        let responses = serde_json::json!({"model": "gpt", "input": "hi", "instructions": "x"});
+        assert!(matches!(detect(responses), Some(ClientProtocol::OpenAI)));
+    }
+
+    #[test]
+    fn ambiguous_body_is_inconclusive() {
+        let body = serde_json::json!({
+            "model": "m",
+            "max_tokens": 16,
+            "messages": [{"role": "user", "content": "hi"}]
+        });
+        assert!(detect(body).is_none());
+        assert!(ProtocolDetector::detect_from_body(&Bytes::from_static(b"not json")).is_none());
+    }
 }
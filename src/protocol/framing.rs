@@ -0,0 +1,684 @@
+use crate::error::Result;
+use crate::models::TargetProtocol;
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use serde_json::Value;
+use std::pin::Pin;
+use tracing::{debug, warn};
+
+/// 上游流式响应的分帧格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFraming {
+    /// 标准 SSE（`data: ...` / `event: ...`）
+    Sse,
+    /// 每行一个 JSON 对象，没有 `data:` 前缀
+    Ndjson,
+}
+
+impl StreamFraming {
+    /// 根据响应体开头的首个非空白字节判断分帧格式
+    ///
+    /// SSE 的行总是以字段名或注释 `:` 开头，不会以 `{` 开头。
+    /// 数据不足以判断时返回 `None`。
+    pub fn detect(prefix: &[u8]) -> Option<Self> {
+        match prefix.iter().find(|b| !b.is_ascii_whitespace())? {
+            b'{' => Some(Self::Ndjson),
+            _ => Some(Self::Sse),
+        }
+    }
+}
+
+/// 将上游流式响应统一规范为以 `\n` 换行的 SSE
+///
+/// - SSE：将 `\r\n` 和单独的 `\r` 换行统一为 `\n`
+/// - NDJSON：每行包装为 `data: ...\n\n`；Anthropic 协议额外补上 `event:` 行，
+///   OpenAI 协议在流结束时补发 `data: [DONE]`
+pub fn normalize_to_sse<S>(
+    source_protocol: &TargetProtocol,
+    stream: S,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    let is_anthropic = matches!(source_protocol, TargetProtocol::Anthropic);
+
+    Box::pin(async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        let mut framing: Option<StreamFraming> = None;
+        // 等待判断分帧格式的数据，或 NDJSON 中尚未结束的行
+        let mut pending = BytesMut::new();
+        // 上一个 chunk 是否以 `\r` 结尾（可能是被拆开的 `\r\n`）
+        let mut trailing_cr = false;
+        let mut done_sent = false;
+
+        while let Some(chunk_result) = stream.next().await {
+            let chunk = match chunk_result {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+
+            let normalized = normalize_line_endings(&chunk, &mut trailing_cr);
+            pending.extend_from_slice(&normalized);
+
+            if framing.is_none() {
+                framing = StreamFraming::detect(&pending);
+                if let Some(framing) = framing {
+                    debug!("Detected upstream stream framing: {:?}", framing);
+                }
+            }
+
+            match framing {
+                None => continue,
+                Some(StreamFraming::Sse) => {
+                    if !pending.is_empty() {
+                        yield Ok(pending.split().freeze());
+                    }
+                }
+                Some(StreamFraming::Ndjson) => {
+                    let mut output = String::new();
+                    while let Some(pos) = pending.iter().position(|&b| b == b'\n') {
+                        let line = pending.split_to(pos + 1);
+                        ndjson_line_to_sse(&line, is_anthropic, &mut done_sent, &mut output);
+                    }
+                    if !output.is_empty() {
+                        yield Ok(Bytes::from(output));
+                    }
+                }
+            }
+        }
+
+        // 流结束：输出剩余数据
+        match framing {
+            Some(StreamFraming::Ndjson) => {
+                let mut output = String::new();
+                ndjson_line_to_sse(&pending, is_anthropic, &mut done_sent, &mut output);
+                if !is_anthropic && !done_sent {
+                    output.push_str("data: [DONE]\n\n");
+                }
+                if !output.is_empty() {
+                    yield Ok(Bytes::from(output));
+                }
+            }
+            _ => {
+                if !pending.is_empty() {
+                    yield Ok(pending.freeze());
+                }
+            }
+        }
+    })
+}
+
+/// 将 `\r\n` 与单独的 `\r` 换行替换为 `\n`
+fn normalize_line_endings(chunk: &[u8], trailing_cr: &mut bool) -> Bytes {
+    if !*trailing_cr && !chunk.contains(&b'\r') {
+        return Bytes::copy_from_slice(chunk);
+    }
+
+    let mut out = BytesMut::with_capacity(chunk.len());
+    let mut bytes = chunk.iter().copied().peekable();
+
+    // 跳过与上一个 chunk 末尾 `\r` 配对的 `\n`
+    if *trailing_cr && bytes.peek() == Some(&b'\n') {
+        bytes.next();
+    }
+    *trailing_cr = false;
+
+    while let Some(b) = bytes.next() {
+        if b == b'\r' {
+            out.extend_from_slice(b"\n");
+            match bytes.peek() {
+                Some(b'\n') => {
+                    bytes.next();
+                }
+                None => *trailing_cr = true,
+                _ => {}
+            }
+        } else {
+            out.extend_from_slice(&[b]);
+        }
+    }
+
+    out.freeze()
+}
+
+/// 将一行 NDJSON 转换为 SSE 事件，追加到 `output`
+fn ndjson_line_to_sse(line: &[u8], is_anthropic: bool, done_sent: &mut bool, output: &mut String) {
+    let line = String::from_utf8_lossy(line);
+    let line = line.trim();
+    if line.is_empty() {
+        return;
+    }
+
+    // 部分实现会在 NDJSON 中混入 SSE 风格的结束标记
+    let data = line.strip_prefix("data:").map(str::trim).unwrap_or(line);
+    if data == "[DONE]" {
+        if !is_anthropic && !*done_sent {
+            output.push_str("data: [DONE]\n\n");
+            *done_sent = true;
+        }
+        return;
+    }
+
+    if is_anthropic {
+        let event_type = serde_json::from_str::<Value>(data)
+            .ok()
+            .and_then(|json| json["type"].as_str().map(str::to_string));
+        if let Some(event_type) = event_type {
+            output.push_str("event: ");
+            output.push_str(&event_type);
+            output.push('\n');
+        }
+    }
+
+    output.push_str("data: ");
+    output.push_str(data);
+    output.push_str("\n\n");
+}
+
+/// 限制上游 SSE 流中单个事件的字节数，输入须为 [`normalize_to_sse`] 规范后的流
+///
+/// 事件超过上限时不再转发其余字节，按上游协议补发一个错误事件后终止流，
+/// 之后的用量收集、协议转换等环节的缓冲因此不会超过上限。
+pub fn limit_event_size<S>(
+    source_protocol: &TargetProtocol,
+    stream: S,
+    max_event_bytes: usize,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    let source_protocol = source_protocol.clone();
+
+    Box::pin(async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        // 当前事件已累计的字节数
+        let mut event_len = 0usize;
+        // 上一个字节是否为 `\n`
+        let mut after_newline = false;
+
+        while let Some(chunk_result) = stream.next().await {
+            let chunk = match chunk_result {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+
+            // 当前事件在本 chunk 中的起始位置
+            let mut event_start = 0;
+            let mut oversized = false;
+            for (i, &byte) in chunk.iter().enumerate() {
+                event_len += 1;
+                if byte == b'\n' && after_newline {
+                    event_len = 0;
+                    event_start = i + 1;
+                }
+                after_newline = byte == b'\n';
+                if event_len > max_event_bytes {
+                    oversized = true;
+                    break;
+                }
+            }
+
+            if !oversized {
+                yield Ok(chunk);
+                continue;
+            }
+
+            warn!(
+                "Upstream SSE event exceeds {} bytes, terminating stream",
+                max_event_bytes
+            );
+            metrics::increment_counter!("gateway_sse_event_oversized_total", "stage" => "upstream");
+            if event_start > 0 {
+                yield Ok(chunk.slice(..event_start));
+            }
+            yield Ok(Bytes::from(oversized_event_error(&source_protocol, max_event_bytes)));
+            return;
+        }
+    })
+}
+
+/// 限制上游流式响应的累计字节数，输入须为 [`normalize_to_sse`] 规范后的流
+///
+/// 累计字节数超过上限时不再转发其余字节，按上游协议补发一个错误事件后终止流，
+/// 避免异常或滥用的长生成持续消耗额度。
+pub fn limit_stream_size<S>(
+    source_protocol: &TargetProtocol,
+    stream: S,
+    max_stream_bytes: u64,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    let source_protocol = source_protocol.clone();
+
+    Box::pin(async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        let mut total = 0u64;
+
+        while let Some(chunk_result) = stream.next().await {
+            let chunk = match chunk_result {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+
+            let room = max_stream_bytes.saturating_sub(total);
+            total += chunk.len() as u64;
+            if total <= max_stream_bytes {
+                yield Ok(chunk);
+                continue;
+            }
+
+            warn!(
+                "Upstream stream exceeds {} bytes, terminating stream",
+                max_stream_bytes
+            );
+            metrics::increment_counter!("gateway_upstream_response_too_large_total", "kind" => "stream");
+            // 只转发上限内完整的事件
+            let allowed = &chunk[..room as usize];
+            if let Some(end) = allowed.windows(2).rposition(|w| w == b"\n\n") {
+                yield Ok(chunk.slice(..end + 2));
+            }
+            let message = format!(
+                "Upstream stream exceeds the gateway limit of {} bytes",
+                max_stream_bytes
+            );
+            yield Ok(Bytes::from(stream_error_event(
+                &source_protocol,
+                &message,
+                "stream_too_large",
+            )));
+            return;
+        }
+    })
+}
+
+// 按上游协议构造事件超限的错误事件，先以空行结束已转发的不完整事件
+fn oversized_event_error(source_protocol: &TargetProtocol, max_event_bytes: usize) -> String {
+    let message = format!(
+        "Upstream stream event exceeds the gateway limit of {} bytes",
+        max_event_bytes
+    );
+    stream_error_event(source_protocol, &message, "sse_event_too_large")
+}
+
+// 按上游协议格式化终止流的错误事件，开头的空行结束可能未完成的事件
+fn stream_error_event(source_protocol: &TargetProtocol, message: &str, code: &str) -> String {
+    match source_protocol {
+        TargetProtocol::Anthropic => format!(
+            "\n\nevent: error\ndata: {}\n\n",
+            serde_json::json!({
+                "type": "error",
+                "error": {"type": "api_error", "message": message},
+            })
+        ),
+        TargetProtocol::OpenAI | TargetProtocol::Custom(_) => format!(
+            "\n\ndata: {}\n\n",
+            serde_json::json!({
+                "error": {
+                    "message": message,
+                    "type": "server_error",
+                    "code": code,
+                },
+            })
+        ),
+    }
+}
+
+/// SSE 中的一个事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    /// `event:` 字段，没有时为空
+    pub event: String,
+    /// `data:` 字段，多行时以 `\n` 连接
+    pub data: String,
+}
+
+/// 将以 `\n` 换行的 SSE 字节流（即网关输出给客户端的流）拆分为事件
+///
+/// 忽略注释和没有 `data:` 的事件，供不使用 SSE 的传输（gRPC、WebSocket）逐条转发。
+pub fn sse_events<S, E>(
+    stream: S,
+) -> Pin<Box<dyn Stream<Item = std::result::Result<SseEvent, E>> + Send>>
+where
+    S: Stream<Item = std::result::Result<Bytes, E>> + Send + 'static,
+    E: Send + 'static,
+{
+    Box::pin(async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        let mut buffer = BytesMut::new();
+
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(chunk) => buffer.extend_from_slice(&chunk),
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+
+            // 事件之间以空行分隔
+            while let Some(end) = buffer.windows(2).position(|w| w == b"\n\n") {
+                let event = buffer.split_to(end + 2);
+                if let Some(event) = parse_sse_event(&event) {
+                    yield Ok(event);
+                }
+            }
+        }
+    })
+}
+
+fn parse_sse_event(event: &[u8]) -> Option<SseEvent> {
+    let text = std::str::from_utf8(event).ok()?;
+    let mut name = String::new();
+    let mut data: Vec<&str> = Vec::new();
+
+    for line in text.lines() {
+        if let Some(value) = line.strip_prefix("event:") {
+            name = value.trim().to_string();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push(value.strip_prefix(' ').unwrap_or(value));
+        }
+    }
+
+    if data.is_empty() {
+        return None;
+    }
+
+    Some(SseEvent {
+        event: name,
+        data: data.join("\n"),
+    })
+}
+
+/// 返回客户端的流式响应格式，按客户端的 `Accept` 请求头协商
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClientStreamFormat {
+    /// 标准 SSE
+    #[default]
+    Sse,
+    /// 每行一个 JSON 对象
+    Ndjson,
+}
+
+/// 视为 NDJSON 的媒体类型
+const NDJSON_MEDIA_TYPES: [&str; 3] = [
+    "application/x-ndjson",
+    "application/ndjson",
+    "application/jsonl",
+];
+
+impl ClientStreamFormat {
+    /// NDJSON 的权重（`q`）高于 `text/event-stream` 时使用 NDJSON，其他情况（包括未携带 Accept）使用 SSE
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        let Some(accept) = accept else {
+            return Self::Sse;
+        };
+
+        let mut ndjson_q = 0.0_f32;
+        let mut sse_q = 0.0_f32;
+        for range in accept.split(',') {
+            let mut params = range.split(';');
+            let media_type = params
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if NDJSON_MEDIA_TYPES.contains(&media_type.as_str()) {
+                ndjson_q = ndjson_q.max(q);
+            } else if media_type == "text/event-stream" {
+                sse_q = sse_q.max(q);
+            }
+        }
+
+        if ndjson_q > 0.0 && ndjson_q > sse_q {
+            Self::Ndjson
+        } else {
+            Self::Sse
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Sse => "text/event-stream",
+            Self::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    /// 将网关输出的 SSE 转换为该格式：NDJSON 每个事件的 `data` 占一行，丢弃 `[DONE]`
+    ///
+    /// Anthropic 事件的类型已在 `data` 的 `type` 字段中，省略 `event:` 行不丢失信息。
+    pub fn encode<S>(self, stream: S) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+    where
+        S: Stream<Item = Result<Bytes>> + Send + 'static,
+    {
+        match self {
+            Self::Sse => Box::pin(stream),
+            Self::Ndjson => Box::pin(sse_events(stream).filter_map(|event| async move {
+                match event {
+                    Ok(event) if event.data == "[DONE]" => None,
+                    Ok(event) => Some(Ok(Bytes::from(format!(
+                        "{}\n",
+                        event.data.replace('\n', "")
+                    )))),
+                    Err(e) => Some(Err(e)),
+                }
+            })),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(parts: &[&'static str]) -> impl Stream<Item = Result<Bytes>> + Send + 'static {
+        let parts: Vec<Result<Bytes>> = parts
+            .iter()
+            .map(|part| Ok(Bytes::from_static(part.as_bytes())))
+            .collect();
+        futures::stream::iter(parts)
+    }
+
+    async fn collect(stream: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>) -> String {
+        let chunks: Vec<Bytes> = stream.map(|chunk| chunk.unwrap()).collect().await;
+        chunks
+            .iter()
+            .map(|chunk| String::from_utf8_lossy(chunk))
+            .collect()
+    }
+
+    // 两个事件各 9 字节，拆分在行中间和事件中间
+    const SPLIT_EVENTS: [&str; 4] = ["data: ", "a\n\ndata: b", "\n", "\n"];
+
+    #[tokio::test]
+    async fn stream_limit_passes_streams_that_hit_the_limit_exactly() {
+        let output = collect(limit_stream_size(
+            &TargetProtocol::OpenAI,
+            chunks(&SPLIT_EVENTS),
+            18,
+        ))
+        .await;
+        assert_eq!(output, "data: a\n\ndata: b\n\n");
+    }
+
+    #[tokio::test]
+    async fn stream_limit_ends_with_one_error_when_exceeded_by_one_byte() {
+        let output = collect(limit_stream_size(
+            &TargetProtocol::OpenAI,
+            chunks(&[
+                SPLIT_EVENTS[0],
+                SPLIT_EVENTS[1],
+                SPLIT_EVENTS[2],
+                SPLIT_EVENTS[3],
+                "data: c\n\n",
+            ]),
+            17,
+        ))
+        .await;
+        assert!(output.starts_with("data: a\n\ndata: b\n\n\ndata: {"));
+        assert_eq!(output.matches("stream_too_large").count(), 1);
+        assert!(!output.contains("data: c"));
+    }
+
+    #[tokio::test]
+    async fn stream_limit_forwards_only_complete_events_of_the_last_chunk() {
+        let output = collect(limit_stream_size(
+            &TargetProtocol::Anthropic,
+            chunks(&["data: a\n\ndata: b\n\n"]),
+            12,
+        ))
+        .await;
+        assert!(output.starts_with("data: a\n\n\n\nevent: error\n"));
+        assert_eq!(output.matches("event: error").count(), 1);
+        assert!(!output.contains("data: b"));
+    }
+
+    #[tokio::test]
+    async fn event_limit_counts_events_across_chunks() {
+        // 每个事件不含结束空行为 8 字节，上限恰好为 8 时全部转发
+        let output = collect(limit_event_size(
+            &TargetProtocol::OpenAI,
+            chunks(&SPLIT_EVENTS),
+            8,
+        ))
+        .await;
+        assert_eq!(output, "data: a\n\ndata: b\n\n");
+    }
+
+    #[tokio::test]
+    async fn event_limit_ends_with_one_error_when_exceeded_by_one_byte() {
+        let output = collect(limit_event_size(
+            &TargetProtocol::OpenAI,
+            chunks(&["data: a\n\nda", "ta: bb", "\n\n", "data: c\n\n"]),
+            8,
+        ))
+        .await;
+        assert!(output.starts_with("data: a\n\ndata: bb\n\ndata: {"));
+        assert_eq!(output.matches("sse_event_too_large").count(), 1);
+        assert!(!output.contains("data: c"));
+    }
+
+    #[tokio::test]
+    async fn event_limit_forwards_completed_events_of_the_oversized_chunk() {
+        let output = collect(limit_event_size(
+            &TargetProtocol::Anthropic,
+            chunks(&["data: a\n\ndata: bbbbbbbb\n\n"]),
+            8,
+        ))
+        .await;
+        assert!(output.starts_with("data: a\n\n\n\nevent: error\n"));
+        assert_eq!(output.matches("event: error").count(), 1);
+        assert!(!output.contains("bbbb"));
+    }
+
+    #[tokio::test]
+    async fn normalizes_crlf_split_across_chunks() {
+        let output = collect(normalize_to_sse(
+            &TargetProtocol::OpenAI,
+            chunks(&["data: a\r", "\n\r", "\ndata: b\r", "\rdata: c\r\n\r"]),
+        ))
+        .await;
+        assert_eq!(output, "data: a\n\ndata: b\n\ndata: c\n\n");
+    }
+
+    #[tokio::test]
+    async fn waits_for_a_non_blank_byte_to_detect_framing() {
+        assert_eq!(StreamFraming::detect(b" \r\n"), None);
+        assert_eq!(
+            StreamFraming::detect(b"\n{\"a\":1}"),
+            Some(StreamFraming::Ndjson)
+        );
+        assert_eq!(StreamFraming::detect(b": ping"), Some(StreamFraming::Sse));
+
+        let output = collect(normalize_to_sse(
+            &TargetProtocol::OpenAI,
+            chunks(&["\r", "\n", "{\"id\":1}\r", "\n"]),
+        ))
+        .await;
+        assert_eq!(output, "data: {\"id\":1}\n\ndata: [DONE]\n\n");
+    }
+
+    #[tokio::test]
+    async fn wraps_ndjson_lines_split_across_chunks() {
+        let output = collect(normalize_to_sse(
+            &TargetProtocol::OpenAI,
+            chunks(&["{\"id\":", "1}\r", "\n{\"id\":2}\n", "data: [DONE]\r\n"]),
+        ))
+        .await;
+        assert_eq!(
+            output,
+            "data: {\"id\":1}\n\ndata: {\"id\":2}\n\ndata: [DONE]\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn adds_event_lines_to_anthropic_ndjson() {
+        let output = collect(normalize_to_sse(
+            &TargetProtocol::Anthropic,
+            chunks(&[
+                "{\"type\":\"message_start\"}\n{\"type\":",
+                "\"message_stop\"}",
+            ]),
+        ))
+        .await;
+        assert_eq!(
+            output,
+            "event: message_start\ndata: {\"type\":\"message_start\"}\n\n\
+             event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn encodes_events_split_across_chunks_as_ndjson() {
+        let output = collect(ClientStreamFormat::Ndjson.encode(chunks(&[
+            "event: message_start\ndata: {\"a\":",
+            "1}\n",
+            "\ndata: {\"b\":\ndata: 2}\n\n: ping\n\ndata: [DONE]\n",
+            "\n",
+        ])))
+        .await;
+        assert_eq!(output, "{\"a\":1}\n{\"b\":2}\n");
+
+        let sse = "data: {\"a\":1}\n\n";
+        assert_eq!(
+            collect(ClientStreamFormat::Sse.encode(chunks(&[sse]))).await,
+            sse
+        );
+    }
+
+    #[test]
+    fn negotiates_client_stream_format() {
+        assert_eq!(ClientStreamFormat::negotiate(None), ClientStreamFormat::Sse);
+        assert_eq!(
+            ClientStreamFormat::negotiate(Some("application/x-ndjson")),
+            ClientStreamFormat::Ndjson
+        );
+        assert_eq!(
+            ClientStreamFormat::negotiate(Some("text/event-stream, application/jsonl")),
+            ClientStreamFormat::Sse
+        );
+        assert_eq!(
+            ClientStreamFormat::negotiate(Some(
+                "text/event-stream;q=0.5, Application/NDJSON;q=0.9"
+            )),
+            ClientStreamFormat::Ndjson
+        );
+        assert_eq!(
+            ClientStreamFormat::negotiate(Some("application/x-ndjson;q=0, */*")),
+            ClientStreamFormat::Sse
+        );
+    }
+}
@@ -0,0 +1,359 @@
+//! OpenAI 旧版函数调用格式（`functions` / `function_call`）
+//!
+//! 旧版客户端以 `functions` 声明函数、以 `function_call` 指定调用方式，助手消息中的调用为
+//! `function_call: {name, arguments}`，函数结果以 `role: "function"` 的消息返回。
+//! 协议转换前将请求规范化为 `tools` 格式；响应（含流式）再转换回旧版格式：
+//! 只保留第一个工具调用，`finish_reason` 为 `tool_calls` 时改为 `function_call`。
+
+use crate::error::Result;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde_json::{json, Map, Value};
+use std::pin::Pin;
+use tracing::warn;
+
+/// 请求是否使用旧版函数调用格式（声明了 `functions` 且未使用 `tools`）
+pub fn is_legacy_request(body: &[u8]) -> bool {
+    let Ok(Value::Object(obj)) = serde_json::from_slice::<Value>(body) else {
+        return false;
+    };
+    !obj.contains_key("tools")
+        && (obj.contains_key("functions") || obj.contains_key("function_call"))
+}
+
+/// 将旧版函数调用格式的请求规范化为 `tools` 格式
+///
+/// 助手消息中的调用分配 `call_legacy_<n>` 作为调用ID，其后同名的函数结果消息引用该ID。
+/// 旧版格式一次只能表示一个调用，规范化后的请求禁用并行工具调用。请求体不是 JSON 对象时原样返回
+pub fn normalize_request(body: Bytes) -> Bytes {
+    let Ok(Value::Object(mut obj)) = serde_json::from_slice::<Value>(&body) else {
+        return body;
+    };
+
+    if let Some(functions) = obj.remove("functions") {
+        let tools: Vec<Value> = functions
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|function| json!({"type": "function", "function": function}))
+            .collect();
+        obj.insert("tools".to_string(), Value::Array(tools));
+        obj.insert("parallel_tool_calls".to_string(), json!(false));
+    }
+    if let Some(call) = obj.remove("function_call") {
+        let choice = match call {
+            Value::Object(call) => {
+                json!({"type": "function", "function": {"name": call.get("name")}})
+            }
+            // "none" / "auto"
+            other => other,
+        };
+        obj.insert("tool_choice".to_string(), choice);
+    }
+
+    if let Some(messages) = obj.get_mut("messages").and_then(|m| m.as_array_mut()) {
+        // 尚未返回结果的调用：(函数名, 调用ID)
+        let mut open_calls: Vec<(String, String)> = Vec::new();
+        for (n, message) in messages.iter_mut().enumerate() {
+            let Some(message) = message.as_object_mut() else {
+                continue;
+            };
+            if let Some(call) = message.remove("function_call") {
+                let id = format!("call_legacy_{}", n);
+                let name = call["name"].as_str().unwrap_or_default().to_string();
+                message.insert(
+                    "tool_calls".to_string(),
+                    json!([{
+                        "id": id,
+                        "type": "function",
+                        "function": {
+                            "name": name,
+                            "arguments": call["arguments"].as_str().unwrap_or("{}"),
+                        },
+                    }]),
+                );
+                open_calls.push((name, id));
+            } else if message.get("role").and_then(|r| r.as_str()) == Some("function") {
+                let name = message
+                    .get("name")
+                    .and_then(|n| n.as_str())
+                    .unwrap_or_default();
+                let id = match open_calls.iter().rposition(|(call, _)| call == name) {
+                    Some(pos) => open_calls.remove(pos).1,
+                    None => format!("call_legacy_{}", n),
+                };
+                message.insert("role".to_string(), json!("tool"));
+                message.insert("tool_call_id".to_string(), json!(id));
+            }
+        }
+    }
+
+    serde_json::to_vec(&obj).map(Bytes::from).unwrap_or(body)
+}
+
+/// 将 OpenAI 格式的非流式响应转换回旧版函数调用格式，响应体不是 JSON 时原样返回
+pub fn response_to_legacy(body: Bytes) -> Bytes {
+    let Ok(mut json) = serde_json::from_slice::<Value>(&body) else {
+        return body;
+    };
+    for choice in json["choices"].as_array_mut().into_iter().flatten() {
+        legacy_finish_reason(choice);
+        let Some(message) = choice["message"].as_object_mut() else {
+            continue;
+        };
+        if let Some(call) = first_tool_call(message) {
+            message.insert("function_call".to_string(), call["function"].clone());
+        }
+    }
+    serde_json::to_vec(&json).map(Bytes::from).unwrap_or(body)
+}
+
+/// 将 OpenAI 格式的流式响应（标准 SSE）转换回旧版函数调用格式
+pub fn stream_to_legacy<S>(stream: S) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    Box::pin(async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        let mut buffer: Vec<u8> = Vec::new();
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            buffer.extend_from_slice(&chunk);
+
+            // 逐个处理完整的事件（以空行分隔），不完整的事件留待下一个chunk
+            let mut out = Vec::new();
+            while let Some(pos) = buffer.windows(2).position(|w| w == b"\n\n") {
+                let event: Vec<u8> = buffer.drain(..pos + 2).collect();
+                out.extend_from_slice(&event_to_legacy(&event));
+            }
+            if !out.is_empty() {
+                yield Ok(Bytes::from(out));
+            }
+        }
+
+        if !buffer.is_empty() {
+            yield Ok(Bytes::from(buffer));
+        }
+    })
+}
+
+// 转换单个 SSE 事件中的 chunk，无法解析的事件原样返回
+fn event_to_legacy(event: &[u8]) -> Vec<u8> {
+    let Some(data) = std::str::from_utf8(event)
+        .ok()
+        .and_then(|e| e.trim_end().strip_prefix("data:"))
+        .map(str::trim)
+    else {
+        return event.to_vec();
+    };
+    let Ok(mut chunk) = serde_json::from_str::<Value>(data) else {
+        return event.to_vec();
+    };
+
+    for choice in chunk["choices"].as_array_mut().into_iter().flatten() {
+        legacy_finish_reason(choice);
+        let Some(delta) = choice["delta"].as_object_mut() else {
+            continue;
+        };
+        if let Some(call) = first_tool_call(delta) {
+            // 首个片段带函数名，后续片段只有参数增量
+            let mut function = Map::new();
+            if let Some(name) = call["function"].get("name") {
+                function.insert("name".to_string(), name.clone());
+            }
+            let arguments = call["function"]["arguments"].as_str().unwrap_or_default();
+            function.insert("arguments".to_string(), json!(arguments));
+            delta.insert("function_call".to_string(), Value::Object(function));
+        }
+    }
+    format!("data: {}\n\n", chunk).into_bytes()
+}
+
+// 移除消息（或增量）中的 tool_calls，返回第一个工具调用（流式为序号0的调用片段）
+fn first_tool_call(message: &mut Map<String, Value>) -> Option<Value> {
+    let calls = message.remove("tool_calls")?;
+    let mut calls = match calls {
+        Value::Array(calls) => calls,
+        _ => return None,
+    };
+    calls.retain(|call| call["index"].as_u64().unwrap_or(0) == 0);
+    if calls.len() > 1 {
+        warn!(
+            "Dropping {} extra tool call(s) not representable in legacy function_call format",
+            calls.len() - 1
+        );
+    }
+    calls.into_iter().next()
+}
+
+fn legacy_finish_reason(choice: &mut Value) {
+    if choice["finish_reason"].as_str() == Some("tool_calls") {
+        choice["finish_reason"] = json!("function_call");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::testkit::parse_sse;
+
+    fn normalize(request: Value) -> Value {
+        let body = normalize_request(Bytes::from(request.to_string()));
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn detects_legacy_requests() {
+        assert!(is_legacy_request(br#"{"functions":[]}"#));
+        assert!(is_legacy_request(br#"{"function_call":"auto"}"#));
+        assert!(!is_legacy_request(br#"{"functions":[],"tools":[]}"#));
+        assert!(!is_legacy_request(br#"{"messages":[]}"#));
+        assert!(!is_legacy_request(b"not json"));
+    }
+
+    #[test]
+    fn normalizes_functions_and_function_call() {
+        let request = normalize(json!({
+            "functions": [{"name": "get_weather", "parameters": {"type": "object"}}],
+            "function_call": {"name": "get_weather"},
+            "messages": [],
+        }));
+        assert_eq!(
+            request["tools"],
+            json!([{
+                "type": "function",
+                "function": {"name": "get_weather", "parameters": {"type": "object"}},
+            }])
+        );
+        assert_eq!(
+            request["tool_choice"],
+            json!({"type": "function", "function": {"name": "get_weather"}})
+        );
+        assert_eq!(request["parallel_tool_calls"], false);
+        assert!(request.get("functions").is_none());
+        assert!(request.get("function_call").is_none());
+
+        let request = normalize(json!({"function_call": "none", "messages": []}));
+        assert_eq!(request["tool_choice"], "none");
+    }
+
+    #[test]
+    fn links_function_results_to_calls() {
+        let request = normalize(json!({
+            "functions": [],
+            "messages": [
+                {"role": "user", "content": "weather?"},
+                {"role": "assistant", "content": null,
+                 "function_call": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}},
+                {"role": "function", "name": "get_weather", "content": "sunny"},
+                {"role": "function", "name": "unknown", "content": "?"},
+            ],
+        }));
+        let messages = request["messages"].as_array().unwrap();
+        let call = &messages[1]["tool_calls"][0];
+        assert_eq!(call["id"], "call_legacy_1");
+        assert_eq!(call["function"]["name"], "get_weather");
+        assert_eq!(call["function"]["arguments"], "{\"city\":\"Paris\"}");
+        assert!(messages[1].get("function_call").is_none());
+
+        assert_eq!(messages[2]["role"], "tool");
+        assert_eq!(messages[2]["tool_call_id"], "call_legacy_1");
+        // 没有对应调用的结果使用自身位置生成ID
+        assert_eq!(messages[3]["tool_call_id"], "call_legacy_3");
+    }
+
+    #[test]
+    fn leaves_non_object_bodies_alone() {
+        let body = Bytes::from_static(b"[1,2]");
+        assert_eq!(normalize_request(body.clone()), body);
+        let body = Bytes::from_static(b"not json");
+        assert_eq!(response_to_legacy(body.clone()), body);
+    }
+
+    #[test]
+    fn converts_response_to_function_call() {
+        let body = response_to_legacy(Bytes::from(
+            json!({
+                "choices": [{
+                    "index": 0,
+                    "message": {
+                        "role": "assistant",
+                        "content": null,
+                        "tool_calls": [
+                            {"id": "call_1", "type": "function",
+                             "function": {"name": "a", "arguments": "{}"}},
+                            {"id": "call_2", "type": "function",
+                             "function": {"name": "b", "arguments": "{}"}},
+                        ],
+                    },
+                    "finish_reason": "tool_calls",
+                }],
+            })
+            .to_string(),
+        ));
+        let response: Value = serde_json::from_slice(&body).unwrap();
+        let choice = &response["choices"][0];
+        assert_eq!(choice["finish_reason"], "function_call");
+        assert_eq!(
+            choice["message"]["function_call"],
+            json!({"name": "a", "arguments": "{}"})
+        );
+        assert!(choice["message"].get("tool_calls").is_none());
+    }
+
+    #[tokio::test]
+    async fn converts_stream_to_function_call_deltas() {
+        let chunks = [
+            json!({"choices": [{"index": 0, "delta": {"tool_calls": [
+                {"index": 0, "id": "call_1", "type": "function",
+                 "function": {"name": "a", "arguments": ""}},
+            ]}, "finish_reason": null}]}),
+            json!({"choices": [{"index": 0, "delta": {"tool_calls": [
+                {"index": 0, "function": {"arguments": "{\"x\":1}"}},
+                {"index": 1, "function": {"arguments": "{}"}},
+            ]}, "finish_reason": null}]}),
+            json!({"choices": [{"index": 0, "delta": {}, "finish_reason": "tool_calls"}]}),
+        ];
+        let sse: String = chunks
+            .iter()
+            .map(|chunk| format!("data: {}\n\n", chunk))
+            .chain(std::iter::once("data: [DONE]\n\n".to_string()))
+            .collect();
+        // 事件跨 chunk 边界
+        let (first, second) = sse.split_at(sse.len() / 2);
+        let input = futures::stream::iter([
+            Ok(Bytes::from(first.to_string())),
+            Ok(Bytes::from(second.to_string())),
+        ]);
+
+        let mut output = Vec::new();
+        let mut stream = stream_to_legacy(input);
+        while let Some(chunk) = stream.next().await {
+            output.extend_from_slice(&chunk.unwrap());
+        }
+        let events = parse_sse(&output);
+        let deltas: Vec<Value> = events
+            .iter()
+            .filter_map(|e| e.json())
+            .map(|chunk| chunk["choices"][0].clone())
+            .collect();
+        assert_eq!(
+            deltas[0]["delta"]["function_call"],
+            json!({"name": "a", "arguments": ""})
+        );
+        assert_eq!(
+            deltas[1]["delta"]["function_call"],
+            json!({"arguments": "{\"x\":1}"})
+        );
+        assert!(deltas[1]["delta"].get("tool_calls").is_none());
+        assert_eq!(deltas[2]["finish_reason"], "function_call");
+        assert!(events.last().unwrap().is_done());
+    }
+}
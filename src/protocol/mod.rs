@@ -1,7 +1,20 @@
 pub mod adapter;
+pub mod agent_clients;
 pub mod anthropic;
+pub mod anthropic_stream;
+pub mod capabilities;
+pub mod compat;
 pub mod detector;
+pub mod framing;
+pub mod legacy_functions;
+pub mod model_field;
+pub mod multipart;
 pub mod openai;
+pub mod rerank;
+pub mod responses;
+pub mod stop_reason;
+pub mod stream_usage;
+pub mod testkit;
 
 use crate::error::Result;
 use crate::models::{ClientProtocol, TargetProtocol};
@@ -34,3 +47,14 @@ pub trait ProtocolAdapter: Send + Sync {
         stream: impl Stream<Item = Result<Bytes>> + Send + 'static,
     ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>>;
 }
+
+/// 流式事件字段的宽松解析：字段类型不符时按缺省值处理，
+/// 避免个别供应商的非标准字段导致整个事件无法解析
+pub(crate) fn lenient<'de, D, T>(deserializer: D) -> std::result::Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned + Default,
+{
+    let value = <serde_json::Value as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(serde_json::from_value(value).unwrap_or_default())
+}
@@ -0,0 +1,63 @@
+//! 请求体模型名的快速替换
+//!
+//! 同协议转发只需替换顶层的 `model` 字段，完整解析为 `Value` 再序列化的开销随请求体增大
+//! （多模态请求中的 base64 图片可达数 MB）。这里只校验 JSON 并定位 `model` 值的字节范围，
+//! 其余字节原样拷贝：顶层字段跳过时不分配内存，`model` 的值以 `RawValue` 借用原始请求体。
+
+use bytes::Bytes;
+use serde::de::{self, Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde_json::value::RawValue;
+use std::borrow::Cow;
+use std::fmt;
+
+/// 将顶层对象的 `model` 字段替换为 `model`，其余字节保持不变
+///
+/// 请求体不是合法的 JSON 对象、没有 `model` 字段或 `model` 字段重复时返回 None，
+/// 调用方应回退到完整解析。
+pub fn replace_model(body: &[u8], model: &str) -> Option<Bytes> {
+    let mut deserializer = serde_json::Deserializer::from_slice(body);
+    let raw = deserializer.deserialize_map(ModelVisitor).ok()??;
+    deserializer.end().ok()?;
+
+    // RawValue 借用请求体，由指针得出 model 值在请求体中的位置
+    let start = raw.get().as_ptr() as usize - body.as_ptr() as usize;
+    let end = start + raw.get().len();
+    let replacement = serde_json::to_vec(model).ok()?;
+
+    let mut replaced = Vec::with_capacity(body.len() - (end - start) + replacement.len());
+    replaced.extend_from_slice(&body[..start]);
+    replaced.extend_from_slice(&replacement);
+    replaced.extend_from_slice(&body[end..]);
+    Some(Bytes::from(replaced))
+}
+
+// 遍历顶层对象，只保留 model 的原始值；字段重复时视为无法快速替换
+struct ModelVisitor;
+
+impl<'de> Visitor<'de> for ModelVisitor {
+    type Value = Option<&'de RawValue>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON object")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut model = None;
+        let mut duplicated = false;
+        while let Some(key) = map.next_key::<Cow<'de, str>>()? {
+            if key == "model" {
+                duplicated |= model.is_some();
+                model = Some(map.next_value::<&'de RawValue>()?);
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        if duplicated {
+            return Err(de::Error::custom("duplicate model field"));
+        }
+        Ok(model)
+    }
+}
@@ -0,0 +1,84 @@
+use bytes::{Bytes, BytesMut};
+use std::ops::Range;
+
+/// 从 `content-type` 中提取 multipart boundary
+///
+/// 例如 `multipart/form-data; boundary=----abc` 返回 `----abc`
+pub fn boundary(content_type: &str) -> Option<String> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    params.split(';').find_map(|param| {
+        let (key, value) = param.trim().split_once('=')?;
+        if key.eq_ignore_ascii_case("boundary") {
+            Some(value.trim().trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// 读取 multipart 表单中的文本字段
+pub fn extract_field(body: &[u8], boundary: &str, name: &str) -> Option<String> {
+    let range = find_field(body, boundary, name)?;
+    std::str::from_utf8(&body[range])
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// 替换 multipart 表单中文本字段的值，其余部分原样保留
+///
+/// 字段不存在时返回 `None`
+pub fn replace_field(body: &[u8], boundary: &str, name: &str, value: &str) -> Option<Bytes> {
+    let range = find_field(body, boundary, name)?;
+
+    let mut replaced = BytesMut::with_capacity(body.len() + value.len());
+    replaced.extend_from_slice(&body[..range.start]);
+    replaced.extend_from_slice(value.as_bytes());
+    replaced.extend_from_slice(&body[range.end..]);
+    Some(replaced.freeze())
+}
+
+/// 定位字段值在请求体中的字节区间
+fn find_field(body: &[u8], boundary: &str, name: &str) -> Option<Range<usize>> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+    let disposition_name = format!("name=\"{}\"", name.to_ascii_lowercase());
+
+    let mut pos = find(body, delimiter, 0)? + delimiter.len();
+    loop {
+        let next = find(body, delimiter, pos)?;
+
+        // 每个 part：\r\n headers \r\n\r\n value \r\n
+        let part = pos..next;
+        if let Some(header_end) = find(&body[..part.end], b"\r\n\r\n", part.start) {
+            let headers = String::from_utf8_lossy(&body[part.start..header_end]);
+            let is_field = headers.lines().any(|line| {
+                let line = line.to_ascii_lowercase();
+                line.starts_with("content-disposition:") && line.contains(&disposition_name)
+            });
+
+            if is_field {
+                let start = header_end + 4;
+                let end = if body[..next].ends_with(b"\r\n") {
+                    next - 2
+                } else {
+                    next
+                };
+                return Some(start..end.max(start));
+            }
+        }
+
+        pos = next + delimiter.len();
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|p| p + from)
+}
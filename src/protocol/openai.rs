@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OpenAIRequest {
@@ -17,14 +17,64 @@ pub struct OpenAIRequest {
     pub frequency_penalty: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Stop>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<String>,
     #[serde(flatten)]
     pub extra: Value,
 }
 
+/// 停止序列，可以是单个字符串或字符串数组
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Stop {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Stop {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            Self::One(s) => vec![s],
+            Self::Many(v) => v,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub role: String,
-    pub content: MessageContent,
+    #[serde(default)]
+    pub content: Option<MessageContent>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// 未识别的消息字段（如 `name`、`refusal`），同协议转发时原样保留
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    // 部分客户端省略 type，缺省为 function
+    #[serde(rename = "type", default = "default_tool_call_type")]
+    pub call_type: String,
+    pub function: FunctionCall,
+}
+
+fn default_tool_call_type() -> String {
+    "function".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub name: String,
+    pub arguments: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,6 +91,9 @@ pub enum ContentPart {
     Text { text: String },
     #[serde(rename = "image_url")]
     ImageUrl { image_url: ImageUrl },
+    /// 无法转换的内容类型（如 `input_audio`、`file`），解析时接受，转换时丢弃
+    #[serde(other, skip_serializing)]
+    Unsupported,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -70,28 +123,303 @@ pub struct Usage {
     pub prompt_tokens: i32,
     pub completion_tokens: i32,
     pub total_tokens: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens_details: Option<CompletionTokensDetails>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// 输入Token明细，`prompt_tokens` 已包含命中缓存的部分
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PromptTokensDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_tokens: Option<i32>,
+    /// 其他明细（如 `audio_tokens`）原样保留
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// 输出Token明细，`completion_tokens` 已包含推理部分
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CompletionTokensDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_tokens: Option<i32>,
+    /// 其他明细（如 `audio_tokens`）原样保留
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Chat Completions 流式 chunk（SSE `data:` 的 JSON）
+///
+/// 各供应商的 chunk 字段不尽相同，所有字段都可缺省，类型不符的字段按缺省值处理，
+/// 只解析转换和用量统计用到的部分。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct OpenAIStreamChunk {
-    pub id: String,
-    pub object: String,
-    pub created: i64,
-    pub model: String,
+    #[serde(
+        default,
+        deserialize_with = "crate::protocol::lenient",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub id: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "crate::protocol::lenient",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub model: Option<String>,
+    #[serde(default, deserialize_with = "crate::protocol::lenient")]
     pub choices: Vec<StreamChoice>,
+    /// 用量，通常只在最后一个 chunk 中出现
+    #[serde(
+        default,
+        deserialize_with = "crate::protocol::lenient",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub usage: Option<ChunkUsage>,
+    /// 流中途的错误
+    #[serde(
+        default,
+        deserialize_with = "crate::protocol::lenient",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub error: Option<StreamError>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct StreamChoice {
-    pub index: i32,
+    #[serde(default, deserialize_with = "crate::protocol::lenient")]
+    pub index: u32,
+    #[serde(default, deserialize_with = "crate::protocol::lenient")]
     pub delta: Delta,
+    #[serde(default, deserialize_with = "crate::protocol::lenient")]
     pub finish_reason: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct Delta {
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "crate::protocol::lenient",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub role: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        deserialize_with = "crate::protocol::lenient",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub content: Option<String>,
-}
\ No newline at end of file
+    /// 推理模型（DeepSeek 等兼容接口）输出的推理内容
+    #[serde(
+        default,
+        deserialize_with = "crate::protocol::lenient",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub reasoning_content: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "crate::protocol::lenient",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub tool_calls: Vec<ToolCallDelta>,
+    #[serde(
+        default,
+        deserialize_with = "crate::protocol::lenient",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub annotations: Vec<Annotation>,
+}
+
+/// 工具调用片段：首个片段带 id 和函数名，后续片段只有参数增量
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct ToolCallDelta {
+    #[serde(default)]
+    pub index: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function: Option<FunctionCallDelta>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FunctionCallDelta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+}
+
+/// 内容标注
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Annotation {
+    UrlCitation {
+        url_citation: UrlCitation,
+    },
+    /// 尚未支持的标注类型
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UrlCitation {
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub start_index: usize,
+    #[serde(default)]
+    pub end_index: usize,
+}
+
+/// 流式响应中的用量，兼容 Chat Completions（`prompt_tokens` / `completion_tokens`）和
+/// Responses API（`input_tokens` / `output_tokens`）两种字段名，上游未返回的项为空
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChunkUsage {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_tokens: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_details: Option<TokensDetails>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens_details: Option<TokensDetails>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_tokens: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_tokens: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_tokens_details: Option<TokensDetails>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_tokens_details: Option<TokensDetails>,
+}
+
+/// 用量明细中的缓存和推理Token数
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokensDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_tokens: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning_tokens: Option<i64>,
+}
+
+/// 流式错误
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StreamError {
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default, rename = "type")]
+    pub error_type: Option<String>,
+    /// 错误码，部分供应商为数字
+    #[serde(default)]
+    pub code: Option<Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(value: Value) -> OpenAIStreamChunk {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn chunk_parses_text_and_tool_call_fragments() {
+        let chunk = parse(json!({
+            "id": "chatcmpl-1",
+            "model": "gpt-4o",
+            "choices": [{"index": 0, "delta": {
+                "content": "Hi",
+                "tool_calls": [{"index": 1, "id": "call_1", "type": "function",
+                    "function": {"name": "f", "arguments": "{"}}],
+            }, "finish_reason": null}],
+        }));
+        assert_eq!(chunk.id.as_deref(), Some("chatcmpl-1"));
+        let delta = &chunk.choices[0].delta;
+        assert_eq!(delta.content.as_deref(), Some("Hi"));
+        let call = &delta.tool_calls[0];
+        assert_eq!(call.index, 1);
+        assert_eq!(call.id.as_deref(), Some("call_1"));
+        let function = call.function.as_ref().unwrap();
+        assert_eq!(function.name.as_deref(), Some("f"));
+        assert_eq!(function.arguments.as_deref(), Some("{"));
+    }
+
+    #[test]
+    fn mistyped_fields_fall_back_to_defaults() {
+        let chunk = parse(json!({
+            "id": 42,
+            "model": null,
+            "choices": [{"index": "0", "delta": {"content": ["not", "text"], "role": "assistant"},
+                "finish_reason": 1}],
+            "usage": "none",
+        }));
+        assert_eq!(chunk.id, None);
+        assert_eq!(chunk.model, None);
+        assert!(chunk.usage.is_none());
+        let choice = &chunk.choices[0];
+        assert_eq!(choice.index, 0);
+        assert_eq!(choice.delta.content, None);
+        assert_eq!(choice.delta.role.as_deref(), Some("assistant"));
+        assert_eq!(choice.finish_reason, None);
+
+        let chunk = parse(json!({"choices": "oops"}));
+        assert!(chunk.choices.is_empty());
+    }
+
+    #[test]
+    fn unknown_annotations_are_kept_as_unknown() {
+        let chunk = parse(json!({"choices": [{"delta": {"annotations": [
+            {"type": "url_citation", "url_citation": {"url": "https://example.com", "start_index": 0, "end_index": 4}},
+            {"type": "file_citation", "file_id": "file_1"},
+        ]}}]}));
+        let annotations = &chunk.choices[0].delta.annotations;
+        assert!(matches!(
+            &annotations[0],
+            Annotation::UrlCitation { url_citation } if url_citation.end_index == 4
+        ));
+        assert!(matches!(annotations[1], Annotation::Unknown));
+    }
+
+    #[test]
+    fn usage_accepts_both_field_conventions() {
+        let chunk = parse(json!({"choices": [], "usage": {
+            "prompt_tokens": 10, "completion_tokens": 5,
+            "prompt_tokens_details": {"cached_tokens": 4},
+        }}));
+        let usage = chunk.usage.unwrap();
+        assert_eq!(usage.prompt_tokens, Some(10));
+        assert_eq!(usage.prompt_tokens_details.unwrap().cached_tokens, Some(4));
+
+        let chunk = parse(json!({"usage": {
+            "input_tokens": 10, "output_tokens": 5,
+            "output_tokens_details": {"reasoning_tokens": 3},
+        }}));
+        let usage = chunk.usage.unwrap();
+        assert_eq!(usage.input_tokens, Some(10));
+        assert_eq!(usage.prompt_tokens, None);
+        assert_eq!(
+            usage.output_tokens_details.unwrap().reasoning_tokens,
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn error_chunk_accepts_numeric_code() {
+        let chunk =
+            parse(json!({"error": {"message": "overloaded", "type": "server_error", "code": 529}}));
+        let error = chunk.error.unwrap();
+        assert_eq!(error.message.as_deref(), Some("overloaded"));
+        assert_eq!(error.error_type.as_deref(), Some("server_error"));
+        assert_eq!(error.code, Some(json!(529)));
+    }
+}
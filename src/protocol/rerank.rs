@@ -0,0 +1,170 @@
+//! 重排序（rerank）接口
+//!
+//! 网关对外提供统一的 `/v1/rerank` 请求格式（与 Cohere / Jina 一致）：
+//! `{"model","query","documents":[..],"top_n","return_documents"}`，
+//! 文档可以是字符串或带 `text` 字段的对象。转发时按路由的 `rerank_provider`
+//! 转换为供应商格式，响应统一规范化为：
+//! `{"id","model","results":[{"index","relevance_score","document":{"text"}}],"usage":{..}}`。
+//! 各供应商返回文档原文的方式不同，`document` 由网关按序号从请求中回填。
+
+use crate::error::{Error, Result};
+use crate::models::RerankProvider;
+use bytes::Bytes;
+use serde_json::{json, Value};
+
+/// 网关统一的重排序请求
+#[derive(Debug, Clone)]
+pub struct RerankRequest {
+    pub model: String,
+    pub query: String,
+    pub documents: Vec<String>,
+    pub top_n: Option<u64>,
+    pub return_documents: bool,
+}
+
+/// 重排序用量
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RerankUsage {
+    /// 供应商按Token计费时的Token数（Voyage、Jina）
+    pub total_tokens: Option<u64>,
+    /// 供应商按搜索单元计费时的单元数（Cohere）
+    pub search_units: Option<u64>,
+}
+
+impl RerankRequest {
+    pub fn parse(body: &[u8]) -> Result<Self> {
+        let v: Value = serde_json::from_slice(body)?;
+        let text = |key: &str| {
+            v.get(key)
+                .and_then(|s| s.as_str())
+                .map(str::to_string)
+                .ok_or_else(|| Error::Protocol(format!("Missing {} field", key)))
+        };
+
+        let documents = v
+            .get("documents")
+            .and_then(|d| d.as_array())
+            .ok_or_else(|| Error::Protocol("Missing documents field".to_string()))?
+            .iter()
+            .map(|doc| match doc {
+                Value::String(text) => Some(text.clone()),
+                _ => doc.get("text")?.as_str().map(str::to_string),
+            })
+            .collect::<Option<Vec<String>>>()
+            .ok_or_else(|| {
+                Error::Protocol("Documents must be strings or objects with text".to_string())
+            })?;
+
+        Ok(Self {
+            model: text("model")?,
+            query: text("query")?,
+            documents,
+            top_n: v.get("top_n").and_then(|n| n.as_u64()),
+            return_documents: v
+                .get("return_documents")
+                .and_then(|r| r.as_bool())
+                .unwrap_or(false),
+        })
+    }
+
+    /// 转换为供应商的请求体，`model` 为上游模型名
+    ///
+    /// 文档原文由网关回填，不要求供应商返回
+    pub fn to_provider(&self, provider: RerankProvider, model: &str) -> Bytes {
+        let mut body = json!({
+            "model": model,
+            "query": self.query,
+            "documents": self.documents,
+        });
+        match provider {
+            RerankProvider::Cohere => {
+                if let Some(top_n) = self.top_n {
+                    body["top_n"] = json!(top_n);
+                }
+            }
+            RerankProvider::Voyage => {
+                if let Some(top_n) = self.top_n {
+                    body["top_k"] = json!(top_n);
+                }
+                body["return_documents"] = json!(false);
+            }
+            RerankProvider::Jina => {
+                if let Some(top_n) = self.top_n {
+                    body["top_n"] = json!(top_n);
+                }
+                body["return_documents"] = json!(false);
+            }
+        }
+        Bytes::from(body.to_string())
+    }
+
+    /// 将供应商响应规范化为网关格式，返回响应体和用量
+    pub fn normalize_response(
+        &self,
+        provider: RerankProvider,
+        body: &[u8],
+    ) -> Result<(Bytes, RerankUsage)> {
+        let v: Value = serde_json::from_slice(body)?;
+
+        // Voyage 的结果在 data 中，Cohere、Jina 在 results 中
+        let results_key = match provider {
+            RerankProvider::Voyage => "data",
+            RerankProvider::Cohere | RerankProvider::Jina => "results",
+        };
+        let results = v
+            .get(results_key)
+            .and_then(|r| r.as_array())
+            .ok_or_else(|| Error::Protocol(format!("Rerank response missing {}", results_key)))?
+            .iter()
+            .filter_map(|result| {
+                let index = result.get("index")?.as_u64()?;
+                let score = result.get("relevance_score")?.as_f64()?;
+                let mut normalized = json!({"index": index, "relevance_score": score});
+                if self.return_documents {
+                    let text = self.documents.get(index as usize)?;
+                    normalized["document"] = json!({ "text": text });
+                }
+                Some(normalized)
+            })
+            .collect::<Vec<Value>>();
+
+        let usage = match provider {
+            RerankProvider::Cohere => RerankUsage {
+                total_tokens: None,
+                search_units: v
+                    .pointer("/meta/billed_units/search_units")
+                    .and_then(|u| u.as_u64()),
+            },
+            RerankProvider::Voyage | RerankProvider::Jina => RerankUsage {
+                total_tokens: v.pointer("/usage/total_tokens").and_then(|t| t.as_u64()),
+                search_units: None,
+            },
+        };
+
+        let mut usage_json = json!({});
+        if let Some(tokens) = usage.total_tokens {
+            usage_json["total_tokens"] = json!(tokens);
+        }
+        if let Some(units) = usage.search_units {
+            usage_json["search_units"] = json!(units);
+        }
+        let mut normalized = json!({
+            "model": self.model,
+            "results": results,
+            "usage": usage_json,
+        });
+        if let Some(id) = v.get("id").and_then(|id| id.as_str()) {
+            normalized["id"] = json!(id);
+        }
+
+        Ok((Bytes::from(normalized.to_string()), usage))
+    }
+}
+
+/// 供应商的默认重排序接口路径
+pub fn default_path(provider: RerankProvider) -> &'static str {
+    match provider {
+        RerankProvider::Cohere => "/v2/rerank",
+        RerankProvider::Voyage | RerankProvider::Jina => "/v1/rerank",
+    }
+}
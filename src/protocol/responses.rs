@@ -0,0 +1,1401 @@
+//! OpenAI Responses API 与 Chat Completions 之间的转换
+//!
+//! Responses API 客户端（`/v1/responses`）路由到只支持 Chat Completions 的上游（或非 OpenAI 上游）时，
+//! 请求转换为 Chat Completions 格式，响应（含流式事件）再转换回 Responses 格式；
+//! Chat Completions 客户端路由到只支持 Responses API 的上游时反向转换。
+//!
+//! 工具调用双向对应：输入中的 `function_call` / `function_call_output` 项对应助手消息的 `tool_calls`
+//! 和 `role: "tool"` 的消息；流式响应中 `delta.tool_calls` 片段对应 `response.output_item.added`、
+//! `response.function_call_arguments.delta` 等事件。内置工具（如 `web_search`）无法转换，转换时丢弃；
+//! 上游保存的会话状态（`previous_response_id`）无法转换，带有该字段的请求不转发到需要转换的路由。
+
+use crate::error::{Error, Result};
+use crate::models::{OpenAIApi, RouteConfig, TargetProtocol};
+use crate::protocol::framing::sse_events;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::pin::Pin;
+use tracing::{debug, warn};
+
+/// Responses API 的请求路径
+pub const RESPONSES_PATH: &str = "/v1/responses";
+
+/// 客户端接口与上游接口之间的转换方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bridge {
+    /// Responses API 请求转换为 Chat Completions 发往上游
+    ToChat,
+    /// Chat Completions 请求转换为 Responses API 发往上游
+    ToResponses,
+}
+
+/// 请求在客户端接口和路由上游之间需要的转换
+///
+/// Responses API 请求发往声明只支持 Chat Completions 的 OpenAI 上游或非 OpenAI 上游时转换为 Chat Completions；
+/// 其他请求（已由协议适配器转换为 Chat Completions）发往只支持 Responses API 的 OpenAI 上游时转换为 Responses API。
+pub fn bridge(responses_client: bool, config: &RouteConfig) -> Option<Bridge> {
+    let openai = matches!(config.protocol, TargetProtocol::OpenAI);
+    match config.openai_api {
+        _ if responses_client && !openai => Some(Bridge::ToChat),
+        Some(OpenAIApi::Chat) if responses_client => Some(Bridge::ToChat),
+        Some(OpenAIApi::Responses) if !responses_client && openai => Some(Bridge::ToResponses),
+        _ => None,
+    }
+}
+
+/// 上游请求路径：发往 Responses API 时为 `/v1/responses`，否则使用路由协议的默认路径
+pub fn upstream_path(responses_client: bool, bridge: Option<Bridge>) -> Option<&'static str> {
+    match bridge {
+        Some(Bridge::ToResponses) => Some(RESPONSES_PATH),
+        Some(Bridge::ToChat) => None,
+        None => responses_client.then_some(RESPONSES_PATH),
+    }
+}
+
+// ================== Responses API -> Chat Completions ==================
+
+/// 将 Responses API 请求转换为 Chat Completions 请求
+///
+/// 流式请求附带 `stream_options.include_usage`，以便从最后的用量 chunk 收集用量。
+pub fn request_to_chat(body: &[u8]) -> Result<Bytes> {
+    let Value::Object(mut req) = serde_json::from_slice::<Value>(body)? else {
+        return Err(Error::Protocol(
+            "Responses request must be a JSON object".to_string(),
+        ));
+    };
+
+    let mut messages: Vec<Value> = Vec::new();
+    if let Some(Value::String(instructions)) = req.remove("instructions") {
+        messages.push(json!({"role": "system", "content": instructions}));
+    }
+    match req.remove("input") {
+        Some(Value::String(text)) => messages.push(json!({"role": "user", "content": text})),
+        Some(Value::Array(items)) => {
+            for item in items {
+                push_input_item(&mut messages, item);
+            }
+        }
+        _ => {}
+    }
+    if continues_response(&req) {
+        return Err(Error::Protocol(PREVIOUS_RESPONSE_UNSUPPORTED.to_string()));
+    }
+
+    let mut chat = Map::new();
+    chat.insert(
+        "model".to_string(),
+        req.remove("model").unwrap_or(Value::Null),
+    );
+    chat.insert("messages".to_string(), Value::Array(messages));
+    for key in [
+        "stream",
+        "temperature",
+        "top_p",
+        "parallel_tool_calls",
+        "user",
+        "service_tier",
+    ] {
+        if let Some(value) = req.remove(key) {
+            chat.insert(key.to_string(), value);
+        }
+    }
+    if let Some(max) = req.remove("max_output_tokens") {
+        chat.insert("max_tokens".to_string(), max);
+    }
+    if let Some(tools) = req.get("tools").and_then(Value::as_array) {
+        let tools: Vec<Value> = tools.iter().filter_map(tool_to_chat).collect();
+        if !tools.is_empty() {
+            chat.insert("tools".to_string(), Value::Array(tools));
+        }
+    }
+    if let Some(choice) = req.remove("tool_choice") {
+        chat.insert("tool_choice".to_string(), tool_choice_to_chat(choice));
+    }
+    if let Some(format) = req
+        .get("text")
+        .and_then(|text| text.get("format"))
+        .and_then(format_to_chat)
+    {
+        chat.insert("response_format".to_string(), format);
+    }
+    if let Some(effort) = req.get("reasoning").and_then(|r| r.get("effort")) {
+        chat.insert("reasoning_effort".to_string(), effort.clone());
+    }
+    if chat.get("stream").and_then(Value::as_bool) == Some(true) {
+        chat.insert("stream_options".to_string(), json!({"include_usage": true}));
+    }
+
+    Ok(Bytes::from(serde_json::to_vec(&chat)?))
+}
+
+/// 路由无法延续上游保存的会话时的错误消息
+pub const PREVIOUS_RESPONSE_UNSUPPORTED: &str =
+    "previous_response_id is not supported for this model: resend the full conversation in input";
+
+/// Responses API 请求是否延续上游保存的会话（`previous_response_id`）
+///
+/// 会话只保存在原上游，转换为 Chat Completions 后模型看不到历史消息，这类请求只能发往
+/// 原生支持 Responses API 的路由。
+pub fn continues_previous_response(body: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|req| req.as_object().map(continues_response))
+        .unwrap_or(false)
+}
+
+fn continues_response(req: &Map<String, Value>) -> bool {
+    req.get("previous_response_id")
+        .is_some_and(|id| !id.is_null())
+}
+
+// 输入项转换为消息：连续的函数调用合并到前一条助手消息
+fn push_input_item(messages: &mut Vec<Value>, item: Value) {
+    match item
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or("message")
+    {
+        "message" => {
+            let role = item.get("role").and_then(Value::as_str).unwrap_or("user");
+            messages.push(json!({
+                "role": role,
+                "content": content_to_chat(item.get("content")),
+            }));
+        }
+        "function_call" => {
+            let call = json!({
+                "id": item["call_id"],
+                "type": "function",
+                "function": {
+                    "name": item["name"],
+                    "arguments": item.get("arguments").and_then(Value::as_str).unwrap_or("{}"),
+                },
+            });
+            match messages.last_mut() {
+                Some(last) if last["role"] == "assistant" => {
+                    match last["tool_calls"].as_array_mut() {
+                        Some(calls) => calls.push(call),
+                        None => last["tool_calls"] = json!([call]),
+                    }
+                }
+                _ => messages.push(json!({
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [call],
+                })),
+            }
+        }
+        "function_call_output" => {
+            let output = match item.get("output") {
+                Some(Value::String(output)) => output.clone(),
+                Some(Value::Array(parts)) => parts_text(parts),
+                Some(other) => other.to_string(),
+                None => String::new(),
+            };
+            messages.push(json!({
+                "role": "tool",
+                "tool_call_id": item["call_id"],
+                "content": output,
+            }));
+        }
+        other => debug!("Dropping Responses input item of type {}", other),
+    }
+}
+
+// 消息内容：只有文本时合并为字符串，含图片时转换为内容数组
+fn content_to_chat(content: Option<&Value>) -> Value {
+    let parts = match content {
+        Some(Value::Array(parts)) => parts,
+        Some(other) => return other.clone(),
+        None => return Value::Null,
+    };
+    if parts.iter().all(|part| part.get("text").is_some()) {
+        return json!(parts_text(parts));
+    }
+    let parts: Vec<Value> = parts
+        .iter()
+        .filter_map(|part| match part["type"].as_str() {
+            Some("input_text" | "output_text" | "text") => {
+                Some(json!({"type": "text", "text": part["text"]}))
+            }
+            Some("input_image") => {
+                let mut image_url = json!({"url": part["image_url"]});
+                if let Some(detail) = part.get("detail") {
+                    image_url["detail"] = detail.clone();
+                }
+                Some(json!({"type": "image_url", "image_url": image_url}))
+            }
+            other => {
+                warn!("Dropping unsupported Responses content part {:?}", other);
+                None
+            }
+        })
+        .collect();
+    Value::Array(parts)
+}
+
+fn parts_text(parts: &[Value]) -> String {
+    parts
+        .iter()
+        .filter_map(|part| part.get("text").and_then(Value::as_str))
+        .collect()
+}
+
+// 函数工具 `{type, name, ...}` 转换为 `{type, function: {name, ...}}`，内置工具丢弃
+fn tool_to_chat(tool: &Value) -> Option<Value> {
+    if tool["type"] != "function" {
+        warn!(
+            "Dropping built-in tool {:?}: not supported on Chat Completions upstreams",
+            tool["type"]
+        );
+        return None;
+    }
+    let mut function = Map::new();
+    for key in ["name", "description", "parameters", "strict"] {
+        if let Some(value) = tool.get(key).filter(|v| !v.is_null()) {
+            function.insert(key.to_string(), value.clone());
+        }
+    }
+    Some(json!({"type": "function", "function": function}))
+}
+
+fn tool_choice_to_chat(choice: Value) -> Value {
+    match choice {
+        Value::Object(choice) if choice.get("type") == Some(&json!("function")) => {
+            json!({"type": "function", "function": {"name": choice.get("name")}})
+        }
+        // 指定内置工具无法转换
+        Value::Object(_) => json!("auto"),
+        // "none" / "auto" / "required"
+        other => other,
+    }
+}
+
+fn format_to_chat(format: &Value) -> Option<Value> {
+    match format["type"].as_str()? {
+        "json_schema" => {
+            let mut schema = format.clone();
+            let schema = schema.as_object_mut()?;
+            schema.remove("type");
+            Some(json!({"type": "json_schema", "json_schema": schema}))
+        }
+        "json_object" => Some(json!({"type": "json_object"})),
+        _ => None,
+    }
+}
+
+/// 将 Chat Completions 非流式响应转换为 Responses API 响应
+pub fn response_from_chat(body: &[u8]) -> Result<Bytes> {
+    let chat: Value = serde_json::from_slice(body)?;
+    let choice = &chat["choices"][0];
+    let message = &choice["message"];
+    let id = chat["id"].as_str().unwrap_or("unknown");
+
+    let mut output = Vec::new();
+    let mut content = Vec::new();
+    if let Some(text) = message["content"].as_str().filter(|t| !t.is_empty()) {
+        content.push(json!({"type": "output_text", "text": text, "annotations": []}));
+    }
+    if let Some(refusal) = message["refusal"].as_str() {
+        content.push(json!({"type": "refusal", "refusal": refusal}));
+    }
+    if !content.is_empty() {
+        output.push(json!({
+            "type": "message",
+            "id": format!("msg_{}", id),
+            "status": "completed",
+            "role": "assistant",
+            "content": content,
+        }));
+    }
+    for call in message["tool_calls"].as_array().into_iter().flatten() {
+        output.push(function_call_item(
+            call["id"].as_str().unwrap_or_default(),
+            call["function"]["name"].as_str().unwrap_or_default(),
+            call["function"]["arguments"].as_str().unwrap_or_default(),
+            "completed",
+        ));
+    }
+
+    let mut response = json!({
+        "id": format!("resp_{}", id),
+        "object": "response",
+        "created_at": chat["created"],
+        "model": chat["model"],
+        "output": output,
+        "usage": usage_from_chat(&chat["usage"]),
+    });
+    set_status(&mut response, choice["finish_reason"].as_str());
+    Ok(Bytes::from(serde_json::to_vec(&response)?))
+}
+
+fn function_call_item(call_id: &str, name: &str, arguments: &str, status: &str) -> Value {
+    json!({
+        "type": "function_call",
+        "id": format!("fc_{}", call_id),
+        "call_id": call_id,
+        "name": name,
+        "arguments": arguments,
+        "status": status,
+    })
+}
+
+// 按 Chat Completions 的结束原因设置响应状态
+fn set_status(response: &mut Value, finish_reason: Option<&str>) {
+    let incomplete = match finish_reason {
+        Some("length") => Some("max_output_tokens"),
+        Some("content_filter") => Some("content_filter"),
+        _ => None,
+    };
+    match incomplete {
+        Some(reason) => {
+            response["status"] = json!("incomplete");
+            response["incomplete_details"] = json!({"reason": reason});
+        }
+        None => {
+            response["status"] = json!("completed");
+            response["incomplete_details"] = Value::Null;
+        }
+    }
+}
+
+fn usage_from_chat(usage: &Value) -> Value {
+    if usage.is_null() {
+        return Value::Null;
+    }
+    json!({
+        "input_tokens": usage["prompt_tokens"].as_u64().unwrap_or(0),
+        "output_tokens": usage["completion_tokens"].as_u64().unwrap_or(0),
+        "total_tokens": usage["total_tokens"].as_u64().unwrap_or(0),
+        "input_tokens_details": {
+            "cached_tokens": usage
+                .pointer("/prompt_tokens_details/cached_tokens")
+                .and_then(Value::as_u64)
+                .unwrap_or(0),
+        },
+        "output_tokens_details": {
+            "reasoning_tokens": usage
+                .pointer("/completion_tokens_details/reasoning_tokens")
+                .and_then(Value::as_u64)
+                .unwrap_or(0),
+        },
+    })
+}
+
+/// 将 Chat Completions 流式响应（标准 SSE）转换为 Responses API 事件流
+pub fn stream_from_chat<S>(stream: S) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    Box::pin(async_stream::stream! {
+        let mut events = sse_events(stream);
+        let mut writer = ResponsesEventWriter::default();
+
+        while let Some(event) = events.next().await {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            let mut out = String::new();
+            if event.data == "[DONE]" {
+                writer.finish(&mut out);
+            } else if let Ok(chunk) = serde_json::from_str::<Value>(&event.data) {
+                writer.push_chunk(&chunk, &mut out);
+            }
+            if !out.is_empty() {
+                yield Ok(Bytes::from(out));
+            }
+        }
+
+        // 上游没有发送 [DONE] 时在流结束处补齐
+        let mut out = String::new();
+        writer.finish(&mut out);
+        if !out.is_empty() {
+            yield Ok(Bytes::from(out));
+        }
+    })
+}
+
+// 正在输出的助手消息
+struct OpenMessage {
+    output_index: usize,
+    id: String,
+    text: String,
+}
+
+// 正在输出的函数调用
+struct OpenCall {
+    output_index: usize,
+    call_id: String,
+    name: String,
+    arguments: String,
+}
+
+// 由 Chat Completions chunk 生成 Responses API 事件
+#[derive(Default)]
+struct ResponsesEventWriter {
+    id: String,
+    model: Value,
+    created_at: Value,
+    sequence: u64,
+    started: bool,
+    finished: bool,
+    // 已完成的输出项，按 output_index 排列
+    output: Vec<Value>,
+    message: Option<OpenMessage>,
+    // Chat Completions 工具调用序号 -> 调用
+    calls: BTreeMap<u64, OpenCall>,
+    finish_reason: Option<String>,
+    usage: Value,
+}
+
+impl ResponsesEventWriter {
+    fn emit(&mut self, out: &mut String, kind: &str, mut data: Value) {
+        data["type"] = json!(kind);
+        data["sequence_number"] = json!(self.sequence);
+        self.sequence += 1;
+        out.push_str(&format!("event: {}\ndata: {}\n\n", kind, data));
+    }
+
+    fn snapshot(&self, status: &str) -> Value {
+        json!({
+            "id": self.id,
+            "object": "response",
+            "created_at": self.created_at,
+            "status": status,
+            "model": self.model,
+            "output": self.output,
+            "usage": self.usage,
+        })
+    }
+
+    fn next_output_index(&self) -> usize {
+        self.output.len() + usize::from(self.message.is_some()) + self.calls.len()
+    }
+
+    fn push_chunk(&mut self, chunk: &Value, out: &mut String) {
+        if self.finished {
+            return;
+        }
+        if let Some(error) = chunk.get("error").filter(|e| e.is_object()) {
+            self.fail(error, out);
+            return;
+        }
+        if !self.started {
+            self.started = true;
+            self.id = format!("resp_{}", chunk["id"].as_str().unwrap_or("unknown"));
+            self.model = chunk["model"].clone();
+            self.created_at = match &chunk["created"] {
+                Value::Number(created) => Value::Number(created.clone()),
+                _ => json!(chrono::Utc::now().timestamp()),
+            };
+            let response = self.snapshot("in_progress");
+            self.emit(out, "response.created", json!({"response": response}));
+            let response = self.snapshot("in_progress");
+            self.emit(out, "response.in_progress", json!({"response": response}));
+        }
+        if chunk.get("usage").is_some_and(|u| !u.is_null()) {
+            self.usage = usage_from_chat(&chunk["usage"]);
+        }
+
+        let Some(choice) = chunk["choices"].as_array().and_then(|c| c.first()) else {
+            return;
+        };
+        let delta = &choice["delta"];
+        if let Some(text) = delta["content"].as_str().filter(|t| !t.is_empty()) {
+            self.push_text(text, out);
+        }
+        for call in delta["tool_calls"].as_array().into_iter().flatten() {
+            self.push_tool_call(call, out);
+        }
+        if let Some(reason) = choice["finish_reason"].as_str() {
+            self.finish_reason = Some(reason.to_string());
+        }
+    }
+
+    fn push_text(&mut self, text: &str, out: &mut String) {
+        if self.message.is_none() {
+            let output_index = self.next_output_index();
+            let id = format!("msg_{}", self.id.trim_start_matches("resp_"));
+            self.emit(
+                out,
+                "response.output_item.added",
+                json!({
+                    "output_index": output_index,
+                    "item": {
+                        "type": "message",
+                        "id": id,
+                        "status": "in_progress",
+                        "role": "assistant",
+                        "content": [],
+                    },
+                }),
+            );
+            self.emit(
+                out,
+                "response.content_part.added",
+                json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "part": {"type": "output_text", "text": "", "annotations": []},
+                }),
+            );
+            self.message = Some(OpenMessage {
+                output_index,
+                id,
+                text: String::new(),
+            });
+        }
+        let Some(message) = self.message.as_mut() else {
+            return;
+        };
+        message.text.push_str(text);
+        let data = json!({
+            "item_id": message.id,
+            "output_index": message.output_index,
+            "content_index": 0,
+            "delta": text,
+        });
+        self.emit(out, "response.output_text.delta", data);
+    }
+
+    fn push_tool_call(&mut self, call: &Value, out: &mut String) {
+        let index = call["index"].as_u64().unwrap_or(0);
+        if !self.calls.contains_key(&index) {
+            // 函数调用开始后不再有文本，先结束正在输出的消息
+            self.close_message(out);
+            let output_index = self.next_output_index();
+            let call_id = call["id"]
+                .as_str()
+                .map_or_else(|| format!("call_{}_{}", self.id, index), str::to_string);
+            let name = call["function"]["name"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            let item = function_call_item(&call_id, &name, "", "in_progress");
+            self.emit(
+                out,
+                "response.output_item.added",
+                json!({"output_index": output_index, "item": item}),
+            );
+            self.calls.insert(
+                index,
+                OpenCall {
+                    output_index,
+                    call_id,
+                    name,
+                    arguments: String::new(),
+                },
+            );
+        }
+
+        let arguments = call["function"]["arguments"].as_str().unwrap_or_default();
+        if arguments.is_empty() {
+            return;
+        }
+        let Some(open) = self.calls.get_mut(&index) else {
+            return;
+        };
+        open.arguments.push_str(arguments);
+        let data = json!({
+            "item_id": format!("fc_{}", open.call_id),
+            "output_index": open.output_index,
+            "delta": arguments,
+        });
+        self.emit(out, "response.function_call_arguments.delta", data);
+    }
+
+    fn close_message(&mut self, out: &mut String) {
+        let Some(message) = self.message.take() else {
+            return;
+        };
+        let part = json!({"type": "output_text", "text": message.text, "annotations": []});
+        self.emit(
+            out,
+            "response.output_text.done",
+            json!({
+                "item_id": message.id,
+                "output_index": message.output_index,
+                "content_index": 0,
+                "text": message.text,
+            }),
+        );
+        self.emit(
+            out,
+            "response.content_part.done",
+            json!({
+                "item_id": message.id,
+                "output_index": message.output_index,
+                "content_index": 0,
+                "part": part,
+            }),
+        );
+        let item = json!({
+            "type": "message",
+            "id": message.id,
+            "status": "completed",
+            "role": "assistant",
+            "content": [part],
+        });
+        self.emit(
+            out,
+            "response.output_item.done",
+            json!({"output_index": message.output_index, "item": item}),
+        );
+        self.output.push(item);
+    }
+
+    // 上游错误结束响应：发送 error 事件，已开始的响应以 response.failed 结束，之后不再发送 completed
+    fn fail(&mut self, error: &Value, out: &mut String) {
+        self.finished = true;
+        let code = error["type"].clone();
+        let message = error["message"].as_str().unwrap_or("Upstream error");
+        self.emit(
+            out,
+            "error",
+            json!({"code": code, "message": message, "param": null}),
+        );
+        if self.started {
+            let mut response = self.snapshot("failed");
+            response["error"] = json!({"code": code, "message": message});
+            self.emit(out, "response.failed", json!({"response": response}));
+        }
+    }
+
+    fn finish(&mut self, out: &mut String) {
+        if self.finished || !self.started {
+            return;
+        }
+        self.finished = true;
+        // 按 output_index 顺序结束各输出项（函数调用之后又输出文本时消息排在最后）
+        let message_first = self.message.as_ref().is_none_or(|message| {
+            self.calls
+                .values()
+                .all(|call| call.output_index > message.output_index)
+        });
+        if message_first {
+            self.close_message(out);
+        }
+        for (_, call) in std::mem::take(&mut self.calls) {
+            let item = function_call_item(&call.call_id, &call.name, &call.arguments, "completed");
+            self.emit(
+                out,
+                "response.function_call_arguments.done",
+                json!({
+                    "item_id": item["id"],
+                    "output_index": call.output_index,
+                    "arguments": call.arguments,
+                }),
+            );
+            self.emit(
+                out,
+                "response.output_item.done",
+                json!({"output_index": call.output_index, "item": item}),
+            );
+            self.output.push(item);
+        }
+        self.close_message(out);
+
+        let mut response = self.snapshot("completed");
+        set_status(&mut response, self.finish_reason.as_deref());
+        let kind = if response["status"] == "incomplete" {
+            "response.incomplete"
+        } else {
+            "response.completed"
+        };
+        self.emit(out, kind, json!({"response": response}));
+    }
+}
+
+// ================== Chat Completions -> Responses API ==================
+
+/// 将 Chat Completions 请求转换为 Responses API 请求，不在上游保存会话状态（`store: false`）
+pub fn request_from_chat(body: &[u8]) -> Result<Bytes> {
+    let Value::Object(mut req) = serde_json::from_slice::<Value>(body)? else {
+        return Err(Error::Protocol(
+            "Chat Completions request must be a JSON object".to_string(),
+        ));
+    };
+
+    let mut input = Vec::new();
+    let messages = match req.remove("messages") {
+        Some(Value::Array(messages)) => messages,
+        _ => Vec::new(),
+    };
+    for message in &messages {
+        push_message_items(&mut input, message);
+    }
+
+    let mut responses = Map::new();
+    responses.insert(
+        "model".to_string(),
+        req.remove("model").unwrap_or(Value::Null),
+    );
+    responses.insert("input".to_string(), Value::Array(input));
+    responses.insert("store".to_string(), json!(false));
+    for key in [
+        "stream",
+        "temperature",
+        "top_p",
+        "parallel_tool_calls",
+        "user",
+        "service_tier",
+    ] {
+        if let Some(value) = req.remove(key) {
+            responses.insert(key.to_string(), value);
+        }
+    }
+    if let Some(max) = req
+        .remove("max_completion_tokens")
+        .or_else(|| req.remove("max_tokens"))
+    {
+        responses.insert("max_output_tokens".to_string(), max);
+    }
+    if let Some(tools) = req.get("tools").and_then(Value::as_array) {
+        let tools: Vec<Value> = tools.iter().filter_map(tool_from_chat).collect();
+        if !tools.is_empty() {
+            responses.insert("tools".to_string(), Value::Array(tools));
+        }
+    }
+    if let Some(choice) = req.remove("tool_choice") {
+        responses.insert("tool_choice".to_string(), tool_choice_from_chat(choice));
+    }
+    if let Some(format) = req.get("response_format").and_then(format_from_chat) {
+        responses.insert("text".to_string(), json!({"format": format}));
+    }
+    if let Some(effort) = req.remove("reasoning_effort") {
+        responses.insert("reasoning".to_string(), json!({"effort": effort}));
+    }
+
+    Ok(Bytes::from(serde_json::to_vec(&responses)?))
+}
+
+// 消息转换为输入项：助手消息的工具调用拆分为 function_call 项，工具结果转换为 function_call_output 项
+fn push_message_items(input: &mut Vec<Value>, message: &Value) {
+    let role = message["role"].as_str().unwrap_or("user");
+    match role {
+        "tool" => {
+            let output = match &message["content"] {
+                Value::Array(parts) => parts_text(parts),
+                Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            input.push(json!({
+                "type": "function_call_output",
+                "call_id": message["tool_call_id"],
+                "output": output,
+            }));
+        }
+        "assistant" => {
+            let text = match &message["content"] {
+                Value::Array(parts) => parts_text(parts),
+                Value::String(text) => text.clone(),
+                _ => String::new(),
+            };
+            if !text.is_empty() {
+                input.push(json!({
+                    "type": "message",
+                    "role": "assistant",
+                    "content": [{"type": "output_text", "text": text}],
+                }));
+            }
+            for call in message["tool_calls"].as_array().into_iter().flatten() {
+                input.push(json!({
+                    "type": "function_call",
+                    "call_id": call["id"],
+                    "name": call["function"]["name"],
+                    "arguments": call["function"]["arguments"],
+                }));
+            }
+        }
+        _ => input.push(json!({
+            "type": "message",
+            "role": role,
+            "content": content_from_chat(&message["content"]),
+        })),
+    }
+}
+
+fn content_from_chat(content: &Value) -> Value {
+    let Some(parts) = content.as_array() else {
+        return content.clone();
+    };
+    let parts: Vec<Value> = parts
+        .iter()
+        .filter_map(|part| match part["type"].as_str() {
+            Some("text") => Some(json!({"type": "input_text", "text": part["text"]})),
+            Some("image_url") => {
+                let mut image =
+                    json!({"type": "input_image", "image_url": part["image_url"]["url"]});
+                if let Some(detail) = part["image_url"].get("detail") {
+                    image["detail"] = detail.clone();
+                }
+                Some(image)
+            }
+            other => {
+                warn!(
+                    "Dropping unsupported Chat Completions content part {:?}",
+                    other
+                );
+                None
+            }
+        })
+        .collect();
+    Value::Array(parts)
+}
+
+fn tool_from_chat(tool: &Value) -> Option<Value> {
+    let function = tool.get("function")?.as_object()?;
+    let mut converted = Map::new();
+    converted.insert("type".to_string(), json!("function"));
+    for (key, value) in function {
+        converted.insert(key.clone(), value.clone());
+    }
+    Some(Value::Object(converted))
+}
+
+fn tool_choice_from_chat(choice: Value) -> Value {
+    match choice.pointer("/function/name") {
+        Some(name) => json!({"type": "function", "name": name}),
+        None => choice,
+    }
+}
+
+fn format_from_chat(format: &Value) -> Option<Value> {
+    match format["type"].as_str()? {
+        "json_schema" => {
+            let mut converted = format.get("json_schema")?.clone();
+            converted["type"] = json!("json_schema");
+            Some(converted)
+        }
+        "json_object" => Some(json!({"type": "json_object"})),
+        _ => None,
+    }
+}
+
+/// 将 Responses API 非流式响应转换为 Chat Completions 响应
+pub fn response_to_chat(body: &[u8]) -> Result<Bytes> {
+    let response: Value = serde_json::from_slice(body)?;
+    if let Some(error) = response.get("error").filter(|e| !e.is_null()) {
+        return Err(Error::Protocol(format!(
+            "Responses upstream returned error: {}",
+            error["message"].as_str().unwrap_or("unknown")
+        )));
+    }
+
+    let mut text = String::new();
+    let mut refusal = None;
+    let mut tool_calls = Vec::new();
+    for item in response["output"].as_array().into_iter().flatten() {
+        match item["type"].as_str() {
+            Some("message") => {
+                for part in item["content"].as_array().into_iter().flatten() {
+                    match part["type"].as_str() {
+                        Some("output_text") => text.push_str(part["text"].as_str().unwrap_or("")),
+                        Some("refusal") => refusal = Some(part["refusal"].clone()),
+                        _ => {}
+                    }
+                }
+            }
+            Some("function_call") => tool_calls.push(json!({
+                "id": item["call_id"],
+                "type": "function",
+                "function": {"name": item["name"], "arguments": item["arguments"]},
+            })),
+            _ => {}
+        }
+    }
+
+    let mut message = json!({
+        "role": "assistant",
+        "content": if text.is_empty() && !tool_calls.is_empty() { Value::Null } else { json!(text) },
+    });
+    if let Some(refusal) = refusal {
+        message["refusal"] = refusal;
+    }
+    let finish_reason = finish_reason_of(&response, !tool_calls.is_empty());
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = Value::Array(tool_calls);
+    }
+
+    let chat = json!({
+        "id": response["id"],
+        "object": "chat.completion",
+        "created": response["created_at"],
+        "model": response["model"],
+        "choices": [{"index": 0, "message": message, "finish_reason": finish_reason}],
+        "usage": usage_to_chat(&response["usage"]),
+    });
+    Ok(Bytes::from(serde_json::to_vec(&chat)?))
+}
+
+// 按响应状态得到 Chat Completions 的结束原因
+fn finish_reason_of(response: &Value, tool_calls: bool) -> &'static str {
+    match response
+        .pointer("/incomplete_details/reason")
+        .and_then(Value::as_str)
+    {
+        Some("max_output_tokens") => "length",
+        Some("content_filter") => "content_filter",
+        _ if tool_calls => "tool_calls",
+        _ => "stop",
+    }
+}
+
+fn usage_to_chat(usage: &Value) -> Value {
+    let input = usage["input_tokens"].as_u64().unwrap_or(0);
+    let output = usage["output_tokens"].as_u64().unwrap_or(0);
+    json!({
+        "prompt_tokens": input,
+        "completion_tokens": output,
+        "total_tokens": usage["total_tokens"].as_u64().unwrap_or(input + output),
+        "prompt_tokens_details": {
+            "cached_tokens": usage
+                .pointer("/input_tokens_details/cached_tokens")
+                .and_then(Value::as_u64)
+                .unwrap_or(0),
+        },
+        "completion_tokens_details": {
+            "reasoning_tokens": usage
+                .pointer("/output_tokens_details/reasoning_tokens")
+                .and_then(Value::as_u64)
+                .unwrap_or(0),
+        },
+    })
+}
+
+/// 将 Responses API 事件流转换为 Chat Completions 流式响应（标准 SSE），结束时附带用量 chunk
+pub fn stream_to_chat<S>(stream: S) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    Box::pin(async_stream::stream! {
+        let mut events = sse_events(stream);
+        let mut writer = ChatChunkWriter::default();
+
+        while let Some(event) = events.next().await {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            let Ok(data) = serde_json::from_str::<Value>(&event.data) else {
+                continue;
+            };
+            let out = writer.push_event(&data);
+            if !out.is_empty() {
+                yield Ok(Bytes::from(out));
+            }
+        }
+    })
+}
+
+// 由 Responses API 事件生成 Chat Completions chunk
+#[derive(Default)]
+struct ChatChunkWriter {
+    id: Value,
+    model: Value,
+    created: Value,
+    // 函数调用输出项ID -> Chat Completions 工具调用序号
+    calls: BTreeMap<String, usize>,
+}
+
+impl ChatChunkWriter {
+    fn chunk(&self, delta: Value, finish_reason: Option<&str>) -> String {
+        let chunk = json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+        });
+        format!("data: {}\n\n", chunk)
+    }
+
+    fn push_event(&mut self, event: &Value) -> String {
+        match event["type"].as_str().unwrap_or_default() {
+            "response.created" => {
+                let response = &event["response"];
+                self.id = response["id"].clone();
+                self.model = response["model"].clone();
+                self.created = response["created_at"].clone();
+                self.chunk(json!({"role": "assistant", "content": ""}), None)
+            }
+            "response.output_text.delta" => self.chunk(json!({"content": event["delta"]}), None),
+            "response.refusal.delta" => self.chunk(json!({"refusal": event["delta"]}), None),
+            "response.output_item.added" if event["item"]["type"] == "function_call" => {
+                let item = &event["item"];
+                let index = self.calls.len();
+                let item_id = item["id"].as_str().unwrap_or_default().to_string();
+                self.calls.insert(item_id, index);
+                self.chunk(
+                    json!({"tool_calls": [{
+                        "index": index,
+                        "id": item["call_id"],
+                        "type": "function",
+                        "function": {"name": item["name"], "arguments": ""},
+                    }]}),
+                    None,
+                )
+            }
+            "response.function_call_arguments.delta" => {
+                let Some(index) = event["item_id"].as_str().and_then(|id| self.calls.get(id))
+                else {
+                    return String::new();
+                };
+                self.chunk(
+                    json!({"tool_calls": [{
+                        "index": index,
+                        "function": {"arguments": event["delta"]},
+                    }]}),
+                    None,
+                )
+            }
+            "response.completed" | "response.incomplete" => {
+                let response = &event["response"];
+                let finish_reason = finish_reason_of(response, !self.calls.is_empty());
+                let mut out = self.chunk(json!({}), Some(finish_reason));
+                if !response["usage"].is_null() {
+                    let usage = json!({
+                        "id": self.id,
+                        "object": "chat.completion.chunk",
+                        "created": self.created,
+                        "model": self.model,
+                        "choices": [],
+                        "usage": usage_to_chat(&response["usage"]),
+                    });
+                    out.push_str(&format!("data: {}\n\n", usage));
+                }
+                out.push_str("data: [DONE]\n\n");
+                out
+            }
+            "response.failed" | "error" => {
+                let error = match event.pointer("/response/error") {
+                    Some(error) if !error.is_null() => error,
+                    _ => event,
+                };
+                let error = json!({
+                    "error": {
+                        "message": error["message"].as_str().unwrap_or("Upstream error"),
+                        "type": error["code"].as_str().unwrap_or("api_error"),
+                    }
+                });
+                format!("data: {}\n\n", error)
+            }
+            _ => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::testkit::{event_types, parse_sse, SseEvent};
+
+    fn route(protocol: &str, openai_api: Option<&str>) -> RouteConfig {
+        serde_json::from_value(json!({
+            "token": "sk-test",
+            "model": "m",
+            "api": "http://localhost",
+            "protocol": protocol,
+            "model_id": "1",
+            "provider_id": "1",
+            "provider_token_id": "1",
+            "openai_api": openai_api,
+        }))
+        .unwrap()
+    }
+
+    fn to_json(bytes: Result<Bytes>) -> Value {
+        serde_json::from_slice(&bytes.unwrap()).unwrap()
+    }
+
+    async fn collect(stream: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>) -> Vec<SseEvent> {
+        let output: Vec<u8> = stream.map(|chunk| chunk.unwrap().to_vec()).concat().await;
+        parse_sse(&output)
+    }
+
+    fn sse_stream(data: Vec<Value>) -> impl Stream<Item = Result<Bytes>> + Send + 'static {
+        futures::stream::iter(
+            data.into_iter()
+                .map(|d| Ok(Bytes::from(format!("data: {}\n\n", d)))),
+        )
+    }
+
+    fn chat_chunk(delta: Value, finish_reason: Option<&str>) -> Value {
+        json!({
+            "id": "1",
+            "model": "gpt-4o",
+            "created": 1,
+            "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+        })
+    }
+
+    #[test]
+    fn bridge_follows_client_api_and_route() {
+        assert_eq!(bridge(true, &route("openai", None)), None);
+        assert_eq!(
+            bridge(true, &route("openai", Some("chat"))),
+            Some(Bridge::ToChat)
+        );
+        assert_eq!(
+            bridge(true, &route("anthropic", None)),
+            Some(Bridge::ToChat)
+        );
+        assert_eq!(
+            bridge(false, &route("openai", Some("responses"))),
+            Some(Bridge::ToResponses)
+        );
+        assert_eq!(bridge(false, &route("anthropic", Some("responses"))), None);
+        assert_eq!(upstream_path(true, None), Some(RESPONSES_PATH));
+        assert_eq!(upstream_path(true, Some(Bridge::ToChat)), None);
+        assert_eq!(
+            upstream_path(false, Some(Bridge::ToResponses)),
+            Some(RESPONSES_PATH)
+        );
+    }
+
+    #[test]
+    fn request_to_chat_converts_input_items_and_tools() {
+        let body = json!({
+            "model": "gpt-4o",
+            "instructions": "Be brief.",
+            "input": [
+                {"role": "user", "content": [{"type": "input_text", "text": "Weather?"}]},
+                {"type": "function_call", "call_id": "call_1", "name": "get_weather", "arguments": "{}"},
+                {"type": "function_call", "call_id": "call_2", "name": "get_time", "arguments": "{}"},
+                {"type": "function_call_output", "call_id": "call_1", "output": "sunny"},
+            ],
+            "tools": [
+                {"type": "function", "name": "get_weather", "parameters": {"type": "object"}},
+                {"type": "web_search"},
+            ],
+            "tool_choice": {"type": "function", "name": "get_weather"},
+            "max_output_tokens": 100,
+            "stream": true,
+        });
+        let chat = to_json(request_to_chat(body.to_string().as_bytes()));
+        let messages = chat["messages"].as_array().unwrap();
+        assert_eq!(
+            messages[0],
+            json!({"role": "system", "content": "Be brief."})
+        );
+        assert_eq!(messages[1], json!({"role": "user", "content": "Weather?"}));
+        // 连续的函数调用合并到同一条助手消息
+        assert_eq!(messages[2]["tool_calls"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            messages[3],
+            json!({"role": "tool", "tool_call_id": "call_1", "content": "sunny"})
+        );
+        assert_eq!(chat["tools"].as_array().unwrap().len(), 1);
+        assert_eq!(chat["tools"][0]["function"]["name"], "get_weather");
+        assert_eq!(chat["tool_choice"]["function"]["name"], "get_weather");
+        assert_eq!(chat["max_tokens"], 100);
+        assert_eq!(chat["stream_options"]["include_usage"], true);
+    }
+
+    #[test]
+    fn previous_response_id_is_rejected() {
+        let body = json!({"model": "gpt-4o", "input": "hi", "previous_response_id": "resp_1"});
+        let body = body.to_string();
+        assert!(continues_previous_response(body.as_bytes()));
+        assert!(request_to_chat(body.as_bytes()).is_err());
+
+        let body = json!({"model": "gpt-4o", "input": "hi", "previous_response_id": null});
+        assert!(!continues_previous_response(body.to_string().as_bytes()));
+    }
+
+    #[test]
+    fn request_from_chat_splits_tool_calls_into_items() {
+        let body = json!({
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "user", "content": "Weather?"},
+                {"role": "assistant", "content": "Checking.", "tool_calls": [{
+                    "id": "call_1", "type": "function",
+                    "function": {"name": "get_weather", "arguments": "{}"},
+                }]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "sunny"},
+            ],
+            "tools": [{"type": "function", "function": {"name": "get_weather"}}],
+            "max_completion_tokens": 50,
+            "reasoning_effort": "low",
+        });
+        let responses = to_json(request_from_chat(body.to_string().as_bytes()));
+        let types: Vec<&str> = responses["input"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            types,
+            [
+                "message",
+                "message",
+                "function_call",
+                "function_call_output"
+            ]
+        );
+        assert_eq!(responses["store"], false);
+        assert_eq!(
+            responses["tools"][0],
+            json!({"type": "function", "name": "get_weather"})
+        );
+        assert_eq!(responses["max_output_tokens"], 50);
+        assert_eq!(responses["reasoning"]["effort"], "low");
+    }
+
+    #[test]
+    fn non_stream_responses_round_trip_tool_calls() {
+        let chat = json!({
+            "id": "1",
+            "created": 1,
+            "model": "gpt-4o",
+            "choices": [{"index": 0, "message": {
+                "role": "assistant",
+                "content": "Checking.",
+                "tool_calls": [{"id": "call_1", "type": "function",
+                    "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}}],
+            }, "finish_reason": "tool_calls"}],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15,
+                "prompt_tokens_details": {"cached_tokens": 4}},
+        });
+        let response = to_json(response_from_chat(chat.to_string().as_bytes()));
+        assert_eq!(response["status"], "completed");
+        assert_eq!(response["output"][1]["call_id"], "call_1");
+        assert_eq!(
+            response["usage"]["input_tokens_details"]["cached_tokens"],
+            4
+        );
+
+        let back = to_json(response_to_chat(response.to_string().as_bytes()));
+        let message = &back["choices"][0]["message"];
+        assert_eq!(message["content"], "Checking.");
+        assert_eq!(
+            message["tool_calls"],
+            chat["choices"][0]["message"]["tool_calls"]
+        );
+        assert_eq!(back["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(back["usage"]["prompt_tokens"], 10);
+    }
+
+    #[test]
+    fn truncated_chat_response_is_incomplete() {
+        let chat = json!({
+            "id": "1",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hel"},
+                "finish_reason": "length"}],
+        });
+        let response = to_json(response_from_chat(chat.to_string().as_bytes()));
+        assert_eq!(response["status"], "incomplete");
+        assert_eq!(
+            response["incomplete_details"]["reason"],
+            "max_output_tokens"
+        );
+        assert_eq!(
+            to_json(response_to_chat(response.to_string().as_bytes()))["choices"][0]
+                ["finish_reason"],
+            "length"
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_from_chat_emits_text_and_function_calls() {
+        let events = collect(stream_from_chat(sse_stream(vec![
+            chat_chunk(json!({"role": "assistant", "content": "Checking."}), None),
+            chat_chunk(
+                json!({"tool_calls": [{"index": 0, "id": "call_1", "type": "function",
+                    "function": {"name": "get_weather", "arguments": ""}}]}),
+                None,
+            ),
+            chat_chunk(
+                json!({"tool_calls": [{"index": 0, "function": {"arguments": "{\"city\":\"Paris\"}"}}]}),
+                None,
+            ),
+            chat_chunk(json!({}), Some("tool_calls")),
+        ])))
+        .await;
+        assert_eq!(
+            event_types(&events),
+            [
+                "response.created",
+                "response.in_progress",
+                "response.output_item.added",
+                "response.content_part.added",
+                "response.output_text.delta",
+                "response.output_text.done",
+                "response.content_part.done",
+                "response.output_item.done",
+                "response.output_item.added",
+                "response.function_call_arguments.delta",
+                "response.function_call_arguments.done",
+                "response.output_item.done",
+                "response.completed",
+            ]
+        );
+        let sequence: Vec<u64> = events
+            .iter()
+            .map(|event| event.json().unwrap()["sequence_number"].as_u64().unwrap())
+            .collect();
+        assert_eq!(sequence, (0..13).collect::<Vec<_>>());
+        let completed = events.last().unwrap().json().unwrap();
+        let output = &completed["response"]["output"];
+        assert_eq!(output[0]["content"][0]["text"], "Checking.");
+        assert_eq!(output[1]["arguments"], "{\"city\":\"Paris\"}");
+    }
+
+    #[tokio::test]
+    async fn stream_from_chat_error_fails_response() {
+        let events = collect(stream_from_chat(sse_stream(vec![
+            chat_chunk(json!({"content": "partial"}), None),
+            json!({"error": {"type": "stream_too_large", "message": "too large"}}),
+            chat_chunk(json!({"content": "ignored"}), Some("stop")),
+        ])))
+        .await;
+        let types = event_types(&events);
+        assert_eq!(&types[types.len() - 2..], ["error", "response.failed"]);
+        let failed = events.last().unwrap().json().unwrap();
+        assert_eq!(failed["response"]["status"], "failed");
+        assert_eq!(failed["response"]["error"]["code"], "stream_too_large");
+        assert!(!types.iter().any(|t| t == "response.completed"));
+    }
+
+    #[tokio::test]
+    async fn stream_to_chat_emits_tool_calls_usage_and_done() {
+        let response = json!({"id": "resp_1", "model": "gpt-4o", "created_at": 1});
+        let item = json!({"type": "function_call", "id": "fc_1", "call_id": "call_1", "name": "f"});
+        let output = stream_to_chat(sse_stream(vec![
+            json!({"type": "response.created", "response": response}),
+            json!({"type": "response.output_text.delta", "delta": "Hi"}),
+            json!({"type": "response.output_item.added", "output_index": 1, "item": item}),
+            json!({"type": "response.function_call_arguments.delta", "item_id": "fc_1", "delta": "{}"}),
+            json!({"type": "response.completed", "response": {
+                "status": "completed",
+                "usage": {"input_tokens": 3, "output_tokens": 2, "total_tokens": 5},
+            }}),
+        ]));
+        let events = collect(output).await;
+        assert!(events.last().unwrap().is_done());
+        let chunks: Vec<Value> = events.iter().filter_map(SseEvent::json).collect();
+        assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+        assert_eq!(chunks[1]["choices"][0]["delta"]["content"], "Hi");
+        let call = &chunks[2]["choices"][0]["delta"]["tool_calls"][0];
+        assert_eq!(call["index"], 0);
+        assert_eq!(call["id"], "call_1");
+        assert_eq!(
+            chunks[3]["choices"][0]["delta"]["tool_calls"][0]["function"]["arguments"],
+            "{}"
+        );
+        assert_eq!(chunks[4]["choices"][0]["finish_reason"], "tool_calls");
+        assert_eq!(chunks[5]["usage"]["total_tokens"], 5);
+    }
+}
@@ -0,0 +1,25 @@
+//! Anthropic `stop_reason` 与 OpenAI `finish_reason` 之间的双向映射
+//!
+//! 流式与非流式转换共用，保证客户端看到的都是各自协议规范内的取值。
+
+/// 将 OpenAI `finish_reason` 转换为 Anthropic `stop_reason`
+pub fn openai_to_anthropic(finish_reason: &str) -> &'static str {
+    match finish_reason {
+        "length" => "max_tokens",
+        "tool_calls" | "function_call" => "tool_use",
+        "content_filter" => "refusal",
+        // "stop" 及未知取值都视为正常结束
+        _ => "end_turn",
+    }
+}
+
+/// 将 Anthropic `stop_reason` 转换为 OpenAI `finish_reason`
+pub fn anthropic_to_openai(stop_reason: &str) -> &'static str {
+    match stop_reason {
+        "max_tokens" => "length",
+        "tool_use" => "tool_calls",
+        "refusal" => "content_filter",
+        // "end_turn"、"stop_sequence"、"pause_turn" 及未知取值
+        _ => "stop",
+    }
+}
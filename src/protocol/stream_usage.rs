@@ -0,0 +1,300 @@
+//! Chat Completions 流式响应中的用量 chunk
+//!
+//! OpenAI 客户端通过 `stream_options.include_usage` 声明是否需要用量：声明时每个 chunk 带有
+//! `"usage": null`，`[DONE]` 之前有一个 `choices` 为空、带有用量的 chunk；未声明时 chunk 中没有 usage 字段。
+//! 协议转换、Responses API 桥接和输出上限都会合成用量，位置和形式各不相同，
+//! 输出给客户端前统一按客户端的声明调整：未声明时移除用量，声明时将用量移到 `[DONE]` 之前的单独 chunk，
+//! 上游始终没有返回用量时按文本估算补发（见 [`crate::usage::estimate`]）。
+
+use crate::error::Result;
+use crate::usage::estimate::count_tokens;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde_json::{json, Value};
+use std::pin::Pin;
+use tracing::debug;
+
+/// 客户端请求是否声明了 `stream_options.include_usage`
+pub fn include_usage_requested(body: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|body| body.pointer("/stream_options/include_usage")?.as_bool())
+        .unwrap_or(false)
+}
+
+/// 按客户端是否声明 `include_usage` 调整 Chat Completions 流（标准 SSE）中的用量
+///
+/// `prompt_tokens` 为估算的输入Token数，只在上游没有返回用量、需要补发时使用
+pub fn conform<S>(
+    stream: S,
+    include_usage: bool,
+    prompt_tokens: Option<u32>,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    Box::pin(async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        let mut shaper = UsageShaper::new(include_usage, prompt_tokens);
+        let mut buffer: Vec<u8> = Vec::new();
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            buffer.extend_from_slice(&chunk);
+
+            // 逐个处理完整的事件（以空行分隔），不完整的事件留待下一个chunk
+            let mut out = Vec::new();
+            while let Some(pos) = buffer.windows(2).position(|w| w == b"\n\n") {
+                let event: Vec<u8> = buffer.drain(..pos + 2).collect();
+                shaper.event(&event, &mut out);
+            }
+            if !out.is_empty() {
+                yield Ok(Bytes::from(out));
+            }
+        }
+
+        if !buffer.is_empty() {
+            yield Ok(Bytes::from(buffer));
+        }
+    })
+}
+
+struct UsageShaper {
+    include_usage: bool,
+    prompt_tokens: Option<u32>,
+    // 上游返回的最新用量，在 [DONE] 之前输出
+    usage: Option<Value>,
+    // 按输出文本估算的输出Token数
+    completion_tokens: u32,
+    // 最近一个 chunk，用于构造用量 chunk（保留 id、model 等字段）
+    last_chunk: Option<Value>,
+}
+
+impl UsageShaper {
+    fn new(include_usage: bool, prompt_tokens: Option<u32>) -> Self {
+        Self {
+            include_usage,
+            prompt_tokens,
+            usage: None,
+            completion_tokens: 0,
+            last_chunk: None,
+        }
+    }
+
+    // 处理单个 SSE 事件，结果写入 out；不是 chunk 的事件（错误事件、注释等）原样输出
+    fn event(&mut self, event: &[u8], out: &mut Vec<u8>) {
+        let Some(data) = std::str::from_utf8(event)
+            .ok()
+            .and_then(|e| e.trim_end().strip_prefix("data:"))
+            .map(str::trim)
+        else {
+            out.extend_from_slice(event);
+            return;
+        };
+        if data == "[DONE]" {
+            if self.include_usage {
+                self.write_usage_chunk(out);
+            }
+            out.extend_from_slice(event);
+            return;
+        }
+        let Ok(Value::Object(mut chunk)) = serde_json::from_str::<Value>(data) else {
+            out.extend_from_slice(event);
+            return;
+        };
+        if !chunk.contains_key("choices") {
+            out.extend_from_slice(event);
+            return;
+        }
+
+        let had_usage = chunk.contains_key("usage");
+        let usage = chunk.remove("usage").filter(|usage| usage.is_object());
+        // 只有用量的 chunk 不单独输出
+        let usage_only = usage.is_some() && chunk["choices"].as_array().is_none_or(Vec::is_empty);
+        if !self.include_usage {
+            if !had_usage {
+                out.extend_from_slice(event);
+            } else if !usage_only {
+                out.extend_from_slice(format!("data: {}\n\n", Value::Object(chunk)).as_bytes());
+            }
+            return;
+        }
+
+        if usage.is_some() {
+            self.usage = usage;
+        }
+        for choice in chunk["choices"].as_array().into_iter().flatten() {
+            let delta = &choice["delta"];
+            for field in ["content", "reasoning_content"] {
+                self.completion_tokens += delta[field].as_str().map_or(0, count_tokens);
+            }
+            for call in delta["tool_calls"].as_array().into_iter().flatten() {
+                self.completion_tokens += call["function"]["arguments"]
+                    .as_str()
+                    .map_or(0, count_tokens);
+            }
+        }
+        chunk.insert("usage".to_string(), Value::Null);
+        let chunk = Value::Object(chunk);
+        if !usage_only {
+            out.extend_from_slice(format!("data: {}\n\n", chunk).as_bytes());
+        }
+        self.last_chunk = Some(chunk);
+    }
+
+    // [DONE] 之前的用量 chunk，上游没有返回用量时按估算值补发
+    fn write_usage_chunk(&mut self, out: &mut Vec<u8>) {
+        let usage = self.usage.take().unwrap_or_else(|| {
+            let prompt_tokens = self.prompt_tokens.unwrap_or(0);
+            debug!(
+                "Stream ended without usage, synthesizing estimate (completion_tokens={})",
+                self.completion_tokens
+            );
+            json!({
+                "prompt_tokens": prompt_tokens,
+                "completion_tokens": self.completion_tokens,
+                "total_tokens": prompt_tokens + self.completion_tokens,
+            })
+        });
+        let mut chunk = self
+            .last_chunk
+            .take()
+            .unwrap_or_else(|| json!({"object": "chat.completion.chunk"}));
+        chunk["choices"] = json!([]);
+        chunk["usage"] = usage;
+        out.extend_from_slice(format!("data: {}\n\n", chunk).as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::testkit::{parse_sse, SseEvent};
+
+    fn chunk(delta: Value, usage: Option<Value>) -> Value {
+        let mut chunk = json!({
+            "id": "chatcmpl-1",
+            "model": "gpt-4o",
+            "choices": [{"index": 0, "delta": delta, "finish_reason": null}],
+        });
+        if let Some(usage) = usage {
+            chunk["usage"] = usage;
+        }
+        chunk
+    }
+
+    fn usage_chunk(usage: Value) -> Value {
+        json!({"id": "chatcmpl-1", "model": "gpt-4o", "choices": [], "usage": usage})
+    }
+
+    // 按给定切分送入 conform，返回解析后的输出事件
+    async fn run(
+        data: Vec<Value>,
+        include_usage: bool,
+        prompt_tokens: Option<u32>,
+        chunk_size: usize,
+    ) -> Vec<SseEvent> {
+        let mut transcript: String = data.iter().map(|d| format!("data: {}\n\n", d)).collect();
+        transcript.push_str("data: [DONE]\n\n");
+        let chunks: Vec<Result<Bytes>> = transcript
+            .as_bytes()
+            .chunks(chunk_size)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        let output: Vec<u8> = conform(futures::stream::iter(chunks), include_usage, prompt_tokens)
+            .map(|chunk| chunk.unwrap().to_vec())
+            .concat()
+            .await;
+        parse_sse(&output)
+    }
+
+    #[test]
+    fn reads_include_usage_from_request() {
+        assert!(include_usage_requested(
+            br#"{"stream":true,"stream_options":{"include_usage":true}}"#
+        ));
+        assert!(!include_usage_requested(br#"{"stream":true}"#));
+        assert!(!include_usage_requested(b"not json"));
+    }
+
+    #[tokio::test]
+    async fn usage_is_removed_when_not_requested() {
+        let usage = json!({"prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4});
+        let events = run(
+            vec![
+                chunk(json!({"content": "Hi"}), Some(Value::Null)),
+                usage_chunk(usage.clone()),
+                chunk(json!({"content": "!"}), Some(usage)),
+            ],
+            false,
+            None,
+            7,
+        )
+        .await;
+        assert_eq!(events.len(), 3);
+        assert!(events[2].is_done());
+        for event in &events[..2] {
+            assert!(event.json().unwrap().get("usage").is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn upstream_usage_moves_before_done() {
+        let usage = json!({"prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4});
+        let events = run(
+            vec![
+                chunk(json!({"content": "Hi"}), Some(usage.clone())),
+                chunk(json!({}), None),
+            ],
+            true,
+            None,
+            5,
+        )
+        .await;
+        assert_eq!(events.len(), 4);
+        for event in &events[..2] {
+            assert_eq!(event.json().unwrap()["usage"], Value::Null);
+        }
+        let last = events[2].json().unwrap();
+        assert_eq!(last["choices"], json!([]));
+        assert_eq!(last["usage"], usage);
+        assert_eq!(last["id"], "chatcmpl-1");
+        assert!(events[3].is_done());
+    }
+
+    #[tokio::test]
+    async fn missing_usage_is_estimated() {
+        let events = run(
+            vec![
+                chunk(json!({"content": "Hello world"}), None),
+                chunk(
+                    json!({"tool_calls": [{"index": 0, "function": {"arguments": "{\"a\":1}"}}]}),
+                    None,
+                ),
+            ],
+            true,
+            Some(12),
+            1024,
+        )
+        .await;
+        let usage = &events[events.len() - 2].json().unwrap()["usage"];
+        assert_eq!(usage["prompt_tokens"], 12);
+        let completion_tokens = usage["completion_tokens"].as_u64().unwrap();
+        assert!(completion_tokens > 0);
+        assert_eq!(usage["total_tokens"], 12 + completion_tokens);
+    }
+
+    #[tokio::test]
+    async fn non_chunk_events_pass_through() {
+        let error = json!({"error": {"type": "stream_too_large", "message": "too large"}});
+        let events = run(vec![error.clone()], false, None, 3).await;
+        assert_eq!(events[0].json().unwrap(), error);
+        assert!(events[1].is_done());
+    }
+}
@@ -0,0 +1,619 @@
+//! 流式协议转换的测试工具
+//!
+//! 将录制的上游 SSE 记录按任意 chunk 边界切分后送入适配器，解析输出事件并断言，
+//! 也可与 golden 文件比对。处理流程与网关一致：先做分帧规范化，再做协议转换。
+//!
+//! 设置环境变量 `AXONGATE_UPDATE_GOLDEN=1` 时，[`assert_golden`] 会用当前输出覆盖 golden 文件。
+
+use crate::error::Result;
+use crate::models::{ClientProtocol, TargetProtocol};
+use crate::protocol::{framing, ProtocolAdapter};
+use bytes::Bytes;
+use futures::StreamExt;
+use serde_json::Value;
+use std::path::Path;
+
+/// 更新 golden 文件的环境变量
+pub const UPDATE_GOLDEN_ENV: &str = "AXONGATE_UPDATE_GOLDEN";
+
+/// 每次输出中都会变化的字段，比较前会移除
+const VOLATILE_FIELDS: &[&str] = &["created"];
+
+/// 一个 SSE 事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SseEvent {
+    /// `event:` 字段（OpenAI 流没有）
+    pub event: Option<String>,
+    /// `data:` 字段，多行 data 以 `\n` 连接
+    pub data: String,
+}
+
+impl SseEvent {
+    /// 将 data 解析为 JSON（如 `[DONE]` 则返回 None）
+    pub fn json(&self) -> Option<Value> {
+        serde_json::from_str(&self.data).ok()
+    }
+
+    /// 事件类型：优先取 `event:` 字段，其次取 JSON 中的 `type`
+    pub fn event_type(&self) -> Option<String> {
+        self.event
+            .clone()
+            .or_else(|| Some(self.json()?.get("type")?.as_str()?.to_string()))
+    }
+
+    /// 是否为 OpenAI 的 `[DONE]` 结束标记
+    pub fn is_done(&self) -> bool {
+        self.data.trim() == "[DONE]"
+    }
+}
+
+/// 切分录制记录的方式
+#[derive(Debug, Clone)]
+pub enum ChunkPlan {
+    /// 整体作为一个 chunk
+    Whole,
+    /// 每 N 个字节一个 chunk
+    Fixed(usize),
+    /// 每个字节一个 chunk
+    Bytewise,
+    /// 在指定的字节偏移处切分
+    At(Vec<usize>),
+}
+
+/// 按切分方式将录制记录拆成 chunk
+pub fn split_chunks(transcript: &[u8], plan: &ChunkPlan) -> Vec<Bytes> {
+    let size = match plan {
+        ChunkPlan::Whole => transcript.len().max(1),
+        ChunkPlan::Fixed(size) => (*size).max(1),
+        ChunkPlan::Bytewise => 1,
+        ChunkPlan::At(offsets) => {
+            let mut offsets: Vec<usize> = offsets
+                .iter()
+                .copied()
+                .filter(|&o| o > 0 && o < transcript.len())
+                .collect();
+            offsets.sort_unstable();
+            offsets.dedup();
+
+            let mut chunks = Vec::with_capacity(offsets.len() + 1);
+            let mut start = 0;
+            for offset in offsets.into_iter().chain(std::iter::once(transcript.len())) {
+                chunks.push(Bytes::copy_from_slice(&transcript[start..offset]));
+                start = offset;
+            }
+            return chunks;
+        }
+    };
+
+    transcript
+        .chunks(size)
+        .map(Bytes::copy_from_slice)
+        .collect()
+}
+
+/// 解析 SSE 文本为事件列表，兼容 `\r\n` 换行，忽略注释行和 data 为空的事件
+pub fn parse_sse(bytes: &[u8]) -> Vec<SseEvent> {
+    let text = String::from_utf8_lossy(bytes).replace("\r\n", "\n");
+    let mut events = Vec::new();
+
+    for block in text.split("\n\n") {
+        let mut event = None;
+        let mut data_lines = Vec::new();
+
+        for line in block.lines() {
+            if line.starts_with(':') {
+                continue;
+            }
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => event = Some(value.to_string()),
+                "data" => data_lines.push(value.to_string()),
+                _ => {}
+            }
+        }
+
+        if !data_lines.is_empty() {
+            events.push(SseEvent {
+                event,
+                data: data_lines.join("\n"),
+            });
+        }
+    }
+
+    events
+}
+
+/// 将 chunk 序列送入适配器做流式转换，返回拼接后的原始输出
+pub async fn transform_chunks<A: ProtocolAdapter>(
+    adapter: &A,
+    source_protocol: &TargetProtocol,
+    target_protocol: &ClientProtocol,
+    chunks: Vec<Bytes>,
+) -> Result<Bytes> {
+    let input = framing::normalize_to_sse(
+        source_protocol,
+        futures::stream::iter(chunks.into_iter().map(Ok)),
+    );
+    let mut output = adapter
+        .transform_stream_chunk(source_protocol, target_protocol, input)
+        .await?;
+
+    let mut collected = Vec::new();
+    while let Some(chunk) = output.next().await {
+        collected.extend_from_slice(&chunk?);
+    }
+    Ok(Bytes::from(collected))
+}
+
+/// 按切分方式转换录制记录，返回解析后的输出事件
+pub async fn transform_transcript<A: ProtocolAdapter>(
+    adapter: &A,
+    source_protocol: &TargetProtocol,
+    target_protocol: &ClientProtocol,
+    transcript: &[u8],
+    plan: &ChunkPlan,
+) -> Result<Vec<SseEvent>> {
+    let chunks = split_chunks(transcript, plan);
+    let output = transform_chunks(adapter, source_protocol, target_protocol, chunks).await?;
+    Ok(parse_sse(&output))
+}
+
+/// 断言在任意 chunk 边界下转换结果都与整体输入一致，返回整体输入的输出事件
+///
+/// 依次尝试逐字节切分、若干固定大小切分以及每个事件边界前后的切分。
+pub async fn assert_chunking_invariant<A: ProtocolAdapter>(
+    adapter: &A,
+    source_protocol: &TargetProtocol,
+    target_protocol: &ClientProtocol,
+    transcript: &[u8],
+) -> Vec<SseEvent> {
+    let expected = transform_transcript(
+        adapter,
+        source_protocol,
+        target_protocol,
+        transcript,
+        &ChunkPlan::Whole,
+    )
+    .await
+    .expect("transform of whole transcript failed");
+
+    let boundaries: Vec<usize> = transcript
+        .iter()
+        .enumerate()
+        .filter(|(_, &b)| b == b'\n')
+        .flat_map(|(i, _)| [i, i + 1])
+        .collect();
+    let mut plans = vec![ChunkPlan::Bytewise, ChunkPlan::At(boundaries)];
+    plans.extend([2, 3, 7, 16, 64].map(ChunkPlan::Fixed));
+
+    for plan in plans {
+        let actual =
+            transform_transcript(adapter, source_protocol, target_protocol, transcript, &plan)
+                .await
+                .unwrap_or_else(|e| panic!("transform with {:?} failed: {}", plan, e));
+        assert_eq!(
+            normalized(&actual),
+            normalized(&expected),
+            "output differs when chunked with {:?}",
+            plan
+        );
+    }
+
+    expected
+}
+
+/// 输出事件的类型序列
+pub fn event_types(events: &[SseEvent]) -> Vec<String> {
+    events
+        .iter()
+        .map(|e| match e.event_type() {
+            Some(t) => t,
+            None if e.is_done() => "[DONE]".to_string(),
+            None => String::new(),
+        })
+        .collect()
+}
+
+/// 拼接输出中的文本增量（支持 OpenAI 和 Anthropic 两种格式）
+pub fn collect_text(events: &[SseEvent]) -> String {
+    events
+        .iter()
+        .filter_map(|e| e.json())
+        .filter_map(|json| {
+            json["choices"][0]["delta"]["content"]
+                .as_str()
+                .or_else(|| json["delta"]["text"].as_str())
+                .map(str::to_string)
+        })
+        .collect()
+}
+
+/// 按 Anthropic SDK 的流式解析规则累积事件，返回完整的 message，事件序列不合规时返回错误
+///
+/// 与 SDK 一致的检查：首个事件为 `message_start`；内容块序号从0连续递增，
+/// 增量只能作用于当前打开的块且类型匹配；`tool_use` 块的参数拼接后必须是合法 JSON；
+/// `message_delta` 必须带 `usage.output_tokens`；流以 `message_stop` 结束。
+/// `error` 事件的错误信息作为错误返回。
+pub fn accumulate_anthropic_message(events: &[SseEvent]) -> std::result::Result<Value, String> {
+    let mut message: Option<Value> = None;
+    let mut open_block: Option<usize> = None;
+    let mut partial_json = String::new();
+    let mut stopped = false;
+
+    for (position, event) in events.iter().enumerate() {
+        let json = event
+            .json()
+            .ok_or_else(|| format!("event {} is not JSON: {}", position, event.data))?;
+        let event_type = json["type"].as_str().unwrap_or_default();
+        if event.event.as_deref().is_some_and(|e| e != event_type) {
+            return Err(format!(
+                "event {}: event field {:?} does not match type {:?}",
+                position, event.event, event_type
+            ));
+        }
+        if stopped {
+            return Err(format!(
+                "event {} ({}) after message_stop",
+                position, event_type
+            ));
+        }
+
+        match event_type {
+            "ping" => continue,
+            "error" => {
+                return Err(format!(
+                    "stream error: {}",
+                    json["error"]["message"].as_str().unwrap_or_default()
+                ))
+            }
+            "message_start" if message.is_none() => {
+                let mut start = json["message"].clone();
+                if !start["content"].is_array() || !start["usage"].is_object() {
+                    return Err("message_start without content array or usage".to_string());
+                }
+                start["content"] = Value::Array(Vec::new());
+                message = Some(start);
+                continue;
+            }
+            _ => {}
+        }
+        let message = message
+            .as_mut()
+            .ok_or_else(|| format!("event {} ({}) before message_start", position, event_type))?;
+        let content = message["content"].as_array_mut().expect("content is array");
+
+        match event_type {
+            "content_block_start" => {
+                if let Some(index) = open_block {
+                    return Err(format!(
+                        "block {} started while block {} is open",
+                        content.len(),
+                        index
+                    ));
+                }
+                if json["index"].as_u64() != Some(content.len() as u64) {
+                    return Err(format!(
+                        "content_block_start index {} (expected {})",
+                        json["index"],
+                        content.len()
+                    ));
+                }
+                open_block = Some(content.len());
+                partial_json.clear();
+                content.push(json["content_block"].clone());
+            }
+            "content_block_delta" => {
+                let index = json["index"].as_u64().map(|i| i as usize);
+                if index.is_none() || index != open_block {
+                    return Err(format!(
+                        "delta for block {} which is not open",
+                        json["index"]
+                    ));
+                }
+                let block = &mut content[index.unwrap_or_default()];
+                let delta = &json["delta"];
+                let expected_block = match delta["type"].as_str().unwrap_or_default() {
+                    "text_delta" | "citations_delta" => "text",
+                    "input_json_delta" => "tool_use",
+                    "thinking_delta" | "signature_delta" => "thinking",
+                    other => return Err(format!("unknown delta type {:?}", other)),
+                };
+                if block["type"].as_str() != Some(expected_block) {
+                    return Err(format!(
+                        "{} applied to {} block",
+                        delta["type"], block["type"]
+                    ));
+                }
+                match delta["type"].as_str().unwrap_or_default() {
+                    "text_delta" => append_str(&mut block["text"], &delta["text"]),
+                    "thinking_delta" => append_str(&mut block["thinking"], &delta["thinking"]),
+                    "signature_delta" => block["signature"] = delta["signature"].clone(),
+                    "input_json_delta" => {
+                        partial_json.push_str(delta["partial_json"].as_str().unwrap_or_default())
+                    }
+                    _ => {
+                        if !block["citations"].is_array() {
+                            block["citations"] = Value::Array(Vec::new());
+                        }
+                        if let Some(citations) = block["citations"].as_array_mut() {
+                            citations.push(delta["citation"].clone());
+                        }
+                    }
+                }
+            }
+            "content_block_stop" => {
+                let index = json["index"].as_u64().map(|i| i as usize);
+                if index.is_none() || index != open_block.take() {
+                    return Err(format!(
+                        "stop for block {} which is not open",
+                        json["index"]
+                    ));
+                }
+                let block = &mut content[index.unwrap_or_default()];
+                if block["type"] == "tool_use" && !partial_json.is_empty() {
+                    block["input"] = serde_json::from_str(&partial_json).map_err(|e| {
+                        format!("invalid tool input JSON {:?}: {}", partial_json, e)
+                    })?;
+                }
+            }
+            "message_delta" => {
+                if json["usage"]["output_tokens"].as_u64().is_none() {
+                    return Err("message_delta without usage.output_tokens".to_string());
+                }
+                for field in ["stop_reason", "stop_sequence"] {
+                    message[field] = json["delta"][field].clone();
+                }
+                for field in ["input_tokens", "output_tokens"] {
+                    if !json["usage"][field].is_null() {
+                        message["usage"][field] = json["usage"][field].clone();
+                    }
+                }
+            }
+            "message_stop" => {
+                if let Some(index) = open_block {
+                    return Err(format!("message_stop while block {} is open", index));
+                }
+                stopped = true;
+            }
+            other => return Err(format!("unexpected event {:?}", other)),
+        }
+    }
+
+    if !stopped {
+        return Err("stream ended without message_stop".to_string());
+    }
+    message.ok_or_else(|| "stream has no message_start".to_string())
+}
+
+fn append_str(target: &mut Value, suffix: &Value) {
+    let mut text = target.as_str().unwrap_or_default().to_string();
+    text.push_str(suffix.as_str().unwrap_or_default());
+    *target = Value::String(text);
+}
+
+/// 将事件与 golden 文件比对
+///
+/// golden 文件为 SSE 文本，JSON data 按规范化格式写入（移除易变字段）。
+/// 设置 [`UPDATE_GOLDEN_ENV`] 时改为写入当前输出。
+pub fn assert_golden(events: &[SseEvent], golden_path: impl AsRef<Path>) {
+    let golden_path = golden_path.as_ref();
+    let actual = render_sse(&normalized(events));
+
+    if std::env::var_os(UPDATE_GOLDEN_ENV).is_some() {
+        if let Some(parent) = golden_path.parent() {
+            std::fs::create_dir_all(parent).expect("failed to create golden directory");
+        }
+        std::fs::write(golden_path, &actual).expect("failed to write golden file");
+        return;
+    }
+
+    let expected = std::fs::read(golden_path).unwrap_or_else(|e| {
+        panic!(
+            "failed to read golden file {} ({}); set {}=1 to create it",
+            golden_path.display(),
+            e,
+            UPDATE_GOLDEN_ENV
+        )
+    });
+    let expected = render_sse(&normalized(&parse_sse(&expected)));
+
+    assert_eq!(
+        actual,
+        expected,
+        "output differs from golden file {}",
+        golden_path.display()
+    );
+}
+
+/// 将事件渲染为 SSE 文本
+pub fn render_sse(events: &[SseEvent]) -> String {
+    events
+        .iter()
+        .map(|e| match &e.event {
+            Some(event) => format!("event: {}\ndata: {}\n\n", event, e.data),
+            None => format!("data: {}\n\n", e.data),
+        })
+        .collect()
+}
+
+/// 移除易变字段并统一 JSON 格式，便于比较
+fn normalized(events: &[SseEvent]) -> Vec<SseEvent> {
+    events
+        .iter()
+        .map(|e| {
+            let data = match e.json() {
+                Some(Value::Object(mut map)) => {
+                    for field in VOLATILE_FIELDS {
+                        map.remove(*field);
+                    }
+                    Value::Object(map).to_string()
+                }
+                Some(json) => json.to_string(),
+                None => e.data.clone(),
+            };
+            SseEvent {
+                event: e.event.clone(),
+                data,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::adapter::UniversalAdapter;
+
+    const OPENAI_TRANSCRIPT: &str = concat!(
+        "data: {\"id\":\"chatcmpl-1\",\"model\":\"gpt-4o\",\"created\":1,\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"chatcmpl-1\",\"model\":\"gpt-4o\",\"created\":1,\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hello\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"chatcmpl-1\",\"model\":\"gpt-4o\",\"created\":1,\"choices\":[{\"index\":0,\"delta\":{\"content\":\" wörld\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"chatcmpl-1\",\"model\":\"gpt-4o\",\"created\":1,\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+        "data: {\"id\":\"chatcmpl-1\",\"model\":\"gpt-4o\",\"created\":1,\"choices\":[],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":2,\"total_tokens\":7}}\n\n",
+        "data: [DONE]\n\n",
+    );
+
+    const ANTHROPIC_TRANSCRIPT: &str = concat!(
+        "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"model\":\"claude\",\"usage\":{\"input_tokens\":5,\"output_tokens\":1}}}\n\n",
+        "event: ping\ndata: {\"type\":\"ping\"}\n\n",
+        "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+        "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"Hello\"}}\n\n",
+        "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\" wörld\"}}\n\n",
+        "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+        "event: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":2}}\n\n",
+        "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+    );
+
+    #[test]
+    fn split_chunks_covers_transcript() {
+        let transcript = b"abcdefg";
+        for plan in [
+            ChunkPlan::Whole,
+            ChunkPlan::Fixed(3),
+            ChunkPlan::Bytewise,
+            ChunkPlan::At(vec![5, 0, 2, 2, 99]),
+        ] {
+            let joined: Vec<u8> = split_chunks(transcript, &plan).concat();
+            assert_eq!(joined, transcript, "{:?}", plan);
+        }
+        assert_eq!(split_chunks(transcript, &ChunkPlan::Fixed(3)).len(), 3);
+        assert_eq!(split_chunks(transcript, &ChunkPlan::Bytewise).len(), 7);
+        assert_eq!(
+            split_chunks(transcript, &ChunkPlan::At(vec![5, 0, 2, 2, 99])),
+            [&b"ab"[..], b"cde", b"fg"]
+        );
+    }
+
+    #[test]
+    fn parse_sse_handles_crlf_comments_and_multiline_data() {
+        let events =
+            parse_sse(b": keepalive\r\n\r\nevent: a\r\ndata: 1\r\ndata:2\r\n\r\ndata: [DONE]\n\n");
+        assert_eq!(
+            events,
+            [
+                SseEvent {
+                    event: Some("a".to_string()),
+                    data: "1\n2".to_string(),
+                },
+                SseEvent {
+                    event: None,
+                    data: "[DONE]".to_string(),
+                },
+            ]
+        );
+        assert!(events[1].is_done());
+        assert_eq!(events[0].json(), None);
+    }
+
+    #[test]
+    fn event_type_falls_back_to_json_type() {
+        let events = parse_sse(b"data: {\"type\":\"ping\"}\n\ndata: [DONE]\n\n");
+        assert_eq!(event_types(&events), ["ping", "[DONE]"]);
+    }
+
+    #[tokio::test]
+    async fn anthropic_to_openai_is_chunking_invariant() {
+        let events = assert_chunking_invariant(
+            &UniversalAdapter::new(),
+            &TargetProtocol::Anthropic,
+            &ClientProtocol::OpenAI,
+            ANTHROPIC_TRANSCRIPT.as_bytes(),
+        )
+        .await;
+        assert_eq!(collect_text(&events), "Hello wörld");
+        assert!(events.last().unwrap().is_done());
+        let usage = events
+            .iter()
+            .filter_map(SseEvent::json)
+            .find(|json| json["usage"].is_object())
+            .unwrap();
+        assert_eq!(usage["usage"]["prompt_tokens"], 5);
+        assert_eq!(usage["usage"]["completion_tokens"], 2);
+    }
+
+    #[tokio::test]
+    async fn openai_to_anthropic_is_chunking_invariant() {
+        let events = assert_chunking_invariant(
+            &UniversalAdapter::new(),
+            &TargetProtocol::OpenAI,
+            &ClientProtocol::Anthropic,
+            OPENAI_TRANSCRIPT.as_bytes(),
+        )
+        .await;
+        assert_eq!(collect_text(&events), "Hello wörld");
+        let message = accumulate_anthropic_message(&events).unwrap();
+        assert_eq!(message["stop_reason"], "end_turn");
+        assert_eq!(message["usage"]["output_tokens"], 2);
+    }
+
+    #[test]
+    fn accumulator_rejects_invalid_sequences() {
+        let events = |sse: &str| parse_sse(sse.as_bytes());
+        let start = "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"content\":[],\"usage\":{}}}\n\n";
+
+        let err = accumulate_anthropic_message(&events(&format!(
+            "{}event: content_block_start\ndata: {{\"type\":\"content_block_start\",\"index\":1,\"content_block\":{{\"type\":\"text\",\"text\":\"\"}}}}\n\n",
+            start
+        )))
+        .unwrap_err();
+        assert!(err.contains("index 1 (expected 0)"), "{}", err);
+
+        let err = accumulate_anthropic_message(&events(&format!(
+            "{}event: content_block_start\ndata: {{\"type\":\"content_block_start\",\"index\":0,\"content_block\":{{\"type\":\"tool_use\",\"id\":\"t\",\"name\":\"f\",\"input\":{{}}}}}}\n\n\
+             event: content_block_delta\ndata: {{\"type\":\"content_block_delta\",\"index\":0,\"delta\":{{\"type\":\"input_json_delta\",\"partial_json\":\"{{\\\"a\\\":\"}}}}\n\n\
+             event: content_block_stop\ndata: {{\"type\":\"content_block_stop\",\"index\":0}}\n\n",
+            start
+        )))
+        .unwrap_err();
+        assert!(err.contains("invalid tool input JSON"), "{}", err);
+
+        let err = accumulate_anthropic_message(&events(&format!(
+            "{}event: message_delta\ndata: {{\"type\":\"message_delta\",\"delta\":{{}},\"usage\":{{}}}}\n\n",
+            start
+        )))
+        .unwrap_err();
+        assert!(err.contains("usage.output_tokens"), "{}", err);
+
+        let err = accumulate_anthropic_message(&events(start)).unwrap_err();
+        assert!(err.contains("without message_stop"), "{}", err);
+    }
+
+    #[test]
+    fn golden_comparison_ignores_volatile_fields_and_formatting() {
+        let path = std::env::temp_dir().join(format!("axongate-golden-{}.sse", std::process::id()));
+        std::fs::write(
+            &path,
+            "data: {\"id\": \"a\", \"created\": 1}\n\ndata: [DONE]\n\n",
+        )
+        .unwrap();
+        let events = parse_sse(b"data: {\"created\":2,\"id\":\"a\"}\n\ndata: [DONE]\n\n");
+        assert_golden(&events, &path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            render_sse(&events),
+            "data: {\"created\":2,\"id\":\"a\"}\n\ndata: [DONE]\n\n"
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use crate::error::{Error, Result};
+use crate::models::{RouteConfig, TargetProtocol};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+
+/// 单一认证方式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthMethod {
+    /// `Authorization: Bearer <token>`
+    Bearer,
+    /// `x-api-key: <token>`
+    XApiKey,
+    /// 自定义请求头，值为令牌本身
+    Header(HeaderName),
+    /// URL 查询参数
+    Query(String),
+}
+
+/// 上游认证方案
+///
+/// 路由的 `auth_scheme` 由逗号分隔的认证方式组成，同时应用，如 `bearer,header:api-key`：
+/// - `bearer`：`Authorization: Bearer <token>`
+/// - `x-api-key`：`x-api-key: <token>`
+/// - `header:<name>`：自定义请求头
+/// - `query:<name>`：URL 查询参数（如 `query:key` 即 `?key=<token>`）
+/// - `none`：不附加认证
+///
+/// 未配置时按协议选择：Anthropic 使用 `x-api-key`，其他协议使用 `bearer`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamAuth {
+    methods: Vec<AuthMethod>,
+}
+
+impl UpstreamAuth {
+    pub fn parse(scheme: &str) -> Result<Self> {
+        let invalid =
+            |reason: &str| Error::Proxy(format!("Invalid auth_scheme {:?}: {}", scheme, reason));
+
+        let mut methods = Vec::new();
+        for part in scheme.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let method = match part.split_once(':') {
+                None => match part.to_ascii_lowercase().as_str() {
+                    "bearer" => AuthMethod::Bearer,
+                    "x-api-key" => AuthMethod::XApiKey,
+                    "none" => continue,
+                    _ => return Err(invalid("unknown scheme")),
+                },
+                Some((kind, name)) => {
+                    let name = name.trim();
+                    if name.is_empty() {
+                        return Err(invalid("missing name"));
+                    }
+                    match kind.trim().to_ascii_lowercase().as_str() {
+                        "header" => AuthMethod::Header(
+                            HeaderName::from_bytes(name.as_bytes())
+                                .map_err(|_| invalid("bad header name"))?,
+                        ),
+                        "query" => AuthMethod::Query(name.to_string()),
+                        _ => return Err(invalid("unknown scheme")),
+                    }
+                }
+            };
+            methods.push(method);
+        }
+        Ok(Self { methods })
+    }
+
+    /// 路由的认证方案，未配置 `auth_scheme` 时按协议选择
+    pub fn for_route(route_config: &RouteConfig) -> Result<Self> {
+        let default = match route_config.protocol {
+            TargetProtocol::Anthropic => AuthMethod::XApiKey,
+            TargetProtocol::OpenAI | TargetProtocol::Custom(_) => AuthMethod::Bearer,
+        };
+        Self::for_route_or(route_config, default)
+    }
+
+    /// 路由的认证方案，未配置 `auth_scheme` 时使用 `default`
+    pub fn for_route_or(route_config: &RouteConfig, default: AuthMethod) -> Result<Self> {
+        match &route_config.auth_scheme {
+            Some(scheme) => Self::parse(scheme),
+            None => Ok(Self {
+                methods: vec![default],
+            }),
+        }
+    }
+
+    /// 将令牌写入请求头和URL，返回附加查询参数后的URL
+    pub fn apply(&self, token: &str, headers: &mut HeaderMap, url: String) -> Result<String> {
+        let token_value = || {
+            HeaderValue::from_str(token).map_err(|_| Error::Proxy("Invalid token format".into()))
+        };
+
+        let mut query = Vec::new();
+        for method in &self.methods {
+            match method {
+                AuthMethod::Bearer => {
+                    headers.insert(
+                        AUTHORIZATION,
+                        HeaderValue::from_str(&format!("Bearer {}", token))
+                            .map_err(|_| Error::Proxy("Invalid token format".into()))?,
+                    );
+                }
+                AuthMethod::XApiKey => {
+                    headers.insert(HeaderName::from_static("x-api-key"), token_value()?);
+                }
+                AuthMethod::Header(name) => {
+                    headers.insert(name.clone(), token_value()?);
+                }
+                AuthMethod::Query(name) => query.push(name),
+            }
+        }
+
+        if query.is_empty() {
+            return Ok(url);
+        }
+        let mut url = reqwest::Url::parse(&url)
+            .map_err(|e| Error::Proxy(format!("Invalid upstream URL {}: {}", url, e)))?;
+        {
+            let mut pairs = url.query_pairs_mut();
+            for name in query {
+                pairs.append_pair(name, token);
+            }
+        }
+        Ok(url.into())
+    }
+}
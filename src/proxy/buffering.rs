@@ -0,0 +1,149 @@
+use crate::error::{Error, Result};
+use crate::models::RouteConfig;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, error::SendTimeoutError};
+use tokio::time::Duration;
+use tracing::warn;
+
+/// 有界缓冲的流式转发
+///
+/// 由后台任务读取上游字节流并写入容量为 `capacity` 的有界通道，客户端从通道消费。
+/// 客户端消费过慢导致通道写满时，后台任务会暂停读取上游（自然背压），
+/// 避免上游数据在网关内无限堆积。
+///
+/// 配置了 `slow_client_timeout` 时，通道持续写满超过该时长会放弃转发，
+/// 在下发完已缓冲的数据后以错误结束流。客户端断开时后台任务随即停止读取上游。
+/// 开启平滑输出的路由应通过 [`slow_client_timeout_for`] 关闭该超时。
+pub fn bounded_stream<S>(
+    stream: S,
+    capacity: usize,
+    slow_client_timeout: Option<Duration>,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    let capacity = capacity.max(1);
+    let (tx, mut rx) = mpsc::channel::<Result<Bytes>>(capacity);
+    let aborted = Arc::new(AtomicBool::new(false));
+
+    let producer_aborted = aborted.clone();
+    tokio::spawn(async move {
+        let mut stream = Box::pin(stream);
+
+        while let Some(item) = stream.next().await {
+            let is_err = item.is_err();
+
+            let sent = match slow_client_timeout {
+                Some(timeout) => match tx.send_timeout(item, timeout).await {
+                    Ok(()) => true,
+                    Err(SendTimeoutError::Timeout(_)) => {
+                        warn!(
+                            "Client did not drain stream buffer within {:?}, aborting stream",
+                            timeout
+                        );
+                        metrics::increment_counter!("gateway_stream_slow_client_aborts_total");
+                        producer_aborted.store(true, Ordering::SeqCst);
+                        false
+                    }
+                    Err(SendTimeoutError::Closed(_)) => false,
+                },
+                None => tx.send(item).await.is_ok(),
+            };
+
+            // 记录缓冲区占用（已缓冲的chunk数）
+            metrics::histogram!(
+                "gateway_stream_buffer_occupancy",
+                (capacity - tx.capacity()) as f64
+            );
+
+            if !sent || is_err {
+                break;
+            }
+        }
+    });
+
+    Box::pin(async_stream::stream! {
+        while let Some(item) = rx.recv().await {
+            yield item;
+        }
+
+        if aborted.load(Ordering::SeqCst) {
+            yield Err(Error::Proxy(
+                "Stream aborted: client is consuming too slowly".to_string(),
+            ));
+        }
+    })
+}
+
+/// 路由实际使用的慢客户端超时，开启平滑输出的路由不启用
+///
+/// 平滑阶段在缓冲区之后按节奏主动放慢消费，缓冲区写满并不代表客户端慢，
+/// 这类路由只保留有界缓冲的背压。
+pub fn slow_client_timeout_for(
+    slow_client_timeout: Option<Duration>,
+    route_config: &RouteConfig,
+) -> Option<Duration> {
+    slow_client_timeout.filter(|_| route_config.smooth_streaming.is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::smoothing::smooth_stream;
+    use serde_json::json;
+
+    fn route(smooth_streaming: Option<u32>) -> RouteConfig {
+        let mut route = json!({
+            "token": "sk-test",
+            "model": "gpt-4o",
+            "api": "https://api.openai.com",
+            "protocol": "openai",
+            "model_id": "m1",
+            "provider_id": "p1",
+            "provider_token_id": "pt1",
+        });
+        if let Some(tokens_per_second) = smooth_streaming {
+            route["smooth_streaming"] = json!({"tokens_per_second": tokens_per_second});
+        }
+        serde_json::from_value(route).unwrap()
+    }
+
+    // 上游一次性突发到达的 10 个事件
+    fn burst() -> impl Stream<Item = Result<Bytes>> + Send + 'static {
+        futures::stream::iter(
+            (0..10).map(|i| Ok(Bytes::from(format!("data: {{\"n\":{}}}\n\n", i)))),
+        )
+    }
+
+    #[tokio::test]
+    async fn smoothed_routes_are_not_aborted_as_slow_clients() {
+        let timeout = Some(Duration::from_millis(20));
+        let route = route(Some(50));
+        let buffered = bounded_stream(burst(), 1, slow_client_timeout_for(timeout, &route));
+        // 平滑阶段每 20ms 下发一个事件，整体耗时约 180ms，远超慢客户端超时
+        let output: Vec<Result<Bytes>> = smooth_stream(buffered, 50).collect().await;
+
+        assert_eq!(output.len(), 10);
+        assert!(output.iter().all(|chunk| chunk.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn slow_clients_are_still_aborted_on_other_routes() {
+        let timeout = slow_client_timeout_for(Some(Duration::from_millis(20)), &route(None));
+        assert_eq!(timeout, Some(Duration::from_millis(20)));
+
+        let mut stream = bounded_stream(burst(), 1, timeout);
+        let first = stream.next().await.unwrap();
+        assert!(first.is_ok());
+        // 客户端停止读取超过超时时长
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let rest: Vec<Result<Bytes>> = stream.collect().await;
+
+        assert!(rest.len() < 9);
+        assert!(matches!(rest.last(), Some(Err(Error::Proxy(msg))) if msg.contains("too slowly")));
+    }
+}
@@ -0,0 +1,216 @@
+use crate::config::{ExternalCompressorConfig, PromptCompressionConfig};
+use crate::models::{RouteConfig, TargetProtocol};
+use crate::protocol::detector::ProtocolDetector;
+use bytes::Bytes;
+use reqwest::Client;
+use serde_json::{json, Value};
+use tracing::{debug, warn};
+
+/// 一次提示词压缩前后的输入Token数（估算）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionStats {
+    pub original_tokens: u32,
+    pub compressed_tokens: u32,
+}
+
+/// 提示词压缩
+///
+/// 作用于协议转换后的请求体（OpenAI 或 Anthropic 格式），只改写 `system` 和 `messages`
+/// 中的文本内容；请求体无法解析时原样转发。
+pub struct PromptCompressor {
+    enabled: bool,
+    collapse_whitespace: bool,
+    dedupe_messages: bool,
+    external: Option<ExternalCompressorConfig>,
+}
+
+impl PromptCompressor {
+    pub fn new(config: &PromptCompressionConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            collapse_whitespace: config.collapse_whitespace,
+            dedupe_messages: config.dedupe_messages,
+            external: config.external.clone(),
+        }
+    }
+
+    /// 路由是否启用提示词压缩
+    pub fn is_enabled_for(&self, route_config: &RouteConfig) -> bool {
+        route_config.prompt_compression.unwrap_or(self.enabled)
+    }
+
+    /// 压缩请求体，返回压缩后的请求体及压缩前后的Token数；路由未启用时原样返回
+    pub async fn compress(
+        &self,
+        client: &Client,
+        route_config: &RouteConfig,
+        body: Bytes,
+    ) -> (Bytes, Option<CompressionStats>) {
+        if !self.is_enabled_for(route_config) {
+            return (body, None);
+        }
+        let Ok(mut json) = serde_json::from_slice::<Value>(&body) else {
+            return (body, None);
+        };
+        let Some(original_tokens) = ProtocolDetector::estimate_prompt_tokens(&body) else {
+            return (body, None);
+        };
+
+        self.compress_locally(&mut json);
+        if let Some(external) = &self.external {
+            match compress_externally(client, external, &route_config.protocol, &json).await {
+                Ok(compressed) => json = compressed,
+                Err(e) => warn!(
+                    "External prompt compressor failed, using local result: {}",
+                    e
+                ),
+            }
+        }
+
+        let compressed = match serde_json::to_vec(&json) {
+            Ok(compressed) => Bytes::from(compressed),
+            Err(_) => return (body, None),
+        };
+        let compressed_tokens =
+            ProtocolDetector::estimate_prompt_tokens(&compressed).unwrap_or(original_tokens);
+        debug!(
+            "Prompt compressed from ~{} to ~{} tokens",
+            original_tokens, compressed_tokens
+        );
+
+        (
+            compressed,
+            Some(CompressionStats {
+                original_tokens,
+                compressed_tokens,
+            }),
+        )
+    }
+
+    fn compress_locally(&self, json: &mut Value) {
+        if self.collapse_whitespace {
+            if let Some(system) = json.get_mut("system") {
+                collapse_content(system);
+            }
+            if let Some(Value::Array(messages)) = json.get_mut("messages") {
+                for message in messages {
+                    if let Some(content) = message.get_mut("content") {
+                        collapse_content(content);
+                    }
+                }
+            }
+        }
+
+        if self.dedupe_messages {
+            if let Some(Value::Array(messages)) = json.get_mut("messages") {
+                let before = messages.len();
+                messages.dedup();
+                if messages.len() < before {
+                    debug!(
+                        "Removed {} repeated history messages",
+                        before - messages.len()
+                    );
+                }
+            }
+        }
+    }
+}
+
+// 调用外部压缩服务
+async fn compress_externally(
+    client: &Client,
+    external: &ExternalCompressorConfig,
+    protocol: &TargetProtocol,
+    request: &Value,
+) -> Result<Value, String> {
+    let protocol = match protocol {
+        TargetProtocol::OpenAI => "openai",
+        TargetProtocol::Anthropic => "anthropic",
+        TargetProtocol::Custom(name) => name.as_str(),
+    };
+
+    let response = client
+        .post(&external.url)
+        .timeout(external.timeout)
+        .json(&json!({ "protocol": protocol, "request": request }))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("status {}", response.status()));
+    }
+
+    let mut body: Value = response.json().await.map_err(|e| e.to_string())?;
+    match body.get_mut("request").map(Value::take) {
+        Some(compressed @ Value::Object(_)) => Ok(compressed),
+        _ => Err("response has no request object".to_string()),
+    }
+}
+
+// 压缩消息内容：字符串内容或内容块数组中的 text 字段
+fn collapse_content(content: &mut Value) {
+    match content {
+        Value::String(text) => *text = collapse_whitespace(text),
+        Value::Array(parts) => {
+            for part in parts {
+                if let Some(Value::String(text)) = part.get_mut("text") {
+                    *text = collapse_whitespace(text);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// 合并行内连续空白（保留行首缩进）、去除行尾空白、将连续空行合并为一个；
+// ``` 围起的代码块内只去除行尾空白
+fn collapse_whitespace(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_code_block = false;
+    let mut blank_run = 0;
+
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+        }
+
+        if line.is_empty() && !in_code_block {
+            blank_run += 1;
+            if blank_run > 1 {
+                continue;
+            }
+        } else {
+            blank_run = 0;
+        }
+
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        if in_code_block {
+            out.push_str(line);
+            continue;
+        }
+
+        let body = line.trim_start();
+        out.push_str(&line[..line.len() - body.len()]);
+        let mut previous_space = false;
+        for c in body.chars() {
+            if c == ' ' || c == '\t' {
+                if !previous_space {
+                    out.push(' ');
+                }
+                previous_space = true;
+            } else {
+                out.push(c);
+                previous_space = false;
+            }
+        }
+    }
+
+    // 保留原文末尾换行（连续空行已合并）
+    if text.ends_with('\n') && !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
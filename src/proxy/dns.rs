@@ -0,0 +1,117 @@
+//! 上游域名解析
+//!
+//! 替换 reqwest 默认的系统解析：结果按配置的时长缓存，解析失败时沿用过期的缓存结果，
+//! 静态解析的域名直接返回配置的IP；返回前按 IP 协议偏好排序或过滤地址。
+//! 连接器按地址顺序先尝试首选协议，首选协议短时间内未连通时再并行尝试另一协议（happy eyeballs）。
+
+use crate::config::{DnsConfig, IpPreference};
+use dashmap::DashMap;
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+pub struct CachingResolver {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    ttl: Duration,
+    preference: IpPreference,
+    // 静态解析（域名小写）
+    overrides: HashMap<String, Vec<IpAddr>>,
+    // 域名 -> (解析时间, 地址)
+    cache: DashMap<String, (Instant, Arc<Vec<IpAddr>>)>,
+}
+
+impl CachingResolver {
+    pub fn new(config: &DnsConfig) -> Self {
+        let overrides = config
+            .overrides
+            .iter()
+            .map(|(host, ips)| {
+                let ips = ips.iter().filter_map(|ip| ip.parse().ok()).collect();
+                (host.to_ascii_lowercase(), ips)
+            })
+            .collect();
+        Self {
+            inner: Arc::new(Inner {
+                ttl: config.cache_ttl,
+                preference: config.ip_preference,
+                overrides,
+                cache: DashMap::new(),
+            }),
+        }
+    }
+}
+
+impl Inner {
+    async fn lookup(&self, host: &str) -> io::Result<Arc<Vec<IpAddr>>> {
+        if let Some(ips) = self.overrides.get(host) {
+            return Ok(Arc::new(ips.clone()));
+        }
+
+        let cached = self.cache.get(host).map(|entry| entry.value().clone());
+        if let Some((resolved_at, ips)) = &cached {
+            if resolved_at.elapsed() < self.ttl {
+                return Ok(ips.clone());
+            }
+        }
+
+        // 端口由连接器按 URL 设置
+        match tokio::net::lookup_host((host, 0)).await {
+            Ok(addrs) => {
+                let ips = Arc::new(addrs.map(|addr| addr.ip()).collect::<Vec<_>>());
+                if !self.ttl.is_zero() && !ips.is_empty() {
+                    self.cache
+                        .insert(host.to_string(), (Instant::now(), ips.clone()));
+                }
+                Ok(ips)
+            }
+            Err(e) => match cached {
+                Some((_, ips)) => {
+                    warn!("DNS lookup for {} failed, using stale result: {}", host, e);
+                    metrics::increment_counter!("gateway_dns_stale_total");
+                    Ok(ips)
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    // 按协议偏好排序或过滤地址
+    fn order(&self, ips: &[IpAddr]) -> Vec<IpAddr> {
+        let (v4, v6): (Vec<IpAddr>, Vec<IpAddr>) = ips.iter().partition(|ip| ip.is_ipv4());
+        match self.preference {
+            IpPreference::Auto => ips.to_vec(),
+            IpPreference::Ipv4 => v4.into_iter().chain(v6).collect(),
+            IpPreference::Ipv6 => v6.into_iter().chain(v4).collect(),
+            IpPreference::Ipv4Only => v4,
+            IpPreference::Ipv6Only => v6,
+        }
+    }
+}
+
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.inner.clone();
+        Box::pin(async move {
+            let host = name.as_str().to_ascii_lowercase();
+            let ips = resolver.order(&resolver.lookup(&host).await?);
+            if ips.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No usable address for {} ({:?})", host, resolver.preference),
+                )
+                .into());
+            }
+            debug!("Resolved {} to {:?}", host, ips);
+            let addrs: Addrs = Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
@@ -0,0 +1,163 @@
+use crate::config::{FaultInjectionConfig, FaultKind};
+use crate::error::{Error, Result};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use reqwest::header::{HeaderMap, HeaderName};
+use std::pin::Pin;
+use std::time::Duration;
+use tracing::warn;
+
+/// 本次路由尝试注入的故障
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    Latency(Duration),
+    /// 以该状态码失败
+    Error(u16),
+    /// 转发该字节数后中断流
+    Truncate(usize),
+}
+
+/// 故障注入
+///
+/// 在预发环境模拟上游延迟、5xx 和流中断，验证故障转移、熔断和遥测行为
+pub struct FaultInjector {
+    enabled: bool,
+    rate: f64,
+    header: Option<HeaderName>,
+    kinds: Vec<FaultKind>,
+    latency: Duration,
+    error_status: u16,
+    truncate_after_bytes: usize,
+}
+
+impl FaultInjector {
+    pub fn from_config(config: &FaultInjectionConfig) -> Self {
+        if config.enabled {
+            warn!(
+                "Fault injection enabled (rate {}, kinds {:?}): do not use in production",
+                config.rate, config.kinds
+            );
+        }
+        Self {
+            enabled: config.enabled,
+            rate: config.rate,
+            header: config
+                .header
+                .as_deref()
+                .and_then(|h| HeaderName::from_bytes(h.as_bytes()).ok()),
+            kinds: config.kinds.clone(),
+            latency: config.latency,
+            error_status: config.error_status,
+            truncate_after_bytes: config.truncate_after_bytes,
+        }
+    }
+
+    /// 是否开启故障注入
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 限定注入范围的请求头，转发上游前需剥离
+    pub fn scope_header(&self) -> Option<&HeaderName> {
+        self.header.as_ref().filter(|_| self.enabled)
+    }
+
+    /// 为一次路由尝试选择故障，不注入时返回 None
+    ///
+    /// 非流式请求不注入截断
+    pub fn pick(&self, client_headers: &HeaderMap, stream: bool) -> Option<Fault> {
+        if !self.enabled {
+            return None;
+        }
+        let applicable = |kind: &FaultKind| stream || *kind != FaultKind::Truncate;
+
+        let kind = match &self.header {
+            Some(header) => {
+                let value = client_headers.get(header)?.to_str().unwrap_or_default();
+                // 请求头的值指定了故障类型时必定注入
+                match parse_kind(value).filter(applicable) {
+                    Some(kind) => kind,
+                    None => self.roll(applicable)?,
+                }
+            }
+            None => self.roll(applicable)?,
+        };
+
+        metrics::increment_counter!("gateway_fault_injected_total", "kind" => kind_label(kind));
+        Some(match kind {
+            FaultKind::Latency => Fault::Latency(self.latency),
+            FaultKind::Error => Fault::Error(self.error_status),
+            FaultKind::Truncate => Fault::Truncate(self.truncate_after_bytes),
+        })
+    }
+
+    // 按比例决定是否注入，并随机选择故障类型
+    fn roll(&self, applicable: impl Fn(&FaultKind) -> bool) -> Option<FaultKind> {
+        let mut rng = rand::thread_rng();
+        if !rng.gen_bool(self.rate.clamp(0.0, 1.0)) {
+            return None;
+        }
+        let kinds: Vec<FaultKind> = self.kinds.iter().copied().filter(applicable).collect();
+        kinds.choose(&mut rng).copied()
+    }
+}
+
+/// 注入的上游错误，格式与真实上游错误一致，按状态码参与故障分类
+pub fn injected_error(status: u16) -> Error {
+    Error::Proxy(format!(
+        "Upstream returned error status {}: injected fault",
+        status
+    ))
+}
+
+/// 转发 `limit` 字节后以错误中断流
+pub fn truncate_stream<S>(
+    stream: S,
+    limit: usize,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    Box::pin(async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        let mut remaining = limit;
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            if chunk.len() >= remaining {
+                if remaining > 0 {
+                    yield Ok(chunk.slice(..remaining));
+                }
+                warn!("Injected fault: truncating upstream stream after {} bytes", limit);
+                yield Err(Error::Proxy("Injected fault: upstream stream truncated".to_string()));
+                return;
+            }
+            remaining -= chunk.len();
+            yield Ok(chunk);
+        }
+    })
+}
+
+fn parse_kind(value: &str) -> Option<FaultKind> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "latency" => Some(FaultKind::Latency),
+        "error" => Some(FaultKind::Error),
+        "truncate" => Some(FaultKind::Truncate),
+        _ => None,
+    }
+}
+
+fn kind_label(kind: FaultKind) -> &'static str {
+    match kind {
+        FaultKind::Latency => "latency",
+        FaultKind::Error => "error",
+        FaultKind::Truncate => "truncate",
+    }
+}
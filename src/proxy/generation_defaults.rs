@@ -0,0 +1,37 @@
+//! 默认生成参数
+//!
+//! 转发前按路由的 `generation_defaults` 填入客户端未指定的 temperature、top_p，
+//! 配置 `force` 时覆盖客户端的值。OpenAI、Anthropic 和 Responses API 请求使用相同的字段名，
+//! 在协议转换前应用，目标模型不支持的参数随后由能力适配移除。
+
+use crate::models::GenerationDefaults;
+use bytes::Bytes;
+use serde_json::{json, Value};
+
+/// 将默认生成参数应用到请求（客户端协议格式）
+///
+/// 请求体不是 JSON 对象时原样返回
+pub fn apply(body: Bytes, defaults: &GenerationDefaults) -> Bytes {
+    let Ok(Value::Object(mut obj)) = serde_json::from_slice::<Value>(&body) else {
+        return body;
+    };
+
+    let mut changed = false;
+    for (field, value) in [
+        ("temperature", defaults.temperature),
+        ("top_p", defaults.top_p),
+    ] {
+        let Some(value) = value else {
+            continue;
+        };
+        if defaults.force || obj.get(field).is_none_or(Value::is_null) {
+            obj.insert(field.to_string(), json!(value));
+            changed = true;
+        }
+    }
+    if !changed {
+        return body;
+    }
+
+    serde_json::to_vec(&obj).map(Bytes::from).unwrap_or(body)
+}
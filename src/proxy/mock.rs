@@ -0,0 +1,261 @@
+use super::UpstreamResponse;
+use crate::config::MockUpstreamConfig;
+use crate::error::Result;
+use crate::models::{RouteConfig, TargetProtocol};
+use bytes::Bytes;
+use futures::Stream;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use serde_json::{json, Value};
+use std::pin::Pin;
+
+/// 由模拟上游响应的路由地址前缀
+pub const MOCK_SCHEME: &str = "mock://";
+
+/// 生成内容使用的词表，每个词计为一个token
+const WORDS: [&str; 8] = [
+    "Mock ",
+    "response ",
+    "from ",
+    "the ",
+    "gateway ",
+    "load ",
+    "test ",
+    "upstream. ",
+];
+
+/// 内置模拟上游
+///
+/// 按路由协议（OpenAI / Anthropic）生成合成的流式或非流式响应，带有可配置的延迟和Token数，
+/// 响应中的 usage 与生成内容一致，用于压测网关的协议转换、用量统计和遥测流程。
+pub struct MockUpstream {
+    config: MockUpstreamConfig,
+}
+
+/// 一次模拟调用的参数
+struct MockCall {
+    anthropic: bool,
+    model: String,
+    input_tokens: u32,
+    output_tokens: u32,
+    // 输出是否被 max_tokens 截断
+    truncated: bool,
+}
+
+impl MockUpstream {
+    pub fn new(config: &MockUpstreamConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    /// 路由是否由模拟上游响应
+    pub fn handles(&self, route_config: &RouteConfig) -> bool {
+        self.config.enabled || route_config.api_endpoint.starts_with(MOCK_SCHEME)
+    }
+
+    /// 非流式响应
+    pub async fn complete(
+        &self,
+        route_config: &RouteConfig,
+        request_body: &[u8],
+    ) -> Result<UpstreamResponse<Bytes>> {
+        let call = self.call(route_config, request_body);
+        tokio::time::sleep(self.config.latency).await;
+
+        let text: String = (0..call.output_tokens).map(word).collect();
+        let body = if call.anthropic {
+            json!({
+                "id": format!("msg_mock_{}", uuid::Uuid::new_v4().simple()),
+                "type": "message",
+                "role": "assistant",
+                "model": call.model,
+                "content": [{"type": "text", "text": text}],
+                "stop_reason": if call.truncated { "max_tokens" } else { "end_turn" },
+                "stop_sequence": null,
+                "usage": {"input_tokens": call.input_tokens, "output_tokens": call.output_tokens},
+            })
+        } else {
+            json!({
+                "id": format!("chatcmpl-mock-{}", uuid::Uuid::new_v4().simple()),
+                "object": "chat.completion",
+                "created": chrono::Utc::now().timestamp(),
+                "model": call.model,
+                "choices": [{
+                    "index": 0,
+                    "message": {"role": "assistant", "content": text},
+                    "finish_reason": if call.truncated { "length" } else { "stop" },
+                }],
+                "usage": {
+                    "prompt_tokens": call.input_tokens,
+                    "completion_tokens": call.output_tokens,
+                    "total_tokens": call.input_tokens + call.output_tokens,
+                },
+            })
+        };
+
+        Ok(UpstreamResponse {
+            headers: content_type("application/json"),
+            body: Bytes::from(serde_json::to_vec(&body)?),
+            request_id: None,
+        })
+    }
+
+    /// 流式响应（SSE），每个token一个事件
+    pub async fn stream(
+        &self,
+        route_config: &RouteConfig,
+        request_body: &[u8],
+    ) -> Result<UpstreamResponse<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>>> {
+        let call = self.call(route_config, request_body);
+        let latency = self.config.latency;
+        let interval = self.config.token_interval;
+
+        let body = async_stream::stream! {
+            tokio::time::sleep(latency).await;
+
+            let events = if call.anthropic {
+                anthropic_events(&call)
+            } else {
+                openai_events(&call)
+            };
+            for (i, event) in events.into_iter().enumerate() {
+                if i > 0 && !interval.is_zero() {
+                    tokio::time::sleep(interval).await;
+                }
+                yield Ok(Bytes::from(event));
+            }
+        };
+
+        Ok(UpstreamResponse {
+            headers: content_type("text/event-stream"),
+            body: Box::pin(body),
+            request_id: None,
+        })
+    }
+
+    fn call(&self, route_config: &RouteConfig, request_body: &[u8]) -> MockCall {
+        let request: Value = serde_json::from_slice(request_body).unwrap_or_default();
+        let max_tokens = request
+            .get("max_tokens")
+            .or_else(|| request.get("max_completion_tokens"))
+            .and_then(Value::as_u64)
+            .map(|n| n.min(u64::from(u32::MAX)) as u32);
+        let output_tokens = max_tokens.map_or(self.config.output_tokens, |max| {
+            max.min(self.config.output_tokens)
+        });
+
+        MockCall {
+            anthropic: matches!(route_config.protocol, TargetProtocol::Anthropic),
+            model: request
+                .get("model")
+                .and_then(Value::as_str)
+                .unwrap_or(&route_config.model)
+                .to_string(),
+            // 按约4字节一个token估算
+            input_tokens: (request_body.len() / 4).max(1) as u32,
+            output_tokens,
+            truncated: output_tokens < self.config.output_tokens,
+        }
+    }
+}
+
+fn word(i: u32) -> &'static str {
+    WORDS[i as usize % WORDS.len()]
+}
+
+fn content_type(value: &'static str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(value));
+    headers
+}
+
+fn openai_events(call: &MockCall) -> Vec<String> {
+    let id = format!("chatcmpl-mock-{}", uuid::Uuid::new_v4().simple());
+    let created = chrono::Utc::now().timestamp();
+    let chunk = |delta: Value, finish_reason: Value| {
+        let data = json!({
+            "id": id,
+            "object": "chat.completion.chunk",
+            "created": created,
+            "model": call.model,
+            "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}],
+        });
+        format!("data: {}\n\n", data)
+    };
+
+    let mut events = vec![chunk(
+        json!({"role": "assistant", "content": ""}),
+        Value::Null,
+    )];
+    events.extend((0..call.output_tokens).map(|i| chunk(json!({"content": word(i)}), Value::Null)));
+    events.push(chunk(
+        json!({}),
+        json!(if call.truncated { "length" } else { "stop" }),
+    ));
+
+    let usage = json!({
+        "id": id,
+        "object": "chat.completion.chunk",
+        "created": created,
+        "model": call.model,
+        "choices": [],
+        "usage": {
+            "prompt_tokens": call.input_tokens,
+            "completion_tokens": call.output_tokens,
+            "total_tokens": call.input_tokens + call.output_tokens,
+        },
+    });
+    events.push(format!("data: {}\n\n", usage));
+    events.push("data: [DONE]\n\n".to_string());
+    events
+}
+
+fn anthropic_events(call: &MockCall) -> Vec<String> {
+    let event = |name: &str, data: Value| format!("event: {}\ndata: {}\n\n", name, data);
+
+    let mut events = vec![
+        event(
+            "message_start",
+            json!({
+                "type": "message_start",
+                "message": {
+                    "id": format!("msg_mock_{}", uuid::Uuid::new_v4().simple()),
+                    "type": "message",
+                    "role": "assistant",
+                    "model": call.model,
+                    "content": [],
+                    "stop_reason": null,
+                    "stop_sequence": null,
+                    "usage": {"input_tokens": call.input_tokens, "output_tokens": 1},
+                },
+            }),
+        ),
+        event(
+            "content_block_start",
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+        ),
+    ];
+    events.extend((0..call.output_tokens).map(|i| {
+        event(
+            "content_block_delta",
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": word(i)}}),
+        )
+    }));
+    events.push(event(
+        "content_block_stop",
+        json!({"type": "content_block_stop", "index": 0}),
+    ));
+    events.push(event(
+        "message_delta",
+        json!({
+            "type": "message_delta",
+            "delta": {
+                "stop_reason": if call.truncated { "max_tokens" } else { "end_turn" },
+                "stop_sequence": null,
+            },
+            "usage": {"output_tokens": call.output_tokens},
+        }),
+    ));
+    events.push(event("message_stop", json!({"type": "message_stop"})));
+    events
+}
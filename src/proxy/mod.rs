@@ -1,48 +1,396 @@
+pub mod auth;
+pub mod buffering;
+pub mod compression;
+pub mod dns;
+pub mod fault;
+pub mod generation_defaults;
+pub mod mock;
+pub mod model_rewrite;
+pub mod mtls;
+pub mod output_cap;
+pub mod racing;
+pub mod size_limit;
+pub mod smoothing;
+pub mod truncation;
+pub mod validation;
+pub mod warmup;
+pub mod watermark;
+
 use crate::config::ProxyConfig;
 use crate::error::{Error, Result};
 use crate::models::RouteConfig;
+use auth::{AuthMethod, UpstreamAuth};
+use buffering::{bounded_stream, slow_client_timeout_for};
 use bytes::Bytes;
-use futures::StreamExt;
+use compression::{CompressionStats, PromptCompressor};
+use dashmap::DashMap;
+use fault::{Fault, FaultInjector};
+use futures::{Stream, StreamExt};
+use mock::MockUpstream;
+use mtls::ClientIdentities;
+use racing::RouteRacing;
 use reqwest::{
-    header::{HeaderMap, HeaderName, HeaderValue},
-    Client, Response,
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE},
+    Client, Response, StatusCode,
 };
-use tracing::{error, info};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+use truncation::TruncationCheck;
+use watermark::Watermark;
+
+/// 上游连接池中空闲连接的保留时长
+pub const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// 预热请求的超时时间
+const WARMUP_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct ProxyForwarder {
     client: Client,
     // Dedicated client for streaming (no global timeout)
     streaming_client: Client,
+    // 上游 mTLS 客户端身份
+    identities: ClientIdentities,
+    // 透传给客户端的上游响应头白名单（小写）
+    passthrough_headers: Vec<String>,
+    // 流式转发缓冲区容量与慢客户端超时
+    stream_buffer_capacity: usize,
+    slow_client_timeout: Option<std::time::Duration>,
+    // 上游单个 SSE 事件的字节数上限
+    max_sse_event_bytes: usize,
+    // 上游非流式响应体和流式响应累计的字节数上限
+    max_response_bytes: Option<u64>,
+    max_stream_bytes: Option<u64>,
+    // 各上游 origin 的请求次数，用于选择预热目标
+    endpoint_hits: DashMap<String, u64>,
+    // 客户端请求头清理
+    header_hygiene: HeaderHygiene,
+    // 内置模拟上游
+    mock: MockUpstream,
+    // 提示词压缩
+    compressor: PromptCompressor,
+    // 未在路由上指定时的最大输出Token数
+    max_output_tokens: Option<u32>,
+    // 故障注入
+    faults: FaultInjector,
+    // 非流式请求的路由竞速
+    racing: RouteRacing,
+    truncation: TruncationCheck,
+    // 响应元数据标记
+    watermark: Option<Watermark>,
+}
+
+/// 客户端请求头清理规则
+struct HeaderHygiene {
+    enabled: bool,
+    // 剥离的请求头模式（小写）
+    strip: Vec<String>,
+    // 剥离后写入的请求头
+    set: HeaderMap,
 }
 
+/// 上游失败类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureClass {
+    /// 瞬时故障，稍后重试可能成功
+    Transient,
+    /// 确定性故障，同一请求体重试仍会失败
+    Deterministic,
+}
+
+/// 上游响应：透传白名单内的响应头和响应体
+pub struct UpstreamResponse<B> {
+    /// 按白名单筛选后的上游响应头
+    pub headers: HeaderMap,
+    pub body: B,
+    /// 上游请求ID（供应商响应头中的 `x-request-id` 等），向供应商反馈问题时使用
+    pub request_id: Option<String>,
+}
+
+/// 文件上传的请求体
+pub struct FileUpload {
+    pub body: reqwest::Body,
+    /// 客户端请求的 `content-type`（含 multipart boundary）
+    pub content_type: String,
+    /// 客户端请求的 `content-length`，分块上传时为空
+    pub content_length: Option<u64>,
+}
+
+/// 携带上游请求ID的响应头，按顺序取第一个
+const UPSTREAM_REQUEST_ID_HEADERS: [&str; 3] =
+    ["x-request-id", "request-id", "anthropic-request-id"];
+
+/// 上游请求ID的最大长度
+const MAX_UPSTREAM_REQUEST_ID_LEN: usize = 128;
+
 impl ProxyForwarder {
     pub fn new(config: ProxyConfig) -> Result<Self> {
-        // Standard client: obeys configured request timeout
-        let client = Client::builder()
-            .timeout(config.timeout)
-            .pool_max_idle_per_host(config.max_connections)
-            .pool_idle_timeout(std::time::Duration::from_secs(60))
-            .tcp_keepalive(if config.keep_alive {
-                Some(std::time::Duration::from_secs(30))
-            } else {
-                None
-            })
-            .build()
-            .map_err(|e| Error::Http(e))?;
-
-        // Streaming client: no global request timeout to allow long-lived SSE
-        let streaming_client = Client::builder()
-            .pool_max_idle_per_host(config.max_connections)
-            .pool_idle_timeout(std::time::Duration::from_secs(60))
-            .tcp_keepalive(if config.keep_alive {
-                Some(std::time::Duration::from_secs(30))
+        // 所有客户端共用解析缓存
+        let resolver = Arc::new(dns::CachingResolver::new(&config.dns));
+        let builder = |streaming: bool| {
+            let builder = Client::builder()
+                .dns_resolver(resolver.clone())
+                .pool_max_idle_per_host(config.max_connections)
+                .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+                .tcp_keepalive(if config.keep_alive {
+                    Some(std::time::Duration::from_secs(30))
+                } else {
+                    None
+                });
+            // Streaming client: no global request timeout to allow long-lived SSE
+            if streaming {
+                builder
             } else {
-                None
-            })
-            .build()
-            .map_err(|e| Error::Http(e))?;
+                builder.timeout(config.timeout)
+            }
+        };
+
+        // Standard client: obeys configured request timeout
+        let client = builder(false).build().map_err(Error::Http)?;
+        let streaming_client = builder(true).build().map_err(Error::Http)?;
+        // mTLS 客户端身份各自使用独立的客户端
+        let identities = ClientIdentities::from_config(&config.client_identities, builder)?;
+
+        let passthrough_headers = config
+            .passthrough_headers
+            .iter()
+            .map(|h| h.to_ascii_lowercase())
+            .collect();
+
+        if config.mock_upstream.enabled {
+            warn!("Mock upstream enabled: all routes are served with synthetic responses");
+        }
+
+        let hygiene = &config.header_hygiene;
+        let header_hygiene = HeaderHygiene {
+            enabled: hygiene.enabled,
+            strip: hygiene
+                .strip
+                .iter()
+                .map(|h| h.to_ascii_lowercase())
+                .collect(),
+            set: hygiene
+                .set
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((
+                        HeaderName::from_bytes(name.as_bytes()).ok()?,
+                        HeaderValue::from_str(value).ok()?,
+                    ))
+                })
+                .collect(),
+        };
+
+        Ok(Self {
+            client,
+            streaming_client,
+            identities,
+            passthrough_headers,
+            stream_buffer_capacity: config.stream_buffer_capacity,
+            slow_client_timeout: config.slow_client_timeout,
+            max_sse_event_bytes: config.max_sse_event_bytes,
+            max_response_bytes: config.max_response_bytes,
+            max_stream_bytes: config.max_stream_bytes,
+            endpoint_hits: DashMap::new(),
+            header_hygiene,
+            mock: MockUpstream::new(&config.mock_upstream),
+            compressor: PromptCompressor::new(&config.prompt_compression),
+            max_output_tokens: config.max_output_tokens,
+            faults: FaultInjector::from_config(&config.fault_injection),
+            racing: RouteRacing::from_config(&config.route_racing),
+            truncation: TruncationCheck::from_config(&config.truncation_retry),
+            watermark: Watermark::from_config(&config.watermark),
+        })
+    }
+
+    // 路由使用的客户端，路由使用 mTLS 客户端身份时为该身份的客户端
+    fn client_for(&self, route_config: &RouteConfig) -> Result<&Client> {
+        Ok(match self.identities.for_route(route_config)? {
+            Some(identity) => &identity.client,
+            None => &self.client,
+        })
+    }
+
+    // 路由使用的流式客户端
+    fn streaming_client_for(&self, route_config: &RouteConfig) -> Result<&Client> {
+        Ok(match self.identities.for_route(route_config)? {
+            Some(identity) => &identity.streaming_client,
+            None => &self.streaming_client,
+        })
+    }
+
+    /// 转发给上游的客户端请求头，路由启用请求头清理时剥离指纹头并写入网关控制的值
+    fn client_headers_for(
+        &self,
+        route_config: &RouteConfig,
+        client_headers: &HeaderMap,
+    ) -> HeaderMap {
+        let hygiene = &self.header_hygiene;
+        let mut headers = if route_config.header_hygiene.unwrap_or(hygiene.enabled) {
+            let mut headers: HeaderMap = client_headers
+                .iter()
+                .filter(|(name, _)| !header_matches(&hygiene.strip, name.as_str()))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect();
+            for (name, value) in hygiene.set.iter() {
+                headers.insert(name.clone(), value.clone());
+            }
+            headers
+        } else {
+            client_headers.clone()
+        };
+
+        // 故障注入、路由竞速的范围请求头只对网关有意义
+        for header in [self.faults.scope_header(), self.racing.scope_header()]
+            .into_iter()
+            .flatten()
+        {
+            headers.remove(header);
+        }
+        headers
+    }
+
+    /// 非流式请求同时尝试的路由数，不竞速时返回 None
+    pub fn race_width(&self, client_headers: &HeaderMap) -> Option<usize> {
+        self.racing.width(client_headers)
+    }
+
+    /// 按故障注入配置处理本次路由尝试：注入延迟或错误，返回流式响应的截断字节数
+    async fn inject_fault(
+        &self,
+        route_config: &RouteConfig,
+        client_headers: &HeaderMap,
+        stream: bool,
+    ) -> Result<Option<usize>> {
+        match self.faults.pick(client_headers, stream) {
+            None => Ok(None),
+            Some(Fault::Latency(delay)) => {
+                warn!(
+                    "Injected fault: delaying request to {} by {:?}",
+                    route_config.api_endpoint, delay
+                );
+                tokio::time::sleep(delay).await;
+                Ok(None)
+            }
+            Some(Fault::Error(status)) => {
+                warn!(
+                    "Injected fault: failing request to {} with status {}",
+                    route_config.api_endpoint, status
+                );
+                Err(fault::injected_error(status))
+            }
+            Some(Fault::Truncate(limit)) => Ok(Some(limit)),
+        }
+    }
+
+    /// 按路由配置压缩协议转换后的请求体，返回压缩后的请求体及压缩前后的Token数
+    pub async fn compress_prompt(
+        &self,
+        route_config: &RouteConfig,
+        body: Bytes,
+    ) -> (Bytes, Option<CompressionStats>) {
+        self.compressor
+            .compress(&self.client, route_config, body)
+            .await
+    }
+
+    /// 路由的最大输出Token数，未在路由上指定时使用全局配置
+    pub fn output_cap(&self, route_config: &RouteConfig) -> Option<u32> {
+        route_config
+            .max_output_tokens
+            .or(self.max_output_tokens)
+            .filter(|cap| *cap > 0)
+    }
+
+    /// 路由的响应元数据标记，未在路由上指定时使用全局配置
+    pub fn watermark(&self, route_config: &RouteConfig) -> Option<&Watermark> {
+        self.watermark
+            .as_ref()
+            .filter(|w| route_config.watermark.unwrap_or(w.enabled_by_default()))
+    }
+
+    /// 上游单个 SSE 事件的字节数上限
+    pub fn max_sse_event_bytes(&self) -> usize {
+        self.max_sse_event_bytes
+    }
+
+    /// 上游非流式响应体的字节数上限
+    pub fn max_response_bytes(&self) -> Option<u64> {
+        self.max_response_bytes
+    }
+
+    /// 上游流式响应累计的字节数上限
+    pub fn max_stream_bytes(&self) -> Option<u64> {
+        self.max_stream_bytes
+    }
+
+    /// 按白名单筛选上游响应头
+    fn select_passthrough_headers(&self, headers: &HeaderMap) -> HeaderMap {
+        let mut selected = HeaderMap::new();
+
+        for (name, value) in headers.iter() {
+            let allowed = header_matches(&self.passthrough_headers, name.as_str());
+
+            if allowed {
+                selected.append(name.clone(), value.clone());
+            }
+        }
 
-        Ok(Self { client, streaming_client })
+        selected
+    }
+
+    /// 记录一次对上游的请求，按 origin（scheme://host:port）计数
+    fn record_endpoint(&self, api_endpoint: &str) {
+        if let Some(origin) = endpoint_origin(api_endpoint) {
+            *self.endpoint_hits.entry(origin).or_insert(0) += 1;
+        }
+    }
+
+    /// 请求量最高的前 `limit` 个上游 origin
+    pub fn top_endpoints(&self, limit: usize) -> Vec<String> {
+        let mut hits: Vec<(String, u64)> = self
+            .endpoint_hits
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect();
+        hits.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        hits.into_iter()
+            .take(limit)
+            .map(|(origin, _)| origin)
+            .collect()
+    }
+
+    /// 预先建立到上游的连接
+    ///
+    /// 普通和流式两个连接池各并发发送 `connections` 个 HEAD 请求，
+    /// 完成 TCP/TLS 握手后连接留在池中供后续请求复用（HTTP/2 上游只需一条连接）。
+    /// 上游返回任何状态码都算成功，返回成功建立的请求数。
+    pub async fn warm_up(&self, endpoint: &str, connections: usize) -> usize {
+        let Some(origin) = endpoint_origin(endpoint) else {
+            return 0;
+        };
+
+        let clients = match self.identities.for_endpoint(&origin) {
+            Some(identity) => [&identity.client, &identity.streaming_client],
+            None => [&self.client, &self.streaming_client],
+        };
+        let requests = clients
+            .into_iter()
+            .flat_map(|client| std::iter::repeat_n(client, connections))
+            .map(|client| client.head(&origin).timeout(WARMUP_REQUEST_TIMEOUT).send());
+
+        let mut warmed = 0;
+        for result in futures::future::join_all(requests).await {
+            match result {
+                Ok(response) => {
+                    debug!("Warmed connection to {} ({:?})", origin, response.version());
+                    warmed += 1;
+                }
+                Err(e) => debug!("Failed to warm connection to {}: {}", origin, e),
+            }
+        }
+        warmed
     }
 
     pub async fn forward_request(
@@ -51,19 +399,124 @@ impl ProxyForwarder {
         request_body: Bytes,
         custom_path: Option<&str>,
         client_headers: &HeaderMap,
-    ) -> Result<Bytes> {
-        // 直接做请求转换
-        let result = self.send_request(route_config, request_body.clone(), custom_path, client_headers).await;
-
-        match result {
-            Ok(response) => {
-                // 转换成功，发送请求
-                return self.process_response(response).await;
+    ) -> Result<UpstreamResponse<Bytes>> {
+        self.inject_fault(route_config, client_headers, false)
+            .await?;
+        if self.mock.handles(route_config) {
+            return self.mock.complete(route_config, &request_body).await;
+        }
+
+        // 响应被截断时按配置先向同一路由重试一次
+        let mut retried = false;
+        loop {
+            let response = self
+                .send_request(
+                    route_config,
+                    request_body.clone(),
+                    custom_path,
+                    client_headers,
+                )
+                .await?;
+            let expected = self.truncation.expect(response.headers());
+            let reason = match self.process_response(response).await {
+                Ok(upstream) => match truncation::check(&expected, &upstream.body) {
+                    Some(reason) => reason,
+                    None => {
+                        if retried {
+                            metrics::increment_counter!("gateway_truncation_retry_total", "outcome" => "recovered");
+                        }
+                        return Ok(upstream);
+                    }
+                },
+                Err(e) if truncation::is_body_error(&e) => {
+                    warn!("Failed to read upstream response body: {}", e);
+                    "body_read"
+                }
+                Err(e) => return Err(e),
+            };
+
+            warn!(
+                "Truncated response from {} ({}), retried: {}",
+                route_config.api_endpoint, reason, retried
+            );
+            metrics::increment_counter!("gateway_upstream_truncated_total", "reason" => reason);
+            if retried {
+                metrics::increment_counter!("gateway_truncation_retry_total", "outcome" => "failed");
             }
-            Err(e) => {
-                return Err(e);
+            if retried || !self.truncation.retries() {
+                return Err(truncation::truncated_error(reason));
             }
+            retried = true;
+        }
+    }
+
+    /// 原样转发请求体（不做JSON转换），用于音频等非对话类接口
+    ///
+    /// 请求体保留客户端的 `content-type`（如 multipart/form-data 的 boundary），
+    /// 响应头中额外带上上游的 `content-type`，以便二进制响应（如音频）原样返回。
+    /// 仅支持 OpenAI 兼容的上游。
+    pub async fn forward_raw(
+        &self,
+        route_config: &RouteConfig,
+        request_body: Bytes,
+        path: &str,
+        content_type: &str,
+        client_headers: &HeaderMap,
+    ) -> Result<UpstreamResponse<Bytes>> {
+        info!(
+            "forward_raw: start -> {}{}",
+            route_config.api_endpoint, path
+        );
+        if self.mock.handles(route_config) {
+            return Err(Error::Proxy(format!(
+                "Mock upstream does not support {}",
+                path
+            )));
+        }
+
+        let mut headers = self.client_headers_for(route_config, client_headers);
+        headers.insert(
+            HeaderName::from_static("content-type"),
+            HeaderValue::from_str(content_type)
+                .map_err(|_| Error::Proxy("Invalid content-type".into()))?,
+        );
+
+        // 处理 API endpoint，智能处理 /v1 前缀
+        let base_url = route_config.api_endpoint.trim_end_matches('/');
+        self.record_endpoint(base_url);
+        let api_path = match path.strip_prefix("/v1") {
+            Some(rest) if base_url.ends_with("/v1") => rest,
+            _ => path,
+        };
+        let url = format!("{}{}", base_url, api_path);
+        let url = UpstreamAuth::for_route_or(route_config, AuthMethod::Bearer)?.apply(
+            &route_config.token,
+            &mut headers,
+            url,
+        )?;
+
+        let response = self
+            .client_for(route_config)?
+            .post(&url)
+            .headers(headers)
+            .body(request_body)
+            .send()
+            .await
+            .map_err(|e| {
+                let e = e.without_url();
+                error!("HTTP client connection failed: {:?}", e);
+                Error::Http(e)
+            })?;
+
+        let upstream_content_type = response.headers().get("content-type").cloned();
+        let mut upstream = self.process_response(response).await?;
+        if let Some(value) = upstream_content_type {
+            upstream
+                .headers
+                .insert(HeaderName::from_static("content-type"), value);
         }
+
+        Ok(upstream)
     }
 
     async fn send_request(
@@ -76,27 +529,7 @@ impl ProxyForwarder {
         info!("send_request: start -> {}", route_config.api_endpoint);
 
         // 先复制客户端headers（已过滤敏感header）
-        let mut headers = client_headers.clone();
-
-        // 根据目标协议设置正确的认证header
-        match &route_config.protocol {
-            crate::models::TargetProtocol::Anthropic => {
-                // Anthropic使用 x-api-key 认证
-                headers.insert(
-                    HeaderName::from_static("x-api-key"),
-                    HeaderValue::from_str(&route_config.token)
-                        .map_err(|_| Error::Proxy("Invalid token format".into()))?,
-                );
-            }
-            crate::models::TargetProtocol::OpenAI | crate::models::TargetProtocol::Custom(_) => {
-                // OpenAI和自定义协议使用 Authorization: Bearer
-                headers.insert(
-                    HeaderName::from_static("authorization"),
-                    HeaderValue::from_str(&format!("Bearer {}", route_config.token))
-                        .map_err(|_| Error::Proxy("Invalid token format".into()))?,
-                );
-            }
-        }
+        let mut headers = self.client_headers_for(route_config, client_headers);
 
         // 确保content-type存在
         headers.insert(
@@ -106,6 +539,7 @@ impl ProxyForwarder {
 
         // 处理 API endpoint，移除尾部斜杠
         let base_url = route_config.api_endpoint.trim_end_matches('/');
+        self.record_endpoint(base_url);
 
         // 根据 custom_path 或协议选择正确的 API 路径，智能处理 /v1 前缀
         let api_path = if let Some(path) = custom_path {
@@ -147,17 +581,22 @@ impl ProxyForwarder {
             }
         };
 
-        let url = format!("{}{}", base_url, api_path);
+        let url = upstream_url(base_url, route_config, custom_path, api_path);
+
+        // 按路由的认证方案写入令牌（未配置时按协议选择）
+        let url =
+            UpstreamAuth::for_route(route_config)?.apply(&route_config.token, &mut headers, url)?;
 
         // request building logs removed to reduce noise
         let response = self
-            .client
+            .client_for(route_config)?
             .post(&url)
             .headers(headers)
             .body(request_body)
             .send()
             .await
             .map_err(|e| {
+                let e = e.without_url();
                 error!("HTTP client connection failed: {:?}", e);
                 Error::Http(e)
             })?;
@@ -169,7 +608,7 @@ impl ProxyForwarder {
     async fn send_request_stream(
         &self,
         route_config: &RouteConfig,
-        request_body: Bytes,
+        request_body: impl Into<reqwest::Body>,
         custom_path: Option<&str>,
         client_headers: &HeaderMap,
     ) -> Result<Response> {
@@ -179,27 +618,7 @@ impl ProxyForwarder {
         );
 
         // 先复制客户端headers（已过滤敏感header）
-        let mut headers = client_headers.clone();
-
-        // 根据目标协议设置正确的认证header
-        match &route_config.protocol {
-            crate::models::TargetProtocol::Anthropic => {
-                // Anthropic使用 x-api-key 认证
-                headers.insert(
-                    HeaderName::from_static("x-api-key"),
-                    HeaderValue::from_str(&route_config.token)
-                        .map_err(|_| Error::Proxy("Invalid token format".into()))?,
-                );
-            }
-            crate::models::TargetProtocol::OpenAI | crate::models::TargetProtocol::Custom(_) => {
-                // OpenAI和自定义协议使用 Authorization: Bearer
-                headers.insert(
-                    HeaderName::from_static("authorization"),
-                    HeaderValue::from_str(&format!("Bearer {}", route_config.token))
-                        .map_err(|_| Error::Proxy("Invalid token format".into()))?,
-                );
-            }
-        }
+        let mut headers = self.client_headers_for(route_config, client_headers);
 
         // 确保content-type存在
         headers.insert(
@@ -208,6 +627,7 @@ impl ProxyForwarder {
         );
 
         let base_url = route_config.api_endpoint.trim_end_matches('/');
+        self.record_endpoint(base_url);
 
         // 根据 custom_path 或协议选择正确的 API 路径，智能处理 /v1 前缀
         let api_path = if let Some(path) = custom_path {
@@ -237,17 +657,22 @@ impl ProxyForwarder {
             }
         };
 
-        let url = format!("{}{}", base_url, api_path);
+        let url = upstream_url(base_url, route_config, custom_path, api_path);
+
+        // 按路由的认证方案写入令牌（未配置时按协议选择）
+        let url =
+            UpstreamAuth::for_route(route_config)?.apply(&route_config.token, &mut headers, url)?;
 
         // request building logs removed to reduce noise
         let response = self
-            .streaming_client
+            .streaming_client_for(route_config)?
             .post(&url)
             .headers(headers)
             .body(request_body)
             .send()
             .await
             .map_err(|e| {
+                let e = e.without_url();
                 error!("HTTP client connection failed (stream): {:?}", e);
                 Error::Http(e)
             })?;
@@ -256,11 +681,11 @@ impl ProxyForwarder {
     }
 
     // 处理非流式响应
-    async fn process_response(&self, response: Response) -> Result<Bytes> {
+    async fn process_response(&self, response: Response) -> Result<UpstreamResponse<Bytes>> {
         let status = response.status();
+        let request_id = upstream_request_id(response.headers());
         if !status.is_success() {
-            let body = response
-                .bytes()
+            let body = size_limit::read_response(response, self.max_response_bytes)
                 .await
                 .unwrap_or_else(|_| Bytes::from("Failed to read error response"));
 
@@ -270,15 +695,12 @@ impl ProxyForwarder {
                 String::from_utf8_lossy(&body)
             );
 
-            return Err(Error::Proxy(format!(
-                "Upstream returned error status {}: {}",
-                status,
-                String::from_utf8_lossy(&body)
-            )));
+            return Err(upstream_status_error(status, request_id.as_deref(), &body));
         }
 
         info!("Upstream success response status: {}", status);
-        let body = response.bytes().await.map_err(|e| Error::Http(e))?;
+        let headers = self.select_passthrough_headers(response.headers());
+        let body = size_limit::read_response(response, self.max_response_bytes).await?;
 
         // 记录响应体大小和内容预览，帮助调试
         let body_size = body.len();
@@ -290,24 +712,61 @@ impl ProxyForwarder {
         };
         info!("Upstream response body size: {} bytes, preview: {}", body_size, preview);
 
-        Ok(body)
+        // 以 200 返回的错误响应体按推断的状态码作为上游错误，触发故障转移
+        if let Some(inferred) = validation::error_body_status(&body) {
+            error!(
+                "Upstream returned an error body with status {}, treating as {}",
+                status, inferred
+            );
+            metrics::increment_counter!("gateway_upstream_error_body_total");
+            return Err(upstream_status_error(
+                inferred,
+                request_id.as_deref(),
+                &body,
+            ));
+        }
+
+        Ok(UpstreamResponse {
+            headers,
+            body,
+            request_id,
+        })
     }
 
     pub fn is_client_error(&self, error: &Error) -> bool {
         match error {
-            Error::Proxy(msg) => {
-                // 4xx错误，客户端错误，不应重试
-                msg.contains("400")
-                    || msg.contains("401")
-                    || msg.contains("403")
-                    || msg.contains("404")
-                    || msg.contains("422")
-                    || msg.contains("429")
-            }
+            // 4xx错误，客户端错误，不应重试；上游错误按状态码判断，避免误匹配请求ID和响应体中的数字
+            Error::Proxy(msg) => match upstream_status(msg) {
+                Some(status) => matches!(status, 400 | 401 | 403 | 404 | 422 | 429),
+                None => {
+                    msg.contains("400")
+                        || msg.contains("401")
+                        || msg.contains("403")
+                        || msg.contains("404")
+                        || msg.contains("422")
+                        || msg.contains("429")
+                }
+            },
             _ => false,
         }
     }
 
+    /// 判断上游失败的类别，决定同一请求内是否值得稍后重试该路由
+    ///
+    /// - 网络错误（连接失败、超时、连接中断）和 502/503/504/529 视为瞬时故障
+    /// - 其余 5xx 及请求构造错误对同一请求体是确定性的，重试也会得到相同结果
+    pub fn classify_failure(&self, error: &Error) -> FailureClass {
+        match error {
+            Error::Http(_) => FailureClass::Transient,
+            e if truncation::is_truncated(e) => FailureClass::Transient,
+            Error::Proxy(msg) => match upstream_status(msg) {
+                Some(408 | 502 | 503 | 504 | 529) => FailureClass::Transient,
+                _ => FailureClass::Deterministic,
+            },
+            _ => FailureClass::Deterministic,
+        }
+    }
+
     /// 新的纯粹流式接口，返回字节流而不包含 Axum 依赖
     /// 这是架构重构第一步的核心接口
     pub async fn stream(
@@ -316,13 +775,38 @@ impl ProxyForwarder {
         request_body: Bytes,
         custom_path: Option<&str>,
         client_headers: &HeaderMap,
-    ) -> Result<impl futures::Stream<Item = Result<Bytes>>> {
+    ) -> Result<UpstreamResponse<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>>> {
         info!("stream: start");
+        if let Some(limit) = self
+            .inject_fault(route_config, client_headers, true)
+            .await?
+        {
+            let mut response = self
+                .stream_upstream(route_config, request_body, custom_path, client_headers)
+                .await?;
+            response.body = fault::truncate_stream(response.body, limit);
+            return Ok(response);
+        }
+        self.stream_upstream(route_config, request_body, custom_path, client_headers)
+            .await
+    }
+
+    async fn stream_upstream(
+        &self,
+        route_config: &RouteConfig,
+        request_body: Bytes,
+        custom_path: Option<&str>,
+        client_headers: &HeaderMap,
+    ) -> Result<UpstreamResponse<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>>> {
+        if self.mock.handles(route_config) {
+            return self.mock.stream(route_config, &request_body).await;
+        }
         // Use streaming client without global timeout
         let response = self.send_request_stream(route_config, request_body, custom_path, client_headers).await?;
         // request sent successfully
 
         let status = response.status();
+        let request_id = upstream_request_id(response.headers());
         if !status.is_success() {
             let body = response
                 .bytes()
@@ -335,23 +819,263 @@ impl ProxyForwarder {
                 String::from_utf8_lossy(&body)
             );
 
-            return Err(Error::Proxy(format!(
-                "Upstream returned error status {}: {}",
-                status,
-                String::from_utf8_lossy(&body)
-            )));
+            return Err(upstream_status_error(status, request_id.as_deref(), &body));
         }
 
         // 返回纯粹的字节流，不包含任何框架依赖
         info!("stream: established (status {})", status);
-        let stream = response.bytes_stream().map(move |chunk| {
-            match chunk {
-                Ok(bytes) => Ok(Bytes::from(bytes)),
-                Err(e) => Err(Error::Http(e))
-            }
+        let headers = self.select_passthrough_headers(response.headers());
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let stream = response.bytes_stream().map(move |chunk| match chunk {
+            Ok(bytes) => Ok(bytes),
+            Err(e) => Err(upstream_error(e)),
         });
+        // 200 但返回 HTML 错误页或非流式 JSON 时按路由失败处理，尚未向客户端输出任何内容
+        let stream = validation::validate_stream(content_type.as_deref(), stream).await?;
         info!("stream: ready to yield");
-        Ok(stream)
+        Ok(UpstreamResponse {
+            headers,
+            // 有界缓冲，客户端消费过慢时对上游施加背压
+            body: bounded_stream(
+                stream,
+                self.stream_buffer_capacity,
+                slow_client_timeout_for(self.slow_client_timeout, route_config),
+            ),
+            request_id,
+        })
+    }
+
+    /// 转发 Anthropic Message Batches 接口请求（创建、查询、取消、删除、结果下载）
+    ///
+    /// `path` 为客户端请求路径（如 `/v1/messages/batches/{id}/results`），请求体原样转发。
+    /// 使用无整体超时的客户端，结果文件可能很大；响应体以字节流返回，
+    /// 响应头中额外带上上游的 `content-type`。
+    pub async fn forward_batch(
+        &self,
+        route_config: &RouteConfig,
+        method: reqwest::Method,
+        path: &str,
+        request_body: Option<Bytes>,
+        client_headers: &HeaderMap,
+    ) -> Result<UpstreamResponse<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>>> {
+        info!(
+            "forward_batch: {} {}{}",
+            method, route_config.api_endpoint, path
+        );
+        if self.mock.handles(route_config) {
+            return Err(Error::Proxy(format!(
+                "Mock upstream does not support {}",
+                path
+            )));
+        }
+
+        let mut headers = self.client_headers_for(route_config, client_headers);
+
+        let base_url = route_config.api_endpoint.trim_end_matches('/');
+        self.record_endpoint(base_url);
+        let api_path = match path.strip_prefix("/v1") {
+            Some(rest) if base_url.ends_with("/v1") => rest,
+            _ => path,
+        };
+        let url = format!("{}{}", base_url, api_path);
+        let url = UpstreamAuth::for_route_or(route_config, AuthMethod::XApiKey)?.apply(
+            &route_config.token,
+            &mut headers,
+            url,
+        )?;
+
+        let mut request = self
+            .streaming_client_for(route_config)?
+            .request(method, &url);
+        if let Some(body) = request_body {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            request = request.body(body);
+        }
+        let response = request.headers(headers).send().await.map_err(|e| {
+            let e = e.without_url();
+            error!("HTTP client connection failed (batch): {:?}", e);
+            Error::Http(e)
+        })?;
+
+        self.object_response(response, "batch").await
+    }
+
+    /// 转发文件接口请求（OpenAI Files、Anthropic Files beta 的上传、列表、查询、下载、删除）
+    ///
+    /// `path` 为客户端请求路径（可带查询参数）。上传的请求体（通常为 multipart/form-data）
+    /// 边读边发，`content_type` 和 `content_length` 取自客户端请求；请求体只能读取一次，
+    /// 失败后调用方无法重试。认证方案按路由协议选择，响应体以字节流返回，
+    /// 响应头中额外带上上游的 `content-type`。
+    pub async fn forward_file(
+        &self,
+        route_config: &RouteConfig,
+        method: reqwest::Method,
+        path: &str,
+        upload: Option<FileUpload>,
+        client_headers: &HeaderMap,
+    ) -> Result<UpstreamResponse<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>>> {
+        info!(
+            "forward_file: {} {}{}",
+            method, route_config.api_endpoint, path
+        );
+        if self.mock.handles(route_config) {
+            return Err(Error::Proxy(format!(
+                "Mock upstream does not support {}",
+                path
+            )));
+        }
+
+        let mut headers = self.client_headers_for(route_config, client_headers);
+
+        let base_url = route_config.api_endpoint.trim_end_matches('/');
+        self.record_endpoint(base_url);
+        let api_path = match path.strip_prefix("/v1") {
+            Some(rest) if base_url.ends_with("/v1") => rest,
+            _ => path,
+        };
+        let url = format!("{}{}", base_url, api_path);
+        let url =
+            UpstreamAuth::for_route(route_config)?.apply(&route_config.token, &mut headers, url)?;
+
+        let mut request = self
+            .streaming_client_for(route_config)?
+            .request(method, &url);
+        if let Some(upload) = upload {
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_str(&upload.content_type)
+                    .map_err(|_| Error::Proxy("Invalid content-type".into()))?,
+            );
+            if let Some(content_length) = upload.content_length {
+                headers.insert(CONTENT_LENGTH, HeaderValue::from(content_length));
+            }
+            request = request.body(upload.body);
+        }
+        let response = request.headers(headers).send().await.map_err(|e| {
+            let e = e.without_url();
+            error!("HTTP client connection failed (file): {:?}", e);
+            Error::Http(e)
+        })?;
+
+        self.object_response(response, "file").await
+    }
+
+    // 批处理、文件接口的上游响应：错误状态码转为上游错误，成功时以字节流返回响应体
+    async fn object_response(
+        &self,
+        response: Response,
+        kind: &str,
+    ) -> Result<UpstreamResponse<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>>> {
+        let status = response.status();
+        let request_id = upstream_request_id(response.headers());
+        if !status.is_success() {
+            let body = response
+                .bytes()
+                .await
+                .unwrap_or_else(|_| Bytes::from("Failed to read error response"));
+
+            error!(
+                "Upstream {} error response (status {}): {}",
+                kind,
+                status,
+                String::from_utf8_lossy(&body)
+            );
+
+            return Err(upstream_status_error(status, request_id.as_deref(), &body));
+        }
+
+        let mut headers = self.select_passthrough_headers(response.headers());
+        if let Some(value) = response.headers().get(CONTENT_TYPE) {
+            headers.insert(CONTENT_TYPE, value.clone());
+        }
+        let stream = response
+            .bytes_stream()
+            .map(|chunk| chunk.map_err(upstream_error));
+
+        Ok(UpstreamResponse {
+            headers,
+            body: bounded_stream(
+                stream,
+                self.stream_buffer_capacity,
+                self.slow_client_timeout,
+            ),
+            request_id,
+        })
+    }
+
+    /// 路由是否由内置模拟上游响应
+    pub fn is_mocked(&self, route_config: &RouteConfig) -> bool {
+        self.mock.handles(route_config)
+    }
+
+    /// 路由尝试是否需要改写请求体或注入故障，此时不能不经缓冲直接转发请求体
+    pub fn alters_request(&self, route_config: &RouteConfig) -> bool {
+        self.output_cap(route_config).is_some()
+            || self.compressor.is_enabled_for(route_config)
+            || self.faults.is_enabled()
+    }
+
+    /// 不经缓冲直接转发请求体，用于无需协议转换的大请求体
+    ///
+    /// `content_length` 写入上游请求头，上游收到的是定长请求而非分块编码；请求体边读边发，
+    /// 上游读取变慢时自然对客户端上传施加背压。请求体只能读取一次，失败后调用方无法重试。
+    /// 响应头中额外带上上游的 `content-type`，由调用方决定按 SSE 还是完整响应返回。
+    pub async fn forward_streaming_body(
+        &self,
+        route_config: &RouteConfig,
+        request_body: reqwest::Body,
+        content_length: u64,
+        client_headers: &HeaderMap,
+    ) -> Result<UpstreamResponse<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>>> {
+        info!(
+            "forward_streaming_body: start -> {} ({} bytes)",
+            route_config.api_endpoint, content_length
+        );
+
+        let mut headers = client_headers.clone();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(content_length));
+        let response = self
+            .send_request_stream(route_config, request_body, None, &headers)
+            .await?;
+
+        let status = response.status();
+        let request_id = upstream_request_id(response.headers());
+        if !status.is_success() {
+            let body = response
+                .bytes()
+                .await
+                .unwrap_or_else(|_| Bytes::from("Failed to read error response"));
+
+            error!(
+                "Upstream error response (status {}): {}",
+                status,
+                String::from_utf8_lossy(&body)
+            );
+
+            return Err(upstream_status_error(status, request_id.as_deref(), &body));
+        }
+
+        let mut headers = self.select_passthrough_headers(response.headers());
+        if let Some(value) = response.headers().get(CONTENT_TYPE) {
+            headers.insert(CONTENT_TYPE, value.clone());
+        }
+        let stream = response
+            .bytes_stream()
+            .map(|chunk| chunk.map_err(upstream_error));
+
+        Ok(UpstreamResponse {
+            headers,
+            body: bounded_stream(
+                stream,
+                self.stream_buffer_capacity,
+                slow_client_timeout_for(self.slow_client_timeout, route_config),
+            ),
+            request_id,
+        })
     }
 
     #[deprecated(note = "Use `stream` method instead. This will be removed in future versions.")]
@@ -362,6 +1086,106 @@ impl ProxyForwarder {
     ) -> Result<impl futures::Stream<Item = Result<Bytes>>> {
         // 兼容性：调用新的 stream 接口，不使用 custom_path，使用空的client_headers
         let empty_headers = HeaderMap::new();
-        self.stream(route_config, request_body, None, &empty_headers).await
+        self.stream(route_config, request_body, None, &empty_headers)
+            .await
+            .map(|response| response.body)
+    }
+}
+
+/// 提取上游地址的 origin（scheme://host:port），无法解析时返回 None
+fn endpoint_origin(endpoint: &str) -> Option<String> {
+    let url = reqwest::Url::parse(endpoint).ok()?;
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{}://{}:{}", url.scheme(), host, port),
+        None => format!("{}://{}", url.scheme(), host),
+    })
+}
+
+/// 拼接上游请求地址
+///
+/// 路由配置了 `path_template` 且不是自定义路径（如 /v1/responses）时使用模板并替换 `{model}`，
+/// 否则使用按协议和 /v1 后缀推断出的路径
+fn upstream_url(
+    base_url: &str,
+    route_config: &RouteConfig,
+    custom_path: Option<&str>,
+    inferred_path: &str,
+) -> String {
+    match (&route_config.path_template, custom_path) {
+        (Some(template), None) => {
+            let path = template.replace("{model}", &model_rewrite::upstream_model(route_config));
+            if path.starts_with('/') {
+                format!("{}{}", base_url, path)
+            } else {
+                format!("{}/{}", base_url, path)
+            }
+        }
+        _ => format!("{}{}", base_url, inferred_path),
     }
 }
+
+/// 上游请求错误，去掉其中的URL（认证方案为 `query:<name>` 时URL中带有令牌）
+fn upstream_error(e: reqwest::Error) -> Error {
+    Error::Http(e.without_url())
+}
+
+/// 上游返回错误状态码
+///
+/// 格式为 `Upstream returned error status <status>[ (request id <id>)]: <body>`，
+/// 状态码和请求ID分别由 [`upstream_status`] 和 [`upstream_request_id_of`] 解析
+fn upstream_status_error(status: StatusCode, request_id: Option<&str>, body: &[u8]) -> Error {
+    let request_id = request_id
+        .map(|id| format!(" (request id {})", id))
+        .unwrap_or_default();
+    Error::Proxy(format!(
+        "Upstream returned error status {}{}: {}",
+        status,
+        request_id,
+        String::from_utf8_lossy(body)
+    ))
+}
+
+/// 上游响应头中的请求ID，只保留字母、数字和 `-_.`，过长时截断
+fn upstream_request_id(headers: &HeaderMap) -> Option<String> {
+    UPSTREAM_REQUEST_ID_HEADERS.iter().find_map(|name| {
+        let id: String = headers
+            .get(*name)?
+            .to_str()
+            .ok()?
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            .take(MAX_UPSTREAM_REQUEST_ID_LEN)
+            .collect();
+        (!id.is_empty()).then_some(id)
+    })
+}
+
+/// 从上游错误中解析上游请求ID
+pub fn upstream_request_id_of(error: &Error) -> Option<String> {
+    let Error::Proxy(msg) = error else {
+        return None;
+    };
+    let head = msg.strip_prefix("Upstream returned error status ")?;
+    let head = &head[..head.find(": ")?];
+    let id = head.split_once(" (request id ")?.1.strip_suffix(')')?;
+    Some(id.to_string())
+}
+
+/// 从上游错误信息中解析HTTP状态码
+pub fn upstream_status(msg: &str) -> Option<u16> {
+    msg.strip_prefix("Upstream returned error status ")?
+        .get(..3)?
+        .parse()
+        .ok()
+}
+
+/// 请求头名是否匹配任一模式（小写，以 `*` 结尾表示前缀匹配）
+fn header_matches(patterns: &[String], name: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern,
+        })
+}
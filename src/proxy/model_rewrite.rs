@@ -0,0 +1,59 @@
+//! 上游模型名改写
+//!
+//! 业务API保存规范的模型ID，部分上游要求不同的写法：聚合平台需要供应商前缀
+//! （如 `anthropic/claude-3-5-sonnet`），有的上游不接受日期后缀。
+//! 路由配置 `model_rewrite` 时，发往上游的模型名（请求体、路径模板）使用改写结果，
+//! 上报给业务API的仍是规范的模型ID。正则编译后缓存，无效的正则不改写并只记录一次错误。
+
+use crate::models::{ModelRewrite, RouteConfig};
+use dashmap::DashMap;
+use regex::Regex;
+use std::borrow::Cow;
+use std::sync::OnceLock;
+use tracing::error;
+
+/// 已编译的正则（编译失败为 None）
+fn patterns() -> &'static DashMap<String, Option<Regex>> {
+    static PATTERNS: OnceLock<DashMap<String, Option<Regex>>> = OnceLock::new();
+    PATTERNS.get_or_init(DashMap::new)
+}
+
+/// 发往路由上游的模型名
+pub fn upstream_model(route_config: &RouteConfig) -> Cow<'_, str> {
+    match &route_config.model_rewrite {
+        Some(rule) => rewrite(&route_config.model, rule),
+        None => Cow::Borrowed(&route_config.model),
+    }
+}
+
+/// 按规则改写模型名
+pub fn rewrite<'a>(model: &'a str, rule: &ModelRewrite) -> Cow<'a, str> {
+    let mut rewritten = Cow::Borrowed(model);
+    if let Some(pattern) = &rule.pattern {
+        let compiled = patterns()
+            .entry(pattern.clone())
+            .or_insert_with(|| {
+                Regex::new(pattern)
+                    .inspect_err(|e| error!("Invalid model rewrite pattern {:?}: {}", pattern, e))
+                    .ok()
+            })
+            .clone();
+        if let Some(regex) = compiled {
+            if let Cow::Owned(replaced) = regex.replace(model, rule.replacement.as_str()) {
+                rewritten = Cow::Owned(replaced);
+            }
+        }
+    }
+    match &rule.template {
+        Some(template) => Cow::Owned(template.replace("{model}", &rewritten)),
+        None => rewritten,
+    }
+}
+
+/// 检查规则中的正则是否有效
+pub fn validate(rule: &ModelRewrite) -> Result<(), String> {
+    match &rule.pattern {
+        Some(pattern) => Regex::new(pattern).map(|_| ()).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,126 @@
+//! 上游 mTLS 客户端身份
+//!
+//! 每个身份启动时加载客户端证书、私钥和可选的 CA 证书包，创建专用的普通和流式客户端，
+//! 连接池按身份隔离。路由通过 `client_identity` 引用身份，未引用时按上游地址的最长前缀匹配身份的 `endpoints`。
+
+use crate::config::ClientIdentityConfig;
+use crate::error::{Error, Result};
+use crate::models::RouteConfig;
+use reqwest::{Certificate, Client, ClientBuilder, Identity};
+use std::collections::HashMap;
+use tracing::info;
+
+/// 单个身份的客户端
+pub struct IdentityClients {
+    pub client: Client,
+    pub streaming_client: Client,
+}
+
+/// 已加载的 mTLS 客户端身份
+#[derive(Default)]
+pub struct ClientIdentities {
+    clients: HashMap<String, IdentityClients>,
+    // (上游地址前缀, 身份名)，按前缀长度降序
+    endpoints: Vec<(String, String)>,
+}
+
+impl ClientIdentities {
+    /// 加载各身份的证书并创建客户端，`builder` 按是否流式返回带有公共设置（超时、连接池、解析器）的构建器
+    pub fn from_config(
+        identities: &HashMap<String, ClientIdentityConfig>,
+        builder: impl Fn(bool) -> ClientBuilder,
+    ) -> Result<Self> {
+        let mut clients = HashMap::new();
+        let mut endpoints = Vec::new();
+        for (name, config) in identities {
+            let (identity, roots) = load(name, config)?;
+            let build = |streaming: bool| {
+                roots
+                    .iter()
+                    .fold(builder(streaming), |builder, root| {
+                        builder.add_root_certificate(root.clone())
+                    })
+                    .identity(identity.clone())
+                    .build()
+                    .map_err(Error::Http)
+            };
+            clients.insert(
+                name.clone(),
+                IdentityClients {
+                    client: build(false)?,
+                    streaming_client: build(true)?,
+                },
+            );
+            endpoints.extend(
+                config
+                    .endpoints
+                    .iter()
+                    .map(|endpoint| (endpoint.trim_end_matches('/').to_string(), name.clone())),
+            );
+            info!(
+                "Loaded client identity {} ({} endpoint prefix(es))",
+                name,
+                config.endpoints.len()
+            );
+        }
+        endpoints.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        Ok(Self { clients, endpoints })
+    }
+
+    /// 路由使用的身份客户端，路由引用了未配置的身份时返回错误
+    pub fn for_route(&self, route_config: &RouteConfig) -> Result<Option<&IdentityClients>> {
+        match &route_config.client_identity {
+            Some(name) => self
+                .clients
+                .get(name)
+                .map(Some)
+                .ok_or_else(|| Error::Config(format!("Unknown client identity {:?}", name))),
+            None => Ok(self.for_endpoint(&route_config.api_endpoint)),
+        }
+    }
+
+    /// 按上游地址前缀匹配的身份客户端
+    pub fn for_endpoint(&self, endpoint: &str) -> Option<&IdentityClients> {
+        let (_, name) = self
+            .endpoints
+            .iter()
+            .find(|(prefix, _)| matches_prefix(endpoint, prefix))?;
+        self.clients.get(name)
+    }
+}
+
+// 前缀须在路径或端口边界处结束，避免 https://a.example.com 匹配 https://a.example.com.evil
+fn matches_prefix(endpoint: &str, prefix: &str) -> bool {
+    endpoint
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', ':', '?']))
+}
+
+// 读取身份的证书、私钥和 CA 证书包
+fn load(name: &str, config: &ClientIdentityConfig) -> Result<(Identity, Vec<Certificate>)> {
+    let read = |path: &str| {
+        std::fs::read(path).map_err(|e| {
+            Error::Config(format!(
+                "Failed to read {:?} for client identity {}: {}",
+                path, name, e
+            ))
+        })
+    };
+    let identity = Identity::from_pkcs8_pem(&read(&config.cert_file)?, &read(&config.key_file)?)
+        .map_err(|e| {
+            Error::Config(format!(
+                "Invalid certificate or key for client identity {}: {}",
+                name, e
+            ))
+        })?;
+    let roots = match &config.ca_file {
+        Some(ca_file) => Certificate::from_pem_bundle(&read(ca_file)?).map_err(|e| {
+            Error::Config(format!(
+                "Invalid CA bundle for client identity {}: {}",
+                name, e
+            ))
+        })?,
+        None => Vec::new(),
+    };
+    Ok((identity, roots))
+}
@@ -0,0 +1,222 @@
+//! 输出Token上限
+//!
+//! 转发前将请求的最大输出Token数限制在上限以内（未指定时注入上限），
+//! 并统计流式响应中已输出的Token数（按输出文本分词估算，见 [`crate::usage::estimate`]），
+//! 上游未遵守限制、超出上限时补发结束事件并终止流。
+
+use crate::error::Result;
+use crate::models::TargetProtocol;
+use crate::usage::estimate::count_tokens;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde_json::{json, Value};
+use std::pin::Pin;
+use tracing::warn;
+
+/// 请求中表示最大输出Token数的字段：Chat/Anthropic、OpenAI 推理模型、Responses API
+const MAX_TOKENS_FIELDS: [&str; 3] = ["max_tokens", "max_completion_tokens", "max_output_tokens"];
+
+/// 将请求（客户端协议格式）的最大输出Token数限制在 `cap` 以内，未指定时注入 `cap`
+///
+/// 请求体不是 JSON 对象时原样返回
+pub fn clamp_max_tokens(body: Bytes, cap: u32) -> Bytes {
+    let Ok(Value::Object(mut obj)) = serde_json::from_slice::<Value>(&body) else {
+        return body;
+    };
+
+    let mut present = false;
+    for field in MAX_TOKENS_FIELDS {
+        if let Some(value) = obj.get_mut(field) {
+            present = true;
+            if value.as_u64().is_none_or(|n| n > u64::from(cap)) {
+                *value = json!(cap);
+            }
+        }
+    }
+    if !present {
+        // Responses API 请求以 input 代替 messages
+        let field = if obj.contains_key("input") && !obj.contains_key("messages") {
+            "max_output_tokens"
+        } else {
+            "max_tokens"
+        };
+        obj.insert(field.to_string(), json!(cap));
+    }
+
+    serde_json::to_vec(&obj).map(Bytes::from).unwrap_or(body)
+}
+
+/// 包装上游流式响应（目标协议的标准 SSE），输出Token数超过 `cap` 时补发结束事件并终止流
+///
+/// 结束事件不带用量，只有 Anthropic 的 `message_delta` 按协议要求带有估算的输出Token数。
+/// 应在用量收集之后包装，使计费用量来自用量收集器（上游未返回的部分标记为估算值）。
+pub fn cap_output_stream<S>(
+    stream: S,
+    protocol: TargetProtocol,
+    cap: u32,
+) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    Box::pin(async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        let mut counter = OutputCounter::new(protocol, cap);
+        let mut buffer: Vec<u8> = Vec::new();
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            buffer.extend_from_slice(&chunk);
+
+            // 逐个处理完整的事件（以空行分隔），不完整的事件留待下一个chunk
+            let mut out = Vec::new();
+            while let Some(pos) = buffer.windows(2).position(|w| w == b"\n\n") {
+                let event: Vec<u8> = buffer.drain(..pos + 2).collect();
+                counter.observe(&event);
+                out.extend_from_slice(&event);
+
+                if counter.exceeded() {
+                    warn!(
+                        "Stream exceeded output token cap ({}), terminating",
+                        cap
+                    );
+                    metrics::increment_counter!("gateway_output_cap_exceeded_total");
+                    out.extend_from_slice(counter.terminal_events().as_bytes());
+                    yield Ok(Bytes::from(out));
+                    return;
+                }
+            }
+            if !out.is_empty() {
+                yield Ok(Bytes::from(out));
+            }
+        }
+
+        if !buffer.is_empty() {
+            yield Ok(Bytes::from(buffer));
+        }
+    })
+}
+
+/// 流式输出Token计数
+struct OutputCounter {
+    protocol: TargetProtocol,
+    cap: u32,
+    tokens: u32,
+    // 最近一个 OpenAI chunk，用于构造结束事件（保留 id、model 等字段）
+    last_chunk: Option<Value>,
+    // Anthropic 当前打开的内容块
+    open_block: Option<u64>,
+}
+
+impl OutputCounter {
+    fn new(protocol: TargetProtocol, cap: u32) -> Self {
+        Self {
+            protocol,
+            cap,
+            tokens: 0,
+            last_chunk: None,
+            open_block: None,
+        }
+    }
+
+    fn tokens(&self) -> u32 {
+        self.tokens
+    }
+
+    fn exceeded(&self) -> bool {
+        self.tokens() > self.cap
+    }
+
+    fn observe(&mut self, event: &[u8]) {
+        let Ok(event) = std::str::from_utf8(event) else {
+            return;
+        };
+        for data in event.lines().filter_map(|line| line.strip_prefix("data:")) {
+            let Ok(json) = serde_json::from_str::<Value>(data.trim()) else {
+                continue;
+            };
+            match self.protocol {
+                TargetProtocol::Anthropic => self.observe_anthropic(&json),
+                TargetProtocol::OpenAI | TargetProtocol::Custom(_) => self.observe_openai(json),
+            }
+        }
+    }
+
+    fn observe_openai(&mut self, json: Value) {
+        for choice in json["choices"].as_array().into_iter().flatten() {
+            let delta = &choice["delta"];
+            for field in ["content", "reasoning_content"] {
+                self.tokens += delta[field].as_str().map_or(0, count_tokens);
+            }
+            for call in delta["tool_calls"].as_array().into_iter().flatten() {
+                self.tokens += call["function"]["arguments"]
+                    .as_str()
+                    .map_or(0, count_tokens);
+            }
+        }
+        self.last_chunk = Some(json);
+    }
+
+    fn observe_anthropic(&mut self, json: &Value) {
+        match json["type"].as_str() {
+            Some("content_block_start") => self.open_block = json["index"].as_u64(),
+            Some("content_block_stop") => self.open_block = None,
+            Some("content_block_delta") => {
+                let delta = &json["delta"];
+                for field in ["text", "partial_json", "thinking"] {
+                    self.tokens += delta[field].as_str().map_or(0, count_tokens);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 目标协议的结束事件，停止原因为达到最大Token数
+    fn terminal_events(&self) -> String {
+        let output_tokens = self.tokens();
+        match self.protocol {
+            TargetProtocol::Anthropic => {
+                let mut events = String::new();
+                if let Some(index) = self.open_block {
+                    events.push_str(&sse_event(
+                        Some("content_block_stop"),
+                        &json!({"type": "content_block_stop", "index": index}),
+                    ));
+                }
+                events.push_str(&sse_event(
+                    Some("message_delta"),
+                    &json!({
+                        "type": "message_delta",
+                        "delta": {"stop_reason": "max_tokens", "stop_sequence": null},
+                        "usage": {"output_tokens": output_tokens},
+                    }),
+                ));
+                events.push_str(&sse_event(
+                    Some("message_stop"),
+                    &json!({"type": "message_stop"}),
+                ));
+                events
+            }
+            TargetProtocol::OpenAI | TargetProtocol::Custom(_) => {
+                let mut chunk = self.last_chunk.clone().unwrap_or_else(|| json!({}));
+                chunk["choices"] = json!([{"index": 0, "delta": {}, "finish_reason": "length"}]);
+                if let Some(obj) = chunk.as_object_mut() {
+                    obj.remove("usage");
+                }
+                format!("{}data: [DONE]\n\n", sse_event(None, &chunk))
+            }
+        }
+    }
+}
+
+fn sse_event(event: Option<&str>, data: &Value) -> String {
+    match event {
+        Some(event) => format!("event: {}\ndata: {}\n\n", event, data),
+        None => format!("data: {}\n\n", data),
+    }
+}
@@ -0,0 +1,47 @@
+use crate::config::RouteRacingConfig;
+use reqwest::header::{HeaderMap, HeaderName};
+
+/// 非流式请求的路由竞速范围
+pub struct RouteRacing {
+    enabled: bool,
+    fanout: usize,
+    header: Option<HeaderName>,
+}
+
+impl RouteRacing {
+    pub fn from_config(config: &RouteRacingConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            fanout: config.fanout,
+            header: config
+                .header
+                .as_deref()
+                .and_then(|h| HeaderName::from_bytes(h.as_bytes()).ok()),
+        }
+    }
+
+    /// 限定竞速范围的请求头，转发上游前需剥离
+    pub fn scope_header(&self) -> Option<&HeaderName> {
+        self.header.as_ref().filter(|_| self.enabled)
+    }
+
+    /// 请求同时尝试的路由数，不竞速时返回 None
+    ///
+    /// 请求头的值为数字时使用该并发数，不超过配置的 `fanout`
+    pub fn width(&self, client_headers: &HeaderMap) -> Option<usize> {
+        if !self.enabled {
+            return None;
+        }
+        let width = match &self.header {
+            Some(header) => {
+                let value = client_headers.get(header)?.to_str().unwrap_or_default();
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .map_or(self.fanout, |n| n.min(self.fanout))
+            }
+            None => self.fanout,
+        };
+        (width >= 2).then_some(width)
+    }
+}
@@ -0,0 +1,108 @@
+//! 上游非流式响应体大小限制
+//!
+//! 非流式响应需要完整读入内存后再做协议转换，异常或滥用的生成可能返回极大的响应体。
+//! 配置 `proxy.max_response_bytes` 后，`Content-Length` 超过上限的响应不读取响应体，
+//! 分块读取时累计超过上限立即中止。响应大小由生成内容决定，换路由通常得到同样大的响应，
+//! 因此超限不转向其他路由，请求以 502 和错误码 `upstream_response_too_large` 结束。
+
+use crate::error::{Error, Result};
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use reqwest::Response;
+use tracing::warn;
+
+/// 响应体超过上限的错误消息前缀
+pub const RESPONSE_TOO_LARGE: &str = "Upstream response too large";
+
+/// 返回给客户端的错误码
+pub const RESPONSE_TOO_LARGE_CODE: &str = "upstream_response_too_large";
+
+/// 响应体超过上限的错误，消息中不带数字以免被误判为客户端错误状态码
+pub fn too_large_error() -> Error {
+    Error::Proxy(format!(
+        "{}: exceeds the gateway response size limit",
+        RESPONSE_TOO_LARGE
+    ))
+}
+
+/// 是否为响应体超过上限的错误
+pub fn is_too_large(error: &Error) -> bool {
+    matches!(error, Error::Proxy(msg) if msg.starts_with(RESPONSE_TOO_LARGE))
+}
+
+/// 读取完整的响应体，超过上限时返回错误
+pub async fn read_response(mut response: Response, limit: Option<u64>) -> Result<Bytes> {
+    let Some(limit) = limit else {
+        return response
+            .bytes()
+            .await
+            .map_err(|e| Error::Http(e.without_url()));
+    };
+    if let Some(length) = response.content_length().filter(|length| *length > limit) {
+        return Err(exceeded(limit, length));
+    }
+
+    let mut body = BytesMut::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| Error::Http(e.without_url()))?
+    {
+        body.extend_from_slice(&chunk);
+        if body.len() as u64 > limit {
+            return Err(exceeded(limit, body.len() as u64));
+        }
+    }
+    Ok(body.freeze())
+}
+
+/// 累计字节数超过上限时以错误结束的字节流，用于读取整个响应体之前
+pub fn limit_body<S>(stream: S, limit: Option<u64>) -> impl Stream<Item = Result<Bytes>> + Send
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        let mut total = 0u64;
+        while let Some(chunk) = stream.next().await {
+            if let (Ok(bytes), Some(limit)) = (&chunk, limit) {
+                total += bytes.len() as u64;
+                if total > limit {
+                    yield Err(exceeded(limit, total));
+                    return;
+                }
+            }
+            yield chunk;
+        }
+    }
+}
+
+fn exceeded(limit: u64, size: u64) -> Error {
+    warn!(
+        "Upstream response exceeds {} bytes (at least {}), aborting",
+        limit, size
+    );
+    metrics::increment_counter!("gateway_upstream_response_too_large_total", "kind" => "non_stream");
+    too_large_error()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn limits_streamed_body() {
+        let chunks = futures::stream::iter(["ab", "cd", "ef"].map(|c| Ok(Bytes::from(c))));
+        let results: Vec<_> = limit_body(chunks, Some(3)).collect().await;
+        assert_eq!(results.len(), 2);
+        assert!(results[1].as_ref().is_err_and(is_too_large));
+    }
+
+    #[test]
+    fn other_errors_are_not_too_large() {
+        assert!(!is_too_large(&Error::Proxy(
+            "Upstream returned error status 500: boom".to_string()
+        )));
+        assert!(!is_too_large(&Error::Cache(RESPONSE_TOO_LARGE.to_string())));
+    }
+}
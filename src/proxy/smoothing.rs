@@ -0,0 +1,247 @@
+use crate::Result;
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use serde_json::Value;
+use tokio::time::{Duration, Instant};
+
+/// 拆分文本增量时每个 token 估算的字符数
+const CHARS_PER_TOKEN: usize = 4;
+
+/// 平滑流式输出（打字机节奏）
+///
+/// 将上游突发到达的大块数据按完整 SSE 事件拆分，文本增量事件（OpenAI 的 `delta.content`、
+/// Anthropic 的 `text_delta` / `thinking_delta`）再按每 [`CHARS_PER_TOKEN`] 个字符一个
+/// token 拆成多个事件，以不超过 `tokens_per_second` 的速率均匀地推送给客户端。
+/// 其他事件按一个 token 计算；当上游本身的速度低于上限时不会引入额外延迟。
+///
+/// 上游出错或结束时，缓冲区中剩余的数据会立即下发，不再等待节奏。
+pub fn smooth_stream<S>(
+    stream: S,
+    tokens_per_second: u32,
+) -> impl Stream<Item = Result<Bytes>> + Send + 'static
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    let interval = Duration::from_secs_f64(1.0 / f64::from(tokens_per_second.max(1)));
+
+    async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        let mut buffer = BytesMut::new();
+        let mut next_emit = Instant::now();
+
+        while let Some(chunk_result) = stream.next().await {
+            match chunk_result {
+                Ok(chunk) => {
+                    buffer.extend_from_slice(&chunk);
+
+                    // 逐个下发缓冲区中完整的 SSE 事件（以 \n\n 结尾）
+                    while let Some(event_end) = find_event_end(&buffer) {
+                        let event = buffer.split_to(event_end).freeze();
+                        let pieces = split_text_delta(&event).unwrap_or_else(|| vec![event]);
+
+                        for piece in pieces {
+                            // 距离上一次下发不足一个间隔时等待，否则立即下发
+                            tokio::time::sleep_until(next_emit).await;
+                            next_emit = Instant::now() + interval;
+
+                            yield Ok(piece);
+                        }
+                    }
+                }
+                Err(e) => {
+                    if !buffer.is_empty() {
+                        yield Ok(buffer.split().freeze());
+                    }
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+
+        // 上游结束，下发不完整的尾部数据
+        if !buffer.is_empty() {
+            yield Ok(buffer.split().freeze());
+        }
+    }
+}
+
+/// 查找第一个 SSE 事件的结束位置（包含分隔符 \n\n）
+fn find_event_end(buffer: &[u8]) -> Option<usize> {
+    buffer
+        .windows(2)
+        .position(|w| w == b"\n\n")
+        .map(|pos| pos + 2)
+}
+
+/// 将文本增量事件按 token 拆成多个事件，不是文本增量或不足两个 token 时返回 None
+fn split_text_delta(event: &[u8]) -> Option<Vec<Bytes>> {
+    let text = std::str::from_utf8(event).ok()?;
+    let mut name = None;
+    let mut data = None;
+    for line in text.lines().filter(|line| !line.is_empty()) {
+        if let Some(value) = line.strip_prefix("event:") {
+            name = Some(value.trim());
+        } else if let Some(value) = line.strip_prefix("data:") {
+            // 多行 data 的事件原样下发
+            if data.replace(value.trim()).is_some() {
+                return None;
+            }
+        } else {
+            return None;
+        }
+    }
+
+    let mut json: Value = serde_json::from_str(data?).ok()?;
+    let pointer = text_pointer(&json)?;
+    let content: Vec<char> = json.pointer(pointer)?.as_str()?.chars().collect();
+    if content.len() <= CHARS_PER_TOKEN {
+        return None;
+    }
+
+    let mut pieces = Vec::new();
+    for (i, piece) in content.chunks(CHARS_PER_TOKEN).enumerate() {
+        *json.pointer_mut(pointer)? = Value::String(piece.iter().collect());
+        // 角色只在第一个分片中出现
+        if i == 1 {
+            if let Some(delta) = json
+                .pointer_mut("/choices/0/delta")
+                .and_then(Value::as_object_mut)
+            {
+                delta.remove("role");
+            }
+        }
+
+        let mut event = String::new();
+        if let Some(name) = name {
+            event.push_str("event: ");
+            event.push_str(name);
+            event.push('\n');
+        }
+        event.push_str("data: ");
+        event.push_str(&json.to_string());
+        event.push_str("\n\n");
+        pieces.push(Bytes::from(event));
+    }
+    Some(pieces)
+}
+
+/// 文本增量在事件 JSON 中的位置
+fn text_pointer(json: &Value) -> Option<&'static str> {
+    if json["type"] == "content_block_delta" {
+        return match json["delta"]["type"].as_str()? {
+            "text_delta" => Some("/delta/text"),
+            "thinking_delta" => Some("/delta/thinking"),
+            _ => None,
+        };
+    }
+
+    // 带结束原因、用量或工具调用的 chunk 不拆分，避免这些字段重复出现
+    let choices = json["choices"].as_array()?;
+    let delta = choices.first()?["delta"].as_object()?;
+    let plain = choices.len() == 1
+        && choices[0]["finish_reason"].is_null()
+        && json["usage"].is_null()
+        && delta.keys().all(|key| key == "role" || key == "content");
+    (plain && delta.get("content")?.is_string()).then_some("/choices/0/delta/content")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::testkit::parse_sse;
+
+    async fn smooth(chunks: &[&'static str], tokens_per_second: u32) -> Vec<Bytes> {
+        let chunks: Vec<Result<Bytes>> = chunks
+            .iter()
+            .map(|chunk| Ok(Bytes::from_static(chunk.as_bytes())))
+            .collect();
+        smooth_stream(futures::stream::iter(chunks), tokens_per_second)
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await
+    }
+
+    fn data(event: &Bytes) -> Value {
+        let events = parse_sse(event);
+        assert_eq!(events.len(), 1);
+        events[0].json().unwrap()
+    }
+
+    #[tokio::test]
+    async fn splits_openai_content_into_tokens() {
+        let output = smooth(
+            &[
+                "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",",
+                "\"content\":\"Hello, world!\"},\"finish_reason\":null}]}\n\n",
+            ],
+            1000,
+        )
+        .await;
+
+        let pieces: Vec<Value> = output.iter().map(data).collect();
+        let content: Vec<&str> = pieces
+            .iter()
+            .map(|p| p["choices"][0]["delta"]["content"].as_str().unwrap())
+            .collect();
+        assert_eq!(content, ["Hell", "o, w", "orld", "!"]);
+        assert_eq!(pieces[0]["choices"][0]["delta"]["role"], "assistant");
+        assert!(pieces[1..]
+            .iter()
+            .all(|p| p["choices"][0]["delta"].get("role").is_none()));
+    }
+
+    #[tokio::test]
+    async fn splits_anthropic_text_and_thinking_deltas() {
+        let output = smooth(
+            &[
+                "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"thinking_delta\",\"thinking\":\"思考一下再回答\"}}\n\n",
+                "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":1,\"delta\":{\"type\":\"text_delta\",\"text\":\"Sunny day\"}}\n\n",
+            ],
+            1000,
+        )
+        .await;
+
+        assert_eq!(output.len(), 5);
+        assert!(output
+            .iter()
+            .all(|e| e.starts_with(b"event: content_block_delta\n")));
+        let thinking: String = output[..2]
+            .iter()
+            .map(|e| data(e)["delta"]["thinking"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(thinking, "思考一下再回答");
+        let text: String = output[2..]
+            .iter()
+            .map(|e| data(e)["delta"]["text"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(text, "Sunny day");
+    }
+
+    #[tokio::test]
+    async fn keeps_other_events_whole() {
+        let events = [
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"The end.\"},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"tool_calls\":[{\"index\":0,\"function\":{\"arguments\":\"{\\\"city\\\":\\\"Paris\\\"}\"}}]}}]}\n\n",
+            "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+            "data: [DONE]\n\n",
+        ];
+        let output = smooth(&events, 1000).await;
+        let output: Vec<&[u8]> = output.iter().map(|e| e.as_ref()).collect();
+        let expected: Vec<&[u8]> = events.iter().map(|e| e.as_bytes()).collect();
+        assert_eq!(output, expected);
+    }
+
+    #[tokio::test]
+    async fn paces_split_tokens() {
+        let started = Instant::now();
+        let output = smooth(
+            &["data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"abcdefghijklmnopqrst\"},\"finish_reason\":null}]}\n\n"],
+            50,
+        )
+        .await;
+
+        // 5 个 token，首个立即下发，其余间隔 20ms
+        assert_eq!(output.len(), 5);
+        assert!(started.elapsed() >= Duration::from_millis(80));
+    }
+}
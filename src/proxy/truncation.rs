@@ -0,0 +1,91 @@
+use crate::config::TruncationRetryConfig;
+use crate::error::Error;
+use reqwest::header::{HeaderMap, HeaderName, CONTENT_LENGTH};
+use serde::de::IgnoredAny;
+use sha2::{Digest, Sha256};
+
+/// 截断错误消息的前缀，按瞬时故障处理
+pub const TRUNCATED_RESPONSE: &str = "Truncated upstream response";
+
+/// 非流式响应截断检测
+pub struct TruncationCheck {
+    retry: bool,
+    checksum_header: Option<HeaderName>,
+}
+
+/// 读取响应体前从响应头中取得的预期长度和校验和
+#[derive(Debug, Default)]
+pub struct Expected {
+    content_length: Option<usize>,
+    checksum: Option<String>,
+}
+
+impl TruncationCheck {
+    pub fn from_config(config: &TruncationRetryConfig) -> Self {
+        Self {
+            retry: config.enabled,
+            checksum_header: config
+                .checksum_header
+                .as_deref()
+                .and_then(|header| HeaderName::from_bytes(header.as_bytes()).ok()),
+        }
+    }
+
+    /// 截断时是否先向同一路由重试
+    pub fn retries(&self) -> bool {
+        self.retry
+    }
+
+    pub fn expect(&self, headers: &HeaderMap) -> Expected {
+        let value = |name: &HeaderName| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+        };
+        Expected {
+            content_length: value(&CONTENT_LENGTH).and_then(|len| len.parse().ok()),
+            checksum: self
+                .checksum_header
+                .as_ref()
+                .and_then(value)
+                .map(str::to_ascii_lowercase),
+        }
+    }
+}
+
+/// 校验响应体是否完整，截断时返回原因
+///
+/// 空响应体不视为截断，由调用方单独处理。
+pub fn check(expected: &Expected, body: &[u8]) -> Option<&'static str> {
+    if body.is_empty() {
+        return None;
+    }
+    if expected.content_length.is_some_and(|len| len != body.len()) {
+        return Some("content_length");
+    }
+    if let Some(checksum) = &expected.checksum {
+        if hex::encode(Sha256::digest(body)) != *checksum {
+            return Some("checksum");
+        }
+    }
+    match serde_json::from_slice::<IgnoredAny>(body) {
+        Err(e) if e.is_eof() => Some("incomplete_json"),
+        _ => None,
+    }
+}
+
+/// 读取响应体中途失败（如连接被重置）
+pub fn is_body_error(error: &Error) -> bool {
+    matches!(error, Error::Http(e) if e.is_body() || e.is_decode())
+}
+
+/// 截断错误，消息中不带数字以免被误判为客户端错误状态码
+pub fn truncated_error(reason: &str) -> Error {
+    Error::Proxy(format!("{} ({})", TRUNCATED_RESPONSE, reason))
+}
+
+/// 是否为截断错误
+pub fn is_truncated(error: &Error) -> bool {
+    matches!(error, Error::Proxy(msg) if msg.starts_with(TRUNCATED_RESPONSE))
+}
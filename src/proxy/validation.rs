@@ -0,0 +1,263 @@
+use crate::error::{Error, Result};
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::pin::Pin;
+use tracing::error;
+
+/// 判断 `{` 开头的响应时最多缓冲的字节数
+const MAX_PROBE_BYTES: usize = 64 * 1024;
+
+/// 日志中响应体预览的最大字节数
+const PREVIEW_BYTES: usize = 512;
+
+/// 在向客户端输出前校验上游流式响应
+///
+/// 部分配置错误的上游会以 200 返回 HTML 错误页或非流式 JSON，直接转发给客户端只会得到乱码。
+/// 这里读取首个非空白内容做判断：
+/// - `data:` / `event:` / `id:` / `retry:` / `:` 开头：SSE，通过；只到达字段名的一部分时继续读取
+/// - `{` 开头：读到首行为止，content-type 为 `application/json` 或首行带有 `error` 字段时视为失败，
+///   否则按 NDJSON 流通过
+/// - 其他内容（如 `<html>`）或空响应：失败
+///
+/// 校验失败返回 `Error::Proxy`，由调用方按路由失败处理并故障转移。
+/// 通过时返回的流会先输出已读取的内容，再继续读取上游。
+pub async fn validate_stream<S>(
+    content_type: Option<&str>,
+    stream: S,
+) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>>
+where
+    S: Stream<Item = Result<Bytes>> + Send + 'static,
+{
+    let mut stream = Box::pin(stream);
+    let mut probe = BytesMut::new();
+    let content_type = content_type.unwrap_or_default().to_ascii_lowercase();
+
+    loop {
+        let start = probe.iter().position(|b| !b.is_ascii_whitespace());
+        match start.map(|i| probe[i]) {
+            Some(b'{') => {
+                let line = &probe[start.unwrap_or_default()..];
+                if let Some(end) = line.iter().position(|&b| b == b'\n') {
+                    check_json_line(&content_type, &line[..end], &probe)?;
+                    break;
+                }
+            }
+            Some(_) => match sse_prefix(&probe[start.unwrap_or_default()..]) {
+                SsePrefix::Complete => break,
+                SsePrefix::Partial => {}
+                SsePrefix::Invalid => return Err(invalid_stream(&content_type, &probe)),
+            },
+            None => {}
+        }
+
+        if probe.len() > MAX_PROBE_BYTES {
+            return Err(invalid_stream(&content_type, &probe));
+        }
+
+        match stream.next().await {
+            Some(Ok(chunk)) => probe.extend_from_slice(&chunk),
+            Some(Err(e)) => return Err(e),
+            None => {
+                // 上游在换行前结束：整体就是一个 JSON 文档
+                let line = probe.trim_ascii();
+                if line.first() == Some(&b'{') {
+                    check_json_line(&content_type, line, &probe)?;
+                    break;
+                }
+                return Err(invalid_stream(&content_type, &probe));
+            }
+        }
+    }
+
+    let probe = probe.freeze();
+    Ok(Box::pin(
+        futures::stream::once(async move { Ok(probe) }).chain(stream),
+    ))
+}
+
+/// SSE 字段名（含冒号）
+const SSE_FIELDS: [&[u8]; 4] = [b"data:", b"event:", b"id:", b"retry:"];
+
+enum SsePrefix {
+    /// 以 SSE 字段或注释开头
+    Complete,
+    /// 内容是某个字段名的前缀（如 `i`、`dat`），需要读取更多内容再判断
+    Partial,
+    Invalid,
+}
+
+// 判断首个非空白内容是否为 SSE 字段行或注释行
+fn sse_prefix(content: &[u8]) -> SsePrefix {
+    if content.starts_with(b":") || SSE_FIELDS.iter().any(|field| content.starts_with(field)) {
+        SsePrefix::Complete
+    } else if SSE_FIELDS.iter().any(|field| field.starts_with(content)) {
+        SsePrefix::Partial
+    } else {
+        SsePrefix::Invalid
+    }
+}
+
+fn check_json_line(content_type: &str, line: &[u8], probe: &[u8]) -> Result<()> {
+    let is_error = serde_json::from_slice::<serde_json::Value>(line)
+        .map(|json| json.get("error").is_some_and(|e| !e.is_null()))
+        .unwrap_or(true);
+
+    if is_error || content_type.starts_with("application/json") {
+        return Err(invalid_stream(content_type, probe));
+    }
+    Ok(())
+}
+
+fn invalid_stream(content_type: &str, probe: &[u8]) -> Error {
+    let preview = &probe[..probe.len().min(PREVIEW_BYTES)];
+    error!(
+        "Upstream returned a non-SSE stream response (content-type: {:?}): {}",
+        content_type,
+        String::from_utf8_lossy(preview)
+    );
+
+    // 错误信息不包含响应体，避免其中的数字被误判为上游状态码
+    Error::Proxy(format!(
+        "Upstream returned a non-SSE stream response (content-type: {:?})",
+        content_type
+    ))
+}
+
+/// 判断以 200 返回的非流式响应体是否为错误，返回推断的上游状态码
+///
+/// 部分 OpenAI 兼容的上游以 200 返回 `{"error": {...}}`（或 Anthropic 的 `{"type": "error", ...}`），
+/// 按成功处理会导致用量提取失败、协议转换输出残缺的响应。带有非空 `error` 字段且没有
+/// `choices` / `content` / `output` / `data` / `results` 等结果字段时视为错误：
+/// `error` 中的数字 `code` / `status` 为 4xx、5xx 时直接使用，否则按错误类型推断，无法识别时视为 502。
+pub fn error_body_status(body: &[u8]) -> Option<StatusCode> {
+    let json: Value = serde_json::from_slice(body).ok()?;
+    let object = json.as_object()?;
+    let error = object.get("error").filter(|e| !e.is_null())?;
+    let has_result = ["choices", "content", "output", "data", "results"]
+        .iter()
+        .any(|key| object.get(*key).is_some_and(|v| !v.is_null()));
+    if has_result {
+        return None;
+    }
+
+    let numeric = ["code", "status"].iter().find_map(|key| {
+        let value = error.get(*key)?;
+        let code = value.as_u64().or_else(|| value.as_str()?.parse().ok())?;
+        u16::try_from(code)
+            .ok()
+            .and_then(|code| StatusCode::from_u16(code).ok())
+            .filter(|status| status.is_client_error() || status.is_server_error())
+    });
+    if numeric.is_some() {
+        return numeric;
+    }
+
+    let kind = ["type", "code"]
+        .iter()
+        .filter_map(|key| error.get(*key)?.as_str())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<String>>()
+        .join(" ");
+    let status = if kind.contains("rate_limit") || kind.contains("quota") {
+        StatusCode::TOO_MANY_REQUESTS
+    } else if kind.contains("authentication") || kind.contains("api_key") {
+        StatusCode::UNAUTHORIZED
+    } else if kind.contains("permission") {
+        StatusCode::FORBIDDEN
+    } else if kind.contains("not_found") {
+        StatusCode::NOT_FOUND
+    } else if kind.contains("invalid_request") {
+        StatusCode::BAD_REQUEST
+    } else if kind.contains("overloaded") {
+        // Anthropic 的过载状态码
+        StatusCode::from_u16(529).unwrap_or(StatusCode::SERVICE_UNAVAILABLE)
+    } else if kind.contains("unavailable") {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else if kind.contains("timeout") {
+        StatusCode::GATEWAY_TIMEOUT
+    } else {
+        StatusCode::BAD_GATEWAY
+    };
+    Some(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+
+    async fn validate(content_type: &str, chunks: &[&'static str]) -> Result<Vec<u8>> {
+        let stream = futures::stream::iter(
+            chunks
+                .iter()
+                .map(|chunk| Ok(Bytes::from_static(chunk.as_bytes())))
+                .collect::<Vec<_>>(),
+        );
+        let stream = validate_stream(Some(content_type), stream).await?;
+        stream
+            .try_fold(Vec::new(), |mut body, chunk| async move {
+                body.extend_from_slice(&chunk);
+                Ok(body)
+            })
+            .await
+    }
+
+    #[tokio::test]
+    async fn accepts_sse_split_inside_field_name() {
+        let body = validate("text/event-stream", &["\n i", "d: 1\n", "data: {}\n\n"])
+            .await
+            .unwrap();
+        assert_eq!(body, b"\n id: 1\ndata: {}\n\n");
+
+        assert!(validate("text/event-stream", &["da", "ta: {}\n\n"])
+            .await
+            .is_ok());
+        assert!(validate("text/event-stream", &[": ping\n\n"]).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_non_sse_content() {
+        assert!(validate("text/html", &["<html>502 Bad Gateway</html>"])
+            .await
+            .is_err());
+        assert!(validate("text/event-stream", &["identity"]).await.is_err());
+        // 只到达字段名的一部分就结束
+        assert!(validate("text/event-stream", &["dat"]).await.is_err());
+        assert!(validate("text/event-stream", &[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn checks_json_first_line() {
+        assert!(
+            validate("application/x-ndjson", &["{\"id\":1}\n{\"id\":2}\n"])
+                .await
+                .is_ok()
+        );
+        assert!(validate("application/json", &["{\"id\":1}"]).await.is_err());
+        assert!(
+            validate("text/event-stream", &["{\"error\":{\"message\":\"x\"}}\n"])
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn infers_error_body_status() {
+        let status = |body: &str| error_body_status(body.as_bytes());
+        assert_eq!(
+            status(r#"{"error":{"code":429}}"#),
+            Some(StatusCode::TOO_MANY_REQUESTS)
+        );
+        assert_eq!(
+            status(r#"{"type":"error","error":{"type":"overloaded_error"}}"#).map(|s| s.as_u16()),
+            Some(529)
+        );
+        assert_eq!(
+            status(r#"{"error":{"message":"?"}}"#),
+            Some(StatusCode::BAD_GATEWAY)
+        );
+        assert_eq!(status(r#"{"error":null,"choices":[]}"#), None);
+    }
+}
@@ -0,0 +1,51 @@
+use super::{endpoint_origin, ProxyForwarder};
+use crate::config::WarmupConfig;
+use std::sync::Arc;
+use tracing::info;
+
+/// 启动上游连接预热任务
+///
+/// 启动时立即预热固定配置的上游，之后按 `interval` 周期性地对固定上游和
+/// 请求量最高的上游补足空闲连接，避免部署后首批请求承担握手延迟。
+/// 预热间隔小于连接池空闲超时，空闲连接因此不会被回收。
+pub fn spawn(proxy: Arc<ProxyForwarder>, config: WarmupConfig, endpoints: Vec<String>) {
+    let mut fixed: Vec<String> = Vec::new();
+    for origin in endpoints
+        .iter()
+        .filter_map(|endpoint| endpoint_origin(endpoint))
+    {
+        if !fixed.contains(&origin) {
+            fixed.push(origin);
+        }
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.interval);
+        let mut first_round = true;
+
+        loop {
+            interval.tick().await;
+
+            let mut targets = fixed.clone();
+            for origin in proxy.top_endpoints(config.top_endpoints) {
+                if !targets.contains(&origin) {
+                    targets.push(origin);
+                }
+            }
+
+            let rounds = targets
+                .iter()
+                .map(|endpoint| proxy.warm_up(endpoint, config.min_idle));
+            let warmed: usize = futures::future::join_all(rounds).await.into_iter().sum();
+
+            if first_round {
+                info!(
+                    "Warmed {} upstream connection(s) across {} endpoint(s)",
+                    warmed,
+                    targets.len()
+                );
+                first_round = false;
+            }
+        }
+    });
+}
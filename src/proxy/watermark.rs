@@ -0,0 +1,110 @@
+//! 响应元数据标记
+//!
+//! 为合规追溯在返回客户端的响应中附加网关元数据，只写入不影响严格解析的位置：
+//! 非流式 JSON 响应在顶层对象末尾增加一个字段（各协议的 SDK 忽略未知的顶层字段），
+//! SSE 流式响应在流末尾追加一行注释（SSE 规范要求解析器忽略注释）。
+//! NDJSON 流式响应没有可忽略的位置，不做标记。
+
+use crate::config::WatermarkConfig;
+use crate::Result;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+
+/// 预先序列化的标记内容
+pub struct Watermark {
+    enabled: bool,
+    field: String,
+    // 元数据的 JSON 对象
+    metadata: String,
+}
+
+impl Watermark {
+    /// 元数据为空时返回 None
+    pub fn from_config(config: &WatermarkConfig) -> Option<Self> {
+        if config.metadata.is_empty() {
+            return None;
+        }
+        Some(Self {
+            enabled: config.enabled,
+            field: config.field.clone(),
+            metadata: serde_json::to_string(&config.metadata).ok()?,
+        })
+    }
+
+    /// 未在路由上指定时是否标记
+    pub fn enabled_by_default(&self) -> bool {
+        self.enabled
+    }
+
+    /// 在非流式响应的顶层对象末尾增加元数据字段，原有内容按字节保留
+    ///
+    /// 响应不是 JSON 对象或已有同名字段时原样返回。
+    pub fn mark_response(&self, body: Bytes) -> Bytes {
+        let is_unmarked_object = serde_json::from_slice::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|value| value.as_object().map(|o| !o.contains_key(&self.field)))
+            .unwrap_or(false);
+        if !is_unmarked_object {
+            return body;
+        }
+
+        // 顶层对象的结束括号是去掉尾部空白后的最后一个字节
+        let Some(end) = body.iter().rposition(|b| !b.is_ascii_whitespace()) else {
+            return body;
+        };
+        let has_members = body[..end]
+            .iter()
+            .rposition(|b| !b.is_ascii_whitespace())
+            .is_some_and(|last| body[last] != b'{');
+        let field = serde_json::to_string(&self.field).unwrap_or_default();
+
+        let mut marked = Vec::with_capacity(body.len() + field.len() + self.metadata.len() + 2);
+        marked.extend_from_slice(&body[..end]);
+        if has_members {
+            marked.push(b',');
+        }
+        marked.extend_from_slice(field.as_bytes());
+        marked.push(b':');
+        marked.extend_from_slice(self.metadata.as_bytes());
+        marked.extend_from_slice(&body[end..]);
+        Bytes::from(marked)
+    }
+
+    /// 在 SSE 流正常结束后追加注释行，流出错时不追加
+    pub fn mark_sse_stream<S>(&self, stream: S) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>
+    where
+        S: Stream<Item = Result<Bytes>> + Send + 'static,
+    {
+        let comment = format!(
+            ": {} {}\n\n",
+            self.field.replace(['\r', '\n'], ""),
+            self.metadata
+        );
+        Box::pin(async_stream::stream! {
+            let mut stream = Box::pin(stream);
+            // 最后一段字节不以换行结尾时先补换行，避免注释拼接到上一行
+            let mut at_line_start = true;
+            while let Some(chunk) = stream.next().await {
+                match chunk {
+                    Ok(bytes) => {
+                        if let Some(last) = bytes.last() {
+                            at_line_start = *last == b'\n';
+                        }
+                        yield Ok(bytes);
+                    }
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                }
+            }
+            let comment = if at_line_start {
+                comment
+            } else {
+                format!("\n{}", comment)
+            };
+            yield Ok(Bytes::from(comment));
+        })
+    }
+}
@@ -0,0 +1,151 @@
+use crate::config::BreakerConfig;
+use crate::router::failover::AttemptOutcome;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use std::time::Duration;
+
+/// 供应商熔断器
+///
+/// 按供应商令牌统计连续的瞬时故障（超时、连接失败、502/503/504/529），达到阈值后熔断，
+/// 冷却期内不参与路由。冷却结束后处于半开状态：下一次瞬时故障立即再次熔断，成功则清零。
+/// 确定性错误与请求内容有关，不计入。
+pub struct ProviderBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    // provider_token_id -> 连续瞬时故障次数
+    failures: DashMap<String, u32>,
+    // provider_token_id -> 冷却结束时间
+    open: DashMap<String, DateTime<Utc>>,
+}
+
+impl ProviderBreaker {
+    pub fn from_config(config: &BreakerConfig) -> Self {
+        Self {
+            failure_threshold: config.failure_threshold,
+            cooldown: config.cooldown,
+            failures: DashMap::new(),
+            open: DashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.failure_threshold > 0
+    }
+
+    /// 记录一次上游调用的结果，新熔断时返回冷却结束时间
+    pub fn record(
+        &self,
+        provider_token_id: &str,
+        outcome: AttemptOutcome,
+    ) -> Option<DateTime<Utc>> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        match outcome {
+            AttemptOutcome::Success | AttemptOutcome::ClientError => {
+                self.failures.remove(provider_token_id);
+                None
+            }
+            AttemptOutcome::Transient => {
+                let mut failures = self
+                    .failures
+                    .entry(provider_token_id.to_string())
+                    .or_insert(0);
+                *failures += 1;
+                if *failures < self.failure_threshold || self.is_open(provider_token_id) {
+                    return None;
+                }
+                // 保留阈值减一的计数，冷却结束后再失败一次即重新熔断
+                *failures = self.failure_threshold - 1;
+                let until =
+                    Utc::now() + chrono::Duration::from_std(self.cooldown).unwrap_or_default();
+                self.open.insert(provider_token_id.to_string(), until);
+                Some(until)
+            }
+            AttemptOutcome::Deterministic | AttemptOutcome::Skipped | AttemptOutcome::Cancelled => {
+                None
+            }
+        }
+    }
+
+    /// 应用其他副本发现的熔断，冷却结束时间取较晚者
+    pub fn open_until(&self, provider_token_id: &str, until: DateTime<Utc>) {
+        if until <= Utc::now() {
+            return;
+        }
+        let mut entry = self
+            .open
+            .entry(provider_token_id.to_string())
+            .or_insert(until);
+        if *entry < until {
+            *entry = until;
+        }
+    }
+
+    /// 供应商令牌是否处于冷却期，到期的条目顺带清理
+    pub fn is_open(&self, provider_token_id: &str) -> bool {
+        let expired = match self.open.get(provider_token_id) {
+            None => return false,
+            Some(until) => *until <= Utc::now(),
+        };
+
+        if expired {
+            self.open.remove(provider_token_id);
+            return false;
+        }
+        true
+    }
+
+    /// 当前处于冷却期的供应商令牌及冷却结束时间
+    pub fn open_providers(&self) -> Vec<(String, DateTime<Utc>)> {
+        let now = Utc::now();
+        self.open.retain(|_, until| *until > now);
+
+        self.open
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(cooldown: Duration) -> ProviderBreaker {
+        ProviderBreaker::from_config(&BreakerConfig {
+            failure_threshold: 2,
+            cooldown,
+        })
+    }
+
+    #[test]
+    fn trips_after_consecutive_transient_failures() {
+        let breaker = breaker(Duration::from_secs(60));
+        assert!(breaker.record("p1", AttemptOutcome::Transient).is_none());
+        assert!(breaker.record("p1", AttemptOutcome::Transient).is_some());
+        assert!(breaker.is_open("p1"));
+        assert!(!breaker.is_open("p2"));
+    }
+
+    #[test]
+    fn success_and_client_errors_reset_count() {
+        let breaker = breaker(Duration::from_secs(60));
+        breaker.record("p1", AttemptOutcome::Transient);
+        breaker.record("p1", AttemptOutcome::ClientError);
+        assert!(breaker.record("p1", AttemptOutcome::Transient).is_none());
+        breaker.record("p1", AttemptOutcome::Deterministic);
+        assert!(breaker.record("p1", AttemptOutcome::Transient).is_some());
+    }
+
+    #[test]
+    fn half_open_trips_on_next_failure() {
+        let breaker = breaker(Duration::from_millis(10));
+        breaker.record("p1", AttemptOutcome::Transient);
+        breaker.record("p1", AttemptOutcome::Transient);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!breaker.is_open("p1"));
+        assert!(breaker.record("p1", AttemptOutcome::Transient).is_some());
+    }
+}
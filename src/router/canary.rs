@@ -0,0 +1,77 @@
+use crate::models::{CanarySpec, RouteConfig};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+/// 分桶精度：万分位，支持 0.01% 粒度的分流比例
+const BUCKETS: u64 = 10_000;
+
+impl CanarySpec {
+    /// 当前时间是否处于生效时间窗口内
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.start_at.is_none_or(|start| now >= start) && self.end_at.is_none_or(|end| now < end)
+    }
+
+    /// 用户是否落在分流比例内
+    pub fn includes(&self, bucket: u64) -> bool {
+        (bucket as f64) < self.percentage * (BUCKETS as f64) / 100.0
+    }
+}
+
+/// 计算用户在某个模型上的分桶（0..10000）
+///
+/// 同一用户对同一模型的分桶结果稳定，保证金丝雀期间用户体验一致。
+pub fn bucket(user_token: &str, model: &str) -> u64 {
+    let digest = Sha256::new()
+        .chain_update(user_token.as_bytes())
+        .chain_update(b"\0")
+        .chain_update(model.as_bytes())
+        .finalize();
+
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(prefix) % BUCKETS
+}
+
+/// 按金丝雀规则筛选并排序路由
+///
+/// - 命中分桶且处于生效时间内的金丝雀路由排在最前
+/// - 未命中的金丝雀路由被移除，稳定路由作为兜底保留在后面
+/// - 筛选后没有任何可用路由时，原样返回，避免因规则配置不当导致请求失败
+pub fn apply(
+    routes: Vec<RouteConfig>,
+    user_token: &str,
+    model: &str,
+    now: DateTime<Utc>,
+) -> Vec<RouteConfig> {
+    if routes.iter().all(|r| r.canary.is_none()) {
+        return routes;
+    }
+
+    let bucket = bucket(user_token, model);
+    let (canary, stable): (Vec<_>, Vec<_>) = routes.iter().partition(|r| r.canary.is_some());
+
+    let mut selected: Vec<RouteConfig> = canary
+        .into_iter()
+        .filter(|r| {
+            r.canary
+                .as_ref()
+                .is_some_and(|spec| spec.is_active(now) && spec.includes(bucket))
+        })
+        .cloned()
+        .collect();
+
+    debug!(
+        "Canary selection for model {}: bucket={}, canary_routes={}",
+        model,
+        bucket,
+        selected.len()
+    );
+
+    selected.extend(stable.into_iter().cloned());
+
+    if selected.is_empty() {
+        return routes;
+    }
+    selected
+}
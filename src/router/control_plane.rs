@@ -0,0 +1,228 @@
+use crate::config::{ControlPlaneBreakerConfig, ControlPlaneRateLimitConfig};
+use rand::Rng;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 业务API（控制面）熔断器
+///
+/// 连续失败（重试耗尽后仍为连接错误或5xx）达到阈值后熔断，熔断期内请求直接失败，
+/// 不再访问业务API。熔断结束后只放行一个探测请求：成功则恢复，失败则再次熔断；
+/// 探测请求被取消（客户端断开等）而没有结果时，经过一个熔断时长后再放行下一个探测请求。
+pub struct ControlPlaneBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    // 连续失败次数
+    failures: u32,
+    // 熔断结束时间
+    open_until: Option<Instant>,
+    // 半开状态下已放行的探测请求的过期时间
+    probe_until: Option<Instant>,
+}
+
+impl ControlPlaneBreaker {
+    pub fn from_config(config: &ControlPlaneBreakerConfig) -> Self {
+        Self {
+            failure_threshold: config.failure_threshold,
+            open_duration: config.open_duration,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    /// 是否允许访问业务API
+    pub fn allow(&self) -> bool {
+        if self.failure_threshold == 0 {
+            return true;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match state.open_until {
+            None => true,
+            Some(until) if now < until => false,
+            // 半开：只放行一个探测请求，探测请求过期后再放行下一个
+            Some(_) if state.probe_until.is_some_and(|probe| now < probe) => false,
+            Some(_) => {
+                state.probe_until = Some(now + self.open_duration);
+                true
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        *state = BreakerState::default();
+    }
+
+    /// 记录一次失败，返回是否因此熔断
+    pub fn record_failure(&self) -> bool {
+        if self.failure_threshold == 0 {
+            return false;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let probe_failed = state.probe_until.take().is_some();
+        // 熔断期间（含等待探测的半开状态）只计入探测请求的失败，熔断前已发出的请求不计入
+        if state.open_until.is_some() && !probe_failed {
+            return false;
+        }
+        state.failures += 1;
+        if !probe_failed && state.failures < self.failure_threshold {
+            return false;
+        }
+
+        state.failures = 0;
+        state.open_until = Some(Instant::now() + self.open_duration);
+        true
+    }
+
+    /// 是否处于熔断状态（含等待探测的半开状态）
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap().open_until.is_some()
+    }
+}
+
+/// 业务API路由解析限速（令牌桶）
+///
+/// 令牌不足时预占下一个令牌并等待其补充，请求按到达顺序平滑放行；
+/// 需要等待的时间超过 `max_wait` 时不排队，直接拒绝。
+pub struct ControlPlaneLimiter {
+    // 每秒补充的令牌数，未配置限速时为 None
+    rate: Option<f64>,
+    burst: f64,
+    max_wait: Duration,
+    // (当前令牌数, 上次补充时间)，令牌数为负表示已被排队的请求预占
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl ControlPlaneLimiter {
+    pub fn from_config(config: &ControlPlaneRateLimitConfig) -> Self {
+        let burst = config.burst as f64;
+        Self {
+            rate: config.requests_per_second,
+            burst,
+            max_wait: config.max_wait,
+            bucket: Mutex::new((burst, Instant::now())),
+        }
+    }
+
+    /// 取得一个令牌，需要排队时返回等待时间，超过最长等待时间时返回 None
+    pub fn acquire(&self) -> Option<Duration> {
+        let Some(rate) = self.rate else {
+            return Some(Duration::ZERO);
+        };
+
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let (tokens, refilled_at) = *bucket;
+        let tokens =
+            (tokens + now.duration_since(refilled_at).as_secs_f64() * rate).min(self.burst);
+        let wait = if tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - tokens) / rate)
+        };
+        if wait > self.max_wait {
+            *bucket = (tokens, now);
+            return None;
+        }
+        *bucket = (tokens - 1.0, now);
+        Some(wait)
+    }
+}
+
+/// 第 `attempt` 次重试（从1开始）前的等待时间
+///
+/// 指数退避：`base * 2^(attempt-1)`，不超过 `max`；实际等待时间在其一半到全部之间随机，
+/// 避免大量请求同时重试
+pub fn backoff_delay(attempt: u32, base: Duration, max: Duration) -> Duration {
+    let exponential = base.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+    let delay = exponential.min(max);
+    let half = delay / 2;
+    half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(open_duration: Duration) -> ControlPlaneBreaker {
+        ControlPlaneBreaker::from_config(&ControlPlaneBreakerConfig {
+            failure_threshold: 2,
+            open_duration,
+        })
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = breaker(Duration::from_secs(60));
+        assert!(!breaker.record_failure());
+        assert!(breaker.record_failure());
+        assert!(!breaker.allow());
+        assert!(breaker.is_open());
+    }
+
+    #[test]
+    fn ignores_failures_while_open() {
+        let breaker = breaker(Duration::from_millis(20));
+        breaker.record_failure();
+        breaker.record_failure();
+        // 熔断前已发出的请求失败不延长熔断
+        assert!(!breaker.record_failure());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn admits_one_probe_and_recovers() {
+        let breaker = breaker(Duration::from_millis(20));
+        breaker.record_failure();
+        breaker.record_failure();
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        breaker.record_success();
+        assert!(breaker.allow());
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    fn failed_probe_reopens() {
+        let breaker = breaker(Duration::from_millis(20));
+        breaker.record_failure();
+        breaker.record_failure();
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.allow());
+        assert!(breaker.record_failure());
+        assert!(!breaker.allow());
+    }
+
+    #[test]
+    fn abandoned_probe_expires() {
+        let breaker = breaker(Duration::from_millis(20));
+        breaker.record_failure();
+        breaker.record_failure();
+        std::thread::sleep(Duration::from_millis(30));
+        // 探测请求被取消，没有记录结果
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn disabled_breaker_never_opens() {
+        let breaker = ControlPlaneBreaker::from_config(&ControlPlaneBreakerConfig {
+            failure_threshold: 0,
+            open_duration: Duration::from_secs(60),
+        });
+        for _ in 0..5 {
+            assert!(!breaker.record_failure());
+        }
+        assert!(breaker.allow());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::config::ErrorBudgetConfig;
+use crate::models::RouteConfig;
+use crate::router::failover::AttemptOutcome;
+use dashmap::DashMap;
+use rand::Rng;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// 滚动窗口划分的桶数
+const WINDOW_BUCKETS: u32 = 10;
+
+/// 按错误率调整的供应商权重
+///
+/// 按供应商令牌统计滚动窗口内的成功和瞬时故障次数（确定性错误与请求内容有关，不计入）。
+/// 每个调整间隔评估一次：故障率超过阈值时权重减半，不低于下限；否则恢复一个步长，直到1。
+/// 路由排序时权重为 w 的路由以 1-w 的概率排到其他路由之后，流量随权重平滑变化。
+pub struct ErrorBudget {
+    config: ErrorBudgetConfig,
+    // provider_token_id -> 统计和权重
+    providers: DashMap<String, ProviderBudget>,
+}
+
+struct ProviderBudget {
+    // (桶开始时间, 成功次数, 故障次数)
+    buckets: VecDeque<(Instant, u64, u64)>,
+    weight: f64,
+    adjusted_at: Instant,
+}
+
+/// 被降权的供应商令牌
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderWeight {
+    pub provider_token_id: String,
+    pub weight: f64,
+    /// 窗口内的故障率
+    pub error_rate: f64,
+    /// 窗口内的请求数
+    pub requests: u64,
+}
+
+impl ProviderBudget {
+    fn new(now: Instant) -> Self {
+        Self {
+            buckets: VecDeque::new(),
+            weight: 1.0,
+            adjusted_at: now,
+        }
+    }
+
+    fn record(&mut self, success: bool, now: Instant, window: Duration) {
+        self.prune(now, window);
+        let bucket_len = window / WINDOW_BUCKETS;
+        let current = match self.buckets.back_mut() {
+            Some(bucket) if now.duration_since(bucket.0) < bucket_len => bucket,
+            _ => {
+                self.buckets.push_back((now, 0, 0));
+                self.buckets.back_mut().unwrap()
+            }
+        };
+        if success {
+            current.1 += 1;
+        } else {
+            current.2 += 1;
+        }
+    }
+
+    fn prune(&mut self, now: Instant, window: Duration) {
+        while self
+            .buckets
+            .front()
+            .is_some_and(|bucket| now.duration_since(bucket.0) >= window)
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    // (请求数, 故障率)
+    fn error_rate(&self) -> (u64, f64) {
+        let (ok, failed) = self
+            .buckets
+            .iter()
+            .fold((0, 0), |(ok, failed), b| (ok + b.1, failed + b.2));
+        let total = ok + failed;
+        let rate = if total == 0 {
+            0.0
+        } else {
+            failed as f64 / total as f64
+        };
+        (total, rate)
+    }
+}
+
+impl ErrorBudget {
+    pub fn from_config(config: &ErrorBudgetConfig) -> Self {
+        Self {
+            config: config.clone(),
+            providers: DashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// 记录一次上游调用的结果，到达调整间隔的供应商顺带调整权重
+    pub fn record(&self, provider_token_id: &str, outcome: AttemptOutcome) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let success = match outcome {
+            AttemptOutcome::Success | AttemptOutcome::ClientError => true,
+            AttemptOutcome::Transient => false,
+            AttemptOutcome::Deterministic | AttemptOutcome::Skipped | AttemptOutcome::Cancelled => {
+                return
+            }
+        };
+        let now = Instant::now();
+        let mut budget = self
+            .providers
+            .entry(provider_token_id.to_string())
+            .or_insert_with(|| ProviderBudget::new(now));
+        budget.record(success, now, self.config.window);
+        self.adjust(provider_token_id, &mut budget, now);
+    }
+
+    fn adjust(&self, provider_token_id: &str, budget: &mut ProviderBudget, now: Instant) {
+        if now.duration_since(budget.adjusted_at) < self.config.adjust_interval {
+            return;
+        }
+        budget.adjusted_at = now;
+
+        let (requests, error_rate) = budget.error_rate();
+        let previous = budget.weight;
+        if requests >= self.config.min_requests && error_rate > self.config.error_rate_threshold {
+            budget.weight = (budget.weight / 2.0).max(self.config.min_weight);
+            if budget.weight < previous {
+                warn!(
+                    "Provider token {} error rate {:.1}% over {} requests, weight lowered to {:.2}",
+                    provider_token_id,
+                    error_rate * 100.0,
+                    requests,
+                    budget.weight
+                );
+                metrics::increment_counter!("gateway_provider_downweighted_total");
+            }
+        } else if budget.weight < 1.0 {
+            budget.weight = (budget.weight + self.config.recovery_step).min(1.0);
+            if budget.weight >= 1.0 {
+                info!("Provider token {} weight restored", provider_token_id);
+            }
+        }
+    }
+
+    /// 按权重调整路由顺序：低权重的路由按概率排到其他路由之后，各自保持原有相对顺序
+    pub fn apply(&self, configs: Vec<RouteConfig>) -> Vec<RouteConfig> {
+        if !self.is_enabled() || configs.len() < 2 {
+            return configs;
+        }
+
+        let now = Instant::now();
+        let mut rng = rand::thread_rng();
+        let (kept, demoted): (Vec<RouteConfig>, Vec<RouteConfig>) =
+            configs.into_iter().partition(|config| {
+                let weight = self.current_weight(&config.provider_token_id, now);
+                weight >= 1.0 || rng.gen_bool(weight)
+            });
+        if !demoted.is_empty() {
+            metrics::counter!("gateway_route_demoted_total", demoted.len() as u64);
+        }
+        kept.into_iter().chain(demoted).collect()
+    }
+
+    // 供应商令牌的当前权重，降权后流量减少，到达调整间隔时在此恢复
+    fn current_weight(&self, provider_token_id: &str, now: Instant) -> f64 {
+        let Some(mut budget) = self.providers.get_mut(provider_token_id) else {
+            return 1.0;
+        };
+        if budget.weight < 1.0 {
+            budget.prune(now, self.config.window);
+            self.adjust(provider_token_id, &mut budget, now);
+        }
+        budget.weight
+    }
+
+    /// 当前被降权的供应商令牌，窗口内没有请求且权重已恢复的统计顺带清理
+    pub fn downweighted(&self) -> Vec<ProviderWeight> {
+        let now = Instant::now();
+        self.providers.retain(|_, budget| {
+            budget.prune(now, self.config.window);
+            !budget.buckets.is_empty() || budget.weight < 1.0
+        });
+
+        self.providers
+            .iter()
+            .filter(|entry| entry.weight < 1.0)
+            .map(|entry| {
+                let (requests, error_rate) = entry.error_rate();
+                ProviderWeight {
+                    provider_token_id: entry.key().clone(),
+                    weight: entry.weight,
+                    error_rate,
+                    requests,
+                }
+            })
+            .collect()
+    }
+}
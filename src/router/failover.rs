@@ -0,0 +1,248 @@
+use crate::models::RouteConfig;
+use crate::proxy::FailureClass;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Instant;
+use tracing::info;
+
+/// 单个请求内的故障转移队列
+///
+/// 按路由顺序依次尝试。因瞬时故障失败的路由推迟到其余路由都尝试过之后再重试一次；
+/// 对同一请求体返回确定性错误的路由在本次请求内不再重试。
+/// 竞速时同时取出多个路由，各尝试完成后依次切换为当前尝试记录结果，未完成的记为取消。
+pub struct FailoverQueue {
+    pending: VecDeque<RouteConfig>,
+    // 瞬时故障的路由，等待首轮结束后重试
+    deferred: Vec<RouteConfig>,
+    // 是否已进入重试轮
+    retrying: bool,
+    attempt: u32,
+    // 各次尝试的记录
+    attempts: Vec<AttemptTrace>,
+    // 尚未记录结果的当前尝试：在 attempts 中的位置和开始时间
+    open_attempt: Option<(usize, Instant)>,
+    // 当前尝试为已完成的竞速尝试时的完成时间
+    open_finished_at: Option<Instant>,
+    // 竞速中尚未切换为当前尝试的尝试
+    racing: Vec<(usize, Instant)>,
+    winner: Option<u32>,
+}
+
+/// 单个请求的路由追踪：尝试顺序、供应商、结果和耗时，以及最终成功的尝试
+#[derive(Debug, Clone, Serialize)]
+pub struct RoutingTrace {
+    pub attempts: Vec<AttemptTrace>,
+    /// 最终成功的尝试序号，全部失败时为空
+    pub winner: Option<u32>,
+}
+
+/// 单次路由尝试
+#[derive(Debug, Clone, Serialize)]
+pub struct AttemptTrace {
+    pub attempt: u32,
+    pub provider_id: String,
+    pub provider_token_id: String,
+    pub model: String,
+    /// 路由所属的池
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool: Option<String>,
+    pub outcome: AttemptOutcome,
+    pub elapsed_ms: u64,
+    /// 上游请求ID（上游响应带有时）
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_request_id: Option<String>,
+}
+
+/// 路由尝试的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttemptOutcome {
+    Success,
+    /// 上游返回客户端错误（4xx），直接返回给客户端
+    ClientError,
+    /// 瞬时故障，首轮结束后可能重试
+    Transient,
+    /// 确定性故障，本次请求内不再重试
+    Deterministic,
+    /// 未按上游失败处理就转向下一路由（如协议转换失败、响应为空）
+    Skipped,
+    /// 竞速中其他路由先成功，请求被取消
+    Cancelled,
+}
+
+impl From<FailureClass> for AttemptOutcome {
+    fn from(class: FailureClass) -> Self {
+        match class {
+            FailureClass::Transient => AttemptOutcome::Transient,
+            FailureClass::Deterministic => AttemptOutcome::Deterministic,
+        }
+    }
+}
+
+impl FailoverQueue {
+    pub fn new(routes: Vec<RouteConfig>) -> Self {
+        Self {
+            pending: routes.into(),
+            deferred: Vec::new(),
+            retrying: false,
+            attempt: 0,
+            attempts: Vec::new(),
+            open_attempt: None,
+            open_finished_at: None,
+            racing: Vec::new(),
+            winner: None,
+        }
+    }
+
+    /// 下一个要尝试的路由及尝试序号（从0开始）
+    pub fn next_route(&mut self) -> Option<(u32, RouteConfig)> {
+        if self.pending.is_empty() && !self.retrying && !self.deferred.is_empty() {
+            info!(
+                "Retrying {} route(s) that failed with transient errors",
+                self.deferred.len()
+            );
+            self.pending = std::mem::take(&mut self.deferred).into();
+            self.retrying = true;
+        }
+
+        // 上一次尝试未记录结果即转向下一路由
+        self.close_attempt(AttemptOutcome::Skipped);
+
+        let route = self.pending.pop_front()?;
+        let attempt = self.push_attempt(&route);
+        self.open_attempt = Some((self.attempts.len() - 1, Instant::now()));
+        Some((attempt, route))
+    }
+
+    /// 同时取出至多 `n` 个路由用于竞速，可用路由不足两个时不竞速、返回空
+    ///
+    /// 各尝试完成后需通过 `resume_race` 切换为当前尝试再记录结果，
+    /// 最后通过 `settle_race` 将其余尝试记为取消
+    pub fn next_race(&mut self, n: usize) -> Vec<(u32, RouteConfig)> {
+        if n < 2 || self.pending.len() < 2 {
+            return Vec::new();
+        }
+        self.close_attempt(AttemptOutcome::Skipped);
+
+        let started = Instant::now();
+        let mut routes = Vec::new();
+        while routes.len() < n {
+            let Some(route) = self.pending.pop_front() else {
+                break;
+            };
+            let attempt = self.push_attempt(&route);
+            self.racing.push((self.attempts.len() - 1, started));
+            routes.push((attempt, route));
+        }
+        routes
+    }
+
+    /// 将竞速中已完成的尝试切换为当前尝试，耗时计算到 `finished_at`
+    pub fn resume_race(&mut self, attempt: u32, finished_at: Instant) {
+        self.close_attempt(AttemptOutcome::Skipped);
+        if let Some(pos) = self
+            .racing
+            .iter()
+            .position(|(index, _)| self.attempts[*index].attempt == attempt)
+        {
+            let (index, started) = self.racing.remove(pos);
+            self.open_attempt = Some((index, started));
+            self.open_finished_at = Some(finished_at);
+        }
+    }
+
+    /// 竞速结束：`completed` 之外的尝试记为取消，返回取消的数量
+    pub fn settle_race(&mut self, completed: &[u32]) -> usize {
+        let (cancelled, racing): (Vec<_>, Vec<_>) = std::mem::take(&mut self.racing)
+            .into_iter()
+            .partition(|(index, _)| !completed.contains(&self.attempts[*index].attempt));
+        self.racing = racing;
+        for (index, started) in &cancelled {
+            let trace = &mut self.attempts[*index];
+            trace.outcome = AttemptOutcome::Cancelled;
+            trace.elapsed_ms = elapsed_ms(*started, None);
+        }
+        cancelled.len()
+    }
+
+    /// 记录路由失败；首轮中的瞬时故障会推迟重试
+    pub fn record_failure(&mut self, route: &RouteConfig, class: FailureClass) {
+        self.close_attempt(class.into());
+        if class == FailureClass::Transient && !self.retrying {
+            self.deferred.push(route.clone());
+        }
+    }
+
+    /// 记录当前尝试成功
+    pub fn record_success(&mut self) {
+        self.close_attempt(AttemptOutcome::Success);
+    }
+
+    /// 记录当前尝试返回客户端错误
+    pub fn record_client_error(&mut self) {
+        self.close_attempt(AttemptOutcome::ClientError);
+    }
+
+    /// 记录当前尝试的上游请求ID
+    pub fn record_upstream_request_id(&mut self, request_id: Option<String>) {
+        let current = match self.open_attempt {
+            Some((index, _)) => self.attempts.get_mut(index),
+            None => self.attempts.last_mut(),
+        };
+        if let Some(current) = current {
+            current.upstream_request_id = request_id;
+        }
+    }
+
+    /// 当前的路由追踪
+    pub fn trace(&self) -> RoutingTrace {
+        let mut attempts = self.attempts.clone();
+        if let Some((index, started)) = self.open_attempt {
+            attempts[index].elapsed_ms = elapsed_ms(started, self.open_finished_at);
+        }
+        for (index, started) in &self.racing {
+            attempts[*index].elapsed_ms = elapsed_ms(*started, None);
+        }
+        RoutingTrace {
+            attempts,
+            winner: self.winner,
+        }
+    }
+
+    fn push_attempt(&mut self, route: &RouteConfig) -> u32 {
+        let attempt = self.attempt;
+        self.attempt += 1;
+        self.attempts.push(AttemptTrace {
+            attempt,
+            provider_id: route.provider_id.clone(),
+            provider_token_id: route.provider_token_id.clone(),
+            model: route.model.clone(),
+            pool: route.pool.clone(),
+            outcome: AttemptOutcome::Skipped,
+            elapsed_ms: 0,
+            upstream_request_id: None,
+        });
+        attempt
+    }
+
+    fn close_attempt(&mut self, outcome: AttemptOutcome) {
+        let Some((index, started)) = self.open_attempt.take() else {
+            return;
+        };
+        let finished_at = self.open_finished_at.take();
+        let current = &mut self.attempts[index];
+        current.outcome = outcome;
+        current.elapsed_ms = elapsed_ms(started, finished_at);
+        if outcome == AttemptOutcome::Success {
+            self.winner = Some(current.attempt);
+        }
+    }
+}
+
+// 从开始到完成（未完成时为当前）的毫秒数
+fn elapsed_ms(started: Instant, finished_at: Option<Instant>) -> u64 {
+    finished_at
+        .unwrap_or_else(Instant::now)
+        .saturating_duration_since(started)
+        .as_millis() as u64
+}
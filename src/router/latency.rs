@@ -0,0 +1,133 @@
+use crate::config::LatencyRoutingConfig;
+use crate::models::{LatencyClass, RouteConfig};
+use axum::http::HeaderMap;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// 按请求优先级调整路由顺序：同等级的路由在前，未标注的居中，另一等级的在后，各自保持原有相对顺序
+pub fn prefer(configs: Vec<RouteConfig>, priority: LatencyClass) -> Vec<RouteConfig> {
+    let rank = |config: &RouteConfig| match config.latency_class {
+        Some(class) if class == priority => 0,
+        None => 1,
+        Some(_) => 2,
+    };
+    if configs.iter().all(|config| rank(config) == 1) {
+        return configs;
+    }
+    let mut configs = configs;
+    configs.sort_by_key(rank);
+    configs
+}
+
+/// 请求优先级识别和延迟目标达标统计
+pub struct LatencySlo {
+    header: String,
+    default_priority: Option<LatencyClass>,
+    interactive: SloCounter,
+    batch: SloCounter,
+}
+
+struct SloCounter {
+    target: Duration,
+    met: AtomicU64,
+    missed: AtomicU64,
+}
+
+/// 一个优先级的延迟目标达标情况
+#[derive(Debug, Clone, Serialize)]
+pub struct SloAttainment {
+    pub target_ms: u64,
+    pub met: u64,
+    pub missed: u64,
+    /// 达标比例，没有请求时为空
+    pub attainment: Option<f64>,
+}
+
+/// 各优先级的延迟目标达标情况
+#[derive(Debug, Clone, Serialize)]
+pub struct SloStatus {
+    pub interactive: SloAttainment,
+    pub batch: SloAttainment,
+}
+
+impl SloCounter {
+    fn new(target: Duration) -> Self {
+        Self {
+            target,
+            met: AtomicU64::new(0),
+            missed: AtomicU64::new(0),
+        }
+    }
+
+    fn attainment(&self) -> SloAttainment {
+        let met = self.met.load(Ordering::Relaxed);
+        let missed = self.missed.load(Ordering::Relaxed);
+        SloAttainment {
+            target_ms: self.target.as_millis() as u64,
+            met,
+            missed,
+            attainment: (met + missed > 0).then(|| met as f64 / (met + missed) as f64),
+        }
+    }
+}
+
+impl LatencySlo {
+    pub fn from_config(config: &LatencyRoutingConfig) -> Self {
+        Self {
+            header: config.header.clone(),
+            default_priority: config.default_priority,
+            interactive: SloCounter::new(config.interactive_slo),
+            batch: SloCounter::new(config.batch_slo),
+        }
+    }
+
+    /// 请求声明的优先级，未声明或无法识别时使用默认优先级
+    pub fn priority(&self, headers: &HeaderMap) -> Option<LatencyClass> {
+        let declared = headers
+            .get(self.header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_ascii_lowercase());
+        match declared.as_deref() {
+            Some("interactive") => Some(LatencyClass::Interactive),
+            Some("batch") => Some(LatencyClass::Batch),
+            _ => self.default_priority,
+        }
+    }
+
+    /// 记录一次成功请求的延迟
+    pub fn record(&self, priority: LatencyClass, elapsed: Duration) {
+        let counter = self.counter(priority);
+        let outcome = if elapsed <= counter.target {
+            counter.met.fetch_add(1, Ordering::Relaxed);
+            "met"
+        } else {
+            counter.missed.fetch_add(1, Ordering::Relaxed);
+            "missed"
+        };
+        metrics::increment_counter!(
+            "gateway_latency_slo_total",
+            "class" => priority.as_str(),
+            "outcome" => outcome
+        );
+        metrics::histogram!(
+            "gateway_latency_slo_seconds",
+            elapsed.as_secs_f64(),
+            "class" => priority.as_str()
+        );
+    }
+
+    pub fn status(&self) -> SloStatus {
+        SloStatus {
+            interactive: self.interactive.attainment(),
+            batch: self.batch.attainment(),
+        }
+    }
+
+    fn counter(&self, priority: LatencyClass) -> &SloCounter {
+        match priority {
+            LatencyClass::Interactive => &self.interactive,
+            LatencyClass::Batch => &self.batch,
+        }
+    }
+}
@@ -1,131 +1,997 @@
-use crate::cache::Cache;
-use crate::config::BusinessApiConfig;
+pub mod breaker;
+pub mod canary;
+pub mod control_plane;
+pub mod error_budget;
+pub mod failover;
+pub mod latency;
+pub mod pools;
+pub mod route_table;
+pub mod shared;
+
+use crate::business_auth::BusinessApiAuth;
+use crate::cache::{Cache, CacheEntryInfo, CacheQuery, RouteProvenance};
+use crate::config::{
+    BreakerConfig, BusinessApiConfig, CanaryRuleConfig, DefaultModelConfig, ErrorBudgetConfig,
+    RouteEnrichmentConfig, RouteResolutionMode,
+};
 use crate::error::{Error, Result};
-use crate::models::{RouteConfig, RouteRequest, RouteResponse};
+use crate::models::{
+    ClientProtocol, ContentLoggingPolicy, DefaultModelRequest, DefaultModelResponse,
+    InvalidationRequest, RouteConfig, RouteHints, RouteRequest, RouteResponse, RouteTableRequest,
+    RouteTableResponse,
+};
+use crate::router::breaker::ProviderBreaker;
+use crate::router::control_plane::{backoff_delay, ControlPlaneBreaker, ControlPlaneLimiter};
+use crate::router::error_budget::{ErrorBudget, ProviderWeight};
+use crate::router::failover::{AttemptOutcome, RoutingTrace};
+use crate::router::route_table::{RouteTable, RouteTableStatus};
+use crate::router::shared::{ProviderEvent, SharedProviderState};
+use crate::secrets::{mask_token, TokenCipher};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
 use reqwest::Client;
+use serde::Serialize;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tracing::{error, info};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+/// 默认模型缓存时长
+const DEFAULT_MODEL_TTL: Duration = Duration::from_secs(300);
+
+/// 业务API返回的令牌所属租户的缓存时长，过期后在下次缓存未命中时重新获取
+const TENANT_TTL: Duration = Duration::from_secs(3600);
+
+/// 按令牌缓存的条目数超过该数量时清理过期条目
+const PRUNE_THRESHOLD: usize = 10_000;
 
 pub struct Router {
     cache: Arc<Cache>,
     client: Client,
     business_api_config: BusinessApiConfig,
+    auth: BusinessApiAuth,
+    // 用户令牌 -> (默认模型, 缓存时间)；业务API未配置默认模型的令牌缓存为 None
+    default_models: ExpiringMap<Option<String>>,
+    // 请求未携带模型名时的默认模型配置
+    default_model: DefaultModelConfig,
+    // 用户令牌 -> (业务API返回的租户ID, 获取时间)
+    tenants: ExpiringMap<String>,
+    // 用户令牌 -> (业务API下发的对话内容记录策略, 获取时间)
+    content_logging: ExpiringMap<ContentLoggingPolicy>,
+    // 本地配置的金丝雀规则（配置了令牌加密时令牌为密文）
+    canary_rules: Vec<CanaryRuleConfig>,
+    // 供应商令牌加密器
+    token_cipher: Option<Arc<TokenCipher>>,
+    // 手动摘除的供应商令牌：provider_token_id -> 自动恢复时间（None 表示需手动恢复）
+    drained: DashMap<String, Option<DateTime<Utc>>>,
+    // 供应商熔断器
+    breaker: ProviderBreaker,
+    // 按错误率调整的供应商权重
+    error_budget: ErrorBudget,
+    // 多副本共享的摘除和熔断状态
+    shared: Option<Arc<SharedProviderState>>,
+    // 业务API熔断器
+    control_plane: ControlPlaneBreaker,
+    // 路由解析请求限速
+    control_plane_limiter: ControlPlaneLimiter,
+    // 路由表模式下的本地路由表
+    route_table: RouteTable,
+}
+
+/// 路由模块各缓存的条目数
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheSizes {
+    /// 路由缓存
+    pub routes: usize,
+    /// 默认模型缓存
+    pub default_models: usize,
+    /// 业务API返回的令牌所属租户
+    pub tenants: usize,
+}
+
+/// 被摘除的供应商令牌
+#[derive(Debug, Clone, Serialize)]
+pub struct DrainedProvider {
+    pub provider_token_id: String,
+    /// 自动恢复时间，None 表示需手动恢复
+    pub until: Option<DateTime<Utc>>,
 }
 
 impl Router {
-    pub fn new(cache: Arc<Cache>, business_api_config: BusinessApiConfig) -> Result<Self> {
+    pub fn new(
+        cache: Arc<Cache>,
+        business_api_config: BusinessApiConfig,
+        canary_rules: Vec<CanaryRuleConfig>,
+        token_cipher: Option<Arc<TokenCipher>>,
+    ) -> Result<Self> {
         let client = Client::builder()
             .timeout(business_api_config.timeout)
             .build()
-            .map_err(|e| Error::Http(e))?;
+            .map_err(Error::Http)?;
+
+        // 启用令牌加密时，配置中的明文令牌也只以密文保存在内存中；
+        // 启动时解密一次，尽早发现密钥不匹配
+        let mut canary_rules = canary_rules;
+        if let Some(cipher) = &token_cipher {
+            for rule in &mut canary_rules {
+                cipher.open(&rule.route.token).map_err(|e| {
+                    Error::Config(format!(
+                        "Failed to decrypt canary route token for model {}: {}",
+                        rule.model, e
+                    ))
+                })?;
+                rule.route.token = cipher.seal(&rule.route.token)?;
+            }
+        }
 
         Ok(Self {
             cache,
             client,
+            auth: BusinessApiAuth::new(business_api_config.auth.clone()),
+            control_plane: ControlPlaneBreaker::from_config(&business_api_config.breaker),
+            control_plane_limiter: ControlPlaneLimiter::from_config(
+                &business_api_config.rate_limit,
+            ),
             business_api_config,
+            default_models: ExpiringMap::new(),
+            default_model: DefaultModelConfig::default(),
+            tenants: ExpiringMap::new(),
+            content_logging: ExpiringMap::new(),
+            canary_rules,
+            token_cipher: token_cipher.clone(),
+            drained: DashMap::new(),
+            breaker: ProviderBreaker::from_config(&BreakerConfig::default()),
+            error_budget: ErrorBudget::from_config(&ErrorBudgetConfig::default()),
+            shared: None,
+            route_table: RouteTable::new(token_cipher),
         })
     }
 
+    /// 启用供应商熔断
+    pub fn with_breaker(mut self, config: &BreakerConfig) -> Self {
+        self.breaker = ProviderBreaker::from_config(config);
+        self
+    }
+
+    /// 启用按错误率调整供应商权重
+    pub fn with_error_budget(mut self, config: &ErrorBudgetConfig) -> Self {
+        self.error_budget = ErrorBudget::from_config(config);
+        self
+    }
+
+    /// 请求未携带模型名时的默认模型配置
+    pub fn with_default_model(mut self, config: &DefaultModelConfig) -> Self {
+        self.default_model = config.clone();
+        self
+    }
+
+    /// 通过共享状态与其他副本同步摘除和熔断状态
+    pub fn with_shared_state(mut self, shared: Option<Arc<SharedProviderState>>) -> Self {
+        self.shared = shared;
+        self
+    }
+
+    /// 路由解析请求附加字段的开关，调用方据此收集 `RouteHints`
+    pub fn route_enrichment(&self) -> &RouteEnrichmentConfig {
+        &self.business_api_config.route_enrichment
+    }
+
+    /// 业务API认证，用于校验业务API发往网关的回调
+    pub fn business_auth(&self) -> &BusinessApiAuth {
+        &self.auth
+    }
+
+    /// 解析路由，并按金丝雀规则筛选排序
+    ///
+    /// `hints` 仅在缓存未命中、需要请求业务API时使用。
+    /// `tenant` 为请求携带的租户，未携带时使用业务API返回的令牌所属租户，路由缓存按租户隔离。
     pub async fn resolve_route(
         &self,
         user_token: &str,
+        tenant: Option<&str>,
         requested_model: &str,
+        hints: &RouteHints,
     ) -> Result<Vec<RouteConfig>> {
+        let mut configs = self
+            .resolve_base_route(user_token, tenant, requested_model, hints)
+            .await?;
+
+        // 合并本地金丝雀规则（不写入缓存）
+        for rule in self
+            .canary_rules
+            .iter()
+            .filter(|rule| rule.model == requested_model)
+        {
+            let token = match &self.token_cipher {
+                Some(cipher) => match cipher.open(&rule.route.token) {
+                    Ok(token) => token,
+                    Err(e) => {
+                        error!("Skipping canary route {}: {}", rule.route.api_endpoint, e);
+                        continue;
+                    }
+                },
+                None => rule.route.token.clone(),
+            };
+            configs.push(RouteConfig {
+                token,
+                canary: Some(rule.spec.clone()),
+                ..rule.route.clone()
+            });
+        }
+
+        // 路由池内按权重排序，池之间按降级顺序
+        let configs = pools::select(configs);
+
+        let configs = canary::apply(configs, user_token, requested_model, Utc::now());
+
+        // 排除被手动摘除的供应商
+        let available: Vec<RouteConfig> = configs
+            .into_iter()
+            .filter(|c| !self.is_drained(&c.provider_token_id))
+            .collect();
+
+        if available.is_empty() {
+            return Err(Error::Routing(format!(
+                "All routes for model {} are drained",
+                requested_model
+            )));
+        }
+
+        // 排除熔断冷却中的供应商；全部处于冷却时仍按原顺序尝试，避免熔断导致整体不可用
+        let (healthy, cooling): (Vec<RouteConfig>, Vec<RouteConfig>) = available
+            .into_iter()
+            .partition(|c| !self.breaker.is_open(&c.provider_token_id));
+        if healthy.is_empty() {
+            warn!(
+                "All routes for model {} are cooling down, trying them anyway",
+                requested_model
+            );
+            return Ok(cooling);
+        }
+
+        // 按请求优先级优先使用对应延迟等级的路由
+        let healthy = match hints.priority {
+            Some(priority) => latency::prefer(healthy, priority),
+            None => healthy,
+        };
+
+        // 按错误率降权的供应商按概率排到其他路由之后
+        Ok(self.error_budget.apply(healthy))
+    }
+
+    /// 摘除供应商令牌，使其不再参与路由（用于计划内维护）
+    ///
+    /// 指定 `duration` 时到期自动恢复，否则需调用 `undrain` 手动恢复。
+    pub fn drain(&self, provider_token_id: &str, duration: Option<Duration>) {
+        let until = duration
+            .and_then(|d| chrono::Duration::from_std(d).ok())
+            .map(|d| Utc::now() + d);
+
+        info!(
+            "Provider token {} drained (until: {:?})",
+            provider_token_id, until
+        );
+        self.drained.insert(provider_token_id.to_string(), until);
+        self.publish(ProviderEvent::Drain {
+            provider_token_id: provider_token_id.to_string(),
+            until,
+        });
+    }
+
+    /// 恢复被摘除的供应商令牌，返回是否原本处于摘除状态
+    ///
+    /// 启用共享状态时总会通知其他副本，本副本未摘除时其他副本也可能已摘除
+    pub fn undrain(&self, provider_token_id: &str) -> bool {
+        let removed = self.drained.remove(provider_token_id).is_some();
+        if removed {
+            info!("Provider token {} undrained", provider_token_id);
+        }
+        self.publish(ProviderEvent::Undrain {
+            provider_token_id: provider_token_id.to_string(),
+        });
+        removed
+    }
+
+    /// 根据请求的路由追踪更新熔断状态和错误率
+    pub fn observe_trace(&self, trace: &RoutingTrace) {
+        for attempt in &trace.attempts {
+            self.observe_attempt(&attempt.provider_token_id, attempt.outcome);
+        }
+    }
+
+    /// 记录一次上游调用的结果，新熔断的供应商同步给其他副本
+    ///
+    /// 不经过路由追踪的调用（大请求体快速通道、透传、文件和批处理接口）直接调用
+    pub fn observe_attempt(&self, provider_token_id: &str, outcome: AttemptOutcome) {
+        self.error_budget.record(provider_token_id, outcome);
+        if let Some(until) = self.breaker.record(provider_token_id, outcome) {
+            warn!(
+                "Provider token {} tripped circuit breaker, cooling down until {}",
+                provider_token_id, until
+            );
+            metrics::increment_counter!("gateway_breaker_tripped_total");
+            self.publish(ProviderEvent::Cooldown {
+                provider_token_id: provider_token_id.to_string(),
+                until,
+            });
+        }
+    }
+
+    /// 应用其他副本同步过来的状态变更（不再转发）
+    pub fn apply_shared_event(&self, event: ProviderEvent) {
+        match event {
+            ProviderEvent::Drain {
+                provider_token_id,
+                until,
+            } => {
+                if until.is_some_and(|t| t <= Utc::now()) {
+                    return;
+                }
+                if self.drained.insert(provider_token_id.clone(), until) != Some(until) {
+                    info!(
+                        "Provider token {} drained by shared state (until: {:?})",
+                        provider_token_id, until
+                    );
+                }
+            }
+            ProviderEvent::Undrain { provider_token_id } => {
+                if self.drained.remove(&provider_token_id).is_some() {
+                    info!(
+                        "Provider token {} undrained by shared state",
+                        provider_token_id
+                    );
+                }
+            }
+            ProviderEvent::Cooldown {
+                provider_token_id,
+                until,
+            } => {
+                self.breaker.open_until(&provider_token_id, until);
+            }
+        }
+    }
+
+    /// 当前按错误率被降权的供应商令牌
+    pub fn downweighted_providers(&self) -> Vec<ProviderWeight> {
+        self.error_budget.downweighted()
+    }
+
+    /// 当前处于熔断冷却期的供应商令牌
+    pub fn cooling_down_providers(&self) -> Vec<DrainedProvider> {
+        self.breaker
+            .open_providers()
+            .into_iter()
+            .map(|(provider_token_id, until)| DrainedProvider {
+                provider_token_id,
+                until: Some(until),
+            })
+            .collect()
+    }
+
+    // 后台写入共享状态，失败只影响其他副本，本地状态已生效
+    fn publish(&self, event: ProviderEvent) {
+        let Some(shared) = self.shared.clone() else {
+            return;
+        };
+        tokio::spawn(async move {
+            if let Err(e) = shared.publish(&event).await {
+                error!("Failed to publish shared provider state: {}", e);
+            }
+        });
+    }
+
+    /// 当前被摘除的供应商令牌列表
+    pub fn drained_providers(&self) -> Vec<DrainedProvider> {
+        let now = Utc::now();
+        self.drained
+            .retain(|_, until| until.is_none_or(|t| t > now));
+
+        self.drained
+            .iter()
+            .map(|entry| DrainedProvider {
+                provider_token_id: entry.key().clone(),
+                until: *entry.value(),
+            })
+            .collect()
+    }
+
+    /// 按业务API的通知失效缓存，返回删除的路由缓存条目数
+    ///
+    /// 失效用户令牌时同时清除其默认模型、所属租户和内容记录策略，下次请求重新从业务API获取
+    pub async fn invalidate(&self, request: &InvalidationRequest) -> usize {
+        if request.all {
+            let removed = self.cache.len();
+            self.cache.clear().await;
+            self.default_models.clear();
+            self.tenants.clear();
+            self.content_logging.clear();
+            info!("Invalidated all cached routes ({} entries)", removed);
+            return removed;
+        }
+
+        let mut removed = 0;
+        if let Some(token) = &request.token {
+            removed += self.cache.invalidate_token(token, request.model.as_deref());
+            if request.model.is_none() {
+                self.default_models.remove(token);
+                self.tenants.remove(token);
+                self.content_logging.remove(token);
+            }
+            info!(
+                "Invalidated cached routes for token {} (model: {:?})",
+                mask_token(token),
+                request.model
+            );
+        }
+        if let Some(provider_token_id) = &request.provider_token_id {
+            removed += self.cache.invalidate_provider(provider_token_id);
+            info!(
+                "Invalidated cached routes using provider token {}",
+                provider_token_id
+            );
+        }
+        removed
+    }
+
+    /// 请求使用的缓存路由的来源信息（路由未缓存时为 None）
+    pub fn route_provenance(
+        &self,
+        user_token: &str,
+        tenant: Option<&str>,
+        requested_model: &str,
+    ) -> Option<RouteProvenance> {
+        let cache_tenant = self.cache_tenant(user_token, tenant);
+        self.cache
+            .provenance(cache_tenant.as_deref(), user_token, requested_model)
+    }
+
+    /// 按条件列出路由缓存条目
+    pub fn inspect_cache(&self, query: &CacheQuery) -> Vec<CacheEntryInfo> {
+        self.cache.inspect(query)
+    }
+
+    /// 各缓存的条目数
+    pub fn cache_sizes(&self) -> CacheSizes {
+        CacheSizes {
+            routes: self.cache.len(),
+            default_models: self.default_models.len(),
+            tenants: self.tenants.len(),
+        }
+    }
+
+    fn is_drained(&self, provider_token_id: &str) -> bool {
+        let expired = match self.drained.get(provider_token_id) {
+            None => return false,
+            Some(until) => until.is_some_and(|t| t <= Utc::now()),
+        };
+
+        // 到期自动恢复
+        if expired {
+            self.drained.remove(provider_token_id);
+            return false;
+        }
+        true
+    }
+
+    /// 业务API返回的令牌所属租户（未过期时），路由表模式下优先取路由表中的租户
+    pub fn tenant_of(&self, user_token: &str) -> Option<String> {
+        if self.table_mode() {
+            if let Some(tenant) = self.route_table.tenant_of(user_token) {
+                return Some(tenant);
+            }
+        }
+        let entry = self.tenants.get(user_token)?;
+        (entry.1.elapsed() < TENANT_TTL).then(|| entry.0.clone())
+    }
+
+    /// 业务API为令牌下发的对话内容记录策略（未过期时），路由表模式下优先取路由表中的策略
+    pub fn content_logging_of(&self, user_token: &str) -> Option<ContentLoggingPolicy> {
+        if self.table_mode() {
+            if let Some(policy) = self.route_table.content_logging_of(user_token) {
+                return Some(policy);
+            }
+        }
+        let entry = self.content_logging.get(user_token)?;
+        (entry.1.elapsed() < TENANT_TTL).then(|| entry.0.clone())
+    }
+
+    // 路由缓存使用的租户：请求携带的租户优先，其次是业务API返回的租户
+    fn cache_tenant(&self, user_token: &str, tenant: Option<&str>) -> Option<String> {
+        tenant
+            .map(str::to_string)
+            .or_else(|| self.tenant_of(user_token))
+    }
+
+    async fn resolve_base_route(
+        &self,
+        user_token: &str,
+        tenant: Option<&str>,
+        requested_model: &str,
+        hints: &RouteHints,
+    ) -> Result<Vec<RouteConfig>> {
+        // 路由表模式：首次同步完成后只从路由表解析（除非允许回退）
+        if self.table_mode() {
+            if let Some(configs) = self.route_table.lookup(user_token, requested_model) {
+                return Ok(configs);
+            }
+            if self.route_table.is_loaded()
+                && !self.business_api_config.route_table.fallback_to_request
+            {
+                return Err(Error::Routing(format!(
+                    "No route for model {} in route table",
+                    requested_model
+                )));
+            }
+        }
+
         // 1. 先查缓存
-        if let Some(configs) = self.cache.get(user_token, requested_model).await {
+        let cache_tenant = self.cache_tenant(user_token, tenant);
+        if let Some(configs) = self
+            .cache
+            .get(cache_tenant.as_deref(), user_token, requested_model)
+            .await
+        {
             if !configs.is_empty() {
                 return Ok(configs);
             }
         }
 
-        // 2. 缓存未命中，调用业务 API
-        let configs = self
-            .fetch_from_business_api(user_token, requested_model)
-            .await?;
+        // 2. 缓存未命中，调用业务 API；业务API不可用时降级使用过期不久的缓存
+        let resolved_at = Utc::now();
+        let started = Instant::now();
+        let response = match self
+            .fetch_from_business_api(user_token, requested_model, hints)
+            .await
+        {
+            Ok(response) => response,
+            Err(Error::Unavailable(reason)) => {
+                let stale = self
+                    .cache
+                    .get_stale(cache_tenant.as_deref(), user_token, requested_model)
+                    .filter(|configs| !configs.is_empty());
+                let Some(configs) = stale else {
+                    return Err(Error::Unavailable(reason));
+                };
+                warn!(
+                    "{}; serving stale routes for model {}",
+                    reason, requested_model
+                );
+                metrics::increment_counter!("gateway_route_stale_served_total");
+                return Ok(configs);
+            }
+            Err(e) => return Err(e),
+        };
+
+        if let Some(tenant_id) = response.tenant_id.filter(|t| !t.is_empty()) {
+            self.tenants
+                .insert_expiring(user_token, tenant_id, TENANT_TTL);
+        }
+        // 内容记录以业务API最新的响应为准，未返回策略即视为关闭
+        match response.content_logging {
+            Some(policy) => {
+                self.content_logging
+                    .insert_expiring(user_token, policy, TENANT_TTL);
+            }
+            None => {
+                self.content_logging.remove(user_token);
+            }
+        }
+        let configs = pools::flatten(response.data, response.pools);
 
-        // 3. 更新缓存
-        if !configs.is_empty() {
+        // 3. 更新缓存（业务API可指定缓存时长或要求不缓存）
+        let ttl = response.ttl_seconds.map(Duration::from_secs);
+        let no_cache = response.no_cache || ttl.is_some_and(|ttl| ttl.is_zero());
+        if !configs.is_empty() && !no_cache {
+            let cache_tenant = self.cache_tenant(user_token, tenant);
+            let provenance = RouteProvenance {
+                resolved_at,
+                business_api_latency_ms: started.elapsed().as_millis() as u64,
+                version: response.version,
+            };
             self.cache
-                .set(user_token, requested_model, configs.clone())
+                .set(
+                    cache_tenant.as_deref(),
+                    user_token,
+                    requested_model,
+                    configs.clone(),
+                    ttl,
+                    provenance,
+                )
                 .await;
         }
 
-        Ok(configs.clone())
+        Ok(configs)
     }
 
     async fn fetch_from_business_api(
         &self,
         user_token: &str,
         requested_model: &str,
-    ) -> Result<Vec<RouteConfig>> {
+        hints: &RouteHints,
+    ) -> Result<RouteResponse> {
         let url = format!("{}/v1/route/resolve", self.business_api_config.base_url);
 
         let request = RouteRequest {
             token: user_token.to_string(),
             model: requested_model.to_string(),
+            estimated_prompt_tokens: hints.estimated_prompt_tokens,
+            stream: hints.stream,
+            client_protocol: hints.client_protocol.clone(),
+            max_tokens: hints.max_tokens,
+            client_app: hints.client_app.clone(),
         };
 
+        // 限速：排队等待令牌，等待过久时按业务API不可用处理
+        match self.control_plane_limiter.acquire() {
+            Some(Duration::ZERO) => {}
+            Some(wait) => {
+                metrics::increment_counter!(
+                    "gateway_business_api_throttled_total",
+                    "outcome" => "delayed"
+                );
+                tokio::time::sleep(wait).await;
+            }
+            None => {
+                metrics::increment_counter!(
+                    "gateway_business_api_throttled_total",
+                    "outcome" => "rejected"
+                );
+                return Err(Error::Unavailable(
+                    "Business API rate limit exceeded".to_string(),
+                ));
+            }
+        }
+
+        let body = serde_json::to_vec(&request)?;
+        let resp = self.post_business_api(&url, body).await?;
+        if !resp.status().is_success() {
+            return Err(Error::Routing(format!(
+                "Business API returned status: {}",
+                resp.status()
+            )));
+        }
+
+        let route_response: RouteResponse = resp.json().await.map_err(Error::Http)?;
+        if route_response.success {
+            Ok(route_response)
+        } else {
+            Err(Error::Routing(format!(
+                "Business API returned error: {}",
+                route_response.message
+            )))
+        }
+    }
+
+    /// 请求业务API
+    ///
+    /// 熔断期间直接返回 `Error::Unavailable`；连接错误和5xx按指数退避（带随机抖动）重试，
+    /// 重试耗尽后计入熔断并返回 `Error::Unavailable`。其他响应原样返回，由调用方处理。
+    async fn post_business_api(&self, url: &str, body: Vec<u8>) -> Result<reqwest::Response> {
+        if !self.control_plane.allow() {
+            metrics::increment_counter!(
+                "gateway_business_api_requests_total",
+                "outcome" => "short_circuited"
+            );
+            return Err(Error::Unavailable(
+                "Business API circuit is open".to_string(),
+            ));
+        }
+
+        let config = &self.business_api_config;
         let mut retry_count = 0;
-        let max_retries = self.business_api_config.retry_attempts;
-
-        loop {
-            let response = self.client.post(&url).json(&request).send().await;
-
-            match response {
-                Ok(resp) => {
-                    if resp.status().is_success() {
-                        let route_response: RouteResponse =
-                            resp.json().await.map_err(|e| Error::Http(e))?;
-
-                        if route_response.success {
-                            return Ok(route_response.data);
-                        } else {
-                            return Err(Error::Routing(format!(
-                                "Business API returned error: {}",
-                                route_response.message
-                            )));
-                        }
-                    } else if resp.status().is_server_error() && retry_count < max_retries {
-                        retry_count += 1;
-                        tokio::time::sleep(tokio::time::Duration::from_millis(
-                            100 * retry_count as u64,
-                        ))
-                        .await;
-                        continue;
-                    } else {
-                        return Err(Error::Routing(format!(
-                            "Business API returned status: {}",
-                            resp.status()
-                        )));
-                    }
-                }
-                Err(e) if retry_count < max_retries => {
-                    retry_count += 1;
-                    error!(
-                        "Error calling business API: {}. Retrying {}/{}",
-                        e, retry_count, max_retries
+        let failure = loop {
+            let failure = match self
+                .auth
+                .apply(self.client.post(url), body.clone())
+                .send()
+                .await
+            {
+                Ok(resp) if !resp.status().is_server_error() => {
+                    self.control_plane.record_success();
+                    metrics::increment_counter!(
+                        "gateway_business_api_requests_total",
+                        "outcome" => "success"
                     );
-                    tokio::time::sleep(tokio::time::Duration::from_millis(
-                        100 * retry_count as u64,
-                    ))
-                    .await;
-                    continue;
-                }
-                Err(e) => {
-                    return Err(Error::Http(e));
+                    metrics::gauge!("gateway_business_api_available", 1.0);
+                    return Ok(resp);
                 }
+                Ok(resp) => format!("status {}", resp.status()),
+                Err(e) => e.to_string(),
+            };
+
+            if retry_count >= config.retry_attempts {
+                break failure;
             }
+            retry_count += 1;
+            let delay = backoff_delay(retry_count, config.retry_backoff, config.retry_backoff_max);
+            error!(
+                "Error calling business API: {}. Retrying {}/{} in {:?}",
+                failure, retry_count, config.retry_attempts, delay
+            );
+            tokio::time::sleep(delay).await;
+        };
+
+        metrics::increment_counter!(
+            "gateway_business_api_requests_total",
+            "outcome" => "failure"
+        );
+        if self.control_plane.record_failure() {
+            error!(
+                "Business API circuit opened for {}",
+                humantime::format_duration(config.breaker.open_duration)
+            );
+            metrics::increment_counter!("gateway_business_api_breaker_tripped_total");
+        }
+        if self.control_plane.is_open() {
+            metrics::gauge!("gateway_business_api_available", 0.0);
         }
+        Err(Error::Unavailable(format!(
+            "Business API request failed: {}",
+            failure
+        )))
+    }
+
+    /// 路由表模式下的拉取间隔，非路由表模式或只接收推送时为 None
+    pub fn route_table_sync_interval(&self) -> Option<Duration> {
+        let config = &self.business_api_config.route_table;
+        (self.table_mode() && !config.sync_interval.is_zero()).then_some(config.sync_interval)
+    }
+
+    /// 路由表状态，非路由表模式时为 None
+    pub fn route_table_status(&self) -> Option<RouteTableStatus> {
+        self.table_mode().then(|| self.route_table.status())
+    }
+
+    /// 从业务API拉取完整路由表
+    pub async fn sync_route_table(&self) -> Result<()> {
+        let url = format!("{}/v1/route/table", self.business_api_config.base_url);
+        let request = RouteTableRequest {
+            version: self.route_table.version(),
+        };
+
+        let body = serde_json::to_vec(&request)?;
+        let resp = self.post_business_api(&url, body).await?;
+        if !resp.status().is_success() {
+            metrics::increment_counter!("gateway_route_table_sync_total", "outcome" => "failure");
+            return Err(Error::Routing(format!(
+                "Business API returned status: {}",
+                resp.status()
+            )));
+        }
+
+        let response: RouteTableResponse = resp.json().await.map_err(Error::Http)?;
+        if response.success && response.not_modified && self.route_table.is_loaded() {
+            metrics::increment_counter!("gateway_route_table_sync_total", "outcome" => "not_modified");
+            return Ok(());
+        }
+        self.apply_route_table(response)?;
+        Ok(())
+    }
+
+    /// 替换本地路由表（拉取结果或业务API推送），返回令牌与模型的组合数
+    pub fn apply_route_table(&self, response: RouteTableResponse) -> Result<usize> {
+        if !self.table_mode() {
+            return Err(Error::Config("Route table mode is not enabled".to_string()));
+        }
+        if !response.success {
+            metrics::increment_counter!("gateway_route_table_sync_total", "outcome" => "failure");
+            return Err(Error::Routing(format!(
+                "Business API returned error: {}",
+                response.message
+            )));
+        }
+
+        let tokens = response.data.len();
+        let routes = self
+            .route_table
+            .replace(response.data, response.version.clone())?;
+        metrics::increment_counter!("gateway_route_table_sync_total", "outcome" => "success");
+        metrics::gauge!("gateway_route_table_routes", routes as f64);
+        info!(
+            "Route table updated: {} tokens, {} routes (version: {:?})",
+            tokens, routes, response.version
+        );
+        Ok(routes)
+    }
+
+    fn table_mode(&self) -> bool {
+        self.business_api_config.route_table.mode == RouteResolutionMode::Table
+    }
+
+    /// 请求未携带模型名时使用的模型
+    ///
+    /// 依次使用业务API（或路由表）为用户令牌配置的默认模型、配置中按客户端协议的兜底模型，
+    /// 查询失败时同样使用兜底模型。未启用默认模型时返回 None。
+    pub async fn default_model_for(
+        &self,
+        user_token: &str,
+        protocol: &ClientProtocol,
+    ) -> Option<String> {
+        if !self.default_model.enabled {
+            return None;
+        }
+        match self.resolve_default_model(user_token).await {
+            Ok(Some(model)) => return Some(model),
+            Ok(None) => {}
+            Err(e) => error!("Failed to resolve default model: {}", e),
+        }
+
+        let fallback = match protocol {
+            ClientProtocol::Anthropic => self
+                .default_model
+                .anthropic_model
+                .as_ref()
+                .or(self.default_model.model.as_ref()),
+            _ => self.default_model.model.as_ref(),
+        };
+        fallback.cloned()
+    }
+
+    /// 查询用户令牌的默认模型
+    ///
+    /// 用于请求体中未携带模型名的客户端（如 Azure SDK），结果（包括未配置默认模型）会缓存一段时间。
+    /// 业务API未配置默认模型时返回 `Ok(None)`。
+    pub async fn resolve_default_model(&self, user_token: &str) -> Result<Option<String>> {
+        if self.table_mode() {
+            if let Some(model) = self.route_table.default_model(user_token) {
+                return Ok(Some(model));
+            }
+            if self.route_table.is_loaded()
+                && !self.business_api_config.route_table.fallback_to_request
+            {
+                return Ok(None);
+            }
+        }
+
+        if let Some(entry) = self.default_models.get(user_token) {
+            if entry.1.elapsed() < DEFAULT_MODEL_TTL {
+                return Ok(entry.0.clone());
+            }
+        }
+
+        let url = format!(
+            "{}/v1/route/default_model",
+            self.business_api_config.base_url
+        );
+        let request = DefaultModelRequest {
+            token: user_token.to_string(),
+        };
+
+        let body = serde_json::to_vec(&request)?;
+        let resp = self.post_business_api(&url, body).await?;
+        if !resp.status().is_success() {
+            return Err(Error::Routing(format!(
+                "Business API returned status: {}",
+                resp.status()
+            )));
+        }
+
+        let response: DefaultModelResponse = resp.json().await?;
+        if !response.success {
+            return Err(Error::Routing(format!(
+                "Business API returned error: {}",
+                response.message
+            )));
+        }
+
+        let model = response.data.filter(|m| !m.is_empty());
+        self.default_models
+            .insert_expiring(user_token, model.clone(), DEFAULT_MODEL_TTL);
+
+        Ok(model)
     }
 
     pub async fn remove_failed_route(
         &self,
         user_token: &str,
+        tenant: Option<&str>,
         requested_model: &str,
         failed_config: &RouteConfig,
     ) {
+        // 错误预算开启时以降权代替移除，避免反复移除和重新解析造成路由抖动
+        if self.error_budget.is_enabled() {
+            return;
+        }
+        let cache_tenant = self.cache_tenant(user_token, tenant);
         self.cache
-            .remove_config(user_token, requested_model, failed_config)
+            .remove_config(
+                cache_tenant.as_deref(),
+                user_token,
+                requested_model,
+                failed_config,
+            )
             .await;
     }
 }
+
+/// 按令牌缓存、记录写入时间的映射
+///
+/// 条目过多时清理超过 TTL 未更新的条目，避免不再活跃的令牌使映射无限增长。
+/// 清理后要等条目数翻倍才会再次清理，活跃条目很多时清理开销仍按写入均摊为 O(1)。
+struct ExpiringMap<V> {
+    entries: DashMap<String, (V, Instant)>,
+    // 下次清理的条目数阈值
+    prune_at: AtomicUsize,
+}
+
+impl<V> ExpiringMap<V> {
+    fn new() -> Self {
+        Self {
+            entries: DashMap::new(),
+            prune_at: AtomicUsize::new(PRUNE_THRESHOLD),
+        }
+    }
+
+    fn insert_expiring(&self, key: &str, value: V, ttl: Duration) {
+        if self.entries.len() > self.prune_at.load(Ordering::Relaxed) {
+            self.entries
+                .retain(|_, (_, updated_at)| updated_at.elapsed() < ttl);
+            self.prune_at.store(
+                (self.entries.len() * 2).max(PRUNE_THRESHOLD),
+                Ordering::Relaxed,
+            );
+        }
+        self.entries
+            .insert(key.to_string(), (value, Instant::now()));
+    }
+}
+
+impl<V> Deref for ExpiringMap<V> {
+    type Target = DashMap<String, (V, Instant)>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prunes_expired_entries_when_full() {
+        let map = ExpiringMap::new();
+        let stale = Instant::now() - Duration::from_secs(120);
+        for i in 0..=PRUNE_THRESHOLD {
+            map.insert(format!("token-{}", i), ("tenant".to_string(), stale));
+        }
+        map.insert("active".to_string(), ("tenant".to_string(), Instant::now()));
+
+        map.insert_expiring("new", "tenant".to_string(), Duration::from_secs(60));
+
+        assert_eq!(map.len(), 2);
+        assert!(map.contains_key("active") && map.contains_key("new"));
+    }
+
+    #[test]
+    fn keeps_entries_below_threshold() {
+        let map = ExpiringMap::new();
+        let stale = Instant::now() - Duration::from_secs(120);
+        map.insert("old".to_string(), ("tenant".to_string(), stale));
+
+        map.insert_expiring("new", "tenant".to_string(), Duration::from_secs(60));
+
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn waits_for_live_entries_to_double_before_pruning_again() {
+        let map = ExpiringMap::new();
+        let ttl = Duration::from_secs(60);
+        for i in 0..=PRUNE_THRESHOLD {
+            map.insert_expiring(&format!("token-{}", i), "tenant".to_string(), ttl);
+        }
+        // 全部条目都未过期，清理后阈值提高到存活条目数的两倍
+        map.insert_expiring("next", "tenant".to_string(), ttl);
+        assert_eq!(
+            map.prune_at.load(Ordering::Relaxed),
+            (PRUNE_THRESHOLD + 1) * 2
+        );
+
+        let stale = Instant::now() - Duration::from_secs(120);
+        map.insert("stale".to_string(), ("tenant".to_string(), stale));
+        map.insert_expiring("later", "tenant".to_string(), ttl);
+        assert!(map.contains_key("stale"));
+    }
+}
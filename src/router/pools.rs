@@ -0,0 +1,62 @@
+use crate::models::{RouteConfig, RoutePool};
+use rand::Rng;
+
+/// 将业务API按池返回的路由展开为有序列表：各池按返回顺序依次降级，池内路由标注所属池名，
+/// 未分组的路由排在所有池之后
+pub fn flatten(data: Vec<RouteConfig>, pools: Vec<RoutePool>) -> Vec<RouteConfig> {
+    if pools.is_empty() {
+        return data;
+    }
+    let mut configs =
+        Vec::with_capacity(data.len() + pools.iter().map(|p| p.routes.len()).sum::<usize>());
+    for pool in pools {
+        configs.extend(pool.routes.into_iter().map(|route| RouteConfig {
+            pool: Some(pool.name.clone()),
+            ..route
+        }));
+    }
+    configs.extend(data);
+    configs
+}
+
+/// 池内按权重排序：连续属于同一池的路由按权重随机排列（权重越高越可能排在前面，
+/// 权重为0的排在池末尾），池之间和未分组路由保持原有顺序
+pub fn select(configs: Vec<RouteConfig>) -> Vec<RouteConfig> {
+    if configs.iter().all(|config| config.pool.is_none()) {
+        return configs;
+    }
+
+    let mut rng = rand::thread_rng();
+    let mut selected = Vec::with_capacity(configs.len());
+    let mut group: Vec<RouteConfig> = Vec::new();
+    for config in configs {
+        if group
+            .last()
+            .is_some_and(|last| last.pool.is_none() || last.pool != config.pool)
+        {
+            selected.extend(shuffle(std::mem::take(&mut group), &mut rng));
+        }
+        group.push(config);
+    }
+    selected.extend(shuffle(group, &mut rng));
+    selected
+}
+
+// 加权随机排列：每条路由取 u^(1/w) 作为排序键，键越大越靠前
+fn shuffle(group: Vec<RouteConfig>, rng: &mut impl Rng) -> Vec<RouteConfig> {
+    if group.len() < 2 || group[0].pool.is_none() {
+        return group;
+    }
+    let mut keyed: Vec<(f64, RouteConfig)> = group
+        .into_iter()
+        .map(|config| {
+            let key = match config.weight.unwrap_or(1) {
+                0 => -1.0,
+                weight => rng.gen::<f64>().powf(1.0 / weight as f64),
+            };
+            (key, config)
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+    keyed.into_iter().map(|(_, config)| config).collect()
+}
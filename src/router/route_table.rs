@@ -0,0 +1,198 @@
+use crate::error::Result;
+use crate::models::{ContentLoggingPolicy, RouteConfig, RouteTableEntry};
+use crate::router::Router;
+use crate::secrets::TokenCipher;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use tracing::{error, warn};
+
+/// 本地路由表
+///
+/// 每次同步整体替换，读取时不加写锁。配置了令牌加密时供应商令牌以密文保存。
+pub struct RouteTable {
+    cipher: Option<Arc<TokenCipher>>,
+    snapshot: RwLock<Option<Arc<Snapshot>>>,
+}
+
+struct Snapshot {
+    // 用户令牌 -> 路由
+    entries: HashMap<String, TableEntry>,
+    version: Option<String>,
+    synced_at: DateTime<Utc>,
+    routes: usize,
+}
+
+struct TableEntry {
+    tenant_id: Option<String>,
+    default_model: Option<String>,
+    content_logging: Option<ContentLoggingPolicy>,
+    // 模型名 -> 路由配置列表
+    routes: HashMap<String, Vec<RouteConfig>>,
+}
+
+/// 路由表状态
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteTableStatus {
+    /// 是否已完成首次同步
+    pub loaded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub synced_at: Option<DateTime<Utc>>,
+    /// 用户令牌数
+    pub tokens: usize,
+    /// 令牌与模型的组合数
+    pub routes: usize,
+}
+
+impl RouteTable {
+    pub fn new(cipher: Option<Arc<TokenCipher>>) -> Self {
+        Self {
+            cipher,
+            snapshot: RwLock::new(None),
+        }
+    }
+
+    /// 是否已完成首次同步
+    pub fn is_loaded(&self) -> bool {
+        self.snapshot.read().unwrap().is_some()
+    }
+
+    /// 当前路由表版本
+    pub fn version(&self) -> Option<String> {
+        self.current()?.version.clone()
+    }
+
+    /// 整体替换路由表，返回令牌与模型的组合数
+    pub fn replace(&self, entries: Vec<RouteTableEntry>, version: Option<String>) -> Result<usize> {
+        let mut table = HashMap::with_capacity(entries.len());
+        let mut routes = 0;
+        for entry in entries {
+            let mut models = HashMap::with_capacity(entry.routes.len());
+            for (model, configs) in entry.routes {
+                if configs.is_empty() {
+                    continue;
+                }
+                models.insert(model, self.seal_tokens(configs)?);
+            }
+            routes += models.len();
+            table.insert(
+                entry.token,
+                TableEntry {
+                    tenant_id: entry.tenant_id.filter(|t| !t.is_empty()),
+                    default_model: entry.default_model.filter(|m| !m.is_empty()),
+                    content_logging: entry.content_logging,
+                    routes: models,
+                },
+            );
+        }
+
+        *self.snapshot.write().unwrap() = Some(Arc::new(Snapshot {
+            entries: table,
+            version,
+            synced_at: Utc::now(),
+            routes,
+        }));
+        Ok(routes)
+    }
+
+    /// 查询令牌访问模型的路由
+    pub fn lookup(&self, user_token: &str, model: &str) -> Option<Vec<RouteConfig>> {
+        let snapshot = self.current()?;
+        let configs = snapshot.entries.get(user_token)?.routes.get(model)?;
+        self.open_tokens(configs.clone())
+    }
+
+    /// 路由表中令牌所属的租户
+    pub fn tenant_of(&self, user_token: &str) -> Option<String> {
+        self.current()?.entries.get(user_token)?.tenant_id.clone()
+    }
+
+    /// 路由表中令牌的默认模型
+    pub fn default_model(&self, user_token: &str) -> Option<String> {
+        self.current()?
+            .entries
+            .get(user_token)?
+            .default_model
+            .clone()
+    }
+
+    /// 路由表中令牌的对话内容记录策略
+    pub fn content_logging_of(&self, user_token: &str) -> Option<ContentLoggingPolicy> {
+        self.current()?
+            .entries
+            .get(user_token)?
+            .content_logging
+            .clone()
+    }
+
+    pub fn status(&self) -> RouteTableStatus {
+        match self.current() {
+            Some(snapshot) => RouteTableStatus {
+                loaded: true,
+                version: snapshot.version.clone(),
+                synced_at: Some(snapshot.synced_at),
+                tokens: snapshot.entries.len(),
+                routes: snapshot.routes,
+            },
+            None => RouteTableStatus {
+                loaded: false,
+                version: None,
+                synced_at: None,
+                tokens: 0,
+                routes: 0,
+            },
+        }
+    }
+
+    fn current(&self) -> Option<Arc<Snapshot>> {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    fn seal_tokens(&self, mut configs: Vec<RouteConfig>) -> Result<Vec<RouteConfig>> {
+        if let Some(cipher) = &self.cipher {
+            for config in &mut configs {
+                config.token = cipher.seal(&config.token)?;
+            }
+        }
+        Ok(configs)
+    }
+
+    // 解密令牌，失败时返回 None
+    fn open_tokens(&self, mut configs: Vec<RouteConfig>) -> Option<Vec<RouteConfig>> {
+        let Some(cipher) = &self.cipher else {
+            return Some(configs);
+        };
+        for config in &mut configs {
+            match cipher.open(&config.token) {
+                Ok(token) => config.token = token,
+                Err(e) => {
+                    warn!("Dropping route table entry: {}", e);
+                    return None;
+                }
+            }
+        }
+        Some(configs)
+    }
+}
+
+/// 路由表模式下启动定期拉取，首次拉取立即执行
+pub fn spawn_sync(router: Arc<Router>) {
+    let Some(interval) = router.route_table_sync_interval() else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+            if let Err(e) = router.sync_route_table().await {
+                error!("Route table sync failed: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,197 @@
+use crate::config::SharedStateConfig;
+use crate::error::{Error, Result};
+use crate::router::Router;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// 订阅连接断开后的重连间隔
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/// 副本间同步的供应商状态变更
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ProviderEvent {
+    /// 手动摘除，`until` 为空表示需手动恢复
+    Drain {
+        provider_token_id: String,
+        until: Option<DateTime<Utc>>,
+    },
+    /// 手动恢复
+    Undrain { provider_token_id: String },
+    /// 熔断冷却
+    Cooldown {
+        provider_token_id: String,
+        until: DateTime<Utc>,
+    },
+}
+
+impl ProviderEvent {
+    fn provider_token_id(&self) -> &str {
+        match self {
+            Self::Drain {
+                provider_token_id, ..
+            }
+            | Self::Undrain { provider_token_id }
+            | Self::Cooldown {
+                provider_token_id, ..
+            } => provider_token_id,
+        }
+    }
+}
+
+/// 基于 Redis 的多副本共享供应商状态
+///
+/// 摘除和熔断状态写入 Redis 键（带与恢复时间一致的过期时间），同时通过 pub/sub 通知其他副本；
+/// 副本启动或订阅重连时从键中加载现有状态，因此状态在副本重启后依然保留。
+/// 运行中 Redis 不可用时各副本退化为只使用本地状态，恢复后自动重新订阅并加载。
+pub struct SharedProviderState {
+    client: redis::Client,
+    conn: ConnectionManager,
+    key_prefix: String,
+}
+
+impl SharedProviderState {
+    pub async fn connect(config: &SharedStateConfig) -> Result<Self> {
+        let client = redis::Client::open(config.redis_url.as_str())
+            .map_err(|e| Error::Config(format!("Invalid shared_state.redis_url: {}", e)))?;
+        let conn = client.get_connection_manager().await?;
+
+        info!("Shared provider state connected to Redis");
+        Ok(Self {
+            client,
+            conn,
+            key_prefix: config.key_prefix.clone(),
+        })
+    }
+
+    /// 保存状态变更并通知其他副本
+    pub async fn publish(&self, event: &ProviderEvent) -> Result<()> {
+        let mut conn = self.conn.clone();
+        let payload = serde_json::to_string(event)?;
+
+        match event {
+            ProviderEvent::Drain { until: None, .. } => {
+                conn.set::<_, _, ()>(self.drain_key(event.provider_token_id()), &payload)
+                    .await?;
+            }
+            ProviderEvent::Drain {
+                until: Some(until), ..
+            } => {
+                let Some(ttl) = ttl_millis(*until) else {
+                    return Ok(());
+                };
+                conn.pset_ex::<_, _, ()>(self.drain_key(event.provider_token_id()), &payload, ttl)
+                    .await?;
+            }
+            ProviderEvent::Undrain { provider_token_id } => {
+                conn.del::<_, ()>(self.drain_key(provider_token_id)).await?;
+            }
+            ProviderEvent::Cooldown {
+                provider_token_id,
+                until,
+            } => {
+                let Some(ttl) = ttl_millis(*until) else {
+                    return Ok(());
+                };
+                conn.pset_ex::<_, _, ()>(self.cooldown_key(provider_token_id), &payload, ttl)
+                    .await?;
+            }
+        }
+
+        conn.publish::<_, _, ()>(self.channel(), &payload).await?;
+        Ok(())
+    }
+
+    /// 读取 Redis 中保存的全部状态
+    async fn load(&self) -> Result<Vec<ProviderEvent>> {
+        let mut conn = self.conn.clone();
+        let mut keys = Vec::new();
+        {
+            let mut iter = conn
+                .scan_match::<_, String>(format!("{}provider:*", self.key_prefix))
+                .await?;
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+        }
+
+        let mut events = Vec::new();
+        for key in keys {
+            // 键可能在扫描后过期
+            let Some(payload) = conn.get::<_, Option<String>>(&key).await? else {
+                continue;
+            };
+            match serde_json::from_str(&payload) {
+                Ok(event) => events.push(event),
+                Err(e) => warn!("Ignoring malformed shared provider state {}: {}", key, e),
+            }
+        }
+        Ok(events)
+    }
+
+    fn drain_key(&self, provider_token_id: &str) -> String {
+        format!("{}provider:drain:{}", self.key_prefix, provider_token_id)
+    }
+
+    fn cooldown_key(&self, provider_token_id: &str) -> String {
+        format!("{}provider:cooldown:{}", self.key_prefix, provider_token_id)
+    }
+
+    fn channel(&self) -> String {
+        format!("{}provider-events", self.key_prefix)
+    }
+}
+
+// 距恢复时间的毫秒数，已过期时返回 None
+fn ttl_millis(until: DateTime<Utc>) -> Option<u64> {
+    let ttl = (until - Utc::now()).num_milliseconds();
+    (ttl > 0).then_some(ttl as u64)
+}
+
+/// 启动后台任务：加载共享状态并订阅其他副本的变更，断线后自动重连并重新加载
+pub fn spawn_subscriber(shared: Arc<SharedProviderState>, router: Arc<Router>) {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = subscribe(&shared, &router).await {
+                error!("Shared provider state subscription failed: {}", e);
+            } else {
+                warn!("Shared provider state subscription closed, reconnecting");
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    });
+}
+
+async fn subscribe(shared: &SharedProviderState, router: &Router) -> Result<()> {
+    let mut pubsub = shared.client.get_async_pubsub().await?;
+    pubsub.subscribe(shared.channel()).await?;
+
+    // 先订阅再加载，避免错过两者之间的变更
+    let events = shared.load().await?;
+    info!("Loaded {} shared provider state entries", events.len());
+    for event in events {
+        router.apply_shared_event(event);
+    }
+
+    let mut messages = pubsub.on_message();
+    while let Some(msg) = messages.next().await {
+        let payload: String = match msg.get_payload() {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("Ignoring non-text shared provider event: {}", e);
+                continue;
+            }
+        };
+        match serde_json::from_str(&payload) {
+            Ok(event) => router.apply_shared_event(event),
+            Err(e) => warn!("Ignoring malformed shared provider event: {}", e),
+        }
+    }
+    Ok(())
+}
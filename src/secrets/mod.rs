@@ -0,0 +1,182 @@
+use crate::config::TokenEncryptionConfig;
+use crate::error::{Error, Result};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use subtle::ConstantTimeEq;
+
+/// 加密令牌的前缀，配置文件中以此前缀开头的令牌视为密文
+pub const SEALED_PREFIX: &str = "enc:v1:";
+
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+const WRAPPED_KEY_LEN: usize = KEY_LEN + TAG_LEN;
+
+/// 供应商令牌的信封加密
+///
+/// 每个令牌使用随机生成的数据密钥（AES-256-GCM）加密，数据密钥再由主密钥加密后
+/// 与密文一起保存。主密钥只在进程内存中以密钥调度形式存在，令牌明文只在转发前短暂出现。
+///
+/// 密文格式：`enc:v1:` + hex(主密钥nonce | 加密后的数据密钥 | 数据nonce | 令牌密文)
+pub struct TokenCipher {
+    master: Aes256Gcm,
+}
+
+impl TokenCipher {
+    /// 按配置从环境变量或密钥文件读取主密钥（64位十六进制，即32字节）
+    pub fn from_config(config: &TokenEncryptionConfig) -> Result<Self> {
+        let encoded = match (&config.key_env, &config.key_file) {
+            (Some(name), None) => std::env::var(name).map_err(|_| {
+                Error::Config(format!("Token encryption key variable {} is not set", name))
+            })?,
+            (None, Some(path)) => std::fs::read_to_string(path).map_err(|e| {
+                Error::Config(format!(
+                    "Failed to read token encryption key file {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?,
+            _ => {
+                return Err(Error::Config(
+                    "token_encryption requires exactly one of key_env or key_file".to_string(),
+                ))
+            }
+        };
+
+        Self::from_hex(encoded.trim())
+    }
+
+    /// 以十六进制编码的32字节主密钥创建
+    pub fn from_hex(encoded: &str) -> Result<Self> {
+        let key = hex::decode(encoded)
+            .ok()
+            .filter(|key| key.len() == KEY_LEN)
+            .ok_or_else(|| {
+                Error::Config("Token encryption key must be 64 hex characters".to_string())
+            })?;
+
+        Ok(Self {
+            master: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        })
+    }
+
+    /// 令牌是否为本模块生成的密文
+    pub fn is_sealed(token: &str) -> bool {
+        token.starts_with(SEALED_PREFIX)
+    }
+
+    /// 加密令牌，已是密文的令牌原样返回
+    pub fn seal(&self, token: &str) -> Result<String> {
+        if Self::is_sealed(token) {
+            return Ok(token.to_string());
+        }
+
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        let key_nonce = Aes256Gcm::generate_nonce(OsRng);
+        let wrapped_key = self
+            .master
+            .encrypt(&key_nonce, data_key.as_slice())
+            .map_err(|_| Error::Crypto("Failed to wrap data key".to_string()))?;
+
+        let data_nonce = Aes256Gcm::generate_nonce(OsRng);
+        let ciphertext = Aes256Gcm::new(&data_key)
+            .encrypt(&data_nonce, token.as_bytes())
+            .map_err(|_| Error::Crypto("Failed to encrypt token".to_string()))?;
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN * 2 + WRAPPED_KEY_LEN + ciphertext.len());
+        sealed.extend_from_slice(&key_nonce);
+        sealed.extend_from_slice(&wrapped_key);
+        sealed.extend_from_slice(&data_nonce);
+        sealed.extend_from_slice(&ciphertext);
+
+        Ok(format!("{}{}", SEALED_PREFIX, hex::encode(sealed)))
+    }
+
+    /// 解密令牌，非密文的令牌原样返回
+    pub fn open(&self, token: &str) -> Result<String> {
+        let Some(encoded) = token.strip_prefix(SEALED_PREFIX) else {
+            return Ok(token.to_string());
+        };
+
+        let invalid = || Error::Crypto("Malformed encrypted token".to_string());
+        let sealed = hex::decode(encoded).map_err(|_| invalid())?;
+        if sealed.len() < NONCE_LEN * 2 + WRAPPED_KEY_LEN + TAG_LEN {
+            return Err(invalid());
+        }
+
+        let (key_nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (wrapped_key, rest) = rest.split_at(WRAPPED_KEY_LEN);
+        let (data_nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        let data_key = self
+            .master
+            .decrypt(Nonce::from_slice(key_nonce), wrapped_key)
+            .map_err(|_| Error::Crypto("Failed to unwrap data key (wrong key?)".to_string()))?;
+        let plaintext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key))
+            .decrypt(Nonce::from_slice(data_nonce), ciphertext)
+            .map_err(|_| Error::Crypto("Failed to decrypt token".to_string()))?;
+
+        String::from_utf8(plaintext).map_err(|_| invalid())
+    }
+}
+
+/// 脱敏令牌，只保留前后各4位，用于日志和 Debug 输出
+pub fn mask_token(token: &str) -> String {
+    if token.len() > 8 && token.is_char_boundary(4) && token.is_char_boundary(token.len() - 4) {
+        format!("{}...{}", &token[..4], &token[token.len() - 4..])
+    } else {
+        "***".to_string()
+    }
+}
+
+/// 以恒定时间比较令牌，避免通过响应耗时逐字节猜出管理令牌等凭据（长度不同时直接返回 false）
+pub fn token_eq(provided: &str, expected: &str) -> bool {
+    provided.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_tokens() {
+        assert!(token_eq("admin-token", "admin-token"));
+        assert!(!token_eq("admin-tokem", "admin-token"));
+        assert!(!token_eq("admin", "admin-token"));
+        assert!(!token_eq("", "admin-token"));
+    }
+
+    #[test]
+    fn masks_tokens() {
+        assert_eq!(mask_token("sk-abcdefghijkl"), "sk-a...ijkl");
+        assert_eq!(mask_token("short"), "***");
+        // JWT 声明可能含多字节字符，截取位置不在字符边界时整体隐藏
+        assert_eq!(mask_token("用户-abcdefgh"), "***");
+        assert_eq!(mask_token("abcdefgh-用户"), "***");
+        assert_eq!(mask_token("abcd-用户-efgh"), "abcd...efgh");
+    }
+
+    #[test]
+    fn seals_and_opens_tokens() {
+        let cipher = TokenCipher::from_hex(&"11".repeat(KEY_LEN)).unwrap();
+        let sealed = cipher.seal("sk-provider").unwrap();
+        assert!(TokenCipher::is_sealed(&sealed));
+        assert_eq!(cipher.seal(&sealed).unwrap(), sealed);
+        assert_eq!(cipher.open(&sealed).unwrap(), "sk-provider");
+        assert_eq!(cipher.open("sk-plain").unwrap(), "sk-plain");
+    }
+
+    #[test]
+    fn reports_crypto_errors() {
+        let sealed = TokenCipher::from_hex(&"11".repeat(KEY_LEN))
+            .unwrap()
+            .seal("sk-provider")
+            .unwrap();
+        let other = TokenCipher::from_hex(&"22".repeat(KEY_LEN)).unwrap();
+        assert!(matches!(other.open(&sealed), Err(Error::Crypto(_))));
+        assert!(matches!(
+            other.open("enc:v1:zz"),
+            Err(Error::Crypto(msg)) if msg == "Malformed encrypted token"
+        ));
+    }
+}
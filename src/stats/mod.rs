@@ -0,0 +1,197 @@
+use crate::error::Result;
+use crate::models::RouteConfig;
+use bytes::Bytes;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// 网关运行时统计
+///
+/// 只在进程内计数，供 `/admin/stats` 快速查看运行状态，无需依赖指标系统。
+pub struct RuntimeStats {
+    started_at: Instant,
+    // 正在处理的请求数（流式请求在开始向客户端输出后即视为处理完毕）
+    in_flight: Arc<AtomicUsize>,
+    // 正在向客户端输出的流式响应数
+    active_streams: Arc<AtomicUsize>,
+    route_attempts: DashMap<RouteKey, RouteCounters>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct RouteKey {
+    api: String,
+    model: String,
+    provider_token_id: String,
+}
+
+#[derive(Default)]
+struct RouteCounters {
+    attempts: AtomicU64,
+    failures: AtomicU64,
+}
+
+/// 单个路由的尝试次数
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteAttempts {
+    pub api: String,
+    pub model: String,
+    pub provider_token_id: String,
+    pub attempts: u64,
+    pub failures: u64,
+}
+
+/// 计数守卫，释放时计数减一
+pub struct ActivityGuard(Arc<AtomicUsize>);
+
+impl ActivityGuard {
+    fn new(counter: &Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter.clone())
+    }
+}
+
+impl Drop for ActivityGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for RuntimeStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RuntimeStats {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            active_streams: Arc::new(AtomicUsize::new(0)),
+            route_attempts: DashMap::new(),
+        }
+    }
+
+    /// 进程启动以来的秒数
+    pub fn uptime_secs(&self) -> u64 {
+        self.started_at.elapsed().as_secs()
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn active_streams(&self) -> usize {
+        self.active_streams.load(Ordering::Relaxed)
+    }
+
+    /// 开始处理一个请求，返回的守卫释放时计数减一
+    pub fn request_started(&self) -> ActivityGuard {
+        ActivityGuard::new(&self.in_flight)
+    }
+
+    /// 包装流式响应，流结束或客户端断开前计为活跃流，并持有令牌的流式名额
+    pub fn track_stream(
+        &self,
+        stream: Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>>,
+        slot: Option<StreamSlot>,
+    ) -> Pin<Box<dyn Stream<Item = Result<Bytes>> + Send>> {
+        let guard = ActivityGuard::new(&self.active_streams);
+        Box::pin(stream.map(move |item| {
+            let _active = (&guard, &slot);
+            item
+        }))
+    }
+
+    /// 记录一次路由尝试
+    pub fn record_attempt(&self, route: &RouteConfig, success: bool) {
+        let key = RouteKey {
+            api: route.api_endpoint.clone(),
+            model: route.model.clone(),
+            provider_token_id: route.provider_token_id.clone(),
+        };
+        let counters = self.route_attempts.entry(key).or_default();
+        counters.attempts.fetch_add(1, Ordering::Relaxed);
+        if !success {
+            counters.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// 各路由的尝试次数，按尝试次数降序
+    pub fn route_attempts(&self) -> Vec<RouteAttempts> {
+        let mut routes: Vec<RouteAttempts> = self
+            .route_attempts
+            .iter()
+            .map(|entry| RouteAttempts {
+                api: entry.key().api.clone(),
+                model: entry.key().model.clone(),
+                provider_token_id: entry.key().provider_token_id.clone(),
+                attempts: entry.attempts.load(Ordering::Relaxed),
+                failures: entry.failures.load(Ordering::Relaxed),
+            })
+            .collect();
+        routes.sort_by_key(|r| std::cmp::Reverse(r.attempts));
+        routes
+    }
+}
+
+/// 按用户令牌限制同时打开的流式响应数
+///
+/// 单个失控的客户端同时打开大量流会耗尽上游并发和文件描述符，
+/// 名额在路由前占用，直到流结束或客户端断开才释放。
+pub struct StreamLimiter {
+    max_per_token: usize,
+    // Key: 用户令牌, Value: 打开中的流式响应数
+    open: Arc<DashMap<String, usize>>,
+}
+
+/// 流式响应名额，释放时计数减一
+pub struct StreamSlot {
+    open: Arc<DashMap<String, usize>>,
+    token: String,
+}
+
+impl StreamLimiter {
+    pub fn new(max_per_token: usize) -> Self {
+        Self {
+            max_per_token,
+            open: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// 占用一个流式响应名额，令牌已达上限时返回 None
+    pub fn try_acquire(&self, token: &str) -> Option<StreamSlot> {
+        let mut count = self.open.entry(token.to_string()).or_insert(0);
+        if *count >= self.max_per_token {
+            return None;
+        }
+        *count += 1;
+
+        Some(StreamSlot {
+            open: self.open.clone(),
+            token: token.to_string(),
+        })
+    }
+
+    /// 当前有打开中流式响应的令牌数
+    pub fn streaming_tokens(&self) -> usize {
+        self.open.len()
+    }
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        // 计数归零时移除条目，避免内存随令牌数增长
+        if let Entry::Occupied(mut entry) = self.open.entry(std::mem::take(&mut self.token)) {
+            *entry.get_mut() -= 1;
+            if *entry.get() == 0 {
+                entry.remove();
+            }
+        }
+    }
+}
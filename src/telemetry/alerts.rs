@@ -0,0 +1,100 @@
+use super::usage_stats::UsageStats;
+use crate::config::AlertsConfig;
+use crate::models::{AlertEvent, AlertScope, UsageEvent};
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::time::Instant;
+
+/// 告警记录超过该数量时清理过期条目
+const FIRED_PRUNE_THRESHOLD: usize = 10_000;
+
+/// 费用阈值告警
+///
+/// 每次记录使用量后检查本次请求涉及的令牌、供应商及全局在窗口内的估算费用，
+/// 超过阈值即生成告警。同一对象在一个窗口内只告警一次，避免持续超限时重复推送。
+pub struct SpendAlerts {
+    config: AlertsConfig,
+    // 各对象最近一次告警的时间
+    fired: DashMap<(AlertScope, String), Instant>,
+}
+
+impl SpendAlerts {
+    /// 未配置任何阈值时返回 None
+    pub fn new(config: &AlertsConfig) -> Option<Self> {
+        config.is_enabled().then(|| Self {
+            config: config.clone(),
+            fired: DashMap::new(),
+        })
+    }
+
+    /// 告警推送地址
+    pub fn webhook_url(&self) -> Option<&str> {
+        self.config.webhook_url.as_deref()
+    }
+
+    /// 检查本次使用量记录后是否有阈值被超过
+    pub fn check(&self, stats: &UsageStats, event: &UsageEvent) -> Vec<AlertEvent> {
+        let spend = stats.spend(self.config.window, &event.token, &event.provider_id);
+
+        [
+            (
+                AlertScope::Token,
+                &event.token,
+                self.config.per_token,
+                spend.token,
+            ),
+            (
+                AlertScope::Provider,
+                &event.provider_id,
+                self.config.per_provider,
+                spend.provider,
+            ),
+            (
+                AlertScope::Global,
+                &String::new(),
+                self.config.global,
+                spend.global,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(scope, subject, threshold, spend)| {
+            let threshold = threshold?;
+            if spend < threshold || !self.try_fire(scope, subject) {
+                return None;
+            }
+
+            Some(AlertEvent {
+                alert_id: uuid::Uuid::new_v4().to_string(),
+                scope,
+                token: (scope == AlertScope::Token).then(|| event.token.clone()),
+                provider_id: (scope == AlertScope::Provider).then(|| event.provider_id.clone()),
+                tenant_id: event.tenant_id.clone(),
+                threshold,
+                spend,
+                window_secs: self.config.window.as_secs(),
+                triggered_at: chrono::Utc::now(),
+            })
+        })
+        .collect()
+    }
+
+    /// 窗口内尚未告警过则记录本次告警并返回 true
+    fn try_fire(&self, scope: AlertScope, subject: &str) -> bool {
+        let window = self.config.window;
+        if self.fired.len() > FIRED_PRUNE_THRESHOLD {
+            self.fired.retain(|_, fired_at| fired_at.elapsed() < window);
+        }
+
+        match self.fired.entry((scope, subject.to_string())) {
+            Entry::Occupied(entry) if entry.get().elapsed() < window => false,
+            Entry::Occupied(mut entry) => {
+                entry.insert(Instant::now());
+                true
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(Instant::now());
+                true
+            }
+        }
+    }
+}
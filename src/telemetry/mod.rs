@@ -1,48 +1,521 @@
+pub mod alerts;
+pub mod prometheus;
+pub mod queue;
+pub mod route_health;
+pub mod usage_stats;
+
+use crate::business_auth::BusinessApiAuth;
+use crate::config::{
+    AlertsConfig, BusinessApiAuthConfig, TelemetryConfig, TelemetryEventConfig, UsageStatsConfig,
+};
 use crate::error::{Error, Result};
-use crate::models::{ErrorEvent, UsageEvent};
-use reqwest::Client;
+use crate::ledger::{EventKind, Ledger};
+use crate::models::{AlertEvent, ErrorEvent, UsageEvent};
+use crate::router::failover::RoutingTrace;
+use crate::secrets::mask_token;
+use alerts::SpendAlerts;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use queue::DropOldestQueue;
+use rand::Rng;
+use reqwest::{Client, RequestBuilder};
+use route_health::RouteHealthTracker;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Semaphore;
 use tokio::time::Duration;
+use tracing::{debug, info, warn};
+use usage_stats::UsageStats;
 
 pub struct TelemetryModule {
     client: Client,
-    business_api_url: String,
+    auth: BusinessApiAuth,
+    // 各类事件的上报地址，关闭上报的事件为 None
+    usage_url: Option<String>,
+    error_url: Option<String>,
+    alert_url: Option<String>,
+    // 错误事件采样比例
+    error_sample_rate: f64,
+    // 本地滚动统计，供管理接口查询
+    usage_stats: UsageStats,
+    // 已上报usage的请求ID，保证每个请求最多上报一次
+    reported_requests: DashMap<String, Instant>,
+    // 下次清理去重记录的条目数阈值
+    reported_prune_at: AtomicUsize,
+    // 本地账本（可选），记录事件并在业务API确认后标记
+    ledger: Option<Arc<Ledger>>,
+    // 费用告警（未配置阈值时为 None）
+    alerts: Option<SpendAlerts>,
+    // 待上报事件队列，由后台任务按并发上限发送
+    queue: Arc<DropOldestQueue<Job>>,
+    // 路由健康报告（未开启时为 None）
+    route_health: Option<RouteHealthReporter>,
+}
+
+struct RouteHealthReporter {
+    tracker: RouteHealthTracker,
+    url: String,
+    interval: Duration,
+}
+
+/// 待发送的上报请求
+enum Job {
+    /// 遥测事件，启用账本时先写入账本
+    Event {
+        kind: EventKind,
+        request_id: String,
+        model: String,
+        body: Vec<u8>,
+        request: RequestBuilder,
+    },
+    /// 告警推送
+    Webhook { request: RequestBuilder },
+    /// 路由健康报告，不写入账本
+    RouteHealth { request: RequestBuilder },
+}
+
+impl Job {
+    // 指标标签
+    fn kind(&self) -> &'static str {
+        match self {
+            Job::Event { kind, .. } => kind.as_str(),
+            Job::Webhook { .. } => "webhook",
+            Job::RouteHealth { .. } => "route_health",
+        }
+    }
+
+    async fn run(self, ledger: Option<&Ledger>) {
+        match self {
+            Job::Event {
+                kind,
+                request_id,
+                model,
+                body,
+                request,
+            } => {
+                let entry_id = match ledger {
+                    Some(ledger) => match ledger.record(kind, &request_id, &model, &body).await {
+                        Ok(id) => Some(id),
+                        Err(e) => {
+                            warn!(
+                                "Failed to record {:?} event {} in ledger: {}",
+                                kind, request_id, e
+                            );
+                            None
+                        }
+                    },
+                    None => None,
+                };
+
+                let acknowledged =
+                    matches!(request.send().await, Ok(resp) if resp.status().is_success());
+
+                if let (Some(ledger), Some(id), true) = (ledger, &entry_id, acknowledged) {
+                    if let Err(e) = ledger.acknowledge(id).await {
+                        warn!("Failed to acknowledge ledger entry {}: {}", id, e);
+                    }
+                }
+            }
+            Job::Webhook { request } => match request.send().await {
+                Ok(resp) if !resp.status().is_success() => {
+                    warn!("Alert webhook returned {}", resp.status())
+                }
+                Err(e) => warn!("Failed to deliver alert webhook: {}", e),
+                Ok(_) => {}
+            },
+            Job::RouteHealth { request } => match request.send().await {
+                Ok(resp) if !resp.status().is_success() => {
+                    warn!("Route health report returned {}", resp.status())
+                }
+                Err(e) => warn!("Failed to deliver route health report: {}", e),
+                Ok(_) => {}
+            },
+        }
+    }
 }
 
+/// 路由健康报告的业务API默认上报路径
+const ROUTE_HEALTH_PATH: &str = "/v1/telemetry/route_health";
+/// 去重记录保留时长
+const REPORTED_REQUEST_TTL: Duration = Duration::from_secs(600);
+/// 去重记录超过该数量时清理过期条目，清理后要等条目数翻倍才会再次清理
+const REPORTED_REQUEST_PRUNE_THRESHOLD: usize = 10_000;
+
 // 检测模块
 impl TelemetryModule {
-    pub fn new(business_api_url: String) -> Result<Self> {
+    pub fn new(
+        business_api_url: String,
+        auth_config: &BusinessApiAuthConfig,
+        telemetry_config: &TelemetryConfig,
+        usage_stats_config: &UsageStatsConfig,
+        alerts_config: &AlertsConfig,
+        ledger: Option<Arc<Ledger>>,
+    ) -> Result<Self> {
         let client = Client::builder()
-            .timeout(Duration::from_secs(5))
+            .timeout(telemetry_config.timeout)
             .build()
-            .map_err(|e| Error::Http(e))?;
+            .map_err(Error::Http)?;
+
+        let resolve = |endpoint: &str| match endpoint {
+            path if path.starts_with('/') => format!("{}{}", business_api_url, path),
+            url => url.to_string(),
+        };
+        let url = |kind: EventKind, event: &TelemetryEventConfig| {
+            event
+                .enabled
+                .then(|| resolve(event.endpoint.as_deref().unwrap_or(kind.telemetry_path())))
+        };
+        let route_health = &telemetry_config.route_health;
+        let queue = Arc::new(DropOldestQueue::new(telemetry_config.queue_size));
+        spawn_worker(queue.clone(), ledger.clone(), telemetry_config.concurrency);
 
         Ok(Self {
             client,
-            business_api_url,
+            usage_url: url(EventKind::Usage, &telemetry_config.usage),
+            error_url: url(EventKind::Error, &telemetry_config.errors),
+            alert_url: url(EventKind::Alert, &telemetry_config.alerts),
+            error_sample_rate: telemetry_config.error_sample_rate,
+            auth: BusinessApiAuth::new(auth_config.clone()),
+            usage_stats: UsageStats::new(usage_stats_config),
+            reported_requests: DashMap::new(),
+            reported_prune_at: AtomicUsize::new(REPORTED_REQUEST_PRUNE_THRESHOLD),
+            ledger,
+            alerts: SpendAlerts::new(alerts_config),
+            queue,
+            route_health: route_health.enabled.then(|| RouteHealthReporter {
+                tracker: RouteHealthTracker::new(),
+                url: resolve(
+                    route_health
+                        .endpoint
+                        .as_deref()
+                        .unwrap_or(ROUTE_HEALTH_PATH),
+                ),
+                interval: route_health.interval,
+            }),
         })
     }
 
-    /// 异步上报错误，不等待结果
-    pub fn report_error(&self, event: ErrorEvent) {
-        let client = self.client.clone();
-        let url = format!("{}/v1/telemetry/errors", self.business_api_url);
+    /// 本地使用量统计
+    pub fn usage_stats(&self) -> &UsageStats {
+        &self.usage_stats
+    }
 
-        // 异步上报，不阻塞主流程
-        tokio::spawn(async move {
-            let _ = client.post(&url).json(&event).send().await;
-            // 忽略上报结果，避免影响主流程
-        });
+    /// 本地账本（未启用时为 None）
+    pub fn ledger(&self) -> Option<&Arc<Ledger>> {
+        self.ledger.as_ref()
+    }
+
+    /// 异步上报错误，不等待结果，按配置的比例采样
+    pub fn report_error(&self, event: ErrorEvent) {
+        // 路由健康报告中的最近错误不受采样影响
+        if let (Some(reporter), Some(provider_token_id)) =
+            (&self.route_health, &event.provider_token_id)
+        {
+            reporter.tracker.record_error(provider_token_id, &event.msg);
+        }
+        if self.error_sample_rate < 1.0 && !rand::thread_rng().gen_bool(self.error_sample_rate) {
+            metrics::increment_counter!("gateway_telemetry_errors_sampled_out_total");
+            return;
+        }
+        let Ok(body) = serde_json::to_vec(&event) else {
+            return;
+        };
+        self.deliver(EventKind::Error, event.request_id, event.model, body);
     }
 
     /// 异步上报使用量，不等待结果
+    ///
+    /// 同一 request_id 只会上报一次（流式收集器和非流式路径、多次故障转移尝试共享同一ID）
     pub fn report_usage(&self, event: UsageEvent) {
-        let client = self.client.clone();
-        let url = format!("{}/v1/telemetry/usage", self.business_api_url);
+        if !self.mark_reported(&event.request_id) {
+            debug!(
+                "Skipping duplicate usage report for request {}",
+                event.request_id
+            );
+            return;
+        }
+
+        let cost = self.usage_stats.record_usage(&event);
+        if let Some(alerts) = self.alerts.as_ref().filter(|_| cost > 0.0) {
+            for alert in alerts.check(&self.usage_stats, &event) {
+                self.report_alert(alert);
+            }
+        }
+
+        let Ok(body) = serde_json::to_vec(&event) else {
+            return;
+        };
+        self.deliver(EventKind::Usage, event.request_id, event.model, body);
+    }
+
+    /// 记录请求结束时的路由追踪，计入路由健康报告
+    pub fn observe_trace(&self, trace: &RoutingTrace) {
+        if let Some(reporter) = &self.route_health {
+            reporter.tracker.observe(trace);
+        }
+    }
+
+    /// 上报当前周期的路由健康报告，周期内没有尝试时不上报
+    fn report_route_health(&self) {
+        let Some(reporter) = &self.route_health else {
+            return;
+        };
+        let Some(report) = reporter.tracker.take_report() else {
+            return;
+        };
+        debug!(
+            "Reporting route health for {} provider token(s)",
+            report.routes.len()
+        );
+        let Ok(body) = serde_json::to_vec(&report) else {
+            return;
+        };
+        let request = self.auth.apply(self.client.post(&reporter.url), body);
+        self.enqueue(Job::RouteHealth { request });
+    }
+
+    /// 异步上报费用告警，配置了推送地址时同时推送（令牌脱敏）
+    fn report_alert(&self, event: AlertEvent) {
+        warn!(
+            "Spend alert ({:?}): {:.4} exceeds {:.4} within {}s, token: {:?}, provider: {:?}",
+            event.scope,
+            event.spend,
+            event.threshold,
+            event.window_secs,
+            event.token.as_deref().map(mask_token),
+            event.provider_id
+        );
+        metrics::increment_counter!("gateway_spend_alerts_total");
 
-        // 异步上报，不阻塞主流程
-        tokio::spawn(async move {
-            let _ = client.post(&url).json(&event).send().await;
-            // 忽略上报结果，避免影响主流程
+        if let Some(url) = self.alerts.as_ref().and_then(|a| a.webhook_url()) {
+            let mut masked = event.clone();
+            masked.token = masked.token.as_deref().map(mask_token);
+            let request = self.client.post(url).json(&masked);
+            self.enqueue(Job::Webhook { request });
+        }
+
+        let Ok(body) = serde_json::to_vec(&event) else {
+            return;
+        };
+        self.deliver(EventKind::Alert, event.alert_id, String::new(), body);
+    }
+
+    /// 异步上报事件，不阻塞主流程
+    ///
+    /// 启用账本时先写入账本，业务API返回成功后标记为已确认；
+    /// 写入或上报失败的事件由对账任务补报。
+    /// 未启用的事件类型直接丢弃。
+    fn deliver(&self, kind: EventKind, request_id: String, model: String, body: Vec<u8>) {
+        let Some(url) = self.endpoint(kind) else {
+            return;
+        };
+        let request = self.auth.apply(self.client.post(url), body.clone());
+        self.enqueue(Job::Event {
+            kind,
+            request_id,
+            model,
+            body,
+            request,
         });
     }
+
+    // 放入上报队列，队列已满时丢弃最早的事件
+    fn enqueue(&self, job: Job) {
+        if let Some(dropped) = self.queue.push(job) {
+            debug!(
+                "Telemetry queue full, dropped oldest {} event",
+                dropped.kind()
+            );
+            metrics::increment_counter!(
+                "gateway_telemetry_dropped_total",
+                "kind" => dropped.kind()
+            );
+        }
+    }
+
+    /// 重新上报账本中未被业务API确认的事件，返回成功补报的条数
+    pub async fn reconcile(&self) -> Result<usize> {
+        let Some(ledger) = &self.ledger else {
+            return Ok(0);
+        };
+
+        let mut delivered = 0;
+        for event in ledger.pending().await? {
+            // 已关闭上报的事件类型保留在账本中，重新启用后补报
+            let Some(url) = self.endpoint(event.kind) else {
+                continue;
+            };
+            ledger.record_attempt(&event.id).await?;
+
+            let request = self.auth.apply(self.client.post(url), event.payload);
+            match request.send().await {
+                Ok(resp) if resp.status().is_success() => {
+                    ledger.acknowledge(&event.id).await?;
+                    delivered += 1;
+                }
+                Ok(resp) => debug!("Ledger entry {} rejected: {}", event.id, resp.status()),
+                Err(e) => {
+                    // 业务API不可用，本轮不再继续
+                    debug!("Ledger reconciliation stopped: {}", e);
+                    break;
+                }
+            }
+        }
+
+        Ok(delivered)
+    }
+
+    // 事件类型的上报地址，未启用时为 None
+    fn endpoint(&self, kind: EventKind) -> Option<&str> {
+        match kind {
+            EventKind::Usage => self.usage_url.as_deref(),
+            EventKind::Error => self.error_url.as_deref(),
+            EventKind::Alert => self.alert_url.as_deref(),
+        }
+    }
+
+    /// 标记请求已上报usage，返回 false 表示此前已上报过
+    ///
+    /// 未过期的记录很多时（持续高请求率），清理开销仍按上报均摊为 O(1)
+    fn mark_reported(&self, request_id: &str) -> bool {
+        if self.reported_requests.len() > self.reported_prune_at.load(Ordering::Relaxed) {
+            self.reported_requests
+                .retain(|_, reported_at| reported_at.elapsed() < REPORTED_REQUEST_TTL);
+            self.reported_prune_at.store(
+                (self.reported_requests.len() * 2).max(REPORTED_REQUEST_PRUNE_THRESHOLD),
+                Ordering::Relaxed,
+            );
+        }
+
+        match self.reported_requests.entry(request_id.to_string()) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(Instant::now());
+                true
+            }
+        }
+    }
+}
+
+/// 启动上报任务：从队列取出事件发送，同时进行的请求数不超过 `concurrency`
+fn spawn_worker(queue: Arc<DropOldestQueue<Job>>, ledger: Option<Arc<Ledger>>, concurrency: usize) {
+    let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+    tokio::spawn(async move {
+        loop {
+            // 先取得并发名额再出队，发送积压时事件留在队列中，由队列按容量丢弃
+            let Ok(permit) = permits.clone().acquire_owned().await else {
+                return;
+            };
+            let job = queue.pop().await;
+            metrics::gauge!("gateway_telemetry_queue_depth", queue.len() as f64);
+            let ledger = ledger.clone();
+            tokio::spawn(async move {
+                job.run(ledger.as_deref()).await;
+                drop(permit);
+            });
+        }
+    });
+}
+
+/// 启动账本对账任务：定期补报未确认的事件并清理过期记录
+pub fn spawn_ledger_reconciliation(telemetry: Arc<TelemetryModule>) {
+    let Some(interval) = telemetry
+        .ledger()
+        .map(|ledger| ledger.config().reconcile_interval)
+    else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // 跳过立即触发的第一次
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+
+            match telemetry.reconcile().await {
+                Ok(0) => {}
+                Ok(count) => info!("Ledger reconciliation re-posted {} event(s)", count),
+                Err(e) => warn!("Ledger reconciliation failed: {}", e),
+            }
+
+            if let Some(ledger) = telemetry.ledger() {
+                match ledger.prune().await {
+                    Ok(0) => {}
+                    Ok(count) => debug!("Pruned {} acknowledged ledger entries", count),
+                    Err(e) => warn!("Failed to prune ledger: {}", e),
+                }
+            }
+        }
+    });
+}
+
+/// 启动路由健康报告任务：按配置的周期上报
+pub fn spawn_route_health_reports(telemetry: Arc<TelemetryModule>) {
+    let Some(interval) = telemetry
+        .route_health
+        .as_ref()
+        .map(|reporter| reporter.interval)
+    else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // 跳过立即触发的第一次
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            telemetry.report_route_health();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn telemetry() -> TelemetryModule {
+        let empty = serde_json::json!({});
+        TelemetryModule::new(
+            "http://127.0.0.1:1".to_string(),
+            &BusinessApiAuthConfig::default(),
+            &serde_json::from_value(empty.clone()).unwrap(),
+            &serde_json::from_value(empty.clone()).unwrap(),
+            &serde_json::from_value(empty).unwrap(),
+            None,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reports_each_request_once() {
+        let telemetry = telemetry();
+        assert!(telemetry.mark_reported("req-1"));
+        assert!(!telemetry.mark_reported("req-1"));
+        assert!(telemetry.mark_reported("req-2"));
+    }
+
+    #[tokio::test]
+    async fn prunes_dedup_records_only_after_doubling() {
+        let telemetry = telemetry();
+        for i in 0..=REPORTED_REQUEST_PRUNE_THRESHOLD {
+            telemetry.mark_reported(&format!("req-{}", i));
+        }
+        // 记录都未过期，清理后阈值提高到存活记录数的两倍，之后的上报不再逐次扫描
+        telemetry.mark_reported("next");
+        let prune_at = telemetry.reported_prune_at.load(Ordering::Relaxed);
+        assert_eq!(prune_at, (REPORTED_REQUEST_PRUNE_THRESHOLD + 1) * 2);
+
+        let stale = Instant::now() - REPORTED_REQUEST_TTL;
+        telemetry
+            .reported_requests
+            .insert("stale".to_string(), stale);
+        telemetry.mark_reported("later");
+        assert!(telemetry.reported_requests.contains_key("stale"));
+        assert!(!telemetry.mark_reported("req-0"));
+    }
 }
@@ -0,0 +1,42 @@
+//! Prometheus 指标导出
+//!
+//! 网关各模块通过 `metrics` 宏记录计数器、仪表和直方图，本模块安装全局的 Prometheus 记录器，
+//! 由 `GET /metrics` 以文本格式输出。记录器进程内只安装一次，主配置和各逻辑网关共用。
+
+use crate::error::{Error, Result};
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::sync::OnceLock;
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Prometheus 文本格式的 Content-Type
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// 安装全局 Prometheus 记录器，已安装时返回同一个句柄
+pub fn install() -> Result<PrometheusHandle> {
+    if let Some(handle) = HANDLE.get() {
+        return Ok(handle.clone());
+    }
+    let recorder = PrometheusBuilder::new().build_recorder();
+    let handle = recorder.handle();
+    metrics::set_boxed_recorder(Box::new(recorder))
+        .map_err(|e| Error::Telemetry(format!("Failed to install metrics recorder: {}", e)))?;
+    Ok(HANDLE.get_or_init(|| handle).clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_recorded_metrics() {
+        let handle = install().unwrap();
+        metrics::increment_counter!("gateway_prometheus_test_total", "kind" => "unit");
+
+        // 再次安装返回同一个句柄
+        assert!(install().is_ok());
+        assert!(handle
+            .render()
+            .contains("gateway_prometheus_test_total{kind=\"unit\"} 1"));
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/// 有界队列，队列满时丢弃最早的元素，为新元素腾出位置
+///
+/// 上报积压时优先保留较新的事件。
+pub struct DropOldestQueue<T> {
+    items: Mutex<VecDeque<T>>,
+    capacity: usize,
+    notify: Notify,
+}
+
+impl<T> DropOldestQueue<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            items: Mutex::new(VecDeque::with_capacity(capacity.min(1024))),
+            capacity: capacity.max(1),
+            notify: Notify::new(),
+        }
+    }
+
+    /// 放入元素，队列已满时返回被丢弃的最早元素
+    pub fn push(&self, item: T) -> Option<T> {
+        let dropped = {
+            let mut items = self.items.lock().unwrap_or_else(|e| e.into_inner());
+            let dropped = if items.len() >= self.capacity {
+                items.pop_front()
+            } else {
+                None
+            };
+            items.push_back(item);
+            dropped
+        };
+        self.notify.notify_one();
+        dropped
+    }
+
+    /// 取出最早的元素，队列为空时等待
+    pub async fn pop(&self) -> T {
+        loop {
+            // 先登记等待再检查队列，避免错过检查之后到达的通知
+            let notified = self.notify.notified();
+            if let Some(item) = self
+                .items
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .pop_front()
+            {
+                return item;
+            }
+            notified.await;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
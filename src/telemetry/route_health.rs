@@ -0,0 +1,161 @@
+use crate::models::{RouteHealth, RouteHealthReport};
+use crate::router::failover::{AttemptOutcome, RoutingTrace};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// 每个供应商令牌每个周期最多保留的延迟样本数，超出后随机替换（蓄水池抽样）
+const MAX_LATENCY_SAMPLES: usize = 1024;
+/// 上报的错误信息最大字符数
+const MAX_ERROR_CHARS: usize = 512;
+
+/// 路由健康统计
+///
+/// 按供应商令牌汇总请求结束时的路由追踪和上报的错误事件，
+/// 生成报告时取走当前周期的统计并开始新周期。
+pub struct RouteHealthTracker {
+    period: Mutex<Period>,
+}
+
+struct Period {
+    started_at: DateTime<Utc>,
+    routes: HashMap<String, Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    provider_id: String,
+    attempts: u64,
+    successes: u64,
+    client_errors: u64,
+    failures: u64,
+    // 成功尝试的延迟样本及样本总数
+    latencies_ms: Vec<u64>,
+    latency_count: u64,
+    last_error: Option<(String, DateTime<Utc>)>,
+}
+
+impl Period {
+    fn new() -> Self {
+        Self {
+            started_at: Utc::now(),
+            routes: HashMap::new(),
+        }
+    }
+}
+
+impl Counters {
+    fn record_latency(&mut self, elapsed_ms: u64) {
+        self.latency_count += 1;
+        if self.latencies_ms.len() < MAX_LATENCY_SAMPLES {
+            self.latencies_ms.push(elapsed_ms);
+        } else {
+            let slot = rand::thread_rng().gen_range(0..self.latency_count);
+            if let Some(sample) = self.latencies_ms.get_mut(slot as usize) {
+                *sample = elapsed_ms;
+            }
+        }
+    }
+
+    fn p95(&mut self) -> Option<u64> {
+        if self.latencies_ms.is_empty() {
+            return None;
+        }
+        self.latencies_ms.sort_unstable();
+        let index = (self.latencies_ms.len() * 95).div_ceil(100) - 1;
+        Some(self.latencies_ms[index])
+    }
+}
+
+impl Default for RouteHealthTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RouteHealthTracker {
+    pub fn new() -> Self {
+        Self {
+            period: Mutex::new(Period::new()),
+        }
+    }
+
+    /// 记录一个请求的路由追踪
+    pub fn observe(&self, trace: &RoutingTrace) {
+        let mut period = self.period.lock().unwrap_or_else(|e| e.into_inner());
+        for attempt in &trace.attempts {
+            // 取消的竞速尝试和未实际转发的尝试不反映供应商状况
+            if matches!(
+                attempt.outcome,
+                AttemptOutcome::Cancelled | AttemptOutcome::Skipped
+            ) {
+                continue;
+            }
+            let counters = period
+                .routes
+                .entry(attempt.provider_token_id.clone())
+                .or_default();
+            counters.provider_id.clone_from(&attempt.provider_id);
+            counters.attempts += 1;
+            match attempt.outcome {
+                AttemptOutcome::Success => {
+                    counters.successes += 1;
+                    counters.record_latency(attempt.elapsed_ms);
+                }
+                AttemptOutcome::ClientError => counters.client_errors += 1,
+                _ => counters.failures += 1,
+            }
+        }
+    }
+
+    /// 记录供应商令牌最近一次错误
+    pub fn record_error(&self, provider_token_id: &str, message: &str) {
+        let message: String = message.chars().take(MAX_ERROR_CHARS).collect();
+        let mut period = self.period.lock().unwrap_or_else(|e| e.into_inner());
+        period
+            .routes
+            .entry(provider_token_id.to_string())
+            .or_default()
+            .last_error = Some((message, Utc::now()));
+    }
+
+    /// 取走当前周期的统计生成报告并开始新周期，周期内没有任何尝试时返回 None
+    pub fn take_report(&self) -> Option<RouteHealthReport> {
+        let period = std::mem::replace(
+            &mut *self.period.lock().unwrap_or_else(|e| e.into_inner()),
+            Period::new(),
+        );
+        if period.routes.is_empty() {
+            return None;
+        }
+
+        let mut routes: Vec<RouteHealth> = period
+            .routes
+            .into_iter()
+            .map(|(provider_token_id, mut counters)| {
+                let p95_latency_ms = counters.p95();
+                let (last_error, last_error_at) = counters.last_error.unzip();
+                RouteHealth {
+                    provider_token_id,
+                    provider_id: counters.provider_id,
+                    attempts: counters.attempts,
+                    successes: counters.successes,
+                    client_errors: counters.client_errors,
+                    failures: counters.failures,
+                    p95_latency_ms,
+                    last_error,
+                    last_error_at,
+                }
+            })
+            .collect();
+        routes.sort_by_key(|route| std::cmp::Reverse(route.attempts));
+
+        Some(RouteHealthReport {
+            report_id: uuid::Uuid::new_v4().to_string(),
+            period_start: period.started_at,
+            period_end: Utc::now(),
+            routes,
+        })
+    }
+}
@@ -0,0 +1,377 @@
+use crate::config::{ModelPrice, UsageStatsConfig};
+use crate::models::UsageEvent;
+use crate::secrets::mask_token;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// 单个维度的累计计数
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageCounters {
+    /// 成功请求数
+    pub requests: u64,
+    /// 输入Token数
+    pub input_tokens: u64,
+    /// 输出Token数
+    pub output_tokens: u64,
+    /// 失败次数（每次上游尝试失败计一次）
+    pub errors: u64,
+    /// 按配置价格估算的费用
+    pub estimated_cost: f64,
+}
+
+impl UsageCounters {
+    fn merge(&mut self, other: &UsageCounters) {
+        self.requests += other.requests;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.errors += other.errors;
+        self.estimated_cost += other.estimated_cost;
+    }
+}
+
+/// 使用量汇总结果
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageSummary {
+    /// 汇总窗口（秒）
+    pub window_secs: u64,
+    /// 全部请求的合计
+    pub total: UsageCounters,
+    /// 按用户token分组，键为脱敏令牌加令牌指纹，脱敏后相同的令牌不会合并
+    pub tokens: HashMap<String, UsageCounters>,
+    /// 按供应商ID分组
+    pub providers: HashMap<String, UsageCounters>,
+}
+
+/// 窗口内的估算费用
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WindowSpend {
+    /// 指定用户令牌的费用
+    pub token: f64,
+    /// 指定供应商的费用
+    pub provider: f64,
+    /// 全部请求的费用
+    pub global: f64,
+}
+
+/// 请求执行前的费用预估
+#[derive(Debug, Clone, Serialize)]
+pub struct CostEstimate {
+    pub model: String,
+    /// 估算的提示词Token数
+    pub prompt_tokens: u32,
+    /// 客户端请求的最大输出Token数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    /// 提示词部分的费用，模型未配置价格时为空
+    pub prompt_cost: Option<f64>,
+    /// 输出达到最大输出Token数时的总费用，未配置价格或未指定最大输出时为空
+    pub max_cost: Option<f64>,
+}
+
+impl CostEstimate {
+    /// 对外返回的预估费用：指定了最大输出时取上限，否则只含提示词部分
+    pub fn headline(&self) -> Option<f64> {
+        self.max_cost.or(self.prompt_cost)
+    }
+}
+
+/// 按分钟聚合的计数桶
+struct MinuteBucket {
+    minute: u64,
+    tokens: HashMap<String, UsageCounters>,
+    providers: HashMap<String, UsageCounters>,
+}
+
+/// 内存中的滚动使用量统计
+///
+/// 按分钟分桶保存 per-token / per-provider 计数，超过保留时长的桶会被丢弃。
+/// 用于在业务API遥测入库延迟时，仍能通过管理接口即时查看使用情况。
+pub struct UsageStats {
+    buckets: Mutex<VecDeque<MinuteBucket>>,
+    retention: Duration,
+    prices: HashMap<String, ModelPrice>,
+}
+
+impl UsageStats {
+    pub fn new(config: &UsageStatsConfig) -> Self {
+        Self {
+            buckets: Mutex::new(VecDeque::new()),
+            retention: config.retention,
+            prices: config.prices.clone(),
+        }
+    }
+
+    /// 记录一次成功请求的使用量，返回估算费用
+    pub fn record_usage(&self, event: &UsageEvent) -> f64 {
+        let cost = self
+            .prices
+            .get(&event.model)
+            .map(|price| price.estimate(event.input_tokens, event.output_tokens))
+            .unwrap_or(0.0);
+
+        let counters = UsageCounters {
+            requests: 1,
+            input_tokens: event.input_tokens.max(0) as u64,
+            output_tokens: event.output_tokens.max(0) as u64,
+            errors: 0,
+            estimated_cost: cost,
+        };
+
+        self.record(&event.token, &event.provider_id, &counters);
+        cost
+    }
+
+    /// 按估算的提示词Token数和最大输出Token数预估请求费用
+    pub fn estimate(
+        &self,
+        model: &str,
+        prompt_tokens: u32,
+        max_output_tokens: Option<u32>,
+    ) -> CostEstimate {
+        let price = self.prices.get(model);
+        let prompt_tokens_i32 = prompt_tokens.min(i32::MAX as u32) as i32;
+        CostEstimate {
+            model: model.to_string(),
+            prompt_tokens,
+            max_output_tokens,
+            prompt_cost: price.map(|price| price.estimate(prompt_tokens_i32, 0)),
+            max_cost: price.zip(max_output_tokens).map(|(price, output)| {
+                price.estimate(prompt_tokens_i32, output.min(i32::MAX as u32) as i32)
+            }),
+        }
+    }
+
+    /// 记录一次上游失败
+    pub fn record_error(&self, user_token: &str, provider_id: &str) {
+        let counters = UsageCounters {
+            errors: 1,
+            ..Default::default()
+        };
+
+        self.record(user_token, provider_id, &counters);
+    }
+
+    /// 汇总最近 `window` 时间内的计数
+    pub fn summary(&self, window: Duration) -> UsageSummary {
+        let window = window.min(self.retention);
+        let window_minutes = window.as_secs().div_ceil(60).max(1);
+        let since = current_minute().saturating_sub(window_minutes - 1);
+
+        let mut summary = UsageSummary {
+            window_secs: window.as_secs(),
+            total: UsageCounters::default(),
+            tokens: HashMap::new(),
+            providers: HashMap::new(),
+        };
+
+        // 按原始令牌汇总，输出时才脱敏
+        let mut tokens: HashMap<&str, UsageCounters> = HashMap::new();
+        let buckets = self.buckets.lock().unwrap();
+        for bucket in buckets.iter().filter(|b| b.minute >= since) {
+            for (token, counters) in &bucket.tokens {
+                summary.total.merge(counters);
+                tokens.entry(token).or_default().merge(counters);
+            }
+            for (provider, counters) in &bucket.providers {
+                summary
+                    .providers
+                    .entry(provider.clone())
+                    .or_default()
+                    .merge(counters);
+            }
+        }
+        summary.tokens = tokens
+            .into_iter()
+            .map(|(token, counters)| (token_label(token), counters))
+            .collect();
+
+        summary
+    }
+
+    /// 最近 `window` 时间内指定令牌、供应商及全局的估算费用
+    pub fn spend(&self, window: Duration, user_token: &str, provider_id: &str) -> WindowSpend {
+        let window_minutes = window.min(self.retention).as_secs().div_ceil(60).max(1);
+        let since = current_minute().saturating_sub(window_minutes - 1);
+
+        let mut spend = WindowSpend::default();
+        let buckets = self.buckets.lock().unwrap();
+        for bucket in buckets.iter().filter(|b| b.minute >= since) {
+            spend.global += bucket
+                .providers
+                .values()
+                .map(|c| c.estimated_cost)
+                .sum::<f64>();
+            if let Some(counters) = bucket.tokens.get(user_token) {
+                spend.token += counters.estimated_cost;
+            }
+            if let Some(counters) = bucket.providers.get(provider_id) {
+                spend.provider += counters.estimated_cost;
+            }
+        }
+
+        spend
+    }
+
+    fn record(&self, user_token: &str, provider_id: &str, counters: &UsageCounters) {
+        let minute = current_minute();
+        let retention_minutes = self.retention.as_secs().div_ceil(60).max(1);
+
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // 丢弃超过保留时长的桶
+        while buckets
+            .front()
+            .is_some_and(|b| b.minute + retention_minutes <= minute)
+        {
+            buckets.pop_front();
+        }
+
+        if buckets.back().map(|b| b.minute) != Some(minute) {
+            buckets.push_back(MinuteBucket {
+                minute,
+                tokens: HashMap::new(),
+                providers: HashMap::new(),
+            });
+        }
+
+        let bucket = buckets.back_mut().unwrap();
+        bucket
+            .tokens
+            .entry(user_token.to_string())
+            .or_default()
+            .merge(counters);
+        bucket
+            .providers
+            .entry(provider_id.to_string())
+            .or_default()
+            .merge(counters);
+    }
+}
+
+impl ModelPrice {
+    /// 根据输入/输出Token数估算费用（价格单位：每百万Token）
+    pub fn estimate(&self, input_tokens: i32, output_tokens: i32) -> f64 {
+        (f64::from(input_tokens.max(0)) * self.input
+            + f64::from(output_tokens.max(0)) * self.output)
+            / 1_000_000.0
+    }
+}
+
+// 令牌的展示名：脱敏令牌加 SHA-256 前 8 位十六进制指纹，区分前后缀相同的令牌和短令牌
+fn token_label(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    format!("{}#{}", mask_token(token), hex::encode(&digest[..4]))
+}
+
+fn current_minute() -> u64 {
+    (chrono::Utc::now().timestamp().max(0) as u64) / 60
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> UsageStats {
+        let config: UsageStatsConfig = serde_json::from_value(serde_json::json!({
+            "retention": "1h",
+            "prices": {"gpt-4o": {"input": 2.0, "output": 8.0}},
+        }))
+        .unwrap();
+        UsageStats::new(&config)
+    }
+
+    fn usage(token: &str, provider_id: &str) -> UsageEvent {
+        UsageEvent {
+            token: token.to_string(),
+            model: "gpt-4o".to_string(),
+            input_tokens: 1_000_000,
+            output_tokens: 500_000,
+            provider_id: provider_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn keeps_tokens_with_the_same_mask_apart() {
+        let stats = stats();
+        stats.record_usage(&usage("sk-a-first-ijkl", "p1"));
+        stats.record_usage(&usage("sk-a-second-ijkl", "p1"));
+        stats.record_usage(&usage("short1", "p2"));
+        stats.record_usage(&usage("short2", "p2"));
+        stats.record_error("short2", "p2");
+
+        let summary = stats.summary(Duration::from_secs(300));
+        assert_eq!(summary.tokens.len(), 4);
+        assert!(summary
+            .tokens
+            .keys()
+            .all(|key| key.starts_with("sk-a...ijkl#") || key.starts_with("***#")));
+        assert!(summary.tokens.keys().all(|key| !key.contains("short")));
+        let short2 = &summary.tokens[&token_label("short2")];
+        assert_eq!((short2.requests, short2.errors), (1, 1));
+
+        assert_eq!(summary.total.requests, 4);
+        assert_eq!(summary.total.errors, 1);
+        assert_eq!(summary.total.input_tokens, 4_000_000);
+        assert!((summary.total.estimated_cost - 24.0).abs() < 1e-9);
+        assert_eq!(summary.providers["p1"].requests, 2);
+        assert_eq!(summary.providers["p2"].errors, 1);
+    }
+
+    #[test]
+    fn sums_only_buckets_inside_the_window() {
+        let stats = stats();
+        stats.record_usage(&usage("sk-user-abcdefgh", "p1"));
+        {
+            let mut buckets = stats.buckets.lock().unwrap();
+            let current = buckets.pop_back().unwrap();
+            // 10 分钟前的桶
+            let mut old = MinuteBucket {
+                minute: current.minute - 10,
+                tokens: current.tokens.clone(),
+                providers: current.providers.clone(),
+            };
+            old.tokens.values_mut().for_each(|c| c.requests = 5);
+            old.providers.values_mut().for_each(|c| c.requests = 5);
+            buckets.push_back(old);
+            buckets.push_back(current);
+        }
+
+        let label = token_label("sk-user-abcdefgh");
+        let recent = stats.summary(Duration::from_secs(300));
+        assert_eq!(recent.window_secs, 300);
+        assert_eq!(recent.tokens[&label].requests, 1);
+        assert_eq!(recent.providers["p1"].requests, 1);
+
+        let longer = stats.summary(Duration::from_secs(900));
+        assert_eq!(longer.tokens[&label].requests, 6);
+        assert_eq!(longer.total.requests, 6);
+
+        // 窗口不超过保留时长
+        assert_eq!(stats.summary(Duration::from_secs(7200)).window_secs, 3600);
+
+        let spend = stats.spend(Duration::from_secs(300), "sk-user-abcdefgh", "p1");
+        assert!((spend.token - 6.0).abs() < 1e-9);
+        assert!((spend.global - spend.provider).abs() < 1e-9);
+    }
+
+    #[test]
+    fn drops_buckets_past_retention() {
+        let stats = stats();
+        stats.record_usage(&usage("sk-user-abcdefgh", "p1"));
+        {
+            let mut buckets = stats.buckets.lock().unwrap();
+            let current = buckets.pop_back().unwrap();
+            buckets.push_back(MinuteBucket {
+                minute: current.minute - 60,
+                tokens: HashMap::new(),
+                providers: HashMap::new(),
+            });
+        }
+        stats.record_error("sk-user-abcdefgh", "p1");
+        assert_eq!(stats.buckets.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,31 @@
+//! 输出Token数估算
+//!
+//! 上游不返回用量时，按流式增量中的文本估算输出Token数。统一使用 cl100k_base 分词，
+//! 各增量分别计数，与供应商实际的分词结果存在少量偏差；分词表加载失败时按约4个字符1个Token估算。
+
+use std::sync::OnceLock;
+use tiktoken_rs::CoreBPE;
+use tracing::warn;
+
+fn tokenizer() -> Option<&'static CoreBPE> {
+    static TOKENIZER: OnceLock<Option<CoreBPE>> = OnceLock::new();
+    TOKENIZER
+        .get_or_init(|| {
+            tiktoken_rs::cl100k_base()
+                .inspect_err(|e| warn!("Failed to load tokenizer, estimating by length: {}", e))
+                .ok()
+        })
+        .as_ref()
+}
+
+/// 估算一段文本的Token数
+pub fn count_tokens(text: &str) -> u32 {
+    if text.is_empty() {
+        return 0;
+    }
+    let tokens = match tokenizer() {
+        Some(bpe) => bpe.encode_ordinary(text).len(),
+        None => text.chars().count().div_ceil(4),
+    };
+    tokens.min(u32::MAX as usize) as u32
+}
@@ -0,0 +1,416 @@
+//! 上游响应用量解析
+//!
+//! 非流式响应和流式事件共用同一套按协议的解析实现，除输入/输出Token数外，
+//! 同时解析总Token数、推理Token数和提示词缓存的读写Token数。
+//! 流式事件还按输出文本估算Token数，供上游不返回用量时使用（见 [`estimate`]）。
+
+pub mod estimate;
+
+use crate::models::{TargetProtocol, UsageEvent};
+use crate::protocol::anthropic::{self, AnthropicStreamEvent, ContentDelta};
+use crate::protocol::openai::{ChunkUsage, OpenAIStreamChunk, TokensDetails};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// 从上游响应中解析出的用量，上游未返回的项为空
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub input_tokens: Option<i32>,
+    pub output_tokens: Option<i32>,
+    pub total_tokens: Option<i32>,
+    /// 输出中的推理Token数
+    pub reasoning_tokens: Option<i32>,
+    /// 命中提示词缓存的输入Token数
+    pub cache_read_input_tokens: Option<i32>,
+    /// 写入提示词缓存的输入Token数
+    pub cache_creation_input_tokens: Option<i32>,
+}
+
+impl TokenUsage {
+    /// 合并后到达的用量，后到的非空项覆盖已有值
+    pub fn merge(&mut self, other: TokenUsage) {
+        self.input_tokens = other.input_tokens.or(self.input_tokens);
+        self.output_tokens = other.output_tokens.or(self.output_tokens);
+        self.total_tokens = other.total_tokens.or(self.total_tokens);
+        self.reasoning_tokens = other.reasoning_tokens.or(self.reasoning_tokens);
+        self.cache_read_input_tokens = other
+            .cache_read_input_tokens
+            .or(self.cache_read_input_tokens);
+        self.cache_creation_input_tokens = other
+            .cache_creation_input_tokens
+            .or(self.cache_creation_input_tokens);
+    }
+
+    /// 输入和输出Token数都已解析时返回二者
+    pub fn totals(&self) -> Option<(i32, i32)> {
+        Some((self.input_tokens?, self.output_tokens?))
+    }
+
+    /// 将明细填入用量事件
+    pub fn annotate(&self, event: UsageEvent) -> UsageEvent {
+        UsageEvent {
+            total_tokens: self.total_tokens,
+            reasoning_tokens: self.reasoning_tokens,
+            cache_read_input_tokens: self.cache_read_input_tokens,
+            cache_creation_input_tokens: self.cache_creation_input_tokens,
+            ..event
+        }
+    }
+}
+
+/// 一个流式事件中解析出的用量
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StreamUsage {
+    pub usage: Option<TokenUsage>,
+    /// 事件表示用量已完整，可以上报
+    pub finished: bool,
+    /// 事件中输出文本（包括推理内容和工具调用参数）的估算Token数
+    pub estimated_output_tokens: u32,
+}
+
+/// 按上游协议解析用量
+pub trait UsageParser: Send + Sync {
+    /// 解析非流式响应体中的用量
+    fn parse_response(&self, body: &Value) -> Option<TokenUsage>;
+
+    /// 解析一个流式事件（SSE data 的 JSON）中的用量
+    fn parse_stream_event(&self, event: &Value) -> StreamUsage;
+}
+
+/// 上游协议对应的解析实现
+pub fn parser_for(protocol: &TargetProtocol) -> &'static dyn UsageParser {
+    match protocol {
+        TargetProtocol::Anthropic => &AnthropicUsageParser,
+        TargetProtocol::OpenAI | TargetProtocol::Custom(_) => &OpenAIUsageParser,
+    }
+}
+
+/// 解析非流式响应体中的用量，响应体不是 JSON 或没有用量时返回 None
+pub fn parse_response(protocol: &TargetProtocol, body: &[u8]) -> Option<TokenUsage> {
+    let body: Value = serde_json::from_slice(body).ok()?;
+    parser_for(protocol).parse_response(&body)
+}
+
+/// OpenAI 协议，兼容 Chat Completions 和 Responses API 两种用量格式
+///
+/// - Chat Completions：`prompt_tokens` / `completion_tokens`，明细在
+///   `prompt_tokens_details.cached_tokens`、`completion_tokens_details.reasoning_tokens`
+/// - Responses API：`input_tokens` / `output_tokens`，明细在
+///   `input_tokens_details.cached_tokens`、`output_tokens_details.reasoning_tokens`
+pub struct OpenAIUsageParser;
+
+impl OpenAIUsageParser {
+    fn parse_usage(usage: &ChunkUsage) -> TokenUsage {
+        let detail = |details: &Option<TokensDetails>, field: fn(&TokensDetails) -> Option<i64>| {
+            tokens(details.as_ref().and_then(field))
+        };
+        if usage.prompt_tokens.is_some() || usage.completion_tokens.is_some() {
+            TokenUsage {
+                input_tokens: tokens(usage.prompt_tokens),
+                output_tokens: tokens(usage.completion_tokens),
+                total_tokens: tokens(usage.total_tokens),
+                reasoning_tokens: detail(&usage.completion_tokens_details, |d| d.reasoning_tokens),
+                cache_read_input_tokens: detail(&usage.prompt_tokens_details, |d| d.cached_tokens),
+                cache_creation_input_tokens: None,
+            }
+        } else {
+            TokenUsage {
+                input_tokens: tokens(usage.input_tokens),
+                output_tokens: tokens(usage.output_tokens),
+                total_tokens: tokens(usage.total_tokens),
+                reasoning_tokens: detail(&usage.output_tokens_details, |d| d.reasoning_tokens),
+                cache_read_input_tokens: detail(&usage.input_tokens_details, |d| d.cached_tokens),
+                cache_creation_input_tokens: None,
+            }
+        }
+    }
+}
+
+impl UsageParser for OpenAIUsageParser {
+    fn parse_response(&self, body: &Value) -> Option<TokenUsage> {
+        let usage = Self::parse_usage(&ChunkUsage::deserialize(body.get("usage")?).ok()?);
+        usage.totals().map(|_| usage)
+    }
+
+    fn parse_stream_event(&self, event: &Value) -> StreamUsage {
+        match event.get("type").and_then(Value::as_str) {
+            // Responses API 的 response.completed / response.done 事件，用量在 response.usage 中
+            Some("response.completed" | "response.done") => {
+                let usage = event
+                    .pointer("/response/usage")
+                    .and_then(|usage| ChunkUsage::deserialize(usage).ok())
+                    .map(|usage| Self::parse_usage(&usage));
+                return StreamUsage {
+                    finished: usage.is_some(),
+                    usage,
+                    estimated_output_tokens: 0,
+                };
+            }
+            // Responses API 的文本、推理摘要、工具调用参数等增量事件
+            Some(kind) if kind.starts_with("response.") && kind.ends_with(".delta") => {
+                return StreamUsage {
+                    estimated_output_tokens: event
+                        .get("delta")
+                        .and_then(Value::as_str)
+                        .map_or(0, estimate::count_tokens),
+                    ..StreamUsage::default()
+                };
+            }
+            _ => {}
+        }
+
+        // Chat Completions 的用量在最后一个 chunk 中，带有输出Token数即表示流结束
+        let Ok(chunk) = OpenAIStreamChunk::deserialize(event) else {
+            return StreamUsage::default();
+        };
+        let usage = chunk.usage.as_ref().map(Self::parse_usage);
+        let estimated_output_tokens = chunk
+            .choices
+            .iter()
+            .map(|choice| {
+                let delta = &choice.delta;
+                let arguments = delta
+                    .tool_calls
+                    .iter()
+                    .filter_map(|call| call.function.as_ref()?.arguments.as_deref());
+                delta
+                    .content
+                    .as_deref()
+                    .into_iter()
+                    .chain(delta.reasoning_content.as_deref())
+                    .chain(arguments)
+                    .map(estimate::count_tokens)
+                    .sum::<u32>()
+            })
+            .sum();
+        StreamUsage {
+            finished: usage.is_some_and(|usage| usage.output_tokens.is_some()),
+            usage,
+            estimated_output_tokens,
+        }
+    }
+}
+
+/// Anthropic 协议：`input_tokens` / `output_tokens`，缓存读写在
+/// `cache_read_input_tokens` / `cache_creation_input_tokens`
+///
+/// 流式响应中 message_start 带有输入用量，message_delta 带有累积的输出用量（带有输入用量时以其为准），
+/// message_stop 表示流结束。
+pub struct AnthropicUsageParser;
+
+impl AnthropicUsageParser {
+    fn parse_usage(usage: &anthropic::StreamUsage) -> TokenUsage {
+        TokenUsage {
+            input_tokens: tokens(usage.input_tokens),
+            output_tokens: tokens(usage.output_tokens),
+            total_tokens: None,
+            reasoning_tokens: None,
+            cache_read_input_tokens: tokens(usage.cache_read_input_tokens),
+            cache_creation_input_tokens: tokens(usage.cache_creation_input_tokens),
+        }
+    }
+}
+
+impl UsageParser for AnthropicUsageParser {
+    fn parse_response(&self, body: &Value) -> Option<TokenUsage> {
+        let usage =
+            Self::parse_usage(&anthropic::StreamUsage::deserialize(body.get("usage")?).ok()?);
+        usage.totals().map(|_| usage)
+    }
+
+    fn parse_stream_event(&self, event: &Value) -> StreamUsage {
+        match AnthropicStreamEvent::deserialize(event) {
+            Ok(AnthropicStreamEvent::MessageStart { message }) => StreamUsage {
+                usage: message.usage.as_ref().map(Self::parse_usage),
+                ..StreamUsage::default()
+            },
+            Ok(AnthropicStreamEvent::ContentBlockDelta { delta, .. }) => StreamUsage {
+                estimated_output_tokens: match &delta {
+                    ContentDelta::TextDelta { text } => estimate::count_tokens(text),
+                    ContentDelta::InputJsonDelta { partial_json } => {
+                        estimate::count_tokens(partial_json)
+                    }
+                    ContentDelta::ThinkingDelta { thinking } => estimate::count_tokens(thinking),
+                    _ => 0,
+                },
+                ..StreamUsage::default()
+            },
+            Ok(AnthropicStreamEvent::MessageDelta { usage, .. }) => StreamUsage {
+                usage: usage.as_ref().map(Self::parse_usage),
+                ..StreamUsage::default()
+            },
+            Ok(AnthropicStreamEvent::MessageStop) => StreamUsage {
+                finished: true,
+                ..StreamUsage::default()
+            },
+            _ => StreamUsage::default(),
+        }
+    }
+}
+
+fn tokens(value: Option<i64>) -> Option<i32> {
+    value.map(|n| n.clamp(0, i32::MAX as i64) as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn openai() -> &'static dyn UsageParser {
+        parser_for(&TargetProtocol::OpenAI)
+    }
+
+    fn anthropic() -> &'static dyn UsageParser {
+        parser_for(&TargetProtocol::Anthropic)
+    }
+
+    #[test]
+    fn parses_chat_completions_response() {
+        let usage = openai()
+            .parse_response(&json!({"usage": {
+                "prompt_tokens": 10,
+                "completion_tokens": 5,
+                "total_tokens": 15,
+                "prompt_tokens_details": {"cached_tokens": 4},
+                "completion_tokens_details": {"reasoning_tokens": 2},
+            }}))
+            .unwrap();
+        assert_eq!(
+            usage,
+            TokenUsage {
+                input_tokens: Some(10),
+                output_tokens: Some(5),
+                total_tokens: Some(15),
+                reasoning_tokens: Some(2),
+                cache_read_input_tokens: Some(4),
+                cache_creation_input_tokens: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parses_responses_api_response() {
+        let usage = openai()
+            .parse_response(&json!({"usage": {
+                "input_tokens": 8,
+                "output_tokens": 3,
+                "total_tokens": 11,
+                "input_tokens_details": {"cached_tokens": 1},
+                "output_tokens_details": {"reasoning_tokens": 2},
+            }}))
+            .unwrap();
+        assert_eq!(usage.totals(), Some((8, 3)));
+        assert_eq!(usage.cache_read_input_tokens, Some(1));
+        assert_eq!(usage.reasoning_tokens, Some(2));
+    }
+
+    #[test]
+    fn parses_anthropic_response() {
+        let usage = anthropic()
+            .parse_response(&json!({"usage": {
+                "input_tokens": 3,
+                "output_tokens": 7,
+                "cache_read_input_tokens": 9,
+                "cache_creation_input_tokens": 2,
+            }}))
+            .unwrap();
+        assert_eq!(usage.totals(), Some((3, 7)));
+        assert_eq!(usage.cache_read_input_tokens, Some(9));
+        assert_eq!(usage.cache_creation_input_tokens, Some(2));
+    }
+
+    #[test]
+    fn incomplete_response_usage_is_none() {
+        assert_eq!(openai().parse_response(&json!({"id": "x"})), None);
+        assert_eq!(
+            openai().parse_response(&json!({"usage": {"prompt_tokens": 1}})),
+            None
+        );
+        assert_eq!(parse_response(&TargetProtocol::OpenAI, b"not json"), None);
+    }
+
+    #[test]
+    fn negative_counts_clamp_to_zero() {
+        let usage = anthropic()
+            .parse_response(&json!({"usage": {"input_tokens": -1, "output_tokens": 2}}))
+            .unwrap();
+        assert_eq!(usage.input_tokens, Some(0));
+    }
+
+    #[test]
+    fn chat_stream_finishes_on_usage_chunk() {
+        let delta = openai().parse_stream_event(&json!({
+            "choices": [{"index": 0, "delta": {"content": "hello world"}}],
+        }));
+        assert!(!delta.finished);
+        assert!(delta.usage.is_none());
+        assert!(delta.estimated_output_tokens > 0);
+
+        let last = openai().parse_stream_event(&json!({
+            "choices": [],
+            "usage": {"prompt_tokens": 4, "completion_tokens": 2},
+        }));
+        assert!(last.finished);
+        assert_eq!(last.usage.unwrap().totals(), Some((4, 2)));
+    }
+
+    #[test]
+    fn responses_stream_finishes_on_completed() {
+        let delta = openai().parse_stream_event(&json!({
+            "type": "response.output_text.delta",
+            "delta": "hello",
+        }));
+        assert!(delta.estimated_output_tokens > 0);
+
+        let completed = openai().parse_stream_event(&json!({
+            "type": "response.completed",
+            "response": {"usage": {"input_tokens": 5, "output_tokens": 6}},
+        }));
+        assert!(completed.finished);
+        assert_eq!(completed.usage.unwrap().totals(), Some((5, 6)));
+    }
+
+    #[test]
+    fn anthropic_stream_merges_start_and_delta() {
+        let mut usage = TokenUsage::default();
+        for event in [
+            json!({"type": "message_start", "message": {"usage": {
+                "input_tokens": 3, "output_tokens": 1, "cache_read_input_tokens": 9,
+            }}}),
+            json!({"type": "content_block_delta", "index": 0,
+                   "delta": {"type": "text_delta", "text": "hi"}}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "end_turn"},
+                   "usage": {"output_tokens": 4}}),
+        ] {
+            let parsed = anthropic().parse_stream_event(&event);
+            assert!(!parsed.finished);
+            if let Some(parsed) = parsed.usage {
+                usage.merge(parsed);
+            }
+        }
+        assert_eq!(usage.totals(), Some((3, 4)));
+        assert_eq!(usage.cache_read_input_tokens, Some(9));
+        assert!(
+            anthropic()
+                .parse_stream_event(&json!({"type": "message_stop"}))
+                .finished
+        );
+    }
+
+    #[test]
+    fn annotate_fills_breakdown() {
+        let usage = TokenUsage {
+            total_tokens: Some(15),
+            reasoning_tokens: Some(2),
+            cache_read_input_tokens: Some(4),
+            cache_creation_input_tokens: Some(1),
+            ..TokenUsage::default()
+        };
+        let event = usage.annotate(UsageEvent::default());
+        assert_eq!(event.total_tokens, Some(15));
+        assert_eq!(event.reasoning_tokens, Some(2));
+        assert_eq!(event.cache_read_input_tokens, Some(4));
+        assert_eq!(event.cache_creation_input_tokens, Some(1));
+    }
+}
@@ -1,43 +1,104 @@
-use std::sync::{Arc, Mutex};
-use futures::Stream;
-use futures::StreamExt;
-use bytes::Bytes;
-use tracing::{error, info, trace, warn};
-use crate::models::{UsageEvent, TargetProtocol, RouteConfig};
+use crate::models::{RouteConfig, UsageEvent};
+use crate::proxy::compression::CompressionStats;
 use crate::telemetry::TelemetryModule;
+use crate::usage::{self, TokenUsage, UsageParser};
 use crate::Result;
+use bytes::Bytes;
+use futures::Stream;
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{info, trace, warn};
 
 /// 流式响应的Usage收集器
 pub struct StreamUsageCollector {
     request_id: String,
     user_token: String,
+    client_ip: Option<String>,
+    claims: Option<HashMap<String, serde_json::Value>>,
+    tenant_id: Option<String>,
     // 携带完整的RouteConfig，便于灵活上报
     route_config: RouteConfig,
-    input_tokens: Arc<Mutex<Option<i32>>>,
-    output_tokens: Arc<Mutex<Option<i32>>>,
+    // 按上游协议解析用量
+    parser: &'static dyn UsageParser,
+    usage: Mutex<TokenUsage>,
     telemetry: Arc<TelemetryModule>,
     // 缓冲区用于累积跨多个chunks的SSE事件
     buffer: Arc<Mutex<String>>,
+    // 是否已上报完整用量
+    reported: AtomicBool,
+    // 提示词压缩前后的Token数
+    compression: Option<CompressionStats>,
+    // 上游请求ID
+    upstream_request_id: Option<String>,
+    // 单个SSE事件的字节数上限
+    max_event_bytes: usize,
+    // 按输出文本估算的输出Token数，随流式增量累加
+    estimated_output_tokens: AtomicU32,
+    // 估算的输入Token数，上游不返回用量时使用
+    estimated_input_tokens: Option<u32>,
 }
 
 impl StreamUsageCollector {
     pub fn new(
         request_id: String,
         user_token: String,
+        client_ip: Option<String>,
+        claims: Option<HashMap<String, serde_json::Value>>,
+        tenant_id: Option<String>,
         route_config: RouteConfig,
         telemetry: Arc<TelemetryModule>,
     ) -> Self {
         Self {
             request_id,
             user_token,
+            client_ip,
+            claims,
+            tenant_id,
+            parser: usage::parser_for(&route_config.protocol),
             route_config,
-            input_tokens: Arc::new(Mutex::new(None)),
-            output_tokens: Arc::new(Mutex::new(None)),
+            usage: Mutex::new(TokenUsage::default()),
             telemetry,
             buffer: Arc::new(Mutex::new(String::new())),
+            reported: AtomicBool::new(false),
+            compression: None,
+            upstream_request_id: None,
+            max_event_bytes: usize::MAX,
+            estimated_output_tokens: AtomicU32::new(0),
+            estimated_input_tokens: None,
         }
     }
 
+    /// 附带提示词压缩前后的Token数，随用量一并上报
+    pub fn with_compression(mut self, compression: Option<CompressionStats>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// 附带上游请求ID，随用量一并上报
+    pub fn with_upstream_request_id(mut self, request_id: Option<String>) -> Self {
+        self.upstream_request_id = request_id;
+        self
+    }
+
+    /// 单个SSE事件的字节数上限，超过时丢弃该事件并计入指标，未设置时不限制
+    pub fn with_max_event_bytes(mut self, max_event_bytes: usize) -> Self {
+        self.max_event_bytes = max_event_bytes;
+        self
+    }
+
+    /// 估算的输入Token数，上游未返回输入用量时代替上报
+    pub fn with_estimated_input_tokens(mut self, tokens: Option<u32>) -> Self {
+        self.estimated_input_tokens = tokens;
+        self
+    }
+
+    /// 目前为止按输出文本估算的输出Token数
+    pub fn estimated_output_tokens(&self) -> u32 {
+        self.estimated_output_tokens.load(Ordering::Relaxed)
+    }
+
     /// 处理流式响应chunk，提取usage信息
     pub fn process_chunk(&self, chunk: &[u8]) {
         // 将chunk转换为字符串并追加到缓冲区
@@ -56,27 +117,26 @@ impl StreamUsageCollector {
         buffer.push_str(chunk_str);
 
         // 处理缓冲区中所有完整的SSE事件（以\n\n分隔）
-        loop {
-            // 查找事件分隔符 \n\n
-            if let Some(event_end) = buffer.find("\n\n") {
-                // 提取完整的事件
-                let event = buffer[..event_end].to_string();
-                // 移除已处理的事件（包括\n\n）
-                *buffer = buffer[event_end + 2..].to_string();
-
-                trace!("Usage Collector - Found complete SSE event");
-
-                // 解析SSE事件
-                self.parse_and_process_sse_event(&event);
-            } else {
-                // 没有完整的事件，等待更多数据
-                break;
-            }
+        // 没有完整的事件时退出循环，等待更多数据
+        while let Some(event_end) = buffer.find("\n\n") {
+            // 提取完整的事件
+            let event = buffer[..event_end].to_string();
+            // 移除已处理的事件（包括\n\n）
+            *buffer = buffer[event_end + 2..].to_string();
+
+            trace!("Usage Collector - Found complete SSE event");
+
+            // 解析SSE事件
+            self.parse_and_process_sse_event(&event);
         }
 
-        // 如果缓冲区太大（超过1MB），清空以防止内存泄漏
-        if buffer.len() > 1024 * 1024 {
-            trace!("Usage Collector - Buffer too large, clearing");
+        // 未完成的事件超过上限时丢弃，该事件中的用量无法统计
+        if buffer.len() > self.max_event_bytes {
+            warn!(
+                "Usage Collector - SSE event exceeds {} bytes, discarding it for request {}",
+                self.max_event_bytes, self.request_id
+            );
+            metrics::increment_counter!("gateway_sse_event_oversized_total", "stage" => "usage_collector");
             buffer.clear();
         }
     }
@@ -127,164 +187,92 @@ impl StreamUsageCollector {
     fn extract_usage_from_json(&self, json: &serde_json::Value) {
         trace!("Usage Collector - Extracting usage from JSON, protocol: {:?}", self.route_config.protocol);
 
-        match &self.route_config.protocol {
-            TargetProtocol::Anthropic => {
-                trace!("Usage Collector - Processing Anthropic protocol");
-
-                // 检查JSON结构
-                if let Some(event_type) = json.get("type").and_then(|v| v.as_str()) {
-                    trace!("Usage Collector - Found event type: {}", event_type);
-
-                    match event_type {
-                        "message_start" => {
-                            trace!("Usage Collector - Processing message_start event");
-
-                            // message_start 包含 input_tokens
-                            if let Some(message) = json.get("message") {
-                                trace!("Usage Collector - Found message object: {}", serde_json::to_string(message).unwrap_or_else(|_| "Invalid".to_string()));
-
-                                if let Some(usage) = message.get("usage") {
-                                    trace!("Usage Collector - Found usage in message: {}", serde_json::to_string(usage).unwrap_or_else(|_| "Invalid".to_string()));
-
-                                    if let Some(input) = usage.get("input_tokens").and_then(|v| v.as_i64()) {
-                                        *self.input_tokens.lock().unwrap() = Some(input as i32);
-                                        trace!("Usage Collector - Collected input_tokens: {}", input);
-                                    } else {
-                                        trace!("Usage Collector - No input_tokens found in usage");
-                                    }
-                                } else {
-                                    trace!("Usage Collector - No usage found in message");
-                                }
-                            } else {
-                                trace!("Usage Collector - No message object found");
-                            }
-                        }
-                        "message_delta" => {
-                            trace!("Usage Collector - Processing message_delta event");
-
-                            // message_delta 包含累积的 output_tokens
-                            if let Some(usage) = json.get("usage") {
-                                trace!("Usage Collector - Found usage in delta: {}", serde_json::to_string(usage).unwrap_or_else(|_| "Invalid".to_string()));
-
-                                if let Some(output) = usage.get("output_tokens").and_then(|v| v.as_i64()) {
-                                    *self.output_tokens.lock().unwrap() = Some(output as i32);
-                                    trace!("Usage Collector - Updated output_tokens: {}", output);
-                                } else {
-                                    trace!("Usage Collector - No output_tokens found in usage");
-                                }
-                            } else {
-                                trace!("Usage Collector - No usage found in message_delta");
-                            }
-                        }
-                        "message_stop" => {
-                            trace!("Usage Collector - Processing message_stop event, triggering usage report");
-                            // 流结束，触发上报
-                            self.report_usage();
-                        }
-                        _ => {
-                            trace!("Usage Collector - Unhandled event type: {}", event_type);
-                        }
-                    }
-                } else {
-                    trace!("Usage Collector - No 'type' field found in JSON");
-                    // 尝试查找其他可能的usage字段
-                    if let Some(usage) = json.get("usage") {
-                        trace!("Usage Collector - Found direct usage field: {}", serde_json::to_string(usage).unwrap_or_else(|_| "Invalid".to_string()));
-                    }
-                }
-            }
-            TargetProtocol::OpenAI | TargetProtocol::Custom(_) => {
-                trace!("Usage Collector - Processing OpenAI/Custom protocol");
-
-                // 首先检查是否是 Codex 的 response.completed 或 response.done 事件
-                if let Some(event_type) = json.get("type").and_then(|v| v.as_str()) {
-                    trace!("Usage Collector - Found event type: {}", event_type);
-
-                    if event_type == "response.completed" || event_type == "response.done" {
-                        trace!("Usage Collector - Processing Codex {} event", event_type);
-
-                        // Codex 的 usage 信息在 response.usage 嵌套对象中
-                        if let Some(response) = json.get("response") {
-                            if let Some(usage) = response.get("usage") {
-                                trace!("Usage Collector - Found Codex usage: {}", serde_json::to_string(usage).unwrap_or_else(|_| "Invalid".to_string()));
-
-                                // Codex 使用 input_tokens 和 output_tokens (类似 Anthropic)
-                                if let Some(input) = usage.get("input_tokens").and_then(|v| v.as_i64()) {
-                                    *self.input_tokens.lock().unwrap() = Some(input as i32);
-                                    trace!("Usage Collector - Collected Codex input_tokens: {}", input);
-                                }
-                                if let Some(output) = usage.get("output_tokens").and_then(|v| v.as_i64()) {
-                                    *self.output_tokens.lock().unwrap() = Some(output as i32);
-                                    trace!("Usage Collector - Collected Codex output_tokens: {}", output);
-                                }
-
-                                // response.completed 表示流结束,触发上报
-                                trace!("Usage Collector - Codex stream completed, triggering usage report");
-                                self.report_usage();
-                            } else {
-                                trace!("Usage Collector - No usage found in Codex response object");
-                            }
-                        } else {
-                            trace!("Usage Collector - No response object found in Codex event");
-                        }
-                        return; // 已处理,直接返回
-                    }
-                }
+        let update = self.parser.parse_stream_event(json);
+        if update.estimated_output_tokens > 0 {
+            self.estimated_output_tokens
+                .fetch_add(update.estimated_output_tokens, Ordering::Relaxed);
+        }
+        if let Some(usage) = update.usage {
+            trace!("Usage Collector - Collected usage: {:?}", usage);
+            self.usage.lock().unwrap().merge(usage);
+        }
+        if update.finished {
+            trace!("Usage Collector - Stream completed, triggering usage report");
+            self.report_usage();
+        }
+    }
 
-                // 标准 OpenAI 流式响应
-                // 检查是否有usage字段（通常在最后一个chunk）
-                if let Some(usage) = json.get("usage") {
-                    trace!("Usage Collector - Found OpenAI usage: {}", serde_json::to_string(usage).unwrap_or_else(|_| "Invalid".to_string()));
-
-                    // 支持两种字段名: prompt_tokens/completion_tokens (OpenAI) 和 input_tokens/output_tokens (兼容)
-                    if let Some(input) = usage.get("prompt_tokens")
-                        .or_else(|| usage.get("input_tokens"))
-                        .and_then(|v| v.as_i64())
-                    {
-                        *self.input_tokens.lock().unwrap() = Some(input as i32);
-                        trace!("Usage Collector - Collected input tokens: {}", input);
-                    }
-                    if let Some(output) = usage.get("completion_tokens")
-                        .or_else(|| usage.get("output_tokens"))
-                        .and_then(|v| v.as_i64())
-                    {
-                        *self.output_tokens.lock().unwrap() = Some(output as i32);
-                        trace!("Usage Collector - Collected output tokens: {}", output);
-                        // OpenAI在有usage时通常意味着流结束
-                        self.report_usage();
-                    }
-                } else {
-                    trace!("Usage Collector - No usage found in OpenAI response");
-                }
+    /// 已收集的用量，上游未返回的输入/输出Token数以估算值代替，第二项表示是否使用了估算值
+    fn collected_usage(&self) -> (TokenUsage, bool) {
+        let mut usage = *self.usage.lock().unwrap();
+        let mut estimated = false;
+        let output_estimate = self.estimated_output_tokens();
+        if usage.output_tokens.is_none() && output_estimate > 0 {
+            usage.output_tokens = Some(output_estimate.min(i32::MAX as u32) as i32);
+            estimated = true;
+        }
+        if let (None, Some(input_estimate)) = (usage.input_tokens, self.estimated_input_tokens) {
+            if usage.output_tokens.is_some() {
+                usage.input_tokens = Some(input_estimate.min(i32::MAX as u32) as i32);
+                estimated = true;
             }
         }
+        (usage, estimated)
     }
 
     /// 上报usage数据
     pub fn report_usage(&self) {
-        let input = self.input_tokens.lock().unwrap().clone();
-        let output = self.output_tokens.lock().unwrap().clone();
+        let (usage, estimated) = self.collected_usage();
+        let (input, output) = (usage.input_tokens, usage.output_tokens);
 
-        trace!("Usage Collector - Attempting to report usage: input={:?}, output={:?}", input, output);
+        trace!(
+            "Usage Collector - Attempting to report usage: input={:?}, output={:?}",
+            input,
+            output
+        );
 
         if let (Some(input_tokens), Some(output_tokens)) = (input, output) {
-            info!("Usage reported: input={}, output={}, model={}",
-                  input_tokens, output_tokens, self.route_config.model);
-
-            self.telemetry.report_usage(UsageEvent {
-                request_id: self.request_id.clone(),
-                token: self.user_token.clone(),
-                model: self.route_config.model.clone(),  // 请求的模型名
-                api: self.route_config.api_endpoint.clone(),
+            info!(
+                "Usage reported: input={}, output={}, model={}{}",
                 input_tokens,
                 output_tokens,
-                // 新增：使用RouteConfig中的ID字段
-                model_id: self.route_config.model_id.clone(),
-                provider_id: self.route_config.provider_id.clone(),
-                provider_token_id: self.route_config.provider_token_id.clone(),
-            });
+                self.route_config.model,
+                if estimated { " (estimated)" } else { "" }
+            );
+
+            self.reported.store(true, Ordering::Relaxed);
+            self.telemetry.report_usage(usage.annotate(UsageEvent {
+                estimated: estimated.then_some(true),
+                ..self.usage_event(input_tokens, output_tokens)
+            }));
         } else {
-            warn!("Cannot report usage: missing tokens (input={:?}, output={:?})", input, output);
+            warn!(
+                "Cannot report usage: missing tokens (input={:?}, output={:?})",
+                input, output
+            );
+        }
+    }
+
+    fn usage_event(&self, input_tokens: i32, output_tokens: i32) -> UsageEvent {
+        UsageEvent {
+            request_id: self.request_id.clone(),
+            token: self.user_token.clone(),
+            model: self.route_config.model.clone(), // 请求的模型名
+            api: self.route_config.api_endpoint.clone(),
+            input_tokens,
+            output_tokens,
+            // 新增：使用RouteConfig中的ID字段
+            model_id: self.route_config.model_id.clone(),
+            provider_id: self.route_config.provider_id.clone(),
+            provider_token_id: self.route_config.provider_token_id.clone(),
+            client_ip: self.client_ip.clone(),
+            claims: self.claims.clone(),
+            tenant_id: self.tenant_id.clone(),
+            canary: self.route_config.canary.as_ref().map(|c| c.tag.clone()),
+            original_prompt_tokens: self.compression.map(|c| c.original_tokens),
+            compressed_prompt_tokens: self.compression.map(|c| c.compressed_tokens),
+            upstream_request_id: self.upstream_request_id.clone(),
+            ..Default::default()
         }
     }
 
@@ -315,4 +303,34 @@ impl StreamUsageCollector {
             self.report_usage();
         }
     }
-}
\ No newline at end of file
+}
+
+/// 流被丢弃时（客户端中途断开导致响应流在读完前被释放，或上游中断）仍未上报完整用量的，
+/// 按已观测到的部分（上游未返回的以估算值代替）上报，并标记 `completed: false`，缺失的一项按0计
+impl Drop for StreamUsageCollector {
+    fn drop(&mut self) {
+        if self.reported.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let (usage, estimated) = self.collected_usage();
+        let (input, output) = (usage.input_tokens, usage.output_tokens);
+        if input.is_none() && output.is_none() {
+            info!(
+                "Stream for request {} ended without any usage observed",
+                self.request_id
+            );
+            return;
+        }
+
+        info!(
+            "Partial usage reported for incomplete stream: input={:?}, output={:?}, model={}",
+            input, output, self.route_config.model
+        );
+        self.telemetry.report_usage(usage.annotate(UsageEvent {
+            completed: Some(false),
+            estimated: estimated.then_some(true),
+            ..self.usage_event(input.unwrap_or(0), output.unwrap_or(0))
+        }));
+    }
+}